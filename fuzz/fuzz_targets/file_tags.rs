@@ -0,0 +1,18 @@
+//! File tag lists (`FILE TAGS-SET`) and chunk response headers (`FILE RESP-CHUNK`).
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use ouroboros_fs::schema::{FileTags, RespChunk};
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(s) = std::str::from_utf8(data) else {
+        return;
+    };
+    if let Ok(tags) = s.parse::<FileTags>() {
+        assert_eq!(tags.to_string().parse::<FileTags>().ok(), Some(tags));
+    }
+    if let Ok(chunk) = s.parse::<RespChunk>() {
+        assert_eq!(chunk.to_string().parse::<RespChunk>().ok(), Some(chunk));
+    }
+});
@@ -0,0 +1,19 @@
+//! Netmap entries (`NETMAP SET`, `NETMAP HOP`), and the labels they carry.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use ouroboros_fs::schema::{Labels, Netmap};
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(s) = std::str::from_utf8(data) else {
+        return;
+    };
+    if let Ok(entries) = s.parse::<Netmap>() {
+        // What was decoded must encode and decode again
+        assert_eq!(entries.to_string().parse::<Netmap>().ok(), Some(entries));
+    }
+    if let Ok(labels) = s.parse::<Labels>() {
+        assert_eq!(labels.to_string().parse::<Labels>().ok(), Some(labels));
+    }
+});
@@ -0,0 +1,28 @@
+//! Any line a peer or client may send must parse or be rejected, never panic,
+//! and the state a parsed line carries must encode and decode again.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use ouroboros_fs::{Command, schema::Topology};
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(line) = std::str::from_utf8(data) else {
+        return;
+    };
+    let Ok(command) = ouroboros_fs::parse_line(line) else {
+        return;
+    };
+    match command {
+        Command::TopologySet { history, .. } => {
+            assert_eq!(history.to_string().parse::<Topology>().ok(), Some(history));
+        }
+        Command::NetmapSet { entries } => {
+            assert_eq!(entries.to_string().parse().ok(), Some(entries));
+        }
+        Command::FileTagsSet { entries } => {
+            assert_eq!(entries.to_string().parse().ok(), Some(entries));
+        }
+        _ => {}
+    }
+});
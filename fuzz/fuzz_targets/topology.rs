@@ -0,0 +1,44 @@
+//! Walk histories (`TOPOLOGY HOP/DONE/SET`), and the decisions a node takes on
+//! them: which snapshot wins (`Node::set_topology_from_history`) and where a
+//! hop goes next.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use ouroboros_fs::{
+    ring_state::{RingState, topology_is_newer},
+    schema::Topology,
+};
+
+fuzz_target!(|data: &[u8]| {
+    // Two epochs, then two histories separated by a newline
+    if data.len() < 16 {
+        return;
+    }
+    let (epochs, rest) = data.split_at(16);
+    let current_epoch = u64::from_le_bytes(epochs[..8].try_into().unwrap_or_default());
+    let epoch = u64::from_le_bytes(epochs[8..].try_into().unwrap_or_default());
+    let Ok(s) = std::str::from_utf8(rest) else {
+        return;
+    };
+    let (current, incoming) = s.split_once('\n').unwrap_or((s, ""));
+    let (Ok(current), Ok(incoming)) = (current.parse::<Topology>(), incoming.parse::<Topology>())
+    else {
+        return;
+    };
+
+    let _ = topology_is_newer(current_epoch, &current.untimed(), epoch, &incoming);
+    assert_eq!(
+        incoming.to_string().parse::<Topology>().ok(),
+        Some(incoming.clone())
+    );
+
+    let state = RingState {
+        port: "127.0.0.1:7000".to_string(),
+        next: current.edges().next().map(|edge| edge.to.clone()),
+        epoch: current_epoch,
+    };
+    let start = incoming.edges().next().map_or("7000", |edge| edge.from.as_str());
+    let _ = state.topology_hop(start, epoch, &incoming);
+    let _ = state.seen_epoch(epoch);
+});
@@ -12,15 +12,18 @@ fi
 HOST="127.0.0.1"
 PORT="7000"
 FILE_NAME=""
+OFFSET=""
 
 # --- Usage Function ---
 usage() {
-  echo "Usage: $0 -f <file_name_on_server> [-h <host>] [-p <port>]" >&2
+  echo "Usage: $0 -f <file_name_on_server> [-h <host>] [-p <port>] [-o <offset>]" >&2
   echo "Outputs the file to stdout. Redirect to save it." >&2
   echo "  -f, --file    Name of the file to pull from the network." >&2
   echo "  -h, --host    Network host (default: 127.0.0.1)." >&2
   echo "  -p, --port    Network port (default: 7000)." >&2
+  echo "  -o, --offset  Skip the first <offset> bytes, to resume a download." >&2
   echo "Example: $0 -f Cargo.toml > ./downloaded_cargo.toml" >&2
+  echo "Resume:  $0 -f Cargo.toml -o \$(wc -c < ./downloaded_cargo.toml) >> ./downloaded_cargo.toml" >&2
   exit 1
 }
 
@@ -42,6 +45,11 @@ while [[ $# -gt 0 ]]; do
       PORT="$2"
       shift 2
       ;;
+    -o | --offset)
+      if [[ -z "$2" || "$2" == -* ]]; then echo "Error: $1 requires an argument." >&2; usage; fi
+      OFFSET="$2"
+      shift 2
+      ;;
     --help)
       usage
       ;;
@@ -58,5 +66,9 @@ if [ -z "${FILE_NAME}" ]; then
   usage
 fi
 
-# Send the FILE PULL command.
-printf "FILE PULL ${FILE_NAME}\n" | nc ${NC_OPTS} ${HOST} ${PORT}
+# Send the FILE PULL command, resuming at OFFSET if given.
+if [ -n "${OFFSET}" ]; then
+  printf "FILE PULL ${FILE_NAME} OFFSET ${OFFSET}\n" | nc ${NC_OPTS} ${HOST} ${PORT}
+else
+  printf "FILE PULL ${FILE_NAME}\n" | nc ${NC_OPTS} ${HOST} ${PORT}
+fi
@@ -22,7 +22,7 @@ export const useNetworkStore = defineStore('network', () => {
     const uploadLoading = ref(false)
     const healLoading = ref(false)
     const killingNodeId = ref<string | null>(null)
-    const API_BASE = 'http://127.0.0.1:8000' // TODO: dynamically update this with envs
+    const API_BASE = 'http://127.0.0.1:8000/api/v1' // TODO: dynamically update this with envs
 
     /** Fetches the latest node status from the gateway */
     async function netmapGet() {
@@ -0,0 +1,129 @@
+//! Node addressing helpers that understand IPv6 literals.
+//!
+//! Addresses travel as text (`127.0.0.1:7000`, `[::1]:7000`), and the ring
+//! often only exchanges ports (`7000`). Splitting on the *first* `:` mangles
+//! IPv6 literals, so everything here splits on the *last* one and keeps the
+//! brackets around IPv6 hosts.
+
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    str::FromStr,
+};
+
+/// Host assumed when an address is just a port
+pub const DEFAULT_HOST: &str = "127.0.0.1";
+
+/// Socket address of a ring node.
+///
+/// Parses `host:port`, bracketed IPv6 literals (`[::1]:7000`) and bare ports
+/// (`7000`, read as `127.0.0.1:7000`). Displays IPv6 hosts with brackets so
+/// the text form always parses back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeAddr(pub SocketAddr);
+
+impl NodeAddr {
+    pub fn ip(&self) -> IpAddr {
+        self.0.ip()
+    }
+
+    pub fn port(&self) -> u16 {
+        self.0.port()
+    }
+
+    /// Host part as it appears in an address (IPv6 keeps its brackets)
+    pub fn host(&self) -> String {
+        match self.0 {
+            SocketAddr::V4(a) => a.ip().to_string(),
+            SocketAddr::V6(a) => format!("[{}]", a.ip()),
+        }
+    }
+
+    /// Address of a peer on the same host
+    pub fn with_port(&self, port: u16) -> NodeAddr {
+        NodeAddr(SocketAddr::new(self.0.ip(), port))
+    }
+}
+
+impl FromStr for NodeAddr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Ok(port) = s.parse::<u16>() {
+            return Ok(NodeAddr(SocketAddr::new(
+                IpAddr::V4(Ipv4Addr::LOCALHOST),
+                port,
+            )));
+        }
+        s.parse::<SocketAddr>()
+            .map(NodeAddr)
+            .map_err(|_| format!("invalid node address '{}'", s))
+    }
+}
+
+impl fmt::Display for NodeAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl From<SocketAddr> for NodeAddr {
+    fn from(addr: SocketAddr) -> Self {
+        NodeAddr(addr)
+    }
+}
+
+/// Port part of `host:port` (or the input itself when it is a bare port)
+pub fn port_str(addr: &str) -> &str {
+    addr.rsplit(':').next().unwrap_or(addr)
+}
+
+/// Host part of `host:port`, brackets included for IPv6; `127.0.0.1` for a bare port
+pub fn host_str(addr: &str) -> &str {
+    match addr.rsplit_once(':') {
+        Some((host, _)) if !host.is_empty() => host,
+        _ => DEFAULT_HOST,
+    }
+}
+
+/// Joins a host and a port, bracketing bare IPv6 hosts (`::1` -> `[::1]:7000`)
+pub fn join_host_port(host: &str, port: impl fmt::Display) -> String {
+    if host.contains(':') && !host.starts_with('[') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    }
+}
+
+/// `host:port` for a URL authority (`host`, `host:port`, `[::1]` or
+/// `[::1]:port`), with `port` when it names none
+pub fn with_default_port(authority: &str, port: u16) -> String {
+    let has_port = match authority.rfind(']') {
+        Some(end) => authority[end + 1..].starts_with(':'),
+        None => authority.contains(':'),
+    };
+    if has_port {
+        authority.to_string()
+    } else {
+        join_host_port(authority, port)
+    }
+}
+
+/// Reduces a netmap/topology key to its port, accepting both `7000` and `[::1]:7000`
+pub fn port_key(entry: &str) -> &str {
+    port_str(entry.trim()).trim()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_ports() {
+        assert_eq!(with_default_port("host", 80), "host:80");
+        assert_eq!(with_default_port("host:8080", 80), "host:8080");
+        assert_eq!(with_default_port("[::1]", 80), "[::1]:80");
+        assert_eq!(with_default_port("[::1]:8080", 80), "[::1]:8080");
+    }
+}
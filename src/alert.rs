@@ -0,0 +1,122 @@
+//! Operator alerts fired when a node finds its neighbor dead.
+
+use crate::{addr::with_default_port, config::DeathHooks};
+use serde::Serialize;
+use std::{error::Error, time::Duration};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    process::Command,
+};
+
+type AnyErr = Box<dyn Error + Send + Sync>;
+
+/// What the detecting node decided to do about the dead peer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RespawnAction {
+    Respawning,
+    Disabled,
+    LimitReached,
+}
+
+impl RespawnAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Respawning => "respawning",
+            Self::Disabled => "disabled",
+            Self::LimitReached => "limit_reached",
+        }
+    }
+}
+
+/// Details handed to the alert hooks.
+#[derive(Debug, Clone, Serialize)]
+pub struct DeathAlert {
+    /// Address of the dead node
+    pub dead_node: String,
+    /// Address of the node that detected the death
+    pub detected_by: String,
+    /// Respawns of the dead node within the current window, this one included
+    pub respawns: u32,
+    pub action: RespawnAction,
+}
+
+/// Fires every configured hook in the background; failures are only logged.
+pub fn fire(hooks: &DeathHooks, alert: DeathAlert) {
+    if let Some(cmd) = hooks.exec.clone() {
+        let alert = alert.clone();
+        tokio::spawn(async move {
+            if let Err(e) = run_exec_hook(&cmd, &alert).await {
+                tracing::warn!(hook = %cmd, error = ?e, "Death exec hook failed");
+            }
+        });
+    }
+    if let Some(url) = hooks.webhook.clone() {
+        tokio::spawn(async move {
+            if let Err(e) = post_webhook(&url, &alert).await {
+                tracing::warn!(url = %url, error = ?e, "Death webhook failed");
+            }
+        });
+    }
+}
+
+async fn run_exec_hook(cmd: &str, alert: &DeathAlert) -> Result<(), AnyErr> {
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(cmd)
+        .env("OUROBOROS_DEAD_NODE", &alert.dead_node)
+        .env("OUROBOROS_DETECTED_BY", &alert.detected_by)
+        .env("OUROBOROS_RESPAWNS", alert.respawns.to_string())
+        .env("OUROBOROS_ACTION", alert.action.as_str())
+        .status()
+        .await?;
+    if !status.success() {
+        return Err(format!("hook exited with {}", status).into());
+    }
+    Ok(())
+}
+
+/// Minimal HTTP/1.1 `POST` of the alert as JSON. Only plain `http://` URLs are supported.
+async fn post_webhook(url: &str, alert: &DeathAlert) -> Result<(), AnyErr> {
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or("only http:// webhooks are supported")?;
+    let (authority, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    let addr = with_default_port(authority, 80);
+
+    let body = serde_json::to_string(alert)?;
+    let request = format!(
+        "POST {} HTTP/1.1\r\n\
+         Host: {}\r\n\
+         Content-Type: application/json\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\
+         \r\n\
+         {}",
+        path,
+        authority,
+        body.len(),
+        body
+    );
+
+    let timeout = Duration::from_secs(5);
+    let mut stream = tokio::time::timeout(timeout, TcpStream::connect(&addr)).await??;
+    stream.write_all(request.as_bytes()).await?;
+
+    // Only the status line matters
+    let mut buf = [0u8; 64];
+    let n = tokio::time::timeout(timeout, stream.read(&mut buf)).await??;
+    let status_line = String::from_utf8_lossy(&buf[..n]);
+    let ok = status_line
+        .split_whitespace()
+        .nth(1)
+        .is_some_and(|code| code.starts_with('2'));
+    if !ok {
+        return Err(format!("unexpected webhook response: {}", status_line.trim()).into());
+    }
+    Ok(())
+}
@@ -0,0 +1,321 @@
+//! Shared-secret authentication of node-to-node commands.
+//!
+//! A node answers every command on its one port, so without a secret any
+//! client could forge the internal ones (`NETMAP DONE`, `TOPOLOGY SET`,
+//! `FILE RELAY-STREAM`, ...; see [`crate::Command::is_internal`]) and corrupt
+//! walks or the file tags. A node started with a cluster token only takes
+//! those on a connection that first sent `NODE AUTH <token>`, and starts every
+//! connection it opens to a peer with that line. All nodes of a ring share the
+//! token.
+//!
+//! Client commands can be restricted too: a node started with an access file
+//! gives every client token in it a [`Role`], and refuses commands above the
+//! role of the connection (see [`crate::Command::role`]). Clients present
+//! their token with the same `NODE AUTH` line; the cluster token has every
+//! role.
+
+use crate::secrets::{KeyRing, Secret, SecretSource, same_secret};
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, OnceLock, RwLock, RwLockReadGuard},
+};
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+};
+
+/// Environment variable `run` reads the token from, and that `set-network` and
+/// respawns hand a token not read from a file to nodes in, so it never shows
+/// on a command line
+pub const CLUSTER_TOKEN_ENV: &str = "OUROBOROS_CLUSTER_TOKEN";
+
+/// Environment variable clients (the CLI tools, the gateway) read the token
+/// they present from
+pub const ACCESS_TOKEN_ENV: &str = "OUROBOROS_ACCESS_TOKEN";
+
+/// Secret the nodes of a ring prove they belong to it with: a [`KeyRing`]
+/// whose first key is presented and all of whose keys are accepted, shared by
+/// every clone so a rotation reaches all of the node's connections
+#[derive(Clone)]
+pub struct ClusterToken {
+    keys: Arc<RwLock<KeyRing>>,
+    source: Option<SecretSource>,
+}
+
+impl ClusterToken {
+    /// A token from `secret`: one key, or several separated by whitespace
+    pub fn new(secret: &str) -> Result<Self, String> {
+        Ok(Self {
+            keys: Arc::new(RwLock::new(Self::parse(secret)?)),
+            source: None,
+        })
+    }
+
+    /// The token held in the file at `path`, which [`ClusterToken::rotate`]
+    /// reads again
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let source = SecretSource::file(path);
+        let invalid = |e: String| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), e),
+            )
+        };
+        let keys = Self::load(&source).map_err(invalid)?;
+        Ok(Self {
+            keys: Arc::new(RwLock::new(keys)),
+            source: Some(source),
+        })
+    }
+
+    /// The token in [`CLUSTER_TOKEN_ENV`], if set
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let source = SecretSource::Env(CLUSTER_TOKEN_ENV.to_string());
+        match source.load().map_err(|e| e.to_string())? {
+            Some(secret) => Ok(Some(Self {
+                keys: Arc::new(RwLock::new(Self::parse(secret.expose())?)),
+                source: Some(source),
+            })),
+            None => Ok(None),
+        }
+    }
+
+    fn parse(secret: &str) -> Result<KeyRing, String> {
+        KeyRing::parse(secret).map_err(|_| "a cluster token needs at least one key".to_string())
+    }
+
+    fn load(source: &SecretSource) -> Result<KeyRing, String> {
+        match source.load().map_err(|e| e.to_string())? {
+            Some(secret) => Self::parse(secret.expose()),
+            None => Err(format!("{} is not set", source)),
+        }
+    }
+
+    fn keys(&self) -> RwLockReadGuard<'_, KeyRing> {
+        self.keys.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Whether `secret` is one of the token's keys. Takes as long whatever the
+    /// first differing byte, so a key cannot be guessed byte by byte.
+    pub fn matches(&self, secret: &str) -> bool {
+        self.keys().accepts(secret)
+    }
+
+    /// The signing key, the one presented to peers
+    pub fn secret(&self) -> Secret {
+        self.keys().signing().clone()
+    }
+
+    /// Every key, one per line, for handing the token to a new node
+    pub fn export(&self) -> Secret {
+        self.keys().export()
+    }
+
+    /// Where the token was read from, if anywhere
+    pub fn source(&self) -> Option<&SecretSource> {
+        self.source.as_ref()
+    }
+
+    /// Reads the token's source again and takes its keys: the number of keys
+    /// now accepted. On error the keys in use are kept.
+    pub fn rotate(&self) -> Result<usize, String> {
+        let source = self
+            .source
+            .as_ref()
+            .ok_or_else(|| "the cluster token was not read from a file".to_string())?;
+        let keys = Self::load(source)?;
+        let count = keys.len();
+        *self.keys.write().unwrap_or_else(|e| e.into_inner()) = keys;
+        Ok(count)
+    }
+
+    /// Starts a connection to a peer: writes `NODE AUTH <signing key>`
+    pub async fn send<W: AsyncWrite + Unpin>(&self, writer: &mut W) -> io::Result<()> {
+        let line = Secret::from(format!("NODE AUTH {}\n", self.secret().expose()));
+        writer.write_all(line.expose().as_bytes()).await
+    }
+}
+
+impl fmt::Debug for ClusterToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ClusterToken(..)")
+    }
+}
+
+/// What a client may do, each role allowing what the ones before it do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    /// Read files and the ring's state (`FILE PULL`, `FILE LIST`, `NODE STATUS`, ...)
+    Reader,
+    /// Store and change files (`FILE PUSH`, `FILE SYNC`, `FILE COPY`, ...)
+    Writer,
+    /// Rewire, heal and reconfigure the ring (`NODE NEXT`, `NODE HEAL`, `NODE CONFIG SET`, ...)
+    Admin,
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "reader" => Ok(Self::Reader),
+            "writer" => Ok(Self::Writer),
+            "admin" => Ok(Self::Admin),
+            _ => Err(format!(
+                "invalid role '{}' (expected reader|writer|admin)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Reader => "reader",
+            Self::Writer => "writer",
+            Self::Admin => "admin",
+        })
+    }
+}
+
+/// Client tokens and their roles, read from an access file of
+/// `<role> <token>` lines. Blank lines and lines starting with `#` are
+/// ignored; the token `*` gives its role to connections that present none.
+#[derive(Clone)]
+pub struct AccessList {
+    /// Where the list was read from, handed to respawned nodes
+    pub path: PathBuf,
+    entries: Arc<RwLock<AccessEntries>>,
+}
+
+/// What an access file holds, swapped whole by [`AccessList::reload`]
+struct AccessEntries {
+    tokens: Vec<(Secret, Role)>,
+    anonymous: Option<Role>,
+}
+
+impl AccessList {
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        let entries = Self::parse(&path)?;
+        Ok(Self {
+            path,
+            entries: Arc::new(RwLock::new(entries)),
+        })
+    }
+
+    /// Reads the file again and takes its tokens: the number of tokens now
+    /// listed. On error the tokens in use are kept.
+    pub fn reload(&self) -> io::Result<usize> {
+        let entries = Self::parse(&self.path)?;
+        let count = entries.tokens.len();
+        *self.entries.write().unwrap_or_else(|e| e.into_inner()) = entries;
+        Ok(count)
+    }
+
+    fn parse(path: &Path) -> io::Result<AccessEntries> {
+        let invalid = |e: String| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), e),
+            )
+        };
+        let contents = SecretSource::File(path.to_path_buf())
+            .load()?
+            .unwrap_or_else(|| Secret::new(""));
+        let mut tokens = Vec::new();
+        let mut anonymous = None;
+        for (i, line) in contents.expose().lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (role, token) = line
+                .split_once(char::is_whitespace)
+                .ok_or_else(|| invalid(format!("line {}: expected '<role> <token>'", i + 1)))?;
+            let role: Role = role.parse().map_err(invalid)?;
+            match token.trim() {
+                "*" => anonymous = Some(role),
+                token if token.contains(char::is_whitespace) => {
+                    return Err(invalid(format!("line {}: a token must be one word", i + 1)));
+                }
+                token => tokens.push((Secret::new(token), role)),
+            }
+        }
+        Ok(AccessEntries { tokens, anonymous })
+    }
+
+    fn entries(&self) -> RwLockReadGuard<'_, AccessEntries> {
+        self.entries.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Role of the client presenting `secret`, if it is one of the list's tokens
+    pub fn role_of(&self, secret: &str) -> Option<Role> {
+        // Every token is compared, so the time taken tells nothing either
+        self.entries()
+            .tokens
+            .iter()
+            .filter(|(token, _)| same_secret(token.expose(), secret))
+            .map(|(_, role)| *role)
+            .fold(None, |best, role| best.max(Some(role)))
+    }
+
+    /// Role of connections that present no token (`*` in the file)
+    pub fn anonymous(&self) -> Option<Role> {
+        self.entries().anonymous
+    }
+}
+
+impl fmt::Debug for AccessList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let entries = self.entries();
+        f.debug_struct("AccessList")
+            .field("path", &self.path)
+            .field("tokens", &entries.tokens.len())
+            .field("anonymous", &entries.anonymous)
+            .finish()
+    }
+}
+
+/// Token this process presents as a client, when set with [`set_client_token`]
+static CLIENT_TOKEN: OnceLock<ClusterToken> = OnceLock::new();
+
+/// Has this process present `token` as a client (a process that starts and
+/// holds the ring's cluster token, like `set-network`). The first call wins.
+pub fn set_client_token(token: &ClusterToken) {
+    let _ = CLIENT_TOKEN.set(token.clone());
+}
+
+/// Token a client presents: the one given to [`set_client_token`], else
+/// [`ACCESS_TOKEN_ENV`], else the cluster token in [`CLUSTER_TOKEN_ENV`]
+pub fn client_token() -> Option<Secret> {
+    if let Some(token) = CLIENT_TOKEN.get() {
+        return Some(token.secret());
+    }
+    // The cluster token's variable may list several keys: the first signs
+    [ACCESS_TOKEN_ENV, CLUSTER_TOKEN_ENV]
+        .into_iter()
+        .find_map(|var| SecretSource::Env(var.to_string()).load().ok().flatten())
+        .and_then(|secret| KeyRing::parse(secret.expose()).ok())
+        .map(|keys| keys.signing().clone())
+}
+
+/// Connects to a node as a client, starting with `NODE AUTH` when there is a
+/// [`client_token`]
+pub async fn connect(addr: &str) -> io::Result<TcpStream> {
+    connect_with(addr, None).await
+}
+
+/// Connects to a node as a client presenting `token`, or the process'
+/// [`client_token`] when `None` (a gateway with credentials of its own)
+pub async fn connect_with(addr: &str, token: Option<&ClusterToken>) -> io::Result<TcpStream> {
+    let mut stream = TcpStream::connect(addr).await?;
+    if let Some(token) = token.map(ClusterToken::secret).or_else(client_token) {
+        let line = Secret::from(format!("NODE AUTH {}\n", token.expose()));
+        stream.write_all(line.expose().as_bytes()).await?;
+    }
+    Ok(stream)
+}
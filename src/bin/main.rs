@@ -1,20 +1,48 @@
-use clap::{Parser, Subcommand};
-use libc;
-use ouroboros_fs::run;
-use std::{env, error::Error, fs, path::Path, path::PathBuf, sync::Arc, time::Duration};
+use clap::{Args, Parser, Subcommand};
+use ouroboros_fs::{
+    NodeBuilder,
+    addr::{DEFAULT_HOST, join_host_port, port_str},
+    auth::{self, AccessList, CLUSTER_TOKEN_ENV, ClusterToken},
+    bulk,
+    compression::Compression,
+    config::{DeathHooks, RespawnMode, TcpOptions},
+    daemon::{self, PidFile},
+    delta,
+    gateway::{
+        DEFAULT_MAX_HEADER, DEFAULT_MAX_UPLOAD, DEFAULT_TUNNEL_IDLE, RequestLimits, TunnelTimeouts,
+    },
+    logging::{self, LogFormat, LogOptions},
+    logs::{self, LogsQuery},
+    output::{OutputFormat, json_line},
+    proxy::{ProxyOptions, TrustedProxies},
+    pull, ring_verify,
+    schema::{Labels, parse_ring_id},
+    secrets::SecretSource,
+    watch::{self, WatchOptions},
+};
+use serde::Serialize;
+use serde_json::json;
+use std::{
+    env, error::Error, fmt, fs, path::Path, path::PathBuf, process::ExitCode, time::Duration,
+};
 use tokio::{
     io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
     net::TcpStream,
     process::{Child, Command},
     time::sleep,
 };
-use tracing_subscriber::{EnvFilter, fmt};
+use tracing_subscriber::EnvFilter;
 
 #[derive(Parser)]
 #[command(name = "ouroboros_fs", version, about = "Ring TCP server & tools")]
 struct Cli {
     #[command(subcommand)]
     command: Cmd,
+    #[command(flatten)]
+    log: LogOpts,
+    /// Result format: "text" lines, or "json" (one JSON object per line)
+    #[arg(long, global = true, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
 }
 
 #[derive(Subcommand)]
@@ -33,17 +61,68 @@ enum Cmd {
         /// Max file size in bytes. 0 to disable. Defaults to 1 gigabyte.
         #[arg(short, long, default_value_t = 1_000_000_000u64)]
         file_size: u64,
+        /// Directory for the node's storage and identity [default: nodes/<port>]
+        #[arg(long)]
+        data_dir: Option<PathBuf>,
+        /// Time (ms) a health check waits for the next node to answer.
+        #[arg(long, default_value_t = 2000u64)]
+        health_timeout: u64,
+        /// Time (ms) `TOPOLOGY WALK` waits for the walk to come back around the ring.
+        #[arg(long, default_value_t = 30_000u64)]
+        walk_timeout: u64,
+        /// Time (ms) `NODE HEAL` waits for the heal walk, respawns included.
+        #[arg(long, default_value_t = 60_000u64)]
+        heal_timeout: u64,
+        /// `key = value` settings file, applied on start and re-read on SIGHUP.
+        #[arg(long)]
+        config: Option<PathBuf>,
+        /// Also answer health checks over UDP on the same port, and ping peers there first
+        #[arg(long)]
+        udp_heartbeat: bool,
+        /// Labels pushes can be placed by, e.g. `zone=eu-west,disk=ssd`
+        #[arg(long)]
+        label: Option<Labels>,
+        /// Label naming the node's failure domain; backups avoid the holder's domain
+        #[arg(long, default_value = "zone")]
+        failure_domain: String,
+        /// Backup copies kept for every chunk. 0 disables backups.
+        #[arg(long, default_value_t = 1u32)]
+        replication: u32,
+        /// Name of this ring; other rings reach its files as `<ring-id>/<name>`
+        #[arg(long, value_parser = parse_ring_id)]
+        ring_id: Option<String>,
+        /// Ring node to be reached through, when this one cannot accept inbound connections
+        #[arg(long)]
+        relay: Option<String>,
+        /// File holding the secret the ring's internal commands need (default: $OUROBOROS_CLUSTER_TOKEN)
+        #[arg(long)]
+        cluster_token_file: Option<PathBuf>,
+        /// File of `<role> <token>` lines restricting client commands to tokens with a role
+        /// allowing them (reader, writer, admin); needs a cluster token
+        #[arg(long)]
+        access_file: Option<PathBuf>,
+        /// Detach into the background once listening; needs --pid-file and --log-file
+        #[arg(long, requires = "pid_file")]
+        daemonize: bool,
+        /// Write the process id to this file, removed on exit (see `stop`)
+        #[arg(long)]
+        pid_file: Option<PathBuf>,
+        #[command(flatten)]
+        respawn: RespawnOpts,
+        #[command(flatten)]
+        tcp: TcpOpts,
     },
 
     /// Spawn N nodes and stitch them into a ring
     SetNetwork {
         /// Number of nodes to start
-        #[arg(short = 'n', long = "nodes", default_value_t = 3)]
+        #[arg(short = 'n', long = "nodes", default_value_t = 3,
+              value_parser = clap::value_parser!(u16).range(1..))]
         nodes: u16,
         /// Base port to use (ports are base, base+1, ..., base+N-1)
         #[arg(short = 'p', long = "base-port", default_value_t = 7000)]
         base_port: u16,
-        /// Interface to bind and to use when wiring SET_NEXT
+        /// Interface to bind and to use when wiring SET_NEXT (IPv6 literals like `::1` are accepted)
         #[arg(long, default_value = "127.0.0.1")]
         host: String,
         /// Do not block, just start and wire nodes, then return
@@ -61,32 +140,425 @@ enum Cmd {
         /// Run the DNS Gateway on this port
         #[arg(long = "dns-port")]
         dns_port: Option<u16>,
+        /// Gateway routes whose responses are compressed (path prefixes, comma separated, or "none")
+        #[arg(long, default_value_t = Compression::default())]
+        gateway_compress: Compression,
+        /// Expect a PROXY protocol header (v1 or v2) on every gateway connection
+        #[arg(long)]
+        gateway_proxy_protocol: bool,
+        /// Proxies whose Forwarded / X-Forwarded-For headers the gateway believes (IPs, comma separated, or "none")
+        #[arg(long, default_value_t = TrustedProxies::default())]
+        gateway_trusted_proxies: TrustedProxies,
+        /// Seconds a raw TCP connection through the gateway may go without traffic (0: no limit)
+        #[arg(long, default_value_t = DEFAULT_TUNNEL_IDLE.as_secs())]
+        gateway_idle_timeout: u64,
+        /// Seconds a raw TCP connection through the gateway may stay open at most (0: no limit)
+        #[arg(long, default_value_t = 0)]
+        gateway_max_duration: u64,
+        /// File holding the token the gateway presents to the nodes for its HTTP API, e.g. a
+        /// client token of --access-file (default: the cluster token)
+        #[arg(long)]
+        gateway_token_file: Option<PathBuf>,
+        /// Largest upload in bytes the gateway takes, answering 413 above it (0: no limit)
+        #[arg(long, default_value_t = DEFAULT_MAX_UPLOAD)]
+        gateway_max_upload: u64,
+        /// Longest request line and headers in bytes the gateway reads, answering 431 above it
+        #[arg(long, default_value_t = DEFAULT_MAX_HEADER,
+              value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+        gateway_max_header: usize,
         /// Max file size in bytes. 0 to disable. Defaults to 1 gigabyte.
         #[arg(short, long, default_value_t = 1_000_000_000u64)]
         file_size: u64,
+        /// Root directory for the nodes' storage (each node's goes to <data-dir>/<port>/)
+        #[arg(long, default_value = "nodes")]
+        data_dir: PathBuf,
+        /// Have every node answer health checks over UDP as well (see `run --udp-heartbeat`)
+        #[arg(long)]
+        udp_heartbeat: bool,
+        /// File holding the secret the ring's internal commands need (default: $OUROBOROS_CLUSTER_TOKEN)
+        #[arg(long)]
+        cluster_token_file: Option<PathBuf>,
+        /// File of `<role> <token>` lines restricting client commands to tokens with a role
+        /// allowing them (reader, writer, admin); needs a cluster token
+        #[arg(long)]
+        access_file: Option<PathBuf>,
+        /// Check the ring with `verify-ring` once it is wired, and stop it if a check fails
+        #[arg(long)]
+        verify: bool,
+        /// Print what would be started and exit without spawning anything; "json" prints it as
+        /// JSON whatever --output says
+        #[arg(long, value_name = "FORMAT", num_args = 0..=1)]
+        dry_run: Option<Option<OutputFormat>>,
+        #[command(flatten)]
+        respawn: RespawnOpts,
+        #[command(flatten)]
+        tcp: TcpOpts,
+    },
+
+    /// Check a running ring: walk, netmap, pings and file chunks. Exits with an
+    /// error if a check fails.
+    VerifyRing {
+        /// Address of any node of the ring ("7000" means 127.0.0.1:7000)
+        #[arg(long, default_value = "127.0.0.1:7000")]
+        addr: String,
+    },
+
+    /// Download a file chunk by chunk from its holders, checking every chunk
+    /// against the file's manifest before the output file is written
+    Pull {
+        /// Name of the file in the ring
+        name: String,
+        /// Address of any node of the ring ("7000" means 127.0.0.1:7000)
+        #[arg(long, default_value = "127.0.0.1:7000")]
+        addr: String,
+        /// Where to write the file (default: its name, in the current directory)
+        #[arg(short = 'o', long = "out")]
+        out: Option<PathBuf>,
+        /// Fetch every chunk at once instead of one after the other
+        #[arg(long)]
+        parallel: bool,
+    },
+
+    /// Update a stored file from a local one, sending only the blocks that changed
+    Sync {
+        /// Local file to send
+        path: PathBuf,
+        /// Address of any node of the ring ("7000" means 127.0.0.1:7000)
+        #[arg(long, default_value = "127.0.0.1:7000")]
+        addr: String,
+        /// Name of the file in the ring (default: the local file's name)
+        #[arg(long)]
+        name: Option<String>,
+        /// Size (bytes) of the blocks compared
+        #[arg(long, default_value_t = delta::DEFAULT_BLOCK_SIZE)]
+        block_size: u64,
+    },
+
+    /// Push every file of a directory tree to the ring, named by its path
+    Import {
+        /// Directory to push
+        dir: PathBuf,
+        /// Address of any node of the ring ("7000" means 127.0.0.1:7000)
+        #[arg(long, default_value = "127.0.0.1:7000")]
+        addr: String,
+        /// Store the files under this directory of the ring (default: at the top)
+        #[arg(long, default_value = "")]
+        prefix: String,
+        /// Files pushed at once
+        #[arg(long, default_value_t = 4usize)]
+        parallel: usize,
+    },
+
+    /// Pull every stored file under a prefix into a local directory, checking each chunk
+    Export {
+        /// Directory of the ring to pull ("" for every file)
+        prefix: String,
+        /// Local directory the files are written below
+        dir: PathBuf,
+        /// Address of any node of the ring ("7000" means 127.0.0.1:7000)
+        #[arg(long, default_value = "127.0.0.1:7000")]
+        addr: String,
+        /// Files pulled at once
+        #[arg(long, default_value_t = 4usize)]
+        parallel: usize,
+    },
+
+    /// Push a directory's files to the ring, then again whenever they change
+    Watch {
+        /// Directory to watch (its top level only)
+        dir: PathBuf,
+        /// Address of any node of the ring ("7000" means 127.0.0.1:7000)
+        #[arg(long, default_value = "127.0.0.1:7000")]
+        addr: String,
+        /// Send changed files with FILE SYNC: only the blocks that changed
+        #[arg(long)]
+        delta: bool,
+        /// Size (bytes) of the blocks compared with --delta
+        #[arg(long, default_value_t = delta::DEFAULT_BLOCK_SIZE)]
+        block_size: u64,
+        /// Quiet time (ms) after the last change before files are sent
+        #[arg(long, default_value_t = 500u64)]
+        debounce: u64,
+    },
+
+    /// Stop a node started with `run --pid-file`, waiting for it to exit
+    Stop {
+        /// PID file the node was started with
+        #[arg(long)]
+        pid_file: PathBuf,
+        /// Time (ms) to wait for the node to exit
+        #[arg(long, default_value_t = 30_000u64)]
+        timeout: u64,
+    },
+
+    /// Show the logs of a ring's nodes, merged and prefixed with each node's port
+    Logs {
+        /// The `--log-file` path the ring was started with (`set-network --log-file`)
+        path: PathBuf,
+        /// Only show this node's log
+        #[arg(long)]
+        node: Option<u16>,
+        /// Keep printing new lines as the nodes write them
+        #[arg(short, long)]
+        follow: bool,
+        /// Lines of history shown per node
+        #[arg(short = 'n', long, default_value_t = 10usize)]
+        lines: usize,
+        /// Only events at least this severe: error, warn, info, debug or trace
+        #[arg(long)]
+        level: Option<tracing::Level>,
     },
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
-    // Initialize tracing subscriber
-    fmt()
-        .with_timer(fmt::time::UtcTime::rfc_3339()) // Adds RFC 3339 timestamps
-        .with_env_filter(EnvFilter::from_default_env()) // Use RUST_LOG env var
-        .with_target(true)
-        .init();
+/// What nodes do when they find their neighbor dead
+#[derive(Args, Clone)]
+struct RespawnOpts {
+    /// Respawn dead neighbors: "always" or "never"
+    #[arg(long, default_value_t = RespawnMode::Always)]
+    respawn: RespawnMode,
+    /// Respawns allowed per peer within 10 minutes. 0 for unlimited.
+    #[arg(long, default_value_t = 0u32)]
+    max_respawns: u32,
+    /// Delay (ms) before respawning the same peer again; doubles on every retry
+    #[arg(long, default_value_t = 1000u64)]
+    respawn_backoff: u64,
+    /// Shell command run when a neighbor dies (details in OUROBOROS_* env vars)
+    #[arg(long)]
+    on_death_exec: Option<String>,
+    /// http:// URL that receives a JSON POST when a neighbor dies
+    #[arg(long)]
+    on_death_webhook: Option<String>,
+}
+
+impl RespawnOpts {
+    fn apply(&self, builder: NodeBuilder) -> NodeBuilder {
+        builder
+            .respawn_policy(
+                self.respawn,
+                self.max_respawns,
+                Duration::from_millis(self.respawn_backoff),
+            )
+            .death_hooks(DeathHooks {
+                exec: self.on_death_exec.clone(),
+                webhook: self.on_death_webhook.clone(),
+            })
+    }
+
+    /// Same options as `run` arguments, for spawned nodes
+    fn child_args(&self) -> Vec<String> {
+        let mut args = vec![
+            "--respawn".to_string(),
+            self.respawn.to_string(),
+            "--max-respawns".to_string(),
+            self.max_respawns.to_string(),
+            "--respawn-backoff".to_string(),
+            self.respawn_backoff.to_string(),
+        ];
+        if let Some(cmd) = &self.on_death_exec {
+            args.extend(["--on-death-exec".to_string(), cmd.clone()]);
+        }
+        if let Some(url) = &self.on_death_webhook {
+            args.extend(["--on-death-webhook".to_string(), url.clone()]);
+        }
+        args
+    }
+}
+
+/// Where and how the process logs
+#[derive(Args, Clone)]
+struct LogOpts {
+    /// Log line format: "text" or "json" (one object per line)
+    #[arg(long, global = true, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+    /// Log to this file instead of stdout; `{port}` is replaced by the node's port
+    #[arg(long, global = true)]
+    log_file: Option<PathBuf>,
+    /// Size (bytes) at which the log file is rotated. 0 never rotates.
+    #[arg(long, global = true, default_value_t = 10_000_000u64)]
+    log_max_size: u64,
+    /// Rotated log files kept (`<file>.1` is the newest)
+    #[arg(long, global = true, default_value_t = 5u32)]
+    log_max_files: u32,
+}
+
+impl LogOpts {
+    fn options(&self) -> LogOptions {
+        LogOptions {
+            format: self.log_format,
+            file: self.log_file.clone(),
+            max_size: self.log_max_size,
+            max_files: self.log_max_files,
+        }
+    }
+}
+
+/// Socket options for the listener and peer connections
+#[derive(Args, Clone)]
+struct TcpOpts {
+    /// Disable Nagle's algorithm so short control lines are sent immediately
+    #[arg(long, default_value_t = true, action = clap::ArgAction::Set)]
+    tcp_nodelay: bool,
+    /// Idle time (s) before TCP keepalive probes are sent. 0 to disable.
+    #[arg(long, default_value_t = 60u64)]
+    tcp_keepalive: u64,
+    /// Socket send buffer size in bytes (OS default if omitted)
+    #[arg(long)]
+    tcp_send_buffer: Option<u32>,
+    /// Socket receive buffer size in bytes (OS default if omitted)
+    #[arg(long)]
+    tcp_recv_buffer: Option<u32>,
+}
+
+impl TcpOpts {
+    fn options(&self) -> TcpOptions {
+        TcpOptions {
+            nodelay: self.tcp_nodelay,
+            keepalive: (self.tcp_keepalive > 0).then(|| Duration::from_secs(self.tcp_keepalive)),
+            send_buffer: self.tcp_send_buffer,
+            recv_buffer: self.tcp_recv_buffer,
+        }
+    }
+}
 
+#[tokio::main]
+async fn main() -> ExitCode {
     let cli = Cli::parse();
+    let output = cli.output;
+    let Err(e) = run(cli).await else {
+        return ExitCode::SUCCESS;
+    };
+    let code = e
+        .downcast_ref::<BootstrapError>()
+        .map_or(1, BootstrapError::exit_code);
+    match output {
+        OutputFormat::Text => eprintln!("Error: {}", e),
+        // Scripts read a failure from stdout like any other result
+        OutputFormat::Json => println!(
+            "{}",
+            json_line("error", &json!({"error": e.to_string(), "code": code}))
+        ),
+    }
+    ExitCode::from(code)
+}
+
+async fn run(cli: Cli) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let output = cli.output;
+    let log = cli.log.options();
+
+    // A daemonized node is this same command, run again without the flag
+    if let Cmd::Run {
+        daemonize: true,
+        pid_file: Some(pid_file),
+        ..
+    } = &cli.command
+    {
+        if log.file.is_none() {
+            return Err("--daemonize needs --log-file: a detached node has no terminal".into());
+        }
+        let args = env::args_os()
+            .skip(1)
+            .filter(|arg| arg != daemon::DAEMONIZE_FLAG)
+            .collect();
+        let pid = daemon::detach(args, pid_file).await?;
+        output.emit(
+            "started",
+            format!("STARTED pid={} pid-file={}", pid, pid_file.display()),
+            &json!({"pid": pid, "pid_file": pid_file}),
+        );
+        return Ok(());
+    }
+
+    // Initialize tracing subscriber, keeping a handle to swap the filter at runtime.
+    // A node's log file is named after its port, set-network's after "network".
+    let log_label = match &cli.command {
+        Cmd::Run { addr, port, .. } => {
+            let bind = resolve_listen_addr(addr.clone(), *port);
+            port_str(&bind).to_string()
+        }
+        Cmd::SetNetwork { .. } => "network".to_string(),
+        Cmd::VerifyRing { .. } => "verify".to_string(),
+        Cmd::Pull { .. } => "pull".to_string(),
+        Cmd::Sync { .. } => "sync".to_string(),
+        Cmd::Import { .. } => "import".to_string(),
+        Cmd::Export { .. } => "export".to_string(),
+        Cmd::Watch { .. } => "watch".to_string(),
+        Cmd::Stop { .. } => "stop".to_string(),
+        Cmd::Logs { .. } => "logs".to_string(),
+    };
+    let (filter_handle, log_buffer) = logging::init(&log, &log_label)?;
+
     match cli.command {
         Cmd::Run {
             addr,
             port,
             wait_time,
             file_size,
+            data_dir,
+            health_timeout,
+            walk_timeout,
+            heal_timeout,
+            config,
+            udp_heartbeat,
+            label,
+            failure_domain,
+            replication,
+            ring_id,
+            relay,
+            cluster_token_file,
+            access_file,
+            daemonize: _,
+            pid_file,
+            respawn,
+            tcp,
         } => {
             let bind = resolve_listen_addr(addr, port);
-            let gossip_interval = Duration::from_millis(wait_time);
-            run(&bind, gossip_interval, file_size).await
+            let mut builder = NodeBuilder::new()
+                .bind(bind)
+                .gossip(Duration::from_millis(wait_time))
+                .health_timeout(Duration::from_millis(health_timeout))
+                .walk_timeout(Duration::from_millis(walk_timeout))
+                .heal_timeout(Duration::from_millis(heal_timeout))
+                .file_size(file_size)
+                .tcp(tcp.options())
+                .udp_heartbeat(udp_heartbeat)
+                .labels(label.unwrap_or_default())
+                .failure_domain(failure_domain)
+                .replication(replication)
+                .logging(log)
+                .log_buffer(log_buffer)
+                .log_filter_hook(move |directive| {
+                    let filter = EnvFilter::try_new(directive).map_err(|e| e.to_string())?;
+                    filter_handle.reload(filter).map_err(|e| e.to_string())
+                });
+            builder = respawn.apply(builder);
+            if let Some(dir) = data_dir {
+                builder = builder.data_dir(dir);
+            }
+            if let Some(id) = ring_id {
+                builder = builder.ring_id(id);
+            }
+            if let Some(addr) = relay {
+                builder = builder.relay(addr);
+            }
+            if let Some(token) = cluster_token(cluster_token_file.as_deref())? {
+                builder = builder.cluster_token(token);
+            }
+            if let Some(path) = access_file {
+                builder = builder.access_list(AccessList::read(&path)?);
+            }
+            if let Some(path) = config {
+                builder = builder.config_file(path);
+            }
+            let handle = builder.build()?;
+            handle.start().await?;
+            let _pid_file = pid_file.as_deref().map(PidFile::create).transpose()?;
+            tokio::select! {
+                res = handle.wait() => res,
+                _ = daemon::terminated() => {
+                    handle.shutdown();
+                    Ok(())
+                }
+            }
         }
         Cmd::SetNetwork {
             nodes,
@@ -97,8 +569,28 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
             wait_time,
             overwrite_nodes_dir,
             dns_port,
+            gateway_compress,
+            gateway_proxy_protocol,
+            gateway_trusted_proxies,
+            gateway_idle_timeout,
+            gateway_max_duration,
+            gateway_token_file,
+            gateway_max_upload,
+            gateway_max_header,
             file_size,
+            data_dir,
+            udp_heartbeat,
+            cluster_token_file,
+            access_file,
+            verify,
+            dry_run,
+            respawn,
+            tcp,
         } => {
+            let (dry_run, plan_output) = match dry_run {
+                Some(format) => (true, format.unwrap_or(output)),
+                None => (false, output),
+            };
             set_network(
                 nodes,
                 base_port,
@@ -108,10 +600,182 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
                 wait_time,
                 overwrite_nodes_dir,
                 dns_port,
+                gateway_compress,
+                ProxyOptions {
+                    proxy_protocol: gateway_proxy_protocol,
+                    trusted: gateway_trusted_proxies,
+                },
+                TunnelTimeouts {
+                    idle: (gateway_idle_timeout > 0)
+                        .then(|| Duration::from_secs(gateway_idle_timeout)),
+                    max: (gateway_max_duration > 0)
+                        .then(|| Duration::from_secs(gateway_max_duration)),
+                },
+                gateway_token_file
+                    .as_deref()
+                    .map(ClusterToken::read)
+                    .transpose()?,
+                RequestLimits {
+                    max_upload: (gateway_max_upload > 0).then_some(gateway_max_upload),
+                    max_header: gateway_max_header,
+                },
                 file_size,
+                &data_dir,
+                udp_heartbeat,
+                cluster_token(cluster_token_file.as_deref())?,
+                access_file.as_deref().map(AccessList::read).transpose()?,
+                verify,
+                dry_run,
+                plan_output,
+                &respawn,
+                &tcp.options(),
+                &log.per_node(),
             )
             .await
         }
+        Cmd::VerifyRing { addr } => {
+            let report = ring_verify::verify_ring(&normalize_addr(addr)).await;
+            output.emit("verify", &report, &report);
+            if !report.ok {
+                return Err("ring verification failed".into());
+            }
+            Ok(())
+        }
+        Cmd::Pull {
+            name,
+            addr,
+            out,
+            parallel,
+        } => {
+            let file = pull::verified_pull(&normalize_addr(addr), &name, parallel).await?;
+            let out = out.unwrap_or_else(|| {
+                Path::new(&name)
+                    .file_name()
+                    .map_or_else(|| PathBuf::from("pulled"), PathBuf::from)
+            });
+            fs::write(&out, &file.data)?;
+            let summary = format!(
+                "PULLED {} bytes={} chunks={} repaired={} to {}",
+                name,
+                file.data.len(),
+                file.chunks.len(),
+                file.repaired(),
+                out.display()
+            );
+            // Text lists the chunks line by line, JSON within the one result
+            let text: Vec<String> = file.chunks.iter().map(ToString::to_string).collect();
+            output.emit(
+                "pulled",
+                format!("{}\n{}", text.join("\n"), summary).trim_start(),
+                &json!({
+                    "name": name,
+                    "bytes": file.data.len(),
+                    "chunks": file.chunks,
+                    "repaired": file.repaired(),
+                    "path": out,
+                }),
+            );
+            Ok(())
+        }
+        Cmd::Sync {
+            path,
+            addr,
+            name,
+            block_size,
+        } => {
+            let name = match name {
+                Some(name) => name,
+                None => path
+                    .file_name()
+                    .ok_or("the path names no file")?
+                    .to_string_lossy()
+                    .into_owned(),
+            };
+            let data = fs::read(&path)?;
+            let report = delta::sync_file(&normalize_addr(addr), &name, &data, block_size).await?;
+            output.emit("synced", &report.summary, &report);
+            Ok(())
+        }
+        Cmd::Import {
+            dir,
+            addr,
+            prefix,
+            parallel,
+        } => {
+            let report =
+                bulk::import(&normalize_addr(addr), &dir, &prefix, parallel, output).await?;
+            output.emit(
+                "imported",
+                format!("IMPORTED {}", report.summary()),
+                &report.json(),
+            );
+            bulk_result(&report)
+        }
+        Cmd::Export {
+            prefix,
+            dir,
+            addr,
+            parallel,
+        } => {
+            let report =
+                bulk::export(&normalize_addr(addr), &prefix, &dir, parallel, output).await?;
+            output.emit(
+                "exported",
+                format!("EXPORTED {}", report.summary()),
+                &report.json(),
+            );
+            bulk_result(&report)
+        }
+        Cmd::Watch {
+            dir,
+            addr,
+            delta,
+            block_size,
+            debounce,
+        } => {
+            let opts = WatchOptions {
+                dir,
+                addr: normalize_addr(addr),
+                delta,
+                block_size,
+                debounce: Duration::from_millis(debounce),
+                output,
+            };
+            watch::watch(&opts).await
+        }
+        Cmd::Stop { pid_file, timeout } => {
+            match daemon::stop(&pid_file, Duration::from_millis(timeout)).await? {
+                Some(pid) => output.emit(
+                    "stopped",
+                    format!("STOPPED pid={}", pid),
+                    &json!({"pid": pid}),
+                ),
+                None => output.emit(
+                    "not-running",
+                    format!("NOT RUNNING (stale {} removed)", pid_file.display()),
+                    &json!({"pid_file": pid_file}),
+                ),
+            }
+            Ok(())
+        }
+        Cmd::Logs {
+            path,
+            node,
+            follow,
+            lines,
+            level,
+        } => {
+            let query = LogsQuery {
+                path,
+                node,
+                lines,
+                level,
+                follow,
+                output,
+            };
+            logs::show(&query).await?;
+            Ok(())
+        }
     }
 }
 
@@ -135,17 +799,317 @@ fn resolve_listen_addr(addr: Option<String>, port: Option<u16>) -> String {
     "127.0.0.1:9000".to_string()
 }
 
-/// Accept "7001" or "127.0.0.1:7001"
+/// Accept "7001", "127.0.0.1:7001" or "[::1]:7001"
+/// Fails an import or export in which some file failed
+fn bulk_result(report: &bulk::BulkReport) -> Result<(), Box<dyn Error + Send + Sync>> {
+    match report.failed.len() {
+        0 => Ok(()),
+        n => Err(format!("{} of {} files failed", n, n + report.files).into()),
+    }
+}
+
 fn normalize_addr(raw: String) -> String {
-    if raw.contains(':') {
-        raw
+    if raw.trim().parse::<u16>().is_ok() {
+        join_host_port(DEFAULT_HOST, raw.trim())
     } else {
-        format!("127.0.0.1:{raw}")
+        raw
     }
 }
 
 /* -------------------------- set-network ------------------------- */
 
+/// What `set-network` starts, in order: the nodes, the `NODE NEXT` calls
+/// wiring them, the gateway, then discovery and the topology walk from `start`
+#[derive(Serialize)]
+struct NetworkPlan {
+    host: String,
+    base_port: u16,
+    end_port: u16,
+    data_dir: PathBuf,
+    /// "create", "reuse" or "wipe" (`--overwrite-nodes-dir`)
+    data_dir_action: &'static str,
+    nodes: Vec<PlannedNode>,
+    wiring: Vec<PlannedLink>,
+    gateway: Option<PlannedGateway>,
+    start: String,
+    verify: bool,
+}
+
+#[derive(Serialize)]
+struct PlannedNode {
+    addr: String,
+    data_dir: PathBuf,
+    /// `None` logs to stdout
+    log_file: Option<PathBuf>,
+    /// The port cannot be bound: something already listens on it
+    in_use: bool,
+    /// Program and arguments the node is spawned with
+    command: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct PlannedLink {
+    from: String,
+    to: String,
+}
+
+#[derive(Serialize)]
+struct PlannedGateway {
+    addr: String,
+    compress: String,
+    proxy_protocol: bool,
+    trusted_proxies: String,
+    /// Seconds, 0 for no limit
+    idle_timeout: u64,
+    /// Seconds, 0 for no limit
+    max_duration: u64,
+    /// Bytes, 0 for no limit
+    max_upload: u64,
+    /// Bytes
+    max_header: usize,
+    in_use: bool,
+}
+
+impl NetworkPlan {
+    /// Plans a ring of `nodes` nodes from `base_port`, each run with `node_args`.
+    /// Fails if the ports run past 65535 or the gateway takes a node's port.
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        nodes: u16,
+        base_port: u16,
+        host: &str,
+        nodes_root: &Path,
+        overwrite_nodes_dir: bool,
+        exe: &Path,
+        node_args: &[String],
+        log: &LogOptions,
+        gateway: Option<(
+            u16,
+            &Compression,
+            &ProxyOptions,
+            &TunnelTimeouts,
+            &RequestLimits,
+        )>,
+        verify: bool,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let end_port = base_port.checked_add(nodes - 1).ok_or_else(|| {
+            format!(
+                "{} nodes from port {} run past port 65535",
+                nodes, base_port
+            )
+        })?;
+        let ports = base_port..=end_port;
+        if let Some((port, ..)) = gateway
+            && ports.contains(&port)
+        {
+            return Err(format!("--dns-port {} is one of the nodes' ports", port).into());
+        }
+
+        let log = log.per_node();
+        let nodes: Vec<PlannedNode> = ports
+            .clone()
+            .map(|port| {
+                let addr = join_host_port(host, port);
+                let data_dir = nodes_root.join(port.to_string());
+                let mut command = vec![
+                    exe.display().to_string(),
+                    "run".to_string(),
+                    "--addr".to_string(),
+                    addr.clone(),
+                    "--data-dir".to_string(),
+                    data_dir.display().to_string(),
+                ];
+                command.extend(node_args.iter().cloned());
+                command.extend(log.cli_args());
+                PlannedNode {
+                    in_use: port_in_use(&addr),
+                    data_dir,
+                    log_file: log.file_for(&port.to_string()),
+                    addr,
+                    command,
+                }
+            })
+            .collect();
+        let wiring = (0..nodes.len())
+            .map(|i| PlannedLink {
+                from: nodes[i].addr.clone(),
+                to: nodes[(i + 1) % nodes.len()].addr.clone(),
+            })
+            .collect();
+        let gateway = gateway.map(|(port, compress, proxy, tunnel, limits)| {
+            let addr = join_host_port(host, port);
+            PlannedGateway {
+                in_use: port_in_use(&addr),
+                addr,
+                compress: compress.to_string(),
+                proxy_protocol: proxy.proxy_protocol,
+                trusted_proxies: proxy.trusted.to_string(),
+                idle_timeout: tunnel.idle.map_or(0, |idle| idle.as_secs()),
+                max_duration: tunnel.max.map_or(0, |max| max.as_secs()),
+                max_upload: limits.max_upload.unwrap_or(0),
+                max_header: limits.max_header,
+            }
+        });
+        let data_dir_action = match (nodes_root.exists(), overwrite_nodes_dir) {
+            (false, _) => "create",
+            (true, false) => "reuse",
+            (true, true) => "wipe",
+        };
+        Ok(Self {
+            host: host.to_string(),
+            base_port,
+            end_port,
+            data_dir: nodes_root.to_path_buf(),
+            data_dir_action,
+            start: nodes[0].addr.clone(),
+            nodes,
+            wiring,
+            gateway,
+            verify,
+        })
+    }
+}
+
+/// One line per step:
+///
+/// ```text
+/// PLAN nodes=3 ports=7000-7002 host=127.0.0.1
+/// DATA-DIR nodes create
+/// NODE 127.0.0.1:7000 data=nodes/7000 log=stdout port=free
+///   /usr/bin/ouroboros_fs run --addr 127.0.0.1:7000 ...
+/// WIRE 127.0.0.1:7000 -> 127.0.0.1:7001
+/// GATEWAY 127.0.0.1:8000 compress=... proxy-protocol=false ... idle-timeout=300s max-duration=0s port=free
+/// START 127.0.0.1:7000 discover walk verify
+/// ```
+impl fmt::Display for NetworkPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let port_state = |in_use: bool| if in_use { "in-use" } else { "free" };
+        writeln!(
+            f,
+            "PLAN nodes={} ports={}-{} host={}",
+            self.nodes.len(),
+            self.base_port,
+            self.end_port,
+            self.host
+        )?;
+        writeln!(
+            f,
+            "DATA-DIR {} {}",
+            self.data_dir.display(),
+            self.data_dir_action
+        )?;
+        for node in &self.nodes {
+            let log = node
+                .log_file
+                .as_ref()
+                .map_or("stdout".to_string(), |file| file.display().to_string());
+            writeln!(
+                f,
+                "NODE {} data={} log={} port={}",
+                node.addr,
+                node.data_dir.display(),
+                log,
+                port_state(node.in_use)
+            )?;
+            writeln!(f, "  {}", node.command.join(" "))?;
+        }
+        for link in &self.wiring {
+            writeln!(f, "WIRE {} -> {}", link.from, link.to)?;
+        }
+        if let Some(gateway) = &self.gateway {
+            writeln!(
+                f,
+                "GATEWAY {} compress={} proxy-protocol={} trusted-proxies={} idle-timeout={}s \
+                 max-duration={}s max-upload={} max-header={} port={}",
+                gateway.addr,
+                gateway.compress,
+                gateway.proxy_protocol,
+                gateway.trusted_proxies,
+                gateway.idle_timeout,
+                gateway.max_duration,
+                gateway.max_upload,
+                gateway.max_header,
+                port_state(gateway.in_use)
+            )?;
+        }
+        let verify = if self.verify { " verify" } else { "" };
+        writeln!(f, "START {} discover walk{}", self.start, verify)
+    }
+}
+
+impl NetworkPlan {
+    /// Addresses of the plan that cannot be bound, with the reason
+    fn occupied(&self) -> Vec<String> {
+        let gateway = self.gateway.as_ref().map(|g| &g.addr);
+        self.nodes
+            .iter()
+            .map(|n| &n.addr)
+            .chain(gateway)
+            .filter_map(|addr| probe_port(addr).err().map(|e| format!("{} ({})", addr, e)))
+            .collect()
+    }
+}
+
+/// Whether something already listens on `addr`
+fn port_in_use(addr: &str) -> bool {
+    probe_port(addr).is_err()
+}
+
+/// Binds `addr` and lets it go again, to find out whether a node can
+fn probe_port(addr: &str) -> std::io::Result<()> {
+    std::net::TcpListener::bind(addr).map(drop)
+}
+
+/// Why `set-network` could not bring its ring up. Each has its own exit
+/// status, so that orchestration tooling can tell them apart.
+#[derive(Debug)]
+enum BootstrapError {
+    /// A node or the gateway could not listen on its port
+    Bind(String),
+    /// A node did not take its successor
+    Wiring(String),
+    /// The nodes run, but netmap discovery, the topology walk or the
+    /// verification of the ring failed
+    PartialRing(String),
+    /// The gateway stopped
+    Gateway(String),
+}
+
+impl BootstrapError {
+    /// Process exit status: 1 is any other error, 2 a command-line error
+    fn exit_code(&self) -> u8 {
+        match self {
+            BootstrapError::Bind(_) => 3,
+            BootstrapError::Wiring(_) => 4,
+            BootstrapError::PartialRing(_) => 5,
+            BootstrapError::Gateway(_) => 6,
+        }
+    }
+
+    /// The gateway server task ended, which it only does on an error
+    fn gateway_stopped(res: Result<std::io::Result<()>, tokio::task::JoinError>) -> Self {
+        match res {
+            Ok(Err(e)) => BootstrapError::Gateway(e.to_string()),
+            Ok(Ok(())) => BootstrapError::Gateway("server stopped".to_string()),
+            Err(e) => BootstrapError::Gateway(e.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for BootstrapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BootstrapError::Bind(e) => write!(f, "bind failure: {}", e),
+            BootstrapError::Wiring(e) => write!(f, "wiring failure: {}", e),
+            BootstrapError::PartialRing(e) => write!(f, "partial ring: {}", e),
+            BootstrapError::Gateway(e) => write!(f, "gateway failure: {}", e),
+        }
+    }
+}
+
+impl Error for BootstrapError {}
+
+#[allow(clippy::too_many_arguments)]
 async fn set_network(
     nodes: u16,
     base_port: u16,
@@ -155,13 +1119,81 @@ async fn set_network(
     wait_time: u64,
     overwrite_nodes_dir: bool,
     dns_port: Option<u16>,
+    gateway_compress: Compression,
+    gateway_proxy: ProxyOptions,
+    gateway_tunnel: TunnelTimeouts,
+    gateway_token: Option<ClusterToken>,
+    gateway_limits: RequestLimits,
     max_file_size: u64,
+    nodes_root: &Path,
+    udp_heartbeat: bool,
+    cluster_token: Option<ClusterToken>,
+    access: Option<AccessList>,
+    verify: bool,
+    dry_run: bool,
+    output: OutputFormat,
+    respawn: &RespawnOpts,
+    tcp: &TcpOptions,
+    log: &LogOptions,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
-    if nodes == 0 {
-        tracing::warn!("--nodes must be >= 1");
+    let exe = current_exe()?;
+    let mut node_args = vec![
+        "--wait-time".to_string(),
+        wait_time.to_string(),
+        "--file-size".to_string(),
+        max_file_size.to_string(),
+    ];
+    node_args.extend(respawn.child_args());
+    node_args.extend(tcp.cli_args());
+    if udp_heartbeat {
+        node_args.push("--udp-heartbeat".to_string());
+    }
+    if let Some(access) = &access {
+        if cluster_token.is_none() {
+            return Err("--access-file needs a cluster token".into());
+        }
+        node_args.push("--access-file".to_string());
+        node_args.push(access.path.display().to_string());
+    }
+    // Wiring, walks, --verify and the gateway present the cluster token
+    if let Some(token) = &cluster_token {
+        auth::set_client_token(token);
+    }
+    let plan = NetworkPlan::new(
+        nodes,
+        base_port,
+        host,
+        nodes_root,
+        overwrite_nodes_dir,
+        &exe,
+        &node_args,
+        log,
+        dns_port.map(|port| {
+            (
+                port,
+                &gateway_compress,
+                &gateway_proxy,
+                &gateway_tunnel,
+                &gateway_limits,
+            )
+        }),
+        verify,
+    )?;
+    if dry_run {
+        match output {
+            OutputFormat::Text => print!("{}", plan),
+            OutputFormat::Json => println!("{}", json_line("plan", &plan)),
+        }
         return Ok(());
     }
 
+    // Fail fast rather than spawn nodes that die on bind
+    let occupied = plan.occupied();
+    if !occupied.is_empty() {
+        let e = format!("ports already in use: {}", occupied.join(", "));
+        return Err(BootstrapError::Bind(e).into());
+    }
+
     // Make this parent `set-network` process a new process group leader, then
     // all children spawned by it (and their children) will inherit this PGID.
     #[cfg(unix)]
@@ -179,14 +1211,12 @@ async fn set_network(
     }
 
     // Prepare a fresh "nodes/" directory
-    let nodes_root = Path::new("nodes");
     if nodes_root.exists() && overwrite_nodes_dir {
         fs::remove_dir_all(nodes_root)?;
-        tracing::info!("Created a fresh 'nodes' directory");
+        tracing::info!(dir = %nodes_root.display(), "Created a fresh nodes directory");
     }
     fs::create_dir_all(nodes_root)?;
 
-    let exe = current_exe()?;
     tracing::info!(
         nodes,
         host,
@@ -198,98 +1228,158 @@ async fn set_network(
 
     // 1. Spawn children
     let mut children: Vec<Child> = Vec::with_capacity(nodes as usize);
-    for i in 0..nodes {
-        let port = base_port + i;
-        let addr = format!("{host}:{port}");
-        let mut cmd = Command::new(&exe);
-        cmd.arg("run")
-            .arg("--addr")
-            .arg(&addr)
-            .arg("--wait-time")
-            .arg(wait_time.to_string())
-            .arg("--file-size")
-            .arg(max_file_size.to_string());
-
-        let child = cmd.spawn()?;
+    for node in &plan.nodes {
+        let mut command = Command::new(&exe);
+        command.args(&node.command[1..]);
+        // A token file is passed by path, so nodes can read it again on
+        // `NODE KEYS ROTATE`; any other token goes in the environment rather
+        // than the arguments, out of sight of `ps`
+        if let Some(token) = &cluster_token {
+            match token.source() {
+                Some(SecretSource::File(path)) => {
+                    command.arg("--cluster-token-file").arg(path);
+                }
+                _ => {
+                    command.env(CLUSTER_TOKEN_ENV, token.export().expose());
+                }
+            }
+        }
+        let child = command.spawn()?;
         children.push(child);
-        tracing::info!(addr = %addr, "Spawned node");
+        tracing::info!(addr = %node.addr, "Spawned node");
     }
 
-    // 2. Give nodes a moment to bind
-    if extra_wait > Duration::from_millis(0) {
-        tokio::time::sleep(extra_wait).await;
-    }
-
-    // 3. Wait until all ports are listening
-    for i in 0..nodes {
-        let port = base_port + i;
-        wait_until_listening(host, port, Duration::from_secs(5)).await?;
-        tracing::info!(host, port, "Node is listening");
-    }
-
-    // 4. Wire the ring
-    for i in 0..nodes {
-        let this_port = base_port + i;
-        let next_port = if i + 1 == nodes {
-            base_port
-        } else {
-            base_port + i + 1
-        };
-        let this_addr = format!("{host}:{this_port}");
-        let next_addr = format!("{host}:{next_port}");
-        send_node_next(&this_addr, &next_addr).await?;
-        tracing::info!(from = %this_addr, to = %next_addr, "Wired node");
-    }
+    let start_addr = join_host_port(host, base_port);
+    let up = async {
+        // 2. Give nodes a moment to bind
+        if extra_wait > Duration::from_millis(0) {
+            tokio::time::sleep(extra_wait).await;
+        }
 
-    tracing::info!("Ring wired successfully.");
+        // 3. Wait until all ports are listening
+        for node in &plan.nodes {
+            wait_until_listening(&node.addr, Duration::from_secs(5))
+                .await
+                .map_err(|e| BootstrapError::Bind(e.to_string()))?;
+            tracing::info!(addr = %node.addr, "Node is listening");
+        }
 
-    // 5. Start the DNS Gateway if requested
-    if let Some(port) = dns_port {
-        // Create the list of all node addresses
-        let node_addrs: Vec<String> = (0..nodes)
-            .map(|i| format!("{}:{}", host, base_port + i))
-            .collect();
+        // 4. Wire the ring
+        for link in &plan.wiring {
+            send_node_next(&link.from, &link.to)
+                .await
+                .map_err(|e| BootstrapError::Wiring(e.to_string()))?;
+            tracing::info!(from = %link.from, to = %link.to, "Wired node");
+        }
 
-        let gateway = ouroboros_fs::Gateway::new(node_addrs);
+        tracing::info!("Ring wired successfully.");
 
-        // Spawn the main gateway server
-        let server_gateway = Arc::clone(&gateway);
-        let dns_listen_addr = format!("{}:{}", host, port);
-        tokio::spawn(async move {
-            if let Err(e) = server_gateway.run_server(dns_listen_addr).await {
-                tracing::error!(error = ?e, "Gateway server failed");
+        // 5. Start the DNS Gateway if requested
+        let gateway = match &plan.gateway {
+            Some(planned) => {
+                let node_addrs = plan.nodes.iter().map(|node| node.addr.clone()).collect();
+                let gateway = ouroboros_fs::Gateway::with_options(
+                    node_addrs,
+                    gateway_compress,
+                    gateway_proxy,
+                    gateway_tunnel,
+                    gateway_token,
+                    gateway_limits,
+                );
+                let mut server = tokio::spawn(gateway.run_server(planned.addr.clone()));
+                tokio::select! {
+                    res = &mut server => return Err(BootstrapError::gateway_stopped(res)),
+                    res = wait_until_listening(&planned.addr, Duration::from_secs(5)) => {
+                        res.map_err(|e| BootstrapError::Gateway(e.to_string()))?;
+                    }
+                }
+                Some(server)
             }
-        });
-    }
+            None => None,
+        };
 
-    // 6. Start a full investigation from the first node
-    let start_addr = format!("{host}:{base_port}");
-    if let Err(e) = send_netmap_discover(&start_addr).await {
-        tracing::warn!(start_addr = %start_addr, error = ?e, "Failed to start netmap discover");
-    } else {
+        // 6. Start a full investigation from the first node. A ring about to be
+        // verified waits for it, so the netmap is complete.
+        send_netmap_discover(&start_addr, verify)
+            .await
+            .map_err(|e| BootstrapError::PartialRing(format!("netmap discover: {}", e)))?;
         tracing::info!(start_addr = %start_addr, "Started netmap discover");
-    }
 
-    // 7. Start a topology walk to populate topology maps
-    if let Err(e) = send_topology_walk(&start_addr).await {
-        tracing::warn!(start_addr = %start_addr, error = ?e, "Failed to start topology walk");
-    } else {
+        // 7. Start a topology walk to populate topology maps
+        send_topology_walk(&start_addr)
+            .await
+            .map_err(|e| BootstrapError::PartialRing(format!("topology walk: {}", e)))?;
         tracing::info!(start_addr = %start_addr, "Started topology walk");
-    }
+        Ok(gateway)
+    };
+    let (gateway, failure) = match up.await {
+        Ok(gateway) => (gateway, None),
+        Err(e) => (None, Some(e)),
+    };
 
-    // 8. Optionally block until user quits / Ctrl-C
-    if block {
-        tracing::info!("Type 'quit' or press Ctrl-C to stop…");
-        wait_for_quit_or_ctrl_c().await;
-        tracing::info!("Stopping nodes…");
+    // 8. Optionally check the ring before declaring it up
+    let report = match verify && failure.is_none() {
+        true => Some(ring_verify::verify_ring(&start_addr).await),
+        false => None,
+    };
+    let failure = match &report {
+        Some(report) if !report.ok => Some(BootstrapError::PartialRing(
+            "ring verification failed".to_string(),
+        )),
+        _ => failure,
+    };
+    match output {
+        OutputFormat::Text => {
+            if let Some(report) = &report {
+                println!("{}", report);
+            }
+        }
+        OutputFormat::Json if failure.is_none() => {
+            let nodes: Vec<_> = plan
+                .nodes
+                .iter()
+                .zip(&children)
+                .map(|(node, child)| {
+                    json!({"addr": node.addr, "pid": child.id(), "data_dir": node.data_dir})
+                })
+                .collect();
+            let network = json!({
+                "nodes": nodes,
+                "gateway": plan.gateway.as_ref().map(|gateway| &gateway.addr),
+                "start": plan.start,
+                "verify": report,
+            });
+            println!("{}", json_line("network", &network));
+        }
+        OutputFormat::Json => {}
     }
 
-    // 9. Cleanup
+    // 9. Optionally block until user quits / Ctrl-C
+    let failure = match (failure, gateway) {
+        (None, Some(mut server)) if block => {
+            tracing::info!("Type 'quit' or press Ctrl-C to stop…");
+            tokio::select! {
+                _ = wait_for_quit_or_ctrl_c() => None,
+                res = &mut server => Some(BootstrapError::gateway_stopped(res)),
+            }
+        }
+        (None, None) if block => {
+            tracing::info!("Type 'quit' or press Ctrl-C to stop…");
+            wait_for_quit_or_ctrl_c().await;
+            None
+        }
+        (failure, _) => failure,
+    };
+    tracing::info!("Stopping nodes…");
+
+    // 10. Cleanup
     #[cfg(unix)]
     {
         tracing::info!(pgid = %pgid, "Stopping process group");
-        // Send SIGTERM to the entire process group
+        // Send SIGTERM to the entire process group, this process aside: it
+        // still has to report how the bootstrap went through its exit status
         unsafe {
+            libc::signal(libc::SIGTERM, libc::SIG_IGN);
             libc::kill(-(pgid as i32), libc::SIGTERM);
         }
         // Wait for all children we know about to exit
@@ -305,7 +1395,20 @@ async fn set_network(
             let _ = child.wait().await;
         }
     }
-    Ok(())
+    match failure {
+        Some(e) => Err(e.into()),
+        None => Ok(()),
+    }
+}
+
+/// The cluster token in `file`, or else in the environment
+fn cluster_token(
+    file: Option<&Path>,
+) -> Result<Option<ClusterToken>, Box<dyn Error + Send + Sync>> {
+    match file {
+        Some(path) => Ok(Some(ClusterToken::read(path)?)),
+        None => Ok(ClusterToken::from_env()?),
+    }
 }
 
 fn current_exe() -> Result<PathBuf, Box<dyn Error + Send + Sync>> {
@@ -313,12 +1416,10 @@ fn current_exe() -> Result<PathBuf, Box<dyn Error + Send + Sync>> {
 }
 
 async fn wait_until_listening(
-    host: &str,
-    port: u16,
+    addr: &str,
     deadline: Duration,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
     let start = tokio::time::Instant::now();
-    let addr = format!("{host}:{port}");
     loop {
         match TcpStream::connect(&addr).await {
             Ok(_) => return Ok(()),
@@ -336,7 +1437,7 @@ async fn send_node_next(
     this_addr: &str,
     next_addr: &str,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
-    let mut s = TcpStream::connect(this_addr).await?;
+    let mut s = auth::connect(this_addr).await?;
     let line = format!("NODE NEXT {next_addr}\n");
     s.write_all(line.as_bytes()).await?;
 
@@ -356,17 +1457,40 @@ async fn send_node_next(
     Ok(())
 }
 
-async fn send_netmap_discover(start_addr: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
-    let mut s = TcpStream::connect(start_addr).await?;
-    s.write_all(b"NETMAP DISCOVER\n").await?;
+/// Starts a netmap discovery; with `wait`, returns once it is complete
+async fn send_netmap_discover(
+    start_addr: &str,
+    wait: bool,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut s = auth::connect(start_addr).await?;
+    if !wait {
+        s.write_all(b"NETMAP DISCOVER\n").await?;
+        let mut reader = BufReader::new(s);
+        let mut buf = String::new();
+        let _ = tokio::time::timeout(Duration::from_millis(100), reader.read_line(&mut buf)).await;
+        return Ok(());
+    }
+
+    s.write_all(b"NETMAP DISCOVER WAIT\n").await?;
     let mut reader = BufReader::new(s);
     let mut buf = String::new();
-    let _ = tokio::time::timeout(Duration::from_millis(100), reader.read_line(&mut buf)).await;
-    Ok(())
+    loop {
+        buf.clear();
+        if reader.read_line(&mut buf).await? == 0 {
+            return Err("connection closed during NETMAP DISCOVER WAIT".into());
+        }
+        let line = buf.trim();
+        if line == "OK" {
+            return Ok(());
+        }
+        if line.starts_with("ERR") {
+            return Err(line.to_string().into());
+        }
+    }
 }
 
 async fn send_topology_walk(start_addr: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
-    let mut s = TcpStream::connect(start_addr).await?;
+    let mut s = auth::connect(start_addr).await?;
     s.write_all(b"TOPOLOGY WALK\n").await?;
     let mut reader = BufReader::new(s);
     let mut buf = String::new();
@@ -0,0 +1,140 @@
+//! A blocking [`Client`], for applications and scripts without an async
+//! runtime (`blocking` feature).
+//!
+//! Each method runs its counterpart on the async [`crate::Client`] it wraps
+//! on a runtime of its own, started with one worker thread on first use and
+//! kept for the life of the process, so callers need not adopt tokio. They
+//! must not be called from within an async runtime: blocking its thread
+//! would stall it (tokio panics instead).
+
+use crate::{auth::ClusterToken, client::RetryPolicy, pull::VerifiedFile, stream::PullStream};
+use std::{
+    error::Error,
+    io::{self, Read},
+    sync::OnceLock,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    runtime::{Builder, Runtime},
+};
+
+type AnyErr = Box<dyn Error + Send + Sync>;
+
+/// Bytes moved between the caller's reader and a push at once
+const BLOCK: usize = 64 * 1024;
+
+/// The runtime the blocking calls run on
+fn runtime() -> &'static Runtime {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| {
+        Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("ouroboros-blocking")
+            .enable_all()
+            .build()
+            .expect("cannot start the blocking client's runtime")
+    })
+}
+
+/// A client of a ring whose calls block: see the module docs
+#[derive(Clone)]
+pub struct Client {
+    inner: crate::Client,
+}
+
+impl Client {
+    /// A client entering the ring through the nodes at `entries`
+    /// (`host:port`), tried in order (see [`crate::Client::new`])
+    pub fn new<I>(entries: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        crate::Client::new(entries).into()
+    }
+
+    /// Presents `token` to every node reached
+    pub fn with_token(self, token: ClusterToken) -> Self {
+        self.inner.with_token(token).into()
+    }
+
+    /// Retries calls as `policy` says (see [`crate::client`])
+    pub fn with_retry(self, policy: RetryPolicy) -> Self {
+        self.inner.with_retry(policy).into()
+    }
+
+    /// Names of the stored files (see [`crate::Client::list`])
+    pub fn list(&self) -> Result<Vec<String>, AnyErr> {
+        runtime().block_on(self.inner.list())
+    }
+
+    /// Downloads `name`, every chunk checked against its hash (see
+    /// [`crate::Client::pull`])
+    pub fn pull(&self, name: &str) -> Result<VerifiedFile, AnyErr> {
+        runtime().block_on(self.inner.pull(name))
+    }
+
+    /// Opens `name` for reading as it arrives (see
+    /// [`crate::Client::pull_stream`])
+    pub fn pull_reader(&self, name: &str) -> Result<PullReader, AnyErr> {
+        let stream = runtime().block_on(self.inner.pull_stream(name))?;
+        Ok(PullReader { stream })
+    }
+
+    /// Stores `data` as `name`, replacing a stored file of that name
+    pub fn push(&self, name: &str, data: &[u8]) -> Result<(), AnyErr> {
+        runtime().block_on(self.inner.push_stream(name, data.len() as u64, data))
+    }
+
+    /// Stores `size` bytes read from `reader` as `name` (see
+    /// [`crate::Client::push_stream`]). Fails if `reader` ends before `size`
+    /// bytes.
+    pub fn push_from<R: Read>(&self, name: &str, size: u64, mut reader: R) -> Result<(), AnyErr> {
+        let rt = runtime();
+        let mut writer = {
+            let _runtime = rt.enter();
+            self.inner.push_writer(name, size)
+        };
+        let mut buf = vec![0u8; BLOCK];
+        let mut left = size;
+        while left > 0 {
+            let want = left.min(BLOCK as u64) as usize;
+            let n = match reader.read(&mut buf[..want]) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+            // A push the node refused stops reading: its answer says why
+            if rt.block_on(writer.write_all(&buf[..n])).is_err() {
+                break;
+            }
+            left -= n as u64;
+        }
+        rt.block_on(writer.finish())
+    }
+}
+
+impl From<crate::Client> for Client {
+    fn from(inner: crate::Client) -> Self {
+        Self { inner }
+    }
+}
+
+/// A file being pulled, read with [`std::io::Read`]
+pub struct PullReader {
+    stream: PullStream,
+}
+
+impl PullReader {
+    /// Size of the file, from its manifest
+    pub fn size(&self) -> u64 {
+        self.stream.size()
+    }
+}
+
+impl Read for PullReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        runtime().block_on(self.stream.read(buf))
+    }
+}
@@ -0,0 +1,422 @@
+use crate::{
+    auth::{AccessList, ClusterToken},
+    config::{DeathHooks, LogFilterHook, NodeConfig, RespawnMode, TcpOptions},
+    event::StampedEvent,
+    fanout, heartbeat, inventory,
+    logging::{LogBuffer, LogOptions},
+    migrate, net,
+    node::Node,
+    pack, relay,
+    schema::Labels,
+    server, verify,
+};
+use std::{
+    error::Error,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    net::{TcpSocket, UdpSocket},
+    sync::broadcast,
+    task::JoinHandle,
+};
+
+type AnyErr = Box<dyn Error + Send + Sync>;
+
+/// Builder for a ring node that can be embedded in another application.
+///
+/// ```no_run
+/// # async fn demo() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+/// let handle = ouroboros_fs::NodeBuilder::new()
+///     .bind("127.0.0.1:7000")
+///     .data_dir("nodes/7000")
+///     .gossip(std::time::Duration::from_secs(5))
+///     .build()?;
+/// handle.start().await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct NodeBuilder {
+    bind_addr: String,
+    config: NodeConfig,
+}
+
+impl Default for NodeBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NodeBuilder {
+    pub fn new() -> Self {
+        Self {
+            bind_addr: "127.0.0.1:0".to_string(),
+            config: NodeConfig::default(),
+        }
+    }
+
+    /// Address to listen on. Port 0 picks a free port.
+    pub fn bind(mut self, addr: impl Into<String>) -> Self {
+        self.bind_addr = addr.into();
+        self
+    }
+
+    /// Directory for the node's storage and identity (defaults to `nodes/<port>`).
+    pub fn data_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.config.data_dir = Some(dir.into());
+        self
+    }
+
+    /// Time between health checks to the next node. Zero disables gossip.
+    pub fn gossip(mut self, interval: Duration) -> Self {
+        self.config.settings.gossip_interval = interval;
+        self
+    }
+
+    /// How long a health check waits for the neighbor to answer.
+    pub fn health_timeout(mut self, timeout: Duration) -> Self {
+        self.config.settings.health_timeout = timeout;
+        self
+    }
+
+    /// How long `TOPOLOGY WALK` waits for the walk to complete.
+    pub fn walk_timeout(mut self, timeout: Duration) -> Self {
+        self.config.settings.walk_timeout = timeout;
+        self
+    }
+
+    /// How long `NODE HEAL` waits for the heal walk to complete.
+    pub fn heal_timeout(mut self, timeout: Duration) -> Self {
+        self.config.settings.heal_timeout = timeout;
+        self
+    }
+
+    /// `key = value` settings file, applied on start and re-read on SIGHUP.
+    pub fn config_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.config_file = Some(path.into());
+        self
+    }
+
+    /// Callback that installs a new tracing filter, enabling the `log-filter` setting.
+    pub fn log_filter_hook<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&str) -> Result<(), String> + Send + Sync + 'static,
+    {
+        self.config.log_filter_hook = Some(LogFilterHook(Arc::new(hook)));
+        self
+    }
+
+    /// Number of backup copies kept for every chunk. Zero disables backups.
+    /// Copies go to the nodes before the chunk's holder, spread over failure
+    /// domains (see [`NodeBuilder::failure_domain`]).
+    pub fn replication(mut self, copies: u32) -> Self {
+        self.config.replication = copies;
+        self
+    }
+
+    /// Whether dead neighbors are respawned, how often, and how fast.
+    pub fn respawn_policy(
+        mut self,
+        mode: RespawnMode,
+        max_respawns: u32,
+        backoff: Duration,
+    ) -> Self {
+        self.config.settings.respawn = mode;
+        self.config.settings.max_respawns = max_respawns;
+        self.config.settings.respawn_backoff = backoff;
+        self
+    }
+
+    /// Alert hooks fired when a neighbor is found dead.
+    pub fn death_hooks(mut self, hooks: DeathHooks) -> Self {
+        self.config.death_hooks = hooks;
+        self
+    }
+
+    /// Socket options for the listener and peer connections.
+    pub fn tcp(mut self, opts: TcpOptions) -> Self {
+        self.config.tcp = opts;
+        self
+    }
+
+    /// Have peers reach this node through the ring node at `addr`, for a node
+    /// that cannot accept inbound connections (see [`crate::relay`]).
+    pub fn relay(mut self, addr: impl Into<String>) -> Self {
+        self.config.relay = Some(addr.into());
+        self
+    }
+
+    /// Require `token` with the ring's internal commands, and send it to peers
+    /// (see [`crate::auth`]). Every node of the ring needs the same one.
+    pub fn cluster_token(mut self, token: ClusterToken) -> Self {
+        self.config.cluster_token = Some(token);
+        self
+    }
+
+    /// Only take client commands from clients whose token `access` gives a
+    /// role allowing them (see [`crate::auth`]). Needs a cluster token.
+    pub fn access_list(mut self, access: AccessList) -> Self {
+        self.config.access = Some(access);
+        self
+    }
+
+    /// Answer health checks on a UDP socket bound to the node's port number,
+    /// and ping peers there before falling back to the data port.
+    pub fn udp_heartbeat(mut self, enabled: bool) -> Self {
+        self.config.udp_heartbeat = enabled;
+        self
+    }
+
+    /// Log options the process was started with, handed to the nodes it
+    /// respawns (the subscriber itself is installed by the binary).
+    pub fn logging(mut self, opts: LogOptions) -> Self {
+        self.config.logging = opts;
+        self
+    }
+
+    /// Recent log lines served by `NODE LOG TAIL` and `NODE LOG FOLLOW`.
+    pub fn log_buffer(mut self, buffer: LogBuffer) -> Self {
+        self.config.log_buffer = Some(buffer);
+        self
+    }
+
+    /// Labels announced in the netmap (`zone=eu-west`, `disk=ssd`), which
+    /// pushes can require with `PLACE`.
+    pub fn labels(mut self, labels: Labels) -> Self {
+        self.config.labels = labels;
+        self
+    }
+
+    /// Label whose value names a node's failure domain (default `zone`).
+    /// Backups of a chunk go to nodes outside its holder's domain, and outside
+    /// each other's, as long as the ring has such nodes.
+    pub fn failure_domain(mut self, key: impl Into<String>) -> Self {
+        self.config.failure_domain = key.into();
+        self
+    }
+
+    /// Name of this node's ring. Federated rings reach its files as
+    /// `<ring>/<name>` through their border nodes (see `FEDERATION LINK`).
+    pub fn ring_id(mut self, id: impl Into<String>) -> Self {
+        self.config.ring_id = Some(id.into());
+        self
+    }
+
+    /// Max file size in bytes.
+    pub fn file_size(mut self, max: u64) -> Self {
+        self.config.settings.file_size = max;
+        self
+    }
+
+    /// Binds the listening socket and creates the node.
+    ///
+    /// Nothing is served until [`NodeHandle::start`] is called.
+    pub fn build(self) -> Result<NodeHandle, AnyErr> {
+        // Without a cluster token a client could send the internal commands,
+        // whatever its role
+        if self.config.access.is_some() && self.config.cluster_token.is_none() {
+            return Err("an access list needs a cluster token".into());
+        }
+
+        // 1. Parse the address with an explicit type annotation
+        let addr: SocketAddr = self.bind_addr.parse()?;
+
+        // 2. Create a socket based on IP version
+        let socket = if addr.is_ipv6() {
+            TcpSocket::new_v6()?
+        } else {
+            TcpSocket::new_v4()?
+        };
+
+        // 3. Set the SO_REUSEADDR option
+        socket.set_reuseaddr(true)?;
+
+        // 4. Set the SO_REUSEPORT option (required on macOS/BSD to bypass TIME_WAIT)
+        #[cfg(unix)]
+        socket.set_reuseport(true)?;
+
+        // 5. Apply buffer sizes; accepted connections inherit them
+        net::tune_socket(&socket, &self.config.tcp)?;
+
+        // 6. Bind the socket to the address
+        socket.bind(addr)?;
+
+        // 7. Get the local address
+        let local = socket.local_addr()?;
+
+        // 8. Bind the heartbeat socket on the same port number
+        let heartbeat = if self.config.udp_heartbeat {
+            let udp = std::net::UdpSocket::bind(local)?;
+            udp.set_nonblocking(true)?;
+            Some(udp)
+        } else {
+            None
+        };
+
+        // Initialize Node structure
+        let node = Node::new(local.into(), &self.config);
+
+        Ok(NodeHandle {
+            node,
+            socket: Mutex::new(Some(socket)),
+            heartbeat: Mutex::new(heartbeat),
+            server: Mutex::new(None),
+            tasks: Mutex::new(Vec::new()),
+        })
+    }
+}
+
+/// A node built by [`NodeBuilder`].
+///
+/// Besides starting and stopping the server, the handle issues commands
+/// against the local node directly, without a TCP round trip.
+#[derive(Debug)]
+pub struct NodeHandle {
+    node: Arc<Node>,
+    socket: Mutex<Option<TcpSocket>>,
+    heartbeat: Mutex<Option<std::net::UdpSocket>>,
+    server: Mutex<Option<JoinHandle<Result<(), AnyErr>>>>,
+    tasks: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl NodeHandle {
+    /// Shared node state
+    pub fn node(&self) -> &Arc<Node> {
+        &self.node
+    }
+
+    /// Same as [`Node::subscribe`]
+    pub fn subscribe(&self) -> broadcast::Receiver<StampedEvent> {
+        self.node.subscribe()
+    }
+
+    /// Address the node is listening on
+    pub fn addr(&self) -> &str {
+        &self.node.port
+    }
+
+    /// Applies the config file (if any), creates the node directories, takes
+    /// their inventory (see [`crate::inventory`]), starts accepting
+    /// connections (and heartbeats, if enabled) and spawns the gossip, scrub
+    /// and packing loops.
+    pub async fn start(&self) -> Result<(), AnyErr> {
+        let Some(socket) = self.socket.lock().unwrap().take() else {
+            return Err("node already started".into());
+        };
+
+        server::reload_config_file(&self.node).await?;
+        server::create_node_dirs(&self.node).await?;
+        inventory::take(&self.node).await;
+
+        // Listen for incoming connections
+        let listener = socket.listen(1024)?;
+        let server_node = Arc::clone(&self.node);
+        *self.server.lock().unwrap() = Some(tokio::spawn(server::serve(server_node, listener)));
+
+        // Answer heartbeat pings on their own socket, away from the data port
+        let udp = self.heartbeat.lock().unwrap().take();
+        if let Some(udp) = udp {
+            let udp = UdpSocket::from_std(udp)?;
+            let task = tokio::spawn(heartbeat::serve(self.node.port.clone(), udp));
+            self.tasks.lock().unwrap().push(task);
+        }
+
+        // Spawn the gossip loop; it idles while the interval is zero, so
+        // gossip can be enabled later through a config reload
+        let gossip_node = Arc::clone(&self.node);
+        let task = tokio::spawn(async move {
+            let interval = gossip_node.settings().await.gossip_interval;
+            tracing::info!(
+                node = %gossip_node.port,
+                interval = ?interval,
+                "Gossip loop starting"
+            );
+            server::spawn_gossip_loop(gossip_node).await;
+        });
+        self.tasks.lock().unwrap().push(task);
+
+        // Spawn the background scrubber; like gossip, it idles while disabled
+        let scrub_node = Arc::clone(&self.node);
+        let task = tokio::spawn(verify::spawn_scrub_loop(scrub_node));
+        self.tasks.lock().unwrap().push(task);
+
+        // Pack small chunks into segment files; idles while pack-threshold is 0
+        let pack_node = Arc::clone(&self.node);
+        let task = tokio::spawn(pack::spawn_pack_loop(pack_node));
+        self.tasks.lock().unwrap().push(task);
+
+        // Move chunks the ring's topology changes displace (only the leader acts)
+        let migrate_node = Arc::clone(&self.node);
+        let task = tokio::spawn(migrate::spawn_migration_loop(migrate_node));
+        self.tasks.lock().unwrap().push(task);
+
+        // Resend the netmap and topology to nodes a broadcast did not reach
+        let redeliver_node = Arc::clone(&self.node);
+        let task = tokio::spawn(fanout::spawn_redelivery_loop(redeliver_node));
+        self.tasks.lock().unwrap().push(task);
+
+        // Keep a session open with the relay, if this node is reached through one
+        if let Some(relay) = self.node.relay.clone() {
+            let relay_node = Arc::clone(&self.node);
+            let task = tokio::spawn(relay::spawn_relay_loop(relay_node, relay));
+            self.tasks.lock().unwrap().push(task);
+        }
+
+        // Reload the config file on SIGHUP
+        #[cfg(unix)]
+        if self.node.config_file.is_some() {
+            let reload_node = Arc::clone(&self.node);
+            let task = tokio::spawn(server::spawn_config_reload_loop(reload_node));
+            self.tasks.lock().unwrap().push(task);
+        }
+
+        Ok(())
+    }
+
+    /// Waits until the server stops, either by failing or via [`NodeHandle::shutdown`].
+    pub async fn wait(&self) -> Result<(), AnyErr> {
+        let Some(server) = self.server.lock().unwrap().take() else {
+            return Ok(());
+        };
+        match server.await {
+            Ok(res) => res,
+            Err(e) if e.is_cancelled() => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Stops accepting connections and stops the gossip loop.
+    ///
+    /// Connections that are already being served run to completion.
+    pub fn shutdown(&self) {
+        if let Some(server) = self.server.lock().unwrap().as_ref() {
+            server.abort();
+        }
+        for task in self.tasks.lock().unwrap().drain(..) {
+            task.abort();
+        }
+        tracing::info!(node = %self.node.port, "Node shut down");
+    }
+
+    /* ---------------- Local commands ---------------- */
+
+    /// Same as `NODE NEXT <addr>`
+    pub async fn set_next(&self, addr: impl Into<String>) {
+        self.node.set_next(addr.into()).await;
+        self.node.announce_prev().await;
+    }
+
+    /// Same as `FILE PUSH <size> <name>` followed by `data`.
+    /// Returns the node's confirmation message.
+    pub async fn push_file(&self, name: &str, data: &[u8]) -> Result<String, AnyErr> {
+        server::push_local(Arc::clone(&self.node), name, data).await
+    }
+
+    /// Same as `FILE PULL <name>`
+    pub async fn pull_file(&self, name: &str) -> Result<Vec<u8>, AnyErr> {
+        server::pull_local(&self.node, name).await
+    }
+}
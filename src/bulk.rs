@@ -0,0 +1,273 @@
+//! `ouroboros_fs import` and `export`: whole directory trees in and out of a
+//! ring.
+//!
+//! Stored names keep their directories (`docs/img/a.png`), so a tree maps to
+//! the names under a prefix. `import` walks a local directory and pushes every
+//! file (`FILE PUSH`) under its relative path; `export` lists the ring's files
+//! (`FILE LIST`), pulls those under a prefix with every chunk checked (see
+//! [`crate::pull`]) and writes them below a local directory. Several files
+//! move at once; a file that fails is reported and the others go on.
+
+use crate::{
+    output::OutputFormat,
+    pull::verified_pull,
+    ring_verify::{list_name, request},
+    watch::push,
+};
+use serde_json::json;
+use std::{
+    error::Error,
+    path::{Component, Path, PathBuf},
+    time::{Duration, Instant},
+};
+use tokio::{fs, task::JoinSet};
+
+type AnyErr = Box<dyn Error + Send + Sync>;
+
+/// How long `FILE LIST` may take
+const LIST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What an import or export moved
+#[derive(Debug, Clone, Default)]
+pub struct BulkReport {
+    /// Files moved, and their bytes
+    pub files: usize,
+    pub bytes: u64,
+    /// Files that could not be moved, with why
+    pub failed: Vec<(String, String)>,
+    pub elapsed: Duration,
+}
+
+impl BulkReport {
+    /// `files=<n> bytes=<n> failed=<n> in <secs>s`
+    pub fn summary(&self) -> String {
+        format!(
+            "files={} bytes={} failed={} in {:.1}s",
+            self.files,
+            self.bytes,
+            self.failed.len(),
+            self.elapsed.as_secs_f64()
+        )
+    }
+
+    /// The report as JSON: `{"files","bytes","failed":[{"name","error"}],"secs"}`
+    pub fn json(&self) -> serde_json::Value {
+        let failed: Vec<_> = self
+            .failed
+            .iter()
+            .map(|(name, error)| json!({"name": name, "error": error}))
+            .collect();
+        json!({
+            "files": self.files,
+            "bytes": self.bytes,
+            "failed": failed,
+            "secs": self.elapsed.as_secs_f64(),
+        })
+    }
+
+    fn record(&mut self, name: String, res: Result<u64, String>) {
+        match res {
+            Ok(bytes) => {
+                self.files += 1;
+                self.bytes += bytes;
+            }
+            Err(e) => self.failed.push((name, e)),
+        }
+    }
+}
+
+/// Pushes every file below `dir` through the node at `addr`, named by its
+/// path relative to `dir` under `prefix`, `parallel` files at a time. Prints
+/// `PUSHED <name> bytes=<n>` or `FAILED <name>: <error>` per file, in `output`.
+pub async fn import(
+    addr: &str,
+    dir: &Path,
+    prefix: &str,
+    parallel: usize,
+    output: OutputFormat,
+) -> Result<BulkReport, AnyErr> {
+    let started = Instant::now();
+    let files = walk(dir).await?;
+    let mut report = BulkReport::default();
+    let mut pushes = JoinSet::new();
+    for (path, rel) in files {
+        if pushes.len() >= parallel.max(1)
+            && let Some(res) = pushes.join_next().await
+        {
+            let (name, res) = res?;
+            report.record(name, res);
+        }
+        let name = join_name(prefix, &rel);
+        let addr = addr.to_string();
+        pushes.spawn(async move {
+            let res = match fs::read(&path).await {
+                Ok(data) => push(&addr, &name, &data)
+                    .await
+                    .map(|()| data.len() as u64)
+                    .map_err(|e| e.to_string()),
+                Err(e) => Err(e.to_string()),
+            };
+            match &res {
+                Ok(bytes) => output.emit(
+                    "pushed",
+                    format!("PUSHED {} bytes={}", name, bytes),
+                    &json!({"name": name, "bytes": bytes}),
+                ),
+                Err(e) => failed(output, &name, e),
+            }
+            (name, res)
+        });
+    }
+    while let Some(res) = pushes.join_next().await {
+        let (name, res) = res?;
+        report.record(name, res);
+    }
+    report.elapsed = started.elapsed();
+    Ok(report)
+}
+
+/// Pulls every stored file under `prefix` (all files for an empty one)
+/// through the node at `addr` into `dir`, at its path below the prefix,
+/// `parallel` files at a time. Prints `PULLED <name> bytes=<n> to <path>` or
+/// `FAILED <name>: <error>` per file, in `output`.
+pub async fn export(
+    addr: &str,
+    prefix: &str,
+    dir: &Path,
+    parallel: usize,
+    output: OutputFormat,
+) -> Result<BulkReport, AnyErr> {
+    let started = Instant::now();
+    let lines = request(addr, "FILE LIST", LIST_TIMEOUT).await?;
+    let prefix = prefix.trim_matches('/');
+    let names: Vec<String> = lines
+        .iter()
+        .skip(1)
+        .filter_map(|l| list_name(l))
+        .filter(|name| in_subtree(name, prefix))
+        .collect();
+    if names.is_empty() {
+        return Err(format!("no stored file under '{}'", prefix).into());
+    }
+
+    let mut report = BulkReport::default();
+    let mut pulls = JoinSet::new();
+    for name in names {
+        if pulls.len() >= parallel.max(1)
+            && let Some(res) = pulls.join_next().await
+        {
+            let (name, res) = res?;
+            report.record(name, res);
+        }
+        let (addr, dir, prefix) = (addr.to_string(), dir.to_path_buf(), prefix.to_string());
+        pulls.spawn(async move {
+            let res = export_file(&addr, &name, &prefix, &dir).await;
+            match &res {
+                Ok((bytes, path)) => output.emit(
+                    "pulled",
+                    format!("PULLED {} bytes={} to {}", name, bytes, path.display()),
+                    &json!({"name": name, "bytes": bytes, "path": path}),
+                ),
+                Err(e) => failed(output, &name, e),
+            }
+            (name, res.map(|(bytes, _)| bytes))
+        });
+    }
+    while let Some(res) = pulls.join_next().await {
+        let (name, res) = res?;
+        report.record(name, res);
+    }
+    report.elapsed = started.elapsed();
+    Ok(report)
+}
+
+/// Prints `FAILED <name>: <error>`
+pub(crate) fn failed(output: OutputFormat, name: &str, error: impl std::fmt::Display) {
+    let error = error.to_string();
+    output.emit(
+        "failed",
+        format!("FAILED {}: {}", name, error),
+        &json!({"name": name, "error": error}),
+    );
+}
+
+async fn export_file(
+    addr: &str,
+    name: &str,
+    prefix: &str,
+    dir: &Path,
+) -> Result<(u64, PathBuf), String> {
+    let path = dir.join(local_path(name, prefix)?);
+    let file = verified_pull(addr, name, false)
+        .await
+        .map_err(|e| e.to_string())?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+    fs::write(&path, &file.data)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok((file.data.len() as u64, path))
+}
+
+/// Regular files below `dir`, with their `/`-separated path relative to it,
+/// sorted. Symbolic links are not followed.
+async fn walk(dir: &Path) -> Result<Vec<(PathBuf, String)>, AnyErr> {
+    let mut files = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(current) = dirs.pop() {
+        let mut entries = fs::read_dir(&current).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let kind = entry.file_type().await?;
+            let path = entry.path();
+            if kind.is_dir() {
+                dirs.push(path);
+            } else if kind.is_file() {
+                let rel = path.strip_prefix(dir)?;
+                let rel: Vec<_> = rel.iter().map(|p| p.to_string_lossy()).collect();
+                files.push((path.clone(), rel.join("/")));
+            }
+        }
+    }
+    files.sort_by(|a, b| a.1.cmp(&b.1));
+    Ok(files)
+}
+
+/// `<prefix>/<rel>`, or `rel` alone under an empty prefix
+fn join_name(prefix: &str, rel: &str) -> String {
+    match prefix.trim_matches('/') {
+        "" => rel.to_string(),
+        prefix => format!("{}/{}", prefix, rel),
+    }
+}
+
+/// Whether `name` is the file `prefix` or below the directory `prefix`
+fn in_subtree(name: &str, prefix: &str) -> bool {
+    prefix.is_empty()
+        || name == prefix
+        || name
+            .strip_prefix(prefix)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// Where `name` goes below the export directory: its path under `prefix`, or
+/// its last component when it is the prefix itself. Refused unless every
+/// component is a plain name, so nothing is written outside the directory.
+fn local_path(name: &str, prefix: &str) -> Result<PathBuf, String> {
+    let rel = match name.strip_prefix(prefix) {
+        Some("") => name.rsplit('/').next().unwrap_or(name),
+        Some(rest) if !prefix.is_empty() => rest.trim_start_matches('/'),
+        _ => name,
+    };
+    let path = PathBuf::from(rel);
+    let plain = path.components().all(|c| matches!(c, Component::Normal(_)));
+    if rel.is_empty() || !plain {
+        return Err(format!(
+            "cannot write '{}' below the export directory",
+            name
+        ));
+    }
+    Ok(path)
+}
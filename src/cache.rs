@@ -0,0 +1,141 @@
+//! In-memory cache of recently served chunks.
+//!
+//! Popular files are read chunk by chunk over and over; keeping the most
+//! recently served chunks in memory spares the disk those repeated reads.
+//! Entries are keyed by `<subdir>/<chunk name>` and evicted least recently
+//! used first once the cache outgrows its byte budget (`chunk-cache-size`).
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+/// A cache never holds a chunk larger than this share of its budget, so a
+/// single big chunk cannot flush everything else
+const MAX_ENTRY_SHARE: u64 = 4;
+
+#[derive(Debug, Default)]
+pub struct ChunkCache {
+    inner: Mutex<Inner>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// key -> (bytes, last use)
+    entries: HashMap<String, (Arc<[u8]>, u64)>,
+    /// last use -> key, oldest first
+    order: BTreeMap<u64, String>,
+    bytes: u64,
+    clock: u64,
+}
+
+/// Counters reported by `NODE METRICS`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: u64,
+    pub bytes: u64,
+}
+
+fn key(subdir: &str, name: &str) -> String {
+    format!("{}/{}", subdir, name)
+}
+
+impl ChunkCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Looks a chunk up, counting a hit or a miss
+    pub fn get(&self, subdir: &str, name: &str) -> Option<Arc<[u8]>> {
+        let mut inner = self.inner.lock().expect("chunk cache poisoned");
+        let key = key(subdir, name);
+        inner.clock += 1;
+        let now = inner.clock;
+        let Some((data, used)) = inner.entries.get_mut(&key) else {
+            drop(inner);
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            return None;
+        };
+        let (data, last) = (Arc::clone(data), std::mem::replace(used, now));
+        inner.order.remove(&last);
+        inner.order.insert(now, key);
+        drop(inner);
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        Some(data)
+    }
+
+    /// Whether a chunk of `size` bytes would be kept by a cache of `capacity` bytes
+    pub fn admits(size: u64, capacity: u64) -> bool {
+        size > 0 && size <= capacity / MAX_ENTRY_SHARE
+    }
+
+    /// Stores a chunk, evicting the least recently used ones to stay within
+    /// `capacity` bytes. Chunks too big for the cache are ignored.
+    pub fn insert(&self, subdir: &str, name: &str, data: Arc<[u8]>, capacity: u64) {
+        let size = data.len() as u64;
+        let mut inner = self.inner.lock().expect("chunk cache poisoned");
+        let key = key(subdir, name);
+        inner.remove(&key);
+        if !Self::admits(size, capacity) {
+            inner.evict_to(capacity);
+            return;
+        }
+        inner.evict_to(capacity - size);
+        inner.clock += 1;
+        let now = inner.clock;
+        inner.order.insert(now, key.clone());
+        inner.entries.insert(key, (data, now));
+        inner.bytes += size;
+    }
+
+    /// Evicts the least recently used chunks until at most `capacity` bytes remain
+    pub fn shrink_to(&self, capacity: u64) {
+        self.inner
+            .lock()
+            .expect("chunk cache poisoned")
+            .evict_to(capacity);
+    }
+
+    /// Drops a chunk whose file changed or went away
+    pub fn invalidate(&self, subdir: &str, name: &str) {
+        let mut inner = self.inner.lock().expect("chunk cache poisoned");
+        inner.remove(&key(subdir, name));
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let inner = self.inner.lock().expect("chunk cache poisoned");
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: inner.entries.len() as u64,
+            bytes: inner.bytes,
+        }
+    }
+}
+
+impl Inner {
+    fn remove(&mut self, key: &str) {
+        if let Some((data, used)) = self.entries.remove(key) {
+            self.order.remove(&used);
+            self.bytes -= data.len() as u64;
+        }
+    }
+
+    fn evict_to(&mut self, budget: u64) {
+        while self.bytes > budget {
+            let Some((_, key)) = self.order.pop_first() else {
+                break;
+            };
+            if let Some((data, _)) = self.entries.remove(&key) {
+                self.bytes -= data.len() as u64;
+            }
+        }
+    }
+}
@@ -0,0 +1,396 @@
+//! A client of a ring reached over TCP, for applications embedding the crate.
+//!
+//! A [`Client`] holds the nodes it enters the ring through and the token it
+//! presents to them. Each call goes through the first entry node that can be
+//! reached, in the order given; the holders of a file's chunks are then
+//! reached directly, on that node's host.
+//!
+//! Its [`RetryPolicy`] decides what happens when a node fails transiently:
+//! it cannot be reached, the exchange breaks off or times out, or it answers
+//! `ERR BUSY` or `ERR DEGRADED`. The call moves on to the next entry node,
+//! then to the policy's alternate nodes; once all of them failed it starts
+//! over after a backoff, up to the policy's attempts. Any other error is the
+//! call's answer. Lists, manifests and pulls change nothing on the ring, so
+//! any of their exchanges may be retried. A push only retries
+//! reaching a node, before its body starts: a body cannot be read twice, and
+//! a push the node may have stored is not sent again. A `MODE version` push
+//! is never retried, as each retry would store another version.
+//!
+//! Files are pulled checked end to end ([`Client::pull`], see
+//! [`crate::pull`]) or as a stream, and pushed from a stream (see
+//! [`crate::stream`]). [`crate::blocking`] wraps a client for code without
+//! an async runtime.
+
+use crate::{
+    auth::{self, ClusterToken},
+    node::FileManifestView,
+    protocol::PushMode,
+    pull::{VerifiedFile, fetch_manifest, pull_verified},
+    ring_verify::{list_name, request_with},
+    stream::{self, PullStream, PushWriter},
+};
+use std::{error::Error, future::Future, io, time::Duration};
+use tokio::{io::AsyncRead, time::sleep};
+
+type AnyErr = Box<dyn Error + Send + Sync>;
+
+/// How long `FILE LIST` may take
+const LIST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Wait before a policy's first retry, unless set with [`RetryPolicy::backoff`]
+const DEFAULT_BACKOFF: Duration = Duration::from_millis(200);
+
+/// Longest wait between two retries, unless set with [`RetryPolicy::backoff`]
+const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+/// How a [`Client`] retries a call that failed transiently (see the module
+/// docs). The default tries each node once, without waiting.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    attempts: u32,
+    backoff: Duration,
+    max_backoff: Duration,
+    alternates: Vec<String>,
+}
+
+impl RetryPolicy {
+    /// A policy going over the nodes up to `attempts` times (at least once)
+    pub fn new(attempts: u32) -> Self {
+        Self {
+            attempts: attempts.max(1),
+            ..Self::default()
+        }
+    }
+
+    /// Waits `first` before the first retry, doubling the wait before each
+    /// next one up to `max`
+    pub fn backoff(mut self, first: Duration, max: Duration) -> Self {
+        self.backoff = first;
+        self.max_backoff = max.max(first);
+        self
+    }
+
+    /// Tries the nodes at `addrs` (`host:port`) once the client's entry nodes
+    /// failed, in order
+    pub fn alternates<I>(mut self, addrs: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        self.alternates = addrs.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Wait before the `retry`th retry (from 1)
+    fn delay(&self, retry: u32) -> Duration {
+        self.backoff
+            .checked_mul(2u32.pow((retry - 1).min(16)))
+            .unwrap_or(self.max_backoff)
+            .min(self.max_backoff)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 1,
+            backoff: DEFAULT_BACKOFF,
+            max_backoff: DEFAULT_MAX_BACKOFF,
+            alternates: Vec::new(),
+        }
+    }
+}
+
+/// Whether another node, or the same one later, may answer a call that
+/// failed with `e` (see the module docs)
+fn is_transient(e: &AnyErr) -> bool {
+    if e.downcast_ref::<io::Error>().is_some() {
+        return true;
+    }
+    let message = e.to_string();
+    message.starts_with("BUSY") || message.starts_with("DEGRADED") || message.ends_with("timed out")
+}
+
+/// A client of a ring: its entry nodes, the token presented to them and how
+/// its calls are retried
+#[derive(Clone)]
+pub struct Client {
+    entries: Vec<String>,
+    token: Option<ClusterToken>,
+    retry: RetryPolicy,
+}
+
+impl Client {
+    /// A client entering the ring through the nodes at `entries`
+    /// (`host:port`), tried in order. It presents the process' client token
+    /// (see [`auth::client_token`]) unless given one with [`Self::with_token`].
+    pub fn new<I>(entries: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        Self {
+            entries: entries.into_iter().map(Into::into).collect(),
+            token: None,
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    /// Presents `token` to every node reached
+    pub fn with_token(mut self, token: ClusterToken) -> Self {
+        self.token = Some(token);
+        self
+    }
+
+    /// Retries calls as `policy` says (see the module docs)
+    pub fn with_retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = policy;
+        self
+    }
+
+    /// The entry nodes, in the order they are tried
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    /// Names of the stored files (`FILE LIST`)
+    pub async fn list(&self) -> Result<Vec<String>, AnyErr> {
+        let token = self.token.as_ref();
+        let lines = self
+            .retried(
+                |addr| async move { request_with(&addr, "FILE LIST", LIST_TIMEOUT, token).await },
+            )
+            .await?;
+        // The first row is the CSV header
+        Ok(lines.iter().skip(1).filter_map(|l| list_name(l)).collect())
+    }
+
+    /// The manifest of `name` (`FILE MANIFEST`): its chunks, their holders
+    /// and hashes
+    pub async fn manifest(&self, name: &str) -> Result<FileManifestView, AnyErr> {
+        Ok(self.manifest_from(name).await?.1)
+    }
+
+    /// Downloads `name`, every chunk checked against its hash (see
+    /// [`crate::pull`])
+    pub async fn pull(&self, name: &str) -> Result<VerifiedFile, AnyErr> {
+        let token = self.token.as_ref();
+        self.retried(|addr| async move {
+            let manifest = fetch_manifest(&addr, name, token).await?;
+            pull_verified(&addr, manifest, false, token).await
+        })
+        .await
+    }
+
+    /// Pulls `name` as a stream of its bytes (see [`crate::stream`]). Fails
+    /// up front if the file is not stored. Only reading the manifest is
+    /// retried: the stream's bytes are read once.
+    pub async fn pull_stream(&self, name: &str) -> Result<PullStream, AnyErr> {
+        let (addr, manifest) = self.manifest_from(name).await?;
+        Ok(stream::pull_stream(&addr, manifest, self.token.clone()))
+    }
+
+    /// Stores `size` bytes read from `reader` as `name`, replacing a stored
+    /// file of that name. Fails if `reader` ends before `size` bytes, or the
+    /// node refuses the push.
+    pub async fn push_stream<R>(&self, name: &str, size: u64, reader: R) -> Result<(), AnyErr>
+    where
+        R: AsyncRead + Unpin,
+    {
+        self.push_stream_with(name, size, PushMode::default(), reader)
+            .await
+    }
+
+    /// [`Self::push_stream`] in `mode` (see [`PushMode`]). A `MODE version`
+    /// push only moves on from entry nodes that cannot be reached, once.
+    pub async fn push_stream_with<R>(
+        &self,
+        name: &str,
+        size: u64,
+        mode: PushMode,
+        reader: R,
+    ) -> Result<(), AnyErr>
+    where
+        R: AsyncRead + Unpin,
+    {
+        let token = self.token.as_ref();
+        let connect = |addr: String| async move { Ok(auth::connect_with(&addr, token).await?) };
+        let stream = if mode == PushMode::Version {
+            self.clone()
+                .with_retry(RetryPolicy::default())
+                .retried(connect)
+                .await?
+        } else {
+            self.retried(connect).await?
+        };
+        stream::push_stream(stream, name, size, mode, reader).await
+    }
+
+    /// Starts pushing `size` bytes as `name`, the bytes being written to the
+    /// returned [`PushWriter`] rather than read from a reader (see
+    /// [`Self::push_stream`]). Must be called within a tokio runtime.
+    pub fn push_writer(&self, name: &str, size: u64) -> PushWriter {
+        stream::push_writer(self.clone(), name, size)
+    }
+
+    /// Reads `name`'s manifest, and returns the address of the node that
+    /// answered with it
+    async fn manifest_from(&self, name: &str) -> Result<(String, FileManifestView), AnyErr> {
+        let token = self.token.as_ref();
+        self.retried(|addr| async move {
+            let manifest = fetch_manifest(&addr, name, token).await?;
+            Ok((addr, manifest))
+        })
+        .await
+    }
+
+    /// Runs `call` with the address of each node in turn, as the retry
+    /// policy says, until it succeeds or fails for good
+    async fn retried<T, F, Fut>(&self, mut call: F) -> Result<T, AnyErr>
+    where
+        F: FnMut(String) -> Fut,
+        Fut: Future<Output = Result<T, AnyErr>>,
+    {
+        let nodes: Vec<&String> = self.entries.iter().chain(&self.retry.alternates).collect();
+        if nodes.is_empty() {
+            return Err("no entry node given".into());
+        }
+        let mut failures = Vec::new();
+        for attempt in 0..self.retry.attempts {
+            if attempt > 0 {
+                sleep(self.retry.delay(attempt)).await;
+            }
+            for addr in &nodes {
+                match call(addr.to_string()).await {
+                    Ok(value) => return Ok(value),
+                    Err(e) if is_transient(&e) => {
+                        tracing::debug!(node = %addr, attempt = attempt + 1, error = %e, "Client call failed, moving on");
+                        failures.push(format!("{}: {}", addr, e));
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        Err(format!(
+            "no node answered after {} attempt(s): {}",
+            self.retry.attempts,
+            failures.join("; ")
+        )
+        .into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    };
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+        net::TcpListener,
+    };
+
+    /// A node answering its first `failures` connections with `failure`,
+    /// then every other one with `answer`. Returns its address and how many
+    /// connections it took.
+    async fn flaky_node(
+        failures: usize,
+        failure: &'static str,
+        answer: &'static str,
+    ) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let seen = Arc::new(AtomicUsize::new(0));
+        let count = Arc::clone(&seen);
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let n = count.fetch_add(1, Ordering::SeqCst);
+                let mut stream = BufReader::new(stream);
+                let mut line = String::new();
+                let _ = stream.read_line(&mut line).await;
+                let text = if n < failures { failure } else { answer };
+                let _ = stream.get_mut().write_all(text.as_bytes()).await;
+                // Take a push's body before answering for good
+                let mut rest = Vec::new();
+                let _ = stream.read_to_end(&mut rest).await;
+            }
+        });
+        (addr, seen)
+    }
+
+    /// An address nothing listens on
+    async fn dead_addr() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        listener.local_addr().unwrap().to_string()
+    }
+
+    fn quick(attempts: u32) -> RetryPolicy {
+        RetryPolicy::new(attempts).backoff(Duration::from_millis(1), Duration::from_millis(5))
+    }
+
+    const LIST: &str = "name,start,size\na.txt,7000,3\n";
+
+    #[tokio::test]
+    async fn list_retries_a_busy_node() {
+        let (addr, seen) = flaky_node(2, "ERR BUSY retry-after=10\n", LIST).await;
+        let client = Client::new([addr]).with_retry(quick(3));
+        assert_eq!(client.list().await.unwrap(), ["a.txt"]);
+        assert_eq!(seen.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn list_gives_up_after_its_attempts() {
+        let (addr, seen) = flaky_node(5, "ERR DEGRADED FILE LIST refused\n", LIST).await;
+        let client = Client::new([addr]).with_retry(quick(2));
+        assert!(client.list().await.is_err());
+        assert_eq!(seen.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn lasting_errors_are_not_retried() {
+        let (addr, seen) = flaky_node(1, "ERR FILE_NOT_FOUND\n", "{}\n").await;
+        let client = Client::new([addr]).with_retry(quick(3));
+        let e = client.manifest("a.txt").await.unwrap_err();
+        assert_eq!(e.to_string(), "FILE_NOT_FOUND");
+        assert_eq!(seen.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn alternates_follow_the_entries() {
+        let (alternate, seen) = flaky_node(0, "", LIST).await;
+        let policy = quick(1).alternates([alternate]);
+        let client = Client::new([dead_addr().await]).with_retry(policy);
+        assert_eq!(client.list().await.unwrap(), ["a.txt"]);
+        assert_eq!(seen.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn push_retries_reaching_a_node() {
+        let (addr, seen) = flaky_node(0, "", "OK\n").await;
+        let policy = quick(2).alternates([addr]);
+        let client = Client::new([dead_addr().await]).with_retry(policy);
+        client.push_stream("a.txt", 3, &b"abc"[..]).await.unwrap();
+        assert_eq!(seen.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn push_is_not_sent_twice() {
+        let (addr, seen) = flaky_node(1, "ERR BUSY retry-after=10\n", "OK\n").await;
+        let client = Client::new([addr]).with_retry(quick(3));
+        assert!(client.push_stream("a.txt", 3, &b"abc"[..]).await.is_err());
+        assert_eq!(seen.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn version_push_is_not_retried() {
+        let dead = dead_addr().await;
+        let client = Client::new([dead]).with_retry(quick(3));
+        let started = std::time::Instant::now();
+        let push = client.push_stream_with("a.txt", 3, PushMode::Version, &b"abc"[..]);
+        let e = push.await.unwrap_err();
+        assert!(e.to_string().contains("after 1 attempt(s)"), "{}", e);
+        assert!(started.elapsed() < Duration::from_millis(500));
+    }
+}
@@ -0,0 +1,163 @@
+//! Compatibility between protocol versions, for rolling upgrades.
+//!
+//! Nodes greet each other with `NODE HELLO <addr> <version> <features>` and
+//! answer with their own `HELLO <version> <features>`. A peer that answers
+//! anything else (an older node replies `ERR unknown NODE command`) is
+//! treated as [`LEGACY_VERSION`], with no features.
+//!
+//! A format change is tied to a [`Feature`]. A node only emits the new
+//! format once every live node of its netmap has advertised the feature (see
+//! `Node::ring_supports`), so a ring can be upgraded one node at a time:
+//! until the last old node is replaced, everyone keeps speaking the old
+//! format. Peers that have not answered a HELLO yet count as legacy.
+
+use std::{collections::BTreeSet, fmt, str::FromStr};
+
+/// Version spoken by this build
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Version assumed for peers that do not understand `NODE HELLO`
+pub const LEGACY_VERSION: u32 = 1;
+
+/// A protocol change older peers may not understand
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Feature {
+    /// `TOPOLOGY HOP/DONE/SET` carry a snapshot epoch before the history
+    TopologyEpoch,
+    /// Netmap entries carry node ids (`7000=Alive:<id>`). Older nodes skip the
+    /// extra field, so ids are sent regardless.
+    NodeIds,
+    /// Nodes announce themselves to their next hop with `NODE PREV`
+    NodePrev,
+    /// `FILE TAG` and file tag lists carry the holder of each chunk
+    Placement,
+    /// Backups are spread over failure domains, so a backup holder is not
+    /// always the predecessor: `FILE NOTIFY-CHUNK-SAVED` names the chunk holder
+    FailureDomains,
+    /// `FEDERATION` commands, and file tags naming the federated ring a file is on
+    Federation,
+    /// `FILE COPY`, and file tags naming the chunk set a copied file shares
+    FileCopy,
+    /// Walk histories carry the latency of every hop (`7000->7001@3ms`)
+    HopTiming,
+    /// `NODE PING` and its `PONG` carry netmap changes, which then spread from
+    /// node to node instead of being sent to every node by the one that saw them
+    Gossip,
+    /// File tags carry a version, and single tags are sent as
+    /// `FILE TAGS-MERGE`, which keeps the newer of two versions
+    TagVersions,
+    /// File tags name chunk holders by node id (older nodes skip the ids, so
+    /// they are sent regardless), and the hops of a relayed push answer with
+    /// the ids of the nodes that stored its chunks (`OK <ids>`)
+    HolderIds,
+}
+
+impl Feature {
+    pub const ALL: &'static [Feature] = &[
+        Feature::TopologyEpoch,
+        Feature::NodeIds,
+        Feature::NodePrev,
+        Feature::Placement,
+        Feature::FailureDomains,
+        Feature::Federation,
+        Feature::FileCopy,
+        Feature::HopTiming,
+        Feature::Gossip,
+        Feature::TagVersions,
+        Feature::HolderIds,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Feature::TopologyEpoch => "topology-epoch",
+            Feature::NodeIds => "node-ids",
+            Feature::NodePrev => "node-prev",
+            Feature::Placement => "placement",
+            Feature::FailureDomains => "failure-domains",
+            Feature::Federation => "federation",
+            Feature::FileCopy => "file-copy",
+            Feature::HopTiming => "hop-timing",
+            Feature::Gossip => "gossip",
+            Feature::TagVersions => "tag-versions",
+            Feature::HolderIds => "holder-ids",
+        }
+    }
+}
+
+impl FromStr for Feature {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Feature::ALL
+            .iter()
+            .copied()
+            .find(|f| f.as_str().eq_ignore_ascii_case(s.trim()))
+            .ok_or_else(|| format!("unknown feature '{}'", s.trim()))
+    }
+}
+
+/// What a node speaks: its protocol version and feature set
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hello {
+    pub version: u32,
+    pub features: BTreeSet<Feature>,
+}
+
+impl Hello {
+    /// What this build speaks
+    pub fn current() -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            features: Feature::ALL.iter().copied().collect(),
+        }
+    }
+
+    /// What a peer that does not understand `NODE HELLO` speaks
+    pub fn legacy() -> Self {
+        Self {
+            version: LEGACY_VERSION,
+            features: BTreeSet::new(),
+        }
+    }
+
+    pub fn supports(&self, feature: Feature) -> bool {
+        self.features.contains(&feature)
+    }
+}
+
+/// `<version> <feature,...>` (`-` for no features)
+impl fmt::Display for Hello {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ", self.version)?;
+        if self.features.is_empty() {
+            return f.write_str("-");
+        }
+        for (i, feature) in self.features.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            f.write_str(feature.as_str())?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Hello {
+    type Err = String;
+
+    /// Features this build does not know are skipped: it cannot use them anyway
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        let version = words
+            .next()
+            .and_then(|v| v.parse().ok())
+            .ok_or_else(|| format!("invalid hello '{}'", s.trim()))?;
+        let features = words
+            .next()
+            .unwrap_or("")
+            .split(',')
+            .filter_map(|f| f.parse().ok())
+            .collect();
+        Ok(Self { version, features })
+    }
+}
@@ -0,0 +1,281 @@
+//! HTTP response compression for the gateway.
+//!
+//! Responses are compressed when the route is enabled in [`Compression`] and
+//! the client's `Accept-Encoding` allows one of the supported encodings
+//! (`zstd` is preferred over `gzip`). File pulls are only compressed when the
+//! file's name gives a compressible content type: archives, images and media
+//! are sent as they are.
+
+use flate2::{Compression as GzLevel, write::GzEncoder};
+use std::{fmt, io, io::Write, str::FromStr};
+
+/// Routes compressed by default: every JSON endpoint
+const DEFAULT_ROUTES: &[&str] = &[
+    "/openapi.json",
+    "/netmap/get",
+    "/topology/get",
+    "/file/list",
+    "/ring/verify",
+    "/file/manifest",
+    "/stats/usage",
+    "/cluster/stats",
+];
+
+/// zstd level: fast, and still well ahead of gzip on JSON
+const ZSTD_LEVEL: i32 = 3;
+
+/// A `Content-Encoding` the gateway can produce
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Gzip,
+    Zstd,
+}
+
+impl Encoding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Zstd => "zstd",
+        }
+    }
+
+    /// The preferred encoding an `Accept-Encoding` header allows, if any.
+    /// Codings with `q=0` are refused, `*` accepts both.
+    pub fn negotiate(accept_encoding: &str) -> Option<Encoding> {
+        let mut gzip = false;
+        let mut zstd = false;
+        for coding in accept_encoding.split(',') {
+            let mut params = coding.split(';');
+            let name = params.next().unwrap_or("").trim().to_ascii_lowercase();
+            let refused = params.any(|p| {
+                p.trim()
+                    .strip_prefix("q=")
+                    .and_then(|q| q.trim().parse::<f32>().ok())
+                    == Some(0.0)
+            });
+            if refused {
+                continue;
+            }
+            match name.as_str() {
+                "gzip" | "x-gzip" => gzip = true,
+                "zstd" => zstd = true,
+                "*" => (gzip, zstd) = (true, true),
+                _ => {}
+            }
+        }
+        if zstd {
+            Some(Encoding::Zstd)
+        } else if gzip {
+            Some(Encoding::Gzip)
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Routes whose responses the gateway compresses, by path prefix:
+/// `/file/list,/file/pull`, or `none`. Routes are given without the
+/// `/api/v1` prefix and cover both spellings. File pulls are only compressed
+/// for `/file/pull`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Compression {
+    routes: Vec<String>,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self {
+            routes: DEFAULT_ROUTES.iter().map(|r| r.to_string()).collect(),
+        }
+    }
+}
+
+impl Compression {
+    /// Compresses nothing
+    pub fn none() -> Self {
+        Self { routes: Vec::new() }
+    }
+
+    /// The encoding to answer a request for `path` with
+    pub fn select(&self, path: &str, accept_encoding: Option<&str>) -> Option<Encoding> {
+        if !self
+            .routes
+            .iter()
+            .any(|route| path.starts_with(route.as_str()))
+        {
+            return None;
+        }
+        Encoding::negotiate(accept_encoding?)
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.routes.is_empty() {
+            return f.write_str("none");
+        }
+        f.write_str(&self.routes.join(","))
+    }
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.trim().eq_ignore_ascii_case("none") {
+            return Ok(Self::none());
+        }
+        let mut routes = Vec::new();
+        for route in s.split(',').map(str::trim).filter(|r| !r.is_empty()) {
+            if !route.starts_with('/') {
+                return Err(format!("invalid route '{}': routes start with '/'", route));
+            }
+            routes.push(route.to_string());
+        }
+        Ok(Self { routes })
+    }
+}
+
+/// Content type of a file, going by its extension
+pub fn content_type(name: &str) -> &'static str {
+    let ext = name
+        .rsplit_once('.')
+        .map(|(_, ext)| ext.to_ascii_lowercase())
+        .unwrap_or_default();
+    match ext.as_str() {
+        "txt" | "log" | "md" => "text/plain",
+        "html" | "htm" => "text/html",
+        "css" => "text/css",
+        "csv" => "text/csv",
+        "js" | "mjs" => "text/javascript",
+        "json" => "application/json",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "toml" | "yaml" | "yml" | "ini" | "conf" | "rs" | "py" | "sh" => "text/plain",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Content type of a file, going by its first bytes, for the formats that
+/// start with a magic number
+pub fn sniff(head: &[u8]) -> Option<&'static str> {
+    const MAGIC: &[(&[u8], &str)] = &[
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"%PDF-", "application/pdf"),
+        (b"PK\x03\x04", "application/zip"),
+        (b"\x1f\x8b", "application/gzip"),
+        (b"\x28\xb5\x2f\xfd", "application/zstd"),
+        (b"BZh", "application/x-bzip2"),
+        (b"\xfd7zXZ\x00", "application/x-xz"),
+        (b"7z\xbc\xaf\x27\x1c", "application/x-7z-compressed"),
+        (b"OggS", "audio/ogg"),
+        (b"fLaC", "audio/flac"),
+        (b"ID3", "audio/mpeg"),
+        (b"\x1a\x45\xdf\xa3", "video/webm"),
+        (b"\x00asm", "application/wasm"),
+    ];
+    if let Some((_, content_type)) = MAGIC.iter().find(|(magic, _)| head.starts_with(magic)) {
+        return Some(content_type);
+    }
+    // Containers naming their format a few bytes in
+    match (head.get(..4), head.get(4..8), head.get(8..12)) {
+        (Some(b"RIFF"), _, Some(b"WEBP")) => return Some("image/webp"),
+        (Some(b"RIFF"), _, Some(b"WAVE")) => return Some("audio/wav"),
+        (_, Some(b"ftyp"), _) => return Some("video/mp4"),
+        _ => {}
+    }
+    let text = head.trim_ascii_start();
+    let starts_with = |prefix: &[u8]| {
+        text.get(..prefix.len())
+            .is_some_and(|start| start.eq_ignore_ascii_case(prefix))
+    };
+    if starts_with(b"<!doctype html") || starts_with(b"<html") {
+        Some("text/html")
+    } else if starts_with(b"<?xml") {
+        Some("application/xml")
+    } else {
+        None
+    }
+}
+
+/// The content type an upload's `Content-Type` header names, unless it is
+/// malformed or what clients send by default for a raw body, which says
+/// nothing about the file
+pub fn upload_type(header: &str) -> Option<&str> {
+    let header = header.trim();
+    let essence = header.split(';').next().unwrap_or("").trim();
+    let generic = essence.is_empty()
+        || essence.eq_ignore_ascii_case("application/octet-stream")
+        || essence.eq_ignore_ascii_case("application/x-www-form-urlencoded");
+    let malformed =
+        header.len() > 255 || !essence.contains('/') || header.chars().any(char::is_control);
+    (!generic && !malformed).then_some(header)
+}
+
+/// Whether responses of `content_type` are worth compressing
+pub fn compressible(content_type: &str) -> bool {
+    // Parameters (`; charset=utf-8`) do not change the answer
+    let content_type = content_type.split(';').next().unwrap_or("").trim();
+    content_type.starts_with("text/")
+        || matches!(
+            content_type,
+            "application/json" | "application/xml" | "image/svg+xml"
+        )
+}
+
+/// Streaming compressor: bytes go in with [`Compressor::write`], and come
+/// out compressed as they are produced
+pub enum Compressor {
+    Gzip(GzEncoder<Vec<u8>>),
+    Zstd(zstd::stream::write::Encoder<'static, Vec<u8>>),
+}
+
+impl Compressor {
+    pub fn new(encoding: Encoding) -> io::Result<Self> {
+        Ok(match encoding {
+            Encoding::Gzip => Compressor::Gzip(GzEncoder::new(Vec::new(), GzLevel::default())),
+            Encoding::Zstd => {
+                Compressor::Zstd(zstd::stream::write::Encoder::new(Vec::new(), ZSTD_LEVEL)?)
+            }
+        })
+    }
+
+    /// Compresses `data`, returning the output produced so far
+    pub fn write(&mut self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Compressor::Gzip(enc) => {
+                enc.write_all(data)?;
+                Ok(std::mem::take(enc.get_mut()))
+            }
+            Compressor::Zstd(enc) => {
+                enc.write_all(data)?;
+                Ok(std::mem::take(enc.get_mut()))
+            }
+        }
+    }
+
+    /// Ends the stream, returning the remaining output
+    pub fn finish(self) -> io::Result<Vec<u8>> {
+        match self {
+            Compressor::Gzip(enc) => enc.finish(),
+            Compressor::Zstd(enc) => enc.finish(),
+        }
+    }
+}
+
+/// Compresses a whole response body
+pub fn compress(encoding: Encoding, data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut compressor = Compressor::new(encoding)?;
+    let mut out = compressor.write(data)?;
+    out.extend(compressor.finish()?);
+    Ok(out)
+}
@@ -0,0 +1,505 @@
+use crate::{
+    auth::{AccessList, ClusterToken},
+    disk::IoLimits,
+    limit::RateLimits,
+    logging::{LogBuffer, LogOptions},
+    schema::Labels,
+};
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+/// Settings a node is started with.
+///
+/// - `data_dir`: directory holding the node's chunks (`content/`, `backup/`),
+///   tags and identity; `nodes/<port>` when not given.
+/// - `replication`: number of backup copies kept for every chunk (0 disables backups).
+/// - `settings`: the subset that can be changed while the node runs.
+/// - `labels`: `key=value` pairs placement constraints select nodes by.
+/// - `failure_domain`: the label whose value backups of a chunk should not share.
+/// - `ring_id`: name of this ring in a federation; files of other rings are `<ring>/<name>`.
+#[derive(Debug, Clone)]
+pub struct NodeConfig {
+    /// Directory for the node's storage and identity (see [`NodeConfig::node_dir`])
+    pub data_dir: Option<PathBuf>,
+
+    /// Number of backup copies kept for every chunk
+    pub replication: u32,
+
+    /// Hot-reloadable settings
+    pub settings: Settings,
+
+    /// `key = value` file re-read on SIGHUP
+    pub config_file: Option<PathBuf>,
+
+    /// Applies a new tracing filter (installed by the binary that owns the subscriber)
+    pub log_filter_hook: Option<LogFilterHook>,
+
+    /// Alerts fired when a neighbor is found dead
+    pub death_hooks: DeathHooks,
+
+    /// Socket options for the listener and every peer connection
+    pub tcp: TcpOptions,
+
+    /// Answer and send health checks over UDP on the node's port number
+    pub udp_heartbeat: bool,
+
+    /// Log format and file, passed on to respawned nodes
+    pub logging: LogOptions,
+
+    /// Recent log lines of the process (installed by the binary that owns the subscriber)
+    pub log_buffer: Option<LogBuffer>,
+
+    /// Labels announced in the netmap, matched by `FILE PUSH ... PLACE`
+    pub labels: Labels,
+
+    /// Label naming a node's failure domain (`zone`, `rack`, ...)
+    pub failure_domain: String,
+
+    /// Name of this ring, for federation with other rings
+    pub ring_id: Option<String>,
+
+    /// Ring node relaying inbound connections, for a node that cannot accept
+    /// them itself (see [`crate::relay`])
+    pub relay: Option<String>,
+
+    /// Secret the ring's internal commands are authenticated with (see
+    /// [`crate::auth`]); `None` takes them from anyone
+    pub cluster_token: Option<ClusterToken>,
+
+    /// Client tokens and their roles (see [`crate::auth`]); `None` lets any
+    /// client send any client command
+    pub access: Option<AccessList>,
+}
+
+impl Default for NodeConfig {
+    fn default() -> Self {
+        Self {
+            data_dir: None,
+            replication: 1,
+            settings: Settings::default(),
+            config_file: None,
+            log_filter_hook: None,
+            death_hooks: DeathHooks::default(),
+            tcp: TcpOptions::default(),
+            udp_heartbeat: false,
+            logging: LogOptions::default(),
+            log_buffer: None,
+            labels: Labels::default(),
+            failure_domain: "zone".to_string(),
+            ring_id: None,
+            relay: None,
+            cluster_token: None,
+            access: None,
+        }
+    }
+}
+
+/// Directory nodes started without a `data_dir` keep theirs in, one per port
+const DEFAULT_DATA_ROOT: &str = "nodes";
+
+impl NodeConfig {
+    /// Directory the node listening on `port` keeps its storage and identity
+    /// in: `data_dir`, or `nodes/<port>` if none was given. A `data_dir` from
+    /// before, holding the node's chunks under `<port>/`, is used as it was.
+    pub fn node_dir(&self, port: u16) -> PathBuf {
+        let Some(dir) = &self.data_dir else {
+            return Path::new(DEFAULT_DATA_ROOT).join(port.to_string());
+        };
+        let legacy = dir.join(port.to_string());
+        if !dir.join("content").is_dir() && legacy.join("content").is_dir() {
+            return legacy;
+        }
+        dir.clone()
+    }
+}
+
+/// Settings that can be changed on a running node, through `NODE CONFIG SET`
+/// or by re-reading the config file on SIGHUP.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Settings {
+    /// Time between gossip health checks. Zero pauses the gossip loop.
+    pub gossip_interval: Duration,
+
+    /// Random spread applied to each gossip delay, in percent either way
+    pub gossip_jitter: u32,
+
+    /// How long a health check waits for connect and PONG
+    pub health_timeout: Duration,
+
+    /// Max file size.
+    pub file_size: u64,
+
+    /// Largest chunk a node stores or reads from a peer. Pushes that would
+    /// need bigger chunks are refused.
+    pub max_chunk_size: u64,
+
+    /// Tracing filter directive (`RUST_LOG` syntax), if overridden
+    pub log_filter: Option<String>,
+
+    /// Whether dead neighbors get respawned
+    pub respawn: RespawnMode,
+
+    /// Respawns allowed per peer within [`RESPAWN_WINDOW`] (0 = unlimited)
+    pub max_respawns: u32,
+
+    /// Delay before a repeated respawn of the same peer; doubles on every retry
+    pub respawn_backoff: Duration,
+
+    /// Time between background scrub passes over stored chunks. Zero disables scrubbing.
+    pub scrub_interval: Duration,
+
+    /// Bytes of recently served chunks kept in memory. Zero disables the cache.
+    pub chunk_cache_size: u64,
+
+    /// Chunks up to this many bytes are moved into segment files in the
+    /// background (see [`crate::pack`]). Zero disables packing.
+    pub pack_threshold: u64,
+
+    /// Client pushes and pulls run at once; more wait their turn. Zero means no limit.
+    pub max_transfers: u32,
+
+    /// Chunk streams from other nodes (`FILE RELAY-STREAM`, `FILE RELAY-BLOB`,
+    /// `FILE PUT-CHUNK`, `FILE MIGRATE-CHUNK`) stored at once. Zero means no limit.
+    pub max_relays: u32,
+
+    /// Chunk streams that may wait for a `max-relays` slot; more are refused
+    /// with `ERR BUSY`
+    pub relay_queue: u32,
+
+    /// Blocks of client chunks, backups and scrubbing read or written at once
+    /// (see [`crate::disk`]). Zero means no limit.
+    pub io_foreground: u32,
+    pub io_backup: u32,
+    pub io_scrub: u32,
+
+    /// How long `TOPOLOGY WALK` waits for the walk to come back around the ring
+    pub walk_timeout: Duration,
+
+    /// How long `NODE HEAL` waits for the heal walk, respawns included
+    pub heal_timeout: Duration,
+
+    /// New connections a source IP may open per second. Zero means no limit.
+    pub conn_rate: u32,
+
+    /// Commands a source IP may send per second. Zero means no limit.
+    pub command_rate: u32,
+
+    /// Protocol errors a source IP may make within a minute before it is banned. Zero never bans.
+    pub ban_errors: u32,
+
+    /// How long a ban lasts
+    pub ban_time: Duration,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            gossip_interval: Duration::from_millis(5000),
+            gossip_jitter: 20,
+            health_timeout: Duration::from_millis(2000),
+            file_size: 1_000_000_000,
+            max_chunk_size: 1_000_000_000,
+            log_filter: None,
+            respawn: RespawnMode::Always,
+            max_respawns: 0,
+            respawn_backoff: Duration::from_millis(1000),
+            scrub_interval: Duration::from_secs(3600),
+            chunk_cache_size: 32 * 1024 * 1024,
+            pack_threshold: 0,
+            max_transfers: 8,
+            max_relays: 16,
+            relay_queue: 64,
+            io_foreground: 8,
+            io_backup: 2,
+            io_scrub: 1,
+            walk_timeout: Duration::from_secs(30),
+            heal_timeout: Duration::from_secs(60),
+            conn_rate: 20,
+            command_rate: 100,
+            ban_errors: 20,
+            ban_time: Duration::from_secs(300),
+        }
+    }
+}
+
+impl Settings {
+    /// Keys accepted by [`Settings::set`]
+    pub const KEYS: &'static [&'static str] = &[
+        "wait-time",
+        "gossip-jitter",
+        "health-timeout",
+        "file-size",
+        "max-chunk-size",
+        "log-filter",
+        "respawn",
+        "max-respawns",
+        "respawn-backoff",
+        "scrub-interval",
+        "chunk-cache-size",
+        "pack-threshold",
+        "max-transfers",
+        "max-relays",
+        "relay-queue",
+        "io-foreground",
+        "io-backup",
+        "io-scrub",
+        "walk-timeout",
+        "heal-timeout",
+        "conn-rate",
+        "command-rate",
+        "ban-errors",
+        "ban-time",
+    ];
+
+    /// Updates one setting from its textual `key` / `value` form.
+    ///
+    /// Durations are given in milliseconds, matching the CLI flags.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let value = value.trim();
+        match key {
+            "wait-time" => self.gossip_interval = Duration::from_millis(parse_num(key, value)?),
+            "gossip-jitter" => {
+                let pct: u32 = parse_num(key, value)?;
+                if pct > 100 {
+                    return Err("gossip-jitter must be a percentage (0-100)".into());
+                }
+                self.gossip_jitter = pct;
+            }
+            "health-timeout" => {
+                let ms = parse_num(key, value)?;
+                if ms == 0 {
+                    return Err("health-timeout must be > 0".into());
+                }
+                self.health_timeout = Duration::from_millis(ms);
+            }
+            "file-size" => self.file_size = parse_num(key, value)?,
+            "max-chunk-size" => self.max_chunk_size = parse_num(key, value)?,
+            "log-filter" => {
+                if value.is_empty() {
+                    return Err("missing value for log-filter".into());
+                }
+                self.log_filter = Some(value.to_string());
+            }
+            "respawn" => self.respawn = value.parse()?,
+            "max-respawns" => self.max_respawns = parse_num(key, value)?,
+            "respawn-backoff" => {
+                self.respawn_backoff = Duration::from_millis(parse_num(key, value)?)
+            }
+            "scrub-interval" => self.scrub_interval = Duration::from_millis(parse_num(key, value)?),
+            "chunk-cache-size" => self.chunk_cache_size = parse_num(key, value)?,
+            "pack-threshold" => self.pack_threshold = parse_num(key, value)?,
+            "max-transfers" => self.max_transfers = parse_num(key, value)?,
+            "max-relays" => self.max_relays = parse_num(key, value)?,
+            "relay-queue" => self.relay_queue = parse_num(key, value)?,
+            "io-foreground" => self.io_foreground = parse_num(key, value)?,
+            "io-backup" => self.io_backup = parse_num(key, value)?,
+            "io-scrub" => self.io_scrub = parse_num(key, value)?,
+            "walk-timeout" | "heal-timeout" => {
+                let ms = parse_num(key, value)?;
+                if ms == 0 {
+                    return Err(format!("{} must be > 0", key));
+                }
+                match key {
+                    "walk-timeout" => self.walk_timeout = Duration::from_millis(ms),
+                    _ => self.heal_timeout = Duration::from_millis(ms),
+                }
+            }
+            "conn-rate" => self.conn_rate = parse_num(key, value)?,
+            "command-rate" => self.command_rate = parse_num(key, value)?,
+            "ban-errors" => self.ban_errors = parse_num(key, value)?,
+            "ban-time" => self.ban_time = Duration::from_millis(parse_num(key, value)?),
+            _ => return Err(format!("unknown setting '{}'", key)),
+        }
+        Ok(())
+    }
+
+    /// Current value of one setting, in the form [`Settings::set`] accepts
+    /// (`-` for an unset `log-filter`)
+    pub fn get(&self, key: &str) -> Option<String> {
+        let ms = |d: Duration| d.as_millis().to_string();
+        Some(match key {
+            "wait-time" => ms(self.gossip_interval),
+            "gossip-jitter" => self.gossip_jitter.to_string(),
+            "health-timeout" => ms(self.health_timeout),
+            "file-size" => self.file_size.to_string(),
+            "max-chunk-size" => self.max_chunk_size.to_string(),
+            "log-filter" => self.log_filter.clone().unwrap_or_else(|| "-".into()),
+            "respawn" => self.respawn.to_string(),
+            "max-respawns" => self.max_respawns.to_string(),
+            "respawn-backoff" => ms(self.respawn_backoff),
+            "scrub-interval" => ms(self.scrub_interval),
+            "chunk-cache-size" => self.chunk_cache_size.to_string(),
+            "pack-threshold" => self.pack_threshold.to_string(),
+            "max-transfers" => self.max_transfers.to_string(),
+            "max-relays" => self.max_relays.to_string(),
+            "relay-queue" => self.relay_queue.to_string(),
+            "io-foreground" => self.io_foreground.to_string(),
+            "io-backup" => self.io_backup.to_string(),
+            "io-scrub" => self.io_scrub.to_string(),
+            "walk-timeout" => ms(self.walk_timeout),
+            "heal-timeout" => ms(self.heal_timeout),
+            "conn-rate" => self.conn_rate.to_string(),
+            "command-rate" => self.command_rate.to_string(),
+            "ban-errors" => self.ban_errors.to_string(),
+            "ban-time" => ms(self.ban_time),
+            _ => return None,
+        })
+    }
+
+    /// The disk scheduler's limits (see [`crate::disk`])
+    pub fn io_limits(&self) -> IoLimits {
+        IoLimits {
+            foreground: self.io_foreground,
+            backup: self.io_backup,
+            scrub: self.io_scrub,
+        }
+    }
+
+    /// The listener's per-source limits (see [`crate::limit`])
+    pub fn rate_limits(&self) -> RateLimits {
+        RateLimits {
+            conn_rate: self.conn_rate,
+            command_rate: self.command_rate,
+            ban_errors: self.ban_errors,
+            ban_time: self.ban_time,
+        }
+    }
+
+    /// Every setting with its current value, in [`Settings::KEYS`] order
+    pub fn entries(&self) -> Vec<(&'static str, String)> {
+        Self::KEYS
+            .iter()
+            .filter_map(|key| Some((*key, self.get(key)?)))
+            .collect()
+    }
+}
+
+/// Respawns of the same peer older than this no longer count towards `max-respawns`
+pub const RESPAWN_WINDOW: Duration = Duration::from_secs(600);
+
+/// What a node does after finding its neighbor dead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RespawnMode {
+    /// Start a new process for the dead node (subject to `max-respawns`)
+    Always,
+    /// Only mark the node as dead and fire the alert hooks
+    Never,
+}
+
+impl std::str::FromStr for RespawnMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "always" => Ok(Self::Always),
+            "never" => Ok(Self::Never),
+            _ => Err(format!(
+                "invalid respawn mode '{}' (expected always|never)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for RespawnMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Always => "always",
+            Self::Never => "never",
+        })
+    }
+}
+
+/// Alerts fired when a neighbor is found dead.
+///
+/// - `exec`: shell command, run with `OUROBOROS_*` environment variables describing the death.
+/// - `webhook`: `http://` URL receiving the same details as a JSON `POST`.
+#[derive(Debug, Clone, Default)]
+pub struct DeathHooks {
+    pub exec: Option<String>,
+    pub webhook: Option<String>,
+}
+
+/// Socket options applied to the listener, accepted connections and
+/// outgoing connections to peers.
+///
+/// - `nodelay`: disables Nagle's algorithm, so short control lines are sent at once.
+/// - `keepalive`: idle time before TCP keepalive probes start (`None` leaves keepalive off).
+/// - `send_buffer` / `recv_buffer`: `SO_SNDBUF` / `SO_RCVBUF` in bytes (`None` keeps the OS default).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TcpOptions {
+    pub nodelay: bool,
+    pub keepalive: Option<Duration>,
+    pub send_buffer: Option<u32>,
+    pub recv_buffer: Option<u32>,
+}
+
+impl Default for TcpOptions {
+    fn default() -> Self {
+        Self {
+            nodelay: true,
+            keepalive: Some(Duration::from_secs(60)),
+            send_buffer: None,
+            recv_buffer: None,
+        }
+    }
+}
+
+impl TcpOptions {
+    /// Same options as `run` arguments, for spawned nodes
+    pub fn cli_args(&self) -> Vec<String> {
+        let mut args = vec![
+            "--tcp-nodelay".to_string(),
+            self.nodelay.to_string(),
+            "--tcp-keepalive".to_string(),
+            self.keepalive.map_or(0, |d| d.as_secs()).to_string(),
+        ];
+        if let Some(size) = self.send_buffer {
+            args.extend(["--tcp-send-buffer".to_string(), size.to_string()]);
+        }
+        if let Some(size) = self.recv_buffer {
+            args.extend(["--tcp-recv-buffer".to_string(), size.to_string()]);
+        }
+        args
+    }
+}
+
+fn parse_num<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, String> {
+    value
+        .parse::<T>()
+        .map_err(|_| format!("invalid value for {}: '{}'", key, value))
+}
+
+/// Parses a config file made of `key = value` lines.
+///
+/// Blank lines and lines starting with `#` are ignored.
+pub fn parse_config_file(contents: &str) -> Result<Vec<(String, String)>, String> {
+    let mut out = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            return Err(format!("line {}: expected 'key = value'", i + 1));
+        };
+        out.push((key.trim().to_string(), value.trim().to_string()));
+    }
+    Ok(out)
+}
+
+type LogFilterFn = dyn Fn(&str) -> Result<(), String> + Send + Sync;
+
+/// Callback used to swap the tracing filter at runtime.
+#[derive(Clone)]
+pub struct LogFilterHook(pub Arc<LogFilterFn>);
+
+impl fmt::Debug for LogFilterHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("LogFilterHook")
+    }
+}
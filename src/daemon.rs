@@ -0,0 +1,216 @@
+//! Running a node in the background, for hosts without systemd.
+//!
+//! `run --daemonize --pid-file <path>` starts the same command again in a new
+//! session, detached from the terminal, and returns once that copy is
+//! listening; forking the running process itself is not an option, as its
+//! async runtime does not survive a `fork()`. The detached node writes its
+//! process id to the PID file, logs to its `--log-file`, and on `SIGTERM`
+//! stops and removes the PID file. `ouroboros_fs stop --pid-file <path>`
+//! sends that signal and waits for the node to exit.
+
+use std::{
+    ffi::OsString,
+    fs, io,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::Duration,
+};
+
+/// Flag the detached copy is started without
+pub const DAEMONIZE_FLAG: &str = "--daemonize";
+
+/// How long `--daemonize` waits for the detached node to write its PID file
+pub const READY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often process states are polled while waiting
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A PID file naming this process, removed when dropped
+#[derive(Debug)]
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// Writes this process's id to `path`. Fails if the file names another
+    /// process that is still running; a stale file is replaced.
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let own = std::process::id();
+        if let Ok(pid) = read_pid(path)
+            && pid != own
+            && is_running(pid)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} names running process {}", path.display(), pid),
+            ));
+        }
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, format!("{}\n", own))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        // Leave the file alone if another process took it over
+        if read_pid(&self.path).is_ok_and(|pid| pid == std::process::id()) {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Process id written in a PID file
+pub fn read_pid(path: &Path) -> io::Result<u32> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+    contents.trim().parse().map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} does not hold a process id", path.display()),
+        )
+    })
+}
+
+/// Whether process `pid` exists
+pub fn is_running(pid: u32) -> bool {
+    #[cfg(unix)]
+    {
+        // Signal 0 only checks the process exists and may be signalled
+        let res = unsafe { libc::kill(pid as libc::pid_t, 0) };
+        res == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = pid;
+        false
+    }
+}
+
+/// Starts this program again with `args` (its own arguments, without
+/// [`DAEMONIZE_FLAG`]) in a new session, with no terminal, and waits for it to
+/// write `pid_file`. Returns the detached process id.
+pub async fn detach(args: Vec<OsString>, pid_file: &Path) -> io::Result<u32> {
+    if let Ok(pid) = read_pid(pid_file)
+        && is_running(pid)
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} names running process {}", pid_file.display(), pid),
+        ));
+    }
+    let mut cmd = Command::new(std::env::current_exe()?);
+    cmd.args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    #[cfg(unix)]
+    unsafe {
+        use std::os::unix::process::CommandExt;
+        // A session of its own: no controlling terminal, no SIGHUP on logout
+        cmd.pre_exec(|| {
+            if libc::setsid() == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let mut child = cmd.spawn()?;
+    let pid = child.id();
+
+    let deadline = tokio::time::Instant::now() + READY_TIMEOUT;
+    loop {
+        if read_pid(pid_file).is_ok_and(|p| p == pid) {
+            return Ok(pid);
+        }
+        if let Some(status) = child.try_wait()? {
+            return Err(io::Error::other(format!(
+                "detached node exited with {} (see its log file)",
+                status
+            )));
+        }
+        if tokio::time::Instant::now() >= deadline {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!(
+                    "process {} did not write {} within {:?}",
+                    pid,
+                    pid_file.display(),
+                    READY_TIMEOUT
+                ),
+            ));
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+/// Stops the node named by `pid_file` with `SIGTERM`, waiting up to `timeout`
+/// for it to exit. Returns its process id, or `None` if it was not running,
+/// in which case a stale PID file is removed.
+pub async fn stop(pid_file: &Path, timeout: Duration) -> io::Result<Option<u32>> {
+    let pid = read_pid(pid_file)?;
+    if !is_running(pid) {
+        let _ = fs::remove_file(pid_file);
+        return Ok(None);
+    }
+    terminate(pid)?;
+
+    let deadline = tokio::time::Instant::now() + timeout;
+    while is_running(pid) {
+        if tokio::time::Instant::now() >= deadline {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("process {} still running after {:?}", pid, timeout),
+            ));
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+    Ok(Some(pid))
+}
+
+fn terminate(pid: u32) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        if unsafe { libc::kill(pid as libc::pid_t, libc::SIGTERM) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = pid;
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+/// Waits for `SIGTERM` or Ctrl-C
+pub async fn terminated() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+        match signal(SignalKind::terminate()) {
+            Ok(mut term) => {
+                tokio::select! {
+                    _ = term.recv() => {}
+                    _ = tokio::signal::ctrl_c() => {}
+                }
+            }
+            Err(e) => {
+                tracing::warn!(error = ?e, "Could not install SIGTERM handler");
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
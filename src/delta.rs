@@ -0,0 +1,279 @@
+//! Delta sync: updating a stored file by sending only the blocks that changed.
+//!
+//! The client cuts the new version of a file into blocks and sends their
+//! signatures (`FILE SYNC`): a rolling checksum and a SHA-256 each. The node
+//! slides a window over the version it stores, rolling the checksum one byte
+//! at a time, so a block is found wherever it moved to. It answers with the
+//! blocks it could not find (`NEED`), the client sends just those, and the
+//! node rebuilds the file and rewrites the chunks whose bytes changed.
+
+use crate::{
+    auth,
+    checksum::{Digest, Sha256},
+    protocol::encode_name,
+};
+use serde::Serialize;
+use std::{collections::HashMap, error::Error, fmt, str::FromStr, time::Duration};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+type AnyErr = Box<dyn Error + Send + Sync>;
+
+/// Block size used when the client does not pick one
+pub const DEFAULT_BLOCK_SIZE: u64 = 64 * 1024;
+
+/// Most blocks a `FILE SYNC` may announce
+pub const MAX_BLOCKS: u64 = 1 << 20;
+
+/// How long a node may take to compare and to store a file
+const SYNC_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Checksum of a window of bytes that can be moved one byte along in O(1),
+/// as in rsync: `a` sums the bytes, `b` sums them weighted by position.
+#[derive(Debug, Clone, Copy)]
+pub struct Rolling {
+    a: u32,
+    b: u32,
+    len: u32,
+}
+
+impl Rolling {
+    pub fn new(window: &[u8]) -> Self {
+        let len = window.len() as u32;
+        let (mut a, mut b) = (0u32, 0u32);
+        for (i, &byte) in window.iter().enumerate() {
+            a = a.wrapping_add(byte as u32);
+            b = b.wrapping_add((len - i as u32).wrapping_mul(byte as u32));
+        }
+        Self { a, b, len }
+    }
+
+    /// Moves the window one byte on: `out` leaves it, `into` enters it
+    pub fn roll(&mut self, out: u8, into: u8) {
+        self.a = self.a.wrapping_sub(out as u32).wrapping_add(into as u32);
+        self.b = self
+            .b
+            .wrapping_sub(self.len.wrapping_mul(out as u32))
+            .wrapping_add(self.a);
+    }
+
+    pub fn value(&self) -> u32 {
+        (self.a & 0xffff) | (self.b << 16)
+    }
+}
+
+/// Signature of one block, `<rolling hex>:<sha256 hex>` on the wire
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockSignature {
+    pub weak: u32,
+    pub strong: Digest,
+}
+
+impl BlockSignature {
+    pub fn of(block: &[u8]) -> Self {
+        Self {
+            weak: Rolling::new(block).value(),
+            strong: Sha256::digest(block),
+        }
+    }
+}
+
+impl fmt::Display for BlockSignature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:08x}:{}", self.weak, self.strong)
+    }
+}
+
+impl FromStr for BlockSignature {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (weak, strong) = s
+            .trim()
+            .split_once(':')
+            .ok_or_else(|| format!("invalid block signature '{}'", s.trim()))?;
+        Ok(Self {
+            weak: u32::from_str_radix(weak, 16)
+                .map_err(|_| format!("invalid block signature '{}'", s.trim()))?,
+            strong: strong.parse()?,
+        })
+    }
+}
+
+/// Signatures of `data` cut into `block_size` blocks (the last one shorter)
+pub fn signatures(data: &[u8], block_size: usize) -> Vec<BlockSignature> {
+    data.chunks(block_size.max(1))
+        .map(BlockSignature::of)
+        .collect()
+}
+
+/// Number of `block_size` blocks in `size` bytes
+pub fn block_count(size: u64, block_size: u64) -> u64 {
+    size.div_ceil(block_size.max(1))
+}
+
+/// Where each block of the new version sits in `old`, or `None` when it has to
+/// be sent. `size` is the new version's length.
+pub fn find_blocks(
+    old: &[u8],
+    sigs: &[BlockSignature],
+    block_size: usize,
+    size: u64,
+) -> Vec<Option<usize>> {
+    let block_size = block_size.max(1);
+    let mut found = vec![None; sigs.len()];
+    let Some(last) = sigs.len().checked_sub(1) else {
+        return found;
+    };
+    let last_len = (size as usize).saturating_sub(last * block_size);
+
+    // Full-size blocks, wherever they are in the old version
+    let mut by_weak: HashMap<u32, Vec<usize>> = HashMap::new();
+    for (i, sig) in sigs.iter().enumerate() {
+        if i < last || last_len == block_size {
+            by_weak.entry(sig.weak).or_default().push(i);
+        }
+    }
+    let mut missing = by_weak.values().map(Vec::len).sum::<usize>();
+    if missing > 0 && old.len() >= block_size {
+        let mut rolling = Rolling::new(&old[..block_size]);
+        for at in 0..=old.len() - block_size {
+            if at > 0 {
+                rolling.roll(old[at - 1], old[at + block_size - 1]);
+            }
+            let Some(blocks) = by_weak.get(&rolling.value()) else {
+                continue;
+            };
+            let mut strong = None;
+            for &i in blocks {
+                if found[i].is_some() {
+                    continue;
+                }
+                let digest =
+                    *strong.get_or_insert_with(|| Sha256::digest(&old[at..at + block_size]));
+                if digest == sigs[i].strong {
+                    found[i] = Some(at);
+                    missing -= 1;
+                }
+            }
+            if missing == 0 {
+                break;
+            }
+        }
+    }
+
+    // A shorter last block: where it was, or at the end of the old version
+    if last_len < block_size && found[last].is_none() {
+        let candidates = [last * block_size, old.len().saturating_sub(last_len)];
+        found[last] = candidates.into_iter().find(|&at| {
+            old.get(at..at + last_len)
+                .is_some_and(|bytes| Sha256::digest(bytes) == sigs[last].strong)
+        });
+    }
+    found
+}
+
+/// `NEED <i>,<j>,...` (`NEED -` for none): the blocks the client must send
+pub fn need_line(found: &[Option<usize>]) -> String {
+    let needed: Vec<String> = found
+        .iter()
+        .enumerate()
+        .filter(|(_, at)| at.is_none())
+        .map(|(i, _)| i.to_string())
+        .collect();
+    if needed.is_empty() {
+        "NEED -".to_string()
+    } else {
+        format!("NEED {}", needed.join(","))
+    }
+}
+
+/// Blocks a `NEED <i>,<j>,...` answer asks for (`NEED -`: none)
+pub(crate) fn parse_need(line: &str) -> Result<Vec<usize>, String> {
+    let list = line
+        .trim()
+        .strip_prefix("NEED ")
+        .ok_or_else(|| format!("unexpected answer '{}'", line.trim()))?;
+    if list == "-" {
+        return Ok(Vec::new());
+    }
+    list.split(',')
+        .map(|i| i.parse().map_err(|_| format!("invalid block '{}'", i)))
+        .collect()
+}
+
+/// What a `FILE SYNC` did
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SyncReport {
+    /// Blocks sent, out of the blocks of the file
+    pub sent: usize,
+    pub blocks: usize,
+    /// Bytes sent
+    pub bytes: u64,
+    /// The node's `SYNCED ...` line
+    pub summary: String,
+}
+
+/// Updates `name` on the ring through the node at `addr` to hold `data`,
+/// sending only the blocks the stored version lacks. A file that is not
+/// stored yet is sent whole.
+pub async fn sync_file(
+    addr: &str,
+    name: &str,
+    data: &[u8],
+    block_size: u64,
+) -> Result<SyncReport, AnyErr> {
+    let block_size = block_size.max(1);
+    let exchange = async {
+        let stream = auth::connect(addr).await?;
+        let mut stream = BufReader::new(stream);
+        let mut request = format!(
+            "FILE SYNC {} {} {}\n",
+            data.len(),
+            block_size,
+            encode_name(name)
+        );
+        for sig in signatures(data, block_size as usize) {
+            request.push_str(&format!("{}\n", sig));
+        }
+        stream.get_mut().write_all(request.as_bytes()).await?;
+
+        let mut line = String::new();
+        stream.read_line(&mut line).await?;
+        if let Some(err) = line.trim().strip_prefix("ERR") {
+            return Err::<_, AnyErr>(err.trim().to_string().into());
+        }
+        let needed = parse_need(&line)?;
+
+        let mut bytes = 0;
+        for &i in &needed {
+            let start = i.saturating_mul(block_size as usize);
+            let block = data
+                .get(start..data.len().min(start.saturating_add(block_size as usize)))
+                .ok_or_else(|| format!("node asked for block {} of {}", i, name))?;
+            stream.get_mut().write_all(block).await?;
+            bytes += block.len() as u64;
+        }
+
+        let mut summary = String::new();
+        loop {
+            line.clear();
+            if stream.read_line(&mut line).await? == 0 {
+                return Err("connection closed before OK".into());
+            }
+            match line.trim() {
+                "OK" => break,
+                l if l.starts_with("ERR") => return Err(l[3..].trim().to_string().into()),
+                l => summary = l.to_string(),
+            }
+        }
+        Ok(SyncReport {
+            sent: needed.len(),
+            blocks: block_count(data.len() as u64, block_size) as usize,
+            bytes,
+            summary,
+        })
+    };
+    tokio::time::timeout(SYNC_TIMEOUT, exchange)
+        .await
+        .map_err(|_| -> AnyErr { "FILE SYNC timed out".into() })?
+}
@@ -0,0 +1,266 @@
+//! Disk I/O scheduling: client traffic before maintenance.
+//!
+//! Chunk bytes a node writes or reads go through its [`DiskScheduler`] one
+//! block (at most [`IO_BLOCK`] bytes) at a time, each block in a class:
+//!
+//! - [`IoClass::Foreground`]: chunks of client pushes as they are stored,
+//!   chunks read for pulls, and `FILE CHECK-CHUNK`,
+//! - [`IoClass::Backup`]: backup copies, written by their holder and read
+//!   for it from `content/`, and chunks moved by `FILE MIGRATE-CHUNK`,
+//! - [`IoClass::Scrub`]: the background scrubber's re-hashing and repairs.
+//!
+//! Each class moves at most its limit of blocks at once (the `io-foreground`,
+//! `io-backup` and `io-scrub` settings, 0 for no limit), and a block waits as
+//! long as blocks of a higher class wait; scrubbing also waits while any
+//! other block is moving. Maintenance so takes the disk when clients leave
+//! it some. A permit is only held for one block, never while waiting on the
+//! network, so nodes streaming chunks to each other never hold the disk
+//! waiting for one another.
+
+use std::{
+    fmt, io,
+    sync::Mutex,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    sync::Notify,
+};
+
+/// Bytes read or written under one permit
+pub const IO_BLOCK: usize = 256 * 1024;
+
+/// What a disk operation is for, highest priority first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum IoClass {
+    Foreground,
+    Backup,
+    Scrub,
+}
+
+impl IoClass {
+    pub const ALL: [IoClass; 3] = [IoClass::Foreground, IoClass::Backup, IoClass::Scrub];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Foreground => "foreground",
+            Self::Backup => "backup",
+            Self::Scrub => "scrub",
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+impl fmt::Display for IoClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Blocks each class may move at once, from the `io-foreground`,
+/// `io-backup` and `io-scrub` settings. Zero means no limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoLimits {
+    pub foreground: u32,
+    pub backup: u32,
+    pub scrub: u32,
+}
+
+impl IoLimits {
+    fn of(&self, class: IoClass) -> u32 {
+        match class {
+            IoClass::Foreground => self.foreground,
+            IoClass::Backup => self.backup,
+            IoClass::Scrub => self.scrub,
+        }
+    }
+}
+
+/// Where a class stands, as reported by `NODE METRICS`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ClassStats {
+    /// Blocks being read or written
+    pub active: u32,
+    /// Operations waiting for a permit
+    pub waiting: u32,
+}
+
+/// Admits a node's chunk reads and writes by class (see the module docs)
+#[derive(Debug)]
+pub struct DiskScheduler {
+    limits: Mutex<IoLimits>,
+    classes: Mutex<[ClassStats; 3]>,
+    /// Bytes moved, per class, since the node started
+    bytes: [AtomicU64; 3],
+    /// Signalled when a permit is given back or a waiter leaves the queue
+    freed: Notify,
+}
+
+/// Lets one block of its class go to disk until dropped
+pub struct IoPermit<'a> {
+    scheduler: &'a DiskScheduler,
+    class: IoClass,
+}
+
+impl Drop for IoPermit<'_> {
+    fn drop(&mut self) {
+        self.scheduler.classes()[self.class.index()].active -= 1;
+        self.scheduler.freed.notify_waiters();
+    }
+}
+
+/// Counts an operation as waiting for as long as it lives, so one given up
+/// on (a cancelled transfer) does not hold lower classes back
+struct Waiting<'a> {
+    scheduler: &'a DiskScheduler,
+    class: IoClass,
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.scheduler.classes()[self.class.index()].waiting -= 1;
+        self.scheduler.freed.notify_waiters();
+    }
+}
+
+impl DiskScheduler {
+    pub fn new(limits: IoLimits) -> Self {
+        Self {
+            limits: Mutex::new(limits),
+            classes: Mutex::new([ClassStats::default(); 3]),
+            bytes: Default::default(),
+            freed: Notify::new(),
+        }
+    }
+
+    /// Applies changed limits; blocks already admitted finish as they are
+    pub fn set_limits(&self, limits: IoLimits) {
+        *self.limits.lock().unwrap_or_else(|e| e.into_inner()) = limits;
+        self.freed.notify_waiters();
+    }
+
+    fn limits(&self) -> IoLimits {
+        *self.limits.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn classes(&self) -> std::sync::MutexGuard<'_, [ClassStats; 3]> {
+        self.classes.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Each class with where it stands, highest priority first
+    pub fn stats(&self) -> Vec<(IoClass, ClassStats)> {
+        let classes = *self.classes();
+        IoClass::ALL
+            .into_iter()
+            .map(|c| (c, classes[c.index()]))
+            .collect()
+    }
+
+    /// Bytes moved by `class` since the node started
+    pub fn bytes(&self, class: IoClass) -> u64 {
+        self.bytes[class.index()].load(Ordering::Relaxed)
+    }
+
+    /// Waits until a block of `class` may go to disk: its class is under its
+    /// limit and no higher class is waiting (or, for scrubbing, moving)
+    pub async fn acquire(&self, class: IoClass) -> IoPermit<'_> {
+        let mut waiting: Option<Waiting<'_>> = None;
+        loop {
+            // Register for a wake-up before checking, so a permit freed in between is not missed
+            let freed = self.freed.notified();
+            let limit = self.limits().of(class);
+            {
+                let mut classes = self.classes();
+                let higher = &classes[..class.index()];
+                // Scrubbing only runs on an otherwise idle disk
+                let yields = higher
+                    .iter()
+                    .any(|c| c.waiting > 0 || (class == IoClass::Scrub && c.active > 0));
+                let stats = &mut classes[class.index()];
+                if !yields && (limit == 0 || stats.active < limit) {
+                    stats.active += 1;
+                    drop(classes);
+                    // Leaving the queue may let a lower class in
+                    drop(waiting);
+                    return IoPermit {
+                        scheduler: self,
+                        class,
+                    };
+                }
+                if waiting.is_none() {
+                    stats.waiting += 1;
+                    waiting = Some(Waiting {
+                        scheduler: self,
+                        class,
+                    });
+                }
+            }
+            freed.await;
+        }
+    }
+
+    /// Counts `n` bytes moved by `class` without going through this scheduler
+    /// (mapped reads, see the `mmap` feature)
+    pub fn record(&self, class: IoClass, n: usize) {
+        self.bytes[class.index()].fetch_add(n as u64, Ordering::Relaxed);
+    }
+
+    /// Copies `reader` into `writer` until the end, writing each block under a
+    /// permit of `class`. Reading is not scheduled: it may wait on the network.
+    pub async fn copy<R, W>(
+        &self,
+        class: IoClass,
+        reader: &mut R,
+        writer: &mut W,
+    ) -> io::Result<u64>
+    where
+        R: AsyncRead + Unpin + ?Sized,
+        W: AsyncWrite + Unpin + ?Sized,
+    {
+        let mut buf = vec![0u8; IO_BLOCK];
+        let mut copied = 0u64;
+        loop {
+            // Fill a whole block first, so small network reads do not each take a permit
+            let mut filled = 0;
+            while filled < buf.len() {
+                let n = reader.read(&mut buf[filled..]).await?;
+                if n == 0 {
+                    break;
+                }
+                filled += n;
+            }
+            self.write_all(class, writer, &buf[..filled]).await?;
+            copied += filled as u64;
+            if filled < buf.len() {
+                return Ok(copied);
+            }
+        }
+    }
+
+    /// Writes `data`, one block per permit of `class`
+    pub async fn write_all<W>(&self, class: IoClass, writer: &mut W, data: &[u8]) -> io::Result<()>
+    where
+        W: AsyncWrite + Unpin + ?Sized,
+    {
+        for block in data.chunks(IO_BLOCK) {
+            let _permit = self.acquire(class).await;
+            writer.write_all(block).await?;
+            self.record(class, block.len());
+        }
+        Ok(())
+    }
+
+    /// Reads up to `buf.len()` bytes under a permit of `class`
+    pub async fn read<R>(&self, class: IoClass, reader: &mut R, buf: &mut [u8]) -> io::Result<usize>
+    where
+        R: AsyncRead + Unpin + ?Sized,
+    {
+        let _permit = self.acquire(class).await;
+        let n = reader.read(buf).await?;
+        self.record(class, n);
+        Ok(n)
+    }
+}
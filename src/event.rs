@@ -0,0 +1,43 @@
+use crate::time::Timestamp;
+use serde::Serialize;
+
+/// Cluster changes observed by a node, delivered through [`crate::Node::subscribe`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum NodeEvent {
+    /// This node's next hop was (re)configured
+    NextChanged { next: String },
+    /// A peer failed a health check or a chunk request
+    PeerDead { port: String },
+    /// A neighbor's pings have been much slower than usual for several rounds
+    PeerSuspect { port: String, rtt_ms: u64 },
+    /// A dead peer was respawned and synced
+    PeerHealed { port: String },
+    /// The coordinator (lowest alive port in the netmap) changed
+    LeaderChanged { port: String },
+    /// A chunk was written to this node's `content/` (or `backup/`) directory
+    ChunkStored { name: String, backup: bool },
+    /// A stored chunk failed verification against the manifest
+    ChunkDamaged {
+        name: String,
+        backup: bool,
+        repaired: bool,
+    },
+    /// A client push entered the ring through this node
+    FilePushed { name: String, size: u64, parts: u32 },
+    /// A topology walk returned to its start node
+    WalkCompleted { token: String, history: String },
+    /// This node reaches too few of its peers and went read-only (see
+    /// [`crate::partition`]); `reached` and `known` count this node too
+    PartitionSuspected { reached: usize, known: usize },
+    /// This node reaches a majority of its peers again and left degraded mode
+    PartitionRecovered { reached: usize, known: usize },
+}
+
+/// A [`NodeEvent`] with the time the node published it, from its hybrid
+/// logical clock (see [`crate::time`]): events of different nodes sort by it
+/// in the order they happened, whatever their wall clocks say
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StampedEvent {
+    pub at: Timestamp,
+    pub event: NodeEvent,
+}
@@ -0,0 +1,368 @@
+//! Reliable delivery of ring-wide state (`NETMAP SET`, `TOPOLOGY SET`,
+//! `FILE TAGS-SET`) to the other nodes.
+//!
+//! A message is put in an outbox per peer before it is sent, and leaves it
+//! once the peer answered `OK`. Each outbox holds one message of each kind:
+//! messages are numbered as this node makes them, and a newer one replaces an
+//! older one still waiting, which it supersedes. The outboxes are kept in the
+//! data directory, so a node restarted before they drained still owes them:
+//!
+//! ```text
+//! <data_dir>/<port>/outbox.json
+//! ```
+//!
+//! Up to [`BROADCAST_CONCURRENCY`] peers are sent their messages at once, one
+//! message at a time each, so a peer sees them in order. A send that times out
+//! or has its connection reset is retried at once with a short backoff; other
+//! failures wait for the flush loop, which tries again every few seconds, and
+//! right away when a peer marked `Dead` comes back. Peers that leave the
+//! netmap are dropped with their outbox.
+
+use crate::{auth::ClusterToken, config::TcpOptions, node::Node, relay::Route};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    io,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    sync::Notify,
+    task::JoinSet,
+};
+
+/// Name of the outbox file inside a node's data directory
+pub const OUTBOX_FILE: &str = "outbox.json";
+
+/// Peers sent their messages at once
+pub const BROADCAST_CONCURRENCY: usize = 16;
+
+/// Sends per message, the first included, for failures worth retrying at once
+const ATTEMPTS: u32 = 3;
+
+/// Pause before the first retry, doubled for each further one
+const RETRY_BACKOFF: Duration = Duration::from_millis(100);
+
+/// How long sending a message and reading its acknowledgement may take
+const SEND_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How often the flush loop retries the outboxes left
+const REDELIVERY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// What a message carries, in the order a peer is sent them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Broadcast {
+    /// `NETMAP SET`
+    Netmap,
+    /// `TOPOLOGY SET`
+    Topology,
+    /// `FILE TAGS-SET`
+    Tags,
+}
+
+/// One message waiting for its acknowledgement
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Message {
+    pub version: u64,
+    /// Line sent, newline included
+    pub line: String,
+}
+
+/// Messages waiting for one peer's acknowledgement, at most one per kind
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Outbox(BTreeMap<Broadcast, Message>);
+
+impl Outbox {
+    /// Queues `msg`, unless a message of its kind at least as new waits
+    /// already
+    pub fn enqueue(&mut self, kind: Broadcast, msg: Message) {
+        if self.0.get(&kind).is_none_or(|m| m.version < msg.version) {
+            self.0.insert(kind, msg);
+        }
+    }
+
+    /// Drops the message of `kind`, if it is still the one of `version`
+    pub fn ack(&mut self, kind: Broadcast, version: u64) {
+        if self.0.get(&kind).is_some_and(|m| m.version == version) {
+            self.0.remove(&kind);
+        }
+    }
+
+    /// Message to send next
+    pub fn next(&self) -> Option<(Broadcast, Message)> {
+        self.0.iter().next().map(|(kind, m)| (*kind, m.clone()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// Every peer's outbox, by port, as kept in [`OUTBOX_FILE`]
+#[derive(Debug)]
+pub struct Outboxes {
+    path: PathBuf,
+    peers: Mutex<BTreeMap<String, Outbox>>,
+    /// Held while a peer is sent its messages, so they go out in order
+    sending: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+    /// Version of the next message
+    version: AtomicU64,
+    /// Wakes the flush loop before its interval is up
+    wake: Notify,
+}
+
+impl Outboxes {
+    /// Reads the outboxes kept in `data_dir`. A missing or unreadable file
+    /// leaves them empty.
+    pub fn load(data_dir: &Path) -> Self {
+        let path = data_dir.join(OUTBOX_FILE);
+        let peers: BTreeMap<String, Outbox> = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|e| {
+                tracing::warn!(path = %path.display(), error = %e, "Ignoring unreadable outbox file");
+                BTreeMap::new()
+            }),
+            Err(_) => BTreeMap::new(),
+        };
+        let last = peers
+            .values()
+            .flat_map(|o| o.0.values().map(|m| m.version))
+            .max()
+            .unwrap_or(0);
+        Self {
+            path,
+            peers: Mutex::new(peers),
+            sending: Mutex::new(HashMap::new()),
+            version: AtomicU64::new(last + 1),
+            wake: Notify::new(),
+        }
+    }
+
+    /// Queues `line` for every port of `ports`, as one new message of `kind`
+    pub fn enqueue(&self, ports: &[String], kind: Broadcast, line: String) {
+        let version = self.version.fetch_add(1, Ordering::Relaxed);
+        let mut peers = self.peers.lock().expect("outboxes poisoned");
+        for port in ports {
+            let msg = Message {
+                version,
+                line: line.clone(),
+            };
+            peers.entry(port.clone()).or_default().enqueue(kind, msg);
+        }
+        self.save(&peers);
+    }
+
+    /// Ports with messages waiting
+    pub fn pending(&self) -> Vec<String> {
+        let peers = self.peers.lock().expect("outboxes poisoned");
+        peers.keys().cloned().collect()
+    }
+
+    /// Messages waiting, over every outbox
+    pub fn len(&self) -> usize {
+        let peers = self.peers.lock().expect("outboxes poisoned");
+        peers.values().map(Outbox::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Drops the outboxes of the ports `keep` refuses
+    pub fn retain(&self, keep: impl Fn(&str) -> bool) {
+        let mut peers = self.peers.lock().expect("outboxes poisoned");
+        let before = peers.len();
+        peers.retain(|port, _| keep(port));
+        if peers.len() != before {
+            self.save(&peers);
+        }
+    }
+
+    /// Has the flush loop run now, as a peer came back
+    pub fn wake(&self) {
+        self.wake.notify_one();
+    }
+
+    /// Waits for [`Outboxes::wake`]
+    pub async fn woken(&self) {
+        self.wake.notified().await
+    }
+
+    fn next(&self, port: &str) -> Option<(Broadcast, Message)> {
+        let peers = self.peers.lock().expect("outboxes poisoned");
+        peers.get(port).and_then(Outbox::next)
+    }
+
+    fn ack(&self, port: &str, kind: Broadcast, version: u64) {
+        let mut peers = self.peers.lock().expect("outboxes poisoned");
+        if let Some(outbox) = peers.get_mut(port) {
+            outbox.ack(kind, version);
+            if outbox.is_empty() {
+                peers.remove(port);
+            }
+        }
+        self.save(&peers);
+    }
+
+    fn sending_lock(&self, port: &str) -> Arc<tokio::sync::Mutex<()>> {
+        let mut sending = self.sending.lock().expect("outboxes poisoned");
+        Arc::clone(sending.entry(port.to_string()).or_default())
+    }
+
+    /// Writes the outboxes to their file, through a temporary one so a crash
+    /// leaves the old or the new version
+    fn save(&self, peers: &BTreeMap<String, Outbox>) {
+        let tmp = self.path.with_extension("json.tmp");
+        let res = serde_json::to_vec(peers)
+            .map_err(io::Error::other)
+            .and_then(|bytes| std::fs::write(&tmp, bytes))
+            .and_then(|()| std::fs::rename(&tmp, &self.path));
+        if let Err(e) = res {
+            tracing::warn!(path = %self.path.display(), error = %e, "Cannot save outboxes");
+        }
+    }
+}
+
+/// Sends the waiting messages of every `(port, route)` peer, `limit` peers at
+/// once. Returns the ports left with messages.
+pub async fn flush(
+    outboxes: &Arc<Outboxes>,
+    peers: Vec<(String, Route)>,
+    tcp: &TcpOptions,
+    token: Option<&ClusterToken>,
+    limit: usize,
+) -> Vec<String> {
+    let mut failed = Vec::new();
+    let mut sends = JoinSet::new();
+    for (port, route) in peers {
+        if sends.len() >= limit.max(1)
+            && let Some(Ok(Some(port))) = sends.join_next().await
+        {
+            failed.push(port);
+        }
+        let (outboxes, tcp, token) = (Arc::clone(outboxes), *tcp, token.cloned());
+        sends.spawn(async move {
+            let res = flush_peer(&outboxes, &port, &route, &tcp, token.as_ref()).await;
+            res.err().map(|e| {
+                tracing::debug!(route = ?route, error = %e, "Message not delivered");
+                port
+            })
+        });
+    }
+    while let Some(res) = sends.join_next().await {
+        if let Ok(Some(port)) = res {
+            failed.push(port);
+        }
+    }
+    failed.sort();
+    failed
+}
+
+/// Sends `port`'s messages along `route` until its outbox is empty or a send
+/// fails. A message the peer answers with anything but `OK` is dropped, as
+/// sending it again would not change that.
+async fn flush_peer(
+    outboxes: &Outboxes,
+    port: &str,
+    route: &Route,
+    tcp: &TcpOptions,
+    token: Option<&ClusterToken>,
+) -> io::Result<()> {
+    let lock = outboxes.sending_lock(port);
+    let _sending = lock.lock().await;
+    while let Some((kind, msg)) = outboxes.next(port) {
+        match send_with_retry(route, &msg.line, tcp, token).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                tracing::warn!(port = %port, kind = ?kind, error = %e, "Message refused, dropping it");
+            }
+            Err(e) => return Err(e),
+        }
+        outboxes.ack(port, kind, msg.version);
+    }
+    Ok(())
+}
+
+async fn send_with_retry(
+    route: &Route,
+    line: &str,
+    tcp: &TcpOptions,
+    token: Option<&ClusterToken>,
+) -> io::Result<()> {
+    let mut backoff = RETRY_BACKOFF;
+    let mut attempt = 1;
+    loop {
+        match send(route, line, tcp, token).await {
+            Ok(()) => return Ok(()),
+            Err(e) if attempt < ATTEMPTS && is_transient(&e) => {
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Writes `line` and waits for the peer's `OK`
+async fn send(
+    route: &Route,
+    line: &str,
+    tcp: &TcpOptions,
+    token: Option<&ClusterToken>,
+) -> io::Result<()> {
+    let exchange = async {
+        let s = route.connect(tcp, token).await?;
+        let (r, mut w) = s.into_split();
+        w.write_all(line.as_bytes()).await?;
+        let mut answer = String::new();
+        BufReader::new(r).read_line(&mut answer).await?;
+        match answer.trim() {
+            "OK" => Ok(()),
+            "" => Err(io::ErrorKind::UnexpectedEof.into()),
+            other => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                other.to_string(),
+            )),
+        }
+    };
+    tokio::time::timeout(SEND_TIMEOUT, exchange)
+        .await
+        .unwrap_or_else(|_| Err(io::ErrorKind::TimedOut.into()))
+}
+
+/// Failures a second try may get past. A refused connection means nobody
+/// listens at that port: it is left to the flush loop.
+fn is_transient(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::TimedOut
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::Interrupted
+            | io::ErrorKind::WouldBlock
+            | io::ErrorKind::UnexpectedEof
+    )
+}
+
+/// Retries the outboxes left by earlier broadcasts, every
+/// [`REDELIVERY_INTERVAL`] and whenever a peer comes back
+pub(crate) async fn spawn_redelivery_loop(node: Arc<Node>) {
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(REDELIVERY_INTERVAL) => {}
+            _ = node.outboxes.woken() => {}
+        }
+        node.flush_outboxes().await;
+    }
+}
@@ -1,22 +1,135 @@
-use crate::NodeStatus;
-use crate::node::port_str;
+use crate::addr::{host_str, join_host_port};
+use crate::auth::{self, ClusterToken};
+use crate::checksum::Digest;
+use crate::compression::{self, Compression, Compressor, Encoding};
+use crate::delta;
+use crate::node::{ChunkView, FileManifestView, port_str};
+use crate::openapi::{self, API_PREFIX};
+use crate::protocol::{self, PushMode, decode_name, encode_name};
+use crate::proxy::{self, ProxyOptions};
+use crate::pull;
+use crate::ring_verify::{self, RingReport};
+use crate::routing::RouteCache;
+use crate::schema::Labels;
+use crate::transfer::{ProgressReader, Transfer, TransferKind, TransferProgress, token_node};
+use crate::{NodeLoad, NodeStatus};
 use serde::Serialize;
 use serde_json;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::io;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::Duration;
 use tokio::io::{
-    AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, copy,
+    AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader,
+    copy,
 };
 use tokio::net::{TcpListener, TcpStream};
 use tokio::process::Command;
+use tokio::sync::RwLock;
 use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+type AnyErr = Box<dyn std::error::Error + Send + Sync>;
+
+/// A chunk being fetched ahead by a pull
+type ChunkFetch = JoinHandle<Result<Vec<u8>, AnyErr>>;
+
+/// How often the progress stream polls a transfer
+const SSE_POLL: Duration = Duration::from_millis(250);
+
+/// How long the progress stream waits for a transfer that has not started yet
+const SSE_START_WAIT: Duration = Duration::from_secs(10);
+
+/// How long a node may take to answer `NODE DU`
+const USAGE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a node may take to answer `NETMAP GET`
+const NETMAP_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long a proxied TCP connection may go without traffic, by default
+pub const DEFAULT_TUNNEL_IDLE: Duration = Duration::from_secs(300);
+
+/// Largest upload the gateway takes, by default
+pub const DEFAULT_MAX_UPLOAD: u64 = 1_000_000_000;
+
+/// Longest request head (request line and headers) the gateway reads, by default
+pub const DEFAULT_MAX_HEADER: usize = 64 * 1024;
+
+/// Bytes a proxied TCP connection moves at a time, per direction
+const PIPE_BUF: usize = 16 * 1024;
+
+/// Longest `FILE SYNC` block signature line forwarded, newline included
+const SIGNATURE_LINE_MAX: u64 = 128;
+
+/// Bytes of chunks a pull fetches ahead of the one it is sending
+const PULL_PREFETCH: u64 = 64 * 1024 * 1024;
 
 #[derive(Debug)]
 pub struct Gateway {
     /// Full addresses
     node_addrs: Vec<String>,
+
+    /// Uploads and downloads going through the gateway, by token
+    transfers: RwLock<HashMap<String, Arc<Transfer>>>,
+    transfer_counter: AtomicU64,
+
+    /// Routes whose responses are compressed for clients that accept it
+    compression: Compression,
+
+    /// How to find the client behind a load balancer
+    proxy: ProxyOptions,
+
+    /// How long a proxied TCP connection may stay open
+    tunnel: TunnelTimeouts,
+
+    /// Token presented to the nodes, instead of the process' client token
+    /// (see [`auth::connect_with`])
+    token: Option<ClusterToken>,
+
+    /// Manifests of recently pulled files
+    routes: RouteCache,
+
+    /// How much a client may send in one HTTP request
+    limits: RequestLimits,
+}
+
+/// How long a raw TCP connection proxied to a node may stay open
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TunnelTimeouts {
+    /// Closed once no byte went either way for this long; `None` never
+    pub idle: Option<Duration>,
+    /// Closed this long after it was opened, busy or not; `None` never
+    pub max: Option<Duration>,
+}
+
+impl Default for TunnelTimeouts {
+    fn default() -> Self {
+        Self {
+            idle: Some(DEFAULT_TUNNEL_IDLE),
+            max: None,
+        }
+    }
+}
+
+/// How much a client may send the gateway in one HTTP request, checked
+/// before any of the body is read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestLimits {
+    /// Largest `Content-Length` of an upload; `None` for no limit
+    pub max_upload: Option<u64>,
+    /// Longest request line and headers, in bytes
+    pub max_header: usize,
+}
+
+impl Default for RequestLimits {
+    fn default() -> Self {
+        Self {
+            max_upload: Some(DEFAULT_MAX_UPLOAD),
+            max_header: DEFAULT_MAX_HEADER,
+        }
+    }
 }
 
 /// HTTP Response Struct
@@ -27,9 +140,112 @@ struct FileInfo {
     size: u64,
 }
 
+/// Where the bytes of a pull come from
+enum PullSource {
+    /// A node answering `FILE PULL`, which gathers the chunks along the ring
+    Node(TcpStream),
+    /// The holders of the chunks the manifest lists, each asked directly
+    Holders(Arc<FileManifestView>),
+}
+
+/// The body of a pull on its way to the client, compressed with the
+/// negotiated encoding (if any) and counted in the transfer's progress
+struct PullBody<'a, W> {
+    writer: &'a mut W,
+    compressor: Option<Compressor>,
+    transfer: &'a Transfer,
+    /// Position in the file of the next byte to send
+    position: u64,
+}
+
+impl<W: AsyncWrite + Unpin> PullBody<'_, W> {
+    async fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        match &mut self.compressor {
+            Some(compressor) => {
+                let out = compressor.write(data)?;
+                self.writer.write_all(&out).await?;
+            }
+            None => self.writer.write_all(data).await?,
+        }
+        self.position += data.len() as u64;
+        self.transfer.advance(data.len() as u64);
+        Ok(())
+    }
+
+    async fn copy_from(&mut self, mut reader: impl AsyncRead + Unpin) -> io::Result<()> {
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            let n = reader.read(&mut buf).await?;
+            if n == 0 {
+                return Ok(());
+            }
+            self.write(&buf[..n]).await?;
+        }
+    }
+
+    /// Sends what the compressor still holds
+    async fn finish(self) -> io::Result<()> {
+        if let Some(compressor) = self.compressor {
+            self.writer.write_all(&compressor.finish()?).await?;
+        }
+        Ok(())
+    }
+}
+
+/// What the headers of a file pull announce
+struct FileHeaders<'a> {
+    filename: &'a str,
+    token: &'a str,
+    etag: Option<&'a str>,
+    content_type: &'a str,
+    /// Bytes of the body, when known before it is sent
+    length: Option<u64>,
+    encoding: Option<Encoding>,
+    /// First byte sent and file size, for a `206 Partial Content`
+    range: Option<(u64, u64)>,
+}
+
 impl Gateway {
     pub fn new(node_addrs: Vec<String>) -> Arc<Self> {
-        Arc::new(Self { node_addrs })
+        Self::with_compression(node_addrs, Compression::default())
+    }
+
+    /// A gateway compressing the responses of the routes in `compression`
+    pub fn with_compression(node_addrs: Vec<String>, compression: Compression) -> Arc<Self> {
+        Self::with_options(
+            node_addrs,
+            compression,
+            ProxyOptions::default(),
+            TunnelTimeouts::default(),
+            None,
+            RequestLimits::default(),
+        )
+    }
+
+    /// A gateway compressing the responses of the routes in `compression`,
+    /// behind the proxies described by `proxy`, cutting proxied TCP
+    /// connections after `tunnel`, presenting `token` to the nodes (or the
+    /// process' client token, see [`auth::client_token`]), refusing requests
+    /// over `limits`
+    pub fn with_options(
+        node_addrs: Vec<String>,
+        compression: Compression,
+        proxy: ProxyOptions,
+        tunnel: TunnelTimeouts,
+        token: Option<ClusterToken>,
+        limits: RequestLimits,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            node_addrs,
+            transfers: RwLock::new(HashMap::new()),
+            transfer_counter: AtomicU64::new(1),
+            compression,
+            proxy,
+            tunnel,
+            token,
+            routes: RouteCache::new(),
+            limits,
+        })
     }
 
     /// Runs the main TCP server to listen for clients
@@ -42,7 +258,10 @@ impl Gateway {
             let gateway_clone = Arc::clone(&self);
 
             tokio::spawn(async move {
-                if let Err(e) = gateway_clone.handle_connection(client_stream).await {
+                if let Err(e) = gateway_clone
+                    .handle_connection(client_stream, client_addr)
+                    .await
+                {
                     tracing::warn!(client = %client_addr, error = ?e, "Gateway client error");
                 }
             });
@@ -53,30 +272,57 @@ impl Gateway {
     async fn handle_connection(
         self: Arc<Self>,
         stream: TcpStream,
+        peer: SocketAddr,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         let (reader, mut writer) = stream.into_split();
         let mut buf_reader = BufReader::new(reader);
 
+        // 0. Behind a proxy speaking the PROXY protocol, the client is in its header
+        let mut client = peer.ip();
+        if self.proxy.proxy_protocol
+            && let Some(source) = proxy::read_header(&mut buf_reader).await?
+        {
+            client = source.ip();
+        }
+
         // 1. Read the first line to sniff the protocol.
+        let max_line = self.limits.max_header as u64;
         let mut first_line = String::new();
-        if let Err(e) = buf_reader.read_line(&mut first_line).await {
-            tracing::debug!(error = ?e, "Client disconnected before sending data");
+        match (&mut buf_reader)
+            .take(max_line)
+            .read_line(&mut first_line)
+            .await
+        {
+            Ok(0) => return Ok(()), // Connected and left, as a port probe does
+            Ok(_) => {}
+            Err(e) => {
+                tracing::debug!(error = ?e, "Client disconnected before sending data");
+                return Ok(());
+            }
+        }
+        let is_http = first_line.starts_with("GET /")
+            || first_line.starts_with("POST /")
+            || first_line.starts_with("OPTIONS /");
+        if !first_line.ends_with('\n') && first_line.len() as u64 == max_line {
+            tracing::info!(client = %client, bytes = max_line, "Refused over-long request line");
+            if is_http {
+                Self::send_error_response(&mut writer, 414, "URI Too Long").await?;
+            } else {
+                let refusal = format!("ERR request line over {} bytes\n", max_line);
+                writer.write_all(refusal.as_bytes()).await?;
+            }
             return Ok(());
         }
 
         // 2. Check if the protocol is HTTP raw TCP
-        if first_line.starts_with("GET /")
-            || first_line.starts_with("POST /")
-            || first_line.starts_with("OPTIONS /")
-        {
+        if is_http {
             // Handle HTTP request
-            tracing::debug!(line = %first_line.trim(), "Handling HTTP request");
-            self.handle_http_request(&mut buf_reader, &mut writer, &first_line)
+            self.handle_http_request(&mut buf_reader, &mut writer, &first_line, client)
                 .await?;
         } else {
             // Handle raw TCP
-            tracing::debug!(line = %first_line.trim(), "Handling TCP proxy");
-            self.handle_tcp_proxy(buf_reader, writer, &first_line)
+            tracing::debug!(client = %client, line = %first_line.trim(), "Handling TCP proxy");
+            self.handle_tcp_proxy(buf_reader, writer, &first_line, client)
                 .await?;
         }
         Ok(())
@@ -89,18 +335,57 @@ impl Gateway {
         reader: &mut BufReader<R>,
         writer: &mut (impl AsyncWrite + Unpin),
         first_line: &str,
+        peer: IpAddr,
     ) -> io::Result<()>
     where
         R: AsyncRead + Unpin,
     {
         let parts: Vec<&str> = first_line.split_whitespace().collect();
-        let method = parts.get(0).cloned().unwrap_or("GET");
-        let path = parts.get(1).cloned().unwrap_or("/");
+        let method = parts.first().cloned().unwrap_or("GET");
+        let target = parts.get(1).cloned().unwrap_or("/");
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        // `/api/v1/...` is the versioned API; the unprefixed paths are its legacy aliases
+        let path = path
+            .strip_prefix(API_PREFIX)
+            .filter(|rest| rest.starts_with('/'))
+            .unwrap_or(path);
+
+        let max_headers = self.limits.max_header.saturating_sub(first_line.len());
+        let Some(headers) = Self::read_headers(reader, max_headers).await? else {
+            tracing::info!(client = %peer, line = %first_line.trim(), "Refused over-long request headers");
+            return Self::send_error_response(writer, 431, "Request Header Fields Too Large").await;
+        };
+        let client = self.proxy.client(&headers, peer);
+        tracing::debug!(client = %client, line = %first_line.trim(), "Handling HTTP request");
+        let encoding = self
+            .compression
+            .select(path, headers.get("accept-encoding").map(String::as_str));
+
+        // Handle GET /file/progress/<token>
+        if method == "GET" && path.starts_with("/file/progress/") {
+            let token = path.strip_prefix("/file/progress/").unwrap_or("");
+            return if token.is_empty() {
+                Self::send_error_response(writer, 400, "Bad Request: Missing token").await
+            } else {
+                self.handle_progress_stream(writer, token).await
+            };
+        }
 
-        // Handle GET /file/pull/<filename>
+        // Handle GET /file/pull/<filename>[?token=<token>]
         if method == "GET" && path.starts_with("/file/pull/") {
             return if let Some(filename) = path.strip_prefix("/file/pull/") {
-                match self.handle_file_pull(writer, filename).await {
+                // URL escapes are the protocol's name escapes
+                let filename = &decode_name(filename);
+                let token = query
+                    .split('&')
+                    .find_map(|kv| kv.strip_prefix("token="))
+                    .map(str::to_string);
+                let if_none_match = headers.get("if-none-match").cloned();
+                let offset = headers.get("range").and_then(|r| range_start(r));
+                match self
+                    .handle_file_pull(writer, filename, token, if_none_match, encoding, offset)
+                    .await
+                {
                     Ok(_) => Ok(()), // Full response was sent
                     Err(e) => Self::send_error_response(writer, 500, &e.to_string()).await,
                 }
@@ -109,28 +394,93 @@ impl Gateway {
             };
         }
 
+        // Handle GET /file/manifest/<filename>
+        if method == "GET" && path.starts_with("/file/manifest/") {
+            let filename = decode_name(path.strip_prefix("/file/manifest/").unwrap_or(""));
+            return if filename.is_empty() {
+                Self::send_error_response(writer, 400, "Bad Request: Missing filename").await
+            } else {
+                match self.fetch_file_manifest(&filename).await {
+                    Ok(Some(manifest)) => {
+                        Self::send_json_response(writer, &manifest, encoding).await
+                    }
+                    Ok(None) => Self::send_error_response(writer, 404, "Not Found").await,
+                    Err(e) => Self::send_error_response(writer, 500, &e.to_string()).await,
+                }
+            };
+        }
+
         match (method, path) {
             ("OPTIONS", _) => {
                 // Handle CORS preflight requests
                 Self::send_options_response(writer).await
             }
+            ("GET", "/openapi.json") => {
+                Self::send_json_response(writer, openapi::spec(), encoding).await
+            }
             ("GET", "/netmap/get") => match self.fetch_node_map().await {
-                Ok(map) => Self::send_json_response(writer, &map).await,
+                Ok(map) => Self::send_json_response(writer, &map, encoding).await,
+                Err(e) => Self::send_error_response(writer, 500, &e.to_string()).await,
+            },
+            ("GET", "/topology/get") => match self.fetch_topology().await {
+                Ok(view) => Self::send_json_response(writer, &view, encoding).await,
                 Err(e) => Self::send_error_response(writer, 500, &e.to_string()).await,
             },
             ("GET", "/file/list") => match self.fetch_file_list().await {
-                Ok(list) => Self::send_json_response(writer, &list).await,
+                Ok(list) => Self::send_json_response(writer, &list, encoding).await,
                 Err(e) => Self::send_error_response(writer, 500, &e.to_string()).await,
             },
-            ("POST", "/file/push") => match self.handle_file_upload(reader).await {
-                Ok(_) => {
-                    Self::send_json_response(writer, serde_json::json!({"status": "ok"})).await
+            ("GET", "/cluster/stats") => match self.fetch_cluster_stats().await {
+                Ok(stats) => Self::send_json_response(writer, &stats, encoding).await,
+                Err(e) => Self::send_error_response(writer, 500, &e.to_string()).await,
+            },
+            ("GET", "/stats/usage") => {
+                let prefix = query
+                    .split('&')
+                    .find_map(|kv| kv.strip_prefix("prefix="))
+                    .map(decode_name)
+                    .unwrap_or_default();
+                match self.fetch_usage(&prefix).await {
+                    Ok(usage) => Self::send_json_response(writer, &usage, encoding).await,
+                    Err(e) => Self::send_error_response(writer, 500, &e.to_string()).await,
+                }
+            }
+            ("GET", "/ring/verify") => match self.verify_ring().await {
+                Ok(report) => Self::send_json_response(writer, &report, encoding).await,
+                Err(e) => Self::send_error_response(writer, 500, &e.to_string()).await,
+            },
+            ("POST", "/file/push") if self.over_upload_limit(&headers) => {
+                tracing::info!(client = %client, "Refused upload over the size limit");
+                Self::send_error_response(writer, 413, "Payload Too Large").await
+            }
+            ("POST", "/file/push") if let Some((status, reason)) = upload_refusal(&headers) => {
+                tracing::info!(client = %client, reason, "Refused upload");
+                Self::send_error_response(writer, status, reason).await
+            }
+            ("POST", "/file/push") => match self.handle_file_upload(reader, &headers, client).await
+            {
+                Ok(token) => {
+                    Self::send_json_response(
+                        writer,
+                        serde_json::json!({"status": "ok", "token": token}),
+                        None,
+                    )
+                    .await
+                }
+                // The node refused to commit a body without the announced digest
+                Err(e) if e.to_string().contains("CHECKSUM_MISMATCH") => {
+                    Self::send_error_response(writer, 422, &e.to_string()).await
                 }
                 Err(e) => Self::send_error_response(writer, 500, &e.to_string()).await,
             },
             ("POST", "/network/heal") => match self.trigger_node_heal().await {
                 Ok(msg) => {
-                    Self::send_json_response(writer, serde_json::json!({ "message": msg })).await
+                    Self::send_json_response(
+                        writer,
+                        serde_json::json!({ "message": msg }),
+                        encoding,
+                    )
+                    .await
                 }
                 Err(e) => Self::send_error_response(writer, 500, &e.to_string()).await,
             },
@@ -145,8 +495,12 @@ impl Gateway {
                 {
                     match self.trigger_node_kill(port_str).await {
                         Ok(msg) => {
-                            Self::send_json_response(writer, serde_json::json!({ "message": msg }))
-                                .await
+                            Self::send_json_response(
+                                writer,
+                                serde_json::json!({ "message": msg }),
+                                encoding,
+                            )
+                            .await
                         }
                         Err(e) => Self::send_error_response(writer, 500, &e.to_string()).await,
                     }
@@ -158,155 +512,680 @@ impl Gateway {
         }
     }
 
-    /// Handles the `POST /api/upload` request
+    /// Whether an upload announces more bytes than the gateway takes, in
+    /// which case none of its body is read
+    fn over_upload_limit(&self, headers: &HashMap<String, String>) -> bool {
+        let Some(max) = self.limits.max_upload else {
+            return false;
+        };
+        match headers.get("content-length").map(|v| (v, v.parse::<u64>())) {
+            Some((_, Ok(length))) => length > max,
+            // Too many digits for a u64 is over any limit
+            Some((v, Err(_))) => !v.is_empty() && v.bytes().all(|b| b.is_ascii_digit()),
+            None => false,
+        }
+    }
+
+    /// Handles the `POST /api/v1/file/push` request.
+    ///
+    /// The body is streamed straight into the ring and tracked under the
+    /// `X-Transfer-Token` header (or a generated token), which is returned.
     async fn handle_file_upload<R>(
         self: Arc<Self>,
         reader: &mut BufReader<R>,
-    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
+        headers: &HashMap<String, String>,
+        client: IpAddr,
+    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>>
     where
         R: AsyncRead + Unpin,
     {
-        // 1. Read headers to find Content-Length and X-Filename
-        let mut content_length: u64 = 0;
-        let mut filename: Option<String> = None;
-        let mut line = String::new();
+        // 1. Find Content-Length and X-Filename among the headers
+        let header = |name: &str| headers.get(name).map(String::as_str);
+        // Both checked by `upload_refusal` already
+        let size: u64 = header("content-length")
+            .ok_or("missing Content-Length")?
+            .parse()?;
+        // Sanitize filename
+        let filename = header("x-filename").ok_or("missing X-Filename")?.replace(
+            |c: char| !c.is_alphanumeric() && c != '.' && c != '_' && c != '-',
+            "_",
+        );
+        let mode: PushMode = header("x-push-mode")
+            .map(str::parse)
+            .transpose()?
+            .unwrap_or_default();
+        let place: Labels = header("x-push-place")
+            .map(str::parse)
+            .transpose()?
+            .unwrap_or_default();
+        let token = header("x-transfer-token").map(str::to_string);
+        let sha256: Option<Digest> = header("x-content-sha256").map(str::parse).transpose()?;
+        // Only SHA-256 can be checked by the nodes before they commit
+        if header("content-md5").is_some() {
+            return Err("Content-MD5 is not supported, send X-Content-SHA256".into());
+        }
 
-        loop {
-            line.clear();
-            if reader.read_line(&mut line).await? == 0 {
-                break; // Premature end
+        // The client's own Content-Type, unless it says nothing about the
+        // file, or else the one its first bytes give away (an empty body has
+        // none to wait for)
+        let content_type = match header("content-type").and_then(compression::upload_type) {
+            Some(t) => Some(t.to_string()),
+            None if size == 0 => None,
+            None => compression::sniff(reader.fill_buf().await?).map(str::to_string),
+        };
+
+        tracing::info!(client = %client, file = %filename, bytes = size, "Receiving file from HTTP POST");
+
+        // 2. Connect to the ring and register the transfer
+        let mut node_stream = self.connect_to_ring().await?;
+        let node_port = node_stream
+            .peer_addr()
+            .map(|a| a.port().to_string())
+            .unwrap_or_default();
+        let transfer = self
+            .begin_transfer(
+                token,
+                TransferKind::Push,
+                &filename,
+                size,
+                vec![(size, node_port)],
+            )
+            .await?;
+        let token = transfer.token.clone();
+
+        let res = async {
+            // 3. Send the FILE PUSH command
+            let mut header = match mode {
+                PushMode::Overwrite => format!("FILE PUSH {} {}", size, encode_name(&filename)),
+                mode => format!(
+                    "FILE PUSH {} {} MODE {}",
+                    size,
+                    encode_name(&filename),
+                    mode
+                ),
+            };
+            if !place.is_empty() {
+                header.push_str(&format!(" PLACE {}", place));
             }
-            let trimmed = line.trim();
-            if trimmed.is_empty() {
-                break; // End of headers
+            if let Some(sha256) = sha256 {
+                header.push_str(&format!(" SHA256 {}", sha256));
+            }
+            if let Some(content_type) = &content_type {
+                header.push_str(&format!(" TYPE {}", encode_name(content_type)));
             }
+            header.push('\n');
+            node_stream.write_all(header.as_bytes()).await?;
 
-            if let Some((key, value)) = trimmed.split_once(':') {
-                let key_lower = key.to_ascii_lowercase();
-                let value_trimmed = value.trim();
+            // 4. Stream the body to the node
+            let mut body = ProgressReader::new((&mut *reader).take(size), Arc::clone(&transfer));
+            copy(&mut body, &mut node_stream).await?;
+
+            // 5. Wait for the "OK" from the node to confirm success
+            let mut node_reader = BufReader::new(node_stream);
+            let mut node_response = String::new();
 
-                if key_lower == "content-length" {
-                    content_length = value_trimmed.parse().unwrap_or(0);
+            // Read lines until we get an "OK" or the stream ends
+            while node_reader.read_line(&mut node_response).await? > 0 {
+                if node_response.starts_with("OK") {
+                    return Ok(());
                 }
-                if key_lower == "x-filename" {
-                    // Sanitize filename
-                    let safe_name = value_trimmed.replace(
-                        |c: char| !c.is_alphanumeric() && c != '.' && c != '_' && c != '-',
-                        "_",
-                    );
-                    filename = Some(safe_name);
+                if let Some(err) = node_response.strip_prefix("ERR ") {
+                    return Err(err.trim().into());
                 }
+                node_response.clear(); // Clear for next line
             }
+            Err::<(), AnyErr>("Node failed to store file: did not receive OK".into())
         }
+        .await;
+        self.transfers.write().await.remove(&token);
+        // Even a failed push may have replaced part of the file
+        self.routes.invalidate(&filename);
+        res?;
+
+        tracing::info!(client = %client, file = %filename, token = %token, "File successfully pushed to ring");
+        Ok(token)
+    }
 
-        if content_length == 0 || filename.is_none() {
-            return Err("Missing Content-Length or X-Filename header".into());
+    /// Connects to the ring and streams a file back to an HTTP client.
+    ///
+    /// The download is tracked under `token` (or a generated token), which is
+    /// sent back in the `X-Transfer-Token` header. The file's content hash
+    /// (from its manifest) is sent as the `ETag`, and a client already holding
+    /// it (`If-None-Match`) gets `304 Not Modified` instead of the bytes.
+    /// Files of a compressible content type are sent with `encoding`, if given.
+    /// A download resumed at `offset` (`Range: bytes=<n>-`) is sent as
+    /// `206 Partial Content`, uncompressed, so the bytes line up with the file.
+    async fn handle_file_pull(
+        self: Arc<Self>,
+        writer: &mut (impl AsyncWrite + Unpin),
+        filename: &str,
+        token: Option<String>,
+        if_none_match: Option<String>,
+        encoding: Option<Encoding>,
+        offset: Option<u64>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        // 1. Look up the size, the content type and the ETag
+        let manifest = self.cached_file_manifest(filename).await;
+        let content_type = manifest
+            .as_ref()
+            .and_then(|manifest| manifest.content_type.clone())
+            .unwrap_or_else(|| compression::content_type(filename).to_string());
+        let encoding =
+            encoding.filter(|_| offset.is_none() && compression::compressible(&content_type));
+        let etag = manifest
+            .as_ref()
+            .and_then(|manifest| manifest.content_hash())
+            .map(|hash| match encoding {
+                // Each encoding is a representation of its own
+                Some(encoding) => format!("\"{}-{}\"", hash, encoding),
+                None => format!("\"{}\"", hash),
+            });
+        if let (Some(etag), Some(if_none_match)) = (&etag, &if_none_match)
+            && etag_matches(if_none_match, etag)
+        {
+            Self::send_not_modified_response(writer, etag).await?;
+            return Ok(());
         }
+        let known_size = match &manifest {
+            Some(manifest) => Some(manifest.size),
+            None => self
+                .fetch_file_list()
+                .await?
+                .into_iter()
+                .find(|f| f.name == filename)
+                .map(|f| f.size),
+        };
+        let size = known_size.unwrap_or(0);
+        let range = offset.map(|offset| (offset, size));
+        if offset.is_some_and(|offset| offset > 0 && offset >= size) {
+            Self::send_error_response(writer, 416, "Range Not Satisfiable").await?;
+            return Ok(());
+        }
+        // Compressed bodies are as long as the compressor makes them
+        let length = known_size
+            .filter(|_| encoding.is_none())
+            .map(|size| size - offset.unwrap_or(0));
+        // 2. Fetch the chunks from their holders, or pull through the least loaded node
+        let source = match manifest.filter(|manifest| {
+            manifest.parts > 1 && manifest.chunks.iter().all(|c| c.node.is_some())
+        }) {
+            Some(manifest) => PullSource::Holders(manifest),
+            None => PullSource::Node(self.open_node_pull(filename, offset.unwrap_or(0)).await?),
+        };
+        let route = match &source {
+            PullSource::Holders(manifest) => manifest
+                .chunks
+                .iter()
+                .map(|c| (c.offset + c.size, c.node.clone().unwrap_or_default()))
+                .collect(),
+            PullSource::Node(stream) => {
+                let port = stream
+                    .peer_addr()
+                    .map(|a| a.port().to_string())
+                    .unwrap_or_default();
+                vec![(size, port)]
+            }
+        };
+        let transfer = self
+            .begin_transfer(token, TransferKind::Pull, filename, size, route)
+            .await?;
+        let token = transfer.token.clone();
+        // The bytes the client already has count as moved
+        transfer.advance(offset.unwrap_or(0));
+
+        let res = async {
+            // 3. Send the HTTP 200 OK and file headers to the browser
+            let headers = FileHeaders {
+                filename,
+                token: &token,
+                etag: etag.as_deref(),
+                content_type: &content_type,
+                length,
+                encoding,
+                range,
+            };
+            Self::send_file_response_headers(writer, &headers).await?;
+
+            // 4. Stream the file data to the browser
+            let mut body = PullBody {
+                writer: &mut *writer,
+                compressor: encoding.map(Compressor::new).transpose()?,
+                transfer: &transfer,
+                position: offset.unwrap_or(0),
+            };
+            match source {
+                PullSource::Node(stream) => body.copy_from(stream).await?,
+                PullSource::Holders(manifest) => self.stream_chunks(&mut body, &manifest).await?,
+            }
+            body.finish().await?;
+            Ok::<(), AnyErr>(())
+        }
+        .await;
+        self.transfers.write().await.remove(&token);
+        if res.is_err() {
+            self.routes.invalidate(filename);
+        }
+        res
+    }
 
-        let filename = filename.unwrap();
-        let size = content_length;
+    /// Connects to the least loaded node and sends it `FILE PULL`, from byte
+    /// `offset` on; the file follows on the returned stream
+    async fn open_node_pull(&self, filename: &str, offset: u64) -> Result<TcpStream, AnyErr> {
+        let mut stream = self.connect_least_loaded().await?;
+        let header = match offset {
+            0 => format!("FILE PULL {}\n", encode_name(filename)),
+            offset => format!("FILE PULL {} OFFSET {}\n", encode_name(filename), offset),
+        };
+        stream.write_all(header.as_bytes()).await?;
+        stream.shutdown().await?;
+        Ok(stream)
+    }
 
-        tracing::info!(file = %filename, bytes = size, "Receiving file from HTTP POST");
+    /// Streams the chunks of `manifest` to `body` from its position on, each
+    /// straight from its holder. Chunks are fetched ahead, all at once, as
+    /// long as they fit in [`PULL_PREFETCH`] bytes together, and checked
+    /// against their hash; a chunk bigger than that is streamed when its turn
+    /// comes. From the first chunk that fails, the rest of the file comes
+    /// through a node (`FILE PULL ... OFFSET`), which falls back on backup
+    /// holders.
+    async fn stream_chunks<W: AsyncWrite + Unpin>(
+        &self,
+        body: &mut PullBody<'_, W>,
+        manifest: &FileManifestView,
+    ) -> Result<(), AnyErr> {
+        let chunks: Vec<&ChunkView> = manifest
+            .chunks
+            .iter()
+            .filter(|c| c.offset + c.size > body.position)
+            .collect();
+        // One entry per chunk from the current one on: its fetch, or `None`
+        // for a chunk to stream
+        let mut ahead: VecDeque<Option<ChunkFetch>> = VecDeque::new();
+        let (mut next, mut buffered) = (0, 0);
+
+        let mut failure = None;
+        for (i, chunk) in chunks.iter().enumerate() {
+            while let Some(upcoming) = chunks.get(next) {
+                if upcoming.size > PULL_PREFETCH && next == i {
+                    ahead.push_back(None);
+                } else if buffered + upcoming.size <= PULL_PREFETCH {
+                    buffered += upcoming.size;
+                    ahead.push_back(Some(self.spawn_chunk_fetch(upcoming)));
+                } else {
+                    break;
+                }
+                next += 1;
+            }
 
-        // 2. Read the raw body
-        let mut file_body = vec![0; size as usize];
-        reader.read_exact(&mut file_body).await?;
+            let sent = match ahead.pop_front().flatten() {
+                Some(fetch) => {
+                    buffered -= chunk.size;
+                    match fetch.await.map_err(AnyErr::from).and_then(|res| res) {
+                        Ok(bytes) => {
+                            let skip = (body.position - chunk.offset) as usize;
+                            body.write(&bytes[skip..]).await.map_err(AnyErr::from)
+                        }
+                        Err(e) => Err(e),
+                    }
+                }
+                None => self.stream_chunk(body, chunk).await,
+            };
+            if let Err(e) = sent {
+                failure = Some((chunk, e));
+                break;
+            }
+        }
+        let Some((chunk, e)) = failure else {
+            return Ok(());
+        };
 
-        // 3. Connect to the ring
-        let mut node_stream = self.connect_to_ring().await?;
+        for fetch in ahead.into_iter().flatten() {
+            fetch.abort();
+        }
+        tracing::warn!(file = %manifest.name, chunk = %chunk.name, holder = ?chunk.node, resume_at = body.position, error = %e, "Parallel pull: Chunk failed, pulling the rest through a node");
+        let stream = self.open_node_pull(&manifest.name, body.position).await?;
+        body.copy_from(stream).await?;
+        Ok(())
+    }
 
-        // 4. Send the FILE PUSH command
-        let header = format!("FILE PUSH {} {}\n", size, filename);
-        node_stream.write_all(header.as_bytes()).await?;
+    /// Fetches and checks a chunk from its holder in the background
+    fn spawn_chunk_fetch(&self, chunk: &ChunkView) -> ChunkFetch {
+        let addr = self.node_addr(chunk.node.as_deref().unwrap_or_default());
+        let (chunk, token) = (chunk.clone(), self.token.clone());
+        tokio::spawn(async move { pull::fetch_checked(&addr, &chunk, token.as_ref()).await })
+    }
 
-        // 5. Stream the file body to the node
-        node_stream.write_all(&file_body).await?;
+    /// Streams a chunk from its holder to `body` as it arrives, from the
+    /// body's position on. Its hash is not checked: its first bytes are
+    /// gone by the time the last arrive.
+    async fn stream_chunk<W: AsyncWrite + Unpin>(
+        &self,
+        body: &mut PullBody<'_, W>,
+        chunk: &ChunkView,
+    ) -> Result<(), AnyErr> {
+        let addr = self.node_addr(chunk.node.as_deref().unwrap_or_default());
+        let (mut reader, len) =
+            pull::open_chunk(&addr, "GET-CHUNK", chunk, self.token.as_ref()).await?;
+        let skip = body.position - chunk.offset;
+        copy(&mut (&mut reader).take(skip), &mut tokio::io::sink()).await?;
+        body.copy_from(reader.take(len.saturating_sub(skip)))
+            .await?;
+        let end = chunk.offset + chunk.size;
+        if body.position < end {
+            return Err(format!("{} of {} bytes", body.position - chunk.offset, chunk.size).into());
+        }
+        Ok(())
+    }
 
-        // 6. Wait for the "OK" from the node to confirm success
-        let mut node_reader = BufReader::new(node_stream);
-        let mut node_response = String::new();
-        let mut found_ok = false;
+    /// Address of the node listening on `port`: one the gateway was given,
+    /// or else the port on the first one's host
+    fn node_addr(&self, port: &str) -> String {
+        self.node_addrs
+            .iter()
+            .find(|addr| port_str(addr) == port)
+            .cloned()
+            .unwrap_or_else(|| {
+                let host = self.node_addrs.first().map_or("127.0.0.1", |a| host_str(a));
+                join_host_port(host, port)
+            })
+    }
 
-        // Read lines until we get an "OK" or the stream ends
-        while node_reader.read_line(&mut node_response).await? > 0 {
-            if node_response.starts_with("OK") {
-                found_ok = true;
-                break;
-            }
-            node_response.clear(); // Clear for next line
+    /// `addr` if it is a node of the ring: one the gateway was given, or a
+    /// port of the ring's netmap on their host. `None` for anything else.
+    async fn member_addr(&self, addr: &str) -> Option<String> {
+        if self.node_addrs.iter().any(|a| a == addr) {
+            return Some(addr.to_string());
+        }
+        let port = port_str(addr);
+        // Only ever the gateway's own host for a port it was not given
+        if self.node_addr(port) != addr {
+            return None;
         }
+        self.netmap_ports()
+            .await
+            .contains(port)
+            .then(|| addr.to_string())
+    }
 
-        if !found_ok {
-            return Err("Node failed to store file: did not receive OK"
-                .to_string()
-                .into());
+    /// Ports of the ring's netmap, as the first node that answers reports it
+    async fn netmap_ports(&self) -> HashSet<String> {
+        for addr in &self.node_addrs {
+            let Ok(lines) = ring_verify::request_with(
+                addr,
+                "NETMAP GET JSON",
+                NETMAP_TIMEOUT,
+                self.token.as_ref(),
+            )
+            .await
+            else {
+                continue;
+            };
+            let Some(Ok(view)) = lines
+                .first()
+                .map(|l| serde_json::from_str::<serde_json::Value>(l))
+            else {
+                continue;
+            };
+            let nodes = view["nodes"].as_array().into_iter().flatten();
+            return nodes
+                .map(|n| &n["port"])
+                .chain([&view["node"]])
+                .filter_map(|port| port.as_str().map(str::to_string))
+                .collect();
         }
+        HashSet::new()
+    }
 
-        tracing::info!(file = %filename, "File successfully pushed to ring");
-        Ok(())
+    /// The manifest of `name` from the routing cache, or else from the ring
+    /// (then cached). `None` if the file is not stored or the ring could not
+    /// say.
+    async fn cached_file_manifest(&self, name: &str) -> Option<Arc<FileManifestView>> {
+        if let Some(manifest) = self.routes.get(name) {
+            tracing::debug!(file = %name, "Route cache: Hit");
+            return Some(manifest);
+        }
+        match self.fetch_file_manifest(name).await {
+            Ok(manifest) => manifest.map(|manifest| self.routes.insert(manifest)),
+            Err(e) => {
+                tracing::debug!(file = %name, error = %e, "No manifest for pull, sending no ETag");
+                None
+            }
+        }
     }
 
-    /// Connects to the ring and streams a file back to an HTTP client.
-    async fn handle_file_pull(
-        self: Arc<Self>,
-        writer: &mut (impl AsyncWrite + Unpin),
-        filename: &str,
-    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        // 1. Connect to a node in the ring
-        let mut node_stream = self.connect_to_ring().await?;
-        let (mut node_read, mut node_write) = node_stream.split();
+    /// Registers an upload or download under the client's token, or a new one
+    async fn begin_transfer(
+        &self,
+        token: Option<String>,
+        kind: TransferKind,
+        name: &str,
+        size: u64,
+        route: Vec<(u64, String)>,
+    ) -> Result<Arc<Transfer>, AnyErr> {
+        let token = match token {
+            Some(t)
+                if t.is_empty()
+                    || t.len() > 64
+                    || !t
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') =>
+            {
+                return Err(format!("invalid transfer token '{}'", t).into());
+            }
+            Some(t) => t,
+            None => format!(
+                "gw-{}",
+                self.transfer_counter.fetch_add(1, Ordering::Relaxed)
+            ),
+        };
+
+        let mut transfers = self.transfers.write().await;
+        if transfers.contains_key(&token) {
+            return Err(format!("transfer token '{}' already in use", token).into());
+        }
+        let transfer = Arc::new(Transfer::new(
+            token.clone(),
+            kind,
+            name.to_string(),
+            size,
+            route,
+        ));
+        transfers.insert(token, Arc::clone(&transfer));
+        Ok(transfer)
+    }
 
-        // 2. Send TCP FILE PULL to the node
-        let header = format!("FILE PULL {}\n", filename);
-        node_write.write_all(header.as_bytes()).await?;
-        node_write.shutdown().await?;
+    /// Handles `GET /file/progress/<token>`: a server-sent-events stream with a
+    /// `progress` event whenever the transfer advances, then a `done` event.
+    ///
+    /// Gateway uploads/downloads are read from the gateway's own table; node
+    /// tokens (`file-<addr>-<n>`) are polled from the node tracking them.
+    async fn handle_progress_stream(
+        &self,
+        writer: &mut (impl AsyncWrite + Unpin),
+        token: &str,
+    ) -> io::Result<()> {
+        let headers = "HTTP/1.1 200 OK\r\n\
+                       Content-Type: text/event-stream\r\n\
+                       Cache-Control: no-cache\r\n\
+                       Access-Control-Allow-Origin: *\r\n\
+                       Connection: close\r\n\
+                       \r\n";
+        writer.write_all(headers.as_bytes()).await?;
+
+        let mut last: Option<TransferProgress> = None;
+        let mut waited = Duration::ZERO;
+        loop {
+            let local = self.transfers.read().await.get(token).map(|t| t.progress());
+            let current = match local {
+                Some(progress) => Some(progress),
+                None => self.fetch_node_progress(token).await,
+            };
 
-        // 3. Send the HTTP 200 OK and file headers to the browser
-        Self::send_file_response_headers(writer, filename).await?;
+            match current {
+                Some(progress) => {
+                    if last.as_ref() != Some(&progress) {
+                        Self::send_event(writer, "progress", &progress).await?;
+                    }
+                    last = Some(progress);
+                }
+                None if last.is_some() => {
+                    // The transfer is over; report where it stopped
+                    return Self::send_event(writer, "done", &last).await;
+                }
+                None if waited >= SSE_START_WAIT => {
+                    let error = serde_json::json!({ "token": token, "error": "unknown transfer" });
+                    return Self::send_event(writer, "error", &error).await;
+                }
+                None => {}
+            }
 
-        // 4. Stream the raw file data from the node directly to the browser
-        copy(&mut node_read, writer).await?;
+            tokio::time::sleep(SSE_POLL).await;
+            waited += SSE_POLL;
+        }
+    }
 
-        Ok(())
+    /// Asks the node named in a `file-<addr>-<n>` token for that transfer's
+    /// progress. A token naming no node of the ring is unknown: the gateway
+    /// never dials an address a client made up (see [`Self::connect_node`]).
+    async fn fetch_node_progress(&self, token: &str) -> Option<TransferProgress> {
+        let addr = token_node(token)?;
+        let timeout = Duration::from_millis(500);
+        let check = async {
+            let mut stream = tokio::time::timeout(timeout, self.connect_node(addr)).await??;
+            stream
+                .write_all(format!("FILE PROGRESS {}\n", token).as_bytes())
+                .await?;
+            let mut reader = BufReader::new(stream);
+            let mut line = String::new();
+            tokio::time::timeout(timeout, reader.read_line(&mut line)).await??;
+            Ok::<_, AnyErr>(line.parse::<TransferProgress>().ok())
+        };
+        check.await.ok().flatten()
     }
 
     // --- TCP PROXY HANDLER ---
 
-    /// This is the proxy for all TCP commands
+    /// Drops the cached manifests a proxied command may outdate
+    fn forget_routes(&self, cmd: &protocol::Command) {
+        match cmd {
+            protocol::Command::FilePush { name, .. }
+            | protocol::Command::FileSync { name, .. }
+            | protocol::Command::FileCopy { dst: name, .. } => self.routes.invalidate(name),
+            protocol::Command::FileMigrate => self.routes.clear(),
+            _ => {}
+        }
+    }
+
+    /// This is the proxy for client TCP commands, one per connection: only
+    /// the command's own body is forwarded after its line, so no other
+    /// command can follow it in. Each direction is closed on its own once its
+    /// sender is done, so a client that half-closes after its command still
+    /// gets the whole answer; the connection as a whole is cut by the
+    /// gateway's [`TunnelTimeouts`].
     async fn handle_tcp_proxy<R>(
         self: Arc<Self>,
         mut client_reader: BufReader<R>,
         mut client_writer: impl AsyncWrite + Unpin,
         first_line: &str,
+        client: IpAddr,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
     where
         R: AsyncRead + Unpin,
     {
-        // 1. Connect to node
-        let mut node_stream = self.connect_to_ring().await?;
-        tracing::debug!(addr = ?node_stream.peer_addr(), "Gateway connected to ring node");
+        let command: Vec<&str> = first_line.split_whitespace().take(2).collect();
+        let command = command.join(" ");
+
+        // 0. Internal commands would let clients rewire the ring
+        let cmd = match protocol::parse_line(first_line) {
+            Ok(cmd) if cmd.is_client() => cmd,
+            Ok(_) => {
+                tracing::info!(client = %client, command = %command, "Refused TCP command");
+                let refusal = format!("ERR FORBIDDEN {} is not a client command\n", command);
+                client_writer.write_all(refusal.as_bytes()).await?;
+                return Ok(());
+            }
+            Err(e) => {
+                client_writer
+                    .write_all(format!("ERR {}\n", e).as_bytes())
+                    .await?;
+                return Ok(());
+            }
+        };
+        let body = Body::of(&cmd);
+
+        // 1. Connect to node. The client gets the role of a connection
+        // without a token, not the gateway's.
+        let mut node_stream = self.connect_to_ring_as(false).await?;
+        let node = node_stream
+            .peer_addr()
+            .map_or_else(|_| "?".to_string(), |addr| addr.to_string());
+        tracing::debug!(addr = %node, "Gateway connected to ring node");
 
         // 2. Send the first line
         node_stream.write_all(first_line.as_bytes()).await?;
+        let (node_read, mut node_write) = node_stream.split();
+        let mut node_read = BufReader::new(node_read);
+        let started = Instant::now();
+        let activity = AtomicU64::new(0);
+        let sent = AtomicU64::new(first_line.len() as u64);
+        let received = AtomicU64::new(0);
+
+        // 3. Find out how long the body is
+        let len = match body {
+            Body::Empty => 0,
+            Body::Bytes(len) => len,
+            Body::Sync { size, block_size } => {
+                let exchange = sync_exchange(
+                    &mut client_reader,
+                    &mut client_writer,
+                    &mut node_read,
+                    &mut node_write,
+                    size,
+                    block_size,
+                    (&sent, &received),
+                );
+                match self.tunnel.idle {
+                    Some(idle) => tokio::time::timeout(idle, exchange).await??,
+                    None => exchange.await?,
+                }
+            }
+        };
 
-        // 3. Proxy all remaining data in both directions
-        let (mut node_read, mut node_write) = node_stream.split();
-
+        // 4. Proxy the body and the answer
         // `client_reader` is the BufReader, which will empty its
         // internal buffer first before reading from the underlying stream.
-        let client_to_server = copy(&mut client_reader, &mut node_write);
-        let server_to_client = copy(&mut node_read, &mut client_writer);
-
-        // Use `try_join!` to wait for both halves to complete.
-        match tokio::try_join!(client_to_server, server_to_client) {
-            Ok(_) => {
-                tracing::debug!("TCP proxy finished successfully.");
-            }
-            Err(e) => {
-                tracing::debug!(error = ?e, "TCP proxy finished with error");
-            }
-        }
+        let mut body = (&mut client_reader).take(len);
+        let client_to_server = pipe(&mut body, &mut node_write, &sent, &activity, started);
+        let server_to_client = pipe(
+            &mut node_read,
+            &mut client_writer,
+            &received,
+            &activity,
+            started,
+        );
 
+        let outcome = tokio::select! {
+            res = async { tokio::try_join!(client_to_server, server_to_client) } => match res {
+                Ok(_) => "closed",
+                Err(e) => {
+                    tracing::debug!(error = ?e, "TCP proxy finished with error");
+                    "error"
+                }
+            },
+            _ = idle_expired(self.tunnel.idle, &activity, started) => "idle-timeout",
+            _ = expired(self.tunnel.max, started) => "max-duration",
+        };
+        self.forget_routes(&cmd);
+        tracing::info!(
+            client = %client,
+            node = %node,
+            command = %command,
+            sent = sent.load(Ordering::Relaxed),
+            received = received.load(Ordering::Relaxed),
+            duration_ms = started.elapsed().as_millis() as u64,
+            outcome,
+            "TCP proxy closed"
+        );
         Ok(())
     }
 
@@ -315,7 +1194,7 @@ impl Gateway {
     /// Sends a "NODE PING" to a single address and returns its status.
     ///
     /// This is a lightweight, best-effort check with a short timeout.
-    async fn ping_node(addr: String) -> (String, NodeStatus) {
+    async fn ping_node(addr: String, token: Option<ClusterToken>) -> (String, NodeStatus) {
         let port = port_str(&addr).to_string();
         let timeout = Duration::from_millis(500);
 
@@ -323,7 +1202,8 @@ impl Gateway {
 
         let check = async {
             // Connect with timeout
-            let mut stream = tokio::time::timeout(timeout, TcpStream::connect(&addr)).await??;
+            let mut stream =
+                tokio::time::timeout(timeout, auth::connect_with(&addr, token.as_ref())).await??;
 
             // Send the PING command
             stream.write_all(b"NODE PING\n").await?;
@@ -348,6 +1228,24 @@ impl Gateway {
         }
     }
 
+    /// Asks a node for its load with `NODE LOAD`. `None` if it does not answer.
+    async fn load_node(addr: String, token: Option<ClusterToken>) -> (String, Option<NodeLoad>) {
+        let timeout = Duration::from_millis(500);
+
+        let check = async {
+            let mut stream =
+                tokio::time::timeout(timeout, auth::connect_with(&addr, token.as_ref())).await??;
+            stream.write_all(b"NODE LOAD\n").await?;
+            let mut reader = BufReader::new(stream);
+            let mut buf = String::new();
+            tokio::time::timeout(timeout, reader.read_line(&mut buf)).await??;
+            Ok::<_, AnyErr>(buf.parse::<NodeLoad>().ok())
+        };
+
+        let load = check.await.ok().flatten();
+        (addr, load)
+    }
+
     /// Checks the real-time status of all nodes by pinging them concurrently.
     async fn fetch_node_map(
         &self,
@@ -356,7 +1254,7 @@ impl Gateway {
 
         // 1. Spawn a concurrent ping task for every node address we know
         for addr in self.node_addrs.clone() {
-            tasks.push(tokio::spawn(Self::ping_node(addr)));
+            tasks.push(tokio::spawn(Self::ping_node(addr, self.token.clone())));
         }
 
         let mut map = HashMap::new();
@@ -376,6 +1274,85 @@ impl Gateway {
         Ok(map)
     }
 
+    /// Connects to the ring and sends `TOPOLOGY GET JSON`.
+    async fn fetch_topology(&self) -> Result<serde_json::Value, AnyErr> {
+        let mut stream = self.connect_to_ring().await?;
+        stream.write_all(b"TOPOLOGY GET JSON\n").await?;
+
+        let mut reader = BufReader::new(&mut stream);
+        let mut line = String::new();
+        reader.read_line(&mut line).await?;
+        if let Some(err) = line.strip_prefix("ERR") {
+            return Err(err.trim().to_string().into());
+        }
+        Ok(serde_json::from_str(&line)?)
+    }
+
+    /// Connects to the ring and sends `FILE MANIFEST <name>`. `None` if the
+    /// file is not stored.
+    async fn fetch_file_manifest(&self, name: &str) -> Result<Option<FileManifestView>, AnyErr> {
+        let mut stream = self.connect_to_ring().await?;
+        stream
+            .write_all(format!("FILE MANIFEST {}\n", encode_name(name)).as_bytes())
+            .await?;
+
+        let mut reader = BufReader::new(&mut stream);
+        let mut line = String::new();
+        reader.read_line(&mut line).await?;
+        match line.strip_prefix("ERR") {
+            Some(err) if err.trim() == "file not found" => Ok(None),
+            Some(err) => Err(err.trim().to_string().into()),
+            None => Ok(Some(serde_json::from_str(&line)?)),
+        }
+    }
+
+    /// Connects to the ring and sends `CLUSTER STATS`
+    async fn fetch_cluster_stats(&self) -> Result<serde_json::Value, AnyErr> {
+        let mut stream = self.connect_to_ring().await?;
+        stream.write_all(b"CLUSTER STATS\n").await?;
+
+        let mut reader = BufReader::new(&mut stream);
+        let mut line = String::new();
+        reader.read_line(&mut line).await?;
+        if let Some(err) = line.strip_prefix("ERR") {
+            return Err(err.trim().to_string().into());
+        }
+        Ok(serde_json::from_str(&line)?)
+    }
+
+    /// `FILE DU JSON <prefix>` from the ring, with the `NODE DU JSON` of every
+    /// node that answers
+    async fn fetch_usage(&self, prefix: &str) -> Result<serde_json::Value, AnyErr> {
+        let mut stream = self.connect_to_ring().await?;
+        stream
+            .write_all(format!("FILE DU JSON {}\n", encode_name(prefix)).as_bytes())
+            .await?;
+        let mut reader = BufReader::new(&mut stream);
+        let mut line = String::new();
+        reader.read_line(&mut line).await?;
+        if let Some(err) = line.strip_prefix("ERR") {
+            return Err(err.trim().to_string().into());
+        }
+        let files: serde_json::Value = serde_json::from_str(&line)?;
+
+        let mut nodes = Vec::new();
+        for addr in &self.node_addrs {
+            let Ok(lines) =
+                ring_verify::request_with(addr, "NODE DU JSON", USAGE_TIMEOUT, self.token.as_ref())
+                    .await
+            else {
+                continue;
+            };
+            if let Some(Ok(usage)) = lines
+                .first()
+                .map(|l| serde_json::from_str::<serde_json::Value>(l))
+            {
+                nodes.push(usage);
+            }
+        }
+        Ok(serde_json::json!({ "files": files, "nodes": nodes }))
+    }
+
     /// Connects to the ring and sends `FILE LIST`.
     async fn fetch_file_list(
         &self,
@@ -395,15 +1372,19 @@ impl Gateway {
             if line.trim().is_empty() {
                 break;
             }
-            let parts: Vec<&str> = line.trim().splitn(3, ',').collect();
+            // The name comes first and may itself hold commas
+            let parts: Vec<&str> = line.trim().rsplitn(3, ',').collect();
             if parts.len() == 3 {
                 // Handle CSV escaping
-                let name = parts[0].trim_matches('\"');
+                let name = parts[2]
+                    .strip_prefix('"')
+                    .and_then(|n| n.strip_suffix('"'))
+                    .map_or_else(|| parts[2].to_string(), |n| n.replace("\"\"", "\""));
 
                 files.push(FileInfo {
-                    name: name.to_string(),
+                    name,
                     start: parts[1].parse().unwrap_or(0),
-                    size: parts[2].parse().unwrap_or(0),
+                    size: parts[0].parse().unwrap_or(0),
                 });
             }
             line.clear();
@@ -411,6 +1392,16 @@ impl Gateway {
         Ok(files)
     }
 
+    /// Runs the `verify-ring` checks through the first node that accepts a connection
+    async fn verify_ring(&self) -> Result<RingReport, AnyErr> {
+        for addr in &self.node_addrs {
+            if TcpStream::connect(addr).await.is_ok() {
+                return Ok(ring_verify::verify_ring_with(addr, self.token.as_ref()).await);
+            }
+        }
+        Err("Could not connect to any node in the ring".into())
+    }
+
     /// Connects to the ring, sends "NODE HEAL", and waits for the full response.
     async fn trigger_node_heal(&self) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
         // 1. Connect to a node in the ring
@@ -496,9 +1487,66 @@ impl Gateway {
     // --- TCP HELPERS ---
 
     /// Tries all node addresses and returns a stream to the first one that connects.
+    /// Connects to the node reporting the lightest load, so downloads of a hot
+    /// file do not all go through the same entry node. Falls back to
+    /// [`Self::connect_to_ring`] when no node reports a load.
+    async fn connect_least_loaded(&self) -> Result<TcpStream, AnyErr> {
+        let tasks: Vec<JoinHandle<(String, Option<NodeLoad>)>> = self
+            .node_addrs
+            .iter()
+            .map(|addr| tokio::spawn(Self::load_node(addr.clone(), self.token.clone())))
+            .collect();
+
+        let mut lightest: Option<(String, NodeLoad)> = None;
+        for task in tasks {
+            if let Ok((addr, Some(load))) = task.await
+                && lightest
+                    .as_ref()
+                    .is_none_or(|(_, best)| load.is_lighter_than(best))
+            {
+                lightest = Some((addr, load));
+            }
+        }
+
+        if let Some((addr, load)) = lightest
+            && let Ok(stream) = self.connect_node(&addr).await
+        {
+            tracing::debug!(node = %addr, transfers = load.transfers, recent_bytes = load.recent_bytes, "Pulling through the least loaded node");
+            return Ok(stream);
+        }
+        self.connect_to_ring().await
+    }
+
+    /// Connects to the node at `addr`, presenting the gateway's token. Only
+    /// ring members (see [`Self::member_addr`]) are dialled, so the token
+    /// never goes anywhere else.
+    async fn connect_node(&self, addr: &str) -> io::Result<TcpStream> {
+        let Some(addr) = self.member_addr(addr).await else {
+            tracing::debug!(addr = %addr, "Refusing to dial an address outside the ring");
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("{} is not a node of the ring", addr),
+            ));
+        };
+        auth::connect_with(&addr, self.token.as_ref()).await
+    }
+
+    /// Connects to the first node that accepts, presenting the gateway's
+    /// token (see [`Self::connect_node`])
     async fn connect_to_ring(&self) -> Result<TcpStream, Box<dyn std::error::Error + Send + Sync>> {
+        self.connect_to_ring_as(true).await
+    }
+
+    async fn connect_to_ring_as(
+        &self,
+        with_token: bool,
+    ) -> Result<TcpStream, Box<dyn std::error::Error + Send + Sync>> {
         for addr in &self.node_addrs {
-            if let Ok(stream) = TcpStream::connect(addr).await {
+            let stream = match with_token {
+                true => self.connect_node(addr).await,
+                false => TcpStream::connect(addr).await,
+            };
+            if let Ok(stream) = stream {
                 return Ok(stream);
             }
         }
@@ -507,12 +1555,49 @@ impl Gateway {
 
     // --- HTTP HELPERS ---
 
+    /// Reads the request headers up to the blank line, keyed by lowercase name
+    /// Reads the headers of a request, up to the blank line ending them, or
+    /// `None` if they run over `limit` bytes
+    async fn read_headers<R>(
+        reader: &mut BufReader<R>,
+        limit: usize,
+    ) -> io::Result<Option<HashMap<String, String>>>
+    where
+        R: AsyncRead + Unpin,
+    {
+        let mut headers = HashMap::new();
+        let mut line = String::new();
+        let mut left = limit as u64;
+        loop {
+            line.clear();
+            let n = (&mut *reader).take(left).read_line(&mut line).await?;
+            if n == 0 {
+                if left == 0 {
+                    return Ok(None);
+                }
+                break; // Premature end
+            }
+            left -= n as u64;
+            if !line.ends_with('\n') && left == 0 {
+                return Ok(None);
+            }
+            let trimmed = line.trim();
+            if trimmed.is_empty() {
+                break; // End of headers
+            }
+            if let Some((key, value)) = trimmed.split_once(':') {
+                headers.insert(key.trim().to_ascii_lowercase(), value.trim().to_string());
+            }
+        }
+        Ok(Some(headers))
+    }
+
     /// Sends a 204 No Content response for OPTIONS preflight requests
     async fn send_options_response(writer: &mut (impl AsyncWrite + Unpin)) -> io::Result<()> {
         let response = "HTTP/1.1 204 No Content\r\n\
                         Access-Control-Allow-Origin: *\r\n\
                         Access-Control-Allow-Methods: POST, GET, OPTIONS\r\n\
-                        Access-Control-Allow-Headers: Content-Type, X-Filename\r\n\
+                        Access-Control-Allow-Headers: Content-Type, If-None-Match, X-Filename, X-Push-Mode, X-Push-Place, X-Transfer-Token\r\n\
                         Connection: close\r\n\
                         \r\n";
         writer.write_all(response.as_bytes()).await
@@ -521,37 +1606,114 @@ impl Gateway {
     /// Sends HTTP headers for a file pull.
     async fn send_file_response_headers(
         writer: &mut (impl AsyncWrite + Unpin),
-        filename: &str,
+        headers: &FileHeaders<'_>,
     ) -> io::Result<()> {
+        let mut extra = String::new();
+        if let Some(etag) = headers.etag {
+            extra.push_str(&format!("ETag: {}\r\n", etag));
+        }
+        if let Some(length) = headers.length {
+            extra.push_str(&format!("Content-Length: {}\r\n", length));
+        }
+        if let Some(encoding) = headers.encoding {
+            extra.push_str(&format!(
+                "Content-Encoding: {}\r\nVary: Accept-Encoding\r\n",
+                encoding
+            ));
+        }
+        let status = match headers.range {
+            Some((offset, size)) if size > 0 => {
+                extra.push_str(&format!(
+                    "Content-Range: bytes {}-{}/{}\r\n",
+                    offset,
+                    size - 1,
+                    size
+                ));
+                "206 Partial Content"
+            }
+            _ => "200 OK",
+        };
         let response = format!(
-            "HTTP/1.1 200 OK\r\n\
-             Content-Type: application/octet-stream\r\n\
+            "HTTP/1.1 {}\r\n\
+             Content-Type: {}\r\n\
+             Accept-Ranges: bytes\r\n\
              Access-Control-Allow-Origin: *\r\n\
+             Access-Control-Expose-Headers: ETag, X-Transfer-Token, Content-Range\r\n\
              Content-Disposition: attachment; filename=\"{}\"\r\n\
+             {}\
+             X-Transfer-Token: {}\r\n\
+             Connection: close\r\n\
+             \r\n",
+            status,
+            headers.content_type,
+            headers
+                .filename
+                .replace(|c: char| c.is_control() || c == '"', "_"),
+            extra,
+            headers.token
+        );
+        writer.write_all(response.as_bytes()).await
+    }
+
+    /// Sends a 304 Not Modified for a conditional pull of an unchanged file
+    async fn send_not_modified_response(
+        writer: &mut (impl AsyncWrite + Unpin),
+        etag: &str,
+    ) -> io::Result<()> {
+        let response = format!(
+            "HTTP/1.1 304 Not Modified\r\n\
+             ETag: {}\r\n\
+             Access-Control-Allow-Origin: *\r\n\
+             Access-Control-Expose-Headers: ETag\r\n\
              Connection: close\r\n\
              \r\n",
-            filename
+            etag
         );
         writer.write_all(response.as_bytes()).await
     }
 
+    /// Writes one server-sent event with a JSON payload
+    async fn send_event<T: Serialize>(
+        writer: &mut (impl AsyncWrite + Unpin),
+        event: &str,
+        data: &T,
+    ) -> io::Result<()> {
+        let json = serde_json::to_string(data).unwrap_or("{}".to_string());
+        writer
+            .write_all(format!("event: {}\ndata: {}\n\n", event, json).as_bytes())
+            .await
+    }
+
+    /// Sends `data` as JSON, compressed with `encoding` if given
     async fn send_json_response<T: Serialize>(
         writer: &mut (impl AsyncWrite + Unpin),
         data: T,
+        encoding: Option<Encoding>,
     ) -> io::Result<()> {
         let json = serde_json::to_string(&data).unwrap_or("{}".to_string());
+        let (body, content_encoding) = match encoding {
+            Some(encoding) => (
+                compression::compress(encoding, json.as_bytes())?,
+                format!(
+                    "Content-Encoding: {}\r\nVary: Accept-Encoding\r\n",
+                    encoding
+                ),
+            ),
+            None => (json.into_bytes(), String::new()),
+        };
         let response = format!(
             "HTTP/1.1 200 OK\r\n\
              Content-Type: application/json\r\n\
+             {}\
              Access-Control-Allow-Origin: *\r\n\
              Content-Length: {}\r\n\
              Connection: close\r\n\
-             \r\n\
-             {}",
-            json.len(),
-            json
+             \r\n",
+            content_encoding,
+            body.len()
         );
-        writer.write_all(response.as_bytes()).await
+        writer.write_all(response.as_bytes()).await?;
+        writer.write_all(&body).await
     }
 
     async fn send_error_response(
@@ -575,3 +1737,194 @@ impl Gateway {
         writer.write_all(response.as_bytes()).await
     }
 }
+
+/// First byte of a `Range: bytes=<n>-` header. Other ranges (closed, suffix or
+/// several) are not supported and get the whole file, as HTTP allows.
+fn range_start(range: &str) -> Option<u64> {
+    let start = range.trim().strip_prefix("bytes=")?.strip_suffix('-')?;
+    start.trim().parse().ok()
+}
+
+/// Whether an `If-None-Match` header (`*`, or a list of possibly weak tags)
+/// names `etag`
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    if_none_match
+        .split(',')
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == etag)
+}
+
+/// Status and reason an upload with these headers is refused with before its
+/// body is read: no `Content-Length` (411), one that is not a byte count, or
+/// no file name (400). `Content-Length: 0` is an empty file.
+fn upload_refusal(headers: &HashMap<String, String>) -> Option<(u16, &'static str)> {
+    match headers
+        .get("content-length")
+        .map(|v| v.trim().parse::<u64>())
+    {
+        None => return Some((411, "Length Required")),
+        Some(Err(_)) => return Some((400, "Bad Request: Invalid Content-Length")),
+        Some(Ok(_)) => {}
+    }
+    match headers.get("x-filename") {
+        Some(name) if !name.trim().is_empty() => None,
+        _ => Some((400, "Bad Request: Missing X-Filename")),
+    }
+}
+
+/// What a client sends after the line of a proxied command
+enum Body {
+    Empty,
+    /// `FILE PUSH`: that many bytes
+    Bytes(u64),
+    /// `FILE SYNC`: block signatures, then the blocks the node asks for
+    Sync {
+        size: u64,
+        block_size: u64,
+    },
+}
+
+impl Body {
+    fn of(cmd: &protocol::Command) -> Self {
+        match *cmd {
+            protocol::Command::FilePush { size, .. } => Body::Bytes(size),
+            protocol::Command::FileSync {
+                size, block_size, ..
+            } => Body::Sync { size, block_size },
+            _ => Body::Empty,
+        }
+    }
+}
+
+/// Forwards the block signatures of a `FILE SYNC` and the node's `NEED`
+/// answer. Returns how many bytes of blocks the client sends next.
+async fn sync_exchange<CR, CW, NR, NW>(
+    client_reader: &mut CR,
+    client_writer: &mut CW,
+    node_reader: &mut NR,
+    node_writer: &mut NW,
+    size: u64,
+    block_size: u64,
+    (sent, received): (&AtomicU64, &AtomicU64),
+) -> io::Result<u64>
+where
+    CR: AsyncBufRead + Unpin,
+    CW: AsyncWrite + Unpin,
+    NR: AsyncBufRead + Unpin,
+    NW: AsyncWrite + Unpin,
+{
+    let block_size = block_size.max(1);
+    let mut line = String::new();
+    for _ in 0..delta::block_count(size, block_size) {
+        line.clear();
+        let n = (&mut *client_reader)
+            .take(SIGNATURE_LINE_MAX)
+            .read_line(&mut line)
+            .await?;
+        if n == 0 {
+            break;
+        }
+        node_writer.write_all(line.as_bytes()).await?;
+        sent.fetch_add(n as u64, Ordering::Relaxed);
+    }
+    line.clear();
+    let n = node_reader.read_line(&mut line).await?;
+    client_writer.write_all(line.as_bytes()).await?;
+    received.fetch_add(n as u64, Ordering::Relaxed);
+    // Anything but `NEED` ends the exchange
+    let needed = delta::parse_need(&line).unwrap_or_default();
+    Ok(needed
+        .iter()
+        .map(|&i| {
+            size.saturating_sub((i as u64).saturating_mul(block_size))
+                .min(block_size)
+        })
+        .sum())
+}
+
+/// Copies `reader` to `writer` until `reader` ends, then shuts `writer` down so
+/// its peer sees the end too. Counts the bytes into `bytes`, and records in
+/// `activity` when the last ones went (milliseconds after `started`).
+async fn pipe<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    bytes: &AtomicU64,
+    activity: &AtomicU64,
+    started: Instant,
+) -> io::Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut buf = vec![0u8; PIPE_BUF];
+    loop {
+        let n = reader.read(&mut buf).await?;
+        if n == 0 {
+            return writer.shutdown().await;
+        }
+        writer.write_all(&buf[..n]).await?;
+        bytes.fetch_add(n as u64, Ordering::Relaxed);
+        activity.store(started.elapsed().as_millis() as u64, Ordering::Relaxed);
+    }
+}
+
+/// Resolves once `activity` (see [`pipe`]) is `idle` old; never for `None`
+async fn idle_expired(idle: Option<Duration>, activity: &AtomicU64, started: Instant) {
+    let Some(idle) = idle else {
+        return std::future::pending().await;
+    };
+    loop {
+        let last = started + Duration::from_millis(activity.load(Ordering::Relaxed));
+        if last.elapsed() >= idle {
+            return;
+        }
+        tokio::time::sleep_until(last + idle).await;
+    }
+}
+
+/// Resolves `max` after `started`; never for `None`
+async fn expired(max: Option<Duration>, started: Instant) {
+    match max {
+        Some(max) => tokio::time::sleep_until(started + max).await,
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn upload_refusals() {
+        let cases = [
+            (&[("x-filename", "a.txt")][..], Some(411)),
+            (&[("content-length", "12"), ("x-filename", "a.txt")], None),
+            (&[("content-length", "0"), ("x-filename", "a.txt")], None),
+            (
+                &[("content-length", "-1"), ("x-filename", "a.txt")],
+                Some(400),
+            ),
+            (
+                &[("content-length", "12 34"), ("x-filename", "a.txt")],
+                Some(400),
+            ),
+            (
+                &[("content-length", ""), ("x-filename", "a.txt")],
+                Some(400),
+            ),
+            (&[("content-length", "12")], Some(400)),
+            (&[("content-length", "12"), ("x-filename", " ")], Some(400)),
+        ];
+        for (pairs, status) in cases {
+            let refusal = upload_refusal(&headers(pairs)).map(|(status, _)| status);
+            assert_eq!(refusal, status, "{:?}", pairs);
+        }
+    }
+}
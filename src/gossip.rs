@@ -0,0 +1,237 @@
+//! Scheduling of the gossip loop's health checks, and the netmap changes
+//! its pings spread.
+//!
+//! With a fixed interval every node of a ring pings at the same moments,
+//! and a node that is merely slow gets declared dead exactly at an interval
+//! boundary. Instead, each round's delay:
+//!
+//! - is spread by a random `gossip-jitter` percent either way,
+//! - grows (up to [`MAX_BACKOFF`] times `wait-time`) while the neighbor keeps
+//!   answering normally, and
+//! - shrinks to a quarter of `wait-time` while the neighbor looks suspect or
+//!   has just missed a ping, so a second probe confirms a death quickly.
+//!
+//! A neighbor is only declared dead after [`DEAD_AFTER`] missed pings in a row.
+//!
+//! Besides its next hop, every round also pings [`GOSSIP_FANOUT`] peers of the
+//! netmap picked at random, so a node cut off from everyone but its
+//! predecessor is still noticed. A random peer missing [`DEAD_AFTER`] of
+//! those pings in a row is marked `Dead` too (see [`PeerProbes`]); respawning
+//! it stays the job of its predecessor.
+//!
+//! Netmap changes are spread epidemically rather than sent to every node by
+//! the one that saw them. A change is kept as a rumor and rides on the next
+//! [`retransmissions`] pings this node sends, to its next hop and to the
+//! random peers of each round; every node taking it does the same,
+//! so it reaches a ring of `n` nodes in `O(log n)` rounds without any node
+//! connecting to all others. Each entry carries the node's incarnation: news
+//! of a newer incarnation wins, and in the same incarnation the worse status
+//! wins (see [`Member::supersedes`]). A node that hears it is `Suspect` or
+//! `Dead` refutes it with a higher incarnation of itself.
+
+use crate::{
+    latency::now_micros,
+    schema::{Member, Netmap},
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::Duration,
+};
+
+/// Consecutive failed health checks before a neighbor is declared dead
+pub const DEAD_AFTER: u32 = 2;
+
+/// Healthy rounds needed for each backoff step
+const BACKOFF_EVERY: u32 = 5;
+
+/// Each backoff step adds this fraction of `wait-time`...
+const BACKOFF_STEP: f64 = 0.25;
+
+/// ...up to this multiple of `wait-time`
+pub const MAX_BACKOFF: f64 = 2.0;
+
+/// While probing, rounds are this many times faster than `wait-time`...
+const PROBE_DIVISOR: u32 = 4;
+
+/// ...but never closer together than this
+const MIN_PROBE: Duration = Duration::from_millis(100);
+
+/// Random peers pinged each round besides the next hop
+pub const GOSSIP_FANOUT: usize = 2;
+
+/// Rumors carried by one ping, so a burst of changes keeps lines short
+const MAX_RUMORS: usize = 32;
+
+/// Times a rumor is sent in a ring of `nodes` nodes: `3 * ceil(log2(n + 1))`,
+/// enough for it to reach every node with high probability
+pub fn retransmissions(nodes: usize) -> u32 {
+    3 * (usize::BITS - nodes.leading_zeros()).max(1)
+}
+
+/// Pings in a row each random peer missed
+#[derive(Debug, Default)]
+pub struct PeerProbes(HashMap<String, u32>);
+
+impl PeerProbes {
+    pub fn on_success(&mut self, port: &str) {
+        self.0.remove(port);
+    }
+
+    /// Records a missed ping. Returns `true` once `port` has missed
+    /// [`DEAD_AFTER`] in a row and should be marked dead.
+    pub fn on_failure(&mut self, port: &str) -> bool {
+        let failures = self.0.entry(port.to_string()).or_insert(0);
+        *failures += 1;
+        if *failures >= DEAD_AFTER {
+            self.0.remove(port);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Forgets the peers no longer probed (dead, or gone from the netmap)
+    pub fn retain(&mut self, live: &[String]) {
+        self.0.retain(|port, _| live.contains(port));
+    }
+}
+
+/// Netmap changes waiting to be spread, at most one per node
+#[derive(Debug, Default)]
+pub struct Rumors(BTreeMap<String, (Member, u32)>);
+
+impl Rumors {
+    /// Spreads `member` as the news about `port`, `sends` times; it replaces
+    /// older news about the same node
+    pub fn push(&mut self, port: &str, member: Member, sends: u32) {
+        self.0.insert(port.to_string(), (member, sends));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Rumors for one ping, those sent least so far first. Each counts as
+    /// sent; one sent often enough is dropped.
+    pub fn take(&mut self) -> Netmap {
+        let mut ports: Vec<(u32, String)> = self
+            .0
+            .iter()
+            .map(|(port, (_, left))| (*left, port.clone()))
+            .collect();
+        ports.sort_unstable_by_key(|(left, _)| std::cmp::Reverse(*left));
+        let mut out = Netmap::default();
+        for (_, port) in ports.into_iter().take(MAX_RUMORS) {
+            let Some((member, left)) = self.0.get_mut(&port) else {
+                continue;
+            };
+            out.0.insert(port.clone(), member.clone());
+            *left = left.saturating_sub(1);
+            if *left == 0 {
+                self.0.remove(&port);
+            }
+        }
+        out
+    }
+}
+
+/// Per-neighbor gossip timing state
+#[derive(Debug)]
+pub struct GossipSchedule {
+    /// Neighbor the state refers to; reset when the next hop changes
+    target: Option<String>,
+    healthy_streak: u32,
+    failures: u32,
+    suspect: bool,
+    rng: u64,
+}
+
+impl GossipSchedule {
+    /// `seed` only needs to differ between nodes (e.g. the node's port)
+    pub fn new(seed: u64) -> Self {
+        Self {
+            target: None,
+            healthy_streak: 0,
+            failures: 0,
+            suspect: false,
+            // xorshift must not start at zero
+            rng: (now_micros() ^ seed.wrapping_mul(0x9e37_79b9_7f4a_7c15)) | 1,
+        }
+    }
+
+    /// Starts over when the neighbor being checked changes
+    pub fn set_target(&mut self, target: &str) {
+        if self.target.as_deref() != Some(target) {
+            self.target = Some(target.to_string());
+            self.healthy_streak = 0;
+            self.failures = 0;
+            self.suspect = false;
+        }
+    }
+
+    /// Records an answered ping, and whether the neighbor's latency is suspect
+    pub fn on_success(&mut self, suspect: bool) {
+        self.failures = 0;
+        self.suspect = suspect;
+        if suspect {
+            self.healthy_streak = 0;
+        } else {
+            self.healthy_streak = self.healthy_streak.saturating_add(1);
+        }
+    }
+
+    /// Records a failed health check. Returns `true` once the neighbor has
+    /// missed [`DEAD_AFTER`] pings in a row and should be declared dead.
+    pub fn on_failure(&mut self) -> bool {
+        self.healthy_streak = 0;
+        self.failures += 1;
+        if self.failures >= DEAD_AFTER {
+            self.failures = 0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Delay before the next health check, for a `wait-time` of `base`
+    pub fn next_delay(&mut self, base: Duration, jitter_pct: u32) -> Duration {
+        let delay = if self.failures > 0 || self.suspect {
+            (base / PROBE_DIVISOR).max(MIN_PROBE).min(base)
+        } else {
+            let steps = (self.healthy_streak / BACKOFF_EVERY) as f64;
+            base.mul_f64((1.0 + steps * BACKOFF_STEP).min(MAX_BACKOFF))
+        };
+        self.jitter(delay, jitter_pct)
+    }
+
+    /// Up to `n` of `items`, picked at random
+    pub fn pick<T>(&mut self, mut items: Vec<T>, n: usize) -> Vec<T> {
+        let len = items.len();
+        let n = n.min(len);
+        for i in 0..n {
+            let j = i + (self.unit() * (len - i) as f64) as usize;
+            items.swap(i, j.min(len - 1));
+        }
+        items.truncate(n);
+        items
+    }
+
+    /// `delay` moved by a random amount within `pct` percent either way
+    fn jitter(&mut self, delay: Duration, pct: u32) -> Duration {
+        if pct == 0 {
+            return delay;
+        }
+        let unit = self.unit();
+        let spread = pct.min(100) as f64 / 100.0;
+        delay.mul_f64(1.0 - spread + 2.0 * spread * unit)
+    }
+
+    /// A random number in `[0, 1)`
+    fn unit(&mut self) -> f64 {
+        // xorshift64: plenty for spreading timers and picking peers
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
@@ -0,0 +1,359 @@
+//! Ring-wide health summary and wiring audit, for `RING SIZE`, `RING HEALTH`
+//! and `RING AUDIT`.
+//!
+//! Every node of the netmap is asked for its next hop (`NODE STATUS`) and its
+//! ping statistics (`NODE METRICS`). The answers tell how many nodes are
+//! reachable, whether following next hops from the asking node leads back to
+//! it, which hop has the slowest pings, and where the wiring is off: nodes no
+//! one points to, and nodes more than one node points to.
+//!
+//! The audit goes further: it names each wiring error and proposes a single
+//! ring through every reachable node that keeps as much of the current wiring
+//! as it can, so only the broken links have to change.
+
+use crate::{
+    node::{Node, port_str},
+    server,
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::task::JoinSet;
+
+/// What one node answered: its next hop, and the slowest ping to it
+struct NodeReport {
+    next: Option<String>,
+    max_rtt: Option<Duration>,
+}
+
+/// State of the ring as seen from one node
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RingHealth {
+    /// Ports of the netmap
+    pub known: BTreeSet<String>,
+    /// Nodes that answered, by port, with their next hop
+    pub next: BTreeMap<String, Option<String>>,
+    /// Following next hops from the asking node leads back to it
+    pub closed: bool,
+    /// Slowest hop by its worst recent ping: `(from, to, rtt)`
+    pub max_hop: Option<(String, String, Duration)>,
+    /// Reachable nodes no reachable node points to
+    pub unpointed: Vec<String>,
+    /// Nodes more than one reachable node points to, with those nodes
+    pub multi_pointed: BTreeMap<String, Vec<String>>,
+}
+
+impl RingHealth {
+    pub fn reachable(&self) -> usize {
+        self.next.len()
+    }
+}
+
+/// `RING HEALTH` reply lines, before the final `OK`
+impl fmt::Display for RingHealth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "REACHABLE {}/{}", self.reachable(), self.known.len())?;
+        writeln!(f, "CLOSED {}", self.closed)?;
+        match &self.max_hop {
+            Some((from, to, rtt)) => {
+                writeln!(f, "MAX-HOP {}->{} rtt_us={}", from, to, rtt.as_micros())?
+            }
+            None => writeln!(f, "MAX-HOP -")?,
+        }
+        for port in &self.unpointed {
+            writeln!(f, "UNPOINTED {}", port)?;
+        }
+        for (port, from) in &self.multi_pointed {
+            writeln!(f, "MULTI-POINTED {} from={}", port, from.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+/// Asks every netmap node for its next hop and ping statistics, all at once
+pub async fn survey(node: &Arc<Node>) -> RingHealth {
+    let own = port_str(&node.port).to_string();
+    let mut known: BTreeSet<String> = node
+        .get_network_nodes_entries()
+        .await
+        .ports()
+        .cloned()
+        .collect();
+    known.insert(own.clone());
+
+    let mut queries = JoinSet::new();
+    for port in known.iter().filter(|p| **p != own).cloned() {
+        let node = Arc::clone(node);
+        queries.spawn(async move {
+            let report = ask(&node, &port).await;
+            (port, report)
+        });
+    }
+    let mut reports = BTreeMap::new();
+    reports.insert(own.clone(), local_report(node).await);
+    while let Some(res) = queries.join_next().await {
+        if let Ok((port, Some(report))) = res {
+            reports.insert(port, report);
+        }
+    }
+
+    // Who points to whom
+    let mut pointed_by: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (port, report) in &reports {
+        if let Some(next) = &report.next {
+            pointed_by
+                .entry(next.clone())
+                .or_default()
+                .push(port.clone());
+        }
+    }
+    let unpointed = reports
+        .keys()
+        .filter(|port| !pointed_by.contains_key(*port))
+        .cloned()
+        .collect();
+    let multi_pointed = pointed_by
+        .iter()
+        .filter(|(_, from)| from.len() > 1)
+        .map(|(port, from)| (port.clone(), from.clone()))
+        .collect();
+
+    // Follow next hops from here; a ring closes within as many hops as nodes answered
+    let mut closed = false;
+    let mut seen = BTreeSet::new();
+    let mut at = own.clone();
+    while seen.insert(at.clone()) {
+        match reports.get(&at).and_then(|r| r.next.clone()) {
+            Some(next) if next == own => {
+                closed = true;
+                break;
+            }
+            Some(next) => at = next,
+            None => break,
+        }
+    }
+
+    let max_hop = reports
+        .iter()
+        .filter_map(|(port, r)| Some((port.clone(), r.next.clone()?, r.max_rtt?)))
+        .max_by_key(|(_, _, rtt)| *rtt);
+
+    RingHealth {
+        known,
+        next: reports
+            .into_iter()
+            .map(|(port, r)| (port, r.next))
+            .collect(),
+        closed,
+        max_hop,
+        unpointed,
+        multi_pointed,
+    }
+}
+
+async fn local_report(node: &Node) -> NodeReport {
+    let next = node.get_next().await.map(|n| port_str(&n).to_string());
+    let max_rtt = next.as_ref().and_then(|next| {
+        node.latency_stats()
+            .into_iter()
+            .find(|(port, stats)| port == next && !stats.is_empty())
+            .map(|(_, stats)| stats.max())
+    });
+    NodeReport { next, max_rtt }
+}
+
+/// `None` when the node does not answer `NODE STATUS`
+async fn ask(node: &Node, port: &str) -> Option<NodeReport> {
+    let addr = node.peer_addr(port);
+    let status = server::query_lines(node, &addr, "NODE STATUS").await.ok()?;
+    let next = status
+        .iter()
+        .find_map(|l| l.strip_prefix("NEXT "))
+        .filter(|next| *next != "<unset>")
+        .map(|next| port_str(next).to_string());
+    // Older nodes, or nodes that have not pinged yet, have no latency to report
+    let max_rtt = match &next {
+        Some(next) => server::query_lines(node, &addr, "NODE METRICS")
+            .await
+            .ok()
+            .and_then(|metrics| {
+                let key = format!("ping_rtt_max_us.{}=", next);
+                metrics
+                    .iter()
+                    .find_map(|l| l.strip_prefix(key.as_str())?.parse().ok())
+            })
+            .map(Duration::from_micros),
+        None => None,
+    };
+    Some(NodeReport { next, max_rtt })
+}
+
+/* --- AUDIT --- */
+
+/// A wiring error found by [`audit`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Anomaly {
+    /// Several nodes have `port` as next hop
+    SharedSuccessor { port: String, from: Vec<String> },
+    /// `port`'s next hop is not in the netmap
+    UnknownSuccessor { port: String, next: String },
+    /// `port`'s next hop is in the netmap but does not answer
+    UnreachableSuccessor { port: String, next: String },
+    /// `port` has no next hop
+    NoSuccessor { port: String },
+    /// These nodes loop among themselves, apart from the other reachable nodes
+    SubRing { ports: Vec<String> },
+}
+
+/// `RING AUDIT` lines: `ANOMALY <kind> <port> ...`
+impl fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Anomaly::SharedSuccessor { port, from } => {
+                write!(
+                    f,
+                    "ANOMALY shared-successor {} from={}",
+                    port,
+                    from.join(",")
+                )
+            }
+            Anomaly::UnknownSuccessor { port, next } => {
+                write!(f, "ANOMALY unknown-successor {} next={}", port, next)
+            }
+            Anomaly::UnreachableSuccessor { port, next } => {
+                write!(f, "ANOMALY unreachable-successor {} next={}", port, next)
+            }
+            Anomaly::NoSuccessor { port } => write!(f, "ANOMALY no-successor {}", port),
+            Anomaly::SubRing { ports } => write!(f, "ANOMALY sub-ring {}", ports.join(",")),
+        }
+    }
+}
+
+/// Wiring errors of a surveyed ring, and the links that would fix them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Audit {
+    pub anomalies: Vec<Anomaly>,
+    /// `(from, to)` next hops to set, only where they differ from today's
+    pub fixes: Vec<(String, String)>,
+}
+
+/// Checks the wiring of `health` (surveyed from `own`) and proposes one ring
+/// through every reachable node
+pub fn audit(health: &RingHealth, own: &str) -> Audit {
+    let reachable = |port: &str| health.next.contains_key(port);
+    let mut anomalies = Vec::new();
+
+    for (port, from) in &health.multi_pointed {
+        anomalies.push(Anomaly::SharedSuccessor {
+            port: port.clone(),
+            from: from.clone(),
+        });
+    }
+    for (port, next) in &health.next {
+        match next {
+            None => anomalies.push(Anomaly::NoSuccessor { port: port.clone() }),
+            Some(next) if !health.known.contains(next) => {
+                anomalies.push(Anomaly::UnknownSuccessor {
+                    port: port.clone(),
+                    next: next.clone(),
+                })
+            }
+            Some(next) if !reachable(next) => anomalies.push(Anomaly::UnreachableSuccessor {
+                port: port.clone(),
+                next: next.clone(),
+            }),
+            Some(_) => {}
+        }
+    }
+    let cycles = cycles(health);
+    if cycles.len() > 1 || cycles.first().is_some_and(|c| c.len() < health.reachable()) {
+        for ports in cycles {
+            anomalies.push(Anomaly::SubRing { ports });
+        }
+    }
+
+    // Chain the nodes along their current links, starting here, then close the ring
+    let order = proposed_order(health, own);
+    let fixes = if order.len() < 2 {
+        Vec::new()
+    } else {
+        order
+            .iter()
+            .zip(order.iter().cycle().skip(1))
+            .filter(|(from, to)| health.next.get(*from) != Some(&Some((*to).clone())))
+            .map(|(from, to)| (from.clone(), to.clone()))
+            .collect()
+    };
+    Audit { anomalies, fixes }
+}
+
+/// Loops of next hops among reachable nodes, each listed from its lowest port
+fn cycles(health: &RingHealth) -> Vec<Vec<String>> {
+    let mut done = BTreeSet::new();
+    let mut out = Vec::new();
+    for start in health.next.keys() {
+        let mut path: Vec<String> = Vec::new();
+        let mut at = start.clone();
+        let closes = loop {
+            if done.contains(&at) {
+                break false;
+            }
+            if path.contains(&at) {
+                break true;
+            }
+            path.push(at.clone());
+            match health.next.get(&at) {
+                Some(Some(next)) if health.next.contains_key(next) => at = next.clone(),
+                _ => break false,
+            }
+        };
+        if closes && let Some(i) = path.iter().position(|p| *p == at) {
+            let mut cycle = path[i..].to_vec();
+            let lowest = cycle
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, p)| *p)
+                .map_or(0, |(i, _)| i);
+            cycle.rotate_left(lowest);
+            out.push(cycle);
+        }
+        done.extend(path);
+    }
+    out
+}
+
+/// Reachable nodes in ring order: the chain of current links from `own`, then
+/// the other chains, each from a node nobody left points to
+fn proposed_order(health: &RingHealth, own: &str) -> Vec<String> {
+    let mut order: Vec<String> = Vec::new();
+    let mut placed = BTreeSet::new();
+    let mut start = health.next.contains_key(own).then(|| own.to_string());
+    while let Some(head) = start {
+        let mut at = head;
+        while placed.insert(at.clone()) {
+            order.push(at.clone());
+            match health.next.get(&at) {
+                Some(Some(next)) if health.next.contains_key(next) => at = next.clone(),
+                _ => break,
+            }
+        }
+        let left: Vec<&String> = health
+            .next
+            .keys()
+            .filter(|p| !placed.contains(*p))
+            .collect();
+        let pointed: BTreeSet<&String> = left
+            .iter()
+            .filter_map(|p| health.next.get(*p)?.as_ref())
+            .collect();
+        start = left
+            .iter()
+            .find(|p| !pointed.contains(**p))
+            .or(left.first())
+            .map(|p| (*p).clone());
+    }
+    order
+}
@@ -0,0 +1,90 @@
+//! Dedicated UDP heartbeat.
+//!
+//! Health checks normally share the data port, so a node busy streaming a
+//! large relay can be slow to accept a `NODE PING` and get "healed" while it
+//! is perfectly alive. With `--udp-heartbeat`, every node also answers pings
+//! on a UDP socket bound to the same port number as its TCP listener (UDP and
+//! TCP ports are separate), and the gossip loop and heal walks try that first.
+//!
+//! A datagram carries the same line as the TCP ping, `NODE PING <seq> <sent_at_us>`,
+//! and is answered with `PONG <seq> <sent_at_us>`.
+
+use crate::{
+    latency,
+    protocol::{self, Command},
+};
+use std::{error::Error, net::SocketAddr, time::Duration};
+use tokio::net::{UdpSocket, lookup_host};
+
+type AnyErr = Box<dyn Error + Send + Sync>;
+
+/// Largest datagram read; pings and pongs are a few dozen bytes
+const MAX_DATAGRAM: usize = 128;
+
+/// Datagrams can get lost: a ping is sent up to this many times, spread over the timeout
+const SENDS: u32 = 3;
+
+/// Answers heartbeat pings on `socket` for as long as the node runs
+pub(crate) async fn serve(node_port: String, socket: UdpSocket) {
+    let mut buf = [0u8; MAX_DATAGRAM];
+    loop {
+        let (n, from) = match socket.recv_from(&mut buf).await {
+            Ok(received) => received,
+            Err(e) => {
+                tracing::debug!(node = %node_port, error = ?e, "Heartbeat: Receive failed");
+                continue;
+            }
+        };
+
+        let line = String::from_utf8_lossy(&buf[..n]);
+        let reply = match protocol::parse_line(&line) {
+            Ok(Command::NodePing {
+                echo: Some((seq, sent_at)),
+                ..
+            }) => format!("PONG {} {}\n", seq, sent_at),
+            Ok(Command::NodePing { echo: None, .. }) => "PONG\n".to_string(),
+            _ => {
+                tracing::debug!(node = %node_port, from = %from, "Heartbeat: Ignoring datagram that is not a ping");
+                continue;
+            }
+        };
+        if let Err(e) = socket.send_to(reply.as_bytes(), from).await {
+            tracing::debug!(node = %node_port, to = %from, error = ?e, "Heartbeat: Reply failed");
+        }
+    }
+}
+
+/// Sends `NODE PING <seq> <now>` to the heartbeat socket of `addr` and waits
+/// up to `timeout` for the echo. Returns the round-trip time, measured from
+/// the first send.
+pub(crate) async fn ping(addr: &str, seq: u64, timeout: Duration) -> Result<Duration, AnyErr> {
+    // 1. One socket per ping, so a late answer to an earlier ping can never be mistaken for this one
+    let peer = lookup_host(addr)
+        .await?
+        .next()
+        .ok_or_else(|| format!("could not resolve '{}'", addr))?;
+    let local: SocketAddr = if peer.is_ipv6() {
+        "[::]:0".parse()?
+    } else {
+        "0.0.0.0:0".parse()?
+    };
+    let socket = UdpSocket::bind(local).await?;
+    socket.connect(peer).await?;
+
+    // 2. Send the ping, again whenever an attempt goes unanswered
+    let sent_at = latency::now_micros();
+    let line = format!("NODE PING {} {}\n", seq, sent_at);
+    let mut buf = [0u8; MAX_DATAGRAM];
+    for _ in 0..SENDS {
+        socket.send(line.as_bytes()).await?;
+
+        // 3. Wait for the PONG; a peer without a heartbeat socket usually makes this fail fast
+        let Ok(received) = tokio::time::timeout(timeout / SENDS, socket.recv(&mut buf)).await
+        else {
+            continue;
+        };
+        let reply = String::from_utf8_lossy(&buf[..received?]);
+        return latency::pong_rtt(&reply, seq)?.ok_or_else(|| "heartbeat PONG without echo".into());
+    }
+    Err(format!("no heartbeat answer within {:?}", timeout).into())
+}
@@ -0,0 +1,153 @@
+//! Persistent node identity.
+//!
+//! Every node gets a random (version 4) UUID the first time it starts, kept
+//! in its data directory:
+//!
+//! ```text
+//! <data_dir>/node-id
+//! ```
+//!
+//! The file holds a single `xxxxxxxx-xxxx-4xxx-yxxx-xxxxxxxxxxxx` line. A node
+//! restarted on the same data directory keeps its identity whatever port it
+//! listens on, and netmap entries carry it next to the port, so a node that
+//! comes back at another address is recognised as the same node.
+//!
+//! Nodes keep what they know of their peers by id. A peer that announces
+//! none (an older version) is known by a stand-in id derived from its port
+//! (see [`NodeId::for_port`]), which is never sent on the wire.
+
+use crate::checksum::Sha256;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+    collections::hash_map::RandomState,
+    fmt,
+    hash::{BuildHasher, Hasher},
+    io::{self, Read},
+    path::Path,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Name of the identity file inside a node's data directory
+pub const NODE_ID_FILE: &str = "node-id";
+
+/// A node's UUID
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(pub [u8; 16]);
+
+impl NodeId {
+    /// A fresh random UUID (version 4, RFC 4122 variant)
+    pub fn generate() -> Self {
+        let mut bytes = random_bytes::<16>();
+        bytes[6] = (bytes[6] & 0x0f) | 0x40;
+        bytes[8] = (bytes[8] & 0x3f) | 0x80;
+        Self(bytes)
+    }
+
+    /// Stand-in id for the node at `port` when it announces none: a version 8
+    /// UUID hashed from the port, so the same port always gets the same one
+    pub fn for_port(port: &str) -> Self {
+        let mut bytes = [0u8; 16];
+        bytes.copy_from_slice(&Sha256::digest(format!("node-port {}", port).as_bytes()).0[..16]);
+        bytes[6] = (bytes[6] & 0x0f) | 0x80;
+        bytes[8] = (bytes[8] & 0x3f) | 0x80;
+        Self(bytes)
+    }
+
+    /// Whether this is a stand-in id (see [`NodeId::for_port`]) rather than
+    /// one a node announced
+    pub fn is_derived(&self) -> bool {
+        self.0[6] >> 4 == 8
+    }
+
+    /// Reads the identity kept in `data_dir`, creating (and storing) a new one
+    /// on first start
+    pub fn load_or_create(data_dir: &Path) -> io::Result<Self> {
+        let path = data_dir.join(NODE_ID_FILE);
+        match std::fs::read_to_string(&path) {
+            Ok(text) => text
+                .parse()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let id = Self::generate();
+                std::fs::create_dir_all(data_dir)?;
+                std::fs::write(&path, format!("{}\n", id))?;
+                Ok(id)
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// `N` bytes from the system's random source
+pub fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut bytes = [0u8; N];
+    if std::fs::File::open("/dev/urandom")
+        .and_then(|mut f| f.read_exact(&mut bytes))
+        .is_err()
+    {
+        // No urandom: mix the clock and the process id through std's
+        // randomly keyed hasher instead
+        for part in bytes.chunks_mut(8) {
+            let mut hasher = RandomState::new().build_hasher();
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_nanos());
+            hasher.write_u128(now);
+            hasher.write_u32(std::process::id());
+            part.copy_from_slice(&hasher.finish().to_be_bytes()[..part.len()]);
+        }
+    }
+    bytes
+}
+
+/// 128 random bits as 32 hex digits, for tokens that must not be guessed
+pub fn random_token() -> String {
+    random_bytes::<16>()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+impl fmt::Display for NodeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, b) in self.0.iter().enumerate() {
+            if matches!(i, 4 | 6 | 8 | 10) {
+                f.write_str("-")?;
+            }
+            write!(f, "{:02x}", b)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for NodeId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex: String = s.trim().chars().filter(|c| *c != '-').collect();
+        if hex.len() != 32 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(format!("invalid node id '{}'", s.trim()));
+        }
+        let mut out = [0u8; 16];
+        for (i, byte) in out.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+                .map_err(|_| format!("invalid node id '{}'", s.trim()))?;
+        }
+        Ok(Self(out))
+    }
+}
+
+impl Serialize for NodeId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for NodeId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
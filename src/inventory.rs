@@ -0,0 +1,165 @@
+//! Startup inventory: what a node finds in its data directory.
+//!
+//! A node keeps its file tags in [`TAGS_FILE`], rewritten whenever they
+//! change, and reads them back as it starts, so it knows its files before
+//! any peer resyncs it. It then compares the chunks in `content/` and
+//! `backup/`, loose or packed (see [`crate::pack`]), with their manifest
+//! entries (see [`crate::manifest`]) and tags:
+//!
+//! - a chunk without a manifest entry (a crash between writing and recording
+//!   it) is hashed and recorded,
+//! - a manifest entry without its chunk, or a tag naming this node as the
+//!   holder of a chunk it does not have, is flagged missing: logged, published
+//!   as [`NodeEvent::ChunkDamaged`] and counted in `NODE METRICS` (`FILE
+//!   VERIFY` restores a missing content chunk from its replica),
+//! - a chunk of no file this node knows is logged and kept, for the tag that
+//!   may still come with the next `FILE TAGS-SET`.
+
+use crate::{
+    NodeEvent,
+    disk::IoClass,
+    manifest, migrate,
+    node::Node,
+    protocol::decode_name,
+    server::{chunk_file_name, sanitize_filename},
+    verify,
+};
+use std::{
+    collections::{BTreeSet, HashSet},
+    fmt,
+    path::Path,
+};
+use tokio::fs;
+
+/// File in a node's data directory holding its file tags, in the
+/// `FILE TAGS-SET` encoding
+pub const TAGS_FILE: &str = "tags";
+
+/// What a node found as it started
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Inventory {
+    /// File tags read back from [`TAGS_FILE`]
+    pub files: usize,
+    /// Chunks found in `content/` and `backup/`
+    pub chunks: usize,
+    /// Chunks that had no manifest entry, now recorded
+    pub registered: usize,
+    /// Chunks the manifest or the tags expect, not on disk
+    pub missing: usize,
+    /// Chunks of no known file
+    pub orphaned: usize,
+}
+
+impl fmt::Display for Inventory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "INVENTORY files={} chunks={} registered={} missing={} orphaned={}",
+            self.files, self.chunks, self.registered, self.missing, self.orphaned
+        )
+    }
+}
+
+/// Loads the saved tags and checks the chunks on disk against the manifests
+/// and tags (see the module docs). Runs once, before the node serves.
+pub(crate) async fn take(node: &Node) {
+    let mut inventory = Inventory {
+        files: node.load_file_tags().await,
+        ..Inventory::default()
+    };
+    migrate::rename_legacy_chunks(node, &node.get_file_tags_entries().await).await;
+
+    // Disk names of every chunk of a known file, and those this node holds
+    let (known, held) = {
+        let tags = node.file_tags.read().await;
+        let mut known = HashSet::new();
+        let mut held = Vec::new();
+        for (name, tag) in tags.iter().filter(|(_, tag)| tag.ring.is_none()) {
+            for i in 0..tag.parts {
+                let chunk = chunk_file_name(tag.chunk_set(name), i, tag.parts);
+                if tag.holders.get(i as usize) == Some(&node.id) {
+                    held.push(sanitize_filename(&chunk));
+                }
+                known.insert(sanitize_filename(&chunk));
+            }
+        }
+        (known, held)
+    };
+
+    let mut missing = BTreeSet::new();
+    for subdir in ["content", "backup"] {
+        let dir = node.data_dir.join(subdir);
+        let manifest_dir = node.manifest_dir(subdir);
+        let mut on_disk = file_names(&dir).await;
+        on_disk.extend(node.packs.of(subdir).names());
+        inventory.chunks += on_disk.len();
+
+        for fname in &on_disk {
+            if !known.contains(fname) {
+                inventory.orphaned += 1;
+                tracing::warn!(node = %node.port, chunk = %decode_name(fname), subdir, "Inventory: Chunk of no known file");
+            }
+            if manifest::lookup(&manifest_dir, fname).await.is_some() {
+                continue;
+            }
+            match verify::hash_chunk(node, IoClass::Scrub, subdir, fname).await {
+                Ok(entry) => match manifest::record(&manifest_dir, fname, entry).await {
+                    Ok(()) => {
+                        inventory.registered += 1;
+                        tracing::info!(node = %node.port, chunk = %decode_name(fname), subdir, "Inventory: Recorded chunk missing from the manifest");
+                    }
+                    Err(e) => {
+                        tracing::warn!(node = %node.port, chunk = %decode_name(fname), error = ?e, "Inventory: Could not record manifest entry")
+                    }
+                },
+                Err(e) => {
+                    tracing::warn!(node = %node.port, chunk = %decode_name(fname), error = ?e, "Inventory: Could not hash chunk")
+                }
+            }
+        }
+
+        let expected = file_names(&manifest_dir).await.into_iter();
+        let expected: Vec<String> = if subdir == "content" {
+            expected.chain(held.iter().cloned()).collect()
+        } else {
+            expected.collect()
+        };
+        for fname in expected {
+            if !on_disk.contains(&fname) {
+                missing.insert((subdir, fname));
+            }
+        }
+    }
+
+    inventory.missing = missing.len();
+    for (subdir, fname) in missing {
+        let name = decode_name(&fname);
+        tracing::warn!(node = %node.port, chunk = %name, subdir, "Inventory: Chunk missing from disk");
+        node.emit(NodeEvent::ChunkDamaged {
+            name,
+            backup: subdir == "backup",
+            repaired: false,
+        });
+    }
+
+    if inventory.missing > 0 || inventory.orphaned > 0 {
+        tracing::warn!(node = %node.port, inventory = %inventory, "Inventory: Data directory does not match its metadata");
+    } else {
+        tracing::info!(node = %node.port, inventory = %inventory, "Inventory: Data directory matches its metadata");
+    }
+    let _ = node.inventory.set(inventory);
+}
+
+/// Names of the files in `dir`; none when it cannot be read
+async fn file_names(dir: &Path) -> HashSet<String> {
+    let mut names = HashSet::new();
+    let Ok(mut entries) = fs::read_dir(dir).await else {
+        return names;
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        if entry.file_type().await.is_ok_and(|t| t.is_file()) {
+            names.insert(entry.file_name().to_string_lossy().into_owned());
+        }
+    }
+    names
+}
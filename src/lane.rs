@@ -0,0 +1,38 @@
+//! Separate runtime for the data plane.
+//!
+//! File transfers stream megabytes, hash them and hit the disk. Sharing a
+//! runtime with everything else, a few large pushes can keep every worker
+//! busy long enough that a `NODE STATUS` or `NETMAP SET` waits seconds for
+//! its turn. Data commands (see [`crate::Command::is_data`]) are therefore
+//! handed to a runtime of their own, and the runtime accepting connections
+//! stays free for control traffic and the gossip loop.
+
+use std::{future::Future, sync::OnceLock};
+use tokio::{
+    runtime::{Builder, Runtime},
+    task::JoinError,
+};
+
+static DATA_RUNTIME: OnceLock<Runtime> = OnceLock::new();
+
+/// The data-plane runtime, started on first use and shared by every node of the process
+fn data_runtime() -> &'static Runtime {
+    DATA_RUNTIME.get_or_init(|| {
+        Builder::new_multi_thread()
+            .thread_name("ouroboros-data")
+            .enable_all()
+            .build()
+            .expect("failed to start the data-plane runtime")
+    })
+}
+
+/// Runs `fut` on the data-plane runtime and waits for its output.
+///
+/// Tasks spawned by `fut` land on the data-plane runtime as well.
+pub(crate) async fn run_data<F>(fut: F) -> Result<F::Output, JoinError>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    data_runtime().spawn(fut).await
+}
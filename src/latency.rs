@@ -0,0 +1,122 @@
+//! Round-trip times to ring neighbors.
+//!
+//! The gossip loop pings its next node with `NODE PING <seq> <sent_at_us>` and
+//! gets both values echoed back in the `PONG`, which gives one RTT sample per
+//! round. Samples are kept in a rolling window per neighbor; a neighbor whose
+//! pings keep coming back far slower than its usual RTT is reported `Suspect`
+//! before it ever fails a health check outright.
+
+use std::{
+    collections::VecDeque,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Samples kept per neighbor
+pub const RTT_WINDOW: usize = 32;
+
+/// Samples needed before spikes are judged
+const MIN_SAMPLES: usize = 4;
+
+/// A sample is a spike when it exceeds this many times the median RTT...
+const SPIKE_FACTOR: u32 = 4;
+
+/// ...and this floor, so jitter on sub-millisecond links is not a spike
+const SPIKE_FLOOR: Duration = Duration::from_millis(50);
+
+/// Consecutive spikes after which a neighbor is suspect
+pub const SUSPECT_AFTER: u32 = 3;
+
+/// Microseconds since the UNIX epoch, as carried by `NODE PING`
+pub fn now_micros() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_micros() as u64)
+}
+
+/// Checks a `PONG [<seq> <sent_at_us>]` reply to ping number `seq`.
+///
+/// Returns the round-trip time from the echoed timestamp, or `None` when the
+/// peer answered a bare `PONG`.
+pub fn pong_rtt(reply: &str, seq: u64) -> Result<Option<Duration>, String> {
+    let mut words = reply.split_whitespace();
+    if !words.next().is_some_and(|w| w.eq_ignore_ascii_case("PONG")) {
+        return Err("invalid PONG response".into());
+    }
+    match (words.next(), words.next()) {
+        (Some(echo_seq), Some(echo_at)) => {
+            if echo_seq.parse() != Ok(seq) {
+                return Err(format!("PONG echoed seq {} instead of {}", echo_seq, seq));
+            }
+            let echo_at: u64 = echo_at.parse().map_err(|_| "invalid PONG timestamp")?;
+            Ok(Some(Duration::from_micros(
+                now_micros().saturating_sub(echo_at),
+            )))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Rolling RTT samples for one neighbor
+#[derive(Debug, Clone, Default)]
+pub struct LatencyStats {
+    samples: VecDeque<Duration>,
+    /// Spikes in a row, reset by a normal sample
+    spikes: u32,
+}
+
+impl LatencyStats {
+    /// Adds a sample; returns whether it was a spike
+    pub fn record(&mut self, rtt: Duration) -> bool {
+        let spike = self.samples.len() >= MIN_SAMPLES
+            && rtt > (self.median() * SPIKE_FACTOR).max(SPIKE_FLOOR);
+        if spike {
+            self.spikes += 1;
+        } else {
+            self.spikes = 0;
+        }
+        if self.samples.len() == RTT_WINDOW {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(rtt);
+        spike
+    }
+
+    /// Whether the latest samples were sustained spikes
+    pub fn is_suspect(&self) -> bool {
+        self.spikes >= SUSPECT_AFTER
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    pub fn last(&self) -> Duration {
+        self.samples.back().copied().unwrap_or_default()
+    }
+
+    pub fn min(&self) -> Duration {
+        self.samples.iter().min().copied().unwrap_or_default()
+    }
+
+    pub fn max(&self) -> Duration {
+        self.samples.iter().max().copied().unwrap_or_default()
+    }
+
+    pub fn mean(&self) -> Duration {
+        if self.samples.is_empty() {
+            return Duration::ZERO;
+        }
+        self.samples.iter().sum::<Duration>() / self.samples.len() as u32
+    }
+
+    /// Median of the window; unlike the mean, a few spikes do not move it
+    pub fn median(&self) -> Duration {
+        let mut sorted: Vec<_> = self.samples.iter().copied().collect();
+        sorted.sort_unstable();
+        sorted.get(sorted.len() / 2).copied().unwrap_or_default()
+    }
+}
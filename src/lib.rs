@@ -1,11 +1,70 @@
+pub mod addr;
+pub mod alert;
+pub mod auth;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+pub mod builder;
+pub mod bulk;
+pub mod cache;
+pub mod checksum;
+pub mod client;
+pub mod compat;
+pub mod compression;
+pub mod config;
+pub mod daemon;
+pub mod delta;
+pub mod disk;
+pub mod event;
+pub mod fanout;
 pub mod gateway;
+pub mod gossip;
+pub mod health;
+pub mod heartbeat;
+pub mod identity;
+pub mod inventory;
+pub mod lane;
+pub mod latency;
+pub mod limit;
+pub mod logging;
+pub mod logs;
+pub mod manifest;
+pub mod migrate;
+pub mod net;
 pub mod node;
 pub mod node_status;
+pub mod openapi;
+pub mod output;
+pub mod pack;
+pub mod partition;
 pub mod protocol;
+pub mod proxy;
+pub mod pull;
+pub mod reconcile;
+pub mod relay;
+pub mod ring_state;
+pub mod ring_verify;
+pub mod routing;
+pub mod schema;
+pub mod secrets;
 pub mod server;
+pub mod staging;
+pub mod stats;
+pub mod stream;
+pub mod time;
+pub mod trace;
+pub mod transfer;
+pub mod usage;
+pub mod verify;
+pub mod watch;
 
+pub use addr::NodeAddr;
+pub use builder::{NodeBuilder, NodeHandle};
+pub use client::{Client, RetryPolicy};
+pub use config::NodeConfig;
+pub use event::{NodeEvent, StampedEvent};
 pub use gateway::Gateway;
+pub use identity::NodeId;
 pub use node::Node;
-pub use node_status::NodeStatus;
+pub use node_status::{NodeLoad, NodeStatus};
 pub use protocol::{Command, parse_line};
 pub use server::run;
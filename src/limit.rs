@@ -0,0 +1,237 @@
+//! Per-source rate limits and bans on the node listener.
+//!
+//! Every source IP gets three token buckets: one for new connections, one
+//! for commands and one for protocol errors. A connection or command that
+//! finds its bucket empty is refused with `ERR RATE-LIMITED`; a command that
+//! walks the ring or asks every node (see [`crate::Command::is_expensive`])
+//! takes [`EXPENSIVE_COST`] tokens instead of one. A source that empties its
+//! error bucket (lines that do not parse, internal commands without
+//! `NODE AUTH`) is banned: its connections are closed with `ERR BANNED` until
+//! the ban ends.
+//!
+//! The ring's own traffic is never limited: internal commands, and every
+//! command of a connection that sent the cluster token, are exempt. Where a
+//! source comes from exempts nothing, as clients may share the ring's host.
+//! A connection is charged at its first other command rather than when it is
+//! accepted, since only then is it known not to be a peer's.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Tokens a ring-wide command takes from the command bucket
+pub const EXPENSIVE_COST: u32 = 10;
+
+/// Window the `ban-errors` count applies to
+pub const ERROR_WINDOW: Duration = Duration::from_secs(60);
+
+/// Sources tracked before idle ones are forgotten
+const MAX_SOURCES: usize = 4096;
+
+/// Limits applied to every source, from the `conn-rate`, `command-rate`,
+/// `ban-errors` and `ban-time` settings. A zero rate or count disables that
+/// limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimits {
+    /// New connections per second
+    pub conn_rate: u32,
+    /// Commands per second; a burst of one second's worth is allowed
+    pub command_rate: u32,
+    /// Protocol errors allowed within [`ERROR_WINDOW`] before a source is banned
+    pub ban_errors: u32,
+    /// How long a ban lasts
+    pub ban_time: Duration,
+}
+
+/// Why a connection or command was turned away
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Refusal {
+    /// The source is banned for this long still
+    Banned(Duration),
+    /// The bucket refills enough in this long
+    RateLimited(Duration),
+}
+
+impl fmt::Display for Refusal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Banned(left) => write!(f, "BANNED for {}s", left.as_secs().max(1)),
+            Self::RateLimited(wait) => {
+                write!(f, "RATE-LIMITED retry in {}ms", wait.as_millis().max(1))
+            }
+        }
+    }
+}
+
+/// Token bucket holding up to `rate` tokens, refilled at `rate` per `per`
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    at: Instant,
+}
+
+impl Bucket {
+    fn full(rate: u32, now: Instant) -> Self {
+        Self {
+            tokens: rate as f64,
+            at: now,
+        }
+    }
+
+    /// Takes `cost` tokens, or says how long until there are enough
+    fn take(&mut self, cost: u32, rate: u32, per: Duration, now: Instant) -> Result<(), Duration> {
+        let capacity = rate as f64;
+        let refill = capacity / per.as_secs_f64();
+        let elapsed = now.saturating_duration_since(self.at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * refill).min(capacity);
+        self.at = now;
+        // A cost above the capacity could never be paid: take it from a full bucket
+        let cost = (cost as f64).min(capacity);
+        if self.tokens >= cost {
+            self.tokens -= cost;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((cost - self.tokens) / refill))
+        }
+    }
+}
+
+/// What is known of one source IP
+#[derive(Debug)]
+struct Source {
+    connections: Bucket,
+    commands: Bucket,
+    errors: Bucket,
+    banned_until: Option<Instant>,
+    seen: Instant,
+}
+
+impl Source {
+    fn new(limits: &RateLimits, now: Instant) -> Self {
+        Self {
+            connections: Bucket::full(limits.conn_rate, now),
+            commands: Bucket::full(limits.command_rate, now),
+            errors: Bucket::full(limits.ban_errors, now),
+            banned_until: None,
+            seen: now,
+        }
+    }
+
+    fn banned(&self, now: Instant) -> Option<Duration> {
+        self.banned_until
+            .filter(|until| *until > now)
+            .map(|until| until - now)
+    }
+}
+
+/// Rate limits and bans of every source a node hears from
+#[derive(Debug)]
+pub struct Limiter {
+    limits: Mutex<RateLimits>,
+    sources: Mutex<HashMap<IpAddr, Source>>,
+}
+
+impl Limiter {
+    pub fn new(limits: RateLimits) -> Self {
+        Self {
+            limits: Mutex::new(limits),
+            sources: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Applies changed settings; sources keep their buckets and bans
+    pub fn set_limits(&self, limits: RateLimits) {
+        *self.limits.lock().unwrap_or_else(|e| e.into_inner()) = limits;
+    }
+
+    pub fn limits(&self) -> RateLimits {
+        *self.limits.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Admits a new connection from `ip`
+    pub fn connect(&self, ip: IpAddr) -> Result<(), Refusal> {
+        let limits = self.limits();
+        self.with_source(ip, &limits, |source, now| {
+            if let Some(left) = source.banned(now) {
+                return Err(Refusal::Banned(left));
+            }
+            if limits.conn_rate == 0 {
+                return Ok(());
+            }
+            let second = Duration::from_secs(1);
+            source
+                .connections
+                .take(1, limits.conn_rate, second, now)
+                .map_err(Refusal::RateLimited)
+        })
+    }
+
+    /// Charges a command costing `cost` tokens to `ip`
+    pub fn command(&self, ip: IpAddr, cost: u32) -> Result<(), Refusal> {
+        let limits = self.limits();
+        self.with_source(ip, &limits, |source, now| {
+            if let Some(left) = source.banned(now) {
+                return Err(Refusal::Banned(left));
+            }
+            if limits.command_rate == 0 {
+                return Ok(());
+            }
+            let second = Duration::from_secs(1);
+            source
+                .commands
+                .take(cost, limits.command_rate, second, now)
+                .map_err(Refusal::RateLimited)
+        })
+    }
+
+    /// Counts a protocol error against `ip`; the ban's length when this one
+    /// got the source banned
+    pub fn protocol_error(&self, ip: IpAddr) -> Option<Duration> {
+        let limits = self.limits();
+        if limits.ban_errors == 0 {
+            return None;
+        }
+        self.with_source(ip, &limits, |source, now| {
+            if source.banned(now).is_some() {
+                return None;
+            }
+            if source
+                .errors
+                .take(1, limits.ban_errors, ERROR_WINDOW, now)
+                .is_ok()
+            {
+                return None;
+            }
+            source.banned_until = Some(now + limits.ban_time);
+            // A lifted ban starts over with a clean record
+            source.errors = Bucket::full(limits.ban_errors, now);
+            Some(limits.ban_time)
+        })
+    }
+
+    fn with_source<T>(
+        &self,
+        ip: IpAddr,
+        limits: &RateLimits,
+        f: impl FnOnce(&mut Source, Instant) -> T,
+    ) -> T {
+        let now = Instant::now();
+        let mut sources = self.sources.lock().unwrap_or_else(|e| e.into_inner());
+        if sources.len() >= MAX_SOURCES && !sources.contains_key(&ip) {
+            // Sources quiet for a while have full buckets again: nothing to keep
+            sources.retain(|_, source| {
+                source.banned(now).is_some()
+                    || now.saturating_duration_since(source.seen) < ERROR_WINDOW
+            });
+        }
+        let source = sources
+            .entry(ip)
+            .or_insert_with(|| Source::new(limits, now));
+        source.seen = now;
+        f(source, now)
+    }
+}
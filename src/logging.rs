@@ -0,0 +1,377 @@
+//! Log output: the tracing subscriber of the `ouroboros_fs` binary.
+//!
+//! Logs go to stdout, or to a file rotated by size, as plain text or as one
+//! JSON object per line (for Loki, ELK and the like). A `{port}` in the log
+//! file path is replaced by the node's port, so every node of a ring can
+//! write its own file; `set-network` hands its nodes such a path. The last
+//! lines are also kept in memory, for `NODE LOG TAIL` and `NODE LOG FOLLOW`.
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex, MutexGuard},
+};
+use tokio::sync::broadcast;
+use tracing_subscriber::{
+    EnvFilter, Layer, Registry, fmt::MakeWriter, layer::Layered, prelude::*, reload,
+};
+
+/// Placeholder for the node's port in log file paths
+pub const PORT_PLACEHOLDER: &str = "{port}";
+
+/// Handle that swaps the subscriber's filter at runtime (`log-filter`)
+pub type FilterHandle = reload::Handle<EnvFilter, Registry>;
+
+/// How log lines are written
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human-readable lines
+    #[default]
+    Text,
+    /// One JSON object per event
+    Json,
+}
+
+impl fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            LogFormat::Text => "text",
+            LogFormat::Json => "json",
+        })
+    }
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            other => Err(format!(
+                "invalid log format '{}': expected text or json",
+                other
+            )),
+        }
+    }
+}
+
+/// Where and how a process logs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogOptions {
+    pub format: LogFormat,
+    /// Log file, `{port}` standing for the node's port. `None` logs to stdout.
+    pub file: Option<PathBuf>,
+    /// Size (bytes) at which the log file is rotated. 0 never rotates.
+    pub max_size: u64,
+    /// Rotated files kept next to the log file (`<file>.1` is the newest)
+    pub max_files: u32,
+}
+
+impl Default for LogOptions {
+    fn default() -> Self {
+        Self {
+            format: LogFormat::Text,
+            file: None,
+            max_size: 10_000_000,
+            max_files: 5,
+        }
+    }
+}
+
+impl LogOptions {
+    /// Same options as `run` arguments, for spawned nodes
+    pub fn cli_args(&self) -> Vec<String> {
+        let mut args = vec!["--log-format".to_string(), self.format.to_string()];
+        if let Some(file) = &self.file {
+            args.extend([
+                "--log-file".to_string(),
+                file.display().to_string(),
+                "--log-max-size".to_string(),
+                self.max_size.to_string(),
+                "--log-max-files".to_string(),
+                self.max_files.to_string(),
+            ]);
+        }
+        args
+    }
+
+    /// Options for the nodes of a ring: a log file path without `{port}`
+    /// gets one before its extension (`ring.log` -> `ring.{port}.log`)
+    pub fn per_node(&self) -> LogOptions {
+        let file = self.file.as_ref().map(|file| {
+            if file.to_string_lossy().contains(PORT_PLACEHOLDER) {
+                return file.clone();
+            }
+            let stem = file.file_stem().unwrap_or_default().to_string_lossy();
+            let name = match file.extension() {
+                Some(ext) => format!("{}.{}.{}", stem, PORT_PLACEHOLDER, ext.to_string_lossy()),
+                None => format!("{}.{}", stem, PORT_PLACEHOLDER),
+            };
+            file.with_file_name(name)
+        });
+        LogOptions {
+            file,
+            ..self.clone()
+        }
+    }
+
+    /// The log file of the process labelled `port`, if logging to a file
+    pub fn file_for(&self, port: &str) -> Option<PathBuf> {
+        self.file
+            .as_ref()
+            .map(|file| PathBuf::from(file.to_string_lossy().replace(PORT_PLACEHOLDER, port)))
+    }
+}
+
+/// Lines kept by the [`LogBuffer`] of a process
+pub const LOG_BUFFER_LINES: usize = 1000;
+
+/// Registry with the reloadable filter, which every output layer sits on
+type Filtered = Layered<reload::Layer<EnvFilter, Registry>, Registry>;
+
+/// Installs the global tracing subscriber: `RUST_LOG` filter, then `opts`'
+/// format and output. `port` fills the log file path's `{port}`. Events are
+/// also kept in the returned [`LogBuffer`], formatted the same way.
+pub fn init(opts: &LogOptions, port: &str) -> io::Result<(FilterHandle, LogBuffer)> {
+    let (filter, handle) = reload::Layer::new(EnvFilter::from_default_env());
+    let output = match opts.file_for(port) {
+        Some(path) => {
+            let file = RotatingFile::open(path, opts.max_size, opts.max_files)?;
+            output_layer(opts.format, file, false)
+        }
+        None => output_layer(opts.format, io::stdout, true),
+    };
+    let buffer = LogBuffer::new(LOG_BUFFER_LINES);
+    let kept = output_layer(opts.format, buffer.clone(), false);
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(vec![output, kept])
+        .init();
+    Ok((handle, buffer))
+}
+
+/// Formatting layer writing `format` lines to `writer`
+fn output_layer<W>(
+    format: LogFormat,
+    writer: W,
+    ansi: bool,
+) -> Box<dyn Layer<Filtered> + Send + Sync>
+where
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let timer = tracing_subscriber::fmt::time::UtcTime::rfc_3339();
+    match format {
+        LogFormat::Text => {
+            let layer = tracing_subscriber::fmt::layer()
+                .with_timer(timer)
+                .with_target(true)
+                .with_writer(writer);
+            if ansi {
+                layer.boxed()
+            } else {
+                layer.with_ansi(false).boxed()
+            }
+        }
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .with_timer(timer)
+            .with_writer(writer)
+            .boxed(),
+    }
+}
+
+/// The last log lines of the process, behind `NODE LOG TAIL`, and a feed of
+/// new ones for `NODE LOG FOLLOW`
+#[derive(Debug, Clone)]
+pub struct LogBuffer {
+    inner: Arc<BufferInner>,
+}
+
+#[derive(Debug)]
+struct BufferInner {
+    lines: Mutex<VecDeque<String>>,
+    capacity: usize,
+    feed: broadcast::Sender<String>,
+}
+
+impl LogBuffer {
+    pub fn new(capacity: usize) -> Self {
+        let (feed, _) = broadcast::channel(capacity.max(1));
+        Self {
+            inner: Arc::new(BufferInner {
+                lines: Mutex::new(VecDeque::with_capacity(capacity)),
+                capacity,
+                feed,
+            }),
+        }
+    }
+
+    /// Keeps a line, dropping the oldest once full, and hands it to followers
+    pub fn push(&self, line: String) {
+        {
+            let mut lines = self.lines();
+            if lines.len() == self.inner.capacity {
+                lines.pop_front();
+            }
+            lines.push_back(line.clone());
+        }
+        // No followers is fine
+        let _ = self.inner.feed.send(line);
+    }
+
+    /// The last `n` lines, oldest first
+    pub fn tail(&self, n: usize) -> Vec<String> {
+        let lines = self.lines();
+        lines
+            .iter()
+            .skip(lines.len().saturating_sub(n))
+            .cloned()
+            .collect()
+    }
+
+    /// Lines pushed from now on
+    pub fn follow(&self) -> broadcast::Receiver<String> {
+        self.inner.feed.subscribe()
+    }
+
+    fn lines(&self) -> MutexGuard<'_, VecDeque<String>> {
+        self.inner.lines.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Collects one event's output, kept as lines once the event is written
+pub struct BufferWriter {
+    buffer: LogBuffer,
+    bytes: Vec<u8>,
+}
+
+impl Write for BufferWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.bytes.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for BufferWriter {
+    fn drop(&mut self) {
+        let text = String::from_utf8_lossy(&self.bytes);
+        for line in text.lines().filter(|l| !l.is_empty()) {
+            self.buffer.push(line.to_string());
+        }
+    }
+}
+
+impl<'a> MakeWriter<'a> for LogBuffer {
+    type Writer = BufferWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        BufferWriter {
+            buffer: self.clone(),
+            bytes: Vec::new(),
+        }
+    }
+}
+
+/// Log file that is rotated once it grows past a size: `<file>` moves to
+/// `<file>.1`, `<file>.1` to `<file>.2`, and so on up to the files kept.
+#[derive(Debug)]
+pub struct RotatingFile {
+    path: PathBuf,
+    max_size: u64,
+    max_files: u32,
+    inner: Mutex<Current>,
+}
+
+#[derive(Debug)]
+struct Current {
+    file: File,
+    written: u64,
+}
+
+impl RotatingFile {
+    /// Opens (appends to) the log file, creating its directory
+    pub fn open(path: PathBuf, max_size: u64, max_files: u32) -> io::Result<Self> {
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let file = append(&path)?;
+        let written = file.metadata()?.len();
+        Ok(Self {
+            path,
+            max_size,
+            max_files,
+            inner: Mutex::new(Current { file, written }),
+        })
+    }
+
+    fn rotated(&self, n: u32) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&self, current: &mut Current) -> io::Result<()> {
+        current.file.flush()?;
+        if self.max_files == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            for n in (1..self.max_files).rev() {
+                let from = self.rotated(n);
+                if from.exists() {
+                    fs::rename(&from, self.rotated(n + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated(1))?;
+        }
+        current.file = append(&self.path)?;
+        current.written = 0;
+        Ok(())
+    }
+}
+
+fn append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// Writer handed out per event; rotation happens between events
+pub struct RotatingWriter<'a> {
+    log: &'a RotatingFile,
+    current: MutexGuard<'a, Current>,
+}
+
+impl Write for RotatingWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let log = self.log;
+        if log.max_size > 0 && self.current.written > 0 && self.current.written >= log.max_size {
+            log.rotate(&mut self.current)?;
+        }
+        let n = self.current.file.write(buf)?;
+        self.current.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.current.file.flush()
+    }
+}
+
+impl<'a> MakeWriter<'a> for RotatingFile {
+    type Writer = RotatingWriter<'a>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RotatingWriter {
+            log: self,
+            current: self.inner.lock().unwrap_or_else(|e| e.into_inner()),
+        }
+    }
+}
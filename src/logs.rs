@@ -0,0 +1,283 @@
+//! `ouroboros_fs logs`: one view over the log files of a ring's nodes.
+//!
+//! The files are found from the `--log-file` path given to `set-network`
+//! (see [`crate::logging`]): every file the path names for a port, plus
+//! set-network's own. Their last lines are printed merged by timestamp, each
+//! prefixed with the node's port; with `follow`, new lines are printed as
+//! they are written, following rotations and nodes that start later.
+
+use crate::{
+    logging::{LogOptions, PORT_PLACEHOLDER},
+    output::OutputFormat,
+};
+use serde_json::json;
+use std::{
+    collections::{HashMap, hash_map::Entry},
+    fs::{self, File},
+    io::{self, BufRead, BufReader, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tracing::Level;
+
+/// How often followed files are checked for new lines
+const FOLLOW_POLL: Duration = Duration::from_millis(250);
+
+/// Label of set-network's own log file
+const NETWORK_LABEL: &str = "network";
+
+/// What to show
+#[derive(Debug, Clone)]
+pub struct LogsQuery {
+    /// `--log-file` path the ring was started with
+    pub path: PathBuf,
+    /// Only this node's file
+    pub node: Option<u16>,
+    /// Lines of history printed per file
+    pub lines: usize,
+    /// Only events at least this severe
+    pub level: Option<Level>,
+    /// Keep printing lines as they are written
+    pub follow: bool,
+    /// How lines are printed
+    pub output: OutputFormat,
+}
+
+/// Log files of the ring `path` was given to, by label (port, or `network`)
+pub fn log_files(path: &Path, node: Option<u16>) -> io::Result<Vec<(String, PathBuf)>> {
+    let opts = LogOptions {
+        file: Some(path.to_path_buf()),
+        ..LogOptions::default()
+    };
+    let mut files = Vec::new();
+    if node.is_none()
+        && let Some(own) = opts.file_for(NETWORK_LABEL).filter(|p| p.is_file())
+    {
+        files.push((NETWORK_LABEL.to_string(), own));
+    }
+
+    let template = opts.per_node().file.unwrap_or_default();
+    let name = template
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned();
+    let Some((prefix, suffix)) = name.split_once(PORT_PLACEHOLDER) else {
+        return Err(io::Error::other(format!(
+            "{} must be in the log file name",
+            PORT_PLACEHOLDER
+        )));
+    };
+    let dir = match template.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let mut nodes = Vec::new();
+    let entries = fs::read_dir(&dir)
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", dir.display(), e)))?;
+    for entry in entries {
+        let entry = entry?;
+        let file_name = entry.file_name().to_string_lossy().into_owned();
+        let port = file_name
+            .strip_prefix(prefix)
+            .and_then(|rest| rest.strip_suffix(suffix))
+            .and_then(|port| port.parse::<u16>().ok());
+        match port {
+            Some(port) if node.is_none_or(|n| n == port) => nodes.push((port, entry.path())),
+            _ => {}
+        }
+    }
+    nodes.sort();
+    files.extend(nodes.into_iter().map(|(port, p)| (port.to_string(), p)));
+    Ok(files)
+}
+
+/// Level of a log line, text (`<timestamp>  WARN ...`) or JSON (`"level":"WARN"`)
+pub fn line_level(line: &str) -> Option<Level> {
+    if line.starts_with('{') {
+        let value: serde_json::Value = serde_json::from_str(line).ok()?;
+        return value.get("level")?.as_str()?.parse().ok();
+    }
+    line.split_whitespace()
+        .take(2)
+        .find_map(|token| token.parse().ok())
+}
+
+/// Timestamp a line starts with (text) or carries (JSON), for merging files
+fn line_timestamp(line: &str) -> String {
+    if line.starts_with('{') {
+        let value: serde_json::Value = serde_json::from_str(line).unwrap_or_default();
+        return value
+            .get("timestamp")
+            .and_then(|t| t.as_str())
+            .unwrap_or_default()
+            .to_string();
+    }
+    line.split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Keeps the lines at least as severe as `level`; lines without a level
+/// (continuations) go with the line before them
+struct LevelFilter {
+    level: Option<Level>,
+    last: bool,
+}
+
+impl LevelFilter {
+    fn new(level: Option<Level>) -> Self {
+        Self { level, last: true }
+    }
+
+    fn keep(&mut self, line: &str) -> bool {
+        let Some(level) = self.level else {
+            return true;
+        };
+        if let Some(line_level) = line_level(line) {
+            self.last = line_level <= level;
+        }
+        self.last
+    }
+}
+
+/// A followed log file
+struct Tailed {
+    label: String,
+    path: PathBuf,
+    reader: BufReader<File>,
+    filter: LevelFilter,
+    /// A line read before its newline was written
+    partial: String,
+}
+
+impl Tailed {
+    /// Opens `path` positioned at its end
+    fn open(label: String, path: PathBuf, level: Option<Level>) -> io::Result<Self> {
+        let mut file = File::open(&path)?;
+        file.seek(SeekFrom::End(0))?;
+        Ok(Self {
+            label,
+            path,
+            reader: BufReader::new(file),
+            filter: LevelFilter::new(level),
+            partial: String::new(),
+        })
+    }
+
+    /// Prints the lines written since the last call, finishing a rotated
+    /// file before moving on to its replacement
+    fn poll(&mut self, output: OutputFormat) -> io::Result<()> {
+        self.drain(output)?;
+        if self.rotated()? {
+            self.reader = BufReader::new(File::open(&self.path)?);
+            self.partial.clear();
+            self.drain(output)?;
+        }
+        Ok(())
+    }
+
+    fn drain(&mut self, output: OutputFormat) -> io::Result<()> {
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Ok(());
+            }
+            if !line.ends_with('\n') {
+                self.partial.push_str(&line);
+                return Ok(());
+            }
+            let line = std::mem::take(&mut self.partial) + line.trim_end_matches(['\r', '\n']);
+            if self.filter.keep(&line) {
+                print_line(output, &self.label, &line);
+            }
+        }
+    }
+
+    /// Whether the path now names another file (rotated) or the file shrank
+    fn rotated(&mut self) -> io::Result<bool> {
+        let Ok(current) = fs::metadata(&self.path) else {
+            return Ok(false); // Between the rename and the new file
+        };
+        let open = self.reader.get_ref().metadata()?;
+        let position = self.reader.stream_position()?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            if current.ino() != open.ino() || current.dev() != open.dev() {
+                return Ok(true);
+            }
+        }
+        Ok(current.len() < position || open.len() < position)
+    }
+}
+
+/// Prints `[<label>] <line>`
+fn print_line(output: OutputFormat, label: &str, line: &str) {
+    output.emit(
+        "log",
+        format!("[{}] {}", label, line),
+        &json!({"node": label, "line": line}),
+    );
+}
+
+/// Last `n` lines of a file that pass `level`
+fn last_lines(path: &Path, n: usize, level: Option<Level>) -> io::Result<Vec<String>> {
+    let mut filter = LevelFilter::new(level);
+    let mut lines: Vec<String> = BufReader::new(File::open(path)?)
+        .lines()
+        .collect::<io::Result<Vec<_>>>()?
+        .into_iter()
+        .filter(|line| filter.keep(line))
+        .collect();
+    Ok(lines.split_off(lines.len().saturating_sub(n)))
+}
+
+/// Prints the ring's logs as `[<port>] <line>`: history first, merged by
+/// timestamp, then (with `follow`) new lines until the process is stopped.
+pub async fn show(query: &LogsQuery) -> io::Result<()> {
+    let files = log_files(&query.path, query.node)?;
+    if files.is_empty() && !query.follow {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no log files found for {}", query.path.display()),
+        ));
+    }
+
+    let mut history = Vec::new();
+    for (label, path) in &files {
+        for line in last_lines(path, query.lines, query.level)? {
+            history.push((line_timestamp(&line), label.clone(), line));
+        }
+    }
+    history.sort_by(|a, b| a.0.cmp(&b.0));
+    for (_, label, line) in history {
+        print_line(query.output, &label, &line);
+    }
+    if !query.follow {
+        return Ok(());
+    }
+
+    let mut tailed: HashMap<PathBuf, Tailed> = HashMap::new();
+    for (label, path) in files {
+        let tail = Tailed::open(label, path.clone(), query.level)?;
+        tailed.insert(path, tail);
+    }
+    loop {
+        tokio::time::sleep(FOLLOW_POLL).await;
+        // Nodes started (or respawned) since are followed from their first line
+        for (label, path) in log_files(&query.path, query.node)? {
+            if let Entry::Vacant(entry) = tailed.entry(path.clone()) {
+                let mut tail = Tailed::open(label, path, query.level)?;
+                tail.reader.seek(SeekFrom::Start(0))?;
+                entry.insert(tail);
+            }
+        }
+        for tail in tailed.values_mut() {
+            if let Err(e) = tail.poll(query.output) {
+                tracing::warn!(file = %tail.path.display(), error = %e, "Cannot read log file");
+            }
+        }
+    }
+}
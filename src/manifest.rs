@@ -0,0 +1,57 @@
+//! Per-node chunk manifest.
+//!
+//! Every chunk a node stores gets a small sidecar file recording its SHA-256
+//! and size, computed while the chunk was written:
+//!
+//! ```text
+//! <data_dir>/<port>/manifest/content/<chunk-name>
+//! <data_dir>/<port>/manifest/backup/<chunk-name>
+//! ```
+//!
+//! Each file holds a single `<sha256-hex> <size>` line. Verification reads
+//! these entries instead of trusting whatever happens to be on disk.
+
+use crate::checksum::Digest;
+use std::{fmt, io, path::Path, str::FromStr};
+use tokio::fs;
+
+/// Manifest record for one stored chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkEntry {
+    pub sha256: Digest,
+    pub size: u64,
+}
+
+impl fmt::Display for ChunkEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.sha256, self.size)
+    }
+}
+
+impl FromStr for ChunkEntry {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut it = s.split_whitespace();
+        let (Some(hash), Some(size), None) = (it.next(), it.next(), it.next()) else {
+            return Err(format!("invalid manifest entry '{}'", s.trim()));
+        };
+        Ok(ChunkEntry {
+            sha256: hash.parse()?,
+            size: size
+                .parse()
+                .map_err(|_| format!("invalid size in manifest entry '{}'", s.trim()))?,
+        })
+    }
+}
+
+/// Writes the entry for `name` into `dir` (a `manifest/<subdir>` directory)
+pub async fn record(dir: &Path, name: &str, entry: ChunkEntry) -> io::Result<()> {
+    fs::write(dir.join(name), format!("{}\n", entry)).await
+}
+
+/// Reads the entry for `name`, or `None` if the chunk has no (valid) entry
+pub async fn lookup(dir: &Path, name: &str) -> Option<ChunkEntry> {
+    let text = fs::read_to_string(dir.join(name)).await.ok()?;
+    text.parse().ok()
+}
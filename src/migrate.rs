@@ -0,0 +1,271 @@
+//! Chunk manifests for files whose tag names no holders.
+//!
+//! A file tag names the node holding each chunk (see `FileTag::holders`), so
+//! a topology change does not move where pulls look for them. Files pushed by
+//! older nodes, or relayed through one, have tags without holders: their
+//! chunks were left on consecutive ring nodes from the start node, and older
+//! leaders moved them as the ring changed. After every topology change, the
+//! leader looks each chunk of such files up on the live nodes (`FILE
+//! STAT-CHUNK`) and records the nodes holding them in the tag, which it sends
+//! to every node. Until then, nodes reading such a file look its chunks up
+//! the same way.
+//!
+//! Chunks stored before names were percent-escaped on disk are also renamed
+//! here, from the name the old `_` escape gave them to the one they are now
+//! looked up by: at startup for the saved tags, then for the tags peers send.
+
+use crate::{
+    NodeId, NodeStatus,
+    disk::IoClass,
+    node::{FileTag, Node},
+    pack, ring_state,
+    schema::FileTags,
+    server::{self, chunk_file_name, sanitize_filename},
+};
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fmt,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::fs;
+
+type AnyErr = Box<dyn Error + Send + Sync>;
+
+/// Pause after a topology change before looking chunks up, so the new map
+/// reaches every node and quick successive changes are handled once
+const MIGRATION_SETTLE: Duration = Duration::from_secs(2);
+
+/// Outcome of one migration pass
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Migration {
+    /// Files without holders looked up
+    pub checked: u64,
+    /// Files whose holders were all found, and recorded in their tag
+    pub located: u64,
+    /// Files with a chunk no live node holds
+    pub failed: u64,
+}
+
+/// `MIGRATED checked=<n> located=<n> failed=<n>`
+impl fmt::Display for Migration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "MIGRATED checked={} located={} failed={}",
+            self.checked, self.located, self.failed
+        )
+    }
+}
+
+/// Waits for topology changes and, on the leader, records the holders of
+/// files whose tag names none.
+pub(crate) async fn spawn_migration_loop(node: Arc<Node>) {
+    loop {
+        node.rewired().await;
+        tokio::time::sleep(MIGRATION_SETTLE).await;
+        if !node.is_leader() {
+            continue;
+        }
+        let migration = migrate_chunks(&node).await;
+        if migration.located > 0 || migration.failed > 0 {
+            tracing::info!(
+                node = %node.port,
+                checked = migration.checked,
+                located = migration.located,
+                failed = migration.failed,
+                "Chunk migration finished"
+            );
+        }
+    }
+}
+
+/// Looks up the chunks of every file without holders and records the nodes
+/// holding them in its tag, sent to every node.
+pub(crate) async fn migrate_chunks(node: &Arc<Node>) -> Migration {
+    let mut migration = Migration::default();
+    // Copies share their source's chunks: each chunk set is looked up once
+    let mut sets: HashMap<String, Option<Vec<NodeId>>> = HashMap::new();
+    for (name, mut tag) in node.get_file_tags_entries().await.0 {
+        // Federated files have no chunks here
+        if tag.parts == 0 || !tag.holders.is_empty() || tag.ring.is_some() {
+            continue;
+        }
+        migration.checked += 1;
+        let set = tag.chunk_set(&name).to_string();
+        let holders = match sets.get(&set) {
+            Some(holders) => holders.clone(),
+            None => {
+                let mut ids = Vec::with_capacity(tag.parts as usize);
+                for port in locate_chunks(node, &set, &tag).await {
+                    match port {
+                        Some(port) => ids.push(node.peer_id(&port).await),
+                        None => break,
+                    }
+                }
+                let holders = (ids.len() == tag.parts as usize).then_some(ids);
+                sets.insert(set, holders.clone());
+                holders
+            }
+        };
+        let Some(holders) = holders else {
+            tracing::warn!(node = %node.port, file = %name, "No live node holds every chunk of file, holders not recorded");
+            migration.failed += 1;
+            continue;
+        };
+        if let Some(port) = node.port_of(holders[0]).await
+            && let Ok(start) = port.parse()
+        {
+            tag.start = start;
+        }
+        tag.holders = holders;
+        let tag = node.stamp_file_tag(&name, tag, None).await;
+        node.insert_file_tag(&name, tag.clone()).await;
+        node.broadcast_file_tag(&name, &tag).await;
+        tracing::info!(node = %node.port, file = %name, "Recorded chunk holders of file");
+        migration.located += 1;
+    }
+    migration
+}
+
+/// Port of a live node with a full content copy of each chunk of the chunk
+/// set `chunk_set` (tagged with `tag`), asked in turn (`FILE STAT-CHUNK`):
+/// `None` for a chunk none of them has.
+pub(crate) async fn locate_chunks(
+    node: &Node,
+    chunk_set: &str,
+    tag: &FileTag,
+) -> Vec<Option<String>> {
+    let mut ports: Vec<String> = node
+        .netmap_statuses()
+        .await
+        .into_iter()
+        .filter(|(_, status)| *status != NodeStatus::Dead)
+        .map(|(port, _)| port)
+        .collect();
+    ports.sort();
+    let mut holders = Vec::with_capacity(tag.parts as usize);
+    for i in 0..tag.parts {
+        let chunk_name = chunk_file_name(chunk_set, i, tag.parts);
+        let size = ring_state::fair_chunk_len(i, tag.size, tag.parts);
+        let mut holder = None;
+        for port in &ports {
+            match server::stat_chunk_on(node, &node.peer_addr(port), &chunk_name).await {
+                Ok((Some(stored), _)) if stored == size => {
+                    holder = Some(port.clone());
+                    break;
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    tracing::debug!(node = %node.port, chunk = %chunk_name, peer = %port, error = %e, "Cannot look chunk up");
+                }
+            }
+        }
+        holders.push(holder);
+    }
+    holders
+}
+
+/* --- LEGACY DISK NAMES --- */
+
+/// Disk name chunk `chunk_name` had before names were percent-escaped on
+/// disk (see [`sanitize_filename`]): unsafe characters became `_`
+fn legacy_disk_name(chunk_name: &str) -> String {
+    chunk_name
+        .chars()
+        .map(|ch| match ch {
+            '/' | '\\' | '\0' | ':' | '|' | ';' | '\n' | '\r' => '_',
+            ch => ch,
+        })
+        .collect()
+}
+
+/// Renames the chunks of `tags` still stored under their legacy disk name
+/// (see [`legacy_disk_name`]), with their manifest entries, to the name they
+/// are looked up by now. A legacy name that is also the current name of a
+/// known chunk is left alone. Returns how many chunks were renamed.
+pub(crate) async fn rename_legacy_chunks(node: &Node, tags: &FileTags) -> u64 {
+    let mut renames = Vec::new();
+    for (name, tag) in tags.0.iter().filter(|(_, tag)| tag.ring.is_none()) {
+        for i in 0..tag.parts {
+            let chunk = chunk_file_name(tag.chunk_set(name), i, tag.parts);
+            let (legacy, current) = (legacy_disk_name(&chunk), sanitize_filename(&chunk));
+            if legacy != current {
+                renames.push((chunk, legacy, current));
+            }
+        }
+    }
+    if renames.is_empty() {
+        return 0;
+    }
+
+    let mut taken = HashSet::new();
+    {
+        let known = node.file_tags.read().await;
+        let all = known.iter().chain(tags.0.iter());
+        for (name, tag) in all.filter(|(_, tag)| tag.ring.is_none()) {
+            for i in 0..tag.parts {
+                taken.insert(sanitize_filename(&chunk_file_name(
+                    tag.chunk_set(name),
+                    i,
+                    tag.parts,
+                )));
+            }
+        }
+    }
+    let mut renamed = 0;
+    for (chunk, legacy, current) in renames {
+        if taken.contains(&legacy) {
+            continue;
+        }
+        for subdir in ["content", "backup"] {
+            match rename_legacy_chunk(node, subdir, &chunk, &legacy, &current).await {
+                Ok(true) => renamed += 1,
+                Ok(false) => {}
+                Err(e) => {
+                    tracing::warn!(node = %node.port, chunk = %chunk, subdir, error = %e, "Could not rename chunk from its legacy disk name")
+                }
+            }
+        }
+    }
+    if renamed > 0 {
+        tracing::info!(node = %node.port, renamed, "Renamed chunks from their legacy disk names");
+    }
+    renamed
+}
+
+/// Moves chunk `chunk` of `subdir` from disk name `legacy` to `current`,
+/// loose or packed, unless it is already there. Returns whether it moved.
+async fn rename_legacy_chunk(
+    node: &Node,
+    subdir: &str,
+    chunk: &str,
+    legacy: &str,
+    current: &str,
+) -> Result<bool, AnyErr> {
+    if pack::size(node, subdir, current).await.is_some() {
+        return Ok(false);
+    }
+    let dir = node.data_dir.join(subdir);
+    let manifests = node.manifest_dir(subdir);
+    if fs::try_exists(dir.join(legacy)).await? {
+        node.chunk_cache.invalidate(subdir, legacy);
+        fs::rename(dir.join(legacy), dir.join(current)).await?;
+        let _ = fs::rename(manifests.join(legacy), manifests.join(current)).await;
+        return Ok(true);
+    }
+    if node.packs.of(subdir).locate(legacy).is_none() {
+        return Ok(false);
+    }
+    // Packed before its tag arrived: stored again, loose, under its name
+    let data = pack::read(node, IoClass::Scrub, subdir, legacy).await?;
+    server::save_into_node_dir(node, chunk, &data, subdir, IoClass::Scrub).await?;
+    {
+        let _files = node.packs.lock_files().await;
+        node.packs.of(subdir).remove(legacy);
+    }
+    node.chunk_cache.invalidate(subdir, legacy);
+    let _ = fs::remove_file(manifests.join(legacy)).await;
+    Ok(true)
+}
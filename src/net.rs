@@ -0,0 +1,57 @@
+//! Socket tuning shared by the listener and peer connections.
+
+use crate::config::TcpOptions;
+use socket2::{SockRef, TcpKeepalive};
+use std::io;
+use tokio::net::{TcpSocket, TcpStream, lookup_host};
+
+/// Applies the buffer sizes to a socket that is not yet bound or connected.
+///
+/// Buffer sizes must be set before `listen`/`connect` to affect the TCP window,
+/// and accepted connections inherit them from the listener.
+pub fn tune_socket(socket: &TcpSocket, opts: &TcpOptions) -> io::Result<()> {
+    if let Some(size) = opts.send_buffer {
+        socket.set_send_buffer_size(size)?;
+    }
+    if let Some(size) = opts.recv_buffer {
+        socket.set_recv_buffer_size(size)?;
+    }
+    Ok(())
+}
+
+/// Applies `TCP_NODELAY` and keepalive to a connected stream.
+pub fn tune_stream(stream: &TcpStream, opts: &TcpOptions) -> io::Result<()> {
+    stream.set_nodelay(opts.nodelay)?;
+    if let Some(idle) = opts.keepalive {
+        let keepalive = TcpKeepalive::new().with_time(idle);
+        SockRef::from(stream).set_tcp_keepalive(&keepalive)?;
+    }
+    Ok(())
+}
+
+/// Connects to `addr` (`host:port`) with the given options, trying every
+/// resolved address in turn.
+pub async fn connect(addr: &str, opts: &TcpOptions) -> io::Result<TcpStream> {
+    let mut last_err = None;
+    for sa in lookup_host(addr).await? {
+        let socket = if sa.is_ipv6() {
+            TcpSocket::new_v6()?
+        } else {
+            TcpSocket::new_v4()?
+        };
+        tune_socket(&socket, opts)?;
+        match socket.connect(sa).await {
+            Ok(stream) => {
+                tune_stream(&stream, opts)?;
+                return Ok(stream);
+            }
+            Err(e) => last_err = Some(e),
+        }
+    }
+    Err(last_err.unwrap_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("could not resolve '{}'", addr),
+        )
+    }))
+}
@@ -1,25 +1,230 @@
-use crate::NodeStatus;
-use serde::Serialize;
+pub use crate::addr::{host_str, port_str};
+use crate::{
+    NodeEvent, NodeId, NodeStatus,
+    addr::{NodeAddr, join_host_port},
+    auth::{AccessList, ClusterToken},
+    cache::ChunkCache,
+    checksum::Sha256,
+    compat::{Feature, Hello},
+    config::{DeathHooks, LogFilterHook, NodeConfig, RESPAWN_WINDOW, Settings, TcpOptions},
+    disk::DiskScheduler,
+    event::StampedEvent,
+    fanout::{self, BROADCAST_CONCURRENCY, Broadcast, Outboxes},
+    gossip::{self, Rumors},
+    identity::random_token,
+    inventory::{Inventory, TAGS_FILE},
+    latency::LatencyStats,
+    limit::Limiter,
+    logging::{LogBuffer, LogOptions},
+    net,
+    node_status::{LoadMeter, NodeLoad},
+    pack::PackStore,
+    partition::{Quorum, Reachability},
+    protocol, reconcile,
+    relay::{RELAY_LABEL, RelayHub, Route},
+    ring_state::{Hop, RingState, retry_hop, topology_is_newer},
+    schema::{
+        Federation, FederationLink, FileTags, Labels, Member, Netmap, TagVersion, Topology,
+        join_holders,
+    },
+    secrets::Secret,
+    time::{Clock, Timestamp},
+    transfer::{Transfer, TransferKind, TransferProgress},
+};
+use serde::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
+    path::PathBuf,
     sync::{
-        Arc,
-        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, OnceLock,
+        atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
     },
-    time::Duration,
+    time::{Duration, Instant},
 };
 use tokio::{
-    io::AsyncWriteExt,
+    fs,
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
     net::TcpStream,
-    sync::{RwLock, oneshot},
+    sync::{Notify, RwLock, broadcast, oneshot},
 };
 use tracing;
 
+/// How long a peer's reported load is trusted before asking again
+const PEER_LOAD_TTL: Duration = Duration::from_secs(1);
+
+/// How long to wait for a peer to answer `NODE LOAD`
+const PEER_LOAD_TIMEOUT: Duration = Duration::from_millis(300);
+
+/// How long to wait for a peer to take or answer `NODE PREV` / `NODE STATUS`
+const NEIGHBOR_QUERY_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// How long a chunk stream from another node waits for a `max-relays` slot
+/// before it is refused
+pub const RELAY_WAIT: Duration = Duration::from_secs(30);
+
+/// When a refused chunk stream's sender is told to try again
+pub const RELAY_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+/// One node of a [`NetmapView`]
+#[derive(Debug, Clone, Serialize)]
+pub struct NodeView {
+    pub port: String,
+    /// Persistent identity, when the node has announced it
+    pub id: Option<NodeId>,
+    /// Labels the node was started with (`--label`)
+    pub labels: Labels,
+    pub status: NodeStatus,
+    /// Times this node has seen the peer come back after being `Dead`
+    pub incarnation: u32,
+    /// Milliseconds since the status last changed
+    pub since_ms: u64,
+}
+
+/// `NETMAP GET JSON` document
+#[derive(Debug, Clone, Serialize)]
+pub struct NetmapView {
+    /// Port of the node reporting the map
+    pub node: String,
+    pub nodes: Vec<NodeView>,
+    /// Nodes of federated rings, for `NETMAP GET JSON FEDERATED`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub federated: Vec<FederatedRingView>,
+}
+
+/// The nodes of one federated ring, as reported through its link
+#[derive(Debug, Clone, Serialize)]
+pub struct FederatedRingView {
+    pub ring: String,
+    pub nodes: Vec<FederatedNodeView>,
+}
+
+/// One node of a [`FederatedRingView`]
+#[derive(Debug, Clone, Serialize)]
+pub struct FederatedNodeView {
+    pub port: String,
+    pub status: NodeStatus,
+}
+
+/// One `from -> to` edge of a [`TopologyView`]
+#[derive(Debug, Clone, Serialize)]
+pub struct EdgeView {
+    pub from: String,
+    pub to: String,
+    /// Identities of both ends, from the netmap
+    pub from_id: Option<NodeId>,
+    pub to_id: Option<NodeId>,
+    /// Milliseconds since the edge was last learned from a walk or `TOPOLOGY SET`
+    pub age_ms: u64,
+    /// Time the walk that learned the edge took to connect over it
+    pub latency_ms: Option<f64>,
+}
+
+/// `TOPOLOGY GET JSON` document
 #[derive(Debug, Clone, Serialize)]
+pub struct TopologyView {
+    /// Port of the node reporting the topology
+    pub node: String,
+    /// Epoch of the snapshot the edges come from (0 before any walk)
+    pub epoch: u64,
+    pub edges: Vec<EdgeView>,
+}
+
+/// One chunk of a [`FileManifestView`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChunkView {
+    pub index: u32,
+    /// Stored chunk name, as requested with `FILE GET-CHUNK`
+    pub name: String,
+    /// Position of the chunk's first byte in the file
+    pub offset: u64,
+    pub size: u64,
+    /// Port of the node holding the chunk
+    pub node: Option<String>,
+    /// SHA-256 recorded when the chunk was stored (lowercase hex), `None`
+    /// when neither its holder nor a backup holder reported it
+    pub sha256: Option<String>,
+}
+
+/// `FILE MANIFEST` document
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileManifestView {
+    pub name: String,
+    pub size: u64,
+    pub parts: u32,
+    pub chunks: Vec<ChunkView>,
+    /// Media type the file was pushed with, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+}
+
+impl FileManifestView {
+    /// Hash of the file's content: the SHA-256 of its chunk hashes, one
+    /// `<sha256> <size>` line each. `None` while some chunk's hash is unknown.
+    pub fn content_hash(&self) -> Option<String> {
+        let mut hasher = Sha256::new();
+        for chunk in &self.chunks {
+            let sha256 = chunk.sha256.as_deref()?;
+            hasher.update(format!("{} {}\n", sha256, chunk.size).as_bytes());
+        }
+        Some(hasher.finalize().to_string())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct FileTag {
     pub start: u16,
     pub size: u64,
     pub parts: u32,
+    /// Id of the node holding each chunk (the file's chunk manifest), recorded
+    /// when the push commits. Empty for files pushed by older nodes until the
+    /// leader finds their chunks (see [`crate::migrate`]).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub holders: Vec<NodeId>,
+    /// Federated ring storing the file, for `<ring>/<name>` files pushed
+    /// through a border node. Such tags hold no chunks of this ring.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ring: Option<String>,
+    /// Name the chunks are stored under, for files made with `FILE COPY`.
+    /// `None` when they are stored under the file's own name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunks: Option<String>,
+    /// For a version set aside when reconciling after a partition, the file
+    /// whose name it had (see [`crate::reconcile`])
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub conflict_of: Option<String>,
+    /// Who wrote this version of the file, and on top of which. `None` for
+    /// tags from older nodes, and on chunk holders until the writer's tag
+    /// reaches them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<TagVersion>,
+    /// Media type the file was pushed with (`text/plain`), sent back by the
+    /// gateway on pulls. `None` when the push named none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+}
+
+/// Separates a file's name from the token of a chunk set made by
+/// [`FileTag::fresh_chunk_set`]
+const FRESH_SET: &str = ".set-";
+
+impl FileTag {
+    /// Name the chunks of the file `name` (tagged with `self`) are stored under
+    pub fn chunk_set<'a>(&'a self, name: &'a str) -> &'a str {
+        self.chunks.as_deref().unwrap_or(name)
+    }
+
+    /// A chunk set for new chunks of the file `name` while another file still
+    /// reads the ones stored under `name` (see `FILE COPY`)
+    pub fn fresh_chunk_set(name: &str) -> String {
+        format!("{}{}{}", name, FRESH_SET, &random_token()[..16])
+    }
+
+    /// Whether `name` is a chunk set made by [`FileTag::fresh_chunk_set`]
+    pub fn is_fresh_chunk_set(name: &str) -> bool {
+        name.rsplit_once(FRESH_SET).is_some_and(|(_, token)| {
+            token.len() == 16 && token.bytes().all(|b| b.is_ascii_hexdigit())
+        })
+    }
 }
 
 /// Shared node state & actions.
@@ -29,147 +234,820 @@ pub struct FileTag {
 /// - FILE push also uses token->oneshot at the start node (to confirm loop).
 #[derive(Debug)]
 pub struct Node {
-    /// Where this node is listening
+    /// Where this node is listening, as text (`127.0.0.1:7000`, `[::1]:7000`)
     pub port: String,
 
+    /// Where this node is listening
+    pub addr: NodeAddr,
+
+    /// Persistent identity, kept in the data directory (see [`crate::identity`])
+    pub id: NodeId,
+
+    /// Labels announced in the netmap, matched by `FILE PUSH ... PLACE`
+    pub labels: Labels,
+
+    /// Label naming a node's failure domain, which backups are spread over
+    pub failure_domain: String,
+
+    /// Name of this ring among federated rings, if it has one
+    pub ring_id: Option<String>,
+
+    /// Ring node this one is reached through, if it cannot accept connections
+    pub relay: Option<String>,
+
+    /// Sessions of the nodes relayed through this one
+    pub(crate) relay_hub: RelayHub,
+
+    /// Secret proving this node belongs to the ring, sent to peers and
+    /// required from them (see [`crate::auth`])
+    pub(crate) cluster_token: Option<ClusterToken>,
+
+    /// Client tokens and their roles, when client commands are restricted
+    pub(crate) access: Option<AccessList>,
+
+    /// Links to federated rings, by ring id, shared by every node of the ring
+    federation: RwLock<Federation>,
+
+    /// Client tokens of the federated rings this node is the border to, by
+    /// ring id: kept here only, never sent to this ring's nodes
+    federation_tokens: RwLock<HashMap<String, Secret>>,
+
     /// Address of the next node in the ring, one until set via NODE NEXT
     pub next_port: RwLock<Option<String>>,
 
-    // WALK pending acks (start node only)
-    pending_walks: RwLock<HashMap<String, oneshot::Sender<String>>>,
-    walk_counter: AtomicU64,
+    /// Address of the previous node in the ring, as announced by it via NODE PREV
+    pub prev_port: RwLock<Option<String>>,
+
+    // WALK pending acks (start node only). Walk tokens are random and only
+    // live here while a walk started on this node runs, so a DONE with a
+    // forged, replayed or late token finds nothing to complete.
+    pending_walks: RwLock<HashMap<String, oneshot::Sender<Topology>>>,
 
     // HEAL pending acks (start node only)
     pending_heals: RwLock<HashMap<String, oneshot::Sender<()>>>,
 
+    // NETMAP DISCOVER pending acks (start node only)
+    pending_discovers: RwLock<HashMap<String, oneshot::Sender<Netmap>>>,
+
     // FILE pending acks (start node only)
     pending_files: RwLock<HashMap<String, oneshot::Sender<()>>>,
-    file_counter: AtomicU64,
 
-    /// Status of all nodes on the network
-    network_nodes: RwLock<HashMap<String, NodeStatus>>,
+    /// Pushes and pulls in flight on this node, by token
+    transfers: RwLock<HashMap<String, Arc<Transfer>>>,
+
+    /// Client transfers holding a slot, and those waiting for one
+    active_transfers: AtomicU32,
+    queued_transfers: AtomicU32,
+    slot_freed: Notify,
+
+    /// Chunk streams from other nodes holding a slot, waiting for one, and
+    /// refused since the node started
+    active_relays: AtomicU32,
+    queued_relays: AtomicU32,
+    refused_relays: AtomicU64,
+    relay_freed: Notify,
+
+    /// What the node found in its data directory as it started
+    pub(crate) inventory: OnceLock<Inventory>,
+
+    /// Status of all nodes on the network, by id (ports map to ids in `node_ids`)
+    network_nodes: RwLock<HashMap<NodeId, NodeStatus>>,
+
+    /// Broadcast messages waiting for each peer's acknowledgement
+    pub(crate) outboxes: Arc<Outboxes>,
+
+    /// Small chunks packed into segment files (see [`crate::pack`])
+    pub(crate) packs: PackStore,
+
+    /// Identity of the node at each netmap port and this node's own: the one
+    /// it announced, or a stand-in (see [`NodeId::for_port`])
+    node_ids: RwLock<HashMap<String, NodeId>>,
+
+    /// Labels of each node, as announced in the netmap
+    node_labels: RwLock<HashMap<NodeId, Labels>>,
+
+    /// What each peer speaks, as learned from `NODE HELLO`
+    peer_hellos: RwLock<HashMap<NodeId, Hello>>,
+
+    /// Port of the elected coordinator, re-elected whenever the netmap changes
+    leader: Mutex<String>,
+
+    /// Last load reported by each peer, and when it was fetched
+    peer_loads: RwLock<HashMap<NodeId, (NodeLoad, Instant)>>,
+
+    /// Chunk reads being served right now
+    serving: AtomicU32,
+
+    /// Chunk bytes served recently
+    served: Mutex<LoadMeter>,
+
+    /// Recently served chunks, kept in memory
+    pub chunk_cache: ChunkCache,
+
+    /// Per-source connection and command limits of the listener
+    pub(crate) limiter: Limiter,
+
+    /// Chunk reads and writes, client traffic first
+    pub(crate) disk: DiskScheduler,
+
+    /// Ping round-trip times per neighbor port
+    latency: Mutex<HashMap<String, LatencyStats>>,
+    ping_seq: AtomicU64,
 
     /// Mapping of file name -> (start port, size, parts)
     pub file_tags: RwLock<HashMap<String, FileTag>>,
 
-    /// Time between gossip health checks
-    pub gossip_interval: Duration,
+    /// Hot-reloadable settings (gossip interval, timeouts, limits, log filter)
+    settings: RwLock<Settings>,
+
+    /// Applies `log-filter` changes to the process' tracing subscriber
+    log_filter_hook: Option<LogFilterHook>,
+
+    /// `key = value` file re-read on SIGHUP
+    pub config_file: Option<PathBuf>,
 
-    /// Max file size.
-    pub file_size: u64,
+    /// Alerts fired when a neighbor is found dead
+    pub death_hooks: DeathHooks,
+
+    /// Socket options for peer connections
+    pub tcp: TcpOptions,
+
+    /// Whether health checks try the peers' UDP heartbeat socket first
+    pub udp_heartbeat: bool,
+
+    /// Log format and file (`{port}` unexpanded), for respawned nodes
+    pub logging: LogOptions,
+
+    /// Recent log lines of the process, if the binary keeps them
+    pub log_buffer: Option<LogBuffer>,
+
+    /// Respawns per peer port: (count within the window, last respawn)
+    respawns: RwLock<HashMap<String, (u32, Instant)>>,
+
+    /// Directory holding this node's `content/` and `backup/` folders, tags and identity
+    pub data_dir: PathBuf,
+
+    /// Number of backup copies kept for every chunk (0 disables backups)
+    pub replication: u32,
 
     /// Map of `port -> next_port` for the entire ring
     pub topology_map: RwLock<HashMap<String, String>>,
+
+    /// When each topology edge (by source port) was last learned
+    topology_seen: RwLock<HashMap<String, Instant>>,
+    /// Hop latency (µs) of each edge, by source port, from the walk that learned it
+    topology_latency: RwLock<HashMap<String, u64>>,
+
+    /// Epoch of the topology snapshot in `topology_map`; only changed with its write lock held
+    topology_epoch: AtomicU64,
+
+    /// Signalled whenever a new snapshot changes the links of `topology_map`
+    rewired: Notify,
+
+    /// Per netmap node: incarnation (raised each time the node is seen
+    /// recovering) and last status change
+    incarnations: RwLock<HashMap<NodeId, (u32, Instant)>>,
+    /// This node's own incarnation, raised to refute news of its failure
+    own_incarnation: AtomicU32,
+    /// Netmap changes still to be spread by the gossip loop
+    rumors: Mutex<Rumors>,
+
+    /// Outcome of the last ping of each peer, for partition detection
+    reachability: Mutex<Reachability>,
+    /// Set while this node reaches too few peers (see [`crate::partition`])
+    degraded: AtomicBool,
+
+    /// Hybrid logical clock stamping events, tag versions and topology epochs
+    clock: Clock,
+    /// Fan-out of cluster events to subscribers
+    events: broadcast::Sender<StampedEvent>,
 }
 
 impl Node {
-    pub fn new(port: String, gossip_interval: Duration, file_size: u64) -> Arc<Self> {
+    pub fn new(addr: NodeAddr, config: &NodeConfig) -> Arc<Self> {
         let network_nodes = RwLock::new(HashMap::new());
+        let port = addr.to_string();
+        let data_dir = config.node_dir(addr.port());
+        let id = NodeId::load_or_create(&data_dir).unwrap_or_else(|e| {
+            let id = NodeId::generate();
+            tracing::warn!(node = %port, error = %e, %id, "Could not keep a node id, using a temporary one");
+            id
+        });
+        let node_ids = RwLock::new(HashMap::from([(addr.port().to_string(), id)]));
+        // A relayed node tells its peers where to reach it
+        let mut labels = config.labels.clone();
+        if let Some(relay) = &config.relay {
+            labels
+                .0
+                .insert(RELAY_LABEL.to_string(), port_str(relay).to_string());
+        }
+        let node_labels = RwLock::new(HashMap::from([(id, labels.clone())]));
 
         Arc::new(Self {
             port,
+            addr,
+            id,
+            labels,
+            failure_domain: config.failure_domain.clone(),
+            ring_id: config.ring_id.clone(),
+            relay: config.relay.clone(),
+            relay_hub: RelayHub::default(),
+            cluster_token: config.cluster_token.clone(),
+            access: config.access.clone(),
+            federation: RwLock::new(Federation::default()),
+            federation_tokens: RwLock::new(HashMap::new()),
             next_port: RwLock::new(None),
+            prev_port: RwLock::new(None),
             pending_walks: RwLock::new(HashMap::new()),
-            walk_counter: AtomicU64::new(1),
             pending_heals: RwLock::new(HashMap::new()),
+            pending_discovers: RwLock::new(HashMap::new()),
             pending_files: RwLock::new(HashMap::new()),
-            file_counter: AtomicU64::new(1),
+            transfers: RwLock::new(HashMap::new()),
+            active_transfers: AtomicU32::new(0),
+            queued_transfers: AtomicU32::new(0),
+            slot_freed: Notify::new(),
+            active_relays: AtomicU32::new(0),
+            queued_relays: AtomicU32::new(0),
+            refused_relays: AtomicU64::new(0),
+            inventory: OnceLock::new(),
+            relay_freed: Notify::new(),
             network_nodes,
+            outboxes: Arc::new(Outboxes::load(&data_dir)),
+            packs: PackStore::load(&data_dir),
+            node_ids,
+            node_labels,
+            peer_hellos: RwLock::new(HashMap::new()),
+            leader: Mutex::new(addr.port().to_string()),
+            peer_loads: RwLock::new(HashMap::new()),
+            serving: AtomicU32::new(0),
+            served: Mutex::new(LoadMeter::new()),
+            chunk_cache: ChunkCache::new(),
+            limiter: Limiter::new(config.settings.rate_limits()),
+            disk: DiskScheduler::new(config.settings.io_limits()),
+            latency: Mutex::new(HashMap::new()),
+            ping_seq: AtomicU64::new(1),
             file_tags: RwLock::new(HashMap::new()),
-            gossip_interval,
-            file_size,
+            settings: RwLock::new(config.settings.clone()),
+            log_filter_hook: config.log_filter_hook.clone(),
+            config_file: config.config_file.clone(),
+            death_hooks: config.death_hooks.clone(),
+            tcp: config.tcp,
+            udp_heartbeat: config.udp_heartbeat,
+            logging: config.logging.clone(),
+            log_buffer: config.log_buffer.clone(),
+            respawns: RwLock::new(HashMap::new()),
+            data_dir,
+            replication: config.replication,
             topology_map: RwLock::new(HashMap::new()),
+            topology_seen: RwLock::new(HashMap::new()),
+            topology_latency: RwLock::new(HashMap::new()),
+            topology_epoch: AtomicU64::new(0),
+            rewired: Notify::new(),
+            incarnations: RwLock::new(HashMap::new()),
+            own_incarnation: AtomicU32::new(0),
+            rumors: Mutex::new(Rumors::default()),
+            reachability: Mutex::new(Reachability::default()),
+            degraded: AtomicBool::new(false),
+            clock: Clock::default(),
+            events: broadcast::channel(256).0,
         })
     }
 
+    /// Subscribes to cluster events observed by this node.
+    ///
+    /// Slow receivers miss the oldest events (see [`broadcast::error::RecvError::Lagged`]).
+    pub fn subscribe(&self) -> broadcast::Receiver<StampedEvent> {
+        self.events.subscribe()
+    }
+
+    /// Publishes an event, stamped with this node's clock; only logged when
+    /// nobody is subscribed.
+    pub(crate) fn emit(&self, event: NodeEvent) {
+        let at = self.clock.now();
+        tracing::debug!(node = %self.port, hlc = %at, event = ?event, "Event");
+        let _ = self.events.send(StampedEvent { at, event });
+    }
+
+    /// A timestamp from this node's hybrid logical clock (see [`crate::time`])
+    pub fn now(&self) -> Timestamp {
+        self.clock.now()
+    }
+
+    /// Moves this node's clock past a timestamp another node sent
+    pub fn observe(&self, seen: Timestamp) -> Timestamp {
+        self.clock.observe(seen)
+    }
+
+    /// Snapshot of the current runtime settings
+    pub async fn settings(&self) -> Settings {
+        self.settings.read().await.clone()
+    }
+
+    /// Everything this node runs with, as `key` / `value` pairs: what it was
+    /// started with (address, identity, storage, sockets, alerts), then the
+    /// current runtime settings. Keys match the `run` flags; unset values are `-`.
+    pub async fn config_entries(&self) -> Vec<(String, String)> {
+        let opt = |v: Option<String>| v.unwrap_or_else(|| "-".to_string());
+        let mut out = vec![
+            ("addr", self.port.clone()),
+            ("id", self.id.to_string()),
+            ("data-dir", self.data_dir.display().to_string()),
+            ("replication", self.replication.to_string()),
+            (
+                "config",
+                opt(self.config_file.as_ref().map(|p| p.display().to_string())),
+            ),
+            ("udp-heartbeat", self.udp_heartbeat.to_string()),
+            (
+                "label",
+                opt((!self.labels.is_empty()).then(|| self.labels.to_string())),
+            ),
+            ("failure-domain", self.failure_domain.clone()),
+            ("ring-id", opt(self.ring_id.clone())),
+            ("relay", opt(self.relay.clone())),
+            ("cluster-token", self.cluster_token.is_some().to_string()),
+            (
+                "access-file",
+                opt(self.access.as_ref().map(|a| a.path.display().to_string())),
+            ),
+            ("tcp-nodelay", self.tcp.nodelay.to_string()),
+            (
+                "tcp-keepalive",
+                self.tcp.keepalive.map_or(0, |d| d.as_secs()).to_string(),
+            ),
+            (
+                "tcp-send-buffer",
+                opt(self.tcp.send_buffer.map(|b| b.to_string())),
+            ),
+            (
+                "tcp-recv-buffer",
+                opt(self.tcp.recv_buffer.map(|b| b.to_string())),
+            ),
+            ("log-format", self.logging.format.to_string()),
+            (
+                "log-file",
+                opt(self
+                    .logging
+                    .file_for(port_str(&self.port))
+                    .map(|p| p.display().to_string())),
+            ),
+            ("on-death-exec", opt(self.death_hooks.exec.clone())),
+            ("on-death-webhook", opt(self.death_hooks.webhook.clone())),
+        ];
+        let settings = self.settings().await;
+        out.extend(settings.entries());
+        out.into_iter().map(|(k, v)| (k.to_string(), v)).collect()
+    }
+
+    /// Changes one runtime setting (see [`Settings::KEYS`] and
+    /// [`Node::set_settings`])
+    pub async fn set_setting(&self, key: &str, value: &str) -> Result<(), String> {
+        self.set_settings(&[(key.to_string(), value.to_string())])
+            .await
+    }
+
+    /// Changes runtime settings (see [`Settings::KEYS`]), all or none: every
+    /// entry is applied to a copy of the settings, which replaces them only
+    /// once all of them were accepted.
+    ///
+    /// A new `log-filter` is handed to the log filter hook before it is stored.
+    /// The settings stay locked from read to write, so concurrent changes to
+    /// different keys are all kept.
+    pub async fn set_settings(&self, entries: &[(String, String)]) -> Result<(), String> {
+        let log_filter = entries
+            .iter()
+            .rfind(|(key, _)| key == "log-filter")
+            .map(|(_, value)| value.trim());
+        if log_filter.is_some() && self.log_filter_hook.is_none() {
+            return Err("log filter cannot be changed on this node".into());
+        }
+
+        let (cache_size, rate_limits, io_limits) = {
+            let mut settings = self.settings.write().await;
+            let mut updated = settings.clone();
+            for (key, value) in entries {
+                updated.set(key, value)?;
+            }
+            if let (Some(filter), Some(hook)) = (log_filter, &self.log_filter_hook) {
+                (hook.0)(filter)?;
+            }
+            *settings = updated;
+            (
+                settings.chunk_cache_size,
+                settings.rate_limits(),
+                settings.io_limits(),
+            )
+        };
+        let changed = |pred: fn(&str) -> bool| entries.iter().any(|(key, _)| pred(key));
+        if changed(|key| key == "chunk-cache-size") {
+            self.chunk_cache.shrink_to(cache_size);
+        }
+        if changed(|key| {
+            matches!(
+                key,
+                "conn-rate" | "command-rate" | "ban-errors" | "ban-time"
+            )
+        }) {
+            self.limiter.set_limits(rate_limits);
+        }
+        if changed(|key| key.starts_with("io-")) {
+            self.disk.set_limits(io_limits);
+        }
+        if changed(|key| key == "max-transfers") {
+            // A higher limit may let queued transfers start
+            self.slot_freed.notify_waiters();
+        }
+        if changed(|key| key == "max-relays") {
+            self.relay_freed.notify_waiters();
+        }
+        for (key, value) in entries {
+            tracing::info!(node = %self.port, key, value, "Setting updated");
+        }
+        Ok(())
+    }
+
+    /// Opens a connection to a peer with this node's TCP options, through its
+    /// relay if it has one
+    pub async fn connect(&self, addr: &str) -> std::io::Result<TcpStream> {
+        let route = self.route(addr).await;
+        route.connect(&self.tcp, self.cluster_token.as_ref()).await
+    }
+
+    /// How to reach `addr`: through the relay its netmap entry names, if any
+    /// (see [`crate::relay`]), or directly
+    pub async fn route(&self, addr: &str) -> Route {
+        let port = port_str(addr);
+        if port != self.addr.port().to_string()
+            && let Some(relay) = self.node_labels(port).await.0.get(RELAY_LABEL)
+        {
+            return Route::Relayed {
+                relay: self.peer_addr(relay),
+                port: port.to_string(),
+            };
+        }
+        Route::Direct(addr.to_string())
+    }
+
+    /// Full address of a ring peer, given its port (peers share this node's host)
+    pub fn peer_addr(&self, port: impl std::fmt::Display) -> String {
+        join_host_port(&self.addr.host(), port)
+    }
+
+    /// Directory for chunks this node owns
+    pub fn content_dir(&self) -> PathBuf {
+        self.data_dir.join("content")
+    }
+
+    /// Directory for chunks backed up from the successor
+    pub fn backup_dir(&self) -> PathBuf {
+        self.data_dir.join("backup")
+    }
+
+    /// Chunks received by a push that has not committed yet
+    pub fn staging_dir(&self) -> PathBuf {
+        self.data_dir.join("staging")
+    }
+
+    /// Manifest entries for the chunks in `subdir` (`content` or `backup`)
+    pub fn manifest_dir(&self, subdir: &str) -> PathBuf {
+        self.data_dir.join("manifest").join(subdir)
+    }
+
     pub async fn set_next(&self, addr: String) {
-        *self.next_port.write().await = Some(addr);
+        *self.next_port.write().await = Some(addr.clone());
+        self.emit(NodeEvent::NextChanged { next: addr });
     }
 
     pub async fn get_next(&self) -> Option<String> {
         self.next_port.read().await.clone()
     }
 
+    pub async fn set_prev(&self, addr: String) {
+        *self.prev_port.write().await = Some(addr);
+    }
+
+    pub async fn get_prev(&self) -> Option<String> {
+        self.prev_port.read().await.clone()
+    }
+
+    /// Tells the next hop that this node is its predecessor (`NODE PREV <addr>`).
+    /// Best effort: a next hop that is not up yet learns it from the topology.
+    pub async fn announce_prev(&self) {
+        let Some(next) = self.get_next().await else {
+            return;
+        };
+        if !self
+            .hello(port_str(&next))
+            .await
+            .supports(Feature::NodePrev)
+        {
+            return;
+        }
+        let announce = async {
+            let mut s = self.connect(&next).await?;
+            s.write_all(format!("NODE PREV {}\n", self.port).as_bytes())
+                .await?;
+            let mut reply = String::new();
+            BufReader::new(s).read_line(&mut reply).await?;
+            Ok::<_, std::io::Error>(())
+        };
+        match tokio::time::timeout(NEIGHBOR_QUERY_TIMEOUT, announce).await {
+            Ok(Ok(())) => {
+                tracing::debug!(node = %self.port, next = %next, "Announced as predecessor")
+            }
+            _ => {
+                tracing::debug!(node = %self.port, next = %next, "Could not announce as predecessor")
+            }
+        }
+    }
+
+    /// Asks the node at `port` for one of its ring neighbors: `NODE PREV`
+    /// (answered `PREV <addr>`) or `NODE STATUS` (answered `PORT`/`NEXT` lines).
+    /// Returns the neighbor's port, `None` if unset or unreachable.
+    pub async fn query_neighbor(&self, port: &str, prev: bool) -> Option<String> {
+        let (line, key) = if prev {
+            ("NODE PREV\n", "PREV ")
+        } else {
+            ("NODE STATUS\n", "NEXT ")
+        };
+        let query = async {
+            let mut s = self.connect(&self.peer_addr(port)).await.ok()?;
+            s.write_all(line.as_bytes()).await.ok()?;
+            let mut lines = BufReader::new(s).lines();
+            while let Some(line) = lines.next_line().await.ok()? {
+                if let Some(addr) = line.strip_prefix(key) {
+                    return Some(addr.trim().to_string());
+                }
+                if line == "OK" {
+                    break;
+                }
+            }
+            None
+        };
+        let addr = tokio::time::timeout(NEIGHBOR_QUERY_TIMEOUT, query)
+            .await
+            .ok()
+            .flatten()?;
+        (addr != "<unset>").then(|| port_str(&addr).to_string())
+    }
+
     pub async fn forward_ring_forward(
         &self,
         ttl: u32,
         msg: &str,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         if let Some(next) = self.get_next().await {
-            let mut s = TcpStream::connect(&next).await?;
+            let mut s = self.connect(&next).await?;
             let line = format!("RING FORWARD {} {}\n", ttl, msg);
             s.write_all(line.as_bytes()).await?;
         }
         Ok(())
     }
 
+    /* ---------------- FEDERATION ---------------- */
+
+    /// Links to every federated ring
+    pub async fn federation(&self) -> Federation {
+        self.federation.read().await.clone()
+    }
+
+    /// Link to the ring `ring`, if it is federated with this one
+    pub async fn federation_link(&self, ring: &str) -> Option<FederationLink> {
+        self.federation.read().await.0.get(ring).cloned()
+    }
+
+    /// Replaces the links with the ones received in `FEDERATION SET`
+    pub async fn set_federation(&self, links: Federation) {
+        *self.federation.write().await = links;
+    }
+
+    /// Sets the token presented to the federated ring `ring` (none: no token)
+    pub async fn set_federation_token(&self, ring: &str, token: Option<Secret>) {
+        let mut tokens = self.federation_tokens.write().await;
+        match token {
+            Some(token) => tokens.insert(ring.to_string(), token),
+            None => tokens.remove(ring),
+        };
+    }
+
+    /// Connects to `addr`, a node of the federated ring `ring`, presenting the
+    /// token given with `FEDERATION LINK` if any
+    pub async fn connect_federated(&self, ring: &str, addr: &str) -> std::io::Result<TcpStream> {
+        let token = self.federation_tokens.read().await.get(ring).cloned();
+        self.connect_remote(addr, token.as_ref()).await
+    }
+
+    /// Connects to `addr`, a node of another ring, presenting `token` (a
+    /// client token of that ring) if given. This ring's cluster token is never
+    /// sent to another ring.
+    pub async fn connect_remote(
+        &self,
+        addr: &str,
+        token: Option<&Secret>,
+    ) -> std::io::Result<TcpStream> {
+        let mut s = net::connect(addr, &self.tcp).await?;
+        if let Some(token) = token {
+            let line = Secret::from(format!("NODE AUTH {}\n", token.expose()));
+            s.write_all(line.expose().as_bytes()).await?;
+        }
+        Ok(s)
+    }
+
+    /// Sends this node's links to every other node of the netmap
+    pub async fn broadcast_federation(&self) {
+        let line = format!("FEDERATION SET {}\n", self.federation().await);
+        let ports = self.netmap_ports().await;
+        for port in ports {
+            let addr = self.peer_addr(&port);
+            if addr == self.port {
+                continue;
+            }
+            if let Ok(mut s) = self.connect(&addr).await {
+                let _ = s.write_all(line.as_bytes()).await;
+            }
+        }
+    }
+
     /* ---------------- FILE TAGS ---------------- */
 
+    /// Tags a file whose chunk this node stores, unless the writer's own
+    /// (versioned) tag got here first. A fresh chunk set is no file: the
+    /// writer sends the tag of the file it belongs to.
     pub async fn set_file_tag(&self, name: &str, start_port: u16, size: u64, parts: u32) {
-        self.file_tags.write().await.insert(
-            name.to_string(),
+        if FileTag::is_fresh_chunk_set(name) {
+            return;
+        }
+        self.merge_file_tag(
+            name,
             FileTag {
                 start: start_port,
                 size,
                 parts,
+                holders: Vec::new(),
+                ring: None,
+                chunks: None,
+                conflict_of: None,
+                version: None,
+                content_type: None,
             },
-        );
+        )
+        .await;
+    }
+
+    pub async fn insert_file_tag(&self, name: &str, tag: FileTag) {
+        let mut tags = self.file_tags.write().await;
+        tags.insert(name.to_string(), tag);
+        self.save_file_tags(&tags);
+    }
+
+    /// `tag` as the next version of the file `name`, written by this node:
+    /// one past the version it replaces, the current tag or `replaced` (for
+    /// an overwrite, whose old tag is already discarded)
+    pub async fn stamp_file_tag(
+        &self,
+        name: &str,
+        mut tag: FileTag,
+        replaced: Option<TagVersion>,
+    ) -> FileTag {
+        let current = self
+            .file_tags
+            .read()
+            .await
+            .get(name)
+            .and_then(|tag| tag.version);
+        let counter = current.max(replaced).map_or(0, |v| v.counter);
+        tag.version = Some(TagVersion {
+            counter: counter + 1,
+            at: self.clock.now(),
+            node: self.id,
+        });
+        tag
+    }
+
+    /// Takes a tag another node wrote for `name`, unless this node holds a
+    /// newer version (see [`TagVersion`]). Unversioned tags replace each
+    /// other, as before versions, but never a versioned one. Of two versions
+    /// written concurrently, the losing one is set aside under
+    /// [`reconcile::conflict_name`] for `FILE CONFLICTS`, the same on every
+    /// node whichever arrives first. Returns whether the tag was taken.
+    pub async fn merge_file_tag(&self, name: &str, tag: FileTag) -> bool {
+        if let Some(version) = tag.version {
+            self.clock.observe(version.at);
+        }
+        let mut tags = self.file_tags.write().await;
+        let Some(current) = tags.get(name) else {
+            tags.insert(name.to_string(), tag);
+            self.save_file_tags(&tags);
+            return true;
+        };
+        if *current == tag {
+            return false;
+        }
+        let (taken, loser) = match (current.version, tag.version) {
+            (Some(_), None) => return false,
+            (None, _) => {
+                tags.insert(name.to_string(), tag);
+                self.save_file_tags(&tags);
+                return true;
+            }
+            (Some(ours), Some(theirs)) if theirs > ours => {
+                let old = tags.insert(name.to_string(), tag);
+                (true, old.filter(|_| ours.concurrent(&theirs)))
+            }
+            (Some(ours), Some(theirs)) => (false, ours.concurrent(&theirs).then_some(tag)),
+        };
+        if let Some(loser) = loser {
+            let copy = reconcile::conflict_name(name, &loser);
+            tracing::warn!(node = %self.port, file = %name, version = %copy, "Concurrent versions of a file, setting the older one aside");
+            tags.entry(copy)
+                .or_insert_with(|| reconcile::set_aside(name, loser));
+        }
+        self.save_file_tags(&tags);
+        taken
     }
 
-    /// Serializes file tags into a single line: `name1:start1:size1:parts1;name2:start2:size2:parts2`
-    pub async fn get_file_tags_entries(&self) -> String {
+    /// First free `<name>.v<N>` (from `v2`), for pushes in `version` mode
+    pub async fn versioned_name(&self, name: &str) -> String {
         let tags = self.file_tags.read().await;
-        let mut items: Vec<(&String, &FileTag)> = tags.iter().collect();
-        items.sort_by(|a, b| a.0.cmp(b.0));
-
-        items
-            .into_iter()
-            .map(|(name, tag)| {
-                // Replace special chars in name to avoid parsing errors
-                let safe_name = name.replace(':', "_").replace(';', "_");
-                format!("{}:{}:{}:{}", safe_name, tag.start, tag.size, tag.parts)
+        (2..)
+            .map(|v| format!("{}.v{}", name, v))
+            .find(|candidate| {
+                !tags.contains_key(candidate)
+                    && !tags
+                        .values()
+                        .any(|tag| tag.chunks.as_ref() == Some(candidate))
             })
-            .collect::<Vec<_>>()
-            .join(";")
+            .expect("version numbers are unbounded")
+    }
+
+    /// All file tags, as sent with `FILE TAGS-SET`
+    pub async fn get_file_tags_entries(&self) -> FileTags {
+        let tags = self.file_tags.read().await;
+        FileTags(
+            tags.iter()
+                .map(|(name, tag)| (name.clone(), tag.clone()))
+                .collect(),
+        )
     }
 
-    /// Parses file tags from a single line: `name1:start1:size1:parts1;name2:start2:size2:parts2`
-    pub async fn set_file_tags_from_entries(&self, entries: &str) {
+    /// Replaces all file tags with the ones received in `FILE TAGS-SET`
+    pub async fn set_file_tags_from_entries(&self, entries: &FileTags) {
         let mut tags = self.file_tags.write().await;
-        tags.clear();
-        for entry in entries.split(';').filter(|s| !s.is_empty()) {
-            let parts: Vec<_> = entry.splitn(4, ':').collect();
-            if parts.len() == 4 {
-                let name = parts[0];
-                let start_res = parts[1].parse::<u16>();
-                let size_res = parts[2].parse::<u64>();
-                let parts_res = parts[3].parse::<u32>();
-                if let (Ok(start), Ok(size), Ok(parts_num)) = (start_res, size_res, parts_res) {
-                    tags.insert(
-                        name.to_string(),
-                        FileTag {
-                            start,
-                            size,
-                            parts: parts_num,
-                        },
-                    );
-                }
+        *tags = entries
+            .0
+            .iter()
+            .map(|(name, tag)| (name.clone(), tag.clone()))
+            .collect();
+        self.save_file_tags(&tags);
+    }
+
+    /// Writes `tags` (the whole table, under its write lock) to
+    /// [`TAGS_FILE`], through a temporary file so a crash leaves the old or
+    /// the new version
+    pub(crate) fn save_file_tags(&self, tags: &HashMap<String, FileTag>) {
+        let path = self.data_dir.join(TAGS_FILE);
+        let tmp = path.with_extension("tmp");
+        let entries = FileTags(tags.iter().map(|(n, t)| (n.clone(), t.clone())).collect());
+        let res = std::fs::write(&tmp, format!("{}\n", entries))
+            .and_then(|()| std::fs::rename(&tmp, &path));
+        if let Err(e) = res {
+            tracing::warn!(node = %self.port, path = %path.display(), error = %e, "Cannot save file tags");
+        }
+    }
+
+    /// Takes back the tags saved in [`TAGS_FILE`] before the node stopped,
+    /// under any it already holds. Returns how many were read.
+    pub(crate) async fn load_file_tags(&self) -> usize {
+        let path = self.data_dir.join(TAGS_FILE);
+        let saved: FileTags = match fs::read_to_string(&path).await {
+            Ok(text) => text.parse().unwrap_or_else(|e| {
+                tracing::warn!(node = %self.port, path = %path.display(), error = %e, "Ignoring unreadable file tags");
+                FileTags::default()
+            }),
+            Err(_) => FileTags::default(),
+        };
+        let mut tags = self.file_tags.write().await;
+        for (name, tag) in &saved.0 {
+            if let Some(version) = tag.version {
+                self.clock.observe(version.at);
             }
+            tags.entry(name.clone()).or_insert_with(|| tag.clone());
         }
+        saved.0.len()
     }
 
     /* ---------------- TOPOLOGY (WALK) helpers ---------------- */
 
+    /// `<port>-<32 random hex digits>`
     fn next_token(&self) -> String {
-        let n = self.walk_counter.fetch_add(1, Ordering::Relaxed);
-        format!("{}-{}", self.port, n)
+        format!("{}-{}", self.port, random_token())
     }
 
     pub fn make_walk_token(&self) -> String {
         self.next_token()
     }
 
-    pub async fn register_walk(&self, token: &str) -> oneshot::Receiver<String> {
+    pub async fn register_walk(&self, token: &str) -> oneshot::Receiver<Topology> {
         let (tx, rx) = oneshot::channel();
         self.pending_walks
             .write()
@@ -187,21 +1065,79 @@ impl Node {
         rx
     }
 
+    /// Sends the walk on to the next node, timing the hop (see
+    /// [`Node::send_walk_line`]). A hop that cannot be delivered is retried
+    /// once through this node's successor in the stored topology (the same
+    /// node again when they agree), recorded as the link taken; if that
+    /// successor is the start node, the walk is finished there instead.
     pub async fn forward_topology_hop(
         &self,
         token: &str,
         start_addr: &str,
-        history: &str,
+        epoch: u64,
+        history: &Topology,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        if let Some(next) = self.get_next().await {
-            let mut s = TcpStream::connect(&next).await?;
-            let line = format!("TOPOLOGY HOP {} {} {}\n", token, start_addr, history);
-            s.write_all(line.as_bytes()).await?;
+        let Some(next) = self.get_next().await else {
+            return Ok(());
+        };
+        let hop = format!("TOPOLOGY HOP {} {}", token, start_addr);
+        let Err(e) = self.send_walk_line(&next, &hop, epoch, history).await else {
+            return Ok(());
+        };
+        let Some(successor) = self.topology_successor().await else {
+            return Err(e);
+        };
+        tracing::warn!(node = %self.port, target = %next, retry = %successor, error = ?e, "TOPOLOGY HOP failed, retrying via topology successor");
+
+        let mut history = history.clone();
+        history.retarget_last(&successor);
+        match retry_hop(&successor, start_addr, history) {
+            Hop::Done { to, payload } => self.send_topology_done(&to, token, epoch, &payload).await,
+            Hop::Forward { to, payload } => self.send_walk_line(&to, &hop, epoch, &payload).await,
+            Hop::Stop => Ok(()),
         }
+    }
+
+    /// Sends `<command> <payload>` to `addr`, the last link of `history`.
+    /// The link's latency is the time taken to connect to `addr`.
+    async fn send_walk_line(
+        &self,
+        addr: &str,
+        command: &str,
+        epoch: u64,
+        history: &Topology,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let started = Instant::now();
+        let mut s = self.connect(addr).await?;
+        let mut history = history.clone();
+        history.time_last(started.elapsed());
+        let line = format!(
+            "{} {}\n",
+            command,
+            self.topology_payload(epoch, &history).await
+        );
+        s.write_all(line.as_bytes()).await?;
+        Ok(())
+    }
+
+    /// This node's successor in the stored topology, where failed walk hops are retried
+    pub async fn topology_successor(&self) -> Option<String> {
+        let port = self.get_next_for_node(port_str(&self.port)).await?;
+        Some(self.peer_addr(&port))
+    }
+
+    /// Connects to `addr` and sends one line, without waiting for an answer
+    pub(crate) async fn send_line(
+        &self,
+        addr: &str,
+        line: &str,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut s = self.connect(addr).await?;
+        s.write_all(line.as_bytes()).await?;
         Ok(())
     }
 
-    pub async fn finish_walk(&self, token: &str, history: String) -> bool {
+    pub async fn finish_walk(&self, token: &str, history: Topology) -> bool {
         if let Some(tx) = self.pending_walks.write().await.remove(token) {
             let _ = tx.send(history);
             true
@@ -210,6 +1146,16 @@ impl Node {
         }
     }
 
+    /// Drops a walk nobody waits for anymore; its DONE will be rejected
+    pub async fn cancel_walk(&self, token: &str) {
+        self.pending_walks.write().await.remove(token);
+    }
+
+    /// Drops a heal walk nobody waits for anymore; its DONE will be rejected
+    pub async fn cancel_heal_walk(&self, token: &str) {
+        self.pending_heals.write().await.remove(token);
+    }
+
     pub async fn finish_heal_walk(&self, token: &str) -> bool {
         if let Some(tx) = self.pending_heals.write().await.remove(token) {
             let _ = tx.send(());
@@ -219,23 +1165,40 @@ impl Node {
         }
     }
 
-    pub async fn send_topology_done(
-        &self,
-        start_addr: &str,
-        token: &str,
-        history: &str,
-    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let mut s = TcpStream::connect(start_addr).await?;
-        let line = format!("TOPOLOGY DONE {} {}\n", token, history);
-        s.write_all(line.as_bytes()).await?;
-        Ok(())
-    }
-
-    /* ---------------- FILE helpers ---------------- */
+    pub async fn register_discover(&self, token: &str) -> oneshot::Receiver<Netmap> {
+        let (tx, rx) = oneshot::channel();
+        self.pending_discovers
+            .write()
+            .await
+            .insert(token.to_string(), tx);
+        rx
+    }
+
+    /// Ends a running discovery, handing back its waiter's sender
+    pub async fn take_discover(&self, token: &str) -> Option<oneshot::Sender<Netmap>> {
+        self.pending_discovers.write().await.remove(token)
+    }
+
+    /// Drops a discovery nobody waits for anymore
+    pub async fn cancel_discover(&self, token: &str) {
+        self.pending_discovers.write().await.remove(token);
+    }
+
+    pub async fn send_topology_done(
+        &self,
+        start_addr: &str,
+        token: &str,
+        epoch: u64,
+        history: &Topology,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let done = format!("TOPOLOGY DONE {}", token);
+        self.send_walk_line(start_addr, &done, epoch, history).await
+    }
+
+    /* ---------------- FILE helpers ---------------- */
 
     fn next_file_token(&self) -> String {
-        let n = self.file_counter.fetch_add(1, Ordering::Relaxed);
-        format!("file-{}-{}", self.port, n)
+        format!("file-{}-{}", self.port, random_token())
     }
 
     pub fn make_file_token(&self) -> String {
@@ -260,6 +1223,50 @@ impl Node {
         }
     }
 
+    /// Registers a push or pull so it can be tracked and cancelled
+    pub async fn begin_transfer(
+        &self,
+        token: &str,
+        kind: TransferKind,
+        name: &str,
+        total: u64,
+        route: Vec<(u64, String)>,
+    ) -> Arc<Transfer> {
+        let transfer = Arc::new(Transfer::new(
+            token.to_string(),
+            kind,
+            name.to_string(),
+            total,
+            route,
+        ));
+        self.transfers
+            .write()
+            .await
+            .insert(token.to_string(), Arc::clone(&transfer));
+        transfer
+    }
+
+    pub async fn end_transfer(&self, token: &str) {
+        self.transfers.write().await.remove(token);
+    }
+
+    pub async fn transfer(&self, token: &str) -> Option<Arc<Transfer>> {
+        self.transfers.read().await.get(token).cloned()
+    }
+
+    /// Progress of every transfer in flight, sorted by token
+    pub async fn transfers(&self) -> Vec<TransferProgress> {
+        let mut out: Vec<_> = self
+            .transfers
+            .read()
+            .await
+            .values()
+            .map(|t| t.progress())
+            .collect();
+        out.sort_by(|a, b| a.token.cmp(&b.token));
+        out
+    }
+
     pub async fn forward_file_relay_blob(
         &self,
         token: &str,
@@ -269,10 +1276,13 @@ impl Node {
         data: &[u8],
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         if let Some(next) = self.get_next().await {
-            let mut s = TcpStream::connect(&next).await?;
+            let mut s = self.connect(&next).await?;
             let header = format!(
                 "FILE RELAY-BLOB {} {} {} {}\n",
-                token, start_addr, size, name
+                token,
+                start_addr,
+                size,
+                protocol::encode_name(name)
             );
             s.write_all(header.as_bytes()).await?;
             s.write_all(data).await?;
@@ -281,78 +1291,439 @@ impl Node {
     }
 }
 
-/* ---------- WALK utility ---------- */
+/* ---------- LOAD helpers ---------- */
+
+/// Holds one of the `max-transfers` slots until dropped
+pub(crate) struct TransferSlot<'a>(&'a Node);
 
-pub fn port_str(addr: &str) -> &str {
-    addr.rsplit(':').next().unwrap_or(addr)
+impl Drop for TransferSlot<'_> {
+    fn drop(&mut self) {
+        self.0.active_transfers.fetch_sub(1, Ordering::AcqRel);
+        self.0.slot_freed.notify_waiters();
+    }
 }
 
-pub fn append_edge(mut history: String, from_addr: &str, to_addr: &str) -> String {
-    let from = port_str(from_addr);
-    let to = port_str(to_addr);
-    let edge = format!("{from}->{to}");
-    if history.is_empty() {
-        edge
-    } else {
-        history.push(';');
-        history.push_str(&edge);
-        history
+/// Holds one of the `max-relays` slots until dropped
+pub(crate) struct RelaySlot<'a>(&'a Node);
+
+impl Drop for RelaySlot<'_> {
+    fn drop(&mut self) {
+        self.0.active_relays.fetch_sub(1, Ordering::AcqRel);
+        self.0.relay_freed.notify_waiters();
+    }
+}
+
+/// Counts a chunk read as in flight until dropped
+pub(crate) struct ServingGuard<'a>(&'a Node);
+
+impl Drop for ServingGuard<'_> {
+    fn drop(&mut self) {
+        self.0.serving.fetch_sub(1, Ordering::Relaxed);
     }
 }
 
 impl Node {
-    pub async fn first_walk_history(&self) -> Option<String> {
-        let next = self.get_next().await?;
-        Some(append_edge(String::new(), &self.port, &next))
+    /// Marks a chunk read in flight for as long as the guard lives
+    pub(crate) fn begin_serving(&self) -> ServingGuard<'_> {
+        self.serving.fetch_add(1, Ordering::Relaxed);
+        ServingGuard(self)
+    }
+
+    /// Waits until fewer than `max-transfers` client transfers run, then takes a slot
+    pub(crate) async fn transfer_slot(&self) -> TransferSlot<'_> {
+        let mut queued = false;
+        loop {
+            // Register for a wake-up before checking, so a slot freed in between is not missed
+            let freed = self.slot_freed.notified();
+            let max = self.settings.read().await.max_transfers;
+            let active = self.active_transfers.load(Ordering::Acquire);
+            if max == 0 || active < max {
+                if self
+                    .active_transfers
+                    .compare_exchange(active, active + 1, Ordering::AcqRel, Ordering::Acquire)
+                    .is_err()
+                {
+                    continue;
+                }
+                if queued {
+                    self.queued_transfers.fetch_sub(1, Ordering::Relaxed);
+                }
+                return TransferSlot(self);
+            }
+            if !queued {
+                queued = true;
+                self.queued_transfers.fetch_add(1, Ordering::Relaxed);
+                tracing::debug!(node = %self.port, active, max, "Transfer queued, waiting for a free slot");
+            }
+            freed.await;
+        }
+    }
+
+    /// Waits until fewer than `max-relays` chunk streams from other nodes
+    /// run, then takes a slot. `None` when `relay-queue` streams already
+    /// wait, or no slot freed up within [`RELAY_WAIT`].
+    pub(crate) async fn relay_slot(&self) -> Option<RelaySlot<'_>> {
+        let deadline = tokio::time::Instant::now() + RELAY_WAIT;
+        let mut queued = false;
+        let slot = loop {
+            let freed = self.relay_freed.notified();
+            let settings = self.settings.read().await;
+            let (max, queue) = (settings.max_relays, settings.relay_queue);
+            drop(settings);
+            let active = self.active_relays.load(Ordering::Acquire);
+            if max == 0 || active < max {
+                if self
+                    .active_relays
+                    .compare_exchange(active, active + 1, Ordering::AcqRel, Ordering::Acquire)
+                    .is_err()
+                {
+                    continue;
+                }
+                break Some(RelaySlot(self));
+            }
+            if !queued {
+                if self.queued_relays.load(Ordering::Acquire) >= queue {
+                    break None;
+                }
+                queued = true;
+                self.queued_relays.fetch_add(1, Ordering::AcqRel);
+                tracing::debug!(node = %self.port, active, max, "Relay queued, waiting for a free slot");
+            }
+            if tokio::time::timeout_at(deadline, freed).await.is_err() {
+                break None;
+            }
+        };
+        if queued {
+            self.queued_relays.fetch_sub(1, Ordering::AcqRel);
+        }
+        if slot.is_none() {
+            self.refused_relays.fetch_add(1, Ordering::Relaxed);
+        }
+        slot
+    }
+
+    /// Counts chunk bytes sent to a reader towards this node's load
+    pub(crate) fn record_served(&self, bytes: u64) {
+        self.served.lock().expect("load meter poisoned").add(bytes);
+    }
+
+    /// This node's current load
+    pub async fn load(&self) -> NodeLoad {
+        let transfers = self.transfers.read().await.len() as u32;
+        NodeLoad {
+            transfers: transfers + self.serving.load(Ordering::Relaxed),
+            recent_bytes: self.served.lock().expect("load meter poisoned").recent(),
+        }
+    }
+
+    /// Load of the node at `port`, asking it with `NODE LOAD` unless a recent
+    /// answer is cached. `None` if it cannot be reached.
+    pub async fn peer_load(&self, port: &str) -> Option<NodeLoad> {
+        if port == port_str(&self.port) {
+            return Some(self.load().await);
+        }
+        let id = self.peer_id(port).await;
+        if let Some((load, at)) = self.peer_loads.read().await.get(&id)
+            && at.elapsed() < PEER_LOAD_TTL
+        {
+            return Some(*load);
+        }
+
+        let query = async {
+            let mut s = self.connect(&self.peer_addr(port)).await?;
+            s.write_all(b"NODE LOAD\n").await?;
+            let mut line = String::new();
+            BufReader::new(s).read_line(&mut line).await?;
+            line.parse::<NodeLoad>()
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+        };
+        match tokio::time::timeout(PEER_LOAD_TIMEOUT, query).await {
+            Ok(Ok(load)) => {
+                self.peer_loads
+                    .write()
+                    .await
+                    .insert(id, (load, Instant::now()));
+                Some(load)
+            }
+            _ => {
+                self.peer_loads.write().await.remove(&id);
+                None
+            }
+        }
     }
 }
 
-/* ---------- NETMAP (INVESTIGATION) helpers ---------- */
+/* ---------- METRICS ---------- */
+
+impl Node {
+    /// Counters reported by `NODE METRICS`, as `(name, value)` pairs.
+    /// Ping latencies are per neighbor, named `ping_rtt_*_us.<port>`.
+    pub async fn metrics(&self) -> Vec<(String, u64)> {
+        let cache = self.chunk_cache.stats();
+        let load = self.load().await;
+        let mut out: Vec<(String, u64)> = vec![
+            ("chunk_cache_hits".into(), cache.hits),
+            ("chunk_cache_misses".into(), cache.misses),
+            ("chunk_cache_entries".into(), cache.entries),
+            ("chunk_cache_bytes".into(), cache.bytes),
+            ("transfers".into(), load.transfers as u64),
+            (
+                "transfers_active".into(),
+                self.active_transfers.load(Ordering::Relaxed) as u64,
+            ),
+            (
+                "transfers_queued".into(),
+                self.queued_transfers.load(Ordering::Relaxed) as u64,
+            ),
+            (
+                "relays_active".into(),
+                self.active_relays.load(Ordering::Relaxed) as u64,
+            ),
+            (
+                "relays_queued".into(),
+                self.queued_relays.load(Ordering::Relaxed) as u64,
+            ),
+            (
+                "relays_refused".into(),
+                self.refused_relays.load(Ordering::Relaxed),
+            ),
+            ("recent_bytes_served".into(), load.recent_bytes),
+            ("broadcast_undelivered".into(), self.outboxes.len() as u64),
+            (
+                "relay_sessions".into(),
+                self.relay_hub.relayed().len() as u64,
+            ),
+            ("degraded".into(), u64::from(self.is_degraded())),
+        ];
+        let inventory = self.inventory.get().copied().unwrap_or_default();
+        out.push(("inventory_registered".into(), inventory.registered as u64));
+        out.push(("inventory_missing".into(), inventory.missing as u64));
+        out.push(("inventory_orphaned".into(), inventory.orphaned as u64));
+        for (subdir, stats) in self.packs.stats() {
+            out.push((format!("pack_{}_chunks", subdir), stats.chunks));
+            out.push((format!("pack_{}_segments", subdir), stats.segments));
+            out.push((format!("pack_{}_bytes", subdir), stats.bytes));
+            out.push((format!("pack_{}_dead_bytes", subdir), stats.dead_bytes));
+        }
+        for (class, stats) in self.disk.stats() {
+            out.push((format!("io_{}_active", class), stats.active as u64));
+            out.push((format!("io_{}_waiting", class), stats.waiting as u64));
+            out.push((format!("io_{}_bytes", class), self.disk.bytes(class)));
+        }
+
+        let latency = self.latency_stats();
+        for (port, stats) in latency {
+            let us = |d: Duration| d.as_micros() as u64;
+            out.push((format!("ping_samples.{}", port), stats.len() as u64));
+            out.push((format!("ping_rtt_last_us.{}", port), us(stats.last())));
+            out.push((format!("ping_rtt_min_us.{}", port), us(stats.min())));
+            out.push((format!("ping_rtt_mean_us.{}", port), us(stats.mean())));
+            out.push((format!("ping_rtt_median_us.{}", port), us(stats.median())));
+            out.push((format!("ping_rtt_max_us.{}", port), us(stats.max())));
+        }
+        out
+    }
+
+    /// Sequence number for the next gossip ping
+    pub fn next_ping_seq(&self) -> u64 {
+        self.ping_seq.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Records a ping round trip to `port`. Returns whether the neighbor was
+    /// suspect before and after this sample.
+    pub fn record_rtt(&self, port: &str, rtt: Duration) -> (bool, bool) {
+        let mut latency = self.latency.lock().expect("latency stats poisoned");
+        let stats = latency.entry(port.to_string()).or_default();
+        let was_suspect = stats.is_suspect();
+        stats.record(rtt);
+        (was_suspect, stats.is_suspect())
+    }
+
+    /// RTT statistics per neighbor port, sorted by port
+    pub fn latency_stats(&self) -> Vec<(String, LatencyStats)> {
+        let latency = self.latency.lock().expect("latency stats poisoned");
+        let mut out: Vec<_> = latency
+            .iter()
+            .map(|(port, stats)| (port.clone(), stats.clone()))
+            .collect();
+        out.sort_by(|a, b| a.0.cmp(&b.0));
+        out
+    }
+}
+
+impl Node {
+    /// Snapshot of what hop decisions depend on (see [`crate::ring_state`])
+    pub async fn ring_state(&self) -> RingState {
+        RingState {
+            port: self.port.clone(),
+            next: self.get_next().await,
+            epoch: self.topology_epoch(),
+        }
+    }
 
-fn host_str(addr: &str) -> &str {
-    addr.split(':').next().unwrap_or("127.0.0.1")
+    /// Walk history holding this node's own link, to start a `TOPOLOGY WALK`
+    pub async fn first_walk_history(&self) -> Option<Topology> {
+        let next = self.get_next().await?;
+        let mut history = Topology::default();
+        history.push(&self.port, &next);
+        Some(history)
+    }
 }
 
-fn parse_entries(entries: &str) -> HashMap<String, NodeStatus> {
-    let mut map = HashMap::new();
-    for part in entries.split(',') {
-        let kv = part.trim();
-        if kv.is_empty() {
-            continue;
-        }
-        let mut it = kv.splitn(2, '=');
-        let k = it.next().unwrap_or("").trim();
-        let v = it.next().unwrap_or("").trim();
-        if k.is_empty() {
-            continue;
-        }
-        let status = match v {
-            "Alive" | "alive" => NodeStatus::Alive,
-            "Dead" | "dead" => NodeStatus::Dead,
-            _ => NodeStatus::Alive,
+/* ---------- Version negotiation (see crate::compat) ---------- */
+impl Node {
+    /// Records what the peer at `port` speaks
+    pub async fn record_hello(&self, port: &str, hello: Hello) {
+        tracing::debug!(node = %self.port, peer = %port, hello = %hello, "Peer version learned");
+        let id = self.peer_id(port_str(port)).await;
+        self.peer_hellos.write().await.insert(id, hello);
+    }
+
+    /// What the peer at `port` speaks, greeting it first if it has not been
+    /// yet. Unreachable peers read as legacy, and are greeted again next time.
+    pub async fn hello(&self, port: &str) -> Hello {
+        let id = self.peer_id(port).await;
+        if let Some(hello) = self.peer_hellos.read().await.get(&id) {
+            return hello.clone();
+        }
+        if port == port_str(&self.port) {
+            return Hello::current();
+        }
+        let greet = async {
+            let mut s = self.connect(&self.peer_addr(port)).await?;
+            let line = format!("NODE HELLO {} {}\n", self.port, Hello::current());
+            s.write_all(line.as_bytes()).await?;
+            let mut reader = BufReader::new(s);
+            let mut reply = String::new();
+            reader.read_line(&mut reply).await?;
+            if reply.starts_with("HELLO ") {
+                // Drain the trailing `OK`
+                reader.read_line(&mut String::new()).await?;
+            }
+            Ok::<_, std::io::Error>(reply)
         };
-        map.insert(k.to_string(), status);
+        match tokio::time::timeout(NEIGHBOR_QUERY_TIMEOUT, greet).await {
+            Ok(Ok(reply)) => {
+                // Older nodes answer "ERR unknown NODE command"
+                let hello = reply
+                    .strip_prefix("HELLO ")
+                    .and_then(|h| h.parse().ok())
+                    .unwrap_or_else(Hello::legacy);
+                self.record_hello(port, hello.clone()).await;
+                hello
+            }
+            _ => Hello::legacy(),
+        }
+    }
+
+    /// Greets every live peer of the netmap not greeted yet
+    pub async fn negotiate(&self) {
+        let ports: Vec<String> = {
+            let nodes = self.network_nodes.read().await;
+            let ids = self.node_ids.read().await;
+            let hellos = self.peer_hellos.read().await;
+            members(&ids, &nodes)
+                .filter(|(_, id, status)| *status != NodeStatus::Dead && !hellos.contains_key(id))
+                .map(|(port, ..)| port.clone())
+                .collect()
+        };
+        for port in ports {
+            self.hello(&port).await;
+        }
+    }
+
+    /// Whether every live node of the netmap has advertised `feature`. Until
+    /// then, this node keeps emitting the format older peers understand. An
+    /// empty netmap (nothing known about the ring yet) supports nothing.
+    pub async fn ring_supports(&self, feature: Feature) -> bool {
+        let nodes = self.network_nodes.read().await;
+        let hellos = self.peer_hellos.read().await;
+        !nodes.is_empty()
+            && nodes
+                .iter()
+                .filter(|(id, status)| **id != self.id && **status != NodeStatus::Dead)
+                .all(|(id, _)| hellos.get(id).is_some_and(|h| h.supports(feature)))
+    }
+
+    /// `<epoch> <hist>` for a topology line, or just `<hist>` while some node
+    /// of the ring predates epochs
+    pub async fn topology_payload(&self, epoch: u64, history: &Topology) -> String {
+        let untimed;
+        let history = if self.ring_supports(Feature::HopTiming).await {
+            history
+        } else {
+            untimed = history.untimed();
+            &untimed
+        };
+        if self.ring_supports(Feature::TopologyEpoch).await {
+            format!("{} {}", epoch, history)
+        } else {
+            history.to_string()
+        }
     }
-    map
 }
 
-fn serialize_entries(map: &HashMap<String, NodeStatus>) -> String {
-    let mut keys: Vec<_> = map.keys().cloned().collect();
+/// A topology map as a history, sorted by source port, with the hop
+/// latencies known
+fn sorted_history(map: &HashMap<String, String>, latency: &HashMap<String, u64>) -> Topology {
+    let mut keys: Vec<_> = map.keys().collect();
     keys.sort_unstable();
-    let mut out = String::new();
-    for (i, k) in keys.iter().enumerate() {
-        if i > 0 {
-            out.push(',');
-        }
-        out.push_str(k);
-        out.push('=');
-        out.push_str(match map.get(k) {
-            Some(NodeStatus::Alive) => "Alive",
-            Some(NodeStatus::Dead) => "Dead",
-            None => "Alive",
-        });
+    let mut history = Topology::default();
+    for from in keys {
+        history.push(from, &map[from]);
+        if let Some(us) = latency.get(from) {
+            history.time_last(Duration::from_micros(*us));
+        }
     }
-    out
+    history
+}
+
+/* ---------- NETMAP (INVESTIGATION) helpers ---------- */
+
+/// Records a netmap status change reported at incarnation `seen`, and returns
+/// the incarnation now held: a node seen recovering (back from `Dead`, or from
+/// `Suspect` to `Alive`) starts a new one unless the report already did
+fn note_status(
+    incarnations: &mut HashMap<NodeId, (u32, Instant)>,
+    id: NodeId,
+    old: Option<NodeStatus>,
+    new: NodeStatus,
+    seen: u32,
+) -> u32 {
+    let entry = incarnations.entry(id).or_insert((seen, Instant::now()));
+    let known = entry.0;
+    if old == Some(new) {
+        entry.0 = known.max(seen);
+        return entry.0;
+    }
+    entry.0 = if old.is_some_and(|old| new < old) && seen <= known {
+        known + 1
+    } else {
+        known.max(seen)
+    };
+    entry.1 = Instant::now();
+    entry.0
+}
+
+/// Id of the node at `port` in `ids`, or its stand-in (see [`NodeId::for_port`])
+fn id_at(ids: &HashMap<String, NodeId>, port: &str) -> NodeId {
+    ids.get(port)
+        .copied()
+        .unwrap_or_else(|| NodeId::for_port(port))
+}
+
+/// `id` as netmap entries carry it: stand-ins are not announced
+fn announced(id: NodeId) -> Option<NodeId> {
+    (!id.is_derived()).then_some(id)
+}
+
+/// Port, id and status of every node of the netmap
+fn members<'a>(
+    ids: &'a HashMap<String, NodeId>,
+    nodes: &'a HashMap<NodeId, NodeStatus>,
+) -> impl Iterator<Item = (&'a String, NodeId, NodeStatus)> {
+    ids.iter()
+        .filter_map(|(port, id)| Some((port, *id, *nodes.get(id)?)))
 }
 
 impl Node {
@@ -360,15 +1731,311 @@ impl Node {
         self.next_token()
     }
 
-    pub fn entries_with_self(&self, entries: &str) -> String {
-        let mut map = parse_entries(entries);
-        map.insert(port_str(&self.port).to_string(), NodeStatus::Alive);
-        serialize_entries(&map)
+    /// `entries` with this node added as `Alive`, replacing any entry left at
+    /// an old port under the same id
+    pub fn entries_with_self(&self, entries: &Netmap) -> Netmap {
+        let mut entries = entries.clone();
+        entries.insert(&self.port, self.own_member());
+        entries
+    }
+
+    /// This node's netmap entry: alive, with its id, labels and incarnation
+    pub fn own_member(&self) -> Member {
+        Member::new(NodeStatus::Alive, Some(self.id))
+            .with_labels(self.labels.clone())
+            .with_incarnation(self.own_incarnation.load(Ordering::Relaxed))
+    }
+
+    /// Answers news that this node is `Suspect` or `Dead` with a newer
+    /// incarnation of itself, spread as a rumor. Whether it did.
+    fn refute(&self, member: &Member) -> bool {
+        let own = self.own_incarnation.load(Ordering::Relaxed);
+        if member.status == NodeStatus::Alive || member.incarnation < own {
+            return false;
+        }
+        self.own_incarnation
+            .store(member.incarnation + 1, Ordering::Relaxed);
+        tracing::info!(node = %self.port, reported = %member.status, incarnation = member.incarnation + 1, "Gossip: Refuting news of this node's failure");
+        true
+    }
+
+    pub async fn set_network_nodes_from_entries(&self, entries: &Netmap) {
+        let mut nodes = self.network_nodes.write().await;
+        let mut ids = self.node_ids.write().await;
+        let mut labels = self.node_labels.write().await;
+        let mut incarnations = self.incarnations.write().await;
+        let mut hellos = self.peer_hellos.write().await;
+        let own = self.addr.port().to_string();
+        let mut map = HashMap::new();
+        let mut directory = HashMap::from([(own.clone(), self.id)]);
+        let mut refuted = false;
+        for (port, member) in &entries.0 {
+            let id = if *port == own {
+                refuted |= self.refute(member);
+                self.id
+            } else {
+                // Entries from older nodes carry no id: keep what is known
+                match member.id.unwrap_or_else(|| id_at(&ids, port)) {
+                    // This node, listed at a port it left
+                    id if id == self.id => continue,
+                    id => id,
+                }
+            };
+            // A node coming back may run another version: greet it again
+            if member.status == NodeStatus::Dead {
+                hellos.remove(&id);
+            }
+            if member.id.is_some() || !member.labels.is_empty() {
+                labels.insert(id, member.labels.clone());
+            }
+            let old = nodes.get(&id).copied();
+            note_status(
+                &mut incarnations,
+                id,
+                old,
+                member.status,
+                member.incarnation,
+            );
+            if old == Some(NodeStatus::Dead) && member.status != NodeStatus::Dead {
+                self.outboxes.wake();
+            }
+            map.insert(id, member.status);
+            directory.retain(|_, known| *known != id);
+            directory.insert(port.clone(), id);
+        }
+        hellos.retain(|id, _| map.contains_key(id));
+        incarnations.retain(|id, _| map.contains_key(id));
+        labels.retain(|id, _| map.contains_key(id) || *id == self.id);
+        *ids = directory;
+        *nodes = map;
+        self.elect_leader(&ids, &nodes);
+        if refuted {
+            self.spread(&own, self.own_member(), nodes.len());
+        }
+    }
+
+    /// Takes the netmap changes gossiped by a peer: each entry replacing what
+    /// this node knows of that port (see [`Member::supersedes`]) is applied and
+    /// spread further. Returns whether any was.
+    pub async fn merge_gossip(&self, news: &Netmap) -> bool {
+        let mut nodes = self.network_nodes.write().await;
+        let mut ids = self.node_ids.write().await;
+        let mut labels = self.node_labels.write().await;
+        let mut incarnations = self.incarnations.write().await;
+        let mut hellos = self.peer_hellos.write().await;
+        let own = self.addr.port().to_string();
+        let mut taken = Vec::new();
+        for (port, member) in &news.0 {
+            if *port == own {
+                if self.refute(member) {
+                    taken.push((own.clone(), self.own_member()));
+                }
+                continue;
+            }
+            let at = ids.get(port).copied();
+            // Entries from older nodes carry no id: keep what is known
+            let id = member.id.or(at).unwrap_or_else(|| NodeId::for_port(port));
+            if id == self.id {
+                // This node, listed at a port it left
+                continue;
+            }
+            // Another node at a known port is news whatever its incarnation, and so
+            // is a node alive at another port than the one it is known at
+            let replaced = at.is_some_and(|previous| previous != id);
+            let elsewhere = ids.iter().any(|(p, known)| *known == id && p != port);
+            if elsewhere && member.status != NodeStatus::Alive {
+                continue;
+            }
+            let old = nodes.get(&id).copied();
+            let known = old.map(|status| {
+                Member::new(status, Some(id))
+                    .with_incarnation(incarnations.get(&id).map_or(0, |(inc, _)| *inc))
+            });
+            if known
+                .as_ref()
+                .is_some_and(|k| !replaced && !elsewhere && !member.supersedes(k))
+            {
+                continue;
+            }
+            if elsewhere || member.status == NodeStatus::Dead {
+                hellos.remove(&id);
+            }
+            ids.retain(|p, known| *known != id || p == port);
+            if let Some(previous) = ids.insert(port.clone(), id)
+                && previous != id
+            {
+                // The node that answered at this port before left the netmap
+                nodes.remove(&previous);
+                labels.remove(&previous);
+                incarnations.remove(&previous);
+                hellos.remove(&previous);
+            }
+            if member.id.is_some() || !member.labels.is_empty() {
+                labels.insert(id, member.labels.clone());
+            }
+            let entry = incarnations
+                .entry(id)
+                .or_insert((member.incarnation, Instant::now()));
+            entry.0 = member.incarnation;
+            if old != Some(member.status) || elsewhere {
+                entry.1 = Instant::now();
+            }
+            if old == Some(NodeStatus::Dead) && member.status != NodeStatus::Dead {
+                self.outboxes.wake();
+            }
+            nodes.insert(id, member.status);
+            tracing::debug!(node = %self.port, peer = %port, status = %member.status, incarnation = member.incarnation, "Gossip: Netmap change taken");
+            taken.push((port.clone(), member.clone()));
+        }
+        if !taken.is_empty() {
+            self.elect_leader(&ids, &nodes);
+        }
+        for (port, member) in &taken {
+            self.spread(port, member.clone(), nodes.len());
+        }
+        !taken.is_empty()
+    }
+
+    /// Queues `member` as news about `port` for the gossip loop, in a ring of
+    /// `nodes` nodes
+    fn spread(&self, port: &str, member: Member, nodes: usize) {
+        self.rumors.lock().unwrap_or_else(|e| e.into_inner()).push(
+            port,
+            member,
+            gossip::retransmissions(nodes),
+        );
+    }
+
+    /// Netmap changes for one ping, if any are waiting (see [`crate::gossip`])
+    pub fn take_rumors(&self) -> Option<Netmap> {
+        let mut rumors = self.rumors.lock().unwrap_or_else(|e| e.into_inner());
+        (!rumors.is_empty()).then(|| rumors.take())
     }
 
-    pub async fn set_network_nodes_from_entries(&self, entries: &str) {
-        let map = parse_entries(entries);
-        *self.network_nodes.write().await = map;
+    /// Ports of the peers worth probing, other than `except`: those not
+    /// marked `Dead`, or all of them while degraded, to notice the partition
+    /// healing
+    pub async fn probe_peers(&self, except: &str) -> Vec<String> {
+        let nodes = self.network_nodes.read().await;
+        let ids = self.node_ids.read().await;
+        let degraded = self.is_degraded();
+        members(&ids, &nodes)
+            .filter(|(port, id, _)| *id != self.id && port.as_str() != except)
+            .filter(|(_, _, status)| degraded || *status != NodeStatus::Dead)
+            .map(|(port, ..)| port.clone())
+            .collect()
+    }
+
+    /// Whether this node suspects it is cut off from most of the ring
+    pub fn is_degraded(&self) -> bool {
+        self.degraded.load(Ordering::Relaxed)
+    }
+
+    /// Records the outcome of a ping of `port` and enters or leaves degraded
+    /// mode accordingly (see [`crate::partition`]). Returns whether the node
+    /// just left it, and so should reconcile with the rest of the ring.
+    pub async fn record_reach(&self, port: &str, reached: bool) -> bool {
+        let mut peers = self.netmap_statuses().await;
+        peers.remove(port_str(&self.port));
+        let quorum = {
+            let mut reach = self.reachability.lock().unwrap_or_else(|e| e.into_inner());
+            if peers.contains_key(port) {
+                reach.record(port, reached);
+            }
+            reach.quorum(&peers)
+        };
+        let degraded = !quorum.holds();
+        let Quorum { reached, known } = quorum;
+        if self.degraded.swap(degraded, Ordering::Relaxed) == degraded {
+            return false;
+        }
+        if degraded {
+            tracing::warn!(
+                reached,
+                known,
+                "Partition suspected: Too few peers reachable, entering read-only degraded mode"
+            );
+            self.emit(NodeEvent::PartitionSuspected { reached, known });
+            false
+        } else {
+            tracing::info!(
+                reached,
+                known,
+                "Partition recovered: Majority reachable again, leaving degraded mode"
+            );
+            self.emit(NodeEvent::PartitionRecovered { reached, known });
+            true
+        }
+    }
+
+    /// Whether the node at `port` greeted this one with `feature`
+    pub async fn peer_supports(&self, port: &str, feature: Feature) -> bool {
+        let id = self.peer_id(port).await;
+        self.peer_hellos
+            .read()
+            .await
+            .get(&id)
+            .is_some_and(|hello| hello.supports(feature))
+    }
+
+    /// Identity of the node at `port`, if it announced one
+    pub async fn node_id(&self, port: &str) -> Option<NodeId> {
+        self.node_ids
+            .read()
+            .await
+            .get(port)
+            .copied()
+            .filter(|id| !id.is_derived())
+    }
+
+    /// Id the node at `port` is known by here: the one it announced, or a
+    /// stand-in (see [`NodeId::for_port`])
+    pub async fn peer_id(&self, port: &str) -> NodeId {
+        id_at(&*self.node_ids.read().await, port)
+    }
+
+    /// Port of the node known by `id`. A stand-in also finds the node at the
+    /// port it was derived from, should that node have announced an id since.
+    pub async fn port_of(&self, id: NodeId) -> Option<String> {
+        let ids = self.node_ids.read().await;
+        let port = ids
+            .iter()
+            .find(|(_, known)| **known == id)
+            .map(|(port, _)| port);
+        port.or_else(|| {
+            ids.keys()
+                .find(|port| id.is_derived() && NodeId::for_port(port) == id)
+        })
+        .cloned()
+    }
+
+    /// Labels of the node at `port` (none if it announced none)
+    pub async fn node_labels(&self, port: &str) -> Labels {
+        let id = self.peer_id(port).await;
+        self.node_labels
+            .read()
+            .await
+            .get(&id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Ports of the nodes in the netmap
+    pub async fn netmap_ports(&self) -> Vec<String> {
+        let nodes = self.network_nodes.read().await;
+        let ids = self.node_ids.read().await;
+        members(&ids, &nodes)
+            .map(|(port, ..)| port.clone())
+            .collect()
+    }
+
+    /// Status of the nodes in the netmap, by port
+    pub async fn netmap_statuses(&self) -> HashMap<String, NodeStatus> {
+        let nodes = self.network_nodes.read().await;
+        let ids = self.node_ids.read().await;
+        members(&ids, &nodes)
+            .map(|(port, _, status)| (port.clone(), status))
+            .collect()
     }
 
     /// Quick count of known nodes (>=1)
@@ -379,17 +2046,11 @@ impl Node {
 
     /// Human-friendly lines for "NETMAP GET"
     pub async fn get_network_nodes_lines(&self) -> Vec<String> {
-        let map = self.network_nodes.read().await;
+        let map = self.netmap_statuses().await;
         let mut keys: Vec<_> = map.keys().cloned().collect();
         keys.sort_unstable();
         keys.into_iter()
-            .map(|k| {
-                format!(
-                    "{}={:?}",
-                    k,
-                    map.get(&k).cloned().unwrap_or(NodeStatus::Alive)
-                )
-            })
+            .map(|k| format!("{}={:?}", k, map[&k]))
             .collect()
     }
 
@@ -397,10 +2058,10 @@ impl Node {
         &self,
         token: &str,
         start_addr: &str,
-        entries: &str,
+        entries: &Netmap,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         if let Some(next) = self.get_next().await {
-            let mut s = TcpStream::connect(&next).await?;
+            let mut s = self.connect(&next).await?;
             let line = format!("NETMAP HOP {} {} {}\n", token, start_addr, entries);
             s.write_all(line.as_bytes()).await?;
         }
@@ -411,90 +2072,406 @@ impl Node {
         &self,
         start_addr: &str,
         token: &str,
-        entries: &str,
+        entries: &Netmap,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let mut s = TcpStream::connect(start_addr).await?;
+        let mut s = self.connect(start_addr).await?;
         let line = format!("NETMAP DONE {} {}\n", token, entries);
         s.write_all(line.as_bytes()).await?;
         Ok(())
     }
 
-    pub async fn broadcast_netmap(&self, entries: &str) {
-        let map = parse_entries(entries);
-        let host = host_str(&self.port).to_string();
-        for port in map.keys() {
-            let addr = format!("{}:{}", host, port);
+    /// Sends `NETMAP SET` to every node of `entries` but this one. Nodes it
+    /// does not reach get it later (see [`crate::fanout`]).
+    pub async fn broadcast_netmap(&self, entries: &Netmap) {
+        let ports = entries.ports().cloned().collect();
+        let line = format!("NETMAP SET {}\n", entries);
+        self.broadcast(Broadcast::Netmap, ports, line).await;
+    }
+
+    /// Queues `line` in the outbox of the nodes at `ports` but this one, then
+    /// sends it, at most [`BROADCAST_CONCURRENCY`] nodes at once. Returns the
+    /// ports it did not reach yet.
+    pub(crate) async fn broadcast(
+        &self,
+        kind: Broadcast,
+        ports: Vec<String>,
+        line: String,
+    ) -> Vec<String> {
+        let mut peers = Vec::new();
+        for port in ports {
+            let addr = self.peer_addr(&port);
+            if addr != self.port {
+                // Don't broadcast to self
+                peers.push((port, self.route(&addr).await));
+            }
+        }
+        let ports: Vec<String> = peers.iter().map(|(port, _)| port.clone()).collect();
+        self.outboxes.enqueue(&ports, kind, line);
+        let failed = fanout::flush(
+            &self.outboxes,
+            peers,
+            &self.tcp,
+            self.cluster_token.as_ref(),
+            BROADCAST_CONCURRENCY,
+        )
+        .await;
+        if !failed.is_empty() {
+            tracing::debug!(node = %self.port, kind = ?kind, ports = ?failed, "Broadcast to be redelivered");
+        }
+        failed
+    }
+
+    /// Sends the messages waiting in the outboxes of the nodes not marked
+    /// `Dead`, and drops the outboxes of nodes no longer in the netmap
+    pub(crate) async fn flush_outboxes(&self) {
+        let nodes = self.netmap_statuses().await;
+        self.outboxes.retain(|port| nodes.contains_key(port));
+        let mut peers = Vec::new();
+        for port in self.outboxes.pending() {
+            if nodes.get(&port).is_some_and(|s| *s != NodeStatus::Dead) {
+                let route = self.route(&self.peer_addr(&port)).await;
+                peers.push((port, route));
+            }
+        }
+        if peers.is_empty() {
+            return;
+        }
+        tracing::debug!(node = %self.port, peers = ?peers, "Flushing outboxes");
+        fanout::flush(
+            &self.outboxes,
+            peers,
+            &self.tcp,
+            self.cluster_token.as_ref(),
+            BROADCAST_CONCURRENCY,
+        )
+        .await;
+    }
+}
+
+/* ---------- Gossip/Topology helpers ---------- */
+impl Node {
+    /// Tells every other node to drop a file's chunks, backups and tag, and
+    /// waits (briefly) for each to confirm, so chunks stored afterwards under
+    /// the same names are not caught by a late discard
+    pub async fn broadcast_file_discard(&self, name: &str, parts: u32) {
+        let ports = self.netmap_ports().await;
+        for port in ports {
+            let addr = self.peer_addr(&port);
             if addr == self.port {
                 continue;
             } // Don't broadcast to self
-            if let Ok(mut s) = TcpStream::connect(&addr).await {
-                let line = format!("NETMAP SET {}\n", entries);
+            if let Ok(mut s) = self.connect(&addr).await {
+                let line = format!("FILE DISCARD {} {}\n", parts, protocol::encode_name(name));
+                if s.write_all(line.as_bytes()).await.is_ok() {
+                    let mut reply = String::new();
+                    let mut reader = BufReader::new(s);
+                    let _ =
+                        tokio::time::timeout(Duration::from_secs(2), reader.read_line(&mut reply))
+                            .await;
+                }
+            }
+        }
+    }
+
+    /// Tells every other node about a file tag: its holders only learn it
+    /// without the chunk manifest or version when they store their chunk.
+    pub async fn broadcast_file_tag(&self, name: &str, tag: &FileTag) {
+        let ports = self.netmap_ports().await;
+        // A versioned tag goes whole, so the receivers keep the newer version
+        let versioned = tag.version.is_some() && self.ring_supports(Feature::TagVersions).await;
+        // Older peers read a holder list as the file's name
+        let manifest = !tag.holders.is_empty() && self.ring_supports(Feature::Placement).await;
+        let line = format!("FILE TAGS-MERGE {}\n", FileTags::single(name, tag.clone()));
+        for port in ports {
+            let addr = self.peer_addr(&port);
+            if addr == self.port {
+                continue;
+            }
+            if let Ok(mut s) = self.connect(&addr).await {
+                if versioned {
+                    let _ = s.write_all(line.as_bytes()).await;
+                    continue;
+                }
+                if let Some(ring) = &tag.ring {
+                    let line = format!(
+                        "FEDERATION TAG {} {} {}\n",
+                        ring,
+                        tag.size,
+                        protocol::encode_name(name)
+                    );
+                    let _ = s.write_all(line.as_bytes()).await;
+                    continue;
+                }
+                let holders = match (&tag.chunks, manifest) {
+                    (Some(chunks), false) => format!("- {} ", protocol::encode_name(chunks)),
+                    (Some(chunks), true) => format!(
+                        "{} {} ",
+                        join_holders(&tag.holders),
+                        protocol::encode_name(chunks)
+                    ),
+                    (None, false) => String::new(),
+                    (None, true) => format!("{} ", join_holders(&tag.holders)),
+                };
+                let line = format!(
+                    "FILE TAG {} {} {} {}{}\n",
+                    tag.start,
+                    tag.size,
+                    tag.parts,
+                    holders,
+                    protocol::encode_name(name)
+                );
                 let _ = s.write_all(line.as_bytes()).await;
             }
         }
     }
-}
 
-/* ---------- Gossip/Topology helpers ---------- */
-impl Node {
     pub async fn update_node_status(&self, port: String, status: NodeStatus) {
-        self.network_nodes.write().await.insert(port, status);
+        let mut nodes = self.network_nodes.write().await;
+        let mut ids = self.node_ids.write().await;
+        let id = *ids
+            .entry(port.clone())
+            .or_insert_with(|| NodeId::for_port(&port));
+        let old = nodes.get(&id).copied();
+        if status == NodeStatus::Dead {
+            self.peer_hellos.write().await.remove(&id);
+        }
+        let incarnation = note_status(&mut *self.incarnations.write().await, id, old, status, 0);
+        if old == Some(NodeStatus::Dead) && status != NodeStatus::Dead {
+            self.outboxes.wake();
+        }
+        nodes.insert(id, status);
+        self.elect_leader(&ids, &nodes);
+        if old != Some(status) {
+            let labels = self.node_labels.read().await.get(&id).cloned();
+            let member = Member::new(status, announced(id))
+                .with_labels(labels.unwrap_or_default())
+                .with_incarnation(incarnation);
+            self.spread(&port, member, nodes.len());
+        }
     }
 
-    pub async fn get_network_nodes_entries(&self) -> String {
+    /// Port of the node coordinating cluster-wide tasks: the lowest port that
+    /// is not `Dead` in this node's netmap (this node itself always counts)
+    pub fn leader(&self) -> String {
+        self.leader.lock().unwrap().clone()
+    }
+
+    /// Whether this node is the coordinator, see [`Node::leader`]
+    pub fn is_leader(&self) -> bool {
+        self.leader() == self.addr.port().to_string()
+    }
+
+    fn elect_leader(&self, ids: &HashMap<String, NodeId>, nodes: &HashMap<NodeId, NodeStatus>) {
+        let own = self.addr.port();
+        let elected = members(ids, nodes)
+            .filter(|(_, _, status)| *status != NodeStatus::Dead)
+            .filter_map(|(port, ..)| port.parse::<u16>().ok())
+            .chain([own])
+            .min()
+            .unwrap_or(own)
+            .to_string();
+
+        let mut leader = self.leader.lock().unwrap();
+        if *leader != elected {
+            tracing::info!(node = %self.port, old = %leader, new = %elected, "Leader changed");
+            *leader = elected.clone();
+            drop(leader);
+            self.emit(NodeEvent::LeaderChanged { port: elected });
+        }
+    }
+
+    /// The netmap with each node's incarnation and the age of its status
+    pub async fn netmap_view(&self) -> NetmapView {
+        let nodes = self.network_nodes.read().await;
+        let ids = self.node_ids.read().await;
+        let labels = self.node_labels.read().await;
+        let incarnations = self.incarnations.read().await;
+        let mut members: Vec<_> = members(&ids, &nodes).collect();
+        members.sort_unstable_by(|a, b| a.0.cmp(b.0));
+        NetmapView {
+            node: port_str(&self.port).to_string(),
+            nodes: members
+                .into_iter()
+                .map(|(port, id, status)| {
+                    let (incarnation, since) = incarnations.get(&id).copied().unzip();
+                    NodeView {
+                        id: announced(id),
+                        labels: labels.get(&id).cloned().unwrap_or_default(),
+                        status,
+                        incarnation: incarnation.unwrap_or(0),
+                        since_ms: since.map_or(0, |t| t.elapsed().as_millis() as u64),
+                        port: port.clone(),
+                    }
+                })
+                .collect(),
+            federated: Vec::new(),
+        }
+    }
+
+    /// The topology map with the age of every edge
+    pub async fn topology_view(&self) -> TopologyView {
+        let map = self.topology_map.read().await;
+        let seen = self.topology_seen.read().await;
+        let latency = self.topology_latency.read().await;
+        let ids = self.node_ids.read().await;
+        let mut from: Vec<_> = map.keys().cloned().collect();
+        from.sort_unstable();
+        TopologyView {
+            node: port_str(&self.port).to_string(),
+            epoch: self.topology_epoch.load(Ordering::Relaxed),
+            edges: from
+                .into_iter()
+                .map(|from| EdgeView {
+                    from_id: ids.get(&from).copied().and_then(announced),
+                    to_id: ids.get(&map[&from]).copied().and_then(announced),
+                    to: map[&from].clone(),
+                    age_ms: seen
+                        .get(&from)
+                        .map_or(0, |t| t.elapsed().as_millis() as u64),
+                    latency_ms: latency.get(&from).map(|us| *us as f64 / 1000.0),
+                    from,
+                })
+                .collect(),
+        }
+    }
+
+    /// Last known status of `port`, if it is in the netmap
+    pub async fn node_status(&self, port: &str) -> Option<NodeStatus> {
+        let nodes = self.network_nodes.read().await;
+        let id = self.node_ids.read().await.get(port).copied()?;
+        nodes.get(&id).copied()
+    }
+
+    /// Respawns of `port` by this node within the respawn window
+    pub async fn recent_respawns(&self, port: &str) -> u32 {
+        match self.respawns.read().await.get(port) {
+            Some((count, last)) if last.elapsed() < RESPAWN_WINDOW => *count,
+            _ => 0,
+        }
+    }
+
+    /// Records a respawn of `port` and returns the count within the window, this one included
+    pub async fn record_respawn(&self, port: &str) -> u32 {
+        let mut respawns = self.respawns.write().await;
+        let entry = respawns
+            .entry(port.to_string())
+            .or_insert((0, Instant::now()));
+        if entry.1.elapsed() >= RESPAWN_WINDOW {
+            entry.0 = 0;
+        }
+        entry.0 += 1;
+        entry.1 = Instant::now();
+        entry.0
+    }
+
+    pub async fn get_network_nodes_entries(&self) -> Netmap {
         let map = self.network_nodes.read().await;
-        serialize_entries(&map)
+        let ids = self.node_ids.read().await;
+        let labels = self.node_labels.read().await;
+        let incarnations = self.incarnations.read().await;
+        Netmap(
+            members(&ids, &map)
+                .map(|(port, id, status)| {
+                    let incarnation = if id == self.id {
+                        self.own_incarnation.load(Ordering::Relaxed)
+                    } else {
+                        incarnations.get(&id).map_or(0, |(inc, _)| *inc)
+                    };
+                    let member = Member::new(status, announced(id))
+                        .with_labels(labels.get(&id).cloned().unwrap_or_default())
+                        .with_incarnation(incarnation);
+                    (port.clone(), member)
+                })
+                .collect(),
+        )
     }
 
-    /// Gets current netmap entries and broadcasts them
+    /// Spreads this node's netmap changes: by gossip once every live node
+    /// speaks it (they are queued already, see [`Node::update_node_status`]),
+    /// else by sending the whole netmap to every node
     pub async fn broadcast_netmap_update(&self) {
+        if self.ring_supports(Feature::Gossip).await {
+            return;
+        }
         let entries = self.get_network_nodes_entries().await;
         self.broadcast_netmap(&entries).await;
     }
 
-    /// Parses "7000->7001;7001->7002" and stores it
-    pub async fn set_topology_from_history(&self, history: &str) {
+    /// Epoch of this node's topology snapshot (0 before any walk)
+    pub fn topology_epoch(&self) -> u64 {
+        self.topology_epoch.load(Ordering::Relaxed)
+    }
+
+    /// Stores the links of a completed walk (or `TOPOLOGY SET`) as the topology
+    /// map, unless this node already holds a newer snapshot. Concurrent walks
+    /// can mint the same epoch; the smaller history then wins everywhere, so
+    /// all nodes settle on the same map. Epoch 0 (no epoch on the wire) only
+    /// replaces another epoch 0 snapshot. Returns whether the map was
+    /// replaced.
+    pub async fn set_topology_from_history(&self, epoch: u64, history: &Topology) -> bool {
         let mut map = self.topology_map.write().await;
-        map.clear();
-        for edge in history.split(';').filter(|s| !s.is_empty()) {
-            if let Some((from, to)) = edge.split_once("->") {
-                map.insert(from.to_string(), to.to_string());
+        let current = self.topology_epoch.load(Ordering::Relaxed);
+        let stored = sorted_history(&map, &HashMap::new());
+        if !topology_is_newer(current, &stored, epoch, history) {
+            tracing::debug!(node = %self.port, epoch, current, "Ignoring stale topology");
+            return false;
+        }
+
+        let mut seen = self.topology_seen.write().await;
+        let mut latency = self.topology_latency.write().await;
+        let old = std::mem::take(&mut *map);
+        self.topology_epoch.store(epoch, Ordering::Relaxed);
+        if epoch > 0 {
+            self.clock.observe(Timestamp(epoch));
+        }
+        seen.clear();
+        latency.clear();
+        let now = Instant::now();
+        for edge in history.edges() {
+            map.insert(edge.from.clone(), edge.to.clone());
+            seen.insert(edge.from.clone(), now);
+            if let Some(us) = edge.latency_us {
+                latency.insert(edge.from.clone(), us);
             }
         }
-        tracing::debug!(node = %self.port, "Topology map updated");
+        if *map != old {
+            self.rewired.notify_one();
+        }
+        tracing::debug!(node = %self.port, epoch, "Topology map updated");
+        true
     }
 
-    /// Serializes topology map back to "7000->7001;7001->7002"
-    pub async fn get_topology_history(&self) -> String {
+    /// Waits for the next topology snapshot that changes the ring's links.
+    /// Changes made while nobody waits are kept, and several count as one.
+    pub(crate) async fn rewired(&self) {
+        self.rewired.notified().await;
+    }
+
+    /// The topology map as a history sorted by source port, with its epoch
+    pub async fn get_topology_history(&self) -> (u64, Topology) {
         let map = self.topology_map.read().await;
-        let mut keys: Vec<_> = map.keys().cloned().collect();
-        keys.sort_unstable();
-        keys.into_iter()
-            .map(|k| format!("{}->{}", k, map.get(&k).unwrap_or(&"".to_string())))
-            .collect::<Vec<_>>()
-            .join(";")
+        let latency = self.topology_latency.read().await;
+        (
+            self.topology_epoch.load(Ordering::Relaxed),
+            sorted_history(&map, &latency),
+        )
     }
 
-    /// Broadcasts the full topology map to all nodes
+    /// Broadcasts the full topology map to all nodes. Nodes it does not reach
+    /// get it later (see [`crate::fanout`]).
     pub async fn broadcast_topology_set(&self) {
-        let history = self.get_topology_history().await;
+        let (epoch, history) = self.get_topology_history().await;
         if history.is_empty() {
             return;
         }
 
-        let map = self.network_nodes.read().await;
-        let host = host_str(&self.port).to_string();
+        let line = format!(
+            "TOPOLOGY SET {}\n",
+            self.topology_payload(epoch, &history).await
+        );
+        let ports = self.netmap_ports().await;
         tracing::debug!(node = %self.port, history = %history, "Broadcasting topology");
-        for port in map.keys() {
-            let addr = format!("{}:{}", host, port);
-            if addr == self.port {
-                continue;
-            }
-            if let Ok(mut s) = TcpStream::connect(&addr).await {
-                let line = format!("TOPOLOGY SET {}\n", history);
-                let _ = s.write_all(line.as_bytes()).await;
-            }
-        }
+        self.broadcast(Broadcast::Topology, ports, line).await;
     }
 
     /// Finds the next hop for a *specific node* from the stored topology
@@ -1,7 +1,138 @@
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    str::FromStr,
+    time::{Duration, Instant},
+};
 
-#[derive(Debug, Clone, PartialEq, Eq, Copy, Serialize)]
+/// Ordered from best to worst: between two reports of the same incarnation,
+/// gossip keeps the worse one
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Copy, Serialize, Deserialize)]
 pub enum NodeStatus {
     Alive,
+    /// Answering, but with pings sustainedly slower than usual
+    Suspect,
     Dead,
 }
+
+impl fmt::Display for NodeStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Alive => "Alive",
+            Self::Suspect => "Suspect",
+            Self::Dead => "Dead",
+        })
+    }
+}
+
+impl FromStr for NodeStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "Alive" | "alive" => Ok(Self::Alive),
+            "Suspect" | "suspect" => Ok(Self::Suspect),
+            "Dead" | "dead" => Ok(Self::Dead),
+            other => Err(format!("unknown node status '{}'", other)),
+        }
+    }
+}
+
+/// How busy a node is serving data, as reported by `NODE LOAD`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct NodeLoad {
+    /// Pushes, pulls and chunk reads in flight
+    pub transfers: u32,
+    /// Chunk bytes served over roughly the last [`LOAD_WINDOW`]
+    pub recent_bytes: u64,
+}
+
+/// Span over which served bytes count towards a node's load
+pub const LOAD_WINDOW: Duration = Duration::from_secs(10);
+
+impl NodeLoad {
+    /// Whether `self` is clearly less busy than `other`: fewer transfers in
+    /// flight, or as many but under half the recent traffic. The margin keeps
+    /// reads from flapping between two replicas with similar load.
+    pub fn is_lighter_than(&self, other: &NodeLoad) -> bool {
+        self.transfers < other.transfers
+            || (self.transfers == other.transfers
+                && self.recent_bytes.saturating_mul(2) < other.recent_bytes)
+    }
+}
+
+impl fmt::Display for NodeLoad {
+    /// `LOAD transfers=<n> bytes=<n>`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "LOAD transfers={} bytes={}",
+            self.transfers, self.recent_bytes
+        )
+    }
+}
+
+impl FromStr for NodeLoad {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bad = || format!("malformed LOAD line '{}'", s.trim());
+        let mut it = s.split_whitespace();
+        let (Some("LOAD"), Some(transfers), Some(bytes)) = (it.next(), it.next(), it.next()) else {
+            return Err(bad());
+        };
+        Ok(NodeLoad {
+            transfers: transfers
+                .strip_prefix("transfers=")
+                .and_then(|v| v.parse().ok())
+                .ok_or_else(bad)?,
+            recent_bytes: bytes
+                .strip_prefix("bytes=")
+                .and_then(|v| v.parse().ok())
+                .ok_or_else(bad)?,
+        })
+    }
+}
+
+/// Sliding count of bytes served: the current [`LOAD_WINDOW`] plus the
+/// share of the previous one that still overlaps the last `LOAD_WINDOW`.
+#[derive(Debug)]
+pub(crate) struct LoadMeter {
+    window_start: Instant,
+    current: u64,
+    previous: u64,
+}
+
+impl LoadMeter {
+    pub(crate) fn new() -> Self {
+        Self {
+            window_start: Instant::now(),
+            current: 0,
+            previous: 0,
+        }
+    }
+
+    fn roll(&mut self) {
+        let elapsed = self.window_start.elapsed();
+        if elapsed >= LOAD_WINDOW * 2 {
+            self.previous = 0;
+            self.current = 0;
+            self.window_start = Instant::now();
+        } else if elapsed >= LOAD_WINDOW {
+            self.previous = self.current;
+            self.current = 0;
+            self.window_start += LOAD_WINDOW;
+        }
+    }
+
+    pub(crate) fn add(&mut self, bytes: u64) {
+        self.roll();
+        self.current += bytes;
+    }
+
+    pub(crate) fn recent(&mut self) -> u64 {
+        self.roll();
+        let overlap = 1.0 - self.window_start.elapsed().as_secs_f64() / LOAD_WINDOW.as_secs_f64();
+        self.current + (self.previous as f64 * overlap.max(0.0)) as u64
+    }
+}
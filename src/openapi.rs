@@ -0,0 +1,448 @@
+//! OpenAPI document of the gateway's HTTP API.
+//!
+//! The document is generated from [`ROUTES`], the table of every endpoint the
+//! gateway serves under [`API_PREFIX`], so client SDKs can be generated from
+//! `GET /api/v1/openapi.json`. The unprefixed legacy paths stay served as
+//! aliases but are not described.
+
+use serde_json::{Map, Value, json};
+
+/// Prefix of the versioned HTTP API
+pub const API_PREFIX: &str = "/api/v1";
+
+/// Where a route's parameter goes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum In {
+    Path,
+    Query,
+    Header,
+}
+
+impl In {
+    fn as_str(&self) -> &'static str {
+        match self {
+            In::Path => "path",
+            In::Query => "query",
+            In::Header => "header",
+        }
+    }
+}
+
+/// A route parameter, always a string
+#[derive(Debug, Clone, Copy)]
+pub struct Param {
+    pub name: &'static str,
+    pub location: In,
+    pub description: &'static str,
+}
+
+/// What a route answers with
+#[derive(Debug, Clone, Copy)]
+pub enum Body {
+    /// `application/json`, described by a schema of [`schemas`]
+    Json(&'static str),
+    /// Raw file bytes
+    Binary,
+    /// `text/event-stream` of [`schemas`]' `Progress` events
+    EventStream,
+}
+
+/// One endpoint of the API
+#[derive(Debug, Clone, Copy)]
+pub struct Route {
+    pub method: &'static str,
+    /// Path below [`API_PREFIX`], with `{param}` placeholders
+    pub path: &'static str,
+    pub operation_id: &'static str,
+    pub summary: &'static str,
+    pub params: &'static [Param],
+    /// Request body, for uploads
+    pub request: Option<Body>,
+    pub response: Body,
+    /// Status codes besides `200` and `500`, with their meaning
+    pub errors: &'static [(u16, &'static str)],
+}
+
+const NAME: Param = Param {
+    name: "name",
+    location: In::Path,
+    description: "File name, URL-escaped",
+};
+
+const TOKEN_HEADER: Param = Param {
+    name: "X-Transfer-Token",
+    location: In::Header,
+    description: "Transfer token to track the upload under",
+};
+
+/// Every endpoint of the API
+pub const ROUTES: &[Route] = &[
+    Route {
+        method: "get",
+        path: "/openapi.json",
+        operation_id: "getOpenApi",
+        summary: "This document",
+        params: &[],
+        request: None,
+        response: Body::Json("OpenApi"),
+        errors: &[],
+    },
+    Route {
+        method: "get",
+        path: "/netmap/get",
+        operation_id: "getNetmap",
+        summary: "Status of every node, by port",
+        params: &[],
+        request: None,
+        response: Body::Json("Netmap"),
+        errors: &[],
+    },
+    Route {
+        method: "get",
+        path: "/topology/get",
+        operation_id: "getTopology",
+        summary: "A ring node's `TOPOLOGY GET JSON` document",
+        params: &[],
+        request: None,
+        response: Body::Json("Topology"),
+        errors: &[],
+    },
+    Route {
+        method: "get",
+        path: "/file/list",
+        operation_id: "listFiles",
+        summary: "Every file stored in the ring",
+        params: &[],
+        request: None,
+        response: Body::Json("FileList"),
+        errors: &[],
+    },
+    Route {
+        method: "get",
+        path: "/cluster/stats",
+        operation_id: "getClusterStats",
+        summary: "A ring node's `CLUSTER STATS` report: totals over every netmap member",
+        params: &[],
+        request: None,
+        response: Body::Json("ClusterStats"),
+        errors: &[],
+    },
+    Route {
+        method: "get",
+        path: "/stats/usage",
+        operation_id: "getUsage",
+        summary: "Logical and physical size of the ring's files, and disk usage of every node",
+        params: &[Param {
+            name: "prefix",
+            location: In::Query,
+            description: "Only count files whose name starts with this",
+        }],
+        request: None,
+        response: Body::Json("Usage"),
+        errors: &[],
+    },
+    Route {
+        method: "get",
+        path: "/ring/verify",
+        operation_id: "verifyRing",
+        summary: "Walk, netmap, ping and file chunk checks of the ring, as `verify-ring` runs them",
+        params: &[],
+        request: None,
+        response: Body::Json("RingReport"),
+        errors: &[],
+    },
+    Route {
+        method: "get",
+        path: "/file/pull/{name}",
+        operation_id: "pullFile",
+        summary: "Download a file through the least loaded node",
+        params: &[
+            NAME,
+            Param {
+                name: "token",
+                location: In::Query,
+                description: "Transfer token to track the download under",
+            },
+            Param {
+                name: "If-None-Match",
+                location: In::Header,
+                description: "ETag of a copy the client already has",
+            },
+            Param {
+                name: "Range",
+                location: In::Header,
+                description: "`bytes=<n>-` resumes a download at byte n (206, uncompressed)",
+            },
+        ],
+        request: None,
+        response: Body::Binary,
+        errors: &[
+            (304, "The file still has the given ETag"),
+            (416, "The range starts past the end of the file"),
+        ],
+    },
+    Route {
+        method: "get",
+        path: "/file/manifest/{name}",
+        operation_id: "getFileManifest",
+        summary: "Chunks of a file, with their holder and hash",
+        params: &[NAME],
+        request: None,
+        response: Body::Json("FileManifest"),
+        errors: &[(404, "No such file")],
+    },
+    Route {
+        method: "get",
+        path: "/file/progress/{token}",
+        operation_id: "followProgress",
+        summary: "Progress events of a transfer",
+        params: &[Param {
+            name: "token",
+            location: In::Path,
+            description: "Gateway or node transfer token",
+        }],
+        request: None,
+        response: Body::EventStream,
+        errors: &[],
+    },
+    Route {
+        method: "post",
+        path: "/file/push",
+        operation_id: "pushFile",
+        summary: "Upload a file, streamed into the ring",
+        params: &[
+            Param {
+                name: "X-Filename",
+                location: In::Header,
+                description: "Name to store the file under",
+            },
+            TOKEN_HEADER,
+            Param {
+                name: "X-Push-Mode",
+                location: In::Header,
+                description: "`fail`, `overwrite` or `version`, when the name is taken",
+            },
+            Param {
+                name: "X-Push-Place",
+                location: In::Header,
+                description: "Placement constraints, e.g. `zone=eu-west,disk=ssd`",
+            },
+        ],
+        request: Some(Body::Binary),
+        response: Body::Json("PushResult"),
+        errors: &[(413, "Content-Length over the gateway's upload limit")],
+    },
+    Route {
+        method: "post",
+        path: "/network/heal",
+        operation_id: "healNetwork",
+        summary: "Trigger a ring-wide network heal",
+        params: &[],
+        request: None,
+        response: Body::Json("Message"),
+        errors: &[],
+    },
+    Route {
+        method: "post",
+        path: "/node/{port}/kill",
+        operation_id: "killNode",
+        summary: "Kill a node process",
+        params: &[Param {
+            name: "port",
+            location: In::Path,
+            description: "Port of the node",
+        }],
+        request: None,
+        response: Body::Json("Message"),
+        errors: &[(400, "Malformed port")],
+    },
+];
+
+/// Schemas of the JSON bodies
+fn schemas() -> Value {
+    let string = json!({ "type": "string" });
+    let integer = json!({ "type": "integer", "format": "int64", "minimum": 0 });
+    json!({
+        "OpenApi": { "type": "object", "description": "An OpenAPI 3.0 document" },
+        "Netmap": {
+            "type": "object",
+            "additionalProperties": { "type": "string", "enum": ["Alive", "Suspect", "Dead"] }
+        },
+        "Topology": { "type": "object", "description": "`TOPOLOGY GET JSON` document" },
+        "FileList": {
+            "type": "array",
+            "items": {
+                "type": "object",
+                "required": ["name", "start", "size"],
+                "properties": {
+                    "name": string,
+                    "start": { "type": "integer", "description": "Port of the file's start node" },
+                    "size": integer
+                }
+            }
+        },
+        "FileManifest": {
+            "type": "object",
+            "required": ["name", "size", "parts", "chunks"],
+            "properties": {
+                "name": string,
+                "size": integer,
+                "parts": integer,
+                "chunks": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "required": ["index", "name", "offset", "size"],
+                        "properties": {
+                            "index": integer,
+                            "name": string,
+                            "offset": integer,
+                            "size": integer,
+                            "node": { "type": "string", "nullable": true },
+                            "sha256": { "type": "string", "nullable": true }
+                        }
+                    }
+                }
+            }
+        },
+        "Progress": {
+            "type": "object",
+            "required": ["token", "kind", "name", "bytes", "total", "hop"],
+            "properties": {
+                "token": string,
+                "kind": { "type": "string", "enum": ["push", "pull"] },
+                "name": string,
+                "bytes": integer,
+                "total": integer,
+                "hop": string
+            }
+        },
+        "ClusterStats": {
+            "type": "object",
+            "description": "`CLUSTER STATS` document: `nodes`, `dead`, `unreachable`, `files`, `logical_bytes`, `disk_bytes`, `chunks`, `backups`, `avg_hop_latency_ms` and one entry per member"
+        },
+        "Usage": {
+            "type": "object",
+            "required": ["files", "nodes"],
+            "properties": {
+                "files": {
+                    "type": "object",
+                    "description": "`FILE DU JSON` document: `files`, `versions`, `logical`, `physical` and one entry per file"
+                },
+                "nodes": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "description": "`NODE DU JSON` document: `node`, `bytes`, `files` and one entry per directory"
+                    }
+                }
+            }
+        },
+        "RingReport": {
+            "type": "object",
+            "required": ["ok", "checks"],
+            "properties": {
+                "ok": { "type": "boolean", "description": "Whether every check passed" },
+                "checks": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "required": ["name", "ok", "detail"],
+                        "properties": {
+                            "name": { "type": "string", "enum": ["walk", "netmap", "ping", "files"] },
+                            "ok": { "type": "boolean" },
+                            "detail": string
+                        }
+                    }
+                }
+            }
+        },
+        "PushResult": {
+            "type": "object",
+            "required": ["status", "token"],
+            "properties": { "status": string, "token": string }
+        },
+        "Message": {
+            "type": "object",
+            "required": ["message"],
+            "properties": { "message": string }
+        }
+    })
+}
+
+fn content(body: Body) -> Value {
+    match body {
+        Body::Json(schema) => json!({
+            "application/json": { "schema": { "$ref": format!("#/components/schemas/{}", schema) } }
+        }),
+        Body::Binary => json!({
+            "application/octet-stream": { "schema": { "type": "string", "format": "binary" } }
+        }),
+        Body::EventStream => json!({
+            "text/event-stream": {
+                "schema": { "type": "string" },
+                "x-events": {
+                    "progress": "#/components/schemas/Progress",
+                    "done": "#/components/schemas/Progress",
+                    "error": "#/components/schemas/Message"
+                }
+            }
+        }),
+    }
+}
+
+fn operation(route: &Route) -> Value {
+    let params: Vec<Value> = route
+        .params
+        .iter()
+        .map(|p| {
+            json!({
+                "name": p.name,
+                "in": p.location.as_str(),
+                "required": p.location == In::Path,
+                "description": p.description,
+                "schema": { "type": "string" }
+            })
+        })
+        .collect();
+    let mut responses = Map::new();
+    responses.insert(
+        "200".to_string(),
+        json!({ "description": "OK", "content": content(route.response) }),
+    );
+    for (status, description) in route.errors {
+        responses.insert(status.to_string(), json!({ "description": description }));
+    }
+    responses.insert("500".to_string(), json!({ "description": "Ring error" }));
+
+    let mut op = json!({
+        "operationId": route.operation_id,
+        "summary": route.summary,
+        "parameters": params,
+        "responses": responses
+    });
+    if let Some(body) = route.request {
+        op["requestBody"] = json!({ "required": true, "content": content(body) });
+    }
+    op
+}
+
+/// The OpenAPI 3.0 document of every route in [`ROUTES`]
+pub fn spec() -> Value {
+    let mut paths = Map::new();
+    for route in ROUTES {
+        let item = paths
+            .entry(format!("{}{}", API_PREFIX, route.path))
+            .or_insert_with(|| json!({}));
+        item[route.method] = operation(route);
+    }
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "OuroborosFS Gateway API",
+            "version": env!("CARGO_PKG_VERSION")
+        },
+        "paths": paths,
+        "components": { "schemas": schemas() }
+    })
+}
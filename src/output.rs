@@ -0,0 +1,70 @@
+//! How the command-line tools print their results: `--output text|json`.
+//!
+//! Text is one line per result, as it has always been (`PUSHED <name> ...`).
+//! JSON is one object per line (JSON Lines), whatever the command, so scripts
+//! can read a long-running command's output as it comes. Every object has an
+//! `"event"` field naming what it reports (`"pushed"`, `"failed"`,
+//! `"imported"`, `"error"`, ...); its other fields are the result's.
+
+use serde::Serialize;
+use serde_json::Value;
+use std::{fmt, str::FromStr};
+
+/// How results are printed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// One human-readable line per result
+    #[default]
+    Text,
+    /// One JSON object per result, on a line of its own
+    Json,
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            OutputFormat::Text => "text",
+            OutputFormat::Json => "json",
+        })
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            other => Err(format!(
+                "invalid output format '{}': expected text or json",
+                other
+            )),
+        }
+    }
+}
+
+impl OutputFormat {
+    /// Prints one result: the `text` line, or `value` as a JSON line tagged
+    /// with `event`
+    pub fn emit<T: Serialize>(self, event: &str, text: impl fmt::Display, value: &T) {
+        match self {
+            OutputFormat::Text => println!("{}", text),
+            OutputFormat::Json => println!("{}", json_line(event, value)),
+        }
+    }
+}
+
+/// `value` as one line of JSON, with `"event": <event>` added. A value that
+/// is not an object goes under `"value"`.
+pub fn json_line<T: Serialize>(event: &str, value: &T) -> String {
+    let mut object = match serde_json::to_value(value) {
+        Ok(Value::Object(object)) => object,
+        Ok(other) => [("value".to_string(), other)].into_iter().collect(),
+        Err(e) => [("error".to_string(), Value::from(e.to_string()))]
+            .into_iter()
+            .collect(),
+    };
+    object.insert("event".to_string(), Value::from(event));
+    Value::Object(object).to_string()
+}
@@ -0,0 +1,497 @@
+//! Packfiles: small chunks kept together in larger segment files.
+//!
+//! Every chunk is stored as its own file, so a node holding many small files
+//! spends an inode on each, and every pass over `content/` or `backup/`
+//! (scrubbing, the startup inventory) lists them all. With `pack-threshold`
+//! set, a background task moves the chunks up to that size, once older than
+//! [`PACK_MIN_AGE`], into segment files appended one after the other:
+//!
+//! ```text
+//! <data_dir>/<port>/pack/content/<n>.seg
+//! <data_dir>/<port>/pack/content/index
+//! ```
+//!
+//! The index (JSON, rewritten through a temporary file) says where each chunk
+//! lies. Chunks are always written loose first, and a loose file wins over a
+//! packed copy of the same chunk. Deleting or rewriting a packed chunk leaves
+//! its bytes as dead space in its segment; the same task rewrites a segment
+//! more than half dead, appending its live chunks to the open segment, and
+//! deletes it. Segments are only ever appended to or deleted, so a reader
+//! holding one open keeps consistent bytes. Packed chunks keep their manifest
+//! entries.
+
+use crate::{disk::IoClass, node::Node};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    io::{self, SeekFrom},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{fs, io::AsyncSeekExt, sync::MutexGuard};
+
+/// A segment takes no more chunks once this big
+pub const SEGMENT_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Time between two packing passes
+pub const PACK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Chunks written more recently than this are left loose: they may still be
+/// rewritten, and are likely to be read soon
+pub const PACK_MIN_AGE: Duration = Duration::from_secs(60);
+
+/// Index of one directory's packed chunks, next to its segments
+const INDEX_FILE: &str = "index";
+
+/// Where a packed chunk lies
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Slot {
+    pub segment: u64,
+    pub offset: u64,
+    pub len: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Index {
+    /// Disk (sanitized) chunk name -> where it lies
+    chunks: BTreeMap<String, Slot>,
+    /// Segment -> bytes written to it, live or dead
+    segments: BTreeMap<u64, u64>,
+}
+
+/// The packed chunks of one directory (`content` or `backup`)
+#[derive(Debug)]
+pub struct Pack {
+    dir: PathBuf,
+    index: Mutex<Index>,
+}
+
+/// How packed a directory is, as reported by `NODE METRICS`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PackStats {
+    pub chunks: u64,
+    pub segments: u64,
+    /// Bytes in segments, dead ones included
+    pub bytes: u64,
+    /// Bytes of chunks since deleted or rewritten
+    pub dead_bytes: u64,
+}
+
+impl Pack {
+    /// Reads the index kept in `dir`. A missing or unreadable one leaves the
+    /// pack empty.
+    fn load(dir: PathBuf) -> Self {
+        let path = dir.join(INDEX_FILE);
+        let index = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|e| {
+                tracing::warn!(path = %path.display(), error = %e, "Ignoring unreadable pack index");
+                Index::default()
+            }),
+            Err(_) => Index::default(),
+        };
+        Self {
+            dir,
+            index: Mutex::new(index),
+        }
+    }
+
+    fn index(&self) -> std::sync::MutexGuard<'_, Index> {
+        self.index.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Writes the index to its file, through a temporary one so a crash
+    /// leaves the old or the new version
+    fn save(&self, index: &Index) {
+        let path = self.dir.join(INDEX_FILE);
+        let tmp = path.with_extension("tmp");
+        let res = serde_json::to_vec(index)
+            .map_err(io::Error::other)
+            .and_then(|bytes| std::fs::write(&tmp, bytes))
+            .and_then(|()| std::fs::rename(&tmp, &path));
+        if let Err(e) = res {
+            tracing::warn!(path = %path.display(), error = %e, "Cannot save pack index");
+        }
+    }
+
+    fn segment_path(&self, segment: u64) -> PathBuf {
+        self.dir.join(format!("{}.seg", segment))
+    }
+
+    /// The segment holding `fname`, and where in it
+    pub fn locate(&self, fname: &str) -> Option<(PathBuf, Slot)> {
+        let slot = *self.index().chunks.get(fname)?;
+        Some((self.segment_path(slot.segment), slot))
+    }
+
+    /// Disk names of the chunks packed here
+    pub fn names(&self) -> Vec<String> {
+        self.index().chunks.keys().cloned().collect()
+    }
+
+    /// Forgets the packed copy of `fname`, its bytes becoming dead space
+    pub fn remove(&self, fname: &str) {
+        let mut index = self.index();
+        if index.chunks.remove(fname).is_some() {
+            self.save(&index);
+        }
+    }
+
+    pub fn stats(&self) -> PackStats {
+        let index = self.index();
+        let bytes: u64 = index.segments.values().sum();
+        let live: u64 = index.chunks.values().map(|slot| slot.len).sum();
+        PackStats {
+            chunks: index.chunks.len() as u64,
+            segments: index.segments.len() as u64,
+            bytes,
+            dead_bytes: bytes.saturating_sub(live),
+        }
+    }
+
+    /// Appends `chunks` to the open segment (moving on to a new one when it
+    /// is full) and syncs them. They only count as packed once
+    /// [`Pack::insert`]ed and the index saved.
+    async fn append(&self, node: &Node, chunks: &[Vec<u8>]) -> io::Result<Vec<Slot>> {
+        fs::create_dir_all(&self.dir).await?;
+        let mut slots = Vec::with_capacity(chunks.len());
+        let mut open: Option<(u64, fs::File)> = None;
+        for data in chunks {
+            let (segment, offset) = match self.index().segments.last_key_value() {
+                Some((&segment, &size)) if size < SEGMENT_SIZE => (segment, size),
+                Some((&segment, _)) => (segment + 1, 0),
+                None => (0, 0),
+            };
+            if open.as_ref().map(|(s, _)| *s) != Some(segment) {
+                if let Some((_, file)) = open.take() {
+                    file.sync_data().await?;
+                }
+                let file = fs::OpenOptions::new()
+                    .create(true)
+                    .write(true)
+                    .truncate(false)
+                    .open(self.segment_path(segment))
+                    .await?;
+                open = Some((segment, file));
+            }
+            let Some((_, file)) = open.as_mut() else {
+                unreachable!("a segment was just opened");
+            };
+            // Whatever a crash left past the recorded size is written over
+            file.seek(SeekFrom::Start(offset)).await?;
+            node.disk.write_all(IoClass::Scrub, file, data).await?;
+            let len = data.len() as u64;
+            self.index().segments.insert(segment, offset + len);
+            slots.push(Slot {
+                segment,
+                offset,
+                len,
+            });
+        }
+        if let Some((_, file)) = open {
+            file.sync_data().await?;
+        }
+        Ok(slots)
+    }
+
+    /// Records `fname` as packed at `slot`, unless `expected` is given and
+    /// no longer where the index has it (the chunk was deleted or rewritten)
+    fn insert(&self, fname: &str, slot: Slot, expected: Option<Slot>) -> bool {
+        let mut index = self.index();
+        if expected.is_some() && index.chunks.get(fname).copied() != expected {
+            return false;
+        }
+        index.chunks.insert(fname.to_string(), slot);
+        true
+    }
+}
+
+/// The packs of a node's `content/` and `backup/` directories
+#[derive(Debug)]
+pub struct PackStore {
+    content: Pack,
+    backup: Pack,
+    /// Held while a loose chunk file is replaced or deleted, so the packing
+    /// task never deletes one it did not pack
+    files: tokio::sync::Mutex<()>,
+}
+
+impl PackStore {
+    /// Reads the pack indexes kept in `data_dir`
+    pub fn load(data_dir: &Path) -> Self {
+        let dir = data_dir.join("pack");
+        Self {
+            content: Pack::load(dir.join("content")),
+            backup: Pack::load(dir.join("backup")),
+            files: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// The pack of `subdir` (`content` or `backup`)
+    pub fn of(&self, subdir: &str) -> &Pack {
+        match subdir {
+            "backup" => &self.backup,
+            _ => &self.content,
+        }
+    }
+
+    /// To be held while replacing or deleting a loose chunk file
+    pub async fn lock_files(&self) -> MutexGuard<'_, ()> {
+        self.files.lock().await
+    }
+
+    /// Both packs' stats, `content` first
+    pub fn stats(&self) -> [(&'static str, PackStats); 2] {
+        [
+            ("content", self.content.stats()),
+            ("backup", self.backup.stats()),
+        ]
+    }
+}
+
+/// A stored chunk opened for reading: `len` bytes from the file's position
+#[derive(Debug)]
+pub struct ChunkFile {
+    pub file: fs::File,
+    pub len: u64,
+}
+
+/// Opens chunk `fname` (a disk name) of `subdir`, loose or packed
+pub async fn open(node: &Node, subdir: &str, fname: &str) -> io::Result<ChunkFile> {
+    match fs::File::open(node.data_dir.join(subdir).join(fname)).await {
+        Ok(file) => {
+            let len = file.metadata().await?.len();
+            return Ok(ChunkFile { file, len });
+        }
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        Err(_) => {}
+    }
+    // Its segment may be rewritten between the lookup and the open: look again
+    for _ in 0..2 {
+        let Some((path, slot)) = node.packs.of(subdir).locate(fname) else {
+            break;
+        };
+        match fs::File::open(&path).await {
+            Ok(mut file) => {
+                file.seek(SeekFrom::Start(slot.offset)).await?;
+                return Ok(ChunkFile {
+                    file,
+                    len: slot.len,
+                });
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Err(io::ErrorKind::NotFound.into())
+}
+
+/// Size of chunk `fname` of `subdir`, loose or packed
+pub async fn size(node: &Node, subdir: &str, fname: &str) -> Option<u64> {
+    match fs::metadata(node.data_dir.join(subdir).join(fname)).await {
+        Ok(meta) => Some(meta.len()),
+        Err(_) => node
+            .packs
+            .of(subdir)
+            .locate(fname)
+            .map(|(_, slot)| slot.len),
+    }
+}
+
+/// Reads the whole chunk `fname` of `subdir`, under disk permits of `class`
+pub async fn read(node: &Node, class: IoClass, subdir: &str, fname: &str) -> io::Result<Vec<u8>> {
+    read_all(node, class, open(node, subdir, fname).await?).await
+}
+
+async fn read_all(node: &Node, class: IoClass, mut chunk: ChunkFile) -> io::Result<Vec<u8>> {
+    let mut data = vec![0u8; chunk.len as usize];
+    let mut filled = 0;
+    while filled < data.len() {
+        let n = node
+            .disk
+            .read(class, &mut chunk.file, &mut data[filled..])
+            .await?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        filled += n;
+    }
+    Ok(data)
+}
+
+/// Bytes of loose chunks read before they are appended and the index saved
+const PACK_BATCH: u64 = 16 * 1024 * 1024;
+
+/// Background packing: every [`PACK_INTERVAL`] while `pack-threshold` is
+/// set, packs the small loose chunks of `content/` and `backup/` and
+/// rewrites the segments more than half dead (see the module docs).
+pub(crate) async fn spawn_pack_loop(node: Arc<Node>) {
+    loop {
+        tokio::time::sleep(PACK_INTERVAL).await;
+        let threshold = node.settings().await.pack_threshold;
+        if threshold == 0 {
+            continue;
+        }
+        for subdir in ["content", "backup"] {
+            let packed = pack_loose(&node, subdir, threshold).await;
+            let rewritten = rewrite_segments(&node, subdir).await;
+            if packed > 0 || rewritten > 0 {
+                tracing::info!(node = %node.port, subdir, packed, rewritten, "Pack: Compacted chunks");
+            }
+        }
+    }
+}
+
+/// A loose chunk about to be packed, as it was when read
+struct Loose {
+    fname: String,
+    path: PathBuf,
+    meta: std::fs::Metadata,
+    data: Vec<u8>,
+}
+
+/// Moves the loose chunks of `subdir` up to `threshold` bytes into its pack.
+/// Returns how many.
+async fn pack_loose(node: &Node, subdir: &str, threshold: u64) -> usize {
+    let Ok(mut entries) = fs::read_dir(node.data_dir.join(subdir)).await else {
+        return 0;
+    };
+    let mut packed = 0;
+    let mut batch = Vec::new();
+    let mut batch_bytes = 0;
+    loop {
+        let entry = entries.next_entry().await.ok().flatten();
+        if let Some(entry) = &entry
+            && let Ok(meta) = entry.metadata().await
+        {
+            let recent = meta
+                .modified()
+                .map(|t| t.elapsed().unwrap_or_default() < PACK_MIN_AGE)
+                .unwrap_or(true);
+            if meta.is_file() && meta.len() <= threshold && !recent {
+                let fname = entry.file_name().to_string_lossy().into_owned();
+                match read(node, IoClass::Scrub, subdir, &fname).await {
+                    Ok(data) => {
+                        batch_bytes += data.len() as u64;
+                        batch.push(Loose {
+                            fname,
+                            path: entry.path(),
+                            meta,
+                            data,
+                        });
+                    }
+                    Err(e) => {
+                        tracing::warn!(node = %node.port, chunk = %fname, subdir, error = ?e, "Pack: Could not read chunk")
+                    }
+                }
+            }
+        }
+        if entry.is_none() || batch_bytes >= PACK_BATCH {
+            match pack_batch(node, subdir, std::mem::take(&mut batch)).await {
+                Ok(n) => packed += n,
+                Err(e) => {
+                    tracing::warn!(node = %node.port, subdir, error = ?e, "Pack: Could not pack chunks");
+                    return packed;
+                }
+            }
+            batch_bytes = 0;
+        }
+        if entry.is_none() {
+            return packed;
+        }
+    }
+}
+
+/// Appends `batch` to the pack of `subdir`, then swaps each loose file that
+/// did not change meanwhile for its packed copy
+async fn pack_batch(node: &Node, subdir: &str, batch: Vec<Loose>) -> io::Result<usize> {
+    if batch.is_empty() {
+        return Ok(0);
+    }
+    let pack = node.packs.of(subdir);
+    let data: Vec<Vec<u8>> = batch.iter().map(|loose| loose.data.clone()).collect();
+    let slots = pack.append(node, &data).await?;
+
+    let _files = node.packs.lock_files().await;
+    let mut packed = Vec::new();
+    for (loose, slot) in batch.iter().zip(slots) {
+        let Ok(now) = fs::metadata(&loose.path).await else {
+            continue;
+        };
+        if now.len() == loose.meta.len() && now.modified().ok() == loose.meta.modified().ok() {
+            pack.insert(&loose.fname, slot, None);
+            packed.push(&loose.path);
+        }
+    }
+    // The index first: a crash in between leaves a loose copy, which wins
+    pack.save(&pack.index());
+    for path in &packed {
+        fs::remove_file(path).await?;
+    }
+    Ok(packed.len())
+}
+
+/// Rewrites the segments of `subdir` more than half dead, but the open one.
+/// Returns how many.
+async fn rewrite_segments(node: &Node, subdir: &str) -> usize {
+    let pack = node.packs.of(subdir);
+    let sparse: Vec<u64> = {
+        let index = pack.index();
+        let open = index.segments.last_key_value().map(|(&s, _)| s);
+        let mut live: BTreeMap<u64, u64> = BTreeMap::new();
+        for slot in index.chunks.values() {
+            *live.entry(slot.segment).or_default() += slot.len;
+        }
+        index
+            .segments
+            .iter()
+            .filter(|(s, size)| {
+                Some(**s) != open && live.get(*s).copied().unwrap_or(0) * 2 < **size
+            })
+            .map(|(&s, _)| s)
+            .collect()
+    };
+
+    let mut rewritten = 0;
+    for segment in sparse {
+        let res = async {
+            let chunks: Vec<(String, Slot)> = pack
+                .index()
+                .chunks
+                .iter()
+                .filter(|(_, slot)| slot.segment == segment)
+                .map(|(name, slot)| (name.clone(), *slot))
+                .collect();
+            let mut data = Vec::with_capacity(chunks.len());
+            for (_, slot) in &chunks {
+                let mut file = fs::File::open(pack.segment_path(segment)).await?;
+                file.seek(SeekFrom::Start(slot.offset)).await?;
+                let chunk = ChunkFile {
+                    file,
+                    len: slot.len,
+                };
+                data.push(read_all(node, IoClass::Scrub, chunk).await?);
+            }
+            let slots = pack.append(node, &data).await?;
+
+            let _files = node.packs.lock_files().await;
+            for ((fname, old), slot) in chunks.iter().zip(slots) {
+                pack.insert(fname, slot, Some(*old));
+            }
+            {
+                let mut index = pack.index();
+                index.segments.remove(&segment);
+                pack.save(&index);
+            }
+            fs::remove_file(pack.segment_path(segment)).await
+        }
+        .await;
+        match res {
+            Ok(()) => rewritten += 1,
+            Err(e) => {
+                tracing::warn!(node = %node.port, subdir, segment, error = ?e, "Pack: Could not rewrite segment")
+            }
+        }
+    }
+    rewritten
+}
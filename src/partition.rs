@@ -0,0 +1,77 @@
+//! Network partition detection and degraded mode.
+//!
+//! A node that can no longer reach most of its ring cannot tell whether the
+//! others died or it was cut off from them. Healing from the cut-off side
+//! would mark live nodes dead and respawn them, so both sides of the cut end
+//! up running the same nodes. Instead, a node that cannot reach a majority
+//! of its netmap enters a degraded mode:
+//!
+//! - it refuses client commands that store data or change the ring (see
+//!   [`crate::Command::changes_ring`]) with `ERR DEGRADED`,
+//! - it stops marking peers dead and respawning them,
+//! - it emits [`crate::NodeEvent::PartitionSuspected`].
+//!
+//! It keeps pinging every peer, dead ones included, and leaves the mode
+//! ([`crate::NodeEvent::PartitionRecovered`]) as soon as it reaches a
+//! majority again, then reconciles its state with the rest of the ring (see
+//! [`crate::reconcile`]).
+//!
+//! What counts is the outcome of this node's own last ping of each peer (the
+//! gossip loop pings its next hop and random peers every round). A peer never
+//! pinged counts as reached, unless the netmap marks it `Dead`: then it is
+//! left out, as a node that died before this one could check it. Rings of
+//! fewer than [`MIN_RING`] nodes never degrade, since one dead peer out of
+//! two looks exactly like a partition.
+
+use crate::NodeStatus;
+use std::collections::HashMap;
+
+/// Smallest ring, this node included, that can tell a partition from deaths
+pub const MIN_RING: usize = 3;
+
+/// How many of its peers a node reached, itself included
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quorum {
+    pub reached: usize,
+    pub known: usize,
+}
+
+impl Quorum {
+    /// Whether the node is on the majority side (or in a ring too small to say)
+    pub fn holds(&self) -> bool {
+        self.known < MIN_RING || self.reached * 2 > self.known
+    }
+}
+
+/// Outcome of this node's last ping of each peer
+#[derive(Debug, Default)]
+pub struct Reachability(HashMap<String, bool>);
+
+impl Reachability {
+    pub fn record(&mut self, port: &str, reached: bool) {
+        self.0.insert(port.to_string(), reached);
+    }
+
+    /// The quorum among `peers` (the netmap without this node)
+    pub fn quorum(&mut self, peers: &HashMap<String, NodeStatus>) -> Quorum {
+        self.0.retain(|port, _| peers.contains_key(port));
+        let mut quorum = Quorum {
+            reached: 1,
+            known: 1,
+        };
+        for (port, status) in peers {
+            match self.0.get(port) {
+                Some(reached) => {
+                    quorum.known += 1;
+                    quorum.reached += usize::from(*reached);
+                }
+                None if *status != NodeStatus::Dead => {
+                    quorum.known += 1;
+                    quorum.reached += 1;
+                }
+                None => {}
+            }
+        }
+        quorum
+    }
+}
@@ -0,0 +1,195 @@
+//! Who a gateway client is, when the gateway sits behind a load balancer.
+//!
+//! A proxy in front of the gateway hides the client's address behind its own.
+//! The gateway learns it back in two ways:
+//!
+//! - The PROXY protocol (HAProxy, versions 1 and 2): with `proxy_protocol` on,
+//!   every connection starts with a header naming the client, which the proxy
+//!   writes before anything else. The listener then takes no connection
+//!   without one, so it should only be reachable through the proxy.
+//! - `Forwarded: for=...` or `X-Forwarded-For` headers on HTTP requests, only
+//!   believed from the [`TrustedProxies`]. The client is the last address of
+//!   the chain that is not itself a trusted proxy.
+
+use std::{
+    collections::HashMap,
+    fmt, io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    str::FromStr,
+};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt};
+
+/// First bytes of a version 2 header
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+/// Longest version 1 header, CRLF included
+const V1_MAX_LEN: usize = 107;
+
+/// How the gateway finds the client behind a proxy
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProxyOptions {
+    /// Every connection starts with a PROXY protocol header
+    pub proxy_protocol: bool,
+    /// Peers whose forwarding headers are believed
+    pub trusted: TrustedProxies,
+}
+
+impl ProxyOptions {
+    /// The client of an HTTP request with `headers`, that came from `peer`
+    pub fn client(&self, headers: &HashMap<String, String>, peer: IpAddr) -> IpAddr {
+        if !self.trusted.contains(peer) {
+            return peer;
+        }
+        let chain: Vec<IpAddr> = match (headers.get("forwarded"), headers.get("x-forwarded-for")) {
+            (Some(forwarded), _) => forwarded_for(forwarded),
+            (None, Some(list)) => list.split(',').filter_map(parse_node).collect(),
+            (None, None) => return peer,
+        };
+        chain
+            .iter()
+            .rev()
+            .find(|ip| !self.trusted.contains(**ip))
+            .or(chain.first())
+            .copied()
+            .unwrap_or(peer)
+    }
+}
+
+/// Addresses of the proxies in front of the gateway: `10.0.0.1,10.0.0.2`, or
+/// `none`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrustedProxies(Vec<IpAddr>);
+
+impl TrustedProxies {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // An IPv4 peer of a dual-stack listener shows as `::ffff:a.b.c.d`
+        self.0.contains(&ip) || self.0.contains(&ip.to_canonical())
+    }
+}
+
+impl fmt::Display for TrustedProxies {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            return f.write_str("none");
+        }
+        let ips: Vec<String> = self.0.iter().map(IpAddr::to_string).collect();
+        f.write_str(&ips.join(","))
+    }
+}
+
+impl FromStr for TrustedProxies {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.trim().eq_ignore_ascii_case("none") {
+            return Ok(Self::default());
+        }
+        s.split(',')
+            .map(str::trim)
+            .filter(|ip| !ip.is_empty())
+            .map(|ip| {
+                ip.parse()
+                    .map_err(|_| format!("invalid proxy address '{}'", ip))
+            })
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
+
+/// Reads the PROXY protocol header a connection starts with. Returns the
+/// client's address, or `None` for a connection the proxy made itself
+/// (`UNKNOWN`, `LOCAL`) or for an address family without one.
+pub async fn read_header<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+) -> io::Result<Option<SocketAddr>> {
+    let invalid = |what: &str| io::Error::new(io::ErrorKind::InvalidData, what.to_string());
+    // Both versions are longer than the v2 signature
+    let mut start = [0u8; 12];
+    reader.read_exact(&mut start).await?;
+    if start == V2_SIGNATURE {
+        let mut fixed = [0u8; 4];
+        reader.read_exact(&mut fixed).await?;
+        let len = u16::from_be_bytes([fixed[2], fixed[3]]) as usize;
+        let mut body = vec![0u8; len];
+        reader.read_exact(&mut body).await?;
+        return match (fixed[0], fixed[1] >> 4) {
+            (0x20, _) => Ok(None),
+            (0x21, family) => {
+                v2_source(family, &body).ok_or_else(|| invalid("truncated PROXY v2 address"))
+            }
+            _ => Err(invalid("unsupported PROXY v2 header")),
+        };
+    }
+    if !start.starts_with(b"PROXY ") {
+        return Err(invalid("missing PROXY protocol header"));
+    }
+    let mut line = start.to_vec();
+    (&mut *reader)
+        .take((V1_MAX_LEN - start.len()) as u64)
+        .read_until(b'\n', &mut line)
+        .await?;
+    let line = std::str::from_utf8(&line).map_err(|_| invalid("invalid PROXY header"))?;
+    let line = line
+        .strip_suffix("\r\n")
+        .ok_or_else(|| invalid("PROXY header too long"))?;
+    v1_source(line).ok_or_else(|| invalid("invalid PROXY header"))
+}
+
+/// `PROXY TCP4 <src> <dst> <sport> <dport>` or `PROXY UNKNOWN ...`
+fn v1_source(line: &str) -> Option<Option<SocketAddr>> {
+    let mut words = line.split(' ').skip(1);
+    match words.next()? {
+        "UNKNOWN" => Some(None),
+        "TCP4" | "TCP6" => {
+            let (src, _dst) = (words.next()?.parse().ok()?, words.next()?);
+            let port = words.next()?.parse().ok()?;
+            Some(Some(SocketAddr::new(src, port)))
+        }
+        _ => None,
+    }
+}
+
+/// Source address of a v2 `PROXY` command, by address family
+fn v2_source(family: u8, body: &[u8]) -> Option<Option<SocketAddr>> {
+    let port = |at: usize| Some(u16::from_be_bytes(body.get(at..at + 2)?.try_into().ok()?));
+    match family {
+        // AF_INET: src, dst, sport, dport
+        0x1 => {
+            let src: [u8; 4] = body.get(..4)?.try_into().ok()?;
+            Some(Some(SocketAddr::new(Ipv4Addr::from(src).into(), port(8)?)))
+        }
+        // AF_INET6
+        0x2 => {
+            let src: [u8; 16] = body.get(..16)?.try_into().ok()?;
+            Some(Some(SocketAddr::new(Ipv6Addr::from(src).into(), port(32)?)))
+        }
+        // AF_UNSPEC, AF_UNIX: no address to report
+        _ => Some(None),
+    }
+}
+
+/// The `for=` addresses of a `Forwarded` header, in order
+fn forwarded_for(header: &str) -> Vec<IpAddr> {
+    header
+        .split([',', ';'])
+        .filter_map(|pair| {
+            let (key, value) = pair.split_once('=')?;
+            key.trim().eq_ignore_ascii_case("for").then_some(value)
+        })
+        .filter_map(parse_node)
+        .collect()
+}
+
+/// An address as forwarding headers write it: `1.2.3.4`, `1.2.3.4:80`,
+/// `"[2001:db8::1]:80"` or `2001:db8::1`. `unknown` and obfuscated names read
+/// as nothing.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    if let Ok(ip) = node.parse() {
+        return Some(ip);
+    }
+    if let Some(rest) = node.strip_prefix('[') {
+        return rest.split(']').next()?.parse().ok();
+    }
+    node.parse::<SocketAddr>().ok().map(|a| a.ip())
+}
@@ -0,0 +1,337 @@
+//! `ouroboros_fs pull`: a download checked end to end, from the client.
+//!
+//! The file's manifest (`FILE MANIFEST`) names every chunk, the node holding
+//! it and the SHA-256 recorded when it was stored. Each chunk is fetched
+//! straight from its holder (`FILE GET-CHUNK`), one after the other or all at
+//! once, and checked against that hash. A chunk its holder cannot serve, or
+//! serves with another hash, is read from its backup holders instead
+//! (`FILE GET-BACKUP-CHUNK`, found with `FILE INFO`). Nothing is returned
+//! unless every chunk passed.
+
+use crate::{
+    addr::{host_str, join_host_port},
+    auth::{self, ClusterToken},
+    checksum::{Digest, Sha256},
+    node::{ChunkView, FileManifestView},
+    protocol::encode_name,
+    ring_verify::request_with,
+    schema::RespChunk,
+};
+use serde::Serialize;
+use std::{collections::HashMap, error::Error, fmt, sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    task::JoinSet,
+};
+
+type AnyErr = Box<dyn Error + Send + Sync>;
+
+/// How long the manifest may take: every chunk's hash is read from its holder
+const MANIFEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long `FILE INFO` may take
+const INFO_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long a node has to send a chunk
+const CHUNK_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How a chunk was obtained
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "outcome", rename_all = "kebab-case")]
+pub enum ChunkOutcome {
+    /// From its holder, with the recorded hash
+    Ok,
+    /// From a backup holder, after the holder failed for `reason`
+    Repaired { reason: String },
+    /// At its full size, but no hash was recorded to check it against
+    Unverified,
+}
+
+/// One chunk of a verified pull
+#[derive(Debug, Clone, Serialize)]
+pub struct ChunkReport {
+    pub index: u32,
+    pub parts: u32,
+    /// Port of the node the bytes came from
+    pub from: String,
+    #[serde(flatten)]
+    pub outcome: ChunkOutcome,
+}
+
+impl fmt::Display for ChunkReport {
+    /// `part <i>/<parts> from=<port> ok|unverified|repaired (<reason>)`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "part {}/{} from={} ",
+            self.index + 1,
+            self.parts,
+            self.from
+        )?;
+        match &self.outcome {
+            ChunkOutcome::Ok => write!(f, "ok"),
+            ChunkOutcome::Repaired { reason } => write!(f, "repaired ({})", reason),
+            ChunkOutcome::Unverified => write!(f, "unverified (no hash recorded)"),
+        }
+    }
+}
+
+/// A file whose chunks all passed
+#[derive(Debug, Clone)]
+pub struct VerifiedFile {
+    pub data: Vec<u8>,
+    pub chunks: Vec<ChunkReport>,
+}
+
+impl VerifiedFile {
+    /// Chunks read from a backup holder
+    pub fn repaired(&self) -> usize {
+        self.chunks
+            .iter()
+            .filter(|c| matches!(c.outcome, ChunkOutcome::Repaired { .. }))
+            .count()
+    }
+}
+
+/// Downloads `name` through the node at `addr`, chunk by chunk or, with
+/// `parallel`, every chunk at once. Holders are reached on the same host, at
+/// the ports the manifest names.
+pub async fn verified_pull(addr: &str, name: &str, parallel: bool) -> Result<VerifiedFile, AnyErr> {
+    let manifest = fetch_manifest(addr, name, None).await?;
+    pull_verified(addr, manifest, parallel, None).await
+}
+
+/// Downloads the file `manifest` describes as [`verified_pull`] does,
+/// presenting `token` to every node (see [`auth::connect_with`])
+pub(crate) async fn pull_verified(
+    addr: &str,
+    manifest: FileManifestView,
+    parallel: bool,
+    token: Option<&ClusterToken>,
+) -> Result<VerifiedFile, AnyErr> {
+    let backups = Arc::new(fetch_backups(addr, &manifest.name, token).await?);
+    let host = host_str(addr).to_string();
+    let parts = manifest.parts;
+
+    let mut results: Vec<(u32, Vec<u8>, ChunkReport)> = Vec::with_capacity(parts as usize);
+    if parallel {
+        let mut fetches = JoinSet::new();
+        for chunk in manifest.chunks {
+            let (host, backups, token) = (host.clone(), Arc::clone(&backups), token.cloned());
+            fetches.spawn(async move {
+                fetch_verified(&host, &chunk, parts, &backups, token.as_ref()).await
+            });
+        }
+        while let Some(res) = fetches.join_next().await {
+            results.push(res??);
+        }
+        results.sort_by_key(|(index, _, _)| *index);
+    } else {
+        for chunk in &manifest.chunks {
+            results.push(fetch_verified(&host, chunk, parts, &backups, token).await?);
+        }
+    }
+
+    let mut data = Vec::with_capacity(usize::try_from(manifest.size)?);
+    let mut chunks = Vec::with_capacity(results.len());
+    for (_, bytes, report) in results {
+        data.extend_from_slice(&bytes);
+        chunks.push(report);
+    }
+    if data.len() as u64 != manifest.size {
+        return Err(format!(
+            "assembled {} bytes, the manifest says {}",
+            data.len(),
+            manifest.size
+        )
+        .into());
+    }
+    Ok(VerifiedFile { data, chunks })
+}
+
+pub(crate) async fn fetch_manifest(
+    addr: &str,
+    name: &str,
+    token: Option<&ClusterToken>,
+) -> Result<FileManifestView, AnyErr> {
+    let line = format!("FILE MANIFEST {}", encode_name(name));
+    let lines = request_with(addr, &line, MANIFEST_TIMEOUT, token).await?;
+    let json = lines.first().ok_or("empty FILE MANIFEST answer")?;
+    Ok(serde_json::from_str(json)?)
+}
+
+/// Backup holders of each chunk, by index, from `FILE INFO` lines
+/// (`part 2/5 node=7003 ... backup=7002,7001 ...`)
+pub(crate) async fn fetch_backups(
+    addr: &str,
+    name: &str,
+    token: Option<&ClusterToken>,
+) -> Result<HashMap<u32, Vec<String>>, AnyErr> {
+    let line = format!("FILE INFO {}", encode_name(name));
+    let mut backups = HashMap::new();
+    for row in request_with(addr, &line, INFO_TIMEOUT, token).await? {
+        let mut words = row.split_whitespace();
+        if words.next() != Some("part") {
+            continue;
+        }
+        let Some(index) = words
+            .next()
+            .and_then(|p| p.split_once('/'))
+            .and_then(|(i, _)| i.parse::<u32>().ok())
+        else {
+            continue;
+        };
+        let ports = words
+            .find_map(|w| w.strip_prefix("backup="))
+            .map(|list| {
+                list.split(',')
+                    .filter(|p| !p.is_empty() && *p != "-")
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default();
+        backups.insert(index.saturating_sub(1), ports);
+    }
+    Ok(backups)
+}
+
+/// Fetches a chunk from its holder, or from a backup holder when the holder
+/// fails or sends bytes that do not match the manifest
+async fn fetch_verified(
+    host: &str,
+    chunk: &ChunkView,
+    parts: u32,
+    backups: &HashMap<u32, Vec<String>>,
+    token: Option<&ClusterToken>,
+) -> Result<(u32, Vec<u8>, ChunkReport), AnyErr> {
+    let expected: Option<Digest> = match &chunk.sha256 {
+        Some(hash) => Some(hash.parse()?),
+        None => None,
+    };
+    let report = |from: &str, outcome| ChunkReport {
+        index: chunk.index,
+        parts,
+        from: from.to_string(),
+        outcome,
+    };
+
+    let reason = match &chunk.node {
+        Some(holder) => {
+            let addr = join_host_port(host, holder);
+            match fetch_chunk(&addr, "GET-CHUNK", chunk, token).await {
+                Ok(bytes) => match check(&bytes, chunk, expected.as_ref()) {
+                    Ok(outcome) => return Ok((chunk.index, bytes, report(holder, outcome))),
+                    Err(e) => format!("holder {}: {}", holder, e),
+                },
+                Err(e) => format!("holder {}: {}", holder, e),
+            }
+        }
+        None => "no holder known".to_string(),
+    };
+
+    let mut failures = vec![reason.clone()];
+    for port in backups.get(&chunk.index).into_iter().flatten() {
+        let addr = join_host_port(host, port);
+        let bytes = match fetch_chunk(&addr, "GET-BACKUP-CHUNK", chunk, token).await {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                failures.push(format!("backup {}: {}", port, e));
+                continue;
+            }
+        };
+        match check(&bytes, chunk, expected.as_ref()) {
+            Ok(ChunkOutcome::Ok) => {
+                let outcome = ChunkOutcome::Repaired { reason };
+                return Ok((chunk.index, bytes, report(port, outcome)));
+            }
+            Ok(outcome) => return Ok((chunk.index, bytes, report(port, outcome))),
+            Err(e) => failures.push(format!("backup {}: {}", port, e)),
+        }
+    }
+    Err(format!(
+        "chunk {}/{} ({}) could not be read: {}",
+        chunk.index + 1,
+        parts,
+        chunk.name,
+        failures.join("; ")
+    )
+    .into())
+}
+
+/// Whether the bytes are the chunk the manifest describes
+fn check(
+    bytes: &[u8],
+    chunk: &ChunkView,
+    expected: Option<&Digest>,
+) -> Result<ChunkOutcome, String> {
+    if bytes.len() as u64 != chunk.size {
+        return Err(format!("{} of {} bytes", bytes.len(), chunk.size));
+    }
+    match expected {
+        Some(expected) if Sha256::digest(bytes) != *expected => Err("hash mismatch".to_string()),
+        Some(_) => Ok(ChunkOutcome::Ok),
+        None => Ok(ChunkOutcome::Unverified),
+    }
+}
+
+/// Fetches a chunk from its holder at `addr`, presenting `token` (see
+/// [`auth::connect_with`]), and checks it against the manifest
+pub(crate) async fn fetch_checked(
+    addr: &str,
+    chunk: &ChunkView,
+    token: Option<&ClusterToken>,
+) -> Result<Vec<u8>, AnyErr> {
+    let expected: Option<Digest> = match &chunk.sha256 {
+        Some(hash) => Some(hash.parse()?),
+        None => None,
+    };
+    let bytes = fetch_chunk(addr, "GET-CHUNK", chunk, token).await?;
+    check(&bytes, chunk, expected.as_ref())?;
+    Ok(bytes)
+}
+
+/// Sends `FILE <command> <chunk>` and reads the `FILE RESP-CHUNK` answer
+async fn fetch_chunk(
+    addr: &str,
+    command: &str,
+    chunk: &ChunkView,
+    token: Option<&ClusterToken>,
+) -> Result<Vec<u8>, AnyErr> {
+    let exchange = async {
+        let (mut reader, size) = open_chunk(addr, command, chunk, token).await?;
+        let mut buf = vec![0u8; usize::try_from(size)?];
+        reader.read_exact(&mut buf).await?;
+        Ok(buf)
+    };
+    tokio::time::timeout(CHUNK_TIMEOUT, exchange)
+        .await
+        .map_err(|_| -> AnyErr { "timed out".into() })?
+}
+
+/// Sends `FILE <command> <chunk>` and reads the `FILE RESP-CHUNK` header:
+/// the chunk's bytes follow on the returned reader, as many as the returned
+/// size
+pub(crate) async fn open_chunk(
+    addr: &str,
+    command: &str,
+    chunk: &ChunkView,
+    token: Option<&ClusterToken>,
+) -> Result<(BufReader<TcpStream>, u64), AnyErr> {
+    let mut stream = auth::connect_with(addr, token).await?;
+    let line = format!("FILE {} {}\n", command, encode_name(&chunk.name));
+    stream.write_all(line.as_bytes()).await?;
+
+    let mut reader = BufReader::new(stream);
+    let mut header = String::new();
+    reader.read_line(&mut header).await?;
+    if let Some(err) = header.trim().strip_prefix("ERR") {
+        return Err(err.trim().to_string().into());
+    }
+    let header: RespChunk = header.parse()?;
+    // A bigger answer is not this chunk; do not read it
+    if header.size > chunk.size {
+        return Err(format!("{} of {} bytes", header.size, chunk.size).into());
+    }
+    Ok((reader, header.size))
+}
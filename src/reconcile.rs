@@ -0,0 +1,77 @@
+//! Reconciliation of the state both sides of a partition built apart.
+//!
+//! While cut off (see [`crate::partition`]), the two sides of a ring keep
+//! their own netmap and file tags. When a node leaves degraded mode (or on
+//! `RING RECONCILE`) it exchanges both with every other node of its netmap:
+//!
+//! - netmaps merge by incarnation, as gossip does (see [`crate::gossip`]),
+//! - files only one side knows are taken as they are,
+//! - a name both sides know with the same content (the SHA-256 of its chunk
+//!   hashes, see [`crate::node::FileManifestView::content_hash`]) keeps one
+//!   of the tags,
+//! - a name whose versions differ, or whose content cannot be checked, keeps
+//!   the newer version (see [`crate::schema::TagVersion`]) and sets the other
+//!   aside under [`conflict_name`], tagged with the name it conflicted with.
+//!   `FILE CONFLICTS` lists them until an operator sorts them out.
+//!
+//! Which version keeps the name only depends on the two tags, so every node
+//! reconciling the same versions settles on the same outcome. Files deleted
+//! on one side come back from the other: tags only ever merge.
+
+use crate::{checksum::Sha256, node::FileTag, schema::FileTags};
+use std::{cmp::Ordering, fmt};
+
+/// Marks a version set aside: `<name>.conflict-<8 hex digits>`
+pub const CONFLICT_MARK: &str = ".conflict-";
+
+/// Name the version `tag` of `name` is set aside under, the same on every node
+pub fn conflict_name(name: &str, tag: &FileTag) -> String {
+    let digest = Sha256::digest(encode(name, tag).as_bytes()).to_string();
+    format!("{}{}{}", name, CONFLICT_MARK, &digest[..8])
+}
+
+/// Whether `ours` keeps the name `name` over `theirs`: the newer version
+/// (see [`crate::schema::TagVersion`]), or between unversioned tags the one
+/// that encodes first
+pub fn keeps_name(name: &str, ours: &FileTag, theirs: &FileTag) -> bool {
+    match ours.version.cmp(&theirs.version) {
+        Ordering::Equal => encode(name, ours) <= encode(name, theirs),
+        newer => newer == Ordering::Greater,
+    }
+}
+
+/// The version `tag` of `name`, to be stored under [`conflict_name`]: its
+/// chunks stay where they are, under the name they were stored with
+pub fn set_aside(name: &str, mut tag: FileTag) -> FileTag {
+    if tag.parts > 0 {
+        tag.chunks = Some(tag.chunk_set(name).to_string());
+    }
+    tag.conflict_of = Some(name.to_string());
+    tag
+}
+
+/// `name` and `tag` as a `FILE TAGS-SET` entry
+fn encode(name: &str, tag: &FileTag) -> String {
+    FileTags::single(name, tag.clone()).to_string()
+}
+
+/// What a reconciliation did
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Reconciled {
+    /// Nodes that answered
+    pub peers: usize,
+    /// Files (or versions) taken from them
+    pub adopted: usize,
+    /// Versions newly set aside
+    pub conflicts: usize,
+}
+
+impl fmt::Display for Reconciled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "RECONCILED peers={} adopted={} conflicts={}",
+            self.peers, self.adopted, self.conflicts
+        )
+    }
+}
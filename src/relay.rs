@@ -0,0 +1,258 @@
+//! Relay mode, for nodes that cannot accept inbound connections (behind a NAT
+//! or a firewall).
+//!
+//! Such a node is started with `--relay <addr>`, naming a ring node that can.
+//! It keeps one connection open to that relay (`RELAY REGISTER <port>`) and
+//! announces it in the netmap with the label `relay=<relay port>`. A node
+//! connecting to it connects to the relay instead, starting with
+//! `RELAY CONNECT <port>`. The relay asks the relayed node, over the open
+//! connection, for a new one (`OPEN <id>`); the relayed node opens it to the
+//! relay (`RELAY ACCEPT <id>`) and to its own listener, and from then on both
+//! copy bytes each way. Hops, chunk transfers and every other command work as
+//! over a direct connection, one relay in between.
+
+use crate::{auth::ClusterToken, config::TcpOptions, net, node::Node};
+use std::{
+    collections::HashMap,
+    io,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{
+        TcpStream,
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+    },
+    sync::{mpsc, oneshot},
+};
+
+/// Netmap label carrying the port of a relayed node's relay
+pub const RELAY_LABEL: &str = "relay";
+
+/// How long a relay waits for the relayed node to open a connection it asked for
+pub const ACCEPT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Pause before reconnecting to the relay, doubled after each failure up to
+/// [`RECONNECT_MAX`]
+const RECONNECT_MIN: Duration = Duration::from_secs(1);
+const RECONNECT_MAX: Duration = Duration::from_secs(30);
+
+/// One side of a connection, as the server reads and writes it
+pub type Conn = (BufReader<OwnedReadHalf>, OwnedWriteHalf);
+
+/// How to reach a peer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Route {
+    /// At its own address
+    Direct(String),
+    /// Through the relay at `relay`, which splices the connection to the node
+    /// at `port`
+    Relayed { relay: String, port: String },
+}
+
+impl Route {
+    /// Opens a connection to the peer, ready for its first command. With a
+    /// `token`, the relay and the peer are both sent it first.
+    pub async fn connect(
+        &self,
+        opts: &TcpOptions,
+        token: Option<&ClusterToken>,
+    ) -> io::Result<TcpStream> {
+        let mut s = match self {
+            Route::Direct(addr) => net::connect(addr, opts).await?,
+            Route::Relayed { relay, port } => {
+                let mut s = net::connect(relay, opts).await?;
+                if let Some(token) = token {
+                    token.send(&mut s).await?;
+                }
+                s.write_all(format!("RELAY CONNECT {}\n", port).as_bytes())
+                    .await?;
+                s
+            }
+        };
+        if let Some(token) = token {
+            token.send(&mut s).await?;
+        }
+        Ok(s)
+    }
+}
+
+/// Relay side: the nodes relayed through this one
+#[derive(Debug, Default)]
+pub struct RelayHub {
+    /// Channel to each relayed node's `RELAY REGISTER` connection, by port
+    sessions: Mutex<HashMap<String, mpsc::UnboundedSender<u64>>>,
+    /// Connections waiting for the relayed node's `RELAY ACCEPT`, by id
+    pending: Mutex<HashMap<u64, oneshot::Sender<Conn>>>,
+    next_id: AtomicU64,
+}
+
+impl RelayHub {
+    /// Starts relaying to the node at `port`, replacing an earlier session.
+    /// Its `OPEN` requests come out of the returned receiver.
+    pub fn register(&self, port: &str) -> mpsc::UnboundedReceiver<u64> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mut sessions = self.sessions.lock().expect("relay sessions poisoned");
+        sessions.insert(port.to_string(), tx);
+        rx
+    }
+
+    /// Ends the session of `port`, unless a newer one replaced it
+    pub fn unregister(&self, port: &str) {
+        let mut sessions = self.sessions.lock().expect("relay sessions poisoned");
+        if sessions.get(port).is_some_and(|tx| tx.is_closed()) {
+            sessions.remove(port);
+        }
+    }
+
+    /// Ports relayed through this node, sorted
+    pub fn relayed(&self) -> Vec<String> {
+        let sessions = self.sessions.lock().expect("relay sessions poisoned");
+        let mut ports: Vec<String> = sessions.keys().cloned().collect();
+        ports.sort();
+        ports
+    }
+
+    /// Asks the node at `port` for a connection, which comes out of the
+    /// returned receiver under the returned id. `None` if it has no session.
+    pub fn open(&self, port: &str) -> Option<(u64, oneshot::Receiver<Conn>)> {
+        let sessions = self.sessions.lock().expect("relay sessions poisoned");
+        let session = sessions.get(port)?;
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = oneshot::channel();
+        self.pending
+            .lock()
+            .expect("relay sessions poisoned")
+            .insert(id, tx);
+        if session.send(id).is_err() {
+            self.pending
+                .lock()
+                .expect("relay sessions poisoned")
+                .remove(&id);
+            return None;
+        }
+        Some((id, rx))
+    }
+
+    /// Hands the connection opened for `id` to the node waiting for it.
+    /// Gives it back if nobody waits for that id (any more).
+    pub fn accept(&self, id: u64, conn: Conn) -> Result<(), Conn> {
+        let waiting = self
+            .pending
+            .lock()
+            .expect("relay sessions poisoned")
+            .remove(&id);
+        match waiting {
+            Some(tx) => tx.send(conn),
+            None => Err(conn),
+        }
+    }
+
+    /// Forgets the connection asked for with `id`, as its asker gave up
+    pub fn forget(&self, id: u64) {
+        self.pending
+            .lock()
+            .expect("relay sessions poisoned")
+            .remove(&id);
+    }
+}
+
+/// Copies bytes both ways between two connections until both are closed
+pub async fn splice(a: Conn, b: Conn) -> io::Result<()> {
+    let ((mut a_read, mut a_write), (mut b_read, mut b_write)) = (a, b);
+    let forward = async {
+        tokio::io::copy(&mut a_read, &mut b_write).await?;
+        b_write.shutdown().await
+    };
+    let backward = async {
+        tokio::io::copy(&mut b_read, &mut a_write).await?;
+        a_write.shutdown().await
+    };
+    tokio::try_join!(forward, backward)?;
+    Ok(())
+}
+
+/// Relayed side: keeps a session open with the relay at `relay`, and opens
+/// the connections it asks for. Reconnects, with a growing pause, whenever
+/// the session drops.
+pub(crate) async fn spawn_relay_loop(node: Arc<Node>, relay: String) {
+    let mut backoff = RECONNECT_MIN;
+    loop {
+        match hold_session(&node, &relay).await {
+            Ok(()) => {
+                tracing::warn!(node = %node.port, relay = %relay, "Relay session closed");
+                backoff = RECONNECT_MIN;
+            }
+            Err(e) => {
+                tracing::warn!(node = %node.port, relay = %relay, error = %e, "Relay session failed")
+            }
+        }
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(RECONNECT_MAX);
+    }
+}
+
+async fn hold_session(node: &Arc<Node>, relay: &str) -> io::Result<()> {
+    let stream = net::connect(relay, &node.tcp).await?;
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    if let Some(token) = &node.cluster_token {
+        token.send(&mut writer).await?;
+    }
+    writer
+        .write_all(format!("RELAY REGISTER {}\n", node.addr.port()).as_bytes())
+        .await?;
+    let mut line = String::new();
+    reader.read_line(&mut line).await?;
+    if line.trim() != "OK" {
+        return Err(io::Error::other(format!(
+            "relay refused the session: {}",
+            line.trim()
+        )));
+    }
+    tracing::info!(node = %node.port, relay = %relay, "Relay session open");
+
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).await? == 0 {
+            return Ok(());
+        }
+        let Some(id) = line
+            .trim()
+            .strip_prefix("OPEN ")
+            .and_then(|id| id.parse().ok())
+        else {
+            tracing::warn!(node = %node.port, relay = %relay, line = %line.trim(), "Unexpected line from relay");
+            continue;
+        };
+        let (node, relay) = (Arc::clone(node), relay.to_string());
+        tokio::spawn(async move {
+            if let Err(e) = open_relayed(&node, &relay, id).await {
+                tracing::debug!(node = %node.port, relay = %relay, id, error = %e, "Relayed connection failed");
+            }
+        });
+    }
+}
+
+/// Opens connection `id` to the relay and splices it to this node's listener
+async fn open_relayed(node: &Node, relay: &str, id: u64) -> io::Result<()> {
+    let mut outer = net::connect(relay, &node.tcp).await?;
+    if let Some(token) = &node.cluster_token {
+        token.send(&mut outer).await?;
+    }
+    outer
+        .write_all(format!("RELAY ACCEPT {}\n", id).as_bytes())
+        .await?;
+    let inner = net::connect(&node.port, &node.tcp).await?;
+    let (outer_read, outer_write) = outer.into_split();
+    let (inner_read, inner_write) = inner.into_split();
+    splice(
+        (BufReader::new(outer_read), outer_write),
+        (BufReader::new(inner_read), inner_write),
+    )
+    .await
+}
@@ -0,0 +1,724 @@
+//! What a node decides when a ring walk or relay reaches it, apart from I/O.
+//!
+//! Walks (`TOPOLOGY`, `NETMAP`, `NODE HEAL-HOP`) and file relays all follow the
+//! same pattern: a node adds itself to what the message carries and either
+//! passes it to its next hop or, when that hop is the start node, sends the
+//! result home. The decisions are pure functions of a [`RingState`] snapshot
+//! and the message, so they can be run over any sequence of hops without a
+//! network; `server.rs` only performs what they return.
+
+use crate::{
+    addr::port_str,
+    schema::{Member, Netmap, Topology},
+};
+use std::collections::BTreeMap;
+
+/// A node's view of the ring, as far as hops are concerned
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RingState {
+    /// This node's address
+    pub port: String,
+    /// Address of the next node, if wired
+    pub next: Option<String>,
+    /// Epoch of this node's topology snapshot
+    pub epoch: u64,
+}
+
+/// Where a walk goes after this node
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Hop<T> {
+    /// No next node: the walk ends here, unfinished
+    Stop,
+    /// Pass the walk on to the next node
+    Forward { to: String, payload: T },
+    /// The next node is the start node: send it the result
+    Done { to: String, payload: T },
+}
+
+impl<T> Hop<T> {
+    /// `Forward` to `next`, or `Done` when `next` is the start node
+    fn toward(next: &str, start_addr: &str, payload: T) -> Self {
+        if same_node(next, start_addr) {
+            Hop::Done {
+                to: start_addr.to_string(),
+                payload,
+            }
+        } else {
+            Hop::Forward {
+                to: next.to_string(),
+                payload,
+            }
+        }
+    }
+}
+
+/// Whether two addresses (or ports) name the same ring node
+pub fn same_node(a: &str, b: &str) -> bool {
+    port_str(a) == port_str(b)
+}
+
+impl RingState {
+    /// A `TOPOLOGY HOP`: the history gains this node's link, and the walk
+    /// carries the newest epoch seen so far
+    pub fn topology_hop(
+        &self,
+        start_addr: &str,
+        epoch: u64,
+        history: &Topology,
+    ) -> Hop<(u64, Topology)> {
+        let Some(next) = &self.next else {
+            return Hop::Stop;
+        };
+        let mut history = history.clone();
+        history.push(&self.port, next);
+        Hop::toward(next, start_addr, (epoch.max(self.epoch), history))
+    }
+
+    /// A `NETMAP HOP`: the entries gain this node as `me`
+    pub fn netmap_hop(&self, start_addr: &str, entries: &Netmap, me: Member) -> Hop<Netmap> {
+        let Some(next) = &self.next else {
+            return Hop::Stop;
+        };
+        let mut entries = entries.clone();
+        entries.insert(&self.port, me);
+        Hop::toward(next, start_addr, entries)
+    }
+
+    /// A `NODE HEAL-HOP`: the next node is checked (and healed) before the walk
+    /// moves on, unless it is the start node
+    pub fn heal_hop(&self, start_addr: &str) -> Hop<()> {
+        match &self.next {
+            Some(next) => Hop::toward(next, start_addr, ()),
+            None => Hop::Stop,
+        }
+    }
+
+    /// Newest epoch a walk started here saw, which the epoch its result is
+    /// stored under must outdate
+    pub fn seen_epoch(&self, walk_epoch: u64) -> u64 {
+        walk_epoch.max(self.epoch)
+    }
+}
+
+/// Where a hop that could not be delivered is retried: the sender's successor
+/// in the stored topology, which finishes the walk if it is the start node
+pub fn retry_hop<T>(successor: &str, start_addr: &str, payload: T) -> Hop<T> {
+    Hop::toward(successor, start_addr, payload)
+}
+
+/// Whether a topology snapshot replaces the current one. Newer epochs win;
+/// on a tie the smaller map does, so nodes settle on the same one. Epoch 0
+/// comes from nodes that predate epochs: last writer wins among those, but
+/// never over a snapshot that has an epoch. Both maps are
+/// compared sorted by source port, as the stored one is kept, whatever order
+/// a walk went in.
+pub fn topology_is_newer(
+    current_epoch: u64,
+    current: &Topology,
+    epoch: u64,
+    history: &Topology,
+) -> bool {
+    current.is_empty()
+        || (epoch == 0 && current_epoch == 0)
+        || epoch > current_epoch
+        || (epoch == current_epoch && by_source(history) < by_source(current))
+}
+
+/// The links of `topology` sorted by source port, without latencies, as
+/// stored in a node's topology map (a later link from a port replaces an
+/// earlier one)
+fn by_source(topology: &Topology) -> String {
+    let links: BTreeMap<&str, &str> = topology
+        .edges()
+        .map(|edge| (edge.from.as_str(), edge.to.as_str()))
+        .collect();
+    let mut sorted = Topology::default();
+    for (from, to) in links {
+        sorted.push(from, to);
+    }
+    sorted.to_string()
+}
+
+/* --- RELAYS --- */
+
+/// Length of chunk `index` of a file split in `parts`: the remainder goes to
+/// the first chunks
+pub fn fair_chunk_len(index: u32, total_size: u64, parts: u32) -> u64 {
+    let base = total_size / parts as u64;
+    let rem = total_size % parts as u64;
+    if (index as u64) < rem { base + 1 } else { base }
+}
+
+/// Chunk holding byte `offset` of a file split in `parts`, and where in the
+/// chunk that byte is. An offset at the end of the file is past the last chunk.
+pub fn chunk_at(offset: u64, total_size: u64, parts: u32) -> (u32, u64) {
+    let mut start = 0;
+    for index in 0..parts {
+        let len = fair_chunk_len(index, total_size, parts);
+        if offset < start + len {
+            return (index, offset - start);
+        }
+        start += len;
+    }
+    (parts, 0)
+}
+
+/// Where chunk `index` sits in a relayed file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RelaySlice {
+    /// Bytes of the file before the chunk
+    pub offset: u64,
+    /// Bytes of the chunk, kept by this hop
+    pub len: u64,
+    /// Bytes after the chunk, passed on to the next hop
+    pub remaining: u64,
+}
+
+impl RelaySlice {
+    pub fn new(index: u32, file_size: u64, parts: u32) -> Self {
+        let offset: u64 = (0..index)
+            .map(|i| fair_chunk_len(i, file_size, parts))
+            .sum();
+        let len = fair_chunk_len(index, file_size, parts);
+        Self {
+            offset,
+            len,
+            remaining: file_size - offset - len,
+        }
+    }
+
+    /// Whether this hop keeps the last chunk, finishing the relay
+    pub fn is_last(&self) -> bool {
+        self.remaining == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(port: &str, next: Option<&str>, epoch: u64) -> RingState {
+        RingState {
+            port: port.to_string(),
+            next: next.map(str::to_string),
+            epoch,
+        }
+    }
+
+    fn topology(s: &str) -> Topology {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn topology_hop_without_next_stops() {
+        let hop = state("127.0.0.1:7001", None, 0).topology_hop("127.0.0.1:7000", 1, &topology(""));
+        assert_eq!(hop, Hop::Stop);
+    }
+
+    #[test]
+    fn topology_hop_forwards_until_the_start_node() {
+        let hop = state("127.0.0.1:7001", Some("127.0.0.1:7002"), 3).topology_hop(
+            "127.0.0.1:7000",
+            5,
+            &topology("7000->7001"),
+        );
+        assert_eq!(
+            hop,
+            Hop::Forward {
+                to: "127.0.0.1:7002".to_string(),
+                payload: (5, topology("7000->7001;7001->7002")),
+            }
+        );
+
+        // The start node is recognised by port, however it is written
+        let hop = state("127.0.0.1:7002", Some("127.0.0.1:7000"), 9).topology_hop(
+            "7000",
+            5,
+            &topology("7000->7001;7001->7002"),
+        );
+        assert_eq!(
+            hop,
+            Hop::Done {
+                to: "7000".to_string(),
+                payload: (9, topology("7000->7001;7001->7002;7002->7000")),
+            }
+        );
+    }
+
+    #[test]
+    fn topology_walk_visits_every_node_once() {
+        let ports: Vec<String> = (7000..7005).map(|p| format!("127.0.0.1:{}", p)).collect();
+        let start = &ports[0];
+        let (mut at, mut epoch, mut history) = (0, 0, Topology::default());
+        for hops in 1.. {
+            let node = state(&ports[at], Some(&ports[(at + 1) % ports.len()]), hops);
+            match node.topology_hop(start, epoch, &history) {
+                Hop::Forward { to, payload } => {
+                    at = ports.iter().position(|p| *p == to).unwrap();
+                    (epoch, history) = payload;
+                }
+                Hop::Done { to, payload } => {
+                    assert_eq!(&to, start);
+                    assert_eq!(hops, ports.len() as u64);
+                    (epoch, history) = payload;
+                    break;
+                }
+                Hop::Stop => panic!("walk stopped at {}", ports[at]),
+            }
+        }
+        assert_eq!(epoch, ports.len() as u64);
+        assert_eq!(
+            history,
+            topology("7000->7001;7001->7002;7002->7003;7003->7004;7004->7000")
+        );
+    }
+
+    #[test]
+    fn anything_replaces_an_empty_topology() {
+        assert!(topology_is_newer(
+            7,
+            &Topology::default(),
+            0,
+            &topology("7000->7001")
+        ));
+        assert!(topology_is_newer(
+            7,
+            &Topology::default(),
+            3,
+            &topology("7000->7001")
+        ));
+    }
+
+    #[test]
+    fn newer_epochs_win() {
+        let current = topology("7000->7001;7001->7000");
+        let other = topology("7000->7002;7002->7000");
+        assert!(topology_is_newer(5, &current, 6, &other));
+        assert!(!topology_is_newer(5, &current, 4, &other));
+    }
+
+    #[test]
+    fn tied_epochs_pick_the_smaller_map_whatever_the_walk_order() {
+        let stored = topology("7000->7002;7001->7000;7002->7001");
+        // The same links, in the order a walk from 7001 finds them
+        let walked = topology("7001->7000;7000->7002;7002->7001");
+        assert!(!topology_is_newer(5, &stored, 5, &walked));
+
+        let smaller = topology("7001->7000;7000->7001");
+        let larger = topology("7000->7002;7002->7000");
+        assert!(topology_is_newer(5, &larger, 5, &smaller));
+        assert!(!topology_is_newer(5, &smaller, 5, &larger));
+    }
+
+    #[test]
+    fn tie_break_ignores_latencies() {
+        let stored = topology("7000->7001;7001->7000");
+        let timed = topology("7001->7000@3ms;7000->7001@0.5ms");
+        assert!(!topology_is_newer(5, &stored, 5, &timed));
+    }
+
+    #[test]
+    fn epoch_zero_only_replaces_epoch_zero() {
+        let current = topology("7000->7001;7001->7000");
+        let other = topology("7000->7002;7002->7000");
+        assert!(topology_is_newer(0, &current, 0, &other));
+        assert!(!topology_is_newer(4, &current, 0, &other));
+    }
+
+    #[test]
+    fn fair_chunks_cover_the_file() {
+        for parts in 1..12u32 {
+            for size in 0..300u64 {
+                let lens: Vec<u64> = (0..parts).map(|i| fair_chunk_len(i, size, parts)).collect();
+                assert_eq!(
+                    lens.iter().sum::<u64>(),
+                    size,
+                    "{} bytes in {}",
+                    size,
+                    parts
+                );
+                // Sizes differ by one at most, larger chunks first
+                assert!(lens.windows(2).all(|w| w[0] >= w[1] && w[0] - w[1] <= 1));
+            }
+        }
+        assert_eq!(fair_chunk_len(0, u64::MAX, 1), u64::MAX);
+    }
+
+    #[test]
+    fn chunk_at_finds_every_byte() {
+        for parts in 1..8u32 {
+            for size in 0..120u64 {
+                let mut offset = 0;
+                for index in 0..parts {
+                    for at in 0..fair_chunk_len(index, size, parts) {
+                        assert_eq!(chunk_at(offset, size, parts), (index, at));
+                        offset += 1;
+                    }
+                }
+                assert_eq!(offset, size);
+                assert_eq!(chunk_at(size, size, parts), (parts, 0));
+                assert_eq!(chunk_at(size + 1, size, parts), (parts, 0));
+            }
+        }
+    }
+
+    #[test]
+    fn relay_slices_split_the_file() {
+        for parts in 1..8u32 {
+            for size in 0..120u64 {
+                let mut offset = 0;
+                for index in 0..parts {
+                    let slice = RelaySlice::new(index, size, parts);
+                    assert_eq!(slice.offset, offset);
+                    assert_eq!(slice.len, fair_chunk_len(index, size, parts));
+                    assert_eq!(slice.offset + slice.len + slice.remaining, size);
+                    if slice.len > 0 {
+                        assert_eq!(chunk_at(slice.offset, size, parts), (index, 0));
+                    }
+                    offset += slice.len;
+                }
+                assert!(RelaySlice::new(parts - 1, size, parts).is_last());
+            }
+        }
+    }
+
+    /* --- A simulated ring, driven by random events --- */
+
+    use crate::{NodeStatus, schema::Member};
+    use proptest::prelude::*;
+    use std::collections::BTreeSet;
+
+    /// Something that happens to a simulated ring
+    #[derive(Debug, Clone)]
+    enum Event {
+        /// `TOPOLOGY SET`: the nodes are rewired in this order, under a new epoch
+        Rewire(Vec<usize>),
+        /// A topology walk from this node
+        Walk(usize),
+        /// A netmap walk from this node
+        Netmap(usize),
+        /// A node dies
+        Kill(usize),
+        /// A heal walk from this node, respawning the dead nodes it meets
+        Heal(usize),
+        /// A push relayed from this node: file size and parts
+        Relay(usize, u64, u32),
+        /// An in-flight relay moves on one hop
+        Step(usize),
+    }
+
+    fn event(n: usize) -> impl Strategy<Value = Event> {
+        prop_oneof![
+            Just((0..n).collect::<Vec<_>>())
+                .prop_shuffle()
+                .prop_map(Event::Rewire),
+            (0..n).prop_map(Event::Walk),
+            (0..n).prop_map(Event::Netmap),
+            (0..n).prop_map(Event::Kill),
+            (0..n).prop_map(Event::Heal),
+            (0..n, 0..5_000u64, 1..=n as u32)
+                .prop_map(|(at, size, parts)| Event::Relay(at, size, parts)),
+            (0..8usize).prop_map(Event::Step),
+        ]
+    }
+
+    /// A relay on its way round the ring
+    #[derive(Debug)]
+    struct InFlight {
+        /// Node holding the relay's `max-relays` slot
+        at: usize,
+        index: u32,
+        size: u64,
+        parts: u32,
+        /// Bytes kept by the hops so far
+        kept: u64,
+    }
+
+    #[derive(Debug)]
+    struct SimNode {
+        state: RingState,
+        alive: bool,
+        /// Stored topology snapshot and its epoch
+        topology: (u64, Topology),
+        /// Relay slots taken
+        relays: u64,
+    }
+
+    #[derive(Debug)]
+    struct Sim {
+        nodes: Vec<SimNode>,
+        relays: Vec<InFlight>,
+    }
+
+    fn addr(i: usize) -> String {
+        format!("127.0.0.1:{}", 7000 + i)
+    }
+
+    impl Sim {
+        fn new(n: usize) -> Self {
+            let nodes = (0..n)
+                .map(|i| SimNode {
+                    state: state(&addr(i), Some(&addr((i + 1) % n)), 0),
+                    alive: true,
+                    topology: (0, Topology::default()),
+                    relays: 0,
+                })
+                .collect();
+            Self {
+                nodes,
+                relays: Vec::new(),
+            }
+        }
+
+        fn index_of(&self, to: &str) -> usize {
+            self.nodes
+                .iter()
+                .position(|node| same_node(&node.state.port, to))
+                .unwrap_or_else(|| panic!("hop to unknown node {}", to))
+        }
+
+        fn next_of(&self, i: usize) -> usize {
+            self.index_of(self.nodes[i].state.next.as_deref().unwrap())
+        }
+
+        fn apply(&mut self, event: &Event) {
+            match *event {
+                Event::Rewire(ref order) => {
+                    let epoch = self.nodes.iter().map(|n| n.state.epoch).max().unwrap() + 1;
+                    for (k, &i) in order.iter().enumerate() {
+                        let next = addr(order[(k + 1) % order.len()]);
+                        self.nodes[i].state.next = Some(next);
+                        self.nodes[i].state.epoch = epoch;
+                    }
+                }
+                Event::Walk(start) => self.walk(start),
+                Event::Netmap(start) => self.netmap(start),
+                Event::Kill(i) => {
+                    self.nodes[i].alive = false;
+                    // Its relays die with it, their slots with the process
+                    self.relays.retain(|relay| relay.at != i);
+                    self.nodes[i].relays = 0;
+                }
+                Event::Heal(start) => self.heal(start),
+                Event::Relay(at, size, parts) => {
+                    if self.nodes[at].alive {
+                        self.take_slot(at);
+                        let relay = InFlight {
+                            at,
+                            index: 0,
+                            size,
+                            parts,
+                            kept: 0,
+                        };
+                        self.relays.push(relay);
+                    }
+                }
+                Event::Step(k) => {
+                    if !self.relays.is_empty() {
+                        self.step(k % self.relays.len());
+                    }
+                }
+            }
+        }
+
+        fn take_slot(&mut self, i: usize) {
+            self.nodes[i].relays += 1;
+        }
+
+        fn release_slot(&mut self, i: usize) {
+            let relays = &mut self.nodes[i].relays;
+            *relays = relays.checked_sub(1).expect("relay slot released twice");
+        }
+
+        /// The relay keeps its chunk where it is, then moves on, ends or fails
+        fn step(&mut self, k: usize) {
+            let relay = &self.relays[k];
+            let (at, index, size, parts) = (relay.at, relay.index, relay.size, relay.parts);
+            let slice = RelaySlice::new(index, size, parts);
+            assert_eq!(slice.offset, relay.kept, "chunk {} of {:?}", index, relay);
+            // What `admit_relay` is told is coming
+            let incoming = size
+                .checked_sub(slice.offset)
+                .expect("offset past the file");
+            assert_eq!(incoming, slice.len + slice.remaining);
+            self.release_slot(at);
+
+            let relay = &mut self.relays[k];
+            relay.kept += slice.len;
+            if slice.is_last() {
+                assert_eq!(relay.kept, size, "{:?} ended short", relay);
+                assert!(index < parts);
+                self.relays.swap_remove(k);
+                return;
+            }
+            assert!(index + 1 < parts, "{:?} runs past its last chunk", relay);
+            let next = self.next_of(at);
+            if !self.nodes[next].alive {
+                self.relays.swap_remove(k);
+                return;
+            }
+            let relay = &mut self.relays[k];
+            relay.at = next;
+            relay.index += 1;
+            self.take_slot(next);
+        }
+
+        fn walk(&mut self, start: usize) {
+            if !self.nodes[start].alive {
+                return;
+            }
+            let start_addr = addr(start);
+            let (mut at, mut epoch, mut history) = (start, 0, Topology::default());
+            for _ in 0..=self.nodes.len() {
+                match self.nodes[at]
+                    .state
+                    .topology_hop(&start_addr, epoch, &history)
+                {
+                    Hop::Forward { to, payload } => {
+                        at = self.index_of(&to);
+                        // An undeliverable hop is retried through the same
+                        // successor, then the walk is given up
+                        if !self.nodes[at].alive {
+                            return;
+                        }
+                        (epoch, history) = payload;
+                    }
+                    Hop::Done { to, payload } => {
+                        assert_eq!(self.index_of(&to), start);
+                        (epoch, history) = payload;
+                        assert_single_cycle(&history, self.nodes.len());
+                        let epoch = self.nodes[start].state.seen_epoch(epoch);
+                        let node = &mut self.nodes[start];
+                        let (current_epoch, current) = &node.topology;
+                        if topology_is_newer(*current_epoch, current, epoch, &history) {
+                            node.topology = (epoch, history);
+                        }
+                        return;
+                    }
+                    Hop::Stop => panic!("node {} has no next", at),
+                }
+            }
+            panic!("walk from {} never came back", start_addr);
+        }
+
+        fn netmap(&mut self, start: usize) {
+            if !self.nodes[start].alive {
+                return;
+            }
+            let start_addr = addr(start);
+            let (mut at, mut entries) = (start, Netmap::default());
+            for _ in 0..=self.nodes.len() {
+                let me = Member::new(NodeStatus::Alive, None);
+                match self.nodes[at].state.netmap_hop(&start_addr, &entries, me) {
+                    Hop::Forward { to, payload } => {
+                        at = self.index_of(&to);
+                        if !self.nodes[at].alive {
+                            return;
+                        }
+                        entries = payload;
+                    }
+                    Hop::Done { payload, .. } => {
+                        let ports: BTreeSet<&String> = payload.0.keys().collect();
+                        assert_eq!(ports.len(), self.nodes.len(), "{}", payload);
+                        return;
+                    }
+                    Hop::Stop => panic!("node {} has no next", at),
+                }
+            }
+            panic!("netmap walk from {} never came back", start_addr);
+        }
+
+        fn heal(&mut self, start: usize) {
+            if !self.nodes[start].alive {
+                return;
+            }
+            let start_addr = addr(start);
+            let mut at = start;
+            for _ in 0..=self.nodes.len() {
+                match self.nodes[at].state.heal_hop(&start_addr) {
+                    Hop::Forward { to, .. } => {
+                        at = self.index_of(&to);
+                        // Respawned on the same port, with nothing in flight
+                        self.nodes[at].alive = true;
+                    }
+                    Hop::Done { .. } => return,
+                    Hop::Stop => panic!("node {} has no next", at),
+                }
+            }
+            panic!("heal walk from {} never came back", start_addr);
+        }
+
+        fn check(&self, healed: bool) {
+            // The ring is one cycle through every node
+            let (mut at, mut seen) = (0, BTreeSet::new());
+            while seen.insert(at) {
+                at = self.next_of(at);
+            }
+            assert_eq!(at, 0, "ring does not come back to its start");
+            assert_eq!(seen.len(), self.nodes.len(), "ring leaves nodes out");
+
+            if healed {
+                for (i, node) in self.nodes.iter().enumerate() {
+                    let next = self.next_of(i);
+                    assert!(
+                        !node.alive || self.nodes[next].alive,
+                        "{} is followed by dead {}",
+                        node.state.port,
+                        addr(next)
+                    );
+                }
+            }
+            for (i, node) in self.nodes.iter().enumerate() {
+                let held = self.relays.iter().filter(|r| r.at == i).count() as u64;
+                assert_eq!(node.relays, held, "relay slots of {}", node.state.port);
+                if !node.topology.1.is_empty() {
+                    assert_single_cycle(&node.topology.1, self.nodes.len());
+                }
+            }
+        }
+    }
+
+    /// Every port in `topology` has one link out and one in, and they form a
+    /// single cycle through `n` nodes
+    fn assert_single_cycle(topology: &Topology, n: usize) {
+        let links: BTreeMap<&str, &str> = topology
+            .edges()
+            .map(|e| (e.from.as_str(), e.to.as_str()))
+            .collect();
+        assert_eq!(links.len(), n, "{}", topology);
+        assert_eq!(topology.edges().count(), n, "{}", topology);
+        let first = *links.keys().next().unwrap();
+        let (mut at, mut steps) = (first, 0);
+        loop {
+            at = links[at];
+            steps += 1;
+            if at == first {
+                break;
+            }
+            assert!(steps < n, "{} has a cycle that skips {}", topology, first);
+        }
+        assert_eq!(steps, n, "{}", topology);
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(512))]
+
+        #[test]
+        fn ring_survives_any_event_sequence(
+            (n, events) in (2..8usize).prop_flat_map(|n| (Just(n), prop::collection::vec(event(n), 1..80)))
+        ) {
+            let mut sim = Sim::new(n);
+            for event in &events {
+                sim.apply(event);
+                let healed = matches!(event, Event::Heal(i) if sim.nodes[*i].alive);
+                sim.check(healed);
+            }
+            // Whatever is still in flight drains, giving every slot back
+            while !sim.relays.is_empty() {
+                sim.step(0);
+            }
+            sim.check(false);
+            prop_assert!(sim.nodes.iter().all(|node| node.relays == 0));
+        }
+    }
+}
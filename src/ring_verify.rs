@@ -0,0 +1,300 @@
+//! `verify-ring`: an end-to-end check of a running ring, from the outside.
+//!
+//! Run against any node, it walks the ring (`TOPOLOGY WALK`), compares the
+//! nodes the walk went through with the netmap (`NETMAP GET`), pings every
+//! node of the netmap (`NODE PING`) and asks where every stored file's chunks
+//! are (`FILE LIST`, then `FILE INFO`). Each check passes or fails on its
+//! own, and the ring passes when all of them do. Behind the CLI's
+//! `verify-ring`, the gateway's `GET /api/v1/ring/verify` and
+//! `set-network --verify`.
+
+use crate::{
+    addr::{host_str, join_host_port},
+    auth::{self, ClusterToken},
+    protocol::encode_name,
+    schema::Topology,
+};
+use serde::Serialize;
+use std::{collections::BTreeSet, error::Error, fmt, time::Duration};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    task::JoinSet,
+};
+
+type AnyErr = Box<dyn Error + Send + Sync>;
+
+/// How long the walk may take: a little over the default `walk-timeout`
+const WALK_TIMEOUT: Duration = Duration::from_secs(35);
+
+/// How long any other request may take
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a node has to answer a ping
+const PING_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Outcome of one check
+#[derive(Debug, Clone, Serialize)]
+pub struct Check {
+    /// `walk`, `netmap`, `ping` or `files`
+    pub name: &'static str,
+    pub ok: bool,
+    /// What was found, or what is wrong
+    pub detail: String,
+}
+
+/// Every check run against a ring. Printed as `PASS|FAIL <check> <detail>`
+/// lines and a final `RESULT PASS|FAIL`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RingReport {
+    /// Whether every check passed
+    pub ok: bool,
+    pub checks: Vec<Check>,
+}
+
+impl RingReport {
+    fn push(&mut self, name: &'static str, result: Result<String, String>) {
+        let (ok, detail) = match result {
+            Ok(detail) => (true, detail),
+            Err(detail) => (false, detail),
+        };
+        self.checks.push(Check { name, ok, detail });
+        self.ok = self.checks.iter().all(|c| c.ok);
+    }
+}
+
+impl fmt::Display for RingReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for check in &self.checks {
+            let status = if check.ok { "PASS" } else { "FAIL" };
+            writeln!(f, "{} {} {}", status, check.name, check.detail)?;
+        }
+        write!(f, "RESULT {}", if self.ok { "PASS" } else { "FAIL" })
+    }
+}
+
+/// Runs every check through the node at `addr`. Peers are reached on the
+/// same host, at the ports the netmap names.
+pub async fn verify_ring(addr: &str) -> RingReport {
+    verify_ring_with(addr, None).await
+}
+
+/// [`verify_ring`], presenting `token` to the nodes rather than the process'
+/// client token (see [`auth::connect_with`])
+pub async fn verify_ring_with(addr: &str, token: Option<&ClusterToken>) -> RingReport {
+    let mut report = RingReport::default();
+
+    let walk = check_walk(addr, token).await;
+    let walked: BTreeSet<String> = walk
+        .as_ref()
+        .map(|(ports, _)| ports.clone())
+        .unwrap_or_default();
+    report.push("walk", walk.map(|(_, detail)| detail));
+
+    let netmap = request_with(addr, "NETMAP GET", REQUEST_TIMEOUT, token)
+        .await
+        .map_err(|e| format!("NETMAP GET failed: {}", e));
+    let entries: Vec<(String, String)> = match &netmap {
+        Ok(lines) => lines
+            .iter()
+            .filter_map(|l| l.split_once('='))
+            .map(|(port, status)| (port.to_string(), status.to_string()))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    report.push(
+        "netmap",
+        netmap.and_then(|_| check_netmap(&entries, &walked)),
+    );
+
+    let ports: Vec<String> = entries.into_iter().map(|(port, _)| port).collect();
+    report.push("ping", check_pings(addr, &ports, token).await);
+    report.push("files", check_files(addr, token).await);
+    report
+}
+
+/// Walks the ring: the ports it went through, and a `7000->7001->...` detail.
+/// Fails unless the walk comes back to where it started.
+async fn check_walk(
+    addr: &str,
+    token: Option<&ClusterToken>,
+) -> Result<(BTreeSet<String>, String), String> {
+    let lines = request_with(addr, "TOPOLOGY WALK", WALK_TIMEOUT, token)
+        .await
+        .map_err(|e| format!("TOPOLOGY WALK failed: {}", e))?;
+    let history: Topology = lines.join(";").parse()?;
+    let edges: Vec<_> = history.edges().collect();
+    let (Some(first), Some(last)) = (edges.first(), edges.last()) else {
+        return Err("the walk went nowhere".to_string());
+    };
+
+    let mut path = first.from.clone();
+    for edge in &edges {
+        path.push_str("->");
+        path.push_str(&edge.to);
+    }
+    if last.to != first.from {
+        return Err(format!("the walk did not come back: {}", path));
+    }
+    let ports: BTreeSet<String> = edges.iter().map(|e| e.from.clone()).collect();
+    if ports.len() != edges.len() {
+        return Err(format!("the walk went through a node twice: {}", path));
+    }
+    let detail = format!("{} nodes: {}", ports.len(), path);
+    Ok((ports, detail))
+}
+
+/// The netmap must hold the walked nodes, all alive, and nothing else
+fn check_netmap(entries: &[(String, String)], walked: &BTreeSet<String>) -> Result<String, String> {
+    let known: BTreeSet<String> = entries.iter().map(|(port, _)| port.clone()).collect();
+    let mut problems = Vec::new();
+    let down: Vec<_> = entries
+        .iter()
+        .filter(|(_, status)| !status.eq_ignore_ascii_case("Alive"))
+        .map(|(port, status)| format!("{}={}", port, status))
+        .collect();
+    if !down.is_empty() {
+        problems.push(format!("not alive: {}", down.join(",")));
+    }
+    if !walked.is_empty() {
+        let unwalked: Vec<_> = known.difference(walked).cloned().collect();
+        if !unwalked.is_empty() {
+            problems.push(format!("not in the walk: {}", unwalked.join(",")));
+        }
+        let unknown: Vec<_> = walked.difference(&known).cloned().collect();
+        if !unknown.is_empty() {
+            problems.push(format!("not in the netmap: {}", unknown.join(",")));
+        }
+    }
+    if known.is_empty() {
+        problems.push("the netmap is empty".to_string());
+    }
+    if problems.is_empty() {
+        Ok(format!("{} nodes, all alive and in the walk", known.len()))
+    } else {
+        Err(problems.join("; "))
+    }
+}
+
+/// Pings every node of the netmap at once
+async fn check_pings(
+    addr: &str,
+    ports: &[String],
+    token: Option<&ClusterToken>,
+) -> Result<String, String> {
+    if ports.is_empty() {
+        return Err("no nodes to ping".to_string());
+    }
+    let host = host_str(addr).to_string();
+    let mut pings = JoinSet::new();
+    for port in ports {
+        let port = port.clone();
+        let peer = join_host_port(&host, &port);
+        let token = token.cloned();
+        pings.spawn(async move {
+            let answered = request_with(&peer, "NODE PING", PING_TIMEOUT, token.as_ref())
+                .await
+                .is_ok_and(|lines| lines.iter().any(|l| l.starts_with("PONG")));
+            (port, answered)
+        });
+    }
+    let mut silent = Vec::new();
+    while let Some(Ok((port, answered))) = pings.join_next().await {
+        if !answered {
+            silent.push(port);
+        }
+    }
+    if silent.is_empty() {
+        Ok(format!("{} nodes answered", ports.len()))
+    } else {
+        silent.sort();
+        Err(format!("no answer from {}", silent.join(",")))
+    }
+}
+
+/// Every chunk of every file must be readable from its holder or a backup
+async fn check_files(addr: &str, token: Option<&ClusterToken>) -> Result<String, String> {
+    let lines = request_with(addr, "FILE LIST", REQUEST_TIMEOUT, token)
+        .await
+        .map_err(|e| format!("FILE LIST failed: {}", e))?;
+    let names: Vec<String> = lines.iter().skip(1).filter_map(|l| list_name(l)).collect();
+
+    let (mut chunks, mut broken) = (0, Vec::new());
+    for name in &names {
+        let info = format!("FILE INFO {}", encode_name(name));
+        match request_with(addr, &info, REQUEST_TIMEOUT, token).await {
+            Ok(lines) => {
+                chunks += lines.iter().filter(|l| l.starts_with("part ")).count();
+                if let Some(holes) = lines.iter().find_map(|l| l.strip_prefix("HOLES ")) {
+                    broken.push(format!("{} (parts {})", name, holes.trim()));
+                }
+            }
+            Err(e) => broken.push(format!("{} ({})", name, e)),
+        }
+    }
+    if broken.is_empty() {
+        Ok(format!(
+            "{} files, {} chunks reachable",
+            names.len(),
+            chunks
+        ))
+    } else {
+        Err(format!("unreachable chunks: {}", broken.join(", ")))
+    }
+}
+
+/// Name of a `FILE LIST` row (`name,start,size`, the name CSV-escaped)
+pub(crate) fn list_name(row: &str) -> Option<String> {
+    let parts: Vec<&str> = row.trim().rsplitn(3, ',').collect();
+    let [_, _, name] = parts[..] else {
+        return None;
+    };
+    Some(
+        name.strip_prefix('"')
+            .and_then(|n| n.strip_suffix('"'))
+            .map_or_else(|| name.to_string(), |n| n.replace("\"\"", "\"")),
+    )
+}
+
+/// Sends one command and reads its answer, up to `OK` or the end of the
+/// stream. An `ERR` answer is an error.
+pub(crate) async fn request(
+    addr: &str,
+    line: &str,
+    timeout: Duration,
+) -> Result<Vec<String>, AnyErr> {
+    request_with(addr, line, timeout, None).await
+}
+
+/// [`request`], presenting `token` rather than the process' client token
+pub(crate) async fn request_with(
+    addr: &str,
+    line: &str,
+    timeout: Duration,
+    token: Option<&ClusterToken>,
+) -> Result<Vec<String>, AnyErr> {
+    let exchange = async {
+        let mut stream = auth::connect_with(addr, token).await?;
+        stream.write_all(format!("{}\n", line).as_bytes()).await?;
+        // Answers without a final OK (FILE LIST, NODE PING) end with the stream
+        stream.shutdown().await?;
+
+        let mut reader = BufReader::new(stream);
+        let mut lines = Vec::new();
+        let mut buf = String::new();
+        while reader.read_line(&mut buf).await? > 0 {
+            let l = buf.trim();
+            if l == "OK" {
+                break;
+            }
+            if let Some(err) = l.strip_prefix("ERR") {
+                return Err::<_, AnyErr>(err.trim().to_string().into());
+            }
+            lines.push(l.to_string());
+            buf.clear();
+        }
+        Ok(lines)
+    };
+    tokio::time::timeout(timeout, exchange)
+        .await
+        .map_err(|_| -> AnyErr { format!("{} timed out", line).into() })?
+}
@@ -0,0 +1,90 @@
+//! The gateway's routing cache: the manifests of recently pulled files.
+//!
+//! Every pull through the gateway needs the file's manifest, for its size and
+//! `ETag`: one `FILE MANIFEST` round trip through the ring, in which each
+//! holder reads its chunk's hash. A manifest also names the node holding
+//! each chunk, the first one being where the file starts. Repeat pulls of a
+//! file take it from here instead.
+//!
+//! An entry is dropped when the gateway passes on a write to its file (a
+//! push, sync or copy, over HTTP or the TCP proxy), when a pull of the file
+//! fails, and once it is older than [`ROUTE_TTL`], which bounds how long a
+//! write made straight on a node goes unnoticed. `FILE MIGRATE` drops them
+//! all, since it records chunk holders.
+
+use crate::node::FileManifestView;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// How long a manifest is trusted
+pub const ROUTE_TTL: Duration = Duration::from_secs(30);
+
+/// Manifests kept at most; the oldest goes first
+const MAX_ROUTES: usize = 1024;
+
+#[derive(Debug, Default)]
+pub struct RouteCache {
+    /// name -> (manifest, when it was fetched)
+    entries: Mutex<HashMap<String, (Arc<FileManifestView>, Instant)>>,
+}
+
+impl RouteCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The manifest of `name`, unless unknown or expired
+    pub fn get(&self, name: &str) -> Option<Arc<FileManifestView>> {
+        let mut entries = self.entries.lock().expect("route cache poisoned");
+        match entries.get(name) {
+            Some((manifest, fetched)) if fetched.elapsed() < ROUTE_TTL => {
+                Some(Arc::clone(manifest))
+            }
+            Some(_) => {
+                entries.remove(name);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Stores the manifest just fetched for its file
+    pub fn insert(&self, manifest: FileManifestView) -> Arc<FileManifestView> {
+        let manifest = Arc::new(manifest);
+        let mut entries = self.entries.lock().expect("route cache poisoned");
+        if entries.len() >= MAX_ROUTES && !entries.contains_key(&manifest.name) {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, (_, fetched))| *fetched)
+                .map(|(name, _)| name.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+        entries.insert(
+            manifest.name.clone(),
+            (Arc::clone(&manifest), Instant::now()),
+        );
+        manifest
+    }
+
+    /// Forgets the manifest of `name`, which changed or could not be pulled
+    pub fn invalidate(&self, name: &str) {
+        let removed = self
+            .entries
+            .lock()
+            .expect("route cache poisoned")
+            .remove(name);
+        if removed.is_some() {
+            tracing::debug!(file = %name, "Route cache: Dropped manifest");
+        }
+    }
+
+    /// Forgets every manifest
+    pub fn clear(&self) {
+        self.entries.lock().expect("route cache poisoned").clear();
+    }
+}
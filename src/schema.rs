@@ -0,0 +1,666 @@
+//! Payloads carried inside protocol lines.
+//!
+//! Netmap entries, topology histories, file tag lists, federation links and
+//! chunk response headers each have one type here. `Display` is the canonical encoding and
+//! `FromStr` the decoder, so every format lives in one place instead of being
+//! re-split wherever a line is read.
+//!
+//! Decoders are lenient the way nodes always were: items they cannot read are
+//! skipped, and extra `:`-separated fields after the known ones (netmap
+//! statuses, file tags) are ignored. A newer node can therefore append fields
+//! to an item without breaking older ones.
+
+use crate::{
+    NodeId, NodeStatus,
+    addr::port_key,
+    node::FileTag,
+    protocol::{decode_name, encode_name},
+    time::Timestamp,
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt, str::FromStr, time::Duration};
+
+/* --- NETMAP --- */
+
+/// One netmap entry: a node's status and, when known, its [`NodeId`] and
+/// [`Labels`], with the incarnation the status belongs to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Member {
+    pub status: NodeStatus,
+    pub id: Option<NodeId>,
+    #[serde(default)]
+    pub labels: Labels,
+    /// Raised each time the node is seen recovering, so news of the recovery
+    /// wins over older news of its failure (see [`crate::gossip`])
+    #[serde(default)]
+    pub incarnation: u32,
+}
+
+impl Member {
+    pub fn new(status: NodeStatus, id: Option<NodeId>) -> Self {
+        Self {
+            status,
+            id,
+            labels: Labels::default(),
+            incarnation: 0,
+        }
+    }
+
+    pub fn with_labels(mut self, labels: Labels) -> Self {
+        self.labels = labels;
+        self
+    }
+
+    pub fn with_incarnation(mut self, incarnation: u32) -> Self {
+        self.incarnation = incarnation;
+        self
+    }
+
+    /// Whether this news about a node replaces `known` news of it: a newer
+    /// incarnation, or a worse status in the same one
+    pub fn supersedes(&self, known: &Member) -> bool {
+        self.incarnation > known.incarnation
+            || (self.incarnation == known.incarnation && self.status > known.status)
+    }
+}
+
+/// Status (and identity, labels and incarnation) of every node, by port:
+/// `7000=Alive:<id>:zone=eu&disk=ssd:2,7001=Dead:<id>`. Entries from older
+/// nodes carry no id; a node without an id or labels but with a later field
+/// writes `-` in its place, and incarnation 0 is left out.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Netmap(pub BTreeMap<String, Member>);
+
+impl Netmap {
+    /// A map holding only `port`
+    pub fn single(port: &str, member: Member) -> Self {
+        let mut map = Self::default();
+        map.insert(port, member);
+        map
+    }
+
+    /// Adds or replaces `port`. A node with the same id listed under another
+    /// port has moved: that stale entry is dropped.
+    pub fn insert(&mut self, port: &str, member: Member) {
+        let port = port_key(port).to_string();
+        if let Some(id) = member.id {
+            self.0.retain(|p, m| *p == port || m.id != Some(id));
+        }
+        self.0.insert(port, member);
+    }
+
+    pub fn ports(&self) -> impl Iterator<Item = &String> {
+        self.0.keys()
+    }
+}
+
+impl fmt::Display for Netmap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (port, member)) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{}={}", port, member.status)?;
+            let incarnation = member.incarnation > 0;
+            match member.id {
+                Some(id) => write!(f, ":{}", id)?,
+                None if !member.labels.is_empty() || incarnation => f.write_str(":-")?,
+                None => {}
+            }
+            if !member.labels.is_empty() {
+                write!(f, ":{}", member.labels)?;
+            } else if incarnation {
+                f.write_str(":-")?;
+            }
+            if incarnation {
+                write!(f, ":{}", member.incarnation)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Netmap {
+    type Err = String;
+
+    /// Entries without a port are skipped; an unknown status reads as `Alive`,
+    /// an unreadable id as none, unreadable labels as none and an unreadable
+    /// incarnation as 0. When two ports claim the same id, the one that is not
+    /// `Dead` (or else the later one) is kept.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut map = Self::default();
+        for item in s.split(',').map(str::trim).filter(|i| !i.is_empty()) {
+            let (port, rest) = item.split_once('=').unwrap_or((item, ""));
+            let port = port_key(port);
+            if port.is_empty() {
+                continue;
+            }
+            let mut fields = rest.split(':');
+            let status = fields.next().unwrap_or("").trim();
+            let member = Member::new(
+                status.parse().unwrap_or(NodeStatus::Alive),
+                fields.next().and_then(|id| id.parse().ok()),
+            )
+            .with_labels(
+                fields
+                    .next()
+                    .and_then(|labels| labels.parse().ok())
+                    .unwrap_or_default(),
+            )
+            .with_incarnation(
+                fields
+                    .next()
+                    .and_then(|incarnation| incarnation.parse().ok())
+                    .unwrap_or(0),
+            );
+            let superseded = member.status == NodeStatus::Dead
+                && map.0.iter().any(|(p, m)| {
+                    p != port
+                        && member.id.is_some()
+                        && m.id == member.id
+                        && m.status != NodeStatus::Dead
+                });
+            if !superseded {
+                map.insert(port, member);
+            }
+        }
+        Ok(map)
+    }
+}
+
+/* --- LABELS --- */
+
+/// `key=value` pairs a node is started with (`--label zone=eu-west,disk=ssd`),
+/// and the constraints of a `FILE PUSH ... PLACE`. Keys and values are limited
+/// to letters, digits, `.`, `_` and `-`, so they fit in netmap entries.
+///
+/// Written `zone=eu-west&disk=ssd`; read with either `&` or `,` between pairs.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Labels(pub BTreeMap<String, String>);
+
+impl Labels {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Whether these labels satisfy every constraint in `constraints`
+    pub fn matches(&self, constraints: &Labels) -> bool {
+        constraints
+            .0
+            .iter()
+            .all(|(key, value)| self.0.get(key) == Some(value))
+    }
+}
+
+impl fmt::Display for Labels {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (key, value)) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str("&")?;
+            }
+            write!(f, "{}={}", key, value)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Labels {
+    type Err = String;
+
+    /// Unlike the other decoders this one is strict: labels come from
+    /// operators, and a typo should not silently place data elsewhere
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut labels = BTreeMap::new();
+        for pair in s.split(['&', ',']).map(str::trim).filter(|p| !p.is_empty()) {
+            match pair.split_once('=') {
+                Some((key, value)) if is_token(key) && is_token(value) => {
+                    labels.insert(key.to_string(), value.to_string());
+                }
+                _ => return Err(format!("invalid label '{}', expected key=value", pair)),
+            }
+        }
+        Ok(Self(labels))
+    }
+}
+
+/// Whether `t` is a label key or value, or a ring id: letters, digits, `.`, `_` and `-`
+fn is_token(t: &str) -> bool {
+    !t.is_empty()
+        && t.bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'.' | b'_' | b'-'))
+}
+
+/* --- FEDERATION --- */
+
+/// Checks a ring id (`--ring-id eu`), which names the ring in federated file names
+pub fn parse_ring_id(s: &str) -> Result<String, String> {
+    let id = s.trim();
+    if is_token(id) {
+        Ok(id.to_string())
+    } else {
+        Err(format!(
+            "invalid ring id '{}': use letters, digits, '.', '_' and '-'",
+            id
+        ))
+    }
+}
+
+/// Where another ring is reached: the border node of this ring holding the
+/// link, and the node of the other ring it connects to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FederationLink {
+    pub border: String,
+    pub remote: String,
+}
+
+/// Links to federated rings, by ring id: `eu=7002@10.0.0.5:8000,...`.
+/// Entries that do not read are skipped.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Federation(pub BTreeMap<String, FederationLink>);
+
+impl Federation {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl fmt::Display for Federation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (ring, link)) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{}={}@{}", ring, link.border, link.remote)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Federation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut links = BTreeMap::new();
+        for entry in s.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let Some((ring, link)) = entry.split_once('=') else {
+                continue;
+            };
+            let Some((border, remote)) = link.split_once('@') else {
+                continue;
+            };
+            if is_token(ring) && !border.is_empty() && !remote.is_empty() {
+                links.insert(
+                    ring.to_string(),
+                    FederationLink {
+                        border: port_key(border).to_string(),
+                        remote: remote.to_string(),
+                    },
+                );
+            }
+        }
+        Ok(Self(links))
+    }
+}
+
+/* --- TOPOLOGY --- */
+
+/// One `from->to` link of the ring, by port, with the time the walk took to
+/// connect over it: `7000->7001@0.12ms`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Edge {
+    pub from: String,
+    pub to: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latency_us: Option<u64>,
+}
+
+impl fmt::Display for Edge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}->{}", self.from, self.to)?;
+        if let Some(us) = self.latency_us {
+            write!(f, "@{}ms", format_ms(us))?;
+        }
+        Ok(())
+    }
+}
+
+/// Microseconds as milliseconds, without trailing zeros: `3`, `0.125`
+pub fn format_ms(us: u64) -> String {
+    let ms = format!("{}.{:03}", us / 1000, us % 1000);
+    ms.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+/// Parses `<ms>ms` (fractional allowed) into microseconds
+fn parse_ms(s: &str) -> Option<u64> {
+    let ms: f64 = s.trim().strip_suffix("ms")?.parse().ok()?;
+    (ms.is_finite() && ms >= 0.0).then(|| (ms * 1000.0).round() as u64)
+}
+
+/// Ring links in walk order: `7000->7001;7001->7002`. Links a walk went over
+/// carry their hop latency (`7000->7001@3ms`).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Topology(pub Vec<Edge>);
+
+impl Topology {
+    /// Appends the link `from_addr -> to_addr` (addresses or ports)
+    pub fn push(&mut self, from_addr: &str, to_addr: &str) {
+        self.0.push(Edge {
+            from: port_key(from_addr).to_string(),
+            to: port_key(to_addr).to_string(),
+            latency_us: None,
+        });
+    }
+
+    /// Records how long the last link's hop took
+    pub fn time_last(&mut self, latency: Duration) {
+        if let Some(edge) = self.0.last_mut() {
+            edge.latency_us = Some(latency.as_micros() as u64);
+        }
+    }
+
+    /// The same links without hop latencies, as nodes without
+    /// `hop-timing` expect them
+    pub fn untimed(&self) -> Topology {
+        Topology(
+            self.0
+                .iter()
+                .map(|edge| Edge {
+                    latency_us: None,
+                    ..edge.clone()
+                })
+                .collect(),
+        )
+    }
+
+    /// Points the last link at `to_addr` instead, for a hop that went elsewhere
+    pub fn retarget_last(&mut self, to_addr: &str) {
+        if let Some(edge) = self.0.last_mut() {
+            edge.to = port_key(to_addr).to_string();
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn edges(&self) -> impl Iterator<Item = &Edge> {
+        self.0.iter()
+    }
+}
+
+impl fmt::Display for Topology {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, edge) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(";")?;
+            }
+            write!(f, "{}", edge)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Topology {
+    type Err = String;
+
+    /// Segments that are not `a->b[@<n>ms]` are skipped
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut topology = Self::default();
+        for (from, to) in s
+            .trim()
+            .split(';')
+            .filter_map(|segment| segment.split_once("->"))
+        {
+            let (to, latency) = match to.split_once('@') {
+                Some((to, latency)) => (to, parse_ms(latency)),
+                None => (to, None),
+            };
+            topology.push(from, to);
+            if let Some(us) = latency {
+                topology.time_last(Duration::from_micros(us));
+            }
+        }
+        Ok(topology)
+    }
+}
+
+/* --- FILE TAG VERSIONS --- */
+
+/// Version of a file tag: `<counter>@<node id>[@<timestamp>]`. The node
+/// writing a file (pushing, syncing, copying or moving it) counts one past the
+/// version it replaces, signs with its id and stamps the write with its clock
+/// (see [`crate::time`]). A higher counter wins; two versions with the same
+/// counter were written concurrently, from the same base, and the later one
+/// wins, or on a tie the greater node id (see [`crate::Node::merge_file_tag`]).
+/// Versions written before timestamps have none and lose such ties.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct TagVersion {
+    pub counter: u64,
+    #[serde(default)]
+    pub at: Timestamp,
+    pub node: NodeId,
+}
+
+impl TagVersion {
+    /// Whether `other` was written concurrently with this version
+    pub fn concurrent(&self, other: &TagVersion) -> bool {
+        self.counter == other.counter && self.node != other.node
+    }
+}
+
+impl fmt::Display for TagVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}@{}", self.counter, self.node)?;
+        if !self.at.is_zero() {
+            write!(f, "@{}", self.at)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for TagVersion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid tag version '{}'", s);
+        let mut parts = s.splitn(3, '@');
+        let (Some(counter), Some(node)) = (parts.next(), parts.next()) else {
+            return Err(invalid());
+        };
+        Ok(TagVersion {
+            counter: counter.parse().map_err(|_| invalid())?,
+            at: match parts.next() {
+                Some(at) => at.parse().map_err(|_| invalid())?,
+                None => Timestamp::default(),
+            },
+            node: node.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+/* --- FILE TAGS --- */
+
+/// Every file's tag, by name:
+/// `name:start:size:parts[:holders[:ring[:chunks[:conflict_of[:version[:type]]]]]];...`,
+/// names encoded with [`encode_name`]. Files with a chunk manifest list
+/// their holders' node ids as a fifth field, `+`-separated. Files stored on a
+/// federated ring name it in a sixth (`eu/a.txt:0:120:0::eu`), and copies the
+/// (encoded) name their chunks are stored under in a seventh
+/// (`b.txt:7000:120:2:<id>+<id>::a.txt`). Versions set aside by a
+/// reconciliation name the file they conflicted with in an eighth, versioned
+/// tags carry their [`TagVersion`] in a ninth, and the (encoded) media type a
+/// file was pushed with comes tenth.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileTags(pub BTreeMap<String, FileTag>);
+
+impl FileTags {
+    /// A list holding only `name`
+    pub fn single(name: &str, tag: FileTag) -> Self {
+        Self(BTreeMap::from([(name.to_string(), tag)]))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl fmt::Display for FileTags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (name, tag)) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(";")?;
+            }
+            write!(
+                f,
+                "{}:{}:{}:{}",
+                encode_name(name),
+                tag.start,
+                tag.size,
+                tag.parts
+            )?;
+            // Optional fields, up to the last one set
+            let optional = [
+                join_holders(&tag.holders),
+                tag.ring.clone().unwrap_or_default(),
+                tag.chunks
+                    .as_deref()
+                    .map(encode_name)
+                    .unwrap_or_default()
+                    .into(),
+                tag.conflict_of
+                    .as_deref()
+                    .map(encode_name)
+                    .unwrap_or_default()
+                    .into(),
+                tag.version.map(|v| v.to_string()).unwrap_or_default(),
+                tag.content_type
+                    .as_deref()
+                    .map(encode_name)
+                    .unwrap_or_default()
+                    .into(),
+            ];
+            let len = optional
+                .iter()
+                .rposition(|field| !field.is_empty())
+                .map_or(0, |last| last + 1);
+            for field in &optional[..len] {
+                write!(f, ":{}", field)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for FileTags {
+    type Err = String;
+
+    /// Entries with missing or non-numeric fields are skipped
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut tags = BTreeMap::new();
+        // Only ASCII whitespace ends a line: names may end in any other
+        for entry in s.trim_ascii().split(';').filter(|e| !e.is_empty()) {
+            let mut fields = entry.split(':');
+            let (Some(name), Some(start), Some(size), Some(parts)) =
+                (fields.next(), fields.next(), fields.next(), fields.next())
+            else {
+                continue;
+            };
+            if let (Ok(start), Ok(size), Ok(parts)) = (start.parse(), size.parse(), parts.parse()) {
+                let holders = fields.next().map(parse_holders).unwrap_or_default();
+                let ring = fields.next().filter(|r| is_token(r)).map(str::to_string);
+                let chunks = fields.next().filter(|c| !c.is_empty()).map(decode_name);
+                let conflict_of = fields.next().filter(|c| !c.is_empty()).map(decode_name);
+                let version = fields.next().and_then(|v| v.parse().ok());
+                let content_type = fields.next().filter(|t| !t.is_empty()).map(decode_name);
+                tags.insert(
+                    decode_name(name),
+                    FileTag {
+                        start,
+                        size,
+                        parts,
+                        holders,
+                        ring,
+                        chunks,
+                        conflict_of,
+                        version,
+                        content_type,
+                    },
+                );
+            }
+        }
+        Ok(Self(tags))
+    }
+}
+
+/// Holder ids as written in file tags: `<id>+<id>`
+pub fn join_holders(holders: &[NodeId]) -> String {
+    holders
+        .iter()
+        .map(NodeId::to_string)
+        .collect::<Vec<_>>()
+        .join("+")
+}
+
+/// Reads a `<id>+<id>` holder list. Ports, which older nodes listed
+/// (`7000+7002`), stand for the stand-in id of their node (see
+/// [`NodeId::for_port`]). A list with anything else in it reads as empty.
+pub fn parse_holders(s: &str) -> Vec<NodeId> {
+    s.split('+')
+        .map(|holder| {
+            let holder = holder.trim();
+            match holder.parse::<u16>() {
+                Ok(_) => Ok(NodeId::for_port(holder)),
+                Err(_) => holder.parse(),
+            }
+        })
+        .collect::<Result<_, _>>()
+        .unwrap_or_default()
+}
+
+/* --- CHUNK RESPONSES --- */
+
+/// Header of a served chunk: `FILE RESP-CHUNK <next_addr> <size> <name>`,
+/// followed on the wire by exactly `size` bytes
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RespChunk {
+    /// Next hop of the serving node
+    pub next_addr: String,
+    pub size: u64,
+    pub name: String,
+}
+
+impl fmt::Display for RespChunk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "FILE RESP-CHUNK {} {} {}",
+            self.next_addr,
+            self.size,
+            encode_name(&self.name)
+        )
+    }
+}
+
+impl FromStr for RespChunk {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rest = s
+            .trim_end_matches(['\r', '\n'])
+            .strip_prefix("FILE RESP-CHUNK ")
+            .ok_or("malformed FILE RESP-CHUNK")?;
+        let mut parts = rest.splitn(3, ' ');
+        let next_addr = parts.next().unwrap_or("").to_string();
+        let size = parts
+            .next()
+            .unwrap_or("")
+            .parse()
+            .map_err(|_| "invalid chunk size")?;
+        let name = decode_name(parts.next().unwrap_or(""));
+        Ok(Self {
+            next_addr,
+            size,
+            name,
+        })
+    }
+}
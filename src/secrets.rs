@@ -0,0 +1,174 @@
+//! Secrets: loading them, keeping them out of logs and memory, rotating them.
+//!
+//! Keys and tokens are read from a file or an environment variable (a
+//! [`SecretSource`]), held as [`Secret`]s, which never print and overwrite
+//! their bytes with zeros when dropped, and grouped into [`KeyRing`]s.
+//!
+//! A key ring lists keys one per word, the first being the signing key: the
+//! one a node presents. Every key listed is accepted, so a key can be
+//! rotated without a flag day: list the new key second and reload every node
+//! (`NODE KEYS ROTATE`), then move it first and reload again, then drop the
+//! old one.
+
+use std::{
+    env, fmt, fs, io,
+    path::PathBuf,
+    ptr,
+    sync::atomic::{Ordering, compiler_fence},
+};
+
+/// Text that must not leak: hidden from `Debug`, zeroed on drop
+pub struct Secret(Box<str>);
+
+impl Secret {
+    pub fn new(text: &str) -> Self {
+        Self(text.into())
+    }
+
+    /// The secret itself; keep the borrow short
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for Secret {
+    /// Takes `text`, zeroing the buffer it was in
+    fn from(text: String) -> Self {
+        zeroed_after(text)
+    }
+}
+
+impl Clone for Secret {
+    fn clone(&self) -> Self {
+        Self::new(&self.0)
+    }
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        wipe(&mut self.0);
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Secret(..)")
+    }
+}
+
+/// Where a secret is read from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SecretSource {
+    File(PathBuf),
+    Env(String),
+}
+
+impl SecretSource {
+    /// A file source, its path made absolute so it still resolves from a
+    /// process started elsewhere
+    pub fn file(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        Self::File(path.canonicalize().unwrap_or(path))
+    }
+
+    /// The secret's text; `None` for an unset variable
+    pub fn load(&self) -> io::Result<Option<Secret>> {
+        match self {
+            Self::File(path) => fs::read_to_string(path)
+                .map(|text| Some(zeroed_after(text)))
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e))),
+            Self::Env(var) => Ok(env::var(var).ok().map(zeroed_after)),
+        }
+    }
+}
+
+impl fmt::Display for SecretSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::File(path) => write!(f, "{}", path.display()),
+            Self::Env(var) => write!(f, "${}", var),
+        }
+    }
+}
+
+/// Keys of one kind: the signing key first, then the others accepted as well
+#[derive(Clone)]
+pub struct KeyRing(Vec<Secret>);
+
+impl KeyRing {
+    /// Keys separated by whitespace; lines starting with `#` are left out
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let keys: Vec<Secret> = text
+            .lines()
+            .filter(|line| !line.trim_start().starts_with('#'))
+            .flat_map(str::split_whitespace)
+            .map(Secret::new)
+            .collect();
+        if keys.is_empty() {
+            return Err("no key given".to_string());
+        }
+        Ok(Self(keys))
+    }
+
+    /// The key presented to others
+    pub fn signing(&self) -> &Secret {
+        &self.0[0]
+    }
+
+    /// Whether `candidate` is one of the keys. Every key is compared, each in
+    /// a time that does not depend on where it first differs.
+    pub fn accepts(&self, candidate: &str) -> bool {
+        self.0.iter().fold(false, |found, key| {
+            found | same_secret(key.expose(), candidate)
+        })
+    }
+
+    /// Number of keys accepted, the signing key included
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Every key, in order, one per line: what [`KeyRing::parse`] reads back
+    pub fn export(&self) -> Secret {
+        let mut text = String::new();
+        for key in &self.0 {
+            text.push_str(key.expose());
+            text.push('\n');
+        }
+        zeroed_after(text)
+    }
+}
+
+impl fmt::Debug for KeyRing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "KeyRing({} keys)", self.0.len())
+    }
+}
+
+/// Whether two secrets are equal, in a time that does not depend on where
+/// they first differ, so a secret cannot be guessed byte by byte
+pub fn same_secret(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// `text` as a secret, its original buffer zeroed
+fn zeroed_after(mut text: String) -> Secret {
+    let secret = Secret::new(&text);
+    wipe(&mut text);
+    secret
+}
+
+/// Overwrites `text` with zeros
+fn wipe(text: &mut str) {
+    // SAFETY: zero bytes are valid UTF-8, and the writes stay within the string
+    for byte in unsafe { text.as_bytes_mut() } {
+        // Volatile, so writes to memory about to be freed are not optimised away
+        unsafe { ptr::write_volatile(byte, 0) };
+    }
+    compiler_fence(Ordering::SeqCst);
+}
@@ -1,108 +1,161 @@
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
-use std::path::Path;
+use std::io::{self, IoSlice};
+use std::net::SocketAddr;
+use std::path::{Component, Path};
+use std::pin::Pin;
 use std::time::{Duration, Instant};
 use std::{env, path::PathBuf, sync::Arc};
 use tokio::fs;
 use tokio::io::{
-    AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, copy,
+    AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader,
+    copy,
+};
+use tokio::net::{
+    TcpListener, TcpStream,
+    tcp::{OwnedReadHalf, OwnedWriteHalf},
 };
-use tokio::net::{TcpSocket, TcpStream};
 use tokio::process::Command;
+use tokio::sync::broadcast;
+use tokio::task::{JoinHandle, JoinSet};
 use tokio::time::sleep;
 use tracing;
 
 use crate::{
-    node::{self, Node, append_edge, port_str},
-    protocol,
+    NodeEvent, NodeId, NodeStatus,
+    addr::{host_str, join_host_port},
+    alert::{self, DeathAlert, RespawnAction},
+    auth::{CLUSTER_TOKEN_ENV, Role},
+    builder::NodeBuilder,
+    cache::ChunkCache,
+    checksum::{Digest, HashingReader, HashingWriter, Sha256},
+    compat::{Feature, Hello},
+    config::RespawnMode,
+    delta::{self, BlockSignature},
+    disk::IoClass,
+    fanout::Broadcast,
+    gossip::{GOSSIP_FANOUT, GossipSchedule, PeerProbes},
+    health, heartbeat, identity, lane, latency,
+    limit::{EXPENSIVE_COST, Refusal},
+    manifest::{self, ChunkEntry},
+    migrate, net,
+    node::{self, Node, RelaySlot, port_str},
+    pack,
+    protocol::{self, PushMode},
+    reconcile::{self, Reconciled},
+    relay::{self, RELAY_LABEL},
+    ring_state::{Hop, RelaySlice, chunk_at, fair_chunk_len, retry_hop, same_node},
+    schema::{
+        FederationLink, FileTags, Labels, Netmap, RespChunk, Topology, join_holders, parse_holders,
+    },
+    secrets::{Secret, SecretSource},
+    staging::{self, CommitRecord},
+    stats,
+    time::Timestamp,
+    trace::{self, Trace},
+    transfer::{ProgressReader, Transfer, TransferKind},
+    usage,
+    verify::{self, ChunkStatus},
 };
 
 type AnyErr = Box<dyn Error + Send + Sync>;
 
 /// Run the TCP server and handle connections.
 pub async fn run(bind_addr: &str, gossip_interval: Duration, file_size: u64) -> Result<(), AnyErr> {
-    // 1. Parse the address with an explicit type annotation
-    let addr: std::net::SocketAddr = bind_addr.parse()?;
-
-    // 2. Create a socket based on IP version
-    let socket = if addr.is_ipv6() {
-        TcpSocket::new_v6()?
-    } else {
-        TcpSocket::new_v4()?
-    };
-
-    // 3. Set the SO_REUSEADDR option
-    socket.set_reuseaddr(true)?;
-
-    // 4. Set the SO_REUSEPORT option (required on macOS/BSD to bypass TIME_WAIT)
-    #[cfg(unix)]
-    socket.set_reuseport(true)?;
-
-    // 5. Bind the socket to the address
-    socket.bind(addr)?;
-
-    // 6. Listen for incoming connections
-    let listener = socket.listen(1024)?;
-
-    // 7. Get the local address
-    let local = listener.local_addr()?;
-
-    // Initialize Node structure
-    let node = Node::new(local.to_string(), gossip_interval, file_size);
-    tracing::info!(node = %node.port, "Node listening");
+    let handle = NodeBuilder::new()
+        .bind(bind_addr)
+        .gossip(gossip_interval)
+        .file_size(file_size)
+        .build()?;
+    handle.start().await?;
+    handle.wait().await
+}
 
-    // Create nodes/<port>/content and nodes/<port>/backup directories
-    let port_only = port_str(&node.port);
-    let content_dir = format!("nodes/{}/content", port_only);
-    let backup_dir = format!("nodes/{}/backup", port_only);
+/// Create `<data_dir>/<port>/content` and `<data_dir>/<port>/backup` directories,
+/// plus their `manifest/` counterparts and `staging/`, finishing the commits a
+/// crash left in it and clearing the rest (see [`crate::staging`])
+pub(crate) async fn create_node_dirs(node: &Arc<Node>) -> Result<(), AnyErr> {
+    let content_dir = node.content_dir();
+    let backup_dir = node.backup_dir();
 
     if let Err(e) = fs::create_dir_all(&content_dir).await {
-        tracing::error!(node = %node.port, dir = %content_dir, error = ?e, "Failed to create node content directory");
+        tracing::error!(node = %node.port, dir = %content_dir.display(), error = ?e, "Failed to create node content directory");
         return Err(e.into());
     }
     if let Err(e) = fs::create_dir_all(&backup_dir).await {
-        tracing::error!(node = %node.port, dir = %backup_dir, error = ?e, "Failed to create node backup directory");
+        tracing::error!(node = %node.port, dir = %backup_dir.display(), error = ?e, "Failed to create node backup directory");
         return Err(e.into());
     }
+    for subdir in ["content", "backup"] {
+        let dir = node.manifest_dir(subdir);
+        if let Err(e) = fs::create_dir_all(&dir).await {
+            tracing::error!(node = %node.port, dir = %dir.display(), error = ?e, "Failed to create node manifest directory");
+            return Err(e.into());
+        }
+    }
 
-    tracing::info!(node = %node.port, content_dir = %content_dir, backup_dir = %backup_dir, "Created node directories");
-
-    // Spawn the gossip loop
-    if gossip_interval > Duration::from_millis(0) {
-        let gossip_node = Arc::clone(&node);
-        tokio::spawn(async move {
-            tracing::info!(
-                node = %gossip_node.port,
-                interval = ?gossip_interval,
-                "Gossip loop starting"
-            );
-            spawn_gossip_loop(gossip_node).await;
-        });
+    // Chunks left in staging belong to pushes that never committed, unless
+    // their commit record made it to disk
+    match staging::recover(node).await {
+        Ok((_, stored)) => {
+            for chunk_name in stored {
+                tokio::spawn(notify_backup_holders(Arc::clone(node), chunk_name));
+            }
+        }
+        Err(e) => {
+            tracing::error!(node = %node.port, dir = %node.staging_dir().display(), error = ?e, "Failed to recover node staging directory");
+            return Err(e.into());
+        }
     }
 
-    // Accept connections
+    tracing::info!(node = %node.port, content_dir = %content_dir.display(), backup_dir = %backup_dir.display(), "Created node directories");
+    Ok(())
+}
+
+/// Accept connections until the listener fails.
+pub(crate) async fn serve(node: Arc<Node>, listener: TcpListener) -> Result<(), AnyErr> {
+    tracing::info!(node = %node.port, "Node listening");
+
     loop {
         let (stream, peer) = listener.accept().await?;
+        if let Err(e) = net::tune_stream(&stream, &node.tcp) {
+            tracing::warn!(node = %node.port, peer = %peer, error = ?e, "Could not apply TCP options");
+        }
         let node = Arc::clone(&node);
 
         // Clone the port for logging before moving `node`
         let node_port = node.port.clone();
 
         tokio::spawn(async move {
-            if let Err(e) = handle_client(node, stream).await {
+            if let Err(e) = handle_client(node, stream, peer).await {
                 tracing::error!(node = %node_port, peer = %peer, error = ?e, "Client connection error");
             }
         });
     }
 }
 
-async fn handle_client(node: Arc<Node>, stream: TcpStream) -> Result<(), AnyErr> {
+async fn handle_client(node: Arc<Node>, stream: TcpStream, peer: SocketAddr) -> Result<(), AnyErr> {
     // Set read and write streams
     let (reader, mut writer) = stream.into_split();
     let mut reader = BufReader::new(reader);
 
+    // Every source is held to the per-source limits; a peer sending the
+    // cluster token is not
+    let mut limited = true;
+    // The connection is admitted at its first command that is not the ring's
+    // own, since only then is it known not to come from a peer
+    let mut admitted = false;
+
     // The protocol is line delimited, so we just need to read the first line
     // when figuring out how to handle the request
     let mut line = String::new();
+    // Without a cluster token, anyone may send the ring's own commands
+    let mut authorized = node.cluster_token.is_none();
+    // and without an access list, any client command
+    let mut role = match &node.access {
+        Some(access) => access.anonymous(),
+        None => Some(Role::Admin),
+    };
 
     loop {
         line.clear();
@@ -111,139 +164,496 @@ async fn handle_client(node: Arc<Node>, stream: TcpStream) -> Result<(), AnyErr>
         }
 
         // Parse the header and match it with a specific command
-        match protocol::parse_line(&line) {
-            Ok(cmd) => match cmd {
-                // NODE
-                protocol::Command::NodeNext(addr) => {
-                    handle_node_next(&node, &mut writer, addr).await?
-                }
-                protocol::Command::NodeStatus => handle_node_status(&node, &mut writer).await?,
-                protocol::Command::NodePing => handle_node_ping(&mut writer).await?,
-                protocol::Command::NodeHeal => {
-                    handle_node_heal(Arc::clone(&node), &mut writer).await?
-                }
-                protocol::Command::NodeHealHop { token, start_addr } => {
-                    handle_node_heal_hop(Arc::clone(&node), &mut writer, token, start_addr).await?
+        let cmd = match protocol::parse_line(&line) {
+            Ok(cmd) => cmd,
+            Err(e) => {
+                if limited && !admitted {
+                    admitted = true;
+                    if !admit(&node, peer, &mut writer).await? {
+                        break;
+                    }
                 }
-                protocol::Command::NodeHealDone { token } => {
-                    handle_node_heal_done(&node, &mut writer, token).await?
+                handle_error(&mut writer, e).await?;
+                if limited && let Some(ban) = count_protocol_error(&node, peer) {
+                    handle_error(&mut writer, Refusal::Banned(ban).to_string()).await?;
+                    break;
                 }
+                continue;
+            }
+        };
 
-                // RING
-                protocol::Command::RingForward { ttl, msg } => {
-                    handle_ring_forward(&node, &mut writer, ttl, msg).await?
-                }
+        // The ring's own commands, and the cluster token, do not admit the connection
+        let from_peer = cmd.is_internal()
+            || matches!(&cmd, protocol::Command::NodeAuth { token }
+                if node.cluster_token.as_ref().is_some_and(|t| t.matches(token)));
+        if limited && !admitted && !from_peer {
+            admitted = true;
+            if !admit(&node, peer, &mut writer).await? {
+                break;
+            }
+        }
 
-                // TOPOLOGY
-                protocol::Command::TopologyWalk => handle_topology_walk(&node, &mut writer).await?,
-                protocol::Command::TopologyHop {
-                    token,
-                    start_addr,
-                    history,
-                } => handle_topology_hop(&node, &mut writer, token, start_addr, history).await?,
-                protocol::Command::TopologyDone { token, history } => {
-                    // Pass an owned Arc so it can be moved into the new task
-                    handle_topology_done(Arc::clone(&node), &mut writer, token, history).await?
-                }
-                protocol::Command::TopologySet { history } => {
-                    handle_topology_set(&node, &mut writer, history).await?
-                }
+        // `NODE AUTH` is not answered, so peers need not wait for it
+        if let protocol::Command::NodeAuth { token } = &cmd {
+            if node.cluster_token.as_ref().is_none_or(|t| t.matches(token)) {
+                authorized = true;
+                role = Some(Role::Admin);
+                // A peer proved it belongs to the ring
+                limited &= node.cluster_token.is_none();
+                continue;
+            }
+            if let Some(client_role) = node.access.as_ref().and_then(|a| a.role_of(token)) {
+                role = Some(client_role);
+                continue;
+            }
+            tracing::warn!(node = %node.port, peer = ?writer.peer_addr(), "Wrong cluster token");
+            let e = match node.access {
+                Some(_) => "UNAUTHORIZED unknown token",
+                None => "UNAUTHORIZED wrong cluster token",
+            };
+            handle_error(&mut writer, e.into()).await?;
+            if limited {
+                count_protocol_error(&node, peer);
+            }
+            break;
+        }
+        // Whatever follows the command (a chunk, a relayed stream) is the
+        // sender's too: drop the connection
+        if cmd.is_internal() && !authorized {
+            let verb = command_verb(&line);
+            tracing::warn!(node = %node.port, peer = ?writer.peer_addr(), command = %verb, "Internal command without NODE AUTH");
+            let e = format!("UNAUTHORIZED {} needs NODE AUTH", verb);
+            handle_error(&mut writer, e).await?;
+            if limited {
+                count_protocol_error(&node, peer);
+            }
+            break;
+        }
+        if !cmd.is_internal() && role < Some(cmd.role()) {
+            let verb = command_verb(&line);
+            tracing::warn!(node = %node.port, peer = ?writer.peer_addr(), command = %verb, role = ?role, "Command above the client's role");
+            let e = format!("FORBIDDEN {} needs the {} role", verb, cmd.role());
+            handle_error(&mut writer, e).await?;
+            break;
+        }
+        // Cut off from most of the ring: writes here could split the cluster
+        if !cmd.is_internal() && cmd.changes_ring() && node.is_degraded() {
+            let verb = command_verb(&line);
+            tracing::warn!(node = %node.port, peer = ?writer.peer_addr(), command = %verb, "Command refused in degraded mode");
+            let e = format!(
+                "DEGRADED {} refused: partition suspected, node is read-only",
+                verb
+            );
+            handle_error(&mut writer, e).await?;
+            break;
+        }
+        // A refused command may have a body on its way: drop the connection
+        if limited && !cmd.is_internal() {
+            let cost = if cmd.is_expensive() {
+                EXPENSIVE_COST
+            } else {
+                1
+            };
+            if let Err(refusal) = node.limiter.command(peer.ip(), cost) {
+                tracing::debug!(node = %node.port, peer = %peer, refusal = %refusal, "Command refused");
+                handle_error(&mut writer, refusal.to_string()).await?;
+                break;
+            }
+        }
 
-                // NETMAP
-                protocol::Command::NetmapDiscover => {
-                    handle_netmap_discover(&node, &mut writer).await?
-                }
-                protocol::Command::NetmapHop {
-                    token,
-                    start_addr,
-                    entries,
-                } => handle_netmap_hop(&node, &mut writer, token, start_addr, entries).await?,
-                protocol::Command::NetmapDone { token, entries } => {
-                    handle_netmap_done(&node, &mut writer, token, entries).await?
-                }
-                protocol::Command::NetmapSet { entries } => {
-                    handle_netmap_set(&node, &mut writer, entries).await?
-                }
-                protocol::Command::NetmapGet => handle_netmap_get(&node, &mut writer).await?,
+        // Data commands go to the data-plane runtime, so they cannot starve control traffic
+        if cmd.is_data() {
+            let data_node = Arc::clone(&node);
+            let in_sync;
+            (reader, writer, in_sync) = lane::run_data(async move {
+                let res = handle_data_command(data_node, cmd, &mut reader, &mut writer).await;
+                (reader, writer, res)
+            })
+            .await?;
+            if !in_sync? {
+                break;
+            }
+            continue;
+        }
 
-                // FILE
-                protocol::Command::FilePush { size, name } => {
-                    handle_file_push(Arc::clone(&node), &mut reader, &mut writer, size, name)
-                        .await?
-                }
-                protocol::Command::FilePull { name } => {
-                    handle_file_pull(&node, &mut writer, name).await?;
-                    break;
-                }
-                protocol::Command::FileList => {
-                    handle_file_list_csv(&node, &mut writer).await?;
-                    break;
-                }
-                protocol::Command::FileTagsSet { entries } => {
-                    handle_file_tags_set(&node, &mut writer, entries).await?
-                }
+        match cmd {
+            // NODE
+            protocol::Command::NodeNext(addr) => handle_node_next(&node, &mut writer, addr).await?,
+            protocol::Command::NodePrev(addr) => handle_node_prev(&node, &mut writer, addr).await?,
+            protocol::Command::NodeHello { addr, hello } => {
+                handle_node_hello(&node, &mut writer, addr, hello).await?
+            }
+            protocol::Command::NodeStatus => handle_node_status(&node, &mut writer).await?,
+            protocol::Command::NodePing { echo, gossip } => {
+                handle_node_ping(node.clone(), &mut writer, echo, gossip).await?
+            }
+            protocol::Command::NodeLoad => handle_node_load(&node, &mut writer).await?,
+            protocol::Command::NodeMetrics => handle_node_metrics(&node, &mut writer).await?,
+            protocol::Command::NodeDu { json } => handle_node_du(&node, &mut writer, json).await?,
+            protocol::Command::NodeConfigSet { key, value } => {
+                handle_node_config_set(&node, &mut writer, key, value).await?
+            }
+            protocol::Command::NodeConfigGet => handle_node_config_get(&node, &mut writer).await?,
+            protocol::Command::NodeKeysRotate => {
+                handle_node_keys_rotate(&node, &mut writer).await?
+            }
+            protocol::Command::NodeLogTail { lines } => {
+                handle_node_log_tail(&node, &mut writer, lines).await?
+            }
+            protocol::Command::NodeLogFollow => handle_node_log_follow(&node, &mut writer).await?,
+            protocol::Command::NodeHeal { timeout } => {
+                handle_node_heal(Arc::clone(&node), &mut writer, timeout).await?
+            }
+            protocol::Command::NodeHealHop { token, start_addr } => {
+                handle_node_heal_hop(Arc::clone(&node), &mut writer, token, start_addr).await?
+            }
+            protocol::Command::NodeHealDone { token } => {
+                handle_node_heal_done(&node, &mut writer, token).await?
+            }
+            protocol::Command::NodeReconcile { entries } => {
+                handle_node_reconcile(node.clone(), &mut writer, entries).await?
+            }
+
+            // RING
+            protocol::Command::RingForward { ttl, msg } => {
+                handle_ring_forward(&node, &mut writer, ttl, msg).await?
+            }
+            protocol::Command::RingSize => handle_ring_size(&node, &mut writer).await?,
+            protocol::Command::RingHealth => handle_ring_health(&node, &mut writer).await?,
+            protocol::Command::RingAudit { apply } => {
+                handle_ring_audit(&node, &mut writer, apply).await?
+            }
+            protocol::Command::RingTrace { dest } => {
+                handle_ring_trace(&node, &mut writer, dest).await?
+            }
+            protocol::Command::RingTraceHop { dest, path } => {
+                handle_ring_trace_hop(&node, &mut writer, dest, path).await?
+            }
+            protocol::Command::RingReconcile => {
+                let outcome = reconcile_ring(&node).await;
+                writer
+                    .write_all(format!("{}\nOK\n", outcome).as_bytes())
+                    .await?;
+            }
+
+            // TOPOLOGY
+            protocol::Command::TopologyWalk { timeout } => {
+                handle_topology_walk(&node, &mut writer, timeout).await?
+            }
+            protocol::Command::TopologyHop {
+                token,
+                start_addr,
+                epoch,
+                history,
+            } => handle_topology_hop(&node, &mut writer, token, start_addr, epoch, history).await?,
+            protocol::Command::TopologyDone {
+                token,
+                epoch,
+                history,
+            } => {
+                // Pass an owned Arc so it can be moved into the new task
+                handle_topology_done(Arc::clone(&node), &mut writer, token, epoch, history).await?
+            }
+            protocol::Command::TopologySet { epoch, history } => {
+                handle_topology_set(&node, &mut writer, epoch, history).await?
+            }
+            protocol::Command::TopologyGet { json } => {
+                handle_topology_get(&node, &mut writer, json).await?
+            }
+
+            // NETMAP
+            protocol::Command::NetmapDiscover { wait, timeout } => {
+                handle_netmap_discover(Arc::clone(&node), &mut writer, wait, timeout).await?
+            }
+            protocol::Command::NetmapHop {
+                token,
+                start_addr,
+                entries,
+            } => handle_netmap_hop(&node, &mut writer, token, start_addr, entries).await?,
+            protocol::Command::NetmapDone { token, entries } => {
+                handle_netmap_done(Arc::clone(&node), &mut writer, token, entries).await?
+            }
+            protocol::Command::NetmapSet { entries } => {
+                handle_netmap_set(Arc::clone(&node), &mut writer, entries).await?
+            }
+            protocol::Command::NetmapGet { json, federated } => {
+                handle_netmap_get(&node, &mut writer, json, federated).await?
+            }
+
+            // CLUSTER
+            protocol::Command::ClusterLeader => handle_cluster_leader(&node, &mut writer).await?,
+            protocol::Command::ClusterStats => {
+                handle_cluster_stats(Arc::clone(&node), &mut writer).await?
+            }
 
-                // FILE (internal)
-                protocol::Command::FileRelayBlob {
-                    token,
-                    start_addr,
+            // RELAY: these take over the connection
+            protocol::Command::RelayRegister { port } => {
+                return handle_relay_register(&node, reader, writer, port).await;
+            }
+            protocol::Command::RelayConnect { port } => {
+                return handle_relay_connect(&node, reader, writer, port).await;
+            }
+            protocol::Command::RelayAccept { id } => {
+                return handle_relay_accept(&node, reader, writer, id).await;
+            }
+
+            // FEDERATION
+            protocol::Command::FederationLink {
+                ring,
+                remote,
+                token,
+            } => {
+                let token = token.map(Secret::from);
+                handle_federation_link(&node, &mut writer, ring, remote, token).await?
+            }
+            protocol::Command::FederationUnlink { ring } => {
+                handle_federation_unlink(&node, &mut writer, ring).await?
+            }
+            protocol::Command::FederationSet { links } => {
+                node.set_federation(links).await;
+                writer.write_all(b"OK\n").await?;
+            }
+            protocol::Command::FederationGet => handle_federation_get(&node, &mut writer).await?,
+            protocol::Command::FederationTag { ring, size, name } => {
+                let tag = node::FileTag {
+                    start: 0,
                     size,
-                    name,
-                } => {
-                    handle_file_relay_blob(
-                        Arc::clone(&node),
-                        &mut reader,
-                        &mut writer,
-                        token,
-                        start_addr,
-                        size,
-                        name,
-                    )
-                    .await?
+                    parts: 0,
+                    holders: Vec::new(),
+                    ring: Some(ring),
+                    chunks: None,
+                    conflict_of: None,
+                    version: None,
+                    content_type: None,
+                };
+                node.merge_file_tag(&name, tag).await;
+                writer.write_all(b"OK\n").await?;
+            }
+
+            // FILE
+            protocol::Command::FileList => {
+                handle_file_list_csv(&node, &mut writer).await?;
+                break;
+            }
+            protocol::Command::FileProgress { token } => {
+                handle_file_progress(&node, &mut writer, token).await?
+            }
+            protocol::Command::FileCancel { token } => {
+                handle_file_cancel(&node, &mut writer, token).await?
+            }
+            protocol::Command::FileInfo { name } => {
+                handle_file_info(Arc::clone(&node), &mut writer, name).await?
+            }
+            protocol::Command::FileTagsSet { entries } => {
+                handle_file_tags_set(&node, &mut writer, entries).await?
+            }
+            protocol::Command::FileTagsMerge { entries } => {
+                migrate::rename_legacy_chunks(&node, &entries).await;
+                for (name, tag) in entries.0 {
+                    node.merge_file_tag(&name, tag).await;
                 }
-                protocol::Command::FileRelayStream {
-                    token,
-                    start_addr,
-                    file_size,
+                writer.write_all(b"OK\n").await?;
+            }
+            protocol::Command::FileDiscard { parts, name } => {
+                handle_file_discard(&node, &mut writer, parts, name).await?
+            }
+            protocol::Command::FileCheckChunk { name } => {
+                handle_file_check_chunk(&node, &mut writer, name).await?
+            }
+            protocol::Command::FileTag {
+                start,
+                size,
+                parts,
+                holders,
+                chunks,
+                name,
+            } => {
+                let tag = node::FileTag {
+                    start,
+                    size,
                     parts,
-                    index,
-                    name,
-                } => {
-                    handle_file_relay_stream(
-                        Arc::clone(&node),
-                        &mut reader,
-                        &mut writer,
-                        token,
-                        start_addr,
-                        file_size,
-                        parts,
-                        index,
-                        name,
-                    )
-                    .await?
-                }
-                protocol::Command::FileGetChunk { name } => {
-                    handle_file_get_chunk(&node, &mut writer, name).await?
-                }
+                    holders,
+                    ring: None,
+                    chunks,
+                    conflict_of: None,
+                    version: None,
+                    content_type: None,
+                };
+                node.merge_file_tag(&name, tag).await;
+                writer.write_all(b"OK\n").await?;
+            }
+            protocol::Command::FileStatChunk { name } => {
+                handle_file_stat_chunk(&node, &mut writer, name).await?
+            }
+            protocol::Command::FileManifest { name } => {
+                handle_file_manifest(&node, &mut writer, name).await?
+            }
+            protocol::Command::FileConflicts => handle_file_conflicts(&node, &mut writer).await?,
+            protocol::Command::FileDu { json, prefix } => {
+                handle_file_du(&node, &mut writer, json, prefix).await?
+            }
+            protocol::Command::FileHashChunk { name } => {
+                handle_file_hash_chunk(&node, &mut writer, name).await?
+            }
+            protocol::Command::FileCopy { src, dst } => {
+                handle_file_copy(&node, &mut writer, src, dst).await?
+            }
+            protocol::Command::FileMigrate => {
+                let migration = migrate::migrate_chunks(&node).await;
+                writer
+                    .write_all(format!("{}\nOK\n", migration).as_bytes())
+                    .await?;
+            }
+            protocol::Command::FileDropChunk { name } => {
+                remove_chunk(&node, "content", &name).await;
+                tracing::info!(node = %node.port, chunk = %name, "Dropped migrated chunk");
+                writer.write_all(b"OK\n").await?;
+            }
 
-                // FILE (backup)
-                protocol::Command::FileNotifyChunkSaved { name } => {
-                    handle_file_notify_chunk_saved(Arc::clone(&node), &mut writer, name).await?
-                }
-                protocol::Command::FileGetChunkForBackup { name } => {
-                    handle_file_get_chunk_for_backup(&node, &mut writer, name).await?
-                }
-                protocol::Command::FileGetBackupChunk { name } => {
-                    handle_file_get_backup_chunk(&node, &mut writer, name).await?
-                }
-            },
-            Err(e) => handle_error(&mut writer, e).await?,
+            // Data commands were dispatched above
+            _ => unreachable!("data command on the control path"),
         }
     }
 
     Ok(())
 }
 
+/// Runs one data command (see [`protocol::Command::is_data`]). Returns whether
+/// the connection is still in sync and can carry another command.
+async fn handle_data_command<R, W>(
+    node: Arc<Node>,
+    cmd: protocol::Command,
+    reader: &mut R,
+    writer: &mut W,
+) -> Result<bool, AnyErr>
+where
+    R: AsyncBufRead + Unpin + Send,
+    W: AsyncWrite + Unpin + Send,
+{
+    match cmd {
+        // FILE: client transfers wait for a free slot (`max-transfers`)
+        protocol::Command::FilePush {
+            size,
+            name,
+            mode,
+            place,
+            sha256,
+            content_type,
+        } => {
+            let _slot = node.transfer_slot().await;
+            return handle_file_push(
+                Arc::clone(&node),
+                reader,
+                writer,
+                size,
+                name,
+                mode,
+                place,
+                sha256,
+                content_type,
+            )
+            .await;
+        }
+        protocol::Command::FilePull { name, offset } => {
+            let _slot = node.transfer_slot().await;
+            handle_file_pull(&node, writer, name, offset).await?;
+            return Ok(false);
+        }
+        protocol::Command::FileSync {
+            size,
+            block_size,
+            name,
+        } => {
+            let _slot = node.transfer_slot().await;
+            return handle_file_sync(Arc::clone(&node), reader, writer, size, block_size, name)
+                .await;
+        }
+        protocol::Command::FileVerify { name } => handle_file_verify(&node, writer, name).await?,
+
+        // FILE (internal)
+        protocol::Command::FileRelayBlob {
+            token,
+            start_addr,
+            size,
+            name,
+        } => {
+            handle_file_relay_blob(
+                Arc::clone(&node),
+                reader,
+                writer,
+                token,
+                start_addr,
+                size,
+                name,
+            )
+            .await?
+        }
+        protocol::Command::FileRelayStream {
+            token,
+            start_addr,
+            file_size,
+            parts,
+            index,
+            name,
+        } => {
+            handle_file_relay_stream(
+                Arc::clone(&node),
+                reader,
+                writer,
+                token,
+                start_addr,
+                file_size,
+                parts,
+                index,
+                name,
+            )
+            .await?
+        }
+        protocol::Command::FilePutChunk {
+            token,
+            start_addr,
+            file_size,
+            parts,
+            index,
+            name,
+        } => {
+            handle_file_put_chunk(
+                Arc::clone(&node),
+                reader,
+                writer,
+                token,
+                start_addr,
+                file_size,
+                parts,
+                index,
+                name,
+            )
+            .await?
+        }
+        protocol::Command::FileGetChunk { name } => {
+            handle_file_get_chunk(&node, writer, name).await?
+        }
+
+        // FILE (backup)
+        protocol::Command::FileNotifyChunkSaved { from, name } => {
+            handle_file_notify_chunk_saved(Arc::clone(&node), writer, from, name).await?
+        }
+        protocol::Command::FileMigrateChunk { from, size, name } => {
+            handle_file_migrate_chunk(&node, writer, from, size, name).await?
+        }
+        protocol::Command::FileGetChunkForBackup { name } => {
+            handle_file_get_chunk_for_backup(&node, writer, name).await?
+        }
+        protocol::Command::FileGetBackupChunk { name } => {
+            handle_file_get_backup_chunk(&node, writer, name).await?
+        }
+
+        // Everything else is handled on the control path
+        _ => unreachable!("control command on the data path"),
+    }
+    Ok(true)
+}
+
 /* --- Command handlers --- */
 
 async fn handle_node_next<W: AsyncWrite + Unpin>(
@@ -252,12 +662,56 @@ async fn handle_node_next<W: AsyncWrite + Unpin>(
     addr: String,
 ) -> Result<(), AnyErr> {
     node.set_next(addr.clone()).await;
+    node.announce_prev().await;
     writer
         .write_all(format!("OK next={}\n", addr).as_bytes())
         .await?;
     Ok(())
 }
 
+/// Handles "NODE HELLO <addr> <version> <features>": records what the
+/// greeting peer speaks and answers with what this node speaks
+async fn handle_node_hello<W: AsyncWrite + Unpin>(
+    node: &Node,
+    writer: &mut W,
+    addr: String,
+    hello: Hello,
+) -> Result<(), AnyErr> {
+    node.record_hello(&addr, hello).await;
+    writer
+        .write_all(format!("HELLO {}\nOK\n", Hello::current()).as_bytes())
+        .await?;
+    Ok(())
+}
+
+/// Handles "NODE PREV [<addr>]": records the node announcing itself as our
+/// predecessor, or reports the one recorded as `PREV <addr>`
+async fn handle_node_prev<W: AsyncWrite + Unpin>(
+    node: &Node,
+    writer: &mut W,
+    addr: Option<String>,
+) -> Result<(), AnyErr> {
+    match addr {
+        Some(addr) => {
+            tracing::debug!(node = %node.port, prev = %addr, "Predecessor set");
+            node.set_prev(addr.clone()).await;
+            writer
+                .write_all(format!("OK prev={}\n", addr).as_bytes())
+                .await?;
+        }
+        None => {
+            let prev = node
+                .get_prev()
+                .await
+                .unwrap_or_else(|| "<unset>".to_string());
+            writer
+                .write_all(format!("PREV {}\nOK\n", prev).as_bytes())
+                .await?;
+        }
+    }
+    Ok(())
+}
+
 async fn handle_node_status<W: AsyncWrite + Unpin>(
     node: &Node,
     writer: &mut W,
@@ -267,74 +721,322 @@ async fn handle_node_status<W: AsyncWrite + Unpin>(
         .await
         .unwrap_or_else(|| "<unset>".to_string());
     writer
-        .write_all(format!("PORT {}\nNEXT {}\nOK\n", node.port, next).as_bytes())
+        .write_all(format!("PORT {}\nID {}\nNEXT {}\nOK\n", node.port, node.id, next).as_bytes())
         .await?;
     Ok(())
 }
 
-async fn handle_node_ping<W: AsyncWrite + Unpin>(writer: &mut W) -> Result<(), AnyErr> {
-    writer.write_all(b"PONG\n").await?;
-    Ok(())
-}
-
-/// Handles "NODE HEAL"
-/// Starts a walk that forces every node to check and heal its neighbor.
-async fn handle_node_heal<W: AsyncWrite + Unpin>(
+/// Handles "NODE PING [<seq> <sent_at_us> [<entries>]]": answers `PONG`,
+/// echoing the sequence number and timestamp when given. A ping gossiping
+/// netmap changes has them taken, and gets this node's own in return.
+async fn handle_node_ping<W: AsyncWrite + Unpin>(
     node: Arc<Node>,
     writer: &mut W,
+    echo: Option<(u64, u64)>,
+    gossip: Option<Netmap>,
 ) -> Result<(), AnyErr> {
-    let token = node.make_walk_token();
-    let rx = node.register_heal_walk(&token).await;
+    let Some((seq, sent_at)) = echo else {
+        writer.write_all(b"PONG\n").await?;
+        return Ok(());
+    };
+    let Some(news) = gossip else {
+        writer
+            .write_all(format!("PONG {} {}\n", seq, sent_at).as_bytes())
+            .await?;
+        return Ok(());
+    };
+    let rumors = gossip_payload(node.take_rumors());
+    writer
+        .write_all(format!("PONG {} {} {}\n", seq, sent_at, rumors).as_bytes())
+        .await?;
+    take_gossip(node, &news).await;
+    Ok(())
+}
 
-    // Spawn a task to do the first check and start the walk
-    let start_addr = node.port.clone();
-    let node_clone = Arc::clone(&node);
-    tokio::spawn(async move {
-        if let Err(e) = check_and_heal_neighbor(node_clone, &token, &start_addr).await {
-            tracing::error!(
-                node = %start_addr,
-                token = %token,
-                error = ?e,
-                "Heal walk: First check failed"
-            );
-        }
-    });
+/// Netmap changes as a ping or `PONG` carries them, `-` for none
+fn gossip_payload(rumors: Option<Netmap>) -> String {
+    rumors.map_or_else(|| "-".to_string(), |rumors| rumors.to_string())
+}
 
-    // Wait for the walk to complete (or time out)
-    let walk_timeout = Duration::from_secs(60);
-    match tokio::time::timeout(walk_timeout, rx).await {
-        Ok(Ok(())) => {
-            writer.write_all(b"OK network healed\n").await?;
-        }
-        Ok(Err(_)) => {
-            writer.write_all(b"ERR heal walk canceled\n").await?;
-        }
-        Err(_) => {
-            writer.write_all(b"ERR heal walk timed out\n").await?;
-        }
+/// Takes netmap changes a peer gossiped, greeting nodes that are new or back
+async fn take_gossip(node: Arc<Node>, news: &Netmap) {
+    if node.merge_gossip(news).await {
+        tokio::spawn(async move { node.negotiate().await });
     }
-
-    Ok(())
 }
 
-/// Handles "NODE HEAL-HOP <token> <start_addr>"
-/// This is received by a node, which then checks its neighbor.
-async fn handle_node_heal_hop<W: AsyncWrite + Unpin>(
+/// Handles "NODE RECONCILE <entries>": answers this node's netmap and file
+/// tags (`NETMAP` and `TAGS` lines, `-` for none) to a node reconciling
+/// after a partition, then merges its netmap
+async fn handle_node_reconcile<W: AsyncWrite + Unpin>(
     node: Arc<Node>,
     writer: &mut W,
-    token: String,
-    start_addr: String,
+    entries: Netmap,
 ) -> Result<(), AnyErr> {
-    // 1. ACK the hop request immediately
-    writer.write_all(b"OK\n").await?;
-
-    // 2. Spawn a task to do the actual work
-    tokio::spawn(async move {
-        let node_port = node.port.clone();
-        if let Err(e) = check_and_heal_neighbor(node, &token, &start_addr).await {
-            tracing::error!(
-                node = %node_port,
-                token = %token,
+    let netmap = node.get_network_nodes_entries().await.to_string();
+    let tags = node.get_file_tags_entries().await.to_string();
+    writer
+        .write_all(
+            format!(
+                "NETMAP {}\nTAGS {}\nOK\n",
+                entries_or_dash(netmap),
+                entries_or_dash(tags)
+            )
+            .as_bytes(),
+        )
+        .await?;
+    take_gossip(node, &entries).await;
+    Ok(())
+}
+
+/// Handles "NODE CONFIG SET <key> <value>"
+/// Applies a runtime setting without restarting the node.
+/// Handles "NODE LOAD": answers `LOAD transfers=<n> bytes=<n>`
+async fn handle_node_load<W: AsyncWrite + Unpin>(
+    node: &Node,
+    writer: &mut W,
+) -> Result<(), AnyErr> {
+    let load = node.load().await;
+    writer.write_all(format!("{}\n", load).as_bytes()).await?;
+    Ok(())
+}
+
+/// Handles "NODE METRICS": one `<name>=<value>` line per counter, then `OK`
+async fn handle_node_metrics<W: AsyncWrite + Unpin>(
+    node: &Node,
+    writer: &mut W,
+) -> Result<(), AnyErr> {
+    let mut out = String::new();
+    for (name, value) in node.metrics().await {
+        out.push_str(&format!("{}={}\n", name, value));
+    }
+    out.push_str("OK\n");
+    writer.write_all(out.as_bytes()).await?;
+    Ok(())
+}
+
+/// Handles "NODE DU [JSON]": one `<dir> bytes=<n> files=<n>` line per
+/// directory of the data directory and a `TOTAL` line, then `OK`; or a
+/// [`usage::NodeUsageView`] document
+async fn handle_node_du<W: AsyncWrite + Unpin>(
+    node: &Node,
+    writer: &mut W,
+    json: bool,
+) -> Result<(), AnyErr> {
+    let dirs = match usage::dir_usage(&node.data_dir).await {
+        Ok(dirs) => dirs,
+        Err(e) => {
+            writer.write_all(format!("ERR {}\n", e).as_bytes()).await?;
+            return Ok(());
+        }
+    };
+    let view = usage::NodeUsageView::new(port_str(&node.port).to_string(), dirs);
+    let out = if json {
+        format!("{}\n", serde_json::to_string(&view)?)
+    } else {
+        let mut out = String::new();
+        for dir in &view.dirs {
+            out.push_str(&format!("{}\n", dir));
+        }
+        out.push_str(&format!(
+            "TOTAL bytes={} files={}\nOK\n",
+            view.bytes, view.files
+        ));
+        out
+    };
+    writer.write_all(out.as_bytes()).await?;
+    Ok(())
+}
+
+async fn handle_node_config_set<W: AsyncWrite + Unpin>(
+    node: &Node,
+    writer: &mut W,
+    key: String,
+    value: String,
+) -> Result<(), AnyErr> {
+    match node.set_setting(&key, &value).await {
+        Ok(()) => {
+            writer
+                .write_all(format!("OK {}={}\n", key, value).as_bytes())
+                .await?
+        }
+        Err(e) => writer.write_all(format!("ERR {}\n", e).as_bytes()).await?,
+    }
+    Ok(())
+}
+
+/// Handles "NODE KEYS ROTATE": reads the cluster token and the access file
+/// again and takes the keys they now hold, answering
+/// `KEYS cluster=<n> access=<n>` (`-` for what the node runs without), then
+/// `OK`. Keys that fail to load leave the ones in use in place.
+async fn handle_node_keys_rotate<W: AsyncWrite + Unpin>(
+    node: &Node,
+    writer: &mut W,
+) -> Result<(), AnyErr> {
+    if node.cluster_token.is_none() && node.access.is_none() {
+        writer.write_all(b"ERR no keys to rotate\n").await?;
+        return Ok(());
+    }
+    let cluster = node.cluster_token.as_ref().map(|token| token.rotate());
+    let access = node
+        .access
+        .as_ref()
+        .map(|access| access.reload().map_err(|e| e.to_string()));
+    let count = |result: &Option<Result<usize, String>>| match result {
+        Some(Ok(n)) => n.to_string(),
+        _ => "-".to_string(),
+    };
+    let failure = [("cluster token", &cluster), ("access file", &access)]
+        .into_iter()
+        .find_map(|(what, result)| match result {
+            Some(Err(e)) => Some(format!("{}: {}", what, e)),
+            _ => None,
+        });
+    if let Some(e) = failure {
+        tracing::warn!(node = %node.port, error = %e, "Key rotation failed");
+        writer.write_all(format!("ERR {}\n", e).as_bytes()).await?;
+        return Ok(());
+    }
+    tracing::info!(node = %node.port, cluster = %count(&cluster), access = %count(&access), "Keys rotated");
+    writer
+        .write_all(
+            format!(
+                "KEYS cluster={} access={}\nOK\n",
+                count(&cluster),
+                count(&access)
+            )
+            .as_bytes(),
+        )
+        .await?;
+    Ok(())
+}
+
+/// Handles "NODE CONFIG GET": one `<key>=<value>` line per effective
+/// setting, then `OK`
+async fn handle_node_config_get<W: AsyncWrite + Unpin>(
+    node: &Node,
+    writer: &mut W,
+) -> Result<(), AnyErr> {
+    let mut out = String::new();
+    for (key, value) in node.config_entries().await {
+        out.push_str(&format!("{}={}\n", key, value));
+    }
+    out.push_str("OK\n");
+    writer.write_all(out.as_bytes()).await?;
+    Ok(())
+}
+
+/// Handles "NODE LOG TAIL [<n>]"
+/// Answers the last `<n>` log lines kept in memory, oldest first, then `OK`.
+async fn handle_node_log_tail<W: AsyncWrite + Unpin>(
+    node: &Node,
+    writer: &mut W,
+    lines: usize,
+) -> Result<(), AnyErr> {
+    let Some(buffer) = &node.log_buffer else {
+        writer.write_all(b"ERR NO_LOG_BUFFER\n").await?;
+        return Ok(());
+    };
+    let mut out = String::new();
+    for line in buffer.tail(lines) {
+        out.push_str(&line);
+        out.push('\n');
+    }
+    out.push_str("OK\n");
+    writer.write_all(out.as_bytes()).await?;
+    Ok(())
+}
+
+/// Handles "NODE LOG FOLLOW"
+/// Streams log lines as they are written until the client goes away. A
+/// client too slow to keep up gets `SKIPPED <n>` for the lines it missed.
+async fn handle_node_log_follow<W: AsyncWrite + Unpin>(
+    node: &Node,
+    writer: &mut W,
+) -> Result<(), AnyErr> {
+    let Some(buffer) = &node.log_buffer else {
+        writer.write_all(b"ERR NO_LOG_BUFFER\n").await?;
+        return Ok(());
+    };
+    let mut feed = buffer.follow();
+    writer.write_all(b"OK following\n").await?;
+    loop {
+        let line = match feed.recv().await {
+            Ok(line) => line + "\n",
+            Err(broadcast::error::RecvError::Lagged(n)) => format!("SKIPPED {}\n", n),
+            Err(broadcast::error::RecvError::Closed) => return Ok(()),
+        };
+        // Fails once the client disconnects, which ends the stream
+        writer.write_all(line.as_bytes()).await?;
+        writer.flush().await?;
+    }
+}
+
+/// Handles "NODE HEAL [TIMEOUT <ms>]"
+/// Starts a walk that forces every node to check and heal its neighbor, and
+/// waits for it up to the given timeout, or the `heal-timeout` setting.
+async fn handle_node_heal<W: AsyncWrite + Unpin>(
+    node: Arc<Node>,
+    writer: &mut W,
+    timeout: Option<Duration>,
+) -> Result<(), AnyErr> {
+    let token = node.make_walk_token();
+    let rx = node.register_heal_walk(&token).await;
+    let token_wait = token.clone();
+
+    // Spawn a task to do the first check and start the walk
+    let start_addr = node.port.clone();
+    let node_clone = Arc::clone(&node);
+    tokio::spawn(async move {
+        if let Err(e) = check_and_heal_neighbor(node_clone, &token, &start_addr).await {
+            tracing::error!(
+                node = %start_addr,
+                token = %token,
+                error = ?e,
+                "Heal walk: First check failed"
+            );
+        }
+    });
+
+    // Wait for the walk to complete (or time out)
+    let walk_timeout = match timeout {
+        Some(timeout) => timeout,
+        None => node.settings().await.heal_timeout,
+    };
+    match tokio::time::timeout(walk_timeout, rx).await {
+        Ok(Ok(())) => {
+            writer.write_all(b"OK network healed\n").await?;
+        }
+        Ok(Err(_)) => {
+            writer.write_all(b"ERR heal walk canceled\n").await?;
+        }
+        Err(_) => {
+            node.cancel_heal_walk(&token_wait).await;
+            writer.write_all(b"ERR heal walk timed out\n").await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Handles "NODE HEAL-HOP <token> <start_addr>"
+/// This is received by a node, which then checks its neighbor.
+async fn handle_node_heal_hop<W: AsyncWrite + Unpin>(
+    node: Arc<Node>,
+    writer: &mut W,
+    token: String,
+    start_addr: String,
+) -> Result<(), AnyErr> {
+    // 1. ACK the hop request immediately
+    writer.write_all(b"OK\n").await?;
+
+    // 2. Spawn a task to do the actual work
+    tokio::spawn(async move {
+        let node_port = node.port.clone();
+        if let Err(e) = check_and_heal_neighbor(node, &token, &start_addr).await {
+            tracing::error!(
+                node = %node_port,
+                token = %token,
                 error = ?e,
                 "Heal walk: Check/forward failed"
             );
@@ -352,7 +1054,9 @@ async fn handle_node_heal_done<W: AsyncWrite + Unpin>(
     token: String,
 ) -> Result<(), AnyErr> {
     // Signal the original "handle_node_heal" waiter
-    node.finish_heal_walk(&token).await;
+    if !node.finish_heal_walk(&token).await {
+        return reject_done(node, writer, "NODE HEAL-DONE", &token).await;
+    }
     writer.write_all(b"OK\n").await?;
     Ok(())
 }
@@ -368,28 +1072,28 @@ async fn check_and_heal_neighbor(
     token: &str,
     start_addr: &str,
 ) -> Result<(), AnyErr> {
-    let Some(next_addr) = node.get_next().await else {
-        tracing::warn!(node = %node.port, "Heal walk: No next node set, stopping walk.");
-        return Ok(()); // Stop the walk
+    let next_addr = match node.ring_state().await.heal_hop(start_addr) {
+        Hop::Stop => {
+            tracing::warn!(node = %node.port, "Heal walk: No next node set, stopping walk.");
+            return Ok(()); // Stop the walk
+        }
+        // 1. Check if the ring was completed
+        Hop::Done { to, .. } => {
+            tracing::info!(node = %node.port, token = %token, "Heal walk: Completed ring, sending DONE.");
+            let mut s = node.connect(&to).await?;
+            s.write_all(format!("NODE HEAL-DONE {}\n", token).as_bytes())
+                .await?;
+            return Ok(());
+        }
+        Hop::Forward { to, .. } => to,
     };
 
-    // 1. Check if the ring was completed
-    if port_str(&next_addr) == port_str(start_addr) {
-        tracing::info!(node = %node.port, token = %token, "Heal walk: Completed ring, sending DONE.");
-        let mut s = TcpStream::connect(start_addr).await?;
-        s.write_all(format!("NODE HEAL-DONE {}\n", token).as_bytes())
-            .await?;
-        return Ok(());
-    }
-
     // 2. Node is not the start, so check its health
     match check_node_health(node.clone(), &next_addr).await {
         Ok(_) => {
             // 3. Node is ALIVE -> Forward the HEAL-HOP request
             tracing::debug!(node = %node.port, target = %next_addr, "Heal walk: Node is alive, forwarding hop.");
-            let mut s = TcpStream::connect(&next_addr).await?;
-            s.write_all(format!("NODE HEAL-HOP {} {}\n", token, start_addr).as_bytes())
-                .await?;
+            forward_heal_hop(&node, &next_addr, token, start_addr).await?;
         }
         Err(e) => {
             // 3. Node is DEAD -> Heal it, then forward
@@ -417,15 +1121,40 @@ async fn check_and_heal_neighbor(
                 target = %next_addr,
                 "Heal walk: Node healed, forwarding hop."
             );
-            let mut s = TcpStream::connect(&next_addr).await?;
-            s.write_all(format!("NODE HEAL-HOP {} {}\n", token, start_addr).as_bytes())
-                .await?;
+            forward_heal_hop(&node, &next_addr, token, start_addr).await?;
         }
     }
 
     Ok(())
 }
 
+/// Sends the heal walk on to `next_addr`. A hop that cannot be delivered is
+/// retried once through this node's successor in the stored topology, which
+/// finishes the walk if that successor is the start node.
+async fn forward_heal_hop(
+    node: &Node,
+    next_addr: &str,
+    token: &str,
+    start_addr: &str,
+) -> Result<(), AnyErr> {
+    let hop = format!("NODE HEAL-HOP {} {}\n", token, start_addr);
+    let Err(e) = node.send_line(next_addr, &hop).await else {
+        return Ok(());
+    };
+    let Some(successor) = node.topology_successor().await else {
+        return Err(e);
+    };
+    tracing::warn!(node = %node.port, target = %next_addr, retry = %successor, error = ?e, "Heal walk: Hop failed, retrying via topology successor");
+    match retry_hop(&successor, start_addr, ()) {
+        Hop::Done { to, .. } => {
+            node.send_line(&to, &format!("NODE HEAL-DONE {}\n", token))
+                .await
+        }
+        Hop::Forward { to, .. } => node.send_line(&to, &hop).await,
+        Hop::Stop => Ok(()),
+    }
+}
+
 async fn handle_ring_forward<W: AsyncWrite + Unpin>(
     node: &Node,
     writer: &mut W,
@@ -449,10 +1178,217 @@ async fn handle_ring_forward<W: AsyncWrite + Unpin>(
     Ok(())
 }
 
-/// Handle "TOPOLOGY WALK" from the client on the start node.
+/// Handles "RING SIZE": how many netmap nodes answer right now
+async fn handle_ring_size<W: AsyncWrite + Unpin>(
+    node: &Arc<Node>,
+    writer: &mut W,
+) -> Result<(), AnyErr> {
+    let health = health::survey(node).await;
+    writer
+        .write_all(format!("SIZE {}\nOK\n", health.reachable()).as_bytes())
+        .await?;
+    Ok(())
+}
+
+/// Handles "RING HEALTH": reachable nodes, whether the ring closes, the
+/// slowest hop and wiring anomalies (see [`health::RingHealth`])
+async fn handle_ring_health<W: AsyncWrite + Unpin>(
+    node: &Arc<Node>,
+    writer: &mut W,
+) -> Result<(), AnyErr> {
+    let health = health::survey(node).await;
+    if !health.closed || !health.unpointed.is_empty() || !health.multi_pointed.is_empty() {
+        tracing::warn!(node = %node.port, reachable = health.reachable(), closed = health.closed, "Ring wiring anomalies found");
+    }
+    writer
+        .write_all(format!("{}OK\n", health).as_bytes())
+        .await?;
+    Ok(())
+}
+
+/// Handles "RING AUDIT [APPLY]": lists wiring errors and the `NODE NEXT`
+/// changes that would turn the reachable nodes into one ring. With `APPLY`,
+/// those changes are sent.
+async fn handle_ring_audit<W: AsyncWrite + Unpin>(
+    node: &Arc<Node>,
+    writer: &mut W,
+    apply: bool,
+) -> Result<(), AnyErr> {
+    let survey = health::survey(node).await;
+    let audit = health::audit(&survey, port_str(&node.port));
+    let mut out = String::new();
+    for anomaly in &audit.anomalies {
+        out.push_str(&format!("{}\n", anomaly));
+    }
+    for (from, to) in &audit.fixes {
+        out.push_str(&format!("FIX {}->{}\n", from, to));
+    }
+    if apply && !audit.fixes.is_empty() {
+        let mut applied = 0;
+        for (from, to) in &audit.fixes {
+            let line = format!("NODE NEXT {}", node.peer_addr(to));
+            match query_lines(node, &node.peer_addr(from), &line).await {
+                Ok(_) => applied += 1,
+                Err(e) => {
+                    tracing::warn!(node = %node.port, from = %from, to = %to, error = %e, "Could not rewire node")
+                }
+            }
+        }
+        tracing::info!(node = %node.port, applied, fixes = audit.fixes.len(), "Ring rewired by audit");
+        out.push_str(&format!("APPLIED {}/{}\n", applied, audit.fixes.len()));
+    }
+    out.push_str("OK\n");
+    writer.write_all(out.as_bytes()).await?;
+    Ok(())
+}
+
+/// Handles "RING TRACE <dest>": follows next hops from this node toward
+/// `dest` and lists each hop with its latency (see [`crate::trace`])
+async fn handle_ring_trace<W: AsyncWrite + Unpin>(
+    node: &Node,
+    writer: &mut W,
+    dest: String,
+) -> Result<(), AnyErr> {
+    let own = port_str(&node.port).to_string();
+    let mut lines = Vec::new();
+    if own == dest {
+        lines.extend(format!("END {}\n", trace::End::Reached).into_bytes());
+    } else {
+        trace_onward(node, &dest, vec![own.clone()], &mut lines).await?;
+    }
+
+    let mut hops: Vec<trace::Hop> = Vec::new();
+    let mut end = None;
+    for line in String::from_utf8_lossy(&lines).lines() {
+        match line.strip_prefix("END ") {
+            Some(outcome) => end = outcome.parse().ok(),
+            None => hops.extend(line.parse().ok()),
+        }
+    }
+    let end = end.unwrap_or_else(|| trace::End::Lost {
+        after: hops.last().map_or(own, |hop| hop.port.clone()),
+    });
+    let trace = Trace { dest, hops, end };
+    if trace.end != trace::End::Reached {
+        tracing::warn!(node = %node.port, dest = %trace.dest, end = %trace.end, hops = trace.hops.len(), "Ring trace did not reach its destination");
+    }
+    writer
+        .write_all(format!("{}OK\n", trace).as_bytes())
+        .await?;
+    Ok(())
+}
+
+/// Handles "RING TRACE-HOP <dest> <path>": answers at once, so the sender
+/// can time the hop, then carries the trace on toward `dest`
+async fn handle_ring_trace_hop<W: AsyncWrite + Unpin>(
+    node: &Node,
+    writer: &mut W,
+    dest: String,
+    mut path: Vec<String>,
+) -> Result<(), AnyErr> {
+    let own = port_str(&node.port).to_string();
+    writer
+        .write_all(format!("TRACE {}\n", own).as_bytes())
+        .await?;
+    if own == dest {
+        writer
+            .write_all(format!("END {}\n", trace::End::Reached).as_bytes())
+            .await?;
+        return Ok(());
+    }
+    path.push(own);
+    trace_onward(node, &dest, path, writer).await
+}
+
+/// Sends a trace on to this node's next hop, `path` being the nodes it
+/// passed (this one last), and writes the `HOP` and `END` lines of the rest
+/// of its way to `out` as they come
+async fn trace_onward<W: AsyncWrite + Unpin>(
+    node: &Node,
+    dest: &str,
+    path: Vec<String>,
+    out: &mut W,
+) -> Result<(), AnyErr> {
+    let own = port_str(&node.port).to_string();
+    let end = |end: trace::End| format!("END {}\n", end).into_bytes();
+    if path.len() > trace::MAX_HOPS {
+        out.write_all(&end(trace::End::MaxHops)).await?;
+        return Ok(());
+    }
+    let Some(next) = node.get_next().await else {
+        out.write_all(&end(trace::End::NoNext { at: own })).await?;
+        return Ok(());
+    };
+    let next_port = port_str(&next).to_string();
+    if path.contains(&next_port) {
+        out.write_all(&end(trace::End::Loop { at: next_port }))
+            .await?;
+        return Ok(());
+    }
+    let via = match node.route(&next).await {
+        relay::Route::Relayed { relay, .. } => Some(port_str(&relay).to_string()),
+        relay::Route::Direct(_) => None,
+    };
+
+    let started = Instant::now();
+    let line = format!("RING TRACE-HOP {} {}\n", dest, path.join("+"));
+    let answered = tokio::time::timeout(trace::HOP_TIMEOUT, async {
+        let mut s = node.connect(&next).await?;
+        s.write_all(line.as_bytes()).await?;
+        let mut reader = BufReader::new(s);
+        let mut ack = String::new();
+        reader.read_line(&mut ack).await?;
+        if !ack.starts_with("TRACE ") {
+            return Err(format!("unexpected reply to TRACE-HOP: '{}'", ack.trim_end()).into());
+        }
+        Ok::<_, AnyErr>(reader)
+    })
+    .await
+    .unwrap_or_else(|_| Err("timed out".into()));
+    let mut reader = match answered {
+        Ok(reader) => reader,
+        Err(e) => {
+            tracing::debug!(node = %node.port, next = %next, error = %e, "Ring trace: Next hop did not answer");
+            let unreachable = trace::End::Unreachable {
+                at: own,
+                next: next_port,
+            };
+            out.write_all(&end(unreachable)).await?;
+            return Ok(());
+        }
+    };
+    let hop = trace::Hop {
+        port: next_port.clone(),
+        rtt: started.elapsed(),
+        via,
+    };
+    out.write_all(format!("{}\n", hop).as_bytes()).await?;
+
+    // Relay the rest of the trace as it comes back
+    let mut line = String::new();
+    loop {
+        line.clear();
+        match tokio::time::timeout(trace::RELAY_TIMEOUT, reader.read_line(&mut line)).await {
+            Ok(Ok(n)) if n > 0 => {
+                out.write_all(line.as_bytes()).await?;
+                if line.starts_with("END ") {
+                    return Ok(());
+                }
+            }
+            _ => break,
+        }
+    }
+    out.write_all(&end(trace::End::Lost { after: next_port }))
+        .await?;
+    Ok(())
+}
+
+/// Handle "TOPOLOGY WALK [TIMEOUT <ms>]" from the client on the start node.
+/// The walk is waited for up to the given timeout, or the `walk-timeout` setting.
 async fn handle_topology_walk<W: AsyncWrite + Unpin>(
     node: &Node,
     writer: &mut W,
+    timeout: Option<Duration>,
 ) -> Result<(), AnyErr> {
     let token = node.make_walk_token();
     let rx = node.register_walk(token.as_str()).await;
@@ -463,7 +1399,7 @@ async fn handle_topology_walk<W: AsyncWrite + Unpin>(
     };
 
     if let Err(e) = node
-        .forward_topology_hop(&token, &node.port, &history)
+        .forward_topology_hop(&token, &node.port, node.topology_epoch(), &history)
         .await
     {
         writer
@@ -472,10 +1408,14 @@ async fn handle_topology_walk<W: AsyncWrite + Unpin>(
         return Ok(());
     }
 
-    match tokio::time::timeout(Duration::from_secs(30), rx).await {
+    let walk_timeout = match timeout {
+        Some(timeout) => timeout,
+        None => node.settings().await.walk_timeout,
+    };
+    match tokio::time::timeout(walk_timeout, rx).await {
         Ok(Ok(final_history)) => {
-            for seg in final_history.split(';').filter(|s| !s.is_empty()) {
-                writer.write_all(format!("{seg}\n").as_bytes()).await?;
+            for edge in final_history.edges() {
+                writer.write_all(format!("{}\n", edge).as_bytes()).await?;
             }
             writer.write_all(b"OK\n").await?;
         }
@@ -483,6 +1423,7 @@ async fn handle_topology_walk<W: AsyncWrite + Unpin>(
             writer.write_all(b"ERR walk canceled\n").await?;
         }
         Err(_) => {
+            node.cancel_walk(&token).await;
             writer.write_all(b"ERR walk timeout\n").await?;
         }
     }
@@ -495,38 +1436,40 @@ async fn handle_topology_hop<W: AsyncWrite + Unpin>(
     writer: &mut W,
     token: String,
     start_addr: String,
-    history: String,
+    epoch: u64,
+    history: Topology,
 ) -> Result<(), AnyErr> {
-    let Some(next_addr) = node.get_next().await else {
-        let _ = writer.write_all(b"OK\n").await;
-        return Ok(());
-    };
-
-    let new_history = append_edge(history, &node.port, &next_addr);
-
-    if port_str(&next_addr) == port_str(&start_addr) {
-        if let Err(e) = node
-            .send_topology_done(&start_addr, &token, &new_history)
-            .await
-        {
-            tracing::warn!(
-                node = %node.port,
-                target = %start_addr,
-                error = ?e,
-                "TOPOLOGY DONE send failed"
-            );
+    let state = node.ring_state().await;
+    match state.topology_hop(&start_addr, epoch, &history) {
+        Hop::Stop => {}
+        Hop::Done {
+            to,
+            payload: (epoch, history),
+        } => {
+            if let Err(e) = node.send_topology_done(&to, &token, epoch, &history).await {
+                tracing::warn!(
+                    node = %node.port,
+                    target = %to,
+                    error = ?e,
+                    "TOPOLOGY DONE send failed"
+                );
+            }
         }
-    } else {
-        if let Err(e) = node
-            .forward_topology_hop(&token, &start_addr, &new_history)
-            .await
-        {
-            tracing::warn!(
-                node = %node.port,
-                target = %next_addr,
-                error = ?e,
-                "TOPOLOGY HOP forward failed"
-            );
+        Hop::Forward {
+            to,
+            payload: (epoch, history),
+        } => {
+            if let Err(e) = node
+                .forward_topology_hop(&token, &start_addr, epoch, &history)
+                .await
+            {
+                tracing::warn!(
+                    node = %node.port,
+                    target = %to,
+                    error = ?e,
+                    "TOPOLOGY HOP forward failed"
+                );
+            }
         }
     }
 
@@ -538,13 +1481,23 @@ async fn handle_topology_done<W: AsyncWrite + Unpin>(
     node: Arc<Node>,
     writer: &mut W,
     token: String,
-    history: String,
+    epoch: u64,
+    history: Topology,
 ) -> Result<(), AnyErr> {
-    // Finish the client walk if we are the start node
-    let _ = node.finish_walk(&token, history.clone()).await;
+    // Only a walk started here and still running may complete
+    if !node.finish_walk(&token, history.clone()).await {
+        return reject_done(&node, writer, "TOPOLOGY DONE", &token).await;
+    }
 
-    // Persist and broadcast the completed topology
-    node.set_topology_from_history(&history).await;
+    // Persist and broadcast the completed topology under an epoch newer than
+    // any the walk saw: a timestamp from this node's clock
+    let seen = node.ring_state().await.seen_epoch(epoch);
+    let epoch = node.observe(Timestamp(seen)).0;
+    node.set_topology_from_history(epoch, &history).await;
+    node.emit(NodeEvent::WalkCompleted {
+        token,
+        history: history.to_string(),
+    });
 
     let node_clone = Arc::clone(&node);
     tokio::spawn(async move {
@@ -558,18 +1511,54 @@ async fn handle_topology_done<W: AsyncWrite + Unpin>(
 async fn handle_topology_set<W: AsyncWrite + Unpin>(
     node: &Node,
     writer: &mut W,
-    history: String,
+    epoch: u64,
+    history: Topology,
+) -> Result<(), AnyErr> {
+    node.set_topology_from_history(epoch, &history).await;
+    writer.write_all(b"OK\n").await?;
+    Ok(())
+}
+
+/// Handles "TOPOLOGY GET [JSON]": the edges this node knows, as `a->b` lines
+/// or as a single-line [`node::TopologyView`]
+async fn handle_topology_get<W: AsyncWrite + Unpin>(
+    node: &Node,
+    writer: &mut W,
+    json: bool,
 ) -> Result<(), AnyErr> {
-    node.set_topology_from_history(&history).await;
+    let view = node.topology_view().await;
+    if json {
+        let view = serde_json::to_string(&view)?;
+        writer.write_all(format!("{}\n", view).as_bytes()).await?;
+        return Ok(());
+    }
+
+    writer
+        .write_all(format!("EPOCH {}\n", view.epoch).as_bytes())
+        .await?;
+    if view.edges.is_empty() {
+        writer.write_all(b"(empty)\n").await?;
+    }
+    for edge in &view.edges {
+        writer
+            .write_all(format!("{}->{}\n", edge.from, edge.to).as_bytes())
+            .await?;
+    }
     writer.write_all(b"OK\n").await?;
     Ok(())
 }
 
 /* -------- NETMAP -------- */
 
+/// Handles "NETMAP DISCOVER [WAIT [TIMEOUT <ms>]]". Without `WAIT` the walk
+/// runs in the background; with it, the discovered entries are answered as
+/// `<port>=<status>` lines and `OK` once the walk is back, or an error after
+/// the timeout (default `walk-timeout`).
 async fn handle_netmap_discover<W: AsyncWrite + Unpin>(
-    node: &Node,
+    node: Arc<Node>,
     writer: &mut W,
+    wait: bool,
+    timeout: Option<Duration>,
 ) -> Result<(), AnyErr> {
     let token = node.make_invest_token();
 
@@ -578,17 +1567,50 @@ async fn handle_netmap_discover<W: AsyncWrite + Unpin>(
         return Ok(());
     };
 
-    // entries begins with "<node_port>=Alive"
-    let entries = format!("{}=Alive", port_str(&node.port));
+    // Registered even when nobody waits, so its NETMAP DONE is accepted
+    let rx = node.register_discover(&token).await;
+    let discover_timeout = match timeout {
+        Some(timeout) => timeout,
+        None => node.settings().await.walk_timeout,
+    };
+
+    // entries begin with this node, Alive
+    let entries = node.entries_with_self(&Netmap::default());
     if let Err(e) = node.forward_netmap_hop(&token, &node.port, &entries).await {
+        node.cancel_discover(&token).await;
         writer
             .write_all(format!("ERR forward failed: {e}\n").as_bytes())
             .await?;
         return Ok(());
     }
 
-    // We don't need to wait here; it's a background ring discovery.
-    writer.write_all(b"OK\n").await?;
+    if !wait {
+        // We don't need to wait here; it's a background ring discovery,
+        // accepted back until the walk timeout
+        tokio::spawn(async move {
+            tokio::time::sleep(discover_timeout).await;
+            node.cancel_discover(&token).await;
+        });
+        writer.write_all(b"OK\n").await?;
+        return Ok(());
+    }
+    match tokio::time::timeout(discover_timeout, rx).await {
+        Ok(Ok(entries)) => {
+            for (port, member) in &entries.0 {
+                writer
+                    .write_all(format!("{}={:?}\n", port, member.status).as_bytes())
+                    .await?;
+            }
+            writer.write_all(b"OK\n").await?;
+        }
+        Ok(Err(_)) => {
+            writer.write_all(b"ERR discovery canceled\n").await?;
+        }
+        Err(_) => {
+            node.cancel_discover(&token).await;
+            writer.write_all(b"ERR discovery timeout\n").await?;
+        }
+    }
     Ok(())
 }
 
@@ -597,38 +1619,30 @@ async fn handle_netmap_hop<W: AsyncWrite + Unpin>(
     writer: &mut W,
     token: String,
     start_addr: String,
-    entries: String,
+    entries: Netmap,
 ) -> Result<(), AnyErr> {
-    let Some(next_addr) = node.get_next().await else {
-        let _ = writer.write_all(b"OK\n").await;
-        return Ok(());
-    };
-
-    let new_entries = node.entries_with_self(&entries);
-
-    if port_str(&next_addr) == port_str(&start_addr) {
-        if let Err(e) = node
-            .send_netmap_done(&start_addr, &token, &new_entries)
-            .await
-        {
-            tracing::warn!(
-                node = %node.port,
-                target = %start_addr,
-                error = ?e,
-                "NETMAP DONE send failed"
-            );
+    let state = node.ring_state().await;
+    match state.netmap_hop(&start_addr, &entries, node.own_member()) {
+        Hop::Stop => {}
+        Hop::Done { to, payload } => {
+            if let Err(e) = node.send_netmap_done(&to, &token, &payload).await {
+                tracing::warn!(
+                    node = %node.port,
+                    target = %to,
+                    error = ?e,
+                    "NETMAP DONE send failed"
+                );
+            }
         }
-    } else {
-        if let Err(e) = node
-            .forward_netmap_hop(&token, &start_addr, &new_entries)
-            .await
-        {
-            tracing::warn!(
-                node = %node.port,
-                target = %next_addr,
-                error = ?e,
-                "NETMAP HOP forward failed"
-            );
+        Hop::Forward { to, payload } => {
+            if let Err(e) = node.forward_netmap_hop(&token, &start_addr, &payload).await {
+                tracing::warn!(
+                    node = %node.port,
+                    target = %to,
+                    error = ?e,
+                    "NETMAP HOP forward failed"
+                );
+            }
         }
     }
 
@@ -637,33 +1651,88 @@ async fn handle_netmap_hop<W: AsyncWrite + Unpin>(
 }
 
 async fn handle_netmap_done<W: AsyncWrite + Unpin>(
-    node: &Node,
+    node: Arc<Node>,
     writer: &mut W,
-    _token: String,
-    entries: String,
+    token: String,
+    entries: Netmap,
 ) -> Result<(), AnyErr> {
+    // Only a discovery started here and still running may complete
+    let Some(waiter) = node.take_discover(&token).await else {
+        return reject_done(&node, writer, "NETMAP DONE", &token).await;
+    };
     // Persist locally, then broadcast to all nodes
     node.set_network_nodes_from_entries(&entries).await;
     node.broadcast_netmap(&entries).await;
+    // A waiting NETMAP DISCOVER answers once the map is sent to every node
+    let _ = waiter.send(entries.clone());
+    tokio::spawn(async move { node.negotiate().await });
 
     let _ = writer.write_all(b"OK\n").await;
     Ok(())
 }
 
-async fn handle_netmap_set<W: AsyncWrite + Unpin>(
+/// Answers a walk's DONE whose token no walk started on this node is waiting
+/// for: forged, replayed, or arriving after the walk finished or timed out
+async fn reject_done<W: AsyncWrite + Unpin>(
     node: &Node,
     writer: &mut W,
-    entries: String,
+    command: &str,
+    token: &str,
+) -> Result<(), AnyErr> {
+    tracing::warn!(node = %node.port, command = %command, token = %token, "Security: rejected DONE for an unknown or finished walk");
+    writer
+        .write_all(format!("ERR UNKNOWN-TOKEN {} {}\n", command, token).as_bytes())
+        .await?;
+    Ok(())
+}
+
+async fn handle_netmap_set<W: AsyncWrite + Unpin>(
+    node: Arc<Node>,
+    writer: &mut W,
+    entries: Netmap,
 ) -> Result<(), AnyErr> {
     node.set_network_nodes_from_entries(&entries).await;
+    // Greet peers that are new (or back) in the background
+    tokio::spawn(async move { node.negotiate().await });
     let _ = writer.write_all(b"OK\n").await;
     Ok(())
 }
 
+/// Handles "NETMAP GET [JSON]". The JSON form is a single line holding a
+/// [`node::NetmapView`], without the trailing `OK`.
 async fn handle_netmap_get<W: AsyncWrite + Unpin>(
     node: &Node,
     writer: &mut W,
+    json: bool,
+    federated: bool,
 ) -> Result<(), AnyErr> {
+    let others = if federated {
+        federated_netmaps(node).await
+    } else {
+        Vec::new()
+    };
+
+    if json {
+        let mut view = node.netmap_view().await;
+        view.federated = others
+            .into_iter()
+            .map(|(ring, map)| node::FederatedRingView {
+                ring,
+                nodes: map
+                    .0
+                    .into_iter()
+                    .map(|(port, member)| node::FederatedNodeView {
+                        port,
+                        status: member.status,
+                    })
+                    .collect(),
+            })
+            .collect();
+        let view = serde_json::to_string(&view)?;
+        writer.write_all(format!("{}\n", view).as_bytes()).await?;
+        return Ok(());
+    }
+
     let lines = node.get_network_nodes_lines().await;
     if lines.is_empty() {
         writer.write_all(b"(empty)\n").await?;
@@ -672,257 +1741,1476 @@ async fn handle_netmap_get<W: AsyncWrite + Unpin>(
             writer.write_all(format!("{l}\n").as_bytes()).await?;
         }
     }
+    // Nodes of federated rings follow, as `<ring>/<port>=<status>`
+    for (ring, map) in others {
+        for (port, member) in map.0 {
+            writer
+                .write_all(format!("{}/{}={}\n", ring, port, member.status).as_bytes())
+                .await?;
+        }
+    }
     writer.write_all(b"OK\n").await?;
     Ok(())
 }
 
-/* -------- FILE CHUNKING helpers -------- */
-
-fn fair_chunk_len(index: u32, total_size: u64, parts: u32) -> u64 {
-    // Distribute remainder to the first (total_size % parts) chunks
-    let base = total_size / parts as u64;
-    let rem = total_size % parts as u64;
-    if (index as u64) < rem { base + 1 } else { base }
-}
-
-fn sum_len_up_to_inclusive(index: u32, total_size: u64, parts: u32) -> u64 {
-    (0..=index)
-        .map(|i| fair_chunk_len(i, total_size, parts))
-        .sum()
-}
+/* -------- CLUSTER -------- */
 
-fn chunk_file_name(name: &str, index: u32, parts: u32) -> String {
-    let safe = sanitize_filename(name);
-    format!("{}.part-{:03}-of-{:03}", safe, index + 1, parts)
+/// Handles "CLUSTER LEADER": the address of the node currently coordinating
+/// cluster-wide tasks, as seen by this node
+async fn handle_cluster_leader<W: AsyncWrite + Unpin>(
+    node: &Node,
+    writer: &mut W,
+) -> Result<(), AnyErr> {
+    let leader = node.peer_addr(node.leader());
+    writer
+        .write_all(format!("LEADER {}\nOK\n", leader).as_bytes())
+        .await?;
+    Ok(())
 }
 
-/* -------- FILE: PUSH / HOP handlers -------- */
-
-async fn handle_file_push<R, W>(
+/// Handles "CLUSTER STATS": asks every netmap member for `NODE DU` and
+/// `NODE METRICS` at once and answers one [`stats::ClusterStatsView`] line
+async fn handle_cluster_stats<W: AsyncWrite + Unpin>(
     node: Arc<Node>,
-    reader: &mut R,
     writer: &mut W,
-    size: u64,
-    name: String,
-) -> Result<(), AnyErr>
-where
-    R: AsyncRead + Unpin,
-    W: AsyncWrite + Unpin,
-{
-    // Handle files larger than the node supports
-    if size > node.file_size {
-        tracing::error!(node = %node.port, file_name = %name, file_size = size, max_file_size = %node.file_size, "File size is too large");
-
-        let msg = format!(
-            "ERR File size is too large ({} > {})\n",
-            size, node.file_size
-        );
-        writer.write_all(msg.as_bytes()).await?;
+) -> Result<(), AnyErr> {
+    let mut queries = JoinSet::new();
+    for (port, member) in node.get_network_nodes_entries().await.0 {
+        let node = Arc::clone(&node);
+        queries.spawn(async move {
+            let addr = node.peer_addr(&port);
+            let answers = async {
+                let du = query_lines(&node, &addr, "NODE DU").await?;
+                let metrics = query_lines(&node, &addr, "NODE METRICS").await?;
+                Ok::<_, AnyErr>(stats::MemberStats::from_answers(
+                    port.clone(),
+                    member.status,
+                    &du,
+                    &metrics,
+                )?)
+            };
+            answers.await.unwrap_or_else(|e| {
+                tracing::debug!(node = %node.port, peer = %port, error = %e, "CLUSTER STATS: no answer from member");
+                stats::MemberStats::unreachable(port, member.status)
+            })
+        });
+    }
+    let mut members = Vec::new();
+    while let Some(member) = queries.join_next().await {
+        members.push(member?);
+    }
+    members.sort_by(|a, b| a.port.cmp(&b.port));
 
-        // Drain the stream to consume the file body the client is sending
-        let mut sink = vec![0u8; size as usize];
-        reader.read_exact(&mut sink).await?;
+    // Files of federated rings take no space in this one
+    let (files, logical) = {
+        let tags = node.file_tags.read().await;
+        let local = tags.values().filter(|tag| tag.ring.is_none());
+        (local.clone().count(), local.map(|tag| tag.size).sum())
+    };
+    let view =
+        stats::ClusterStatsView::new(port_str(&node.port).to_string(), files, logical, members);
+    writer
+        .write_all(format!("{}\n", serde_json::to_string(&view)?).as_bytes())
+        .await?;
+    Ok(())
+}
 
-        return Ok(());
-    }
+/* -------- RELAY -------- */
 
-    let name = Path::new(&name)
-        .file_name()
-        .unwrap()
-        .to_str()
-        .unwrap()
-        .to_string();
+/// Handles "RELAY REGISTER <port>": keeps the connection as the session of
+/// the node at `port`, writing an `OPEN <id>` line whenever a peer connects
+/// to it, until the relayed node hangs up or opens a newer session
+async fn handle_relay_register(
+    node: &Node,
+    mut reader: BufReader<OwnedReadHalf>,
+    mut writer: OwnedWriteHalf,
+    port: String,
+) -> Result<(), AnyErr> {
+    let mut opens = node.relay_hub.register(&port);
+    writer.write_all(b"OK\n").await?;
+    tracing::info!(node = %node.port, relayed = %port, "Relay session open");
 
-    // Determine how many parts to split into: number of known nodes (fallback to 1)
-    let parts: u32 = node.network_size().await as u32;
+    let mut line = String::new();
+    let res = loop {
+        tokio::select! {
+            id = opens.recv() => match id {
+                Some(id) => {
+                    if let Err(e) = writer.write_all(format!("OPEN {}\n", id).as_bytes()).await {
+                        break Err(e);
+                    }
+                }
+                None => break Ok(()),
+            },
+            read = reader.read_line(&mut line) => match read {
+                Ok(0) => break Ok(()),
+                Ok(_) => line.clear(),
+                Err(e) => break Err(e),
+            },
+        }
+    };
+    drop(opens);
+    node.relay_hub.unregister(&port);
+    tracing::info!(node = %node.port, relayed = %port, "Relay session closed");
+    Ok(res?)
+}
 
-    // Update local file_tags (start, size, parts)
-    let start_port_num: u16 = port_str(&node.port).parse().unwrap_or(0);
-    node.set_file_tag(&name, start_port_num, size, parts).await;
+/// Handles "RELAY CONNECT <port>": has the node at `port` open a connection
+/// through its session and splices this one to it
+async fn handle_relay_connect(
+    node: &Node,
+    reader: BufReader<OwnedReadHalf>,
+    mut writer: OwnedWriteHalf,
+    port: String,
+) -> Result<(), AnyErr> {
+    let Some((id, accepted)) = node.relay_hub.open(&port) else {
+        writer
+            .write_all(format!("ERR no relay session for {}\n", port).as_bytes())
+            .await?;
+        return Ok(());
+    };
+    let conn = match tokio::time::timeout(relay::ACCEPT_TIMEOUT, accepted).await {
+        Ok(Ok(conn)) => conn,
+        _ => {
+            node.relay_hub.forget(id);
+            writer
+                .write_all(format!("ERR relayed node {} did not connect\n", port).as_bytes())
+                .await?;
+            return Ok(());
+        }
+    };
+    relay::splice((reader, writer), conn).await?;
+    Ok(())
+}
 
-    if parts == 1 {
-        // Single node: read everything and store locally
-        let mut buf = vec![0u8; size as usize];
-        reader.read_exact(&mut buf).await?;
-        let _ = save_into_node_dir(&node, &name, &buf, "content").await?;
+/// Handles "RELAY ACCEPT <id>": hands the connection to the peer waiting for
+/// connection `id`
+async fn handle_relay_accept(
+    node: &Node,
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+    id: u64,
+) -> Result<(), AnyErr> {
+    if let Err((_, mut writer)) = node.relay_hub.accept(id, (reader, writer)) {
+        writer
+            .write_all(format!("ERR unknown relay connection {}\n", id).as_bytes())
+            .await?;
+    }
+    Ok(())
+}
 
-        // Notify predecessor
-        let node_clone = Arc::clone(&node);
-        let name_clone = name.clone();
-        tokio::spawn(async move {
-            notify_predecessor(node_clone, name_clone).await;
-        });
+/* -------- FEDERATION -------- */
 
+/// Handles "FEDERATION LINK <ring> <remote> [<token>]": makes this node the
+/// border to `ring` once `remote` confirms it belongs to that ring, and
+/// shares the link with every node of this ring. The token, presented to the
+/// other ring instead of this ring's cluster token, stays on this node.
+async fn handle_federation_link<W: AsyncWrite + Unpin>(
+    node: &Node,
+    writer: &mut W,
+    ring: String,
+    remote: String,
+    token: Option<Secret>,
+) -> Result<(), AnyErr> {
+    if node.ring_id.as_deref() == Some(ring.as_str()) {
         writer
-            .write_all(format!("FILE {} bytes '{}' stored locally\nOK", size, name).as_bytes())
+            .write_all(b"ERR cannot federate a ring with itself\n")
             .await?;
         return Ok(());
     }
-
-    // We need a next hop
-    let Some(next) = node.get_next().await else {
-        writer.write_all(b"ERR no next hop set\n").await?;
-        // Drain the stream to keep protocol in sync
-        let mut sink = vec![0u8; size as usize];
-        reader.read_exact(&mut sink).await?;
+    if node.network_size().await > 1 && !node.ring_supports(Feature::Federation).await {
+        writer
+            .write_all(b"ERR FEDERATION_UNSUPPORTED some nodes do not understand federated files\n")
+            .await?;
         return Ok(());
-    };
-
-    let first_len = fair_chunk_len(0, size, parts);
-    // Read and save this node's first chunk
-    let mut first = vec![0u8; first_len as usize];
-    reader.read_exact(&mut first).await?;
-    let chunk_name = chunk_file_name(&name, 0, parts);
-    let saved_as = save_into_node_dir(&node, &chunk_name, &first, "content").await?;
-
-    // Notify predecessor
-    let node_clone = Arc::clone(&node);
-    let chunk_name_clone = chunk_name.clone();
-    tokio::spawn(async move {
-        notify_predecessor(node_clone, chunk_name_clone).await;
-    });
+    }
 
-    tracing::info!(
-        node = %node.port,
-        chunk = 1,
-        parts,
-        file = %saved_as.display(),
-        bytes = first_len,
-        "Saved file chunk"
-    );
+    // The remote node names its ring on the first line of FEDERATION GET
+    let connect = node.connect_remote(&remote, token.as_ref());
+    let remote_ring = query_over(node, &remote, "FEDERATION GET", connect)
+        .await
+        .map(|lines| {
+            lines
+                .first()
+                .and_then(|l| l.strip_prefix("RING "))
+                .map(str::to_string)
+        });
+    match remote_ring {
+        Ok(Some(id)) if id == ring => {}
+        Ok(id) => {
+            let id = id.unwrap_or_else(|| "-".to_string());
+            writer
+                .write_all(
+                    format!("ERR {} is on ring '{}', not '{}'\n", remote, id, ring).as_bytes(),
+                )
+                .await?;
+            return Ok(());
+        }
+        Err(e) => {
+            writer
+                .write_all(format!("ERR cannot reach {}: {}\n", remote, e).as_bytes())
+                .await?;
+            return Ok(());
+        }
+    }
 
-    // Open connection to next and stream the remaining bytes
-    let mut s = TcpStream::connect(&next).await?;
-    let token = node.make_file_token();
-    let header = format!(
-        "FILE RELAY-STREAM {} {} {} {} {} {}\n",
-        token, &node.port, size, parts, 1, name
+    node.set_federation_token(&ring, token).await;
+    let mut links = node.federation().await;
+    links.0.insert(
+        ring.clone(),
+        FederationLink {
+            border: port_str(&node.port).to_string(),
+            remote: remote.clone(),
+        },
     );
-    s.write_all(header.as_bytes()).await?;
-
-    // Forward exactly the remaining bytes (size - first_len) from client -> next
-    let mut limited = reader.take(size - first_len);
-    copy(&mut limited, &mut s).await?;
+    node.set_federation(links).await;
+    node.broadcast_federation().await;
+    tracing::info!(node = %node.port, ring = %ring, remote = %remote, "Federated with ring");
+    writer.write_all(b"OK\n").await?;
+    Ok(())
+}
 
-    writer
-        .write_all(
-            format!(
-                "FILE {} bytes split into {} chunks and distributed\nOK\n",
-                size, parts
-            )
-            .as_bytes(),
-        )
-        .await?;
+/// Handles "FEDERATION UNLINK <ring>": drops the link on every node of this ring
+async fn handle_federation_unlink<W: AsyncWrite + Unpin>(
+    node: &Node,
+    writer: &mut W,
+    ring: String,
+) -> Result<(), AnyErr> {
+    let mut links = node.federation().await;
+    if links.0.remove(&ring).is_none() {
+        writer
+            .write_all(format!("ERR ring '{}' is not federated\n", ring).as_bytes())
+            .await?;
+        return Ok(());
+    }
+    node.set_federation(links).await;
+    node.set_federation_token(&ring, None).await;
+    node.broadcast_federation().await;
+    tracing::info!(node = %node.port, ring = %ring, "Federation link removed");
+    writer.write_all(b"OK\n").await?;
     Ok(())
 }
 
-async fn handle_file_relay_blob<R, W>(
-    node: Arc<Node>,
-    reader: &mut R,
+/// Handles "FEDERATION GET": this ring's id, then one line per link
+async fn handle_federation_get<W: AsyncWrite + Unpin>(
+    node: &Node,
     writer: &mut W,
-    token: String,
-    start_addr: String,
-    size: u64,
-    name: String,
-) -> Result<(), AnyErr>
-where
-    R: AsyncRead + Unpin,
-    W: AsyncWrite + Unpin,
-{
-    // Read the exact file body first
-    let mut buf = vec![0u8; size as usize];
-    reader.read_exact(&mut buf).await?;
+) -> Result<(), AnyErr> {
+    let mut out = format!("RING {}\n", node.ring_id.as_deref().unwrap_or("-"));
+    for (ring, link) in node.federation().await.0 {
+        out.push_str(&format!(
+            "LINK {} {} {}\n",
+            ring,
+            node.peer_addr(&link.border),
+            link.remote
+        ));
+    }
+    out.push_str("OK\n");
+    writer.write_all(out.as_bytes()).await?;
+    Ok(())
+}
 
-    // If this hop delivered back to the start node, just finish & ACK.
-    if port_str(&node.port) == port_str(&start_addr) {
-        let _ = node.finish_file(&token).await;
-        let _ = writer.write_all(b"OK\n").await;
-        return Ok(());
+/// Next hop towards a federated ring's `<ring>/<name>` file: the border node,
+/// or on the border node itself the remote ring, which knows the file by
+/// `<name>` alone
+struct FederationHop {
+    ring: String,
+    addr: String,
+    name: String,
+    at_border: bool,
+}
+
+async fn federation_hop(node: &Node, name: &str) -> Option<FederationHop> {
+    let (ring, rest) = name.split_once('/')?;
+    let link = node.federation_link(ring).await?;
+    let at_border = link.border == port_str(&node.port);
+    Some(FederationHop {
+        ring: ring.to_string(),
+        addr: if at_border {
+            link.remote
+        } else {
+            node.peer_addr(&link.border)
+        },
+        name: if at_border { rest } else { name }.to_string(),
+        at_border,
+    })
+}
+
+impl FederationHop {
+    /// Connects to the next hop: another ring from the border node (see
+    /// [`Node::connect_federated`]), the border node of this ring elsewhere
+    async fn connect(&self, node: &Node) -> io::Result<TcpStream> {
+        if self.at_border {
+            node.connect_federated(&self.ring, &self.addr).await
+        } else {
+            node.connect(&self.addr).await
+        }
     }
+}
 
-    // Save locally
-    if let Err(e) = save_into_node_dir(&node, &name, &buf, "content").await {
-        tracing::error!(node = %node.port, file_name = %name, error = ?e, "Failed to save relayed file blob");
-    } else {
-        // Notify predecessor
-        let node_clone = Arc::clone(&node);
-        let name_clone = name.clone();
-        tokio::spawn(async move {
-            notify_predecessor(node_clone, name_clone).await;
-        });
+/// Drops the `<ring>/` prefix from names given with this node's own ring id
+fn own_ring_name(node: &Node, name: String) -> String {
+    match (&node.ring_id, name.split_once('/')) {
+        (Some(id), Some((ring, rest))) if ring == id => rest.to_string(),
+        _ => name,
     }
+}
 
-    // Forward to next
-    if let Some(_) = node.get_next().await {
-        if let Err(e) = node
-            .forward_file_relay_blob(&token, &start_addr, size, &name, &buf)
-            .await
-        {
-            tracing::warn!(node = %node.port, error = ?e, "FILE RELAY-BLOB forward failed");
+/// Forwards a push one hop towards a federated ring, and relays its answer.
+/// The border node tags the file on every node of this ring once the other
+/// ring has stored it.
+#[allow(clippy::too_many_arguments)]
+async fn federated_push<R, W>(
+    node: &Node,
+    reader: &mut R,
+    writer: &mut W,
+    size: u64,
+    hop: FederationHop,
+    mode: PushMode,
+    place: Labels,
+    sha256: Option<Digest>,
+    content_type: Option<String>,
+) -> Result<bool, AnyErr>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut line = format!("FILE PUSH {} {}", size, protocol::encode_name(&hop.name));
+    if mode != PushMode::default() {
+        line.push_str(&format!(" MODE {}", mode));
+    }
+    if !place.is_empty() {
+        line.push_str(&format!(" PLACE {}", place));
+    }
+    if let Some(sha256) = sha256 {
+        line.push_str(&format!(" SHA256 {}", sha256));
+    }
+    if let Some(content_type) = &content_type {
+        line.push_str(&format!(" TYPE {}", protocol::encode_name(content_type)));
+    }
+    let mut s = match hop.connect(node).await {
+        Ok(s) => s,
+        Err(e) => {
+            tracing::warn!(node = %node.port, ring = %hop.ring, addr = %hop.addr, error = %e, "Federated ring unreachable");
+            writer
+                .write_all(format!("ERR ring '{}' unreachable: {}\n", hop.ring, e).as_bytes())
+                .await?;
+            discard_body(reader, size).await?;
+            return Ok(true);
+        }
+    };
+    s.write_all(format!("{}\n", line).as_bytes()).await?;
+    copy(&mut (&mut *reader).take(size), &mut s).await?;
+    // The other side keeps reading commands until the connection is closed
+    s.shutdown().await?;
+    let mut reply = String::new();
+    s.read_to_string(&mut reply).await?;
+
+    if hop.at_border {
+        // Names chosen by the other ring (`MODE version`) get the ring prefix back
+        reply = reply
+            .split_inclusive('\n')
+            .map(|l| match l.strip_prefix("STORED ") {
+                Some(stored) => format!("STORED {}/{}", hop.ring, stored),
+                None => l.to_string(),
+            })
+            .collect();
+        let stored =
+            reply.lines().any(|l| l.trim() == "OK") && !reply.lines().any(|l| l.starts_with("ERR"));
+        if stored {
+            let name = reply
+                .lines()
+                .find_map(|l| l.strip_prefix("STORED "))
+                .map(protocol::decode_name)
+                .unwrap_or_else(|| format!("{}/{}", hop.ring, hop.name));
+            let tag = node::FileTag {
+                start: 0,
+                size,
+                parts: 0,
+                holders: Vec::new(),
+                ring: Some(hop.ring.clone()),
+                chunks: None,
+                conflict_of: None,
+                version: None,
+                content_type,
+            };
+            let tag = node.stamp_file_tag(&name, tag, None).await;
+            node.insert_file_tag(&name, tag.clone()).await;
+            node.broadcast_file_tag(&name, &tag).await;
+            tracing::info!(node = %node.port, ring = %hop.ring, file = %name, size, "File stored on federated ring");
         }
     }
+    writer.write_all(reply.as_bytes()).await?;
+    Ok(true)
+}
 
-    let _ = writer.write_all(b"OK\n").await;
+/// Streams a federated ring's file from the next hop towards it
+async fn federated_pull<W: AsyncWrite + Unpin>(
+    node: &Node,
+    writer: &mut W,
+    hop: FederationHop,
+    offset: u64,
+) -> Result<(), AnyErr> {
+    let mut s = match hop.connect(node).await {
+        Ok(s) => s,
+        Err(e) => {
+            writer
+                .write_all(format!("ERR ring '{}' unreachable: {}\n", hop.ring, e).as_bytes())
+                .await?;
+            return Ok(());
+        }
+    };
+    let mut line = format!("FILE PULL {}", protocol::encode_name(&hop.name));
+    if offset > 0 {
+        line.push_str(&format!(" OFFSET {}", offset));
+    }
+    s.write_all(format!("{}\n", line).as_bytes()).await?;
+    copy(&mut s, writer).await?;
     Ok(())
 }
 
-async fn handle_file_relay_stream<R, W>(
+/// Nodes of every federated ring, as reported through its link. Rings that do
+/// not answer are left out.
+async fn federated_netmaps(node: &Node) -> Vec<(String, Netmap)> {
+    let mut out = Vec::new();
+    for (ring, link) in node.federation().await.0 {
+        let entries = if link.border == port_str(&node.port) {
+            let connect = node.connect_federated(&ring, &link.remote);
+            query_over(node, &link.remote, "NETMAP GET", connect)
+                .await
+                .map(|lines| lines.join(","))
+        } else {
+            // The border node lists the other ring's nodes after its own, prefixed
+            let prefix = format!("{}/", ring);
+            query_lines(node, &node.peer_addr(&link.border), "NETMAP GET FEDERATED")
+                .await
+                .map(|lines| {
+                    lines
+                        .iter()
+                        .filter_map(|l| l.strip_prefix(&prefix))
+                        .collect::<Vec<_>>()
+                        .join(",")
+                })
+        };
+        match entries {
+            Ok(entries) => {
+                // `(empty)` is what a node with no netmap answers
+                let entries: Vec<&str> = entries.split(',').filter(|e| e.contains('=')).collect();
+                out.push((ring, entries.join(",").parse().unwrap_or_default()));
+            }
+            Err(e) => {
+                tracing::warn!(node = %node.port, ring = %ring, error = %e, "Cannot read federated netmap")
+            }
+        }
+    }
+    out
+}
+
+/// Sends one command line to `addr` and collects the reply lines before `OK`
+/// (or `OK <detail>`, as `NODE NEXT` answers)
+pub(crate) async fn query_lines(
+    node: &Node,
+    addr: &str,
+    line: &str,
+) -> Result<Vec<String>, AnyErr> {
+    query_over(node, addr, line, node.connect(addr)).await
+}
+
+/// [`query_lines`] over the connection `connect` opens to `addr`
+async fn query_over(
+    node: &Node,
+    addr: &str,
+    line: &str,
+    connect: impl Future<Output = io::Result<TcpStream>>,
+) -> Result<Vec<String>, AnyErr> {
+    let timeout = node.settings().await.health_timeout;
+    let query = async {
+        let mut s = BufReader::new(connect.await?);
+        s.get_mut()
+            .write_all(format!("{}\n", line).as_bytes())
+            .await?;
+        let mut lines = Vec::new();
+        let mut buf = String::new();
+        loop {
+            buf.clear();
+            if s.read_line(&mut buf).await? == 0 {
+                break;
+            }
+            let l = buf.trim_end();
+            if l == "OK" || l.starts_with("OK ") {
+                break;
+            }
+            if let Some(e) = l.strip_prefix("ERR ") {
+                return Err(e.to_string().into());
+            }
+            lines.push(l.to_string());
+        }
+        Ok::<_, AnyErr>(lines)
+    };
+    tokio::time::timeout(timeout, query)
+        .await
+        .map_err(|_| format!("{} did not answer", addr))?
+}
+
+/* -------- FILE CHUNKING helpers -------- */
+
+/// Name of a file's chunk. Like file names, it is only sanitized where it
+/// touches the disk.
+pub(crate) fn chunk_file_name(name: &str, index: u32, parts: u32) -> String {
+    format!("{}.part-{:03}-of-{:03}", name, index + 1, parts)
+}
+
+/* -------- FILE: PUSH / HOP handlers -------- */
+
+/// Name a pushed file is stored under: its path, `/`-separated, without
+/// leading `/` or `.` components. A name climbing out (`..`) or left blank
+/// names nothing.
+fn stored_name(name: &str) -> Option<String> {
+    let mut parts = Vec::new();
+    for component in Path::new(name).components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_str()?),
+            Component::CurDir | Component::RootDir => {}
+            Component::ParentDir | Component::Prefix(_) => return None,
+        }
+    }
+    let name = parts.join("/");
+    (!name.trim().is_empty()).then_some(name)
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn handle_file_push<R, W>(
     node: Arc<Node>,
     reader: &mut R,
     writer: &mut W,
-    token: String,
-    start_addr: String,
-    file_size: u64,
-    parts: u32,
-    index: u32,
+    size: u64,
     name: String,
-) -> Result<(), AnyErr>
+    mode: PushMode,
+    place: Labels,
+    sha256: Option<Digest>,
+    content_type: Option<String>,
+) -> Result<bool, AnyErr>
 where
     R: AsyncRead + Unpin,
     W: AsyncWrite + Unpin,
 {
-    if index >= parts {
+    // Files of a federated ring are stored there, through the border node
+    if let Some(hop) = federation_hop(&node, &name).await {
+        return federated_push(
+            &node,
+            reader,
+            writer,
+            size,
+            hop,
+            mode,
+            place,
+            sha256,
+            content_type,
+        )
+        .await;
+    }
+    let name = own_ring_name(&node, name);
+
+    // With PLACE constraints only the matching nodes hold chunks, one each
+    let placed = if place.is_empty() {
+        None
+    } else {
+        match placement(&node, &place).await {
+            Ok(holders) => Some(holders),
+            Err(e) => {
+                tracing::warn!(node = %node.port, file_name = %name, place = %place, error = %e, "Cannot place file");
+                writer.write_all(format!("ERR {}\n", e).as_bytes()).await?;
+                // Drain the stream to keep protocol in sync
+                discard_body(reader, size).await?;
+                return Ok(true);
+            }
+        }
+    };
+
+    // Determine how many parts to split into: number of known nodes (fallback to 1)
+    let parts: u32 = match &placed {
+        Some(holders) => holders.len() as u32,
+        None => node.network_size().await as u32,
+    };
+
+    // Handle files (or chunks) larger than the node supports
+    let settings = node.settings().await;
+    let too_large = if size > settings.file_size {
+        Some(too_large("file", size, settings.file_size))
+    } else if fair_chunk_len(0, size, parts) > settings.max_chunk_size {
+        Some(too_large(
+            "chunk",
+            fair_chunk_len(0, size, parts),
+            settings.max_chunk_size,
+        ))
+    } else {
+        None
+    };
+    if let Some(err) = too_large {
+        tracing::error!(node = %node.port, file_name = %name, file_size = size, error = %err, "File size is too large");
         writer
-            .write_all(b"ERR bad FILE RELAY-STREAM index\n")
+            .write_all(format!("ERR {}\n", err).as_bytes())
             .await?;
-        return Ok(());
+
+        // Drain the stream to consume the file body the client is sending
+        discard_body(reader, size).await?;
+        return Ok(true);
     }
 
-    // Compute my chunk length and read exactly those bytes
-    let my_len = fair_chunk_len(index, file_size, parts);
-    let mut buf = vec![0u8; my_len as usize];
-    reader.read_exact(&mut buf).await?;
+    // Refused here rather than when parsing, so the body can still be drained
+    let Some(name) = stored_name(&name) else {
+        writer.write_all(b"ERR invalid file name\n").await?;
+        discard_body(reader, size).await?;
+        return Ok(true);
+    };
 
-    // Tag the file on this node too
-    let start_port_num: u16 = port_str(&start_addr).parse().unwrap_or(0);
-    node.set_file_tag(&name, start_port_num, file_size, parts)
-        .await;
+    // Decide what happens to a file already stored under this name
+    let (existing, shared) = {
+        let tags = node.file_tags.read().await;
+        let shared = chunk_set_user(&tags, &name, &name).map(str::to_string);
+        (tags.get(&name).cloned(), shared)
+    };
+    let (name, replaced) = match (existing, mode) {
+        (Some(_), PushMode::Fail) => {
+            writer.write_all(b"ERR FILE_EXISTS\n").await?;
+            // Drain the stream to keep protocol in sync
+            discard_body(reader, size).await?;
+            return Ok(true);
+        }
+        (Some(_), PushMode::Version) => {
+            let versioned = node.versioned_name(&name).await;
+            writer
+                .write_all(format!("STORED {}\n", protocol::encode_name(&versioned)).as_bytes())
+                .await?;
+            (versioned, None)
+        }
+        (None, _) => (name, None),
+        (Some(old), PushMode::Overwrite) => {
+            // Old chunks go first: a different parts count would otherwise leave them behind
+            tracing::info!(node = %node.port, file = %name, parts = old.parts, "Overwriting file, discarding old chunks");
+            discard_file(&node, &name, old.parts).await;
+            node.broadcast_file_discard(&name, old.parts).await;
+            (name, old.version)
+        }
+    };
 
-    // Save my chunk locally
-    let chunk_name = chunk_file_name(&name, index, parts);
-    let saved_as = save_into_node_dir(&node, &chunk_name, &buf, "content").await?;
+    // New chunks stored under the name would overwrite the ones a copy still
+    // reads: they get a chunk set of their own, and the copy keeps the old one
+    let chunk_set = match shared {
+        Some(copy) if size > 0 => {
+            let chunk_set = node::FileTag::fresh_chunk_set(&name);
+            tracing::info!(node = %node.port, file = %name, shared_with = %copy, chunks = %chunk_set, "Chunks of file still shared, storing new ones apart");
+            chunk_set
+        }
+        _ => name.clone(),
+    };
 
-    // Notify predecessor
-    let node_clone = Arc::clone(&node);
-    tokio::spawn(async move {
-        notify_predecessor(node_clone, chunk_name).await;
-    });
+    let start_port_num: u16 = port_str(&node.port).parse().unwrap_or(0);
 
-    tracing::info!(
-        node = %node.port,
-        chunk = index + 1,
+    // An empty file has no chunks: it is just a tag, sent to every node
+    if size == 0 {
+        if let Err(e) = check_push_digest(sha256, Sha256::digest(&[])) {
+            writer.write_all(format!("ERR {}\n", e).as_bytes()).await?;
+            return Ok(true);
+        }
+        let tag = node::FileTag {
+            start: start_port_num,
+            size: 0,
+            parts: 0,
+            holders: Vec::new(),
+            ring: None,
+            chunks: None,
+            conflict_of: None,
+            version: None,
+            content_type,
+        };
+        let tag = node.stamp_file_tag(&name, tag, replaced).await;
+        node.insert_file_tag(&name, tag.clone()).await;
+        node.broadcast_file_tag(&name, &tag).await;
+        node.emit(NodeEvent::FilePushed {
+            name: name.clone(),
+            size: 0,
+            parts: 0,
+        });
+        writer
+            .write_all(format!("FILE 0 bytes '{}' tagged, no chunks\nOK\n", name).as_bytes())
+            .await?;
+        return Ok(true);
+    }
+
+    // The holder of every chunk, recorded in the tag by id so pulls find it
+    // wherever the ring puts it. Without a full topology chunks are relayed
+    // down the ring instead, and the hops name their holders as they answer.
+    let holders = match placed {
+        Some(holders) => holders,
+        None => chunk_holders(&node, start_port_num, parts).await,
+    };
+    let mut manifest = Vec::with_capacity(holders.len());
+    if holders.len() == parts as usize {
+        for port in &holders {
+            manifest.push(node.peer_id(port).await);
+        }
+    }
+
+    // Update local file_tags (start, size, parts, and the holder of each chunk)
+    let tag = node::FileTag {
+        start: holders
+            .first()
+            .filter(|_| !manifest.is_empty())
+            .and_then(|port| port.parse().ok())
+            .unwrap_or(start_port_num),
+        size,
         parts,
-        file = %saved_as.display(),
-        bytes = my_len,
-        "Saved file chunk"
+        holders: manifest,
+        ring: None,
+        chunks: (chunk_set != name).then(|| chunk_set.clone()),
+        conflict_of: None,
+        version: None,
+        content_type,
+    };
+    let tag = node.stamp_file_tag(&name, tag, replaced).await;
+    node.insert_file_tag(&name, tag.clone()).await;
+
+    if parts == 1 && tag.start == start_port_num {
+        // Single node: the whole file is the only chunk, streamed to disk
+        let token = node.make_file_token();
+        let chunk_name = chunk_file_name(&chunk_set, 0, parts);
+        let staged = stage_chunk(&node, &token, &chunk_name, (&mut *reader).take(size)).await;
+        let checked = staged.and_then(|entry| {
+            check_push_digest(sha256, entry.sha256)?;
+            Ok(entry)
+        });
+        let stored = match checked {
+            Ok(entry) => commit_chunk(&node, &token, &chunk_name, entry).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = stored {
+            let _ = fs::remove_file(staged_path(&node, &token, &chunk_name)).await;
+            discard_file(&node, &name, parts).await;
+            if e.to_string().starts_with(CHECKSUM_MISMATCH) {
+                writer.write_all(format!("ERR {}\n", e).as_bytes()).await?;
+                return Ok(true);
+            }
+            return Err(e);
+        }
+
+        node.emit(NodeEvent::FilePushed {
+            name: name.clone(),
+            size,
+            parts,
+        });
+        writer
+            .write_all(format!("FILE {} bytes '{}' stored locally\nOK", size, name).as_bytes())
+            .await?;
+        return Ok(true);
+    }
+
+    // We need a next hop
+    let Some(next) = node.get_next().await else {
+        writer.write_all(b"ERR no next hop set\n").await?;
+        // Drain the stream to keep protocol in sync
+        discard_body(reader, size).await?;
+        return Ok(true);
+    };
+
+    // Track the push so it can be followed with FILE PROGRESS and stopped with FILE CANCEL
+    let token = node.make_file_token();
+    let route = route_over(&holders, 0, size, parts);
+    let transfer = node
+        .begin_transfer(&token, TransferKind::Push, &name, size, route)
+        .await;
+    writer
+        .write_all(format!("TRANSFER {}\n", token).as_bytes())
+        .await?;
+
+    // Upload straight to every chunk's holder when the topology names them all,
+    // otherwise fall back to streaming the file down the ring
+    let local_index = holders
+        .iter()
+        .position(|p| p == port_str(&node.port))
+        .unwrap_or(0) as u32;
+    let mut reader = HashingReader::new(ProgressReader::new(reader, Arc::clone(&transfer)));
+    let res: Result<Vec<NodeId>, AnyErr> = tokio::select! {
+        res = async {
+            if holders.len() == parts as usize {
+                distribute_push(&node, &mut reader, &holders, &token, size, &chunk_set, sha256).await?;
+                Ok(Vec::new())
+            } else {
+                tracing::debug!(node = %node.port, file = %name, "Topology incomplete, relaying push down the ring");
+                // Relayed chunks are stored as they go by: a mismatch can only
+                // discard them afterwards
+                let relayed = relay_push(&node, &mut reader, &next, &token, size, parts, &chunk_set).await?;
+                check_push_digest(sha256, reader.digest())?;
+                Ok(relayed)
+            }
+        } => res,
+        _ = transfer.cancelled() => Err("transfer cancelled".into()),
+    };
+    node.end_transfer(&token).await;
+
+    let relayed = match res {
+        Ok(relayed) => relayed,
+        Err(e) => {
+            // Drop whatever part of the file already reached the ring
+            tracing::warn!(node = %node.port, token = %token, file = %name, error = %e, "Push aborted, discarding stored chunks");
+            let _ = fs::remove_file(staged_path(
+                &node,
+                &token,
+                &chunk_file_name(&chunk_set, local_index, parts),
+            ))
+            .await;
+            discard_file(&node, &name, parts).await;
+            node.broadcast_file_discard(&name, parts).await;
+            if chunk_set != name {
+                // The holders have no tag naming it
+                node.broadcast_file_discard(&chunk_set, parts).await;
+            }
+            // A holder too busy to take its chunk: the client may simply retry
+            let reply = match busy_refusal(&e.to_string()) {
+                Some(busy) => format!("ERR {}\n", busy),
+                None => format!("ERR push {} aborted: {}\n", token, e),
+            };
+            writer.write_all(reply.as_bytes()).await?;
+            return Ok(false); // The rest of the body was not consumed
+        }
+    };
+
+    // The relay hops named the holders of the other chunks
+    let mut tag = tag;
+    if tag.holders.is_empty() && relayed.len() + 1 == parts as usize {
+        tag.holders = std::iter::once(node.id).chain(relayed).collect();
+        node.insert_file_tag(&name, tag.clone()).await;
+    }
+
+    // Holders only learn the tag without the holder list or version, and
+    // none of a fresh chunk set: everyone needs the full one
+    if !tag.holders.is_empty()
+        || tag.chunks.is_some()
+        || node.ring_supports(Feature::TagVersions).await
+    {
+        node.broadcast_file_tag(&name, &tag).await;
+    }
+
+    node.emit(NodeEvent::FilePushed { name, size, parts });
+    writer
+        .write_all(
+            format!(
+                "FILE {} bytes split into {} chunks and distributed\nOK\n",
+                size, parts
+            )
+            .as_bytes(),
+        )
+        .await?;
+    Ok(true)
+}
+
+/// Uploads every chunk of a push directly to its holder, then commits them.
+///
+/// All connections are opened up front and each chunk is forwarded as soon as
+/// its bytes arrive, so holders store in parallel instead of one hop after the
+/// other. Holders stage what they receive and only move it into `content/` on
+/// `COMMIT`, which is sent once every one of them has staged its chunk.
+///
+/// This node is usually the first holder; a placed file may leave it out.
+/// With `sha256`, nothing is committed unless the whole body has it.
+async fn distribute_push<R: AsyncRead + Unpin>(
+    node: &Arc<Node>,
+    reader: &mut HashingReader<R>,
+    holders: &[String],
+    token: &str,
+    size: u64,
+    name: &str,
+    sha256: Option<Digest>,
+) -> Result<(), AnyErr> {
+    let parts = holders.len() as u32;
+    let own = port_str(&node.port);
+
+    // 1. Open a connection to each remote holder and announce its chunk
+    let mut conns = Vec::with_capacity(holders.len());
+    for (index, port) in holders.iter().enumerate() {
+        if port == own {
+            continue;
+        }
+        let mut s = node.connect(&node.peer_addr(port)).await?;
+        let header = format!(
+            "FILE PUT-CHUNK {} {} {} {} {} {}\n",
+            token,
+            &node.port,
+            size,
+            parts,
+            index,
+            protocol::encode_name(name)
+        );
+        s.write_all(header.as_bytes()).await?;
+        conns.push((port, BufReader::new(s)));
+    }
+
+    // 2. Stage our own chunk here and forward the others as they arrive
+    let mut local = None;
+    let mut remote = conns.iter_mut();
+    for index in 0..parts {
+        let len = fair_chunk_len(index, size, parts);
+        if holders[index as usize] == own {
+            let chunk_name = chunk_file_name(name, index, parts);
+            let entry = stage_chunk(node, token, &chunk_name, (&mut *reader).take(len)).await?;
+            local = Some((index, chunk_name, len, entry));
+        } else if let Some((_, conn)) = remote.next() {
+            copy(&mut (&mut *reader).take(len), conn.get_mut()).await?;
+        }
+    }
+
+    // 3. Wait until every holder has staged its chunk, and check the body
+    for (port, conn) in conns.iter_mut() {
+        expect_line(conn, "OK")
+            .await
+            .map_err(|e| format!("holder {} failed to stage: {}", port, e))?;
+    }
+    check_push_digest(sha256, reader.digest())?;
+
+    // 4. Commit everywhere
+    for (_, conn) in conns.iter_mut() {
+        conn.get_mut().write_all(b"COMMIT\n").await?;
+    }
+    if let Some((index, chunk_name, len, entry)) = local {
+        commit_chunk(node, token, &chunk_name, entry).await?;
+        tracing::info!(node = %node.port, chunk = index + 1, parts, bytes = len, "Saved file chunk");
+    }
+    for (port, conn) in conns.iter_mut() {
+        expect_line(conn, "OK")
+            .await
+            .map_err(|e| format!("holder {} failed to commit: {}", port, e))?;
+    }
+    Ok(())
+}
+
+/// Error a push whose body does not have the client's `SHA256` fails with
+const CHECKSUM_MISMATCH: &str = "CHECKSUM_MISMATCH";
+
+/// Error a chunk stream is refused with when no `max-relays` slot frees up
+const BUSY: &str = "BUSY";
+
+/// Takes a `max-relays` slot for a chunk stream from another node, or
+/// refuses it with `ERR BUSY retry-after=<ms>`. The `size` bytes of a
+/// refused stream are still read, so its sender gets to read the answer.
+async fn admit_relay<'a, R, W>(
+    node: &'a Node,
+    reader: &mut R,
+    writer: &mut W,
+    size: u64,
+) -> Result<Option<RelaySlot<'a>>, AnyErr>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    if let Some(slot) = node.relay_slot().await {
+        return Ok(Some(slot));
+    }
+    tracing::warn!(node = %node.port, bytes = size, "Too many chunk streams, refusing one");
+    let refusal = format!(
+        "ERR {} retry-after={}\n",
+        BUSY,
+        node::RELAY_RETRY_AFTER.as_millis()
     );
+    writer.write_all(refusal.as_bytes()).await?;
+    discard_body(reader, size).await?;
+    Ok(None)
+}
 
-    // If not the last chunk, forward remaining bytes to next with index+1
-    let consumed = sum_len_up_to_inclusive(index, file_size, parts);
-    let remaining = file_size - consumed;
-    if remaining > 0 {
-        if let Some(next) = node.get_next().await {
-            let mut s = TcpStream::connect(&next).await?;
+/// `BUSY retry-after=<ms>`, when a push failed because a holder refused its chunk
+fn busy_refusal(error: &str) -> Option<String> {
+    let at = error.find(&format!("{} retry-after=", BUSY))?;
+    let after = error[at + BUSY.len()..].split_whitespace().next()?;
+    Some(format!("{} {}", BUSY, after))
+}
+
+/// Checks the digest of a push's body against the one the client sent, if any
+fn check_push_digest(expected: Option<Digest>, body: Digest) -> Result<(), AnyErr> {
+    match expected {
+        Some(expected) if expected != body => Err(format!(
+            "{} expected sha256 {}, body has {}",
+            CHECKSUM_MISMATCH, expected, body
+        )
+        .into()),
+        _ => Ok(()),
+    }
+}
+
+/// Stores new bytes for the `changed` chunks of a file whose size did not
+/// change, on the holders that have them. Like a push, every holder stages
+/// its chunk first and none is committed unless all of them staged theirs.
+#[allow(clippy::too_many_arguments)]
+async fn rewrite_chunks(
+    node: &Arc<Node>,
+    name: &str,
+    tag: &node::FileTag,
+    holders: &[String],
+    token: &str,
+    data: &[u8],
+    changed: &[u32],
+) -> Result<(), AnyErr> {
+    let own = port_str(&node.port);
+    let slice = |index: u32| {
+        let slice = RelaySlice::new(index, tag.size, tag.parts);
+        &data[slice.offset as usize..(slice.offset + slice.len) as usize]
+    };
+
+    // 1. Stage every changed chunk, here or on its holder
+    let mut local = Vec::new();
+    let mut conns = Vec::new();
+    let staged = async {
+        for &index in changed {
+            let port = &holders[index as usize];
+            if port == own {
+                let chunk_name = chunk_file_name(name, index, tag.parts);
+                let bytes = slice(index);
+                let entry =
+                    stage_chunk(node, token, &chunk_name, bytes.take(bytes.len() as u64)).await;
+                // Dropped below if anything fails, even a failed stage
+                local.push((chunk_name, entry));
+                continue;
+            }
+            let mut s = node.connect(&node.peer_addr(port)).await?;
+            let header = format!(
+                "FILE PUT-CHUNK {} {} {} {} {} {}\n",
+                token,
+                node.peer_addr(&holders[0]),
+                tag.size,
+                tag.parts,
+                index,
+                protocol::encode_name(name)
+            );
+            s.write_all(header.as_bytes()).await?;
+            s.write_all(slice(index)).await?;
+            conns.push((port, BufReader::new(s)));
+        }
+        for (port, conn) in conns.iter_mut() {
+            expect_line(conn, "OK")
+                .await
+                .map_err(|e| format!("holder {} failed to stage: {}", port, e))?;
+        }
+        match local.iter().find_map(|(_, entry)| entry.as_ref().err()) {
+            Some(e) => Err::<(), AnyErr>(e.to_string().into()),
+            None => Ok(()),
+        }
+    }
+    .await;
+    if let Err(e) = staged {
+        for (chunk_name, _) in &local {
+            let _ = fs::remove_file(staged_path(node, token, chunk_name)).await;
+        }
+        return Err(e);
+    }
+
+    // 2. Commit everywhere
+    for (_, conn) in conns.iter_mut() {
+        conn.get_mut().write_all(b"COMMIT\n").await?;
+    }
+    for (chunk_name, entry) in local {
+        commit_chunk(node, token, &chunk_name, entry?).await?;
+    }
+    for (port, conn) in conns.iter_mut() {
+        expect_line(conn, "OK")
+            .await
+            .map_err(|e| format!("holder {} failed to commit: {}", port, e))?;
+    }
+    tracing::info!(node = %node.port, file = %name, chunks = changed.len(), parts = tag.parts, "Rewrote changed chunks");
+    Ok(())
+}
+
+/// Reads one line and checks it is `expected`
+async fn expect_line(conn: &mut BufReader<TcpStream>, expected: &str) -> Result<(), AnyErr> {
+    let mut line = String::new();
+    conn.read_line(&mut line).await?;
+    match line.trim_end() {
+        l if l == expected => Ok(()),
+        "" => Err("connection closed".into()),
+        other => Err(other.to_string().into()),
+    }
+}
+
+/// Handles "FILE PUT-CHUNK": stages one chunk of a parallel push, then stores
+/// it for good on `COMMIT`, or drops it on anything else.
+#[allow(clippy::too_many_arguments)]
+async fn handle_file_put_chunk<R, W>(
+    node: Arc<Node>,
+    reader: &mut R,
+    writer: &mut W,
+    token: String,
+    start_addr: String,
+    file_size: u64,
+    parts: u32,
+    index: u32,
+    name: String,
+) -> Result<(), AnyErr>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    if index >= parts {
+        writer.write_all(b"ERR bad FILE PUT-CHUNK index\n").await?;
+        return Ok(());
+    }
+
+    // 1. Stage the chunk
+    let chunk_name = chunk_file_name(&name, index, parts);
+    let len = fair_chunk_len(index, file_size, parts);
+    if let Err(e) = check_chunk_size(&node, len).await {
+        // The body is not read, so the connection cannot be reused
+        writer.write_all(format!("ERR {}\n", e).as_bytes()).await?;
+        return Err(e);
+    }
+    let Some(slot) = admit_relay(&node, reader, writer, len).await? else {
+        return Ok(());
+    };
+    let staged = stage_chunk(&node, &token, &chunk_name, (&mut *reader).take(len)).await;
+    // Only the disk writes are limited, not the wait for the commit
+    drop(slot);
+    let entry = match staged {
+        Ok(entry) => entry,
+        Err(e) => {
+            // The body was not fully read, so the connection cannot be reused
+            tracing::warn!(node = %node.port, token = %token, chunk = %chunk_name, error = %e, "Failed to stage chunk");
+            let _ = fs::remove_file(staged_path(&node, &token, &chunk_name)).await;
+            return Err(e);
+        }
+    };
+    writer.write_all(b"OK\n").await?;
+
+    // 2. Wait for the start node's decision
+    let mut line = String::new();
+    reader.read_line(&mut line).await?;
+    if line.trim_end() != "COMMIT" {
+        tracing::info!(node = %node.port, token = %token, chunk = %chunk_name, "Push not committed, dropping staged chunk");
+        let _ = fs::remove_file(staged_path(&node, &token, &chunk_name)).await;
+        return Ok(());
+    }
+
+    // 3. Store it
+    let start_port_num: u16 = port_str(&start_addr).parse().unwrap_or(0);
+    node.set_file_tag(&name, start_port_num, file_size, parts)
+        .await;
+    commit_chunk(&node, &token, &chunk_name, entry).await?;
+    tracing::info!(node = %node.port, chunk = index + 1, parts, bytes = len, "Saved file chunk");
+    writer.write_all(b"OK\n").await?;
+    Ok(())
+}
+
+fn staged_path(node: &Node, token: &str, chunk_name: &str) -> PathBuf {
+    node.staging_dir()
+        .join(format!("{}-{}", token, sanitize_filename(chunk_name)))
+}
+
+/// Writes exactly `reader`'s bytes into staging, hashing them on the way
+async fn stage_chunk<R: AsyncRead + Unpin>(
+    node: &Node,
+    token: &str,
+    chunk_name: &str,
+    mut reader: tokio::io::Take<R>,
+) -> Result<ChunkEntry, AnyErr> {
+    let expected = reader.limit();
+    let mut writer =
+        HashingWriter::new(fs::File::create(staged_path(node, token, chunk_name)).await?);
+    node.disk
+        .copy(IoClass::Foreground, &mut reader, &mut writer)
+        .await?;
+    writer.flush().await?;
+    let (file, sha256, size) = writer.finish();
+    if size != expected {
+        return Err(format!(
+            "chunk truncated ({} of {} bytes)",
+            size,
+            size + reader.limit()
+        )
+        .into());
+    }
+    // On disk before any commit record can point at it
+    file.sync_data().await?;
+    Ok(ChunkEntry { sha256, size })
+}
+
+/// Stages and commits a chunk in one go, for pushes that store chunks as the
+/// bytes go by (the relay chain) rather than on `COMMIT`
+async fn store_chunk<R: AsyncRead + Unpin>(
+    node: &Arc<Node>,
+    token: &str,
+    chunk_name: &str,
+    reader: tokio::io::Take<R>,
+) -> Result<(), AnyErr> {
+    match stage_chunk(node, token, chunk_name, reader).await {
+        Ok(entry) => commit_chunk(node, token, chunk_name, entry).await,
+        Err(e) => {
+            let _ = fs::remove_file(staged_path(node, token, chunk_name)).await;
+            Err(e)
+        }
+    }
+}
+
+/// Moves a staged chunk into `content/`, records it in the manifest and asks
+/// the backup holders to back it up. The commit record written first lets a
+/// restart finish the commit (see [`crate::staging`]).
+async fn commit_chunk(
+    node: &Arc<Node>,
+    token: &str,
+    chunk_name: &str,
+    entry: ChunkEntry,
+) -> Result<(), AnyErr> {
+    let fname = sanitize_filename(chunk_name);
+    let staged = staged_path(node, token, chunk_name);
+    let record = CommitRecord {
+        chunk: fname.clone(),
+        entry,
+    };
+    staging::write_record(&staged, &record).await?;
+    node.chunk_cache.invalidate("content", &fname);
+    let renamed = {
+        let _files = node.packs.lock_files().await;
+        node.packs.of("content").remove(&fname);
+        fs::rename(&staged, node.content_dir().join(&fname)).await
+    };
+    if let Err(e) = renamed {
+        staging::remove_record(&staged).await;
+        return Err(e.into());
+    }
+    manifest::record(&node.manifest_dir("content"), &fname, entry).await?;
+    staging::remove_record(&staged).await;
+    node.emit(NodeEvent::ChunkStored {
+        name: chunk_name.to_string(),
+        backup: false,
+    });
+
+    let node_clone = Arc::clone(node);
+    let chunk_name = chunk_name.to_string();
+    tokio::spawn(async move {
+        notify_backup_holders(node_clone, chunk_name).await;
+    });
+    Ok(())
+}
+
+/// Stores the first chunk of a push locally and streams the rest of the file
+/// down the ring, waiting until every hop has stored its chunk. Returns the
+/// ids of the nodes holding the other chunks, in order, as the hops named
+/// them (none from hops of older versions).
+async fn relay_push<R: AsyncRead + Unpin>(
+    node: &Arc<Node>,
+    reader: &mut R,
+    next: &str,
+    token: &str,
+    size: u64,
+    parts: u32,
+    name: &str,
+) -> Result<Vec<NodeId>, AnyErr> {
+    let first_len = fair_chunk_len(0, size, parts);
+    // Stream this node's first chunk to disk
+    let chunk_name = chunk_file_name(name, 0, parts);
+    store_chunk(node, token, &chunk_name, (&mut *reader).take(first_len)).await?;
+
+    tracing::info!(
+        node = %node.port,
+        chunk = 1,
+        parts,
+        file = %chunk_name,
+        bytes = first_len,
+        "Saved file chunk"
+    );
+
+    // Open connection to next and stream the remaining bytes
+    let mut s = node.connect(next).await?;
+    let header = format!(
+        "FILE RELAY-STREAM {} {} {} {} {} {}\n",
+        token,
+        &node.port,
+        size,
+        parts,
+        1,
+        protocol::encode_name(name)
+    );
+    s.write_all(header.as_bytes()).await?;
+
+    // Forward exactly the remaining bytes (size - first_len) from client -> next
+    let mut limited = reader.take(size - first_len);
+    copy(&mut limited, &mut s).await?;
+
+    expect_relay_ok(&mut s).await
+}
+
+/// Waits for the next hop's answer to a RELAY-STREAM, which it only sends
+/// once the rest of the chain has stored its chunks. Returns the holders it
+/// names (`OK <ids>`), one per chunk from the hop's own.
+async fn expect_relay_ok(s: &mut TcpStream) -> Result<Vec<NodeId>, AnyErr> {
+    let mut line = String::new();
+    BufReader::new(s).read_line(&mut line).await?;
+    match line.trim_end() {
+        "OK" => Ok(Vec::new()),
+        "" => Err("relay chain broken".into()),
+        other => match other.strip_prefix("OK ") {
+            Some(holders) => Ok(parse_holders(holders)),
+            None => Err(format!("relay failed: {}", other).into()),
+        },
+    }
+}
+
+async fn handle_file_relay_blob<R, W>(
+    node: Arc<Node>,
+    reader: &mut R,
+    writer: &mut W,
+    token: String,
+    start_addr: String,
+    size: u64,
+    name: String,
+) -> Result<(), AnyErr>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    // Read the exact file body first, if it is not larger than any file we accept
+    let max_file_size = node.settings().await.file_size;
+    if size > max_file_size {
+        let err = too_large("file", size, max_file_size);
+        writer
+            .write_all(format!("ERR {}\n", err).as_bytes())
+            .await?;
+        discard_body(reader, size).await?;
+        return Ok(());
+    }
+    // The slot is held until the blob is passed on, since it stays in memory
+    let Some(_slot) = admit_relay(&node, reader, writer, size).await? else {
+        return Ok(());
+    };
+    let mut buf = vec![0u8; usize::try_from(size)?];
+    reader.read_exact(&mut buf).await?;
+
+    // If this hop delivered back to the start node, just finish & ACK.
+    if same_node(&node.port, &start_addr) {
+        let _ = node.finish_file(&token).await;
+        let _ = writer.write_all(b"OK\n").await;
+        return Ok(());
+    }
+
+    // Save locally
+    if let Err(e) = save_into_node_dir(&node, &name, &buf, "content", IoClass::Foreground).await {
+        tracing::error!(node = %node.port, file_name = %name, error = ?e, "Failed to save relayed file blob");
+    } else {
+        // Notify the backup holders
+        let node_clone = Arc::clone(&node);
+        let name_clone = name.clone();
+        tokio::spawn(async move {
+            notify_backup_holders(node_clone, name_clone).await;
+        });
+    }
+
+    // Forward to next
+    if node.get_next().await.is_some()
+        && let Err(e) = node
+            .forward_file_relay_blob(&token, &start_addr, size, &name, &buf)
+            .await
+    {
+        tracing::warn!(node = %node.port, error = ?e, "FILE RELAY-BLOB forward failed");
+    }
+
+    let _ = writer.write_all(b"OK\n").await;
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn handle_file_relay_stream<R, W>(
+    node: Arc<Node>,
+    reader: &mut R,
+    writer: &mut W,
+    token: String,
+    start_addr: String,
+    file_size: u64,
+    parts: u32,
+    index: u32,
+    name: String,
+) -> Result<(), AnyErr>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    if index >= parts {
+        writer
+            .write_all(b"ERR bad FILE RELAY-STREAM index\n")
+            .await?;
+        return Ok(());
+    }
+
+    // Track this hop of the push under the same token
+    let slice = RelaySlice::new(index, file_size, parts);
+    let Some(slot) = admit_relay(&node, reader, writer, file_size - slice.offset).await? else {
+        return Ok(());
+    };
+    let start_port_num: u16 = port_str(&start_addr).parse().unwrap_or(0);
+    let my_port_num: u16 = port_str(&node.port).parse().unwrap_or(0);
+    let route = chunk_route(&node, my_port_num, index, file_size, parts).await;
+    let transfer = node
+        .begin_transfer(
+            &token,
+            TransferKind::Push,
+            &name,
+            file_size - slice.offset,
+            route,
+        )
+        .await;
+
+    let mut reader = ProgressReader::new(reader, Arc::clone(&transfer));
+    let res = tokio::select! {
+        res = relay_stream_hop(&node, slot, &mut reader, &token, &start_addr, start_port_num, file_size, parts, index, &name) => res,
+        _ = transfer.cancelled() => Err("transfer cancelled".into()),
+    };
+    node.end_transfer(&token).await;
+
+    // On failure the connection is dropped, so the previous hop sees the abort
+    let holders = res?;
+    let reply = if node.ring_supports(Feature::HolderIds).await {
+        format!("OK {}\n", join_holders(&holders))
+    } else {
+        "OK\n".to_string()
+    };
+    writer.write_all(reply.as_bytes()).await?;
+    Ok(())
+}
+
+/// One hop of a RELAY-STREAM: store this node's chunk and pass the rest on.
+/// `slot` is given back once the chunk is stored: passing the rest on does
+/// not touch the disk, and the next hop may be waiting for a slot itself.
+/// Returns the holders of this chunk and the following ones.
+#[allow(clippy::too_many_arguments)]
+async fn relay_stream_hop<R: AsyncRead + Unpin>(
+    node: &Arc<Node>,
+    slot: RelaySlot<'_>,
+    reader: &mut R,
+    token: &str,
+    start_addr: &str,
+    start_port_num: u16,
+    file_size: u64,
+    parts: u32,
+    index: u32,
+    name: &str,
+) -> Result<Vec<NodeId>, AnyErr> {
+    // Compute my chunk length and stream exactly those bytes to disk
+    let slice = RelaySlice::new(index, file_size, parts);
+    check_chunk_size(node, slice.len).await?;
+    let chunk_name = chunk_file_name(name, index, parts);
+    store_chunk(node, token, &chunk_name, (&mut *reader).take(slice.len)).await?;
+    drop(slot);
+
+    // Tag the file on this node too
+    node.set_file_tag(name, start_port_num, file_size, parts)
+        .await;
+
+    tracing::info!(
+        node = %node.port,
+        chunk = index + 1,
+        parts,
+        file = %chunk_name,
+        bytes = slice.len,
+        "Saved file chunk"
+    );
+
+    // If not the last chunk, forward remaining bytes to next with index+1
+    let mut holders = vec![node.id];
+    if !slice.is_last() {
+        if let Some(next) = node.get_next().await {
+            let mut s = node.connect(&next).await?;
             let header = format!(
                 "FILE RELAY-STREAM {} {} {} {} {} {}\n",
                 token,
@@ -930,93 +3218,1261 @@ where
                 file_size,
                 parts,
                 index + 1,
-                name
+                protocol::encode_name(name)
             );
             s.write_all(header.as_bytes()).await?;
-            let mut limited = reader.take(remaining);
+            let mut limited = reader.take(slice.remaining);
             copy(&mut limited, &mut s).await?;
+            holders.extend(expect_relay_ok(&mut s).await?);
         }
     } else {
         // nothing left to do
-        let _ = node.finish_file(&token).await;
+        let _ = node.finish_file(token).await;
+    }
+    Ok(holders)
+}
+
+async fn handle_file_tags_set<W: AsyncWrite + Unpin>(
+    node: &Node,
+    writer: &mut W,
+    entries: FileTags,
+) -> Result<(), AnyErr> {
+    node.set_file_tags_from_entries(&entries).await;
+    migrate::rename_legacy_chunks(node, &entries).await;
+    writer.write_all(b"OK\n").await?;
+    Ok(())
+}
+
+/* -------- FILE RETRIEVAL (PULL / GET-CHUNK) -------- */
+
+async fn handle_file_pull<W: AsyncWrite + Unpin>(
+    node: &Node,
+    writer: &mut W,
+    name: String,
+    offset: u64,
+) -> Result<(), AnyErr> {
+    if let Some(hop) = federation_hop(node, &name).await {
+        return federated_pull(node, writer, hop, offset).await;
+    }
+    let name = own_ring_name(node, name);
+    let bytes = match pull_file(node, &name, offset).await {
+        Ok(Some(bytes)) => bytes,
+        Ok(None) => {
+            writer.write_all(b"ERR file not found\n").await?;
+            return Ok(());
+        }
+        Err(e) => {
+            // Nothing was sent yet: report the hole instead of truncated bytes
+            writer.write_all(format!("ERR {}\n", e).as_bytes()).await?;
+            return Ok(());
+        }
+    };
+
+    // IMPORTANT: return *pure bytes*, no textual header or trailer.
+    writer.write_all(&bytes).await?;
+    Ok(())
+}
+
+/// Assembles a file from the ring, from byte `offset` on, or `None` if it is
+/// not tagged. Chunks wholly before `offset` are not fetched.
+async fn pull_file(node: &Node, name: &str, offset: u64) -> Result<Option<Vec<u8>>, AnyErr> {
+    let tags = node.file_tags.read().await;
+    let Some(tag) = tags.get(name) else {
+        return Ok(None);
+    };
+    let tag = tag.clone();
+    drop(tags);
+    if let Some(ring) = &tag.ring {
+        return Err(format!("file is on ring '{}', which is no longer federated", ring).into());
+    }
+    if offset > tag.size {
+        return Err(format!(
+            "offset {} is past the end of the file ({} bytes)",
+            offset, tag.size
+        )
+        .into());
+    }
+
+    // Track the pull so it can be followed with FILE PROGRESS and stopped with FILE CANCEL
+    let token = node.make_file_token();
+    let holders = tag_holders(node, name, &tag).await;
+    let known: Vec<String> = holders.iter().map_while(Clone::clone).collect();
+    let route = route_over(&known, 0, tag.size, tag.parts);
+    let transfer = node
+        .begin_transfer(&token, TransferKind::Pull, name, tag.size, route)
+        .await;
+
+    // Assemble the full file from the holder of each chunk
+    let chunk_set = tag.chunk_set(name);
+    let res = pull_file_from_ring(node, chunk_set, tag.parts, &holders, &transfer, offset).await;
+    node.end_transfer(&token).await;
+    Ok(Some(res?))
+}
+
+/// Handles "FILE SYNC <size> <block_size> <name>": reads the new version's
+/// block signatures, answers `NEED` with the blocks the stored version lacks
+/// (see [`crate::delta`]), reads those, and rebuilds the file. When its size
+/// is unchanged only the chunks whose bytes differ are rewritten, on their
+/// holders; otherwise (or for a new file) it is stored like a `FILE PUSH`.
+async fn handle_file_sync<R, W>(
+    node: Arc<Node>,
+    reader: &mut R,
+    writer: &mut W,
+    size: u64,
+    block_size: u64,
+    name: String,
+) -> Result<bool, AnyErr>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    // 1. The signatures come first, one line per block
+    let blocks = delta::block_count(size, block_size);
+    if blocks > delta::MAX_BLOCKS {
+        writer
+            .write_all(format!("ERR too many blocks ({}), use bigger ones\n", blocks).as_bytes())
+            .await?;
+        return Ok(false); // The signatures were not read
+    }
+    let mut sigs = Vec::with_capacity(blocks as usize);
+    let mut line = String::new();
+    for _ in 0..blocks {
+        line.clear();
+        reader.read_line(&mut line).await?;
+        match line.parse::<BlockSignature>() {
+            Ok(sig) => sigs.push(sig),
+            Err(e) => {
+                writer.write_all(format!("ERR {}\n", e).as_bytes()).await?;
+                return Ok(false);
+            }
+        }
+    }
+
+    // 2. Refusals, answered before any block is sent
+    let settings = node.settings().await;
+    let refusal = if federation_hop(&node, &name).await.is_some() {
+        Some("FILE SYNC does not reach federated rings".to_string())
+    } else if size > settings.file_size {
+        Some(too_large("file", size, settings.file_size))
+    } else {
+        None
+    };
+    let name = stored_name(&own_ring_name(&node, name));
+    let (name, refusal) = match (name, refusal) {
+        (Some(name), None) => (name, None),
+        (_, Some(refusal)) => (String::new(), Some(refusal)),
+        (None, None) => (String::new(), Some("invalid file name".to_string())),
+    };
+    if let Some(refusal) = refusal {
+        writer
+            .write_all(format!("ERR {}\n", refusal).as_bytes())
+            .await?;
+        return Ok(true);
+    }
+
+    // 3. Compare with the stored version
+    let tag = node.file_tags.read().await.get(&name).cloned();
+    let old = match pull_file(&node, &name, 0).await {
+        Ok(old) => old.unwrap_or_default(),
+        Err(e) => {
+            tracing::warn!(node = %node.port, file = %name, error = %e, "Cannot read the stored version, syncing the whole file");
+            Vec::new()
+        }
+    };
+    let found = delta::find_blocks(&old, &sigs, block_size as usize, size);
+    writer
+        .write_all(format!("{}\n", delta::need_line(&found)).as_bytes())
+        .await?;
+
+    // 4. Rebuild the new version from the stored bytes and the blocks sent
+    let mut data = Vec::with_capacity(usize::try_from(size)?);
+    let (mut received, mut sent, mut bad) = (0u64, 0usize, None);
+    for (i, at) in found.iter().enumerate() {
+        let len = block_size.min(size - i as u64 * block_size) as usize;
+        match at {
+            Some(at) => data.extend_from_slice(&old[*at..*at + len]),
+            None => {
+                let start = data.len();
+                data.resize(start + len, 0);
+                reader.read_exact(&mut data[start..]).await?;
+                if Sha256::digest(&data[start..]) != sigs[i].strong {
+                    bad.get_or_insert(i);
+                }
+                received += len as u64;
+                sent += 1;
+            }
+        }
+    }
+    if let Some(i) = bad {
+        writer
+            .write_all(format!("ERR block {} does not match its signature\n", i).as_bytes())
+            .await?;
+        return Ok(true);
+    }
+
+    // 5. Store it: rewrite the chunks that changed, or push it anew
+    let in_place = match &tag {
+        Some(tag) if tag.size == size && tag.parts > 0 && tag.chunks.is_none() => {
+            let holders = tag_holders(&node, &name, tag).await;
+            let holders: Option<Vec<String>> = holders.into_iter().collect();
+            let holders = holders.filter(|holders| holders.len() == tag.parts as usize);
+            let shared = chunk_set_user(&*node.file_tags.read().await, &name, &name).is_some();
+            holders
+                .filter(|_| !shared)
+                .map(|holders| (tag.clone(), holders))
+        }
+        _ => None,
+    };
+    let (rewritten, parts) = match in_place {
+        Some((tag, holders)) => {
+            let changed: Vec<u32> = (0..tag.parts)
+                .filter(|&i| {
+                    let slice = RelaySlice::new(i, size, tag.parts);
+                    let range = slice.offset as usize..(slice.offset + slice.len) as usize;
+                    old.get(range.clone()) != data.get(range)
+                })
+                .collect();
+            if !changed.is_empty() {
+                let token = node.make_file_token();
+                let res =
+                    rewrite_chunks(&node, &name, &tag, &holders, &token, &data, &changed).await;
+                if let Err(e) = res {
+                    writer
+                        .write_all(format!("ERR sync of {} failed: {}\n", name, e).as_bytes())
+                        .await?;
+                    return Ok(true);
+                }
+                // New content, new version. Holders only learn the tag without the holder list.
+                let tag = node.stamp_file_tag(&name, tag.clone(), None).await;
+                node.insert_file_tag(&name, tag.clone()).await;
+                if !tag.holders.is_empty() || node.ring_supports(Feature::TagVersions).await {
+                    node.broadcast_file_tag(&name, &tag).await;
+                }
+            }
+            (changed.len() as u32, tag.parts)
+        }
+        None => {
+            let mut out = Vec::new();
+            let pushed = handle_file_push(
+                Arc::clone(&node),
+                &mut &data[..],
+                &mut out,
+                size,
+                name.clone(),
+                PushMode::Overwrite,
+                Labels::default(),
+                None,
+                // A new version keeps the media type of the old one
+                tag.and_then(|tag| tag.content_type),
+            )
+            .await;
+            let out = String::from_utf8_lossy(&out);
+            if let Some(err) = out.lines().find(|l| l.starts_with("ERR")) {
+                writer.write_all(format!("{}\n", err).as_bytes()).await?;
+                return Ok(true);
+            }
+            pushed?;
+            let parts = node
+                .file_tags
+                .read()
+                .await
+                .get(&name)
+                .map_or(0, |t| t.parts);
+            (parts, parts)
+        }
+    };
+
+    node.emit(NodeEvent::FilePushed {
+        name: name.clone(),
+        size,
+        parts,
+    });
+    writer
+        .write_all(
+            format!(
+                "SYNCED {} blocks={}/{} bytes={} chunks={}/{}\nOK\n",
+                protocol::encode_name(&name),
+                sent,
+                found.len(),
+                received,
+                rewritten,
+                parts
+            )
+            .as_bytes(),
+        )
+        .await?;
+    Ok(true)
+}
+
+/* -------- In-process commands (used by `NodeHandle`) -------- */
+
+/// Pushes `data` into the ring from this node, exactly as `FILE PUSH` would.
+pub(crate) async fn push_local(node: Arc<Node>, name: &str, data: &[u8]) -> Result<String, AnyErr> {
+    let mut reader = data;
+    let mut out = Vec::new();
+    handle_file_push(
+        node,
+        &mut reader,
+        &mut out,
+        data.len() as u64,
+        name.to_string(),
+        PushMode::default(),
+        Labels::default(),
+        None,
+        None,
+    )
+    .await?;
+
+    let response = String::from_utf8_lossy(&out);
+    if let Some(err) = response.lines().find_map(|l| l.strip_prefix("ERR ")) {
+        return Err(err.to_string().into());
+    }
+    let response: Vec<&str> = response
+        .lines()
+        .filter(|l| !l.starts_with("TRANSFER "))
+        .collect();
+    Ok(response.join("\n").trim().to_string())
+}
+
+/// Pulls a whole file from the ring, exactly as `FILE PULL` would.
+pub(crate) async fn pull_local(node: &Node, name: &str) -> Result<Vec<u8>, AnyErr> {
+    pull_file(node, name, 0)
+        .await?
+        .ok_or_else(|| "file not found".into())
+}
+
+async fn handle_file_get_chunk<W: AsyncWrite + Unpin>(
+    node: &Node,
+    writer: &mut W,
+    name: String,
+) -> Result<(), AnyErr> {
+    // Serve the specific chunk from the "content" directory
+    serve_chunk(node, writer, "content", &name).await
+}
+
+/// Answers with `FILE RESP-CHUNK <next> <size> <name>` and the bytes of a
+/// stored chunk, from the chunk cache when it holds it.
+///
+/// A chunk small enough for the cache is read whole and cached on the way
+/// out; bigger ones are streamed from disk.
+async fn serve_chunk<W: AsyncWrite + Unpin>(
+    node: &Node,
+    writer: &mut W,
+    subdir: &str,
+    name: &str,
+) -> Result<(), AnyErr> {
+    let next = node.get_next().await.unwrap_or_else(|| node.port.clone());
+    let header = |size: u64| {
+        let header = RespChunk {
+            next_addr: next.clone(),
+            size,
+            name: name.to_string(),
+        };
+        format!("{}\n", header).into_bytes()
+    };
+    let fname = sanitize_filename(name);
+    let path = node.data_dir.join(subdir).join(&fname);
+    let capacity = node.settings().await.chunk_cache_size;
+    let _serving = node.begin_serving();
+
+    // 1. From memory
+    let cached = if capacity > 0 {
+        node.chunk_cache.get(subdir, &fname)
+    } else {
+        None
+    };
+    // 2. From disk, keeping a copy if it fits the cache
+    let cached = match cached {
+        Some(data) => Some(data),
+        None => {
+            let size = pack::size(node, subdir, &fname).await.unwrap_or(0);
+            if ChunkCache::admits(size, capacity) {
+                let read = pack::read(node, IoClass::Foreground, subdir, &fname).await;
+                read.ok().map(|data| {
+                    let data: Arc<[u8]> = data.into();
+                    node.chunk_cache
+                        .insert(subdir, &fname, Arc::clone(&data), capacity);
+                    data
+                })
+            } else {
+                None
+            }
+        }
+    };
+
+    let sent = match cached {
+        Some(data) => {
+            write_all_vectored(writer, &header(data.len() as u64), &data).await?;
+            data.len() as u64
+        }
+        // 3. Too big for the cache (or missing): map it if large enough, or stream it
+        None => match send_mapped_chunk(node, writer, &path, &header).await? {
+            Some(sent) => sent,
+            None => {
+                let class = IoClass::Foreground;
+                send_chunk_file(node, class, writer, subdir, &fname, header).await?
+            }
+        },
+    };
+    node.record_served(sent);
+    Ok(())
+}
+
+/// Size of the buffer used to stream chunk files to a socket
+const CHUNK_SEND_BUF: usize = 64 * 1024;
+
+/// Chunks at least this big are sent from a memory map (`mmap` feature)
+#[cfg(feature = "mmap")]
+const MMAP_MIN_SIZE: u64 = 4 * 1024 * 1024;
+
+/// Sends `header(size)` followed by a chunk of at least [`MMAP_MIN_SIZE`]
+/// bytes straight from a read-only mapping of its file, read ahead
+/// sequentially, instead of copying it block by block through a buffer.
+/// `None` when the file is smaller or cannot be mapped, to be streamed
+/// instead.
+///
+/// The pages fault in as they are sent, outside the disk scheduler's permits;
+/// their bytes still count as foreground I/O.
+#[cfg(feature = "mmap")]
+async fn send_mapped_chunk<W, F>(
+    node: &Node,
+    writer: &mut W,
+    path: &Path,
+    header: F,
+) -> Result<Option<u64>, AnyErr>
+where
+    W: AsyncWrite + Unpin,
+    F: FnOnce(u64) -> Vec<u8>,
+{
+    let Ok(file) = fs::File::open(path).await else {
+        return Ok(None);
+    };
+    let file = file.into_std().await;
+    let size = file.metadata().map_or(0, |m| m.len());
+    if size < MMAP_MIN_SIZE {
+        return Ok(None);
+    }
+    // SAFETY: chunk files are replaced by renaming a new file over them, never
+    // truncated or written in place (see `save_into_node_dir`), so the mapped
+    // bytes stay valid while they are sent
+    let Ok(map) = (unsafe { memmap2::Mmap::map(&file) }) else {
+        return Ok(None);
+    };
+    if let Err(e) = map.advise(memmap2::Advice::Sequential) {
+        tracing::debug!(path = %path.display(), error = ?e, "madvise(SEQUENTIAL) failed");
+    }
+    write_all_vectored(writer, &header(size), &map).await?;
+    node.disk.record(IoClass::Foreground, map.len());
+    Ok(Some(size))
+}
+
+/// Without the `mmap` feature every chunk too big for the cache is streamed
+#[cfg(not(feature = "mmap"))]
+async fn send_mapped_chunk<W, F>(
+    _node: &Node,
+    _writer: &mut W,
+    _path: &Path,
+    _header: F,
+) -> Result<Option<u64>, AnyErr>
+where
+    W: AsyncWrite + Unpin,
+    F: FnOnce(u64) -> Vec<u8>,
+{
+    Ok(None)
+}
+
+/// Sends `header(size)` followed by chunk `fname` of `subdir` (loose or
+/// packed, see [`pack::open`]), without reading it whole into memory.
+///
+/// The header goes out together with the first block of the chunk in one
+/// vectored write; the rest is copied through a single reused buffer.
+/// Each block is read under a disk permit of `class`. A missing or
+/// unreadable chunk is sent as 0 bytes.
+async fn send_chunk_file<W, F>(
+    node: &Node,
+    class: IoClass,
+    writer: &mut W,
+    subdir: &str,
+    fname: &str,
+    header: F,
+) -> Result<u64, AnyErr>
+where
+    W: AsyncWrite + Unpin,
+    F: FnOnce(u64) -> Vec<u8>,
+{
+    // 1. Open the chunk and learn its size
+    let (mut file, size) = match pack::open(node, subdir, fname).await {
+        Ok(chunk) => (chunk.file, chunk.len),
+        Err(e) => {
+            tracing::debug!(subdir, chunk = %fname, error = ?e, "Chunk not readable, sending 0 bytes");
+            writer.write_all(&header(0)).await?;
+            return Ok(0);
+        }
+    };
+    let head = header(size);
+
+    // 2. Header + first block in one write
+    let mut buf = vec![0u8; (CHUNK_SEND_BUF as u64).min(size) as usize];
+    let mut sent = 0u64;
+    let n = if buf.is_empty() {
+        0
+    } else {
+        node.disk.read(class, &mut file, &mut buf).await?
+    };
+    write_all_vectored(writer, &head, &buf[..n]).await?;
+    sent += n as u64;
+
+    // 3. Stream the rest, never more than announced in the header
+    while sent < size {
+        let want = (buf.len() as u64).min(size - sent) as usize;
+        let n = node.disk.read(class, &mut file, &mut buf[..want]).await?;
+        if n == 0 {
+            return Err(format!("{}/{} shrank while being sent", subdir, fname).into());
+        }
+        writer.write_all(&buf[..n]).await?;
+        sent += n as u64;
+    }
+    Ok(sent)
+}
+
+/// `write_all` for two buffers, using vectored writes
+async fn write_all_vectored<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    mut head: &[u8],
+    mut body: &[u8],
+) -> std::io::Result<()> {
+    while !head.is_empty() || !body.is_empty() {
+        let slices = [IoSlice::new(head), IoSlice::new(body)];
+        let n = writer.write_vectored(&slices).await?;
+        if n == 0 {
+            return Err(std::io::ErrorKind::WriteZero.into());
+        }
+        let from_head = n.min(head.len());
+        head = &head[from_head..];
+        body = &body[n - from_head..];
+    }
+    Ok(())
+}
+
+/* -------- TRANSFER HANDLERS -------- */
+
+/// Handles "FILE PROGRESS [<token>]"
+/// With a token, reports that transfer; without one, every transfer in flight
+/// on this node. One `TRANSFER <token> <kind> <name> bytes=.. total=.. hop=..`
+/// line each, then `OK`.
+async fn handle_file_progress<W: AsyncWrite + Unpin>(
+    node: &Node,
+    writer: &mut W,
+    token: Option<String>,
+) -> Result<(), AnyErr> {
+    let lines = match token {
+        Some(token) => match node.transfer(&token).await {
+            Some(transfer) => vec![transfer.progress()],
+            None => {
+                writer.write_all(b"ERR unknown transfer\n").await?;
+                return Ok(());
+            }
+        },
+        None => node.transfers().await,
+    };
+    for progress in lines {
+        writer
+            .write_all(format!("{}\n", progress).as_bytes())
+            .await?;
+    }
+    writer.write_all(b"OK\n").await?;
+    Ok(())
+}
+
+/// Handles "FILE CANCEL <token>"
+/// Stops a transfer running on this node. A cancelled push breaks the relay
+/// chain, and the node that accepted it discards the chunks already stored.
+async fn handle_file_cancel<W: AsyncWrite + Unpin>(
+    node: &Node,
+    writer: &mut W,
+    token: String,
+) -> Result<(), AnyErr> {
+    let Some(transfer) = node.transfer(&token).await else {
+        writer.write_all(b"ERR unknown transfer\n").await?;
+        return Ok(());
+    };
+    transfer.cancel();
+    tracing::info!(node = %node.port, token = %token, file = %transfer.name, "Transfer cancelled");
+    writer
+        .write_all(format!("OK cancelled {}\n", token).as_bytes())
+        .await?;
+    Ok(())
+}
+
+/// Handles "FILE DISCARD <parts> <name>"
+async fn handle_file_discard<W: AsyncWrite + Unpin>(
+    node: &Node,
+    writer: &mut W,
+    parts: u32,
+    name: String,
+) -> Result<(), AnyErr> {
+    discard_file(node, &name, parts).await;
+    writer.write_all(b"OK\n").await?;
+    Ok(())
+}
+
+/// Removes a file's tag, and its chunks and backups (with their manifest
+/// entries) from this node. Chunks another file still shares (see `FILE
+/// COPY`) are kept.
+async fn discard_file(node: &Node, name: &str, parts: u32) {
+    let chunk_set = {
+        let mut tags = node.file_tags.write().await;
+        let chunk_set = match tags.remove(name) {
+            Some(tag) => {
+                node.save_file_tags(&tags);
+                tag.chunk_set(name).to_string()
+            }
+            None => name.to_string(),
+        };
+        if let Some(user) = chunk_set_user(&tags, &chunk_set, name) {
+            tracing::info!(node = %node.port, file = %name, chunks = %chunk_set, shared_with = %user, "Discarded file, chunks still shared");
+            return;
+        }
+        chunk_set
+    };
+    for i in 0..parts {
+        let chunk_name = chunk_file_name(&chunk_set, i, parts);
+        for subdir in ["content", "backup"] {
+            remove_chunk(node, subdir, &chunk_name).await;
+        }
+    }
+    tracing::info!(node = %node.port, file = %name, parts, "Discarded file");
+}
+
+/// A file other than `except` whose chunks are stored under `chunk_set`
+fn chunk_set_user<'a>(
+    tags: &'a HashMap<String, node::FileTag>,
+    chunk_set: &str,
+    except: &str,
+) -> Option<&'a str> {
+    tags.iter()
+        .find(|(name, tag)| name.as_str() != except && tag.chunk_set(name) == chunk_set)
+        .map(|(name, _)| name.as_str())
+}
+
+/// Deletes one copy of a chunk (`content` or `backup`) and its manifest entry
+async fn remove_chunk(node: &Node, subdir: &str, chunk_name: &str) {
+    let fname = sanitize_filename(chunk_name);
+    node.chunk_cache.invalidate(subdir, &fname);
+    {
+        let _files = node.packs.lock_files().await;
+        let _ = fs::remove_file(node.data_dir.join(subdir).join(&fname)).await;
+        node.packs.of(subdir).remove(&fname);
+    }
+    let _ = fs::remove_file(node.manifest_dir(subdir).join(&fname)).await;
+}
+
+/* -------- COPY HANDLERS -------- */
+
+/// Handles "FILE COPY <src> <dst>": tags `dst` with the chunks of `src`, so
+/// no data moves. The chunks stay until neither file is left.
+async fn handle_file_copy<W: AsyncWrite + Unpin>(
+    node: &Node,
+    writer: &mut W,
+    src: String,
+    dst: String,
+) -> Result<(), AnyErr> {
+    let (src, dst) = (own_ring_name(node, src), own_ring_name(node, dst));
+    if federation_hop(node, &src).await.is_some() || federation_hop(node, &dst).await.is_some() {
+        writer
+            .write_all(b"ERR files of federated rings cannot be copied\n")
+            .await?;
+        return Ok(());
+    }
+    if node.network_size().await > 1 && !node.ring_supports(Feature::FileCopy).await {
+        writer
+            .write_all(b"ERR COPY_UNSUPPORTED some nodes do not understand copied files\n")
+            .await?;
+        return Ok(());
+    }
+
+    let (tag, exists) = {
+        let tags = node.file_tags.read().await;
+        (tags.get(&src).cloned(), tags.contains_key(&dst))
+    };
+    let tag = match tag {
+        None => Err("file not found".to_string()),
+        Some(_) if exists => Err("FILE_EXISTS".to_string()),
+        Some(tag) if tag.ring.is_some() => Err(format!(
+            "file is on ring '{}', which cannot be copied",
+            tag.ring.unwrap_or_default()
+        )),
+        Some(mut tag) => {
+            // Empty files have no chunks to share
+            if tag.parts > 0 {
+                tag.chunks = Some(tag.chunk_set(&src).to_string());
+            }
+            tag.conflict_of = None;
+            Ok(tag)
+        }
+    };
+    let tag = match tag {
+        Ok(tag) => node.stamp_file_tag(&dst, tag, None).await,
+        Err(e) => {
+            writer.write_all(format!("ERR {}\n", e).as_bytes()).await?;
+            return Ok(());
+        }
+    };
+    node.insert_file_tag(&dst, tag.clone()).await;
+    node.broadcast_file_tag(&dst, &tag).await;
+    tracing::info!(node = %node.port, src = %src, dst = %dst, chunks = ?tag.chunks, "Copied file");
+    writer
+        .write_all(
+            format!(
+                "FILE '{}' copied from '{}', {} chunks shared\nOK\n",
+                dst, src, tag.parts
+            )
+            .as_bytes(),
+        )
+        .await?;
+    Ok(())
+}
+
+/* -------- VERIFY HANDLERS -------- */
+
+/// Handles "FILE VERIFY <name>"
+/// Asks the holder of every chunk to re-hash it against its manifest (repairing
+/// it from the backup if needed) and reports one line per chunk, e.g.
+/// `part 2/3 a.bin.part-002-of-003 node=7001 status=ok`.
+async fn handle_file_verify<W: AsyncWrite + Unpin>(
+    node: &Node,
+    writer: &mut W,
+    name: String,
+) -> Result<(), AnyErr> {
+    let tag = node.file_tags.read().await.get(&name).cloned();
+    let Some(tag) = tag else {
+        writer.write_all(b"ERR file not found\n").await?;
+        return Ok(());
+    };
+
+    let holders = tag_holders(node, &name, &tag).await;
+    let mut failed = 0;
+    for i in 0..tag.parts {
+        let chunk_name = chunk_file_name(tag.chunk_set(&name), i, tag.parts);
+        let (holder, status) = match holders.get(i as usize).and_then(Option::as_ref) {
+            Some(port) => {
+                let status = check_chunk_on(node, &node.peer_addr(port), &chunk_name)
+                    .await
+                    .unwrap_or_else(|e| {
+                        tracing::warn!(node = %node.port, holder = %port, chunk = %chunk_name, error = ?e, "Verify: chunk holder did not answer");
+                        ChunkStatus::Unreachable
+                    });
+                (port.as_str(), status)
+            }
+            None => ("?", ChunkStatus::Unreachable),
+        };
+        if !status.is_healthy() {
+            failed += 1;
+        }
+        writer
+            .write_all(
+                format!(
+                    "part {}/{} {} node={} status={}\n",
+                    i + 1,
+                    tag.parts,
+                    chunk_name,
+                    holder,
+                    status
+                )
+                .as_bytes(),
+            )
+            .await?;
+    }
+
+    if failed == 0 {
+        writer.write_all(b"OK\n").await?;
+    } else {
+        writer
+            .write_all(
+                format!(
+                    "ERR {} of {} chunks failed verification\n",
+                    failed, tag.parts
+                )
+                .as_bytes(),
+            )
+            .await?;
+    }
+    Ok(())
+}
+
+/// Handles "FILE MANIFEST <name>": every chunk of a file with its offset,
+/// size, holder and recorded SHA-256, as a single-line
+/// [`node::FileManifestView`], so clients can fetch chunks themselves and
+/// verify what they download. Hashes come from the holder, or else from one of
+/// its backup holders.
+async fn handle_file_manifest<W: AsyncWrite + Unpin>(
+    node: &Node,
+    writer: &mut W,
+    name: String,
+) -> Result<(), AnyErr> {
+    let name = own_ring_name(node, name);
+    let tag = node.file_tags.read().await.get(&name).cloned();
+    let Some(tag) = tag else {
+        writer.write_all(b"ERR file not found\n").await?;
+        return Ok(());
+    };
+    if let Some(ring) = &tag.ring {
+        writer
+            .write_all(format!("ERR file is on ring '{}'\n", ring).as_bytes())
+            .await?;
+        return Ok(());
+    }
+
+    let view = serde_json::to_string(&file_manifest(node, name, &tag).await)?;
+    writer.write_all(format!("{}\n", view).as_bytes()).await?;
+    Ok(())
+}
+
+/// Chunks of the file `name` tagged with `tag`, with the hashes their holders
+/// (or backup holders) recorded
+async fn file_manifest(node: &Node, name: String, tag: &node::FileTag) -> node::FileManifestView {
+    let holders = tag_holders(node, &name, tag).await;
+    let mut chunks = Vec::with_capacity(tag.parts as usize);
+    let mut offset = 0;
+    for i in 0..tag.parts {
+        let chunk_name = chunk_file_name(tag.chunk_set(&name), i, tag.parts);
+        let size = fair_chunk_len(i, tag.size, tag.parts);
+        let holder = holders.get(i as usize).cloned().flatten();
+        let mut sources = Vec::new();
+        if let Some(port) = &holder {
+            sources.push(port.clone());
+            sources.extend(backup_holders(node, port).await);
+        }
+        let mut sha256 = None;
+        for port in sources {
+            match hash_chunk_on(node, &node.peer_addr(&port), &chunk_name).await {
+                Ok(Some(entry)) if entry.size == size => {
+                    sha256 = Some(entry.sha256.to_string());
+                    break;
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    tracing::debug!(node = %node.port, holder = %port, chunk = %chunk_name, error = ?e, "Manifest: no hash from node");
+                }
+            }
+        }
+        chunks.push(node::ChunkView {
+            index: i,
+            name: chunk_name,
+            offset,
+            size,
+            node: holder,
+            sha256,
+        });
+        offset += size;
+    }
+
+    node::FileManifestView {
+        name,
+        size: tag.size,
+        parts: tag.parts,
+        chunks,
+        content_type: tag.content_type.clone(),
+    }
+}
+
+/// Handles "FILE DU [JSON] [<prefix>]": the size of every file whose name
+/// starts with `<prefix>`, one line each, and a `TOTAL` line; or a
+/// [`usage::UsageView`] document. Physical bytes are the chunk copies the
+/// holders and backup holders report; unreachable ones count for nothing.
+/// Files of federated rings are left to their ring.
+async fn handle_file_du<W: AsyncWrite + Unpin>(
+    node: &Node,
+    writer: &mut W,
+    json: bool,
+    prefix: String,
+) -> Result<(), AnyErr> {
+    let mut tags: Vec<(String, node::FileTag)> = node
+        .file_tags
+        .read()
+        .await
+        .iter()
+        .filter(|(name, tag)| name.starts_with(&prefix) && tag.ring.is_none())
+        .map(|(name, tag)| (name.clone(), tag.clone()))
+        .collect();
+    tags.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut entries = Vec::with_capacity(tags.len());
+    for (name, tag) in tags {
+        let mut entry = usage::FileUsageView {
+            name,
+            logical: tag.size,
+            physical: 0,
+            chunks: 0,
+            parts: tag.parts,
+            backups: 0,
+            shared: tag.chunks.clone(),
+        };
+        let holders = tag_holders(node, &entry.name, &tag).await;
+        for i in 0..tag.parts {
+            let chunk_name = chunk_file_name(tag.chunk_set(&entry.name), i, tag.parts);
+            let Some(port) = holders.get(i as usize).and_then(Option::as_ref) else {
+                continue;
+            };
+            if let Ok((Some(stored), _)) =
+                stat_chunk_on(node, &node.peer_addr(port), &chunk_name).await
+            {
+                entry.chunks += 1;
+                if entry.shared.is_none() {
+                    entry.physical += stored;
+                }
+            }
+            for backup in backup_holders(node, port).await {
+                if let Ok((_, Some(stored))) =
+                    stat_chunk_on(node, &node.peer_addr(&backup), &chunk_name).await
+                {
+                    entry.backups += 1;
+                    if entry.shared.is_none() {
+                        entry.physical += stored;
+                    }
+                }
+            }
+        }
+        entries.push(entry);
+    }
+
+    let view = usage::UsageView::new(port_str(&node.port).to_string(), prefix, entries);
+    let out = if json {
+        format!("{}\n", serde_json::to_string(&view)?)
+    } else {
+        let mut out = String::new();
+        for entry in &view.entries {
+            out.push_str(&format!("{}\n", entry));
+        }
+        out.push_str(&format!("{}\nOK\n", view.total_line()));
+        out
+    };
+    writer.write_all(out.as_bytes()).await?;
+    Ok(())
+}
+
+/// Handles "FILE INFO <name>"
+/// Reports where every chunk of a file lives, one line per chunk, e.g.
+/// `part 2/5 node=7003 size=1048576 status=ok backup=7002`.
+/// Holders come from the file tag and the topology; `status` is a live health
+/// check of the holder (`ok` or `dead`). With several backups, `backup` and
+/// `backup_status` list them all, comma separated.
+async fn handle_file_info<W: AsyncWrite + Unpin>(
+    node: Arc<Node>,
+    writer: &mut W,
+    name: String,
+) -> Result<(), AnyErr> {
+    let tag = node.file_tags.read().await.get(&name).cloned();
+    let Some(tag) = tag else {
+        writer.write_all(b"ERR file not found\n").await?;
+        return Ok(());
+    };
+
+    // A federated ring's file has no chunks on this ring
+    if let Some(ring) = &tag.ring {
+        writer
+            .write_all(format!("ring={} size={}\nOK\n", ring, tag.size).as_bytes())
+            .await?;
+        return Ok(());
+    }
+
+    let holders = tag_holders(&node, &name, &tag).await;
+    let mut health: HashMap<String, &str> = HashMap::new();
+    let mut holes = Vec::new();
+    for i in 0..tag.parts {
+        let size = fair_chunk_len(i, tag.size, tag.parts);
+        let Some(port) = holders.get(i as usize).and_then(Option::as_ref) else {
+            writer
+                .write_all(
+                    format!(
+                        "part {}/{} node=? size={} status=unknown backup=? backup_status=unknown\n",
+                        i + 1,
+                        tag.parts,
+                        size
+                    )
+                    .as_bytes(),
+                )
+                .await?;
+            continue;
+        };
+        let chunk_name = chunk_file_name(tag.chunk_set(&name), i, tag.parts);
+
+        // 1. Is the chunk on its (live) holder?
+        let status = match node_health(&node, &mut health, port).await {
+            "ok" => match stat_chunk_on(&node, &node.peer_addr(port), &chunk_name).await {
+                Ok((Some(stored), _)) if stored == size => "ok",
+                Ok(_) => "missing",
+                Err(_) => "unknown",
+            },
+            dead => dead,
+        };
+
+        // 2. Are its backups on the holder's backup holders?
+        let (backup, backup_status, backed_up) = if node.replication == 0 {
+            ("-".to_string(), "-".to_string(), false)
+        } else {
+            let backups = backup_holders(&node, port).await;
+            let mut statuses = Vec::with_capacity(backups.len());
+            for backup in &backups {
+                let backup_status = match node_health(&node, &mut health, backup).await {
+                    "ok" => {
+                        match stat_chunk_on(&node, &node.peer_addr(backup), &chunk_name).await {
+                            Ok((_, Some(stored))) if stored == size => "ok",
+                            Ok(_) => "missing",
+                            Err(_) => "unknown",
+                        }
+                    }
+                    dead => dead,
+                };
+                statuses.push(backup_status);
+            }
+            let backed_up = statuses.contains(&"ok");
+            if backups.is_empty() {
+                ("?".to_string(), "unknown".to_string(), backed_up)
+            } else {
+                (backups.join(","), statuses.join(","), backed_up)
+            }
+        };
+        if status != "ok" && !backed_up {
+            holes.push((i + 1).to_string());
+        }
+
+        writer
+            .write_all(
+                format!(
+                    "part {}/{} node={} size={} status={} backup={} backup_status={}\n",
+                    i + 1,
+                    tag.parts,
+                    port,
+                    size,
+                    status,
+                    backup,
+                    backup_status
+                )
+                .as_bytes(),
+            )
+            .await?;
     }
 
+    // 3. Parts with no readable copy at all
+    if !holes.is_empty() {
+        writer
+            .write_all(format!("HOLES {}\n", holes.join(",")).as_bytes())
+            .await?;
+    }
     writer.write_all(b"OK\n").await?;
     Ok(())
 }
 
-async fn handle_file_tags_set<W: AsyncWrite + Unpin>(
-    node: &Node,
+/// Health of `port` (`ok` or `dead`), checked once per `FILE INFO`
+async fn node_health(
+    node: &Arc<Node>,
+    health: &mut HashMap<String, &'static str>,
+    port: &str,
+) -> &'static str {
+    if let Some(status) = health.get(port) {
+        return status;
+    }
+    let alive = check_node_health(Arc::clone(node), &node.peer_addr(port))
+        .await
+        .is_ok();
+    let status = if alive { "ok" } else { "dead" };
+    health.insert(port.to_string(), status);
+    status
+}
+
+/// Handles "FILE MIGRATE-CHUNK <from> <size> <name>": stores a chunk an older
+/// leader moved onto this node, from `from`'s content copy or else its
+/// backup copy, then hands it to this node's backup holders
+async fn handle_file_migrate_chunk<W: AsyncWrite + Unpin>(
+    node: &Arc<Node>,
     writer: &mut W,
-    entries: String,
+    from: String,
+    size: u64,
+    name: String,
 ) -> Result<(), AnyErr> {
-    node.set_file_tags_from_entries(&entries).await;
+    if let Err(e) = check_chunk_size(node, size).await {
+        writer.write_all(format!("ERR {}\n", e).as_bytes()).await?;
+        return Ok(());
+    }
+    // Fetching the chunk takes a `max-relays` slot like a pushed one
+    let Some(_slot) = node.relay_slot().await else {
+        tracing::warn!(node = %node.port, chunk = %name, "Too many chunk streams, refusing a migration");
+        let refusal = format!(
+            "ERR {} retry-after={}\n",
+            BUSY,
+            node::RELAY_RETRY_AFTER.as_millis()
+        );
+        writer.write_all(refusal.as_bytes()).await?;
+        return Ok(());
+    };
+    let data = match request_chunk_from(node, &from, &name).await {
+        Ok((data, _)) if data.len() as u64 == size => Some(data),
+        _ => match request_backup_chunk_from(node, &from, &name).await {
+            Ok((data, _)) if data.len() as u64 == size => Some(data),
+            _ => None,
+        },
+    };
+    let Some(data) = data else {
+        writer
+            .write_all(format!("ERR no full copy of {} on {}\n", name, from).as_bytes())
+            .await?;
+        return Ok(());
+    };
+    save_into_node_dir(node, &name, &data, "content", IoClass::Backup).await?;
+    tokio::spawn(notify_backup_holders(Arc::clone(node), name.clone()));
+    tracing::debug!(node = %node.port, chunk = %name, from = %from, size, "Migrated chunk stored");
     writer.write_all(b"OK\n").await?;
     Ok(())
 }
 
-/* -------- FILE RETRIEVAL (PULL / GET-CHUNK) -------- */
+/// Handles "FILE STAT-CHUNK <name>": the sizes of this node's copy of a chunk
+/// in `content/` and `backup/`, as `CHUNK <content> <backup>` (`-` if absent)
+async fn handle_file_stat_chunk<W: AsyncWrite + Unpin>(
+    node: &Node,
+    writer: &mut W,
+    name: String,
+) -> Result<(), AnyErr> {
+    let fname = sanitize_filename(&name);
+    let mut sizes = Vec::with_capacity(2);
+    for subdir in ["content", "backup"] {
+        let size = pack::size(node, subdir, &fname).await;
+        sizes.push(size.map_or("-".to_string(), |len| len.to_string()));
+    }
+    writer
+        .write_all(format!("CHUNK {} {}\n", sizes[0], sizes[1]).as_bytes())
+        .await?;
+    Ok(())
+}
 
-async fn handle_file_pull<W: AsyncWrite + Unpin>(
+/// Handles "FILE HASH-CHUNK <name>": the manifest entry this node recorded
+/// for a chunk, from its `content/` copy or else its `backup/` copy, as
+/// `HASH <sha256> <size>` (`HASH -` if it has neither)
+async fn handle_file_hash_chunk<W: AsyncWrite + Unpin>(
     node: &Node,
     writer: &mut W,
     name: String,
 ) -> Result<(), AnyErr> {
-    let tags = node.file_tags.read().await;
-    let Some(tag) = tags.get(&name) else {
-        writer.write_all(b"ERR file not found\n").await?;
-        return Ok(());
+    let fname = sanitize_filename(&name);
+    let mut entry = None;
+    for subdir in ["content", "backup"] {
+        entry = manifest::lookup(&node.manifest_dir(subdir), &fname).await;
+        if entry.is_some() {
+            break;
+        }
+    }
+    let reply = match entry {
+        Some(entry) => format!("HASH {}\n", entry),
+        None => "HASH -\n".to_string(),
     };
-    let start_port = tag.start;
-    let parts = tag.parts;
-    let file_size = tag.size;
-    let start_addr = format!("{}:{}", host_of(&node.port), start_port);
-    drop(tags);
+    writer.write_all(reply.as_bytes()).await?;
+    Ok(())
+}
 
-    // Assemble full file by walking the ring starting at start_addr
-    let bytes = pull_file_from_ring(node, &name, &start_addr, parts, file_size).await?;
+/// Sends "FILE HASH-CHUNK" to `addr`: the manifest entry it has for a chunk
+async fn hash_chunk_on(
+    node: &Node,
+    addr: &str,
+    chunk_name: &str,
+) -> Result<Option<ChunkEntry>, AnyErr> {
+    let mut s = node.connect(addr).await?;
+    s.write_all(format!("FILE HASH-CHUNK {}\n", protocol::encode_name(chunk_name)).as_bytes())
+        .await?;
 
-    // IMPORTANT: return *pure bytes*, no textual header or trailer.
-    writer.write_all(&bytes).await?;
-    Ok(())
+    let mut reader = BufReader::new(s);
+    let mut line = String::new();
+    reader.read_line(&mut line).await?;
+    let entry = line
+        .trim_end()
+        .strip_prefix("HASH ")
+        .ok_or_else(|| format!("unexpected reply to HASH-CHUNK: '{}'", line.trim_end()))?;
+    Ok(if entry == "-" {
+        None
+    } else {
+        Some(entry.parse()?)
+    })
 }
 
-async fn handle_file_get_chunk<W: AsyncWrite + Unpin>(
+/// Handles "FILE CHECK-CHUNK <name>"
+/// Re-hashes a chunk from this node's `content/` directory, repairs it from the
+/// predecessor's backup if it is bad, and answers `CHUNK <status>`.
+async fn handle_file_check_chunk<W: AsyncWrite + Unpin>(
     node: &Node,
     writer: &mut W,
     name: String,
 ) -> Result<(), AnyErr> {
-    let next = node.get_next().await.unwrap_or_else(|| node.port.clone());
+    let status =
+        verify::verify_local_chunk(node, "content", &name, true, IoClass::Foreground).await;
+    if status != ChunkStatus::Ok {
+        node.emit(NodeEvent::ChunkDamaged {
+            name,
+            backup: false,
+            repaired: status == ChunkStatus::Repaired,
+        });
+    }
+    writer
+        .write_all(format!("CHUNK {}\n", status).as_bytes())
+        .await?;
+    Ok(())
+}
 
-    // Read the specific chunk from the "content" directory
-    let chunk_path = PathBuf::from(format!(
-        "nodes/{}/content/{}",
-        port_str(&node.port),
-        sanitize_filename(&name)
-    ));
+/// Sends "FILE STAT-CHUNK" to `addr`: the sizes of its `content/` and
+/// `backup/` copies of a chunk, if it has them
+pub(crate) async fn stat_chunk_on(
+    node: &Node,
+    addr: &str,
+    chunk_name: &str,
+) -> Result<(Option<u64>, Option<u64>), AnyErr> {
+    let mut s = node.connect(addr).await?;
+    s.write_all(format!("FILE STAT-CHUNK {}\n", protocol::encode_name(chunk_name)).as_bytes())
+        .await?;
 
-    let chunk = fs::read(&chunk_path).await.unwrap_or_default();
+    let mut reader = BufReader::new(s);
+    let mut line = String::new();
+    reader.read_line(&mut line).await?;
+    let mut sizes = line
+        .trim_end()
+        .strip_prefix("CHUNK ")
+        .ok_or_else(|| format!("unexpected reply to STAT-CHUNK: '{}'", line.trim_end()))?
+        .split(' ')
+        .map(|size| size.parse().ok());
+    Ok((sizes.next().flatten(), sizes.next().flatten()))
+}
 
-    // Header + exact bytes for node-to-node transfer
-    writer
-        .write_all(format!("FILE RESP-CHUNK {} {} {}\n", next, chunk.len(), name).as_bytes())
+/// Sends "FILE CHECK-CHUNK" to `addr` and parses the status it answers
+async fn check_chunk_on(node: &Node, addr: &str, chunk_name: &str) -> Result<ChunkStatus, AnyErr> {
+    let mut s = node.connect(addr).await?;
+    s.write_all(format!("FILE CHECK-CHUNK {}\n", protocol::encode_name(chunk_name)).as_bytes())
         .await?;
-    writer.write_all(&chunk).await?;
-    Ok(())
+
+    let mut reader = BufReader::new(s);
+    let mut line = String::new();
+    reader.read_line(&mut line).await?;
+    let status = line
+        .trim_end()
+        .strip_prefix("CHUNK ")
+        .ok_or_else(|| format!("unexpected reply to CHECK-CHUNK: '{}'", line.trim_end()))?;
+    Ok(status.parse()?)
 }
 
 /* -------- BACKUP HANDLERS -------- */
 
-/// Handles "FILE NOTIFY-CHUNK-SAVED <name>"
-/// This node is the predecessor (i). It is being notified by its successor (i+1).
-/// It must now fetch the chunk from (i+1) and save it to its /backup dir.
+/// Handles "FILE NOTIFY-CHUNK-SAVED [<addr>] <name>"
+/// This node keeps backups for the chunk holder at `<addr>`: usually its
+/// successor (i+1), which older nodes leave out. It must now fetch the chunk
+/// from the holder and save it to its /backup dir.
 async fn handle_file_notify_chunk_saved<W: AsyncWrite + Unpin>(
     node: Arc<Node>,
     writer: &mut W,
+    from: Option<String>,
     chunk_name: String,
 ) -> Result<(), AnyErr> {
-    // Find the successor (i+1) from whom it received the notification
-    let Some(next_addr) = node.get_next().await else {
+    // Find the holder from whom it received the notification
+    let next_addr = match from {
+        Some(addr) => Some(addr),
+        None => node.get_next().await,
+    };
+    let Some(next_addr) = next_addr else {
         tracing::warn!(node = %node.port, chunk = %chunk_name, "Got NOTIFY-CHUNK-SAVED but have no next_port to fetch from.");
         writer.write_all(b"OK (but no next_port)\n").await?;
         return Ok(());
@@ -1031,7 +4487,7 @@ async fn handle_file_notify_chunk_saved<W: AsyncWrite + Unpin>(
 
     // Spawn a new task to do the backup and ACK the notification immediately
     tokio::spawn(async move {
-        match request_chunk_for_backup(&next_addr, &chunk_name).await {
+        match request_chunk_for_backup(&node, &next_addr, &chunk_name).await {
             Ok(chunk_data) => {
                 if chunk_data.is_empty() {
                     tracing::warn!(
@@ -1044,7 +4500,9 @@ async fn handle_file_notify_chunk_saved<W: AsyncWrite + Unpin>(
                 }
 
                 // Save to "/backup" directory
-                match save_into_node_dir(&node, &chunk_name, &chunk_data, "backup").await {
+                match save_into_node_dir(&node, &chunk_name, &chunk_data, "backup", IoClass::Backup)
+                    .await
+                {
                     Ok(path) => {
                         tracing::info!(
                             node = %node.port,
@@ -1092,35 +4550,21 @@ async fn handle_file_get_chunk_for_backup<W: AsyncWrite + Unpin>(
 ) -> Result<(), AnyErr> {
     // Sanitize the name, although it should already be safe
     let fname = sanitize_filename(&name);
-    let path = PathBuf::from(format!(
-        "nodes/{}/content/{}", // Read from "/content"
-        port_str(&node.port),
-        fname
-    ));
-
-    let (chunk, size) = match fs::read(&path).await {
-        Ok(data) => {
-            let size = data.len() as u64;
-            (data, size)
-        }
-        Err(e) => {
-            tracing::warn!(
-                node = %node.port,
-                path = %path.display(),
-                error = ?e,
-                "GET-CHUNK-FOR-BACKUP: File not found or unreadable."
-            );
-            (Vec::new(), 0u64) // Send 0 bytes on error
-        }
-    };
 
-    // 1. Send the 8-byte size (u64, big-endian)
-    writer.write_all(&size.to_be_bytes()).await?;
-
-    // 2. Send the raw file bytes
-    if size > 0 {
-        writer.write_all(&chunk).await?;
+    // Read from "/content"
+    if pack::size(node, "content", &fname).await.is_none() {
+        tracing::warn!(
+            node = %node.port,
+            chunk = %fname,
+            "GET-CHUNK-FOR-BACKUP: File not found or unreadable."
+        );
     }
+
+    // 8-byte size (u64, big-endian) followed by the raw file bytes; 0 bytes on error
+    send_chunk_file(node, IoClass::Backup, writer, "content", &fname, |size| {
+        size.to_be_bytes().to_vec()
+    })
+    .await?;
     Ok(())
 }
 
@@ -1132,46 +4576,262 @@ async fn handle_file_get_backup_chunk<W: AsyncWrite + Unpin>(
     writer: &mut W,
     name: String,
 ) -> Result<(), AnyErr> {
-    let next = node.get_next().await.unwrap_or_else(|| node.port.clone());
+    // Serve from the "backup" directory, with the same protocol message as GET-CHUNK
+    serve_chunk(node, writer, "backup", &name).await
+}
 
-    // Read from "backup" directory
-    let chunk_path = PathBuf::from(format!(
-        "nodes/{}/backup/{}",
-        port_str(&node.port),
-        sanitize_filename(&name)
-    ));
+/* --- PULL helpers --- */
 
-    let chunk = fs::read(&chunk_path).await.unwrap_or_default();
+/// `(end offset, port)` for chunks `first_index..parts` of a file, offsets
+/// counted from the start of chunk `first_index`. Used for progress reporting.
+async fn chunk_route(
+    node: &Node,
+    first_port: u16,
+    first_index: u32,
+    size: u64,
+    parts: u32,
+) -> Vec<(u64, String)> {
+    let holders = chunk_holders(node, first_port, parts - first_index).await;
+    route_over(&holders, first_index, size, parts)
+}
 
-    // Respond with the same protocol message as GET-CHUNK
-    writer
-        .write_all(format!("FILE RESP-CHUNK {} {} {}\n", next, chunk.len(), name).as_bytes())
-        .await?;
-    writer.write_all(&chunk).await?;
-    Ok(())
+/// Like [`chunk_route`], for chunks `first_index..` held by `holders` in order
+fn route_over(holders: &[String], first_index: u32, size: u64, parts: u32) -> Vec<(u64, String)> {
+    let mut end = 0;
+    holders
+        .iter()
+        .zip(first_index..parts)
+        .map(|(port, i)| {
+            end += fair_chunk_len(i, size, parts);
+            (end, port.clone())
+        })
+        .collect()
 }
 
-/* --- PULL helpers --- */
+/// Port of the node holding each chunk of the file `name`, from its chunk
+/// manifest (`None` for a holder gone from the netmap). The chunks of a file
+/// whose tag names no holders are looked up (see [`migrate::locate_chunks`]).
+pub(crate) async fn tag_holders(
+    node: &Node,
+    name: &str,
+    tag: &node::FileTag,
+) -> Vec<Option<String>> {
+    if tag.holders.is_empty() {
+        return migrate::locate_chunks(node, tag.chunk_set(name), tag).await;
+    }
+    let mut ports = Vec::with_capacity(tag.holders.len());
+    for id in &tag.holders {
+        ports.push(node.port_of(*id).await);
+    }
+    ports
+}
+
+/// Live nodes whose labels satisfy `place`, in ring order from this node:
+/// the holders of a `FILE PUSH ... PLACE`.
+async fn placement(node: &Node, place: &Labels) -> Result<Vec<String>, String> {
+    let size = node.network_size().await as u32;
+    if size > 1 && !node.ring_supports(Feature::Placement).await {
+        return Err("PLACEMENT_UNSUPPORTED some nodes do not understand placed files".into());
+    }
+    let own: u16 = port_str(&node.port).parse().unwrap_or(0);
+    let ring = chunk_holders(node, own, size).await;
+    if ring.len() < size as usize {
+        return Err("topology incomplete, run TOPOLOGY WALK before placing files".into());
+    }
+    let mut holders = Vec::new();
+    for port in ring {
+        if node.node_status(&port).await != Some(NodeStatus::Dead)
+            && node.node_labels(&port).await.matches(place)
+        {
+            holders.push(port);
+        }
+    }
+    if holders.is_empty() {
+        return Err(format!("NO_PLACEMENT no live node matches {}", place));
+    }
+    Ok(holders)
+}
+
+/// Ports expected to hold each chunk of a file, by following the topology from
+/// its start node. Shorter than `parts` if the topology is incomplete.
+pub(crate) async fn chunk_holders(node: &Node, start_port: u16, parts: u32) -> Vec<String> {
+    let topology = node.topology_map.read().await;
+    let mut holders = Vec::with_capacity(parts as usize);
+    let mut current = start_port.to_string();
+    for _ in 0..parts {
+        holders.push(current.clone());
+        match topology.get(&current) {
+            Some(next) => current = port_str(next).to_string(),
+            None => break,
+        }
+    }
+    holders
+}
 
+/// Reads every chunk of the chunk set `name` in order from its holder in
+/// `holders` (see [`tag_holders`]).
+///
+/// The reads are pipelined: chunk `i + 1` is already asked of its holder
+/// while chunk `i` is still arriving, so each hop's connection and seek
+/// overlap the previous transfer instead of adding up. At most one chunk is
+/// read ahead.
 async fn pull_file_from_ring(
     node: &Node,
     name: &str,
-    start_addr: &str,
     parts: u32,
-    _file_size: u64,
+    holders: &[Option<String>],
+    transfer: &Transfer,
+    offset: u64,
 ) -> Result<Vec<u8>, AnyErr> {
     let mut out = Vec::new();
-    let mut current_addr = start_addr.to_string();
-    let mut current_port = port_str(start_addr).to_string();
-    let host = host_of(start_addr);
-    let topology = node.topology_map.read().await;
+    let (first, mut skip) = chunk_at(offset, transfer.total, parts);
+    // The bytes already received count as moved
+    transfer.advance(offset);
+    let has_topology = !node.topology_map.read().await.is_empty();
+
+    // Chunks over our limit would otherwise look like unreachable holders
+    if parts > 0 {
+        check_chunk_size(node, fair_chunk_len(0, transfer.total, parts)).await?;
+    }
 
-    for i in 0..parts {
-        let chunk_name = chunk_file_name(name, i, parts);
-        let chunk: Vec<u8>;
+    // Where chunk `i` is read from, if its holder is known
+    let ring_chunk = |i: u32| -> Option<RingChunk> {
+        let port = holders.get(i as usize)?.as_ref()?;
+        Some(RingChunk {
+            index: i,
+            parts,
+            name: chunk_file_name(name, i, parts),
+            port: port.clone(),
+            addr: node.peer_addr(port),
+            expected_len: fair_chunk_len(i, transfer.total, parts),
+        })
+    };
+
+    let mut ahead = (first < parts)
+        .then(|| ring_chunk(first))
+        .flatten()
+        .map(|chunk| ReadAhead::Pending(Box::pin(read_ring_chunk(node, chunk, has_topology))));
+    for i in first..parts {
+        if transfer.is_cancelled() {
+            return Err(format!("pull {} cancelled", transfer.token).into());
+        }
+        let Some(current) = ahead.take() else {
+            return Err(format!("no holder known for chunk {}/{}", i + 1, parts).into());
+        };
+
+        // Ask for the next chunk, and keep it coming while this one arrives
+        let mut next = (i + 1 < parts)
+            .then(|| ring_chunk(i + 1))
+            .flatten()
+            .map(|chunk| Box::pin(read_ring_chunk(node, chunk, has_topology)));
+        let chunk = match current {
+            ReadAhead::Done(res) => res?,
+            ReadAhead::Pending(mut current) => {
+                let mut next_done = None;
+                let res = loop {
+                    tokio::select! {
+                        biased;
+                        res = &mut current => break res,
+                        res = async { next.as_mut().expect("checked").await },
+                            if next.is_some() && next_done.is_none() =>
+                        {
+                            next_done = Some(res);
+                        }
+                    }
+                };
+                if let Some(res) = next_done {
+                    ahead = Some(ReadAhead::Done(res));
+                    next = None;
+                }
+                res?
+            }
+        };
+        if ahead.is_none() {
+            ahead = next.map(ReadAhead::Pending);
+        }
+
+        let kept = &chunk[std::mem::take(&mut skip) as usize..];
+        transfer.advance(kept.len() as u64);
+        out.extend_from_slice(kept);
+    }
+
+    Ok(out)
+}
+
+/// A chunk of a ring-walk pull and the holder it is read from
+struct RingChunk {
+    index: u32,
+    parts: u32,
+    /// Chunk name, as asked for with `FILE GET-CHUNK`
+    name: String,
+    port: String,
+    addr: String,
+    expected_len: u64,
+}
+
+/// The chunk a pull reads ahead: still arriving, or already in
+enum ReadAhead<F> {
+    Pending(Pin<Box<F>>),
+    Done(Result<Vec<u8>, AnyErr>),
+}
+
+/// Reads one chunk of a ring-walk pull: from its backup holder when that one
+/// is clearly less busy, otherwise from its holder, falling back on a backup
+/// (and marking the holder `Dead`) when the holder cannot be reached, or on a
+/// backup when the holder's copy is missing or short.
+async fn read_ring_chunk(
+    node: &Node,
+    chunk: RingChunk,
+    has_topology: bool,
+) -> Result<Vec<u8>, AnyErr> {
+    let RingChunk {
+        index: i,
+        parts,
+        name: chunk_name,
+        port: current_port,
+        addr: current_addr,
+        expected_len,
+    } = chunk;
+    let host = host_str(&current_addr);
+    let mut chunk: Vec<u8>;
+
+    // 1. Read from the backup instead when its holder is clearly less busy
+    let mut backup = None;
+    if has_topology {
+        for port in backup_holders(node, &current_port).await {
+            if node.node_status(&port).await != Some(crate::NodeStatus::Dead) {
+                backup = Some(port);
+                break;
+            }
+        }
+    }
+    let mut from_backup = None;
+    if let Some(backup_port) = backup
+        && prefer_backup(node, &current_port, &backup_port).await
+    {
+        let backup_addr = join_host_port(host, &backup_port);
+        match request_backup_chunk_from(node, &backup_addr, &chunk_name).await {
+            // A backup that is not there yet comes back empty
+            Ok((chunk_data, _)) if chunk_data.len() as u64 == expected_len => {
+                tracing::debug!(
+                    node = %node.port,
+                    from_backup_node = %backup_addr,
+                    chunk_name = %chunk_name,
+                    "Read chunk from the less loaded backup."
+                );
+                from_backup = Some(chunk_data);
+            }
+            _ => {
+                tracing::debug!(node = %node.port, backup_node = %backup_addr, chunk_name = %chunk_name, "Backup read failed, using primary.");
+            }
+        }
+    }
 
-        // 1. Try to get chunk from the current node
-        match request_chunk_from(&current_addr, &chunk_name).await {
+    // 2. Otherwise get the chunk from the current node
+    if let Some(chunk_data) = from_backup {
+        chunk = chunk_data;
+    } else {
+        match request_chunk_from(node, &current_addr, &chunk_name).await {
             Ok((chunk_data, _next_addr_ignored)) => {
                 // Node is alive.
                 tracing::debug!(
@@ -1192,97 +4852,89 @@ async fn pull_file_from_ring(
                     "Failed to get chunk from node. Attempting to use backup."
                 );
 
-                // Mark node as Dead and broadcast this change
-                tracing::info!(
-                    node = %node.port,
-                    dead_node = %current_port,
-                    "Marking node as Dead and broadcasting netmap update."
-                );
-                node.update_node_status(current_port.clone(), crate::NodeStatus::Dead)
-                    .await;
-
-                // Await the broadcast to ensure state is sent before we continue
-                node.broadcast_netmap_update().await;
-
-                // 1.3. Find the predecessor of the dead node (the one holding the backup)
-                let pred_port = topology
-                    .iter()
-                    .find(|(_from, to)| port_str(to) == current_port)
-                    .map(|(from, _to)| from.clone());
+                // Mark node as Dead and broadcast this change, unless we may be the ones cut off
+                if !node.is_degraded() {
+                    tracing::info!(
+                        node = %node.port,
+                        dead_node = %current_port,
+                        "Marking node as Dead and broadcasting netmap update."
+                    );
+                    node.update_node_status(current_port.clone(), crate::NodeStatus::Dead)
+                        .await;
+                    node.emit(NodeEvent::PeerDead {
+                        port: current_port.clone(),
+                    });
+
+                    // Spread the change (by gossip, or a broadcast awaited here) before we continue
+                    node.broadcast_netmap_update().await;
+                }
 
-                let Some(pred_port) = pred_port else {
+                // 1.3. Read the chunk from one of the dead node's backups
+                let Some((backup_addr, chunk_data)) =
+                    read_backup(node, &current_port, &chunk_name, expected_len).await
+                else {
                     tracing::error!(
                         node = %node.port,
                         dead_node = %current_addr,
-                        "No predecessor found in topology for dead node. Cannot fetch backup."
+                        chunk_name = %chunk_name,
+                        "No backup of the chunk could be read."
                     );
-                    chunk = Vec::new();
-                    out.extend_from_slice(&chunk);
-
-                    // Manually advance to the next node to avoid getting stuck
-                    let next_port = topology.get(&current_port).cloned();
-                    if let Some(port) = next_port {
-                        current_port = port.clone();
-                        current_addr = format!("{}:{}", host, port);
-                    } else {
-                        tracing::error!(node=%node.port, dead_node=%current_port, "Topology broken. Cannot find next hop.");
-                        break;
-                    }
-                    continue;
+                    return Err(format!(
+                        "chunk {}/{} is on dead node {}, and none of its backups has it",
+                        i + 1,
+                        parts,
+                        current_port
+                    )
+                    .into());
                 };
-
-                let pred_addr = format!("{}:{}", host, pred_port);
-
-                // 1.4. Request the backup chunk from the predecessor
-                match request_backup_chunk_from(&pred_addr, &chunk_name).await {
-                    Ok((chunk_data, _)) => {
-                        tracing::info!(
-                            node = %node.port,
-                            from_backup_node = %pred_addr,
-                            chunk_name = %chunk_name,
-                            "Successfully retrieved chunk from backup."
-                        );
-                        chunk = chunk_data;
-                    }
-                    Err(e_backup) => {
-                        tracing::error!(
-                            node = %node.port,
-                            backup_node = %pred_addr,
-                            chunk_name = %chunk_name,
-                            error = ?e_backup,
-                            "Failed to get chunk from backup node. File will be corrupt."
-                        );
-                        chunk = Vec::new();
-                    }
-                }
+                tracing::info!(
+                    node = %node.port,
+                    from_backup_node = %backup_addr,
+                    chunk_name = %chunk_name,
+                    "Successfully retrieved chunk from backup."
+                );
+                chunk = chunk_data;
             }
         }
+    }
 
-        out.extend_from_slice(&chunk);
+    // 3. A chunk missing (or short) on its holder is a hole unless the backup has it
+    if chunk.len() as u64 != expected_len {
+        tracing::warn!(node = %node.port, chunk_name = %chunk_name, holder = %current_port, got = chunk.len(), expected = expected_len, "Chunk missing or short, trying its backup");
+        let recovered = read_backup(node, &current_port, &chunk_name, expected_len).await;
+        chunk = recovered.map(|(_, data)| data).ok_or_else(|| {
+            format!(
+                "chunk {}/{} ({}) is missing on node {} and on its backup",
+                i + 1,
+                parts,
+                chunk_name,
+                current_port
+            )
+        })?;
+    }
 
-        // 2. Find the next node in the chain to query
-        let next_port = topology.get(&current_port).cloned();
+    Ok(chunk)
+}
 
-        if let Some(port) = next_port {
-            current_port = port.clone();
-            current_addr = format!("{}:{}", host, port);
-        } else {
-            // This should only happen if the topology is broken
-            tracing::error!(
-                node = %node.port,
-                from_node = %current_port,
-                "Topology map is broken. Cannot find next hop. Stopping pull."
-            );
-            break;
-        }
+/// Whether a chunk should be read from its backup holder rather than its
+/// primary, going by the load both report. Unknown loads favour the primary.
+async fn prefer_backup(node: &Node, primary_port: &str, backup_port: &str) -> bool {
+    let Some(backup) = node.peer_load(backup_port).await else {
+        return false;
+    };
+    match node.peer_load(primary_port).await {
+        Some(primary) => backup.is_lighter_than(&primary),
+        None => false,
     }
-
-    Ok(out)
 }
 
-async fn request_chunk_from(addr: &str, chunk_name: &str) -> Result<(Vec<u8>, String), AnyErr> {
-    let mut s = TcpStream::connect(addr).await?;
-    s.write_all(format!("FILE GET-CHUNK {}\n", chunk_name).as_bytes())
+pub(crate) async fn request_chunk_from(
+    node: &Node,
+    addr: &str,
+    chunk_name: &str,
+) -> Result<(Vec<u8>, String), AnyErr> {
+    let mut s = node.connect(addr).await?;
+    s.write_all(format!("FILE GET-CHUNK {}\n", protocol::encode_name(chunk_name)).as_bytes())
         .await?;
 
     let (r, mut w) = s.into_split();
@@ -1291,36 +4943,33 @@ async fn request_chunk_from(addr: &str, chunk_name: &str) -> Result<(Vec<u8>, St
     // Parse FILE RESP-CHUNK <next_addr> <size> <name>
     let mut header = String::new();
     reader.read_line(&mut header).await?;
-    let header = header.trim_end_matches(['\r', '\n']);
-
-    let rest = header
-        .strip_prefix("FILE RESP-CHUNK ")
-        .ok_or_else(|| "malformed FILE RESP-CHUNK".to_string())?;
-    let mut parts = rest.splitn(3, ' ');
-    let next_addr = parts.next().unwrap_or("").to_string();
-    let size_str = parts.next().unwrap_or("");
-    let _name_echo = parts.next().unwrap_or("").to_string();
-
-    let size: usize = size_str
-        .parse()
-        .map_err(|_| "invalid chunk size".to_string())?;
-    let mut buf = vec![0u8; size];
+    let header: RespChunk = header.parse()?;
+
+    check_chunk_size(node, header.size).await?;
+    let mut buf = vec![0u8; usize::try_from(header.size)?];
     reader.read_exact(&mut buf).await?;
 
     // Ensure the is writer not dropped too early
-    let _ = (&mut w).shutdown().await;
+    let _ = w.shutdown().await;
 
-    Ok((buf, next_addr))
+    Ok((buf, header.next_addr))
 }
 
-async fn request_backup_chunk_from(
+pub(crate) async fn request_backup_chunk_from(
+    node: &Node,
     addr: &str,
     chunk_name: &str,
 ) -> Result<(Vec<u8>, String), AnyErr> {
-    let mut s = TcpStream::connect(addr).await?;
+    let mut s = node.connect(addr).await?;
     // Send the new command
-    s.write_all(format!("FILE GET-BACKUP-CHUNK {}\n", chunk_name).as_bytes())
-        .await?;
+    s.write_all(
+        format!(
+            "FILE GET-BACKUP-CHUNK {}\n",
+            protocol::encode_name(chunk_name)
+        )
+        .as_bytes(),
+    )
+    .await?;
 
     let (r, mut w) = s.into_split();
     let mut reader = BufReader::new(r);
@@ -1328,52 +4977,127 @@ async fn request_backup_chunk_from(
     // Parse FILE RESP-CHUNK <next_addr> <size> <name>
     let mut header = String::new();
     reader.read_line(&mut header).await?;
-    let header = header.trim_end_matches(['\r', '\n']);
-
-    let rest = header
-        .strip_prefix("FILE RESP-CHUNK ")
-        .ok_or_else(|| "malformed FILE RESP-CHUNK".to_string())?;
-    let mut parts = rest.splitn(3, ' ');
-    let next_addr = parts.next().unwrap_or("").to_string();
-    let size_str = parts.next().unwrap_or("");
-    let _name_echo = parts.next().unwrap_or("").to_string();
-
-    let size: usize = size_str
-        .parse()
-        .map_err(|_| "invalid chunk size".to_string())?;
-    let mut buf = vec![0u8; size];
+    let header: RespChunk = header.parse()?;
+
+    check_chunk_size(node, header.size).await?;
+    let mut buf = vec![0u8; usize::try_from(header.size)?];
     reader.read_exact(&mut buf).await?;
 
-    // ensure writer not dropped too early
-    let _ = (&mut w).shutdown().await;
+    // ensure writer not dropped too early
+    let _ = w.shutdown().await;
+
+    Ok((buf, header.next_addr))
+}
+
+/* -------- FILE LIST -------- */
+
+async fn handle_file_list_csv<W: AsyncWrite + Unpin>(
+    node: &Node,
+    writer: &mut W,
+) -> Result<(), AnyErr> {
+    // Pure CSV output (header + rows)
+    writer.write_all(b"name,start,size\n").await?;
+
+    let tags = node.file_tags.read().await;
+    let mut items: Vec<(&String, &node::FileTag)> = tags.iter().collect();
+    items.sort_by(|a, b| a.0.cmp(b.0));
+
+    for (name, tag) in items {
+        let name_escaped = csv_escape(name);
+        writer
+            .write_all(format!("{},{},{}\n", name_escaped, tag.start, tag.size).as_bytes())
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Handles "FILE CONFLICTS": one `<name> <version>` line per version set
+/// aside by a reconciliation (see [`crate::reconcile`]), names encoded, then `OK`
+async fn handle_file_conflicts<W: AsyncWrite + Unpin>(
+    node: &Node,
+    writer: &mut W,
+) -> Result<(), AnyErr> {
+    let mut conflicts: Vec<(String, String)> = node
+        .file_tags
+        .read()
+        .await
+        .iter()
+        .filter_map(|(copy, tag)| Some((tag.conflict_of.clone()?, copy.clone())))
+        .collect();
+    conflicts.sort();
+    for (name, copy) in conflicts {
+        writer
+            .write_all(
+                format!(
+                    "{} {}\n",
+                    protocol::encode_name(&name),
+                    protocol::encode_name(&copy)
+                )
+                .as_bytes(),
+            )
+            .await?;
+    }
+    writer.write_all(b"OK\n").await?;
+    Ok(())
+}
+
+/* --- Helpers and Errors --- */
+
+/// Error text for a size over its limit: `TOO_LARGE <what> of <size> bytes exceeds <limit>`
+fn too_large(what: &str, size: u64, limit: u64) -> String {
+    format!("TOO_LARGE {} of {} bytes exceeds {}", what, size, limit)
+}
+
+/// Fails with `TOO_LARGE` if a chunk announced on the wire is over `max-chunk-size`,
+/// before anything is allocated for it
+async fn check_chunk_size(node: &Node, size: u64) -> Result<(), AnyErr> {
+    let limit = node.settings().await.max_chunk_size;
+    if size > limit {
+        return Err(too_large("chunk", size, limit).into());
+    }
+    Ok(())
+}
 
-    Ok((buf, next_addr))
+/// Reads and drops exactly `size` bytes of a body we are not going to store
+async fn discard_body<R: AsyncRead + Unpin>(reader: &mut R, size: u64) -> Result<(), AnyErr> {
+    let read = copy(&mut reader.take(size), &mut tokio::io::sink()).await?;
+    if read < size {
+        return Err(format!("body truncated ({} of {} bytes)", read, size).into());
+    }
+    Ok(())
 }
 
-/* -------- FILE LIST -------- */
+/// First two words of a command line (`FILE PUSH`), for errors and logs
+fn command_verb(line: &str) -> String {
+    line.split_whitespace()
+        .take(2)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
 
-async fn handle_file_list_csv<W: AsyncWrite + Unpin>(
+/// Counts a protocol error against `peer`; the ban's length when it got the
+/// source banned
+/// Charges a new connection to its source; refuses it and returns false when
+/// the source is over its limit or banned
+async fn admit<W: AsyncWrite + Unpin>(
     node: &Node,
+    peer: SocketAddr,
     writer: &mut W,
-) -> Result<(), AnyErr> {
-    // Pure CSV output (header + rows)
-    writer.write_all(b"name,start,size\n").await?;
-
-    let tags = node.file_tags.read().await;
-    let mut items: Vec<(&String, &node::FileTag)> = tags.iter().collect();
-    items.sort_by(|a, b| a.0.cmp(b.0));
-
-    for (name, tag) in items {
-        let name_escaped = csv_escape(name);
-        writer
-            .write_all(format!("{},{},{}\n", name_escaped, tag.start, tag.size).as_bytes())
-            .await?;
-    }
-
-    Ok(())
+) -> Result<bool, AnyErr> {
+    let Err(refusal) = node.limiter.connect(peer.ip()) else {
+        return Ok(true);
+    };
+    tracing::debug!(node = %node.port, peer = %peer, refusal = %refusal, "Connection refused");
+    handle_error(writer, refusal.to_string()).await?;
+    Ok(false)
 }
 
-/* --- Helpers and Errors --- */
+fn count_protocol_error(node: &Node, peer: SocketAddr) -> Option<Duration> {
+    let ban = node.limiter.protocol_error(peer.ip())?;
+    tracing::warn!(node = %node.port, peer = %peer, ban_secs = ban.as_secs(), "Source banned after repeated protocol errors");
+    Some(ban)
+}
 
 async fn handle_error<W: AsyncWrite + Unpin>(writer: &mut W, err: String) -> Result<(), AnyErr> {
     writer
@@ -1382,40 +5106,69 @@ async fn handle_error<W: AsyncWrite + Unpin>(writer: &mut W, err: String) -> Res
     Ok(())
 }
 
-fn sanitize_filename(name: &str) -> String {
+/// Maps a file name to a name safe on disk. Unsafe characters (and `%`
+/// itself) become `%XX` escapes, so two different names never share a file.
+pub(crate) fn sanitize_filename(name: &str) -> String {
     let mut out = String::with_capacity(name.len());
     for ch in name.chars() {
-        let bad = ch == '/'
-            || ch == '\\'
-            || ch == '\0'
-            || ch == ':'
-            || ch == '|'
-            || ch == ';'
-            || ch == '\n'
-            || ch == '\r';
+        let bad = matches!(ch, '/' | '\\' | '\0' | ':' | '|' | ';' | '\n' | '\r' | '%');
         if bad {
-            out.push('_');
+            out.push_str(&format!("%{:02X}", ch as u8));
         } else {
             out.push(ch);
         }
     }
-    if out.is_empty() { "_".into() } else { out }
+    match out.as_str() {
+        "" => "_".into(),
+        // Would name the directory itself, or its parent
+        "." | ".." => out.replace('.', "%2E"),
+        _ => out,
+    }
 }
 
-async fn save_into_node_dir(
+/// Stores `data` as chunk `name` in `subdir`, writing under disk permits of `class`
+pub(crate) async fn save_into_node_dir(
     node: &Node,
     name: &str,
     data: &[u8],
     subdir: &str,
+    class: IoClass,
 ) -> Result<PathBuf, AnyErr> {
     let fname = sanitize_filename(name);
-    let path = PathBuf::from(format!(
-        "nodes/{}/{}/{}",
-        port_str(&node.port),
-        subdir,
-        fname
-    ));
-    fs::write(&path, data).await?;
+    let path = node.data_dir.join(subdir).join(&fname);
+    node.chunk_cache.invalidate(subdir, &fname);
+
+    // Hash while writing, so checksumming costs no extra read pass. The chunk
+    // is written aside and renamed over the old one, never rewritten in place,
+    // so a pull reading (or mapping) the old file keeps consistent bytes.
+    let tmp = node
+        .staging_dir()
+        .join(format!("{}-{}", identity::random_token(), fname));
+    let mut writer = HashingWriter::new(fs::File::create(&tmp).await?);
+    node.disk.write_all(class, &mut writer, data).await?;
+    writer.flush().await?;
+    let (_, sha256, size) = writer.finish();
+    let renamed = {
+        let _files = node.packs.lock_files().await;
+        node.packs.of(subdir).remove(&fname);
+        fs::rename(&tmp, &path).await
+    };
+    if let Err(e) = renamed {
+        let _ = fs::remove_file(&tmp).await;
+        return Err(e.into());
+    }
+    manifest::record(
+        &node.manifest_dir(subdir),
+        &fname,
+        ChunkEntry { sha256, size },
+    )
+    .await?;
+    tracing::debug!(node = %node.port, chunk = %fname, sha256 = %sha256, size, "Chunk stored");
+
+    node.emit(NodeEvent::ChunkStored {
+        name: name.to_string(),
+        backup: subdir == "backup",
+    });
     Ok(path)
 }
 
@@ -1437,69 +5190,222 @@ fn csv_escape(s: &str) -> String {
     out
 }
 
-fn host_of(addr: &str) -> &str {
-    if addr.contains(':') {
-        addr.split(':').next().unwrap_or("127.0.0.1")
-    } else {
-        "127.0.0.1" // Assume localhost if no host is given
+/* --- BACKUP HELPERS --- */
+
+/// Ports keeping the backups of `port`'s chunks, in the order they are tried.
+///
+/// Up to `replication` nodes, walking the ring backwards from `port`'s
+/// predecessor. Nodes whose failure domain (the `failure-domain` label) is not
+/// used yet, by the holder or an earlier backup, come first; the others only
+/// fill the copies left, so a ring too small or too uniform to spread them
+/// still keeps every copy. Nodes without the label count as a domain of their
+/// own. Without a topology, or while some node predates failure domains, the
+/// single backup stays on the predecessor.
+pub(crate) async fn backup_holders(node: &Node, port: &str) -> Vec<String> {
+    if node.replication == 0 {
+        return Vec::new();
+    }
+    let ring = ring_before(node, port).await;
+    if ring.is_empty() || !node.ring_supports(Feature::FailureDomains).await {
+        return predecessor_of(node, port).await.into_iter().collect();
     }
-}
 
-/* --- BACKUP HELPERS --- */
+    let domain = |labels: Labels| labels.0.get(&node.failure_domain).cloned();
+    let mut used: HashSet<String> = domain(node.node_labels(port).await).into_iter().collect();
+    let (mut spread, mut rest) = (Vec::new(), Vec::new());
+    for peer in ring {
+        match domain(node.node_labels(&peer).await) {
+            Some(d) if used.contains(&d) => rest.push(peer),
+            d => {
+                used.extend(d);
+                spread.push(peer);
+            }
+        }
+    }
+    spread
+        .into_iter()
+        .chain(rest)
+        .take(node.replication as usize)
+        .collect()
+}
 
-/// Helper to find the predecessor node from the topology map
-async fn get_predecessor_addr(node: &Node) -> Option<String> {
-    let my_port = port_str(&node.port);
+/// Every other node of the ring, from `port`'s predecessor backwards, as the
+/// topology map has it. Empty when `port` is not in the map.
+async fn ring_before(node: &Node, port: &str) -> Vec<String> {
     let topology = node.topology_map.read().await;
-    if topology.is_empty() {
-        return None;
+    let prev: HashMap<&str, &str> = topology
+        .iter()
+        .map(|(from, to)| (port_str(to), from.as_str()))
+        .collect();
+    let mut out = Vec::new();
+    let mut current = port;
+    while let Some(&before) = prev.get(current) {
+        if before == port || out.len() >= topology.len() {
+            break;
+        }
+        out.push(before.to_string());
+        current = before;
+    }
+    out
+}
+
+/// A chunk held by `holder`, read from the first of its backups that has it
+/// in full. Returns the backup's address with the bytes.
+async fn read_backup(
+    node: &Node,
+    holder: &str,
+    chunk_name: &str,
+    expected_len: u64,
+) -> Option<(String, Vec<u8>)> {
+    for backup in backup_holders(node, holder).await {
+        if node.node_status(&backup).await == Some(NodeStatus::Dead) {
+            continue;
+        }
+        let addr = node.peer_addr(&backup);
+        match request_backup_chunk_from(node, &addr, chunk_name).await {
+            Ok((data, _)) if data.len() as u64 == expected_len => return Some((addr, data)),
+            Ok((data, _)) => {
+                tracing::debug!(node = %node.port, backup_node = %addr, chunk = %chunk_name, got = data.len(), expected = expected_len, "Backup copy missing or short");
+            }
+            Err(e) => {
+                tracing::debug!(node = %node.port, backup_node = %addr, chunk = %chunk_name, error = ?e, "Backup read failed");
+            }
+        }
+    }
+    None
+}
+
+/// Port of the node holding a stored chunk (by its on-disk name), going by
+/// the file tags
+pub(crate) async fn chunk_holder_of(node: &Node, chunk_file: &str) -> Option<String> {
+    let tags = node.file_tags.read().await.clone();
+    for (name, tag) in &tags {
+        if let Some(index) = (0..tag.parts).find(|i| {
+            sanitize_filename(&chunk_file_name(tag.chunk_set(name), *i, tag.parts)) == chunk_file
+        }) {
+            return tag_holders(node, name, tag)
+                .await
+                .get(index as usize)
+                .cloned()
+                .flatten();
+        }
+    }
+    None
+}
+
+/// Port of the node whose next hop is `port`. Asked in order: the node
+/// itself (`NODE PREV`), the topology map, then every other live node for its
+/// next hop, so a dead node's predecessor is found without a walk having run.
+async fn predecessor_of(node: &Node, port: &str) -> Option<String> {
+    // 1. The node knows its predecessor, once announced
+    if port == port_str(&node.port) {
+        if let Some(prev) = node.get_prev().await {
+            return Some(port_str(&prev).to_string());
+        }
+    } else if let Some(prev) = node.query_neighbor(port, true).await {
+        return Some(prev);
     }
 
-    // Find the key whose value is `my_port`
-    let predecessor_port = topology
+    // 2. The last walk
+    let from_topology = node
+        .topology_map
+        .read()
+        .await
         .iter()
-        .find(|(_from, to)| port_str(to) == my_port)
+        .find(|(_from, to)| port_str(to) == port)
         .map(|(from, _to)| from.clone());
+    if from_topology.is_some() {
+        return from_topology;
+    }
 
-    predecessor_port.map(|port| format!("{}:{}", host_of(&node.port), port))
+    // 3. Whoever has it as next hop
+    let own = port_str(&node.port);
+    if node
+        .get_next()
+        .await
+        .is_some_and(|next| port_str(&next) == port)
+    {
+        return Some(own.to_string());
+    }
+    for (peer, member) in node.get_network_nodes_entries().await.0 {
+        if peer == port || peer == own || member.status == NodeStatus::Dead {
+            continue;
+        }
+        if node.query_neighbor(&peer, false).await.as_deref() == Some(port) {
+            return Some(peer);
+        }
+    }
+    None
 }
 
-/// Helper to send the notification
-async fn notify_predecessor(node: Arc<Node>, chunk_name: String) {
-    if let Some(pred_addr) = get_predecessor_addr(&node).await {
+/// Asks every node keeping this node's backups (see [`backup_holders`]) to
+/// copy a newly stored chunk
+async fn notify_backup_holders(node: Arc<Node>, chunk_name: String) {
+    if node.replication == 0 {
+        return; // Backups disabled
+    }
+
+    let holders = backup_holders(&node, port_str(&node.port)).await;
+    if holders.is_empty() {
+        tracing::warn!(node = %node.port, chunk = %chunk_name, "No predecessor known. Cannot send backup notification.");
+        return;
+    }
+    // Older nodes fetch from their next hop, which is right while backups stay on the predecessor
+    let line = if node.ring_supports(Feature::FailureDomains).await {
+        format!(
+            "FILE NOTIFY-CHUNK-SAVED {} {}\n",
+            node.port,
+            protocol::encode_name(&chunk_name)
+        )
+    } else {
+        format!(
+            "FILE NOTIFY-CHUNK-SAVED {}\n",
+            protocol::encode_name(&chunk_name)
+        )
+    };
+
+    for backup in holders {
+        let backup_addr = node.peer_addr(&backup);
         tracing::info!(
             node = %node.port,
-            predecessor = %pred_addr,
+            backup_node = %backup_addr,
             chunk = %chunk_name,
-            "Notifying predecessor of new chunk."
+            "Notifying backup holder of new chunk."
         );
 
         // Try to send the notification
-        match TcpStream::connect(&pred_addr).await {
+        match node.connect(&backup_addr).await {
             Ok(mut stream) => {
-                let line = format!("FILE NOTIFY-CHUNK-SAVED {}\n", chunk_name);
                 if let Err(e) = stream.write_all(line.as_bytes()).await {
-                    tracing::warn!(node = %node.port, target = %pred_addr, error = ?e, "Failed to send chunk notification.");
+                    tracing::warn!(node = %node.port, target = %backup_addr, error = ?e, "Failed to send chunk notification.");
                 }
                 // No need to wait for an ACK
                 let _ = stream.shutdown().await;
             }
             Err(e) => {
-                tracing::warn!(node = %node.port, target = %pred_addr, error = ?e, "Failed to connect to predecessor for notification.");
+                tracing::warn!(node = %node.port, target = %backup_addr, error = ?e, "Failed to connect to backup holder for notification.");
             }
         }
-    } else {
-        tracing::warn!(node = %node.port, chunk = %chunk_name, "No predecessor found in topology map. Cannot send backup notification.");
     }
 }
 
 /// Helper function for the backup process
-async fn request_chunk_for_backup(addr: &str, name: &str) -> Result<Vec<u8>, AnyErr> {
-    let mut s = TcpStream::connect(addr).await?;
+pub(crate) async fn request_chunk_for_backup(
+    node: &Node,
+    addr: &str,
+    name: &str,
+) -> Result<Vec<u8>, AnyErr> {
+    let mut s = node.connect(addr).await?;
 
     // 1. Send the request
-    s.write_all(format!("FILE GET-CHUNK-FOR-BACKUP {}\n", name).as_bytes())
-        .await?;
+    s.write_all(
+        format!(
+            "FILE GET-CHUNK-FOR-BACKUP {}\n",
+            protocol::encode_name(name)
+        )
+        .as_bytes(),
+    )
+    .await?;
 
     // 2. Read the 8-byte size prefix
     let mut size_buf = [0u8; 8];
@@ -1512,7 +5418,8 @@ async fn request_chunk_for_backup(addr: &str, name: &str) -> Result<Vec<u8>, Any
     }
 
     // 3. Read exactly 'size' bytes of data
-    let mut buf = vec![0u8; size as usize];
+    check_chunk_size(node, size).await?;
+    let mut buf = vec![0u8; usize::try_from(size)?];
     s.read_exact(&mut buf).await?;
 
     // The TcpStream 's' is dropped here when the task ends,
@@ -1523,10 +5430,19 @@ async fn request_chunk_for_backup(addr: &str, name: &str) -> Result<Vec<u8>, Any
 /* --- Gossip and Healing Functions --- */
 
 /// The main gossip loop task
-async fn spawn_gossip_loop(node: Arc<Node>) {
+pub(crate) async fn spawn_gossip_loop(node: Arc<Node>) {
+    let mut schedule = GossipSchedule::new(node.addr.port() as u64);
+    let mut peer_probes = PeerProbes::default();
     loop {
-        // Wait for the gossip interval
-        tokio::time::sleep(node.gossip_interval).await;
+        // Wait for the next round (settings are re-read every round, they can be hot-reloaded)
+        let settings = node.settings().await;
+        if settings.gossip_interval.is_zero() {
+            // Gossip is paused; check again later in case it is re-enabled
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            continue;
+        }
+        let delay = schedule.next_delay(settings.gossip_interval, settings.gossip_jitter);
+        tokio::time::sleep(delay).await;
 
         // Find out who to ping
         let Some(next_addr) = node.get_next().await else {
@@ -1536,14 +5452,38 @@ async fn spawn_gossip_loop(node: Arc<Node>) {
             );
             continue;
         };
-
-        tracing::debug!(node = %node.port, target = %next_addr, "Gossip: Sending PING");
-        match check_node_health(node.clone(), &next_addr).await {
-            Ok(_) => {
-                tracing::debug!(node = %node.port, from = %next_addr, "Gossip: Received PONG");
+        schedule.set_target(&next_addr);
+
+        tracing::debug!(node = %node.port, target = %next_addr, delay_ms = delay.as_millis() as u64, "Gossip: Sending PING");
+        let probes = probe_random_peers(&node, &mut schedule, &mut peer_probes, &next_addr).await;
+        let health = check_node_health(node.clone(), &next_addr).await;
+        note_reach(&node, port_str(&next_addr), health.is_ok()).await;
+        match health {
+            Ok(rtt) => {
+                tracing::debug!(node = %node.port, from = %next_addr, rtt_us = rtt.as_micros() as u64, "Gossip: Received PONG");
+                let suspect = track_latency(&node, port_str(&next_addr), rtt).await;
+                schedule.on_success(suspect);
+            }
+            Err(e) if !schedule.on_failure() => {
+                // One miss may just be a slow node: probe again soon before declaring it dead
+                tracing::warn!(
+                    node = %node.port,
+                    target = %next_addr,
+                    error = ?e,
+                    "Gossip: Health check failed, probing again"
+                );
+            }
+            Err(e) if node.is_degraded() => {
+                // Cut off from most of the ring: the next hop may well be alive on the other side
+                tracing::warn!(
+                    node = %node.port,
+                    target = %next_addr,
+                    error = ?e,
+                    "Gossip: Health check failed, not healing while a partition is suspected"
+                );
             }
             Err(e) => {
-                // Health check failed, start the healing process
+                // Health check failed repeatedly, start the healing process
                 tracing::error!(
                     node = %node.port,
                     target = %next_addr,
@@ -1561,43 +5501,335 @@ async fn spawn_gossip_loop(node: Arc<Node>) {
                 });
             }
         }
+        settle_probes(&node, &mut peer_probes, probes).await;
+    }
+}
+
+/// Starts pinging up to [`GOSSIP_FANOUT`] random peers other than the
+/// next hop, in the background: they check more of the ring than the next
+/// hop alone, and carry netmap changes further than the ring's order would
+async fn probe_random_peers(
+    node: &Arc<Node>,
+    schedule: &mut GossipSchedule,
+    peer_probes: &mut PeerProbes,
+    next_addr: &str,
+) -> Vec<(String, JoinHandle<Result<Duration, AnyErr>>)> {
+    let peers = node.probe_peers(port_str(next_addr)).await;
+    peer_probes.retain(&peers);
+    schedule
+        .pick(peers, GOSSIP_FANOUT)
+        .into_iter()
+        .map(|port| {
+            let node = node.clone();
+            let addr = node.peer_addr(&port);
+            let probe = tokio::spawn(async move { check_node_health(node, &addr).await });
+            (port, probe)
+        })
+        .collect()
+}
+
+/// Waits for the pings of [`probe_random_peers`]. A peer that missed
+/// [`crate::gossip::DEAD_AFTER`] of them in a row is marked `Dead` and the news
+/// spread; its predecessor, whose own pings fail as well, respawns it. Nobody
+/// is marked while a partition is suspected.
+async fn settle_probes(
+    node: &Arc<Node>,
+    peer_probes: &mut PeerProbes,
+    probes: Vec<(String, JoinHandle<Result<Duration, AnyErr>>)>,
+) {
+    for (port, probe) in probes {
+        let error = match probe.await {
+            Ok(Ok(_)) => {
+                note_reach(node, &port, true).await;
+                peer_probes.on_success(&port);
+                continue;
+            }
+            Ok(Err(e)) => e.to_string(),
+            Err(e) => e.to_string(),
+        };
+        note_reach(node, &port, false).await;
+        if !peer_probes.on_failure(&port) || node.is_degraded() {
+            tracing::debug!(node = %node.port, peer = %port, error = %error, "Gossip: Random peer missed a ping");
+            continue;
+        }
+        if node.node_status(&port).await == Some(crate::NodeStatus::Dead) {
+            continue;
+        }
+        tracing::warn!(node = %node.port, peer = %port, error = %error, "Gossip: Random peer unreachable, marking Dead");
+        node.update_node_status(port.clone(), crate::NodeStatus::Dead)
+            .await;
+        node.emit(NodeEvent::PeerDead { port });
+        node.broadcast_netmap_update().await;
     }
 }
 
-/// Tries to send "NODE PING" and expects "PONG"
-async fn check_node_health(_node: Arc<Node>, addr: &str) -> Result<(), AnyErr> {
-    let timeout = Duration::from_secs(2);
+/// Sends "NODE PING <seq> <sent_at_us>" and expects the matching "PONG".
+/// Returns the round-trip time.
+///
+/// A peer that speaks gossip gets the netmap changes waiting to be spread,
+/// and answers with its own. With the UDP heartbeat enabled the ping goes to
+/// the peer's heartbeat socket first when there are none, so a data port busy
+/// with transfers does not make the peer look dead; the TCP ping is only
+/// tried when that gets no answer.
+async fn check_node_health(node: Arc<Node>, addr: &str) -> Result<Duration, AnyErr> {
+    let timeout = node.settings().await.health_timeout;
+    let seq = node.next_ping_seq();
+    let rumors = if node.peer_supports(port_str(addr), Feature::Gossip).await {
+        Some(gossip_payload(node.take_rumors()))
+    } else {
+        None
+    };
+
+    // 1. Dedicated heartbeat, if enabled and there is nothing to gossip
+    if node.udp_heartbeat && rumors.as_ref().is_none_or(|r| r == "-") {
+        match heartbeat::ping(addr, seq, timeout).await {
+            Ok(rtt) => return Ok(rtt),
+            Err(e) => {
+                tracing::debug!(node = %node.port, target = %addr, error = ?e, "Heartbeat: No UDP answer, falling back to TCP ping")
+            }
+        }
+    }
+
+    // 2. Ping over the data port
+    let sent_at = latency::now_micros();
+    let started = Instant::now();
 
     // Connect with timeout
-    let mut stream = tokio::time::timeout(timeout, TcpStream::connect(addr)).await??;
-    stream.write_all(b"NODE PING\n").await?;
+    let mut stream = tokio::time::timeout(timeout, node.connect(addr)).await??;
+    let line = match &rumors {
+        Some(rumors) => format!("NODE PING {} {} {}\n", seq, sent_at, rumors),
+        None => format!("NODE PING {} {}\n", seq, sent_at),
+    };
+    stream.write_all(line.as_bytes()).await?;
 
     // Read response with timeout
     let mut reader = BufReader::new(stream);
     let mut buf = String::new();
     tokio::time::timeout(timeout, reader.read_line(&mut buf)).await??;
 
-    if buf.trim().eq_ignore_ascii_case("PONG") {
-        Ok(())
+    // A peer that does not echo: time it locally
+    let rtt = latency::pong_rtt(&buf, seq)?.unwrap_or_else(|| started.elapsed());
+    if rumors.is_some()
+        && let Some(news) = buf.split_whitespace().nth(3)
+    {
+        take_gossip(node, &protocol::parse_gossip(news)).await;
+    }
+    Ok(rtt)
+}
+
+/// Records a ping RTT and flips the neighbor between `Alive` and `Suspect`
+/// when its latency spikes for several rounds in a row, or settles again.
+/// Returns whether the neighbor is suspect.
+async fn track_latency(node: &Node, port: &str, rtt: Duration) -> bool {
+    let (was_suspect, suspect) = node.record_rtt(port, rtt);
+    if suspect == was_suspect || node.node_status(port).await == Some(crate::NodeStatus::Dead) {
+        return suspect;
+    }
+
+    if suspect {
+        tracing::warn!(node = %node.port, peer = %port, rtt_ms = rtt.as_millis() as u64, "Gossip: Neighbor latency spiking, marking Suspect");
+        node.update_node_status(port.to_string(), crate::NodeStatus::Suspect)
+            .await;
+        node.emit(NodeEvent::PeerSuspect {
+            port: port.to_string(),
+            rtt_ms: rtt.as_millis() as u64,
+        });
+    } else {
+        tracing::info!(node = %node.port, peer = %port, rtt_ms = rtt.as_millis() as u64, "Gossip: Neighbor latency back to normal");
+        node.update_node_status(port.to_string(), crate::NodeStatus::Alive)
+            .await;
+    }
+    node.broadcast_netmap_update().await;
+    suspect
+}
+
+/* --- Reconciliation --- */
+
+/// Notes the outcome of a ping of `port` for partition detection, and
+/// reconciles with the ring in the background when it ends degraded mode
+async fn note_reach(node: &Arc<Node>, port: &str, reached: bool) {
+    if node.record_reach(port, reached).await {
+        let node = node.clone();
+        tokio::spawn(async move { reconcile_ring(&node).await });
+    }
+}
+
+/// Reconciles this node's state with every other node of its netmap, `Dead`
+/// ones included, as after a partition (see [`crate::reconcile`]). The
+/// merged file tags then go to every node.
+async fn reconcile_ring(node: &Arc<Node>) -> Reconciled {
+    let netmap = node.get_network_nodes_entries().await;
+    let own = node.addr.port().to_string();
+    let line = format!("NODE RECONCILE {}", entries_or_dash(netmap.to_string()));
+    let mut outcome = Reconciled::default();
+    let mut ports = Vec::new();
+    for port in netmap.ports().filter(|port| **port != own) {
+        ports.push(port.clone());
+        let lines = match query_lines(node, &node.peer_addr(port), &line).await {
+            Ok(lines) => lines,
+            Err(e) => {
+                tracing::debug!(node = %node.port, peer = %port, error = ?e, "Reconcile: Peer did not answer");
+                continue;
+            }
+        };
+        outcome.peers += 1;
+        for line in lines {
+            if let Some(entries) = line.strip_prefix("NETMAP ") {
+                take_gossip(node.clone(), &protocol::parse_gossip(entries)).await;
+            } else if let Some(entries) = line.strip_prefix("TAGS ")
+                && entries != "-"
+                && let Ok(tags) = entries.parse()
+            {
+                merge_file_tags(node, tags, &mut outcome).await;
+            }
+        }
+    }
+
+    if outcome.adopted + outcome.conflicts > 0 {
+        let tags = node.get_file_tags_entries().await;
+        node.broadcast(Broadcast::Tags, ports, format!("FILE TAGS-SET {}\n", tags))
+            .await;
+    }
+    node.broadcast_netmap_update().await;
+    tracing::info!(
+        node = %node.port,
+        peers = outcome.peers,
+        adopted = outcome.adopted,
+        conflicts = outcome.conflicts,
+        "Reconcile: Merged state with the ring"
+    );
+    outcome
+}
+
+/// Merges a peer's file tags into this node's: files it alone knows are
+/// taken, and of two versions of a name with different content one keeps
+/// the name and the other is set aside (see [`crate::reconcile`])
+async fn merge_file_tags(node: &Node, theirs: FileTags, outcome: &mut Reconciled) {
+    migrate::rename_legacy_chunks(node, &theirs).await;
+    for (name, tag) in theirs.0 {
+        if let Some(version) = tag.version {
+            node.observe(version.at);
+        }
+        let ours = node.file_tags.read().await.get(&name).cloned();
+        let Some(ours) = ours else {
+            node.insert_file_tag(&name, tag).await;
+            outcome.adopted += 1;
+            continue;
+        };
+        if ours == tag {
+            continue;
+        }
+        let (keep, other) = if reconcile::keeps_name(&name, &ours, &tag) {
+            (ours.clone(), tag)
+        } else {
+            (tag, ours.clone())
+        };
+        if keep != ours {
+            node.insert_file_tag(&name, keep.clone()).await;
+            outcome.adopted += 1;
+        }
+        if same_content(node, &name, &keep, &other).await {
+            continue;
+        }
+        let copy = reconcile::conflict_name(&name, &other);
+        if node.file_tags.read().await.contains_key(&copy) {
+            continue;
+        }
+        tracing::warn!(node = %node.port, file = %name, version = %copy, "Reconcile: Conflicting versions of a file, setting one aside");
+        node.insert_file_tag(&copy, reconcile::set_aside(&name, other))
+            .await;
+        outcome.conflicts += 1;
+    }
+}
+
+/// Whether two tags of the file `name` hold the same bytes: the same ring,
+/// or the same hash of their chunk hashes. Content that cannot be hashed
+/// counts as different.
+async fn same_content(node: &Node, name: &str, a: &node::FileTag, b: &node::FileTag) -> bool {
+    if a.size != b.size || a.ring != b.ring {
+        return false;
+    }
+    if a.ring.is_some() {
+        return true;
+    }
+    let a = file_manifest(node, name.to_string(), a)
+        .await
+        .content_hash();
+    let b = file_manifest(node, name.to_string(), b)
+        .await
+        .content_hash();
+    a.is_some() && a == b
+}
+
+/// Netmap or tags entries as sent on one line, `-` for none
+fn entries_or_dash(entries: String) -> String {
+    if entries.is_empty() {
+        "-".to_string()
     } else {
-        Err("invalid PONG response".into())
+        entries
+    }
+}
+
+/* --- Config reload --- */
+
+/// Re-reads the node's config file and applies every setting in it, or
+/// none when one is refused. Returns how many settings were applied.
+pub(crate) async fn reload_config_file(node: &Node) -> Result<usize, AnyErr> {
+    let Some(path) = &node.config_file else {
+        return Ok(0);
+    };
+    let contents = fs::read_to_string(path).await?;
+    let entries = crate::config::parse_config_file(&contents)?;
+    // A bad entry leaves every setting as it was
+    node.set_settings(&entries).await?;
+    tracing::info!(node = %node.port, file = %path.display(), settings = entries.len(), "Config file applied");
+    Ok(entries.len())
+}
+
+/// Reloads the config file every time the process receives SIGHUP.
+#[cfg(unix)]
+pub(crate) async fn spawn_config_reload_loop(node: Arc<Node>) {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(s) => s,
+        Err(e) => {
+            tracing::warn!(node = %node.port, error = ?e, "Could not install SIGHUP handler");
+            return;
+        }
+    };
+    while hangups.recv().await.is_some() {
+        tracing::info!(node = %node.port, "SIGHUP received, reloading config");
+        if let Err(e) = reload_config_file(&node).await {
+            tracing::error!(node = %node.port, error = ?e, "Config reload failed");
+        }
     }
 }
 
+/// Longest wait before respawning a node that keeps dying, however large
+/// `respawn-backoff` is
+const MAX_RESPAWN_BACKOFF: Duration = Duration::from_secs(3600);
+
 /// The healing process workflow
 async fn handle_node_death(node: Arc<Node>, dead_addr: String) -> Result<(), AnyErr> {
+    if node.is_degraded() {
+        return Err("partition suspected, not healing".into());
+    }
     tracing::info!(
         node = %node.port,
         dead_node = %dead_addr,
         "Starting healing process"
     );
     let dead_port = port_str(&dead_addr).to_string();
-    let dead_host = host_of(&dead_addr);
-    let full_dead_addr = format!("{}:{}", dead_host, dead_port);
+    let full_dead_addr = join_host_port(host_str(&dead_addr), &dead_port);
+    let was_alive = node.node_status(&dead_port).await != Some(crate::NodeStatus::Dead);
 
     // 1. Update local map to Dead
     node.update_node_status(dead_port.clone(), crate::NodeStatus::Dead)
         .await;
+    node.emit(NodeEvent::PeerDead {
+        port: dead_port.clone(),
+    });
 
     // 2. Broadcast change
     tracing::info!(
@@ -1608,7 +5840,54 @@ async fn handle_node_death(node: Arc<Node>, dead_addr: String) -> Result<(), Any
     );
     node.broadcast_netmap_update().await;
 
-    // 3. Start a new process
+    // 3. Apply the respawn policy and alert operators
+    let settings = node.settings().await;
+    let previous = node.recent_respawns(&dead_port).await;
+    let action = if settings.respawn == RespawnMode::Never {
+        RespawnAction::Disabled
+    } else if settings.max_respawns > 0 && previous >= settings.max_respawns {
+        RespawnAction::LimitReached
+    } else {
+        RespawnAction::Respawning
+    };
+
+    // Only alert once for a node that stays dead, but on every respawn
+    if was_alive || action == RespawnAction::Respawning {
+        alert::fire(
+            &node.death_hooks,
+            DeathAlert {
+                dead_node: full_dead_addr.clone(),
+                detected_by: node.port.clone(),
+                respawns: previous + u32::from(action == RespawnAction::Respawning),
+                action,
+            },
+        );
+    }
+
+    if action != RespawnAction::Respawning {
+        tracing::warn!(
+            node = %node.port,
+            dead_node = %full_dead_addr,
+            respawns = previous,
+            action = action.as_str(),
+            "Not respawning dead node"
+        );
+        return Err(format!("respawn of {} skipped: {}", full_dead_addr, action.as_str()).into());
+    }
+
+    // Back off when the same node keeps dying
+    let attempt = node.record_respawn(&dead_port).await;
+    if attempt > 1 {
+        let delay = settings
+            .respawn_backoff
+            .checked_mul(2u32.pow((attempt - 2).min(6)))
+            .unwrap_or(MAX_RESPAWN_BACKOFF)
+            .min(MAX_RESPAWN_BACKOFF);
+        tracing::info!(node = %node.port, respawn_addr = %full_dead_addr, attempt, delay = ?delay, "Backing off before respawn");
+        sleep(delay).await;
+    }
+
+    // 4. Start a new process
     tracing::info!(node = %node.port, respawn_addr = %full_dead_addr, "Respawning node");
     let exe = current_exe()?;
 
@@ -1617,7 +5896,61 @@ async fn handle_node_death(node: Arc<Node>, dead_addr: String) -> Result<(), Any
         .arg("--addr")
         .arg(&full_dead_addr)
         .arg("--wait-time")
-        .arg(node.gossip_interval.as_millis().to_string());
+        .arg(settings.gossip_interval.as_millis().to_string())
+        .arg("--respawn")
+        .arg(settings.respawn.to_string())
+        .arg("--max-respawns")
+        .arg(settings.max_respawns.to_string())
+        .arg("--respawn-backoff")
+        .arg(settings.respawn_backoff.as_millis().to_string())
+        .args(node.tcp.cli_args())
+        .args(node.logging.cli_args());
+    if node.udp_heartbeat {
+        cmd.arg("--udp-heartbeat");
+    }
+    // The respawned node keeps the labels the dead one announced
+    let labels = node.node_labels(&dead_port).await;
+    if !labels.is_empty() {
+        cmd.arg("--label").arg(labels.to_string());
+    }
+    cmd.arg("--failure-domain")
+        .arg(&node.failure_domain)
+        .arg("--replication")
+        .arg(node.replication.to_string());
+    if let Some(id) = &node.ring_id {
+        cmd.arg("--ring-id").arg(id);
+    }
+    if let Some(relay) = labels.0.get(RELAY_LABEL) {
+        cmd.arg("--relay").arg(node.peer_addr(relay));
+    }
+    // A token file is passed on so `NODE KEYS ROTATE` keeps reading it
+    match node
+        .cluster_token
+        .as_ref()
+        .map(|token| (token, token.source()))
+    {
+        Some((_, Some(SecretSource::File(path)))) => {
+            cmd.arg("--cluster-token-file").arg(path);
+        }
+        Some((token, _)) => {
+            cmd.env(CLUSTER_TOKEN_ENV, token.export().expose());
+        }
+        None => {}
+    }
+    if let Some(access) = &node.access {
+        cmd.arg("--access-file").arg(&access.path);
+    }
+    // Its data directory is the one next to this node's, named after its
+    // port, as `set-network` lays them out
+    if let Some(data_root) = node.data_dir.parent() {
+        cmd.arg("--data-dir").arg(data_root.join(&dead_port));
+    }
+    if let Some(hook) = &node.death_hooks.exec {
+        cmd.arg("--on-death-exec").arg(hook);
+    }
+    if let Some(url) = &node.death_hooks.webhook {
+        cmd.arg("--on-death-webhook").arg(url);
+    }
 
     // Spawn the child and detach it
     let _ = cmd.spawn()?;
@@ -1628,22 +5961,27 @@ async fn handle_node_death(node: Arc<Node>, dead_addr: String) -> Result<(), Any
         respawn_addr = %full_dead_addr,
         "Waiting for respawned node to listen..."
     );
-    wait_until_listening(dead_host, dead_port.parse()?, Duration::from_secs(10)).await?;
+    wait_until_listening(
+        host_str(&dead_addr),
+        dead_port.parse()?,
+        Duration::from_secs(10),
+    )
+    .await?;
     tracing::info!(node = %node.port, respawn_addr = %full_dead_addr, "Respawned node is up.");
 
-    // 4. Update map to Alive
+    // 5. Update map to Alive
     node.update_node_status(dead_port.clone(), crate::NodeStatus::Alive)
         .await;
 
-    // 5. Share shared data
+    // 6. Share shared data
     tracing::info!(
         node = %node.port,
         target_node = %full_dead_addr,
         "Sharing network data with new node"
     );
-    share_data_with_new_node(&node, &full_dead_addr).await?;
+    share_data_with_new_node(&node, &dead_port, &full_dead_addr).await?;
 
-    // 6. Broadcast change (Alive)
+    // 7. Broadcast change (Alive)
     tracing::info!(
         node = %node.port,
         target_node = %dead_port,
@@ -1651,6 +5989,7 @@ async fn handle_node_death(node: Arc<Node>, dead_addr: String) -> Result<(), Any
         "Broadcasting node status"
     );
     node.broadcast_netmap_update().await;
+    node.emit(NodeEvent::PeerHealed { port: dead_port });
 
     tracing::info!(
         node = %node.port, healed_node = %full_dead_addr, "Healing process complete."
@@ -1658,45 +5997,58 @@ async fn handle_node_death(node: Arc<Node>, dead_addr: String) -> Result<(), Any
     Ok(())
 }
 
-/// Sends all shared state to a newly spawned node
-async fn share_data_with_new_node(node: &Node, new_node_addr: &str) -> Result<(), AnyErr> {
+/// Sends all shared state to a newly spawned node. The netmap, topology and
+/// file tags go through its outbox, so they are sent again if it does not
+/// acknowledge them.
+async fn share_data_with_new_node(
+    node: &Node,
+    new_node_port: &str,
+    new_node_addr: &str,
+) -> Result<(), AnyErr> {
     let timeout = Duration::from_millis(500);
-
-    // Share NETMAP
-    let entries = node.get_network_nodes_entries().await;
-    let mut s_netmap = tokio::time::timeout(timeout, TcpStream::connect(new_node_addr)).await??;
-    s_netmap
-        .write_all(format!("NETMAP SET {}\n", entries).as_bytes())
-        .await?;
-    s_netmap.shutdown().await?;
-
-    // Share TOPOLOGY
-    let history = node.get_topology_history().await;
+    let port = vec![new_node_port.to_string()];
+    let mut shared = vec![(
+        Broadcast::Netmap,
+        format!("NETMAP SET {}\n", node.get_network_nodes_entries().await),
+    )];
+    let (epoch, history) = node.get_topology_history().await;
     if !history.is_empty() {
-        let mut s_topo = tokio::time::timeout(timeout, TcpStream::connect(new_node_addr)).await??;
-        s_topo
-            .write_all(format!("TOPOLOGY SET {}\n", history).as_bytes())
-            .await?;
-        s_topo.shutdown().await?;
+        let payload = node.topology_payload(epoch, &history).await;
+        shared.push((Broadcast::Topology, format!("TOPOLOGY SET {}\n", payload)));
     }
-
-    // Share FILE TAGS
     let tags_entries = node.get_file_tags_entries().await;
     if !tags_entries.is_empty() {
-        let mut s_tags = tokio::time::timeout(timeout, TcpStream::connect(new_node_addr)).await??;
-        s_tags
-            .write_all(format!("FILE TAGS-SET {}\n", tags_entries).as_bytes())
+        shared.push((Broadcast::Tags, format!("FILE TAGS-SET {}\n", tags_entries)));
+    }
+    for (kind, line) in shared {
+        if !node.broadcast(kind, port.clone(), line).await.is_empty() {
+            return Err(format!("{} did not acknowledge {:?}", new_node_addr, kind).into());
+        }
+    }
+
+    // Share FEDERATION links
+    let links = node.federation().await;
+    if !links.is_empty() {
+        let mut s_links = tokio::time::timeout(timeout, node.connect(new_node_addr)).await??;
+        s_links
+            .write_all(format!("FEDERATION SET {}\n", links).as_bytes())
             .await?;
-        s_tags.shutdown().await?;
+        s_links.shutdown().await?;
     }
 
+    // Share its PREV hop: the healer is the node whose next it is
+    let mut s_prev = tokio::time::timeout(timeout, node.connect(new_node_addr)).await??;
+    s_prev
+        .write_all(format!("NODE PREV {}\n", node.port).as_bytes())
+        .await?;
+    s_prev.shutdown().await?;
+
     // Share its NEXT hop
     let next_hop_port = node.get_next_for_node(port_str(new_node_addr)).await;
     if let Some(port) = next_hop_port {
         // Reconstruct the full address from the healing node's host and the port
-        let host = host_of(&node.port);
-        let next_addr = format!("{}:{}", host, port);
-        let mut s_next = tokio::time::timeout(timeout, TcpStream::connect(new_node_addr)).await??;
+        let next_addr = node.peer_addr(port);
+        let mut s_next = tokio::time::timeout(timeout, node.connect(new_node_addr)).await??;
         s_next
             .write_all(format!("NODE NEXT {}\n", next_addr).as_bytes())
             .await?;
@@ -1716,7 +6068,7 @@ async fn wait_until_listening(
     deadline: Duration,
 ) -> Result<(), Box<dyn Error + Send + Sync>> {
     let start = Instant::now();
-    let addr = format!("{}:{}", host, port);
+    let addr = join_host_port(host, port);
     loop {
         match TcpStream::connect(&addr).await {
             Ok(_) => return Ok(()),
@@ -1729,3 +6081,129 @@ async fn wait_until_listening(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{NodeConfig, auth::ClusterToken};
+
+    #[tokio::test]
+    async fn bad_config_line_changes_nothing() {
+        let dir = std::env::temp_dir().join(format!("ouroboros-reload-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("node.conf");
+        std::fs::write(
+            &file,
+            "max-relays = 7\nchunk-cache-size = lots\nmax-transfers = 9\n",
+        )
+        .unwrap();
+        let config = NodeConfig {
+            data_dir: Some(dir.clone()),
+            config_file: Some(file),
+            ..NodeConfig::default()
+        };
+        let node = Node::new("127.0.0.1:7000".parse().unwrap(), &config);
+        let before = node.settings().await;
+
+        assert!(reload_config_file(&node).await.is_err());
+        assert_eq!(node.settings().await, before);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    /// Sends `lines` on a new connection to `addr`, and reads every answer
+    async fn exchange(addr: SocketAddr, lines: &str) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(lines.as_bytes()).await.unwrap();
+        stream.shutdown().await.unwrap();
+        let mut answer = String::new();
+        stream.read_to_string(&mut answer).await.unwrap();
+        answer
+    }
+
+    #[tokio::test]
+    async fn only_the_cluster_token_escapes_the_limits() {
+        let dir = std::env::temp_dir().join(format!("ouroboros-limits-{}", std::process::id()));
+        let config = NodeConfig {
+            data_dir: Some(dir.clone()),
+            cluster_token: Some(ClusterToken::new("ring-key").unwrap()),
+            ..NodeConfig::default()
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let node = Node::new(addr.into(), &config);
+        node.set_setting("conn-rate", "1").await.unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, peer)) = listener.accept().await {
+                tokio::spawn(handle_client(Arc::clone(&node), stream, peer));
+            }
+        });
+
+        // A client on the ring's own host is limited like any other
+        assert!(!exchange(addr, "NOPE\n").await.contains("RATE-LIMITED"));
+        assert!(exchange(addr, "NOPE\n").await.contains("RATE-LIMITED"));
+        // while a peer sending the cluster token is not
+        for _ in 0..3 {
+            let answer = exchange(addr, "NODE AUTH ring-key\nNOPE\n").await;
+            assert!(
+                answer.starts_with("ERR ") && !answer.contains("RATE-LIMITED"),
+                "{}",
+                answer
+            );
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn stored_names() {
+        let cases = [
+            ("a.txt", Some("a.txt")),
+            ("dir/a.txt", Some("dir/a.txt")),
+            ("/etc/passwd", Some("etc/passwd")),
+            ("./a/./b", Some("a/b")),
+            ("dir/", Some("dir")),
+            ("a MODE version", Some("a MODE version")),
+            ("", None),
+            (" ", None),
+            (".", None),
+            ("..", None),
+            ("/", None),
+            ("a/..", None),
+            ("../x", None),
+            ("a/../../x", None),
+        ];
+        for (raw, expected) in cases {
+            assert_eq!(stored_name(raw).as_deref(), expected, "{:?}", raw);
+        }
+    }
+
+    #[test]
+    fn stored_names_stay_in_content_dir() {
+        let content = Path::new("/data/7000/content");
+        let hostile = [
+            "..",
+            "../x",
+            "a/..",
+            "a/../..",
+            "/",
+            "//",
+            "/..",
+            "./..",
+            "dir/",
+            "..\\x",
+            "%2E%2E",
+            ".. ",
+            "x/../../../etc/passwd",
+            "a\0b",
+        ];
+        for raw in hostile {
+            let Some(name) = stored_name(raw) else {
+                continue;
+            };
+            for file in [name.clone(), chunk_file_name(&name, 0, 1)] {
+                let path = content.join(sanitize_filename(&file));
+                assert!(path.file_name().is_some(), "{:?} names {:?}", raw, path);
+                assert_eq!(path.parent(), Some(content), "{:?} names {:?}", raw, path);
+            }
+        }
+    }
+}
@@ -0,0 +1,181 @@
+//! Crash recovery of the chunks a node stages for pushes.
+//!
+//! A chunk received for a push is written to `staging/` and only moved into
+//! `content/` once the push commits. The commit first writes a commit record
+//! next to the staged chunk, `<staged name>.commit`, and syncs it to disk:
+//! from then on the push counts as committed on this node. The chunk is then
+//! renamed into `content/` and recorded in the manifest, and the record is
+//! removed.
+//!
+//! When the node starts, a record still in `staging/` marks a commit a crash
+//! interrupted: it is finished (the chunk moved into place, if it is not
+//! already, and its manifest entry written). Anything else in `staging/`
+//! belongs to a push that never committed, or to a write that never
+//! finished, and is deleted.
+
+use crate::{
+    NodeEvent,
+    manifest::{self, ChunkEntry},
+    node::Node,
+    protocol::decode_name,
+};
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use tokio::{fs, io::AsyncWriteExt};
+
+/// Suffix of a commit record, after the name of its staged chunk
+pub const COMMIT_SUFFIX: &str = ".commit";
+
+/// A committed chunk not yet in place: its name in `content/` and its
+/// manifest entry, as two lines
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommitRecord {
+    /// Sanitized chunk name, as stored on disk
+    pub chunk: String,
+    pub entry: ChunkEntry,
+}
+
+impl fmt::Display for CommitRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.chunk)?;
+        writeln!(f, "{}", self.entry)
+    }
+}
+
+impl FromStr for CommitRecord {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut lines = s.lines();
+        let (Some(chunk), Some(entry)) = (lines.next(), lines.next()) else {
+            return Err("truncated commit record".into());
+        };
+        if chunk.is_empty() {
+            return Err("commit record names no chunk".into());
+        }
+        Ok(CommitRecord {
+            chunk: chunk.to_string(),
+            entry: entry.parse()?,
+        })
+    }
+}
+
+/// Commit record of the chunk staged at `staged`
+pub fn record_path(staged: &Path) -> PathBuf {
+    let mut path = staged.as_os_str().to_owned();
+    path.push(COMMIT_SUFFIX);
+    PathBuf::from(path)
+}
+
+/// Writes the commit record of the chunk staged at `staged` and syncs it, so
+/// the commit survives a crash
+pub async fn write_record(staged: &Path, record: &CommitRecord) -> io::Result<()> {
+    let mut file = fs::File::create(record_path(staged)).await?;
+    file.write_all(record.to_string().as_bytes()).await?;
+    file.sync_all().await
+}
+
+/// Drops the commit record of the chunk staged at `staged`, once the chunk
+/// is in place
+pub async fn remove_record(staged: &Path) {
+    let _ = fs::remove_file(record_path(staged)).await;
+}
+
+/// What the startup pass over `staging/` did
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Recovered {
+    /// Commits finished
+    pub completed: usize,
+    /// Committed chunks found neither staged nor in place
+    pub lost: usize,
+    /// Files of uncommitted pushes and unfinished writes deleted
+    pub discarded: usize,
+}
+
+impl fmt::Display for Recovered {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "STAGING completed={} lost={} discarded={}",
+            self.completed, self.lost, self.discarded
+        )
+    }
+}
+
+/// Finishes the commits a crash interrupted and deletes everything else in
+/// `staging/` (see the module docs). Returns the chunks now in `content/`,
+/// for their backup holders to copy. Runs once, before the node serves.
+pub(crate) async fn recover(node: &Node) -> io::Result<(Recovered, Vec<String>)> {
+    let dir = node.staging_dir();
+    fs::create_dir_all(&dir).await?;
+    let mut outcome = Recovered::default();
+    let mut stored = Vec::new();
+
+    // 1. Finish the commits that have a record
+    let mut records = Vec::new();
+    let mut entries = fs::read_dir(&dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if let Some(staged) = name.strip_suffix(COMMIT_SUFFIX) {
+            records.push((dir.join(staged), entry.path()));
+        }
+    }
+    for (staged, path) in records {
+        let record: CommitRecord = match fs::read_to_string(&path).await {
+            Ok(text) => match text.parse() {
+                Ok(record) => record,
+                // Not synced yet when the node stopped: the push never committed here
+                Err(e) => {
+                    tracing::warn!(node = %node.port, record = %path.display(), error = %e, "Staging: Ignoring unreadable commit record");
+                    continue;
+                }
+            },
+            Err(_) => continue,
+        };
+        let target = node.content_dir().join(&record.chunk);
+        let chunk = decode_name(&record.chunk);
+        if fs::try_exists(&staged).await.unwrap_or(false) {
+            node.packs.of("content").remove(&record.chunk);
+            fs::rename(&staged, &target).await?;
+        } else if !fs::try_exists(&target).await.unwrap_or(false) {
+            outcome.lost += 1;
+            tracing::error!(node = %node.port, chunk = %chunk, "Staging: Committed chunk is gone");
+            fs::remove_file(&path).await?;
+            continue;
+        }
+        manifest::record(&node.manifest_dir("content"), &record.chunk, record.entry).await?;
+        fs::remove_file(&path).await?;
+        outcome.completed += 1;
+        tracing::info!(node = %node.port, chunk = %chunk, "Staging: Finished interrupted commit");
+        node.emit(NodeEvent::ChunkStored {
+            name: chunk.clone(),
+            backup: false,
+        });
+        stored.push(chunk);
+    }
+
+    // 2. Whatever is left never committed
+    let mut entries = fs::read_dir(&dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        let removed = if entry.file_type().await?.is_dir() {
+            fs::remove_dir_all(&path).await
+        } else {
+            fs::remove_file(&path).await
+        };
+        match removed {
+            Ok(()) => outcome.discarded += 1,
+            Err(e) => {
+                tracing::warn!(node = %node.port, path = %path.display(), error = ?e, "Staging: Could not delete leftover")
+            }
+        }
+    }
+
+    if outcome != Recovered::default() {
+        tracing::info!(node = %node.port, recovered = %outcome, "Staging: Recovered after restart");
+    }
+    Ok((outcome, stored))
+}
@@ -0,0 +1,140 @@
+//! `CLUSTER STATS`: one report on the whole ring, gathered by the node asked.
+//!
+//! That node asks every netmap member for its disk usage (`NODE DU`) and
+//! counters (`NODE METRICS`) at once, and adds what it knows itself: the
+//! files of the ring and the status of each member. Members that do not
+//! answer are listed as unreachable and count for nothing in the totals.
+
+use crate::{NodeStatus, usage::DirUsage};
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// One netmap member of a [`ClusterStatsView`]
+#[derive(Debug, Clone, Serialize)]
+pub struct MemberStats {
+    pub port: String,
+    /// Status in the reporting node's netmap
+    pub status: NodeStatus,
+    /// Whether the member answered; the figures below are `None` otherwise
+    pub reachable: bool,
+    /// Bytes of its data directory
+    pub disk_bytes: Option<u64>,
+    /// Chunks in its `content/` and `backup/` directories
+    pub chunks: Option<u64>,
+    pub backups: Option<u64>,
+    /// Client transfers running on it
+    pub transfers_active: Option<u64>,
+    /// Mean gossip ping round trip to its neighbors, in milliseconds
+    pub hop_latency_ms: Option<f64>,
+}
+
+impl MemberStats {
+    /// A member that could not be asked
+    pub fn unreachable(port: String, status: NodeStatus) -> Self {
+        Self {
+            port,
+            status,
+            reachable: false,
+            disk_bytes: None,
+            chunks: None,
+            backups: None,
+            transfers_active: None,
+            hop_latency_ms: None,
+        }
+    }
+
+    /// A member from its `NODE DU` and `NODE METRICS` answers
+    pub fn from_answers(
+        port: String,
+        status: NodeStatus,
+        du: &[String],
+        metrics: &[String],
+    ) -> Result<Self, String> {
+        let dirs = du
+            .iter()
+            .map(|l| l.parse::<DirUsage>())
+            .collect::<Result<Vec<_>, _>>()?;
+        let files_in = |dir: &str| dirs.iter().find(|d| d.dir == dir).map_or(0, |d| d.files);
+        let metrics: HashMap<&str, u64> = metrics
+            .iter()
+            .filter_map(|l| l.split_once('='))
+            .filter_map(|(key, value)| Some((key, value.parse().ok()?)))
+            .collect();
+        Ok(Self {
+            port,
+            status,
+            reachable: true,
+            disk_bytes: dirs.iter().find(|d| d.dir == "TOTAL").map(|d| d.bytes),
+            chunks: Some(files_in("content")),
+            backups: Some(files_in("backup")),
+            transfers_active: metrics.get("transfers_active").copied(),
+            hop_latency_ms: mean_rtt_ms(&metrics),
+        })
+    }
+}
+
+/// Mean of the `ping_rtt_mean_us.<port>` counters of neighbors with samples
+fn mean_rtt_ms(metrics: &HashMap<&str, u64>) -> Option<f64> {
+    let rtts: Vec<u64> = metrics
+        .iter()
+        .filter_map(|(key, us)| {
+            let port = key.strip_prefix("ping_rtt_mean_us.")?;
+            let samples = metrics.get(format!("ping_samples.{}", port).as_str())?;
+            (*samples > 0).then_some(*us)
+        })
+        .collect();
+    if rtts.is_empty() {
+        return None;
+    }
+    Some(rtts.iter().sum::<u64>() as f64 / rtts.len() as f64 / 1000.0)
+}
+
+/// `CLUSTER STATS` document
+#[derive(Debug, Clone, Serialize)]
+pub struct ClusterStatsView {
+    /// Port of the node that gathered the report
+    pub node: String,
+    /// Members of the netmap, and those of them it marks `Dead`
+    pub nodes: usize,
+    pub dead: Vec<String>,
+    /// Members that did not answer
+    pub unreachable: Vec<String>,
+    /// Files of the ring and their total length
+    pub files: usize,
+    pub logical_bytes: u64,
+    /// Sums over the members that answered
+    pub disk_bytes: u64,
+    pub chunks: u64,
+    pub backups: u64,
+    /// Mean of the members' hop latencies, in milliseconds
+    pub avg_hop_latency_ms: Option<f64>,
+    pub members: Vec<MemberStats>,
+}
+
+impl ClusterStatsView {
+    pub fn new(node: String, files: usize, logical_bytes: u64, members: Vec<MemberStats>) -> Self {
+        let ports = |f: fn(&MemberStats) -> bool| {
+            members
+                .iter()
+                .filter(|m| f(m))
+                .map(|m| m.port.clone())
+                .collect()
+        };
+        let sum = |f: fn(&MemberStats) -> Option<u64>| members.iter().filter_map(f).sum();
+        let latencies: Vec<f64> = members.iter().filter_map(|m| m.hop_latency_ms).collect();
+        Self {
+            node,
+            nodes: members.len(),
+            dead: ports(|m| m.status == NodeStatus::Dead),
+            unreachable: ports(|m| !m.reachable),
+            files,
+            logical_bytes,
+            disk_bytes: sum(|m| m.disk_bytes),
+            chunks: sum(|m| m.chunks),
+            backups: sum(|m| m.backups),
+            avg_hop_latency_ms: (!latencies.is_empty())
+                .then(|| latencies.iter().sum::<f64>() / latencies.len() as f64),
+            members,
+        }
+    }
+}
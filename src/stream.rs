@@ -0,0 +1,366 @@
+//! Streamed pulls and pushes, for applications that reach a ring over TCP.
+//!
+//! [`Client::pull_stream`] returns a file as an [`AsyncRead`] and
+//! [`Client::push_stream`] stores one read from an [`AsyncRead`], so an application can pipe data
+//! to and from the ring (a socket, a file, a decompressor) without holding
+//! the whole file in memory.
+//!
+//! A streamed pull reads the file's manifest (`FILE MANIFEST`), then each
+//! chunk in turn straight from its holder (`FILE GET-CHUNK`), passing its
+//! bytes on as they arrive. A chunk its holder cannot serve, or stops
+//! serving, is read on from its backup holders (`FILE GET-BACKUP-CHUNK`,
+//! found with `FILE INFO`), skipping what was already passed on. Each chunk
+//! is checked against its hash once all its bytes have gone by: a chunk that
+//! does not match, or cannot be read from anywhere, makes the next read fail
+//! instead of ending the stream early.
+
+use crate::{
+    Client,
+    addr::{host_str, join_host_port},
+    auth::ClusterToken,
+    checksum::{Digest, Sha256},
+    node::{ChunkView, FileManifestView},
+    protocol::{PushMode, encode_name},
+    pull::{fetch_backups, open_chunk},
+};
+use std::{
+    collections::HashMap,
+    error::Error,
+    io,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, ready},
+    time::Duration,
+};
+use tokio::{
+    io::{
+        AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader,
+        DuplexStream, ReadBuf,
+    },
+    net::TcpStream,
+};
+
+type AnyErr = Box<dyn Error + Send + Sync>;
+
+/// Bytes buffered between the task reading a pulled file and its reader
+const STREAM_BUF: usize = 256 * 1024;
+
+/// How long a holder may take to answer, and then between two reads
+const READ_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How long the node may take to answer a push once its body is sent: it
+/// distributes the chunks meanwhile
+const PUSH_ANSWER_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// A file being pulled by [`Client::pull_stream`]: its bytes in order, as they
+/// arrive from the chunk holders
+pub struct PullStream {
+    pipe: DuplexStream,
+    size: u64,
+    read: u64,
+    /// Why the task reading the chunks stopped, if it failed
+    failure: Arc<Mutex<Option<String>>>,
+}
+
+impl PullStream {
+    /// Size of the file, from its manifest
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+impl AsyncRead for PullStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        ready!(Pin::new(&mut self.pipe).poll_read(cx, buf))?;
+        let n = (buf.filled().len() - before) as u64;
+        self.read += n;
+        if n == 0 && buf.remaining() > 0 {
+            // The task is gone: it either sent the whole file or failed
+            let failure = self
+                .failure
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .take();
+            if let Some(failure) = failure {
+                return Poll::Ready(Err(io::Error::other(failure)));
+            }
+            if self.read < self.size {
+                return Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!("pull ended after {} of {} bytes", self.read, self.size),
+                )));
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Pulls the file `manifest` describes, read through the node at `addr`, as
+/// a stream (see the module docs). Holders are reached on the same host, at
+/// the ports the manifest names.
+pub(crate) fn pull_stream(
+    addr: &str,
+    manifest: FileManifestView,
+    token: Option<ClusterToken>,
+) -> PullStream {
+    let (pipe, mut writer) = tokio::io::duplex(STREAM_BUF);
+    let failure = Arc::new(Mutex::new(None));
+    let stream = PullStream {
+        pipe,
+        size: manifest.size,
+        read: 0,
+        failure: Arc::clone(&failure),
+    };
+
+    let addr = addr.to_string();
+    tokio::spawn(async move {
+        if let Err(e) = send_chunks(&addr, &manifest, token.as_ref(), &mut writer).await {
+            tracing::debug!(file = %manifest.name, error = %e, "Streamed pull failed");
+            *failure.lock().unwrap_or_else(|e| e.into_inner()) = Some(e.to_string());
+        }
+        // Only now may the reader see the end, with the failure recorded
+        drop(writer);
+    });
+    stream
+}
+
+/// Writes every chunk of `manifest` to `writer`, in order
+async fn send_chunks(
+    addr: &str,
+    manifest: &FileManifestView,
+    token: Option<&ClusterToken>,
+    writer: &mut DuplexStream,
+) -> Result<(), AnyErr> {
+    let host = host_str(addr);
+    // Read from `FILE INFO` when a chunk first needs them
+    let mut backups: Option<HashMap<u32, Vec<String>>> = None;
+
+    for chunk in &manifest.chunks {
+        let expected: Option<Digest> = match &chunk.sha256 {
+            Some(hash) => Some(hash.parse()?),
+            None => None,
+        };
+        let mut progress = ChunkProgress::default();
+        let mut failures = Vec::new();
+
+        // 1. From its holder
+        match &chunk.node {
+            Some(holder) => {
+                let holder_addr = join_host_port(host, holder);
+                if let Err(e) = send_chunk(
+                    &holder_addr,
+                    "GET-CHUNK",
+                    chunk,
+                    token,
+                    &mut progress,
+                    writer,
+                )
+                .await?
+                {
+                    failures.push(format!("holder {}: {}", holder, e));
+                }
+            }
+            None => failures.push("no holder known".to_string()),
+        }
+
+        // 2. Then on from its backup holders
+        if progress.sent < chunk.size {
+            if backups.is_none() {
+                backups = Some(fetch_backups(addr, &manifest.name, token).await?);
+            }
+            let ports = backups.as_ref().and_then(|b| b.get(&chunk.index));
+            for port in ports.into_iter().flatten() {
+                let backup_addr = join_host_port(host, port);
+                match send_chunk(
+                    &backup_addr,
+                    "GET-BACKUP-CHUNK",
+                    chunk,
+                    token,
+                    &mut progress,
+                    writer,
+                )
+                .await?
+                {
+                    Ok(()) => break,
+                    Err(e) => failures.push(format!("backup {}: {}", port, e)),
+                }
+            }
+        }
+        if progress.sent < chunk.size {
+            return Err(format!(
+                "chunk {}/{} ({}) could not be read after {} of {} bytes: {}",
+                chunk.index + 1,
+                manifest.parts,
+                chunk.name,
+                progress.sent,
+                chunk.size,
+                failures.join("; ")
+            )
+            .into());
+        }
+
+        // 3. Every byte of it is out: check them
+        if let Some(expected) = expected
+            && progress.hasher.finalize() != expected
+        {
+            return Err(format!(
+                "chunk {}/{} ({}) does not match its hash",
+                chunk.index + 1,
+                manifest.parts,
+                chunk.name
+            )
+            .into());
+        }
+    }
+    writer.shutdown().await?;
+    Ok(())
+}
+
+/// How much of a chunk was passed on, and the hash of those bytes
+#[derive(Default)]
+struct ChunkProgress {
+    sent: u64,
+    hasher: Sha256,
+}
+
+/// Sends `FILE <command> <chunk>` to `addr`, presenting `token`, and writes the chunk's bytes
+/// past those already sent to `writer`. The outer error is `writer`'s: the
+/// stream's reader is gone. The inner one is the source's, which another
+/// copy of the chunk may make up for.
+async fn send_chunk(
+    addr: &str,
+    command: &str,
+    chunk: &ChunkView,
+    token: Option<&ClusterToken>,
+    progress: &mut ChunkProgress,
+    writer: &mut DuplexStream,
+) -> io::Result<Result<(), String>> {
+    let opened = tokio::time::timeout(READ_TIMEOUT, open_chunk(addr, command, chunk, token)).await;
+    let mut reader = match opened {
+        Ok(Ok((reader, size))) if size == chunk.size => reader,
+        Ok(Ok((_, size))) => return Ok(Err(format!("{} of {} bytes", size, chunk.size))),
+        Ok(Err(e)) => return Ok(Err(e.to_string())),
+        Err(_) => return Ok(Err("timed out".to_string())),
+    };
+
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut at = 0u64;
+    while progress.sent < chunk.size {
+        // Skip what an earlier copy already sent
+        let want = if at < progress.sent {
+            (progress.sent - at).min(buf.len() as u64)
+        } else {
+            (chunk.size - at).min(buf.len() as u64)
+        } as usize;
+        let n = match tokio::time::timeout(READ_TIMEOUT, reader.read(&mut buf[..want])).await {
+            Ok(Ok(0)) => return Ok(Err(format!("{} of {} bytes", at, chunk.size))),
+            Ok(Ok(n)) => n,
+            Ok(Err(e)) => return Ok(Err(e.to_string())),
+            Err(_) => return Ok(Err("timed out".to_string())),
+        };
+        if at >= progress.sent {
+            writer.write_all(&buf[..n]).await?;
+            progress.hasher.update(&buf[..n]);
+            progress.sent += n as u64;
+        }
+        at += n as u64;
+    }
+    Ok(Ok(()))
+}
+
+/// Stores `size` bytes read from `reader` as `name` through the node
+/// `stream` is connected to, as `FILE PUSH` would in `mode`. Fails if
+/// `reader` ends before `size` bytes, or the node refuses the push.
+pub(crate) async fn push_stream<R>(
+    stream: TcpStream,
+    name: &str,
+    size: u64,
+    mode: PushMode,
+    reader: R,
+) -> Result<(), AnyErr>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut stream = BufReader::new(stream);
+    let mut header = format!("FILE PUSH {} {}", size, encode_name(name));
+    if mode != PushMode::default() {
+        header.push_str(&format!(" MODE {}", mode));
+    }
+    header.push('\n');
+    stream.get_mut().write_all(header.as_bytes()).await?;
+    let sent = tokio::io::copy(&mut reader.take(size), stream.get_mut()).await?;
+    if sent < size {
+        // Closing the connection leaves the node short of the body: it stores nothing
+        return Err(format!("reader ended after {} of {} bytes", sent, size).into());
+    }
+
+    tokio::time::timeout(PUSH_ANSWER_TIMEOUT, read_push_answer(&mut stream))
+        .await
+        .map_err(|_| -> AnyErr { "FILE PUSH timed out".into() })?
+}
+
+/// Reads a push's answer up to `OK`; an `ERR` line is an error
+async fn read_push_answer(stream: &mut BufReader<TcpStream>) -> Result<(), AnyErr> {
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if stream.read_line(&mut line).await? == 0 {
+            return Err("connection closed before OK".into());
+        }
+        let l = line.trim();
+        if l == "OK" {
+            return Ok(());
+        }
+        if let Some(err) = l.strip_prefix("ERR") {
+            return Err(err.trim().to_string().into());
+        }
+    }
+}
+
+/// A push's body, written by the application: see [`Client::push_writer`]
+pub struct PushWriter {
+    pipe: DuplexStream,
+    done: tokio::task::JoinHandle<Result<(), AnyErr>>,
+}
+
+impl PushWriter {
+    /// Waits for the node's answer once every byte is written. Fails if
+    /// fewer than the announced size were, or the node refused the push.
+    pub async fn finish(mut self) -> Result<(), AnyErr> {
+        self.pipe.shutdown().await?;
+        drop(self.pipe);
+        self.done.await?
+    }
+}
+
+impl AsyncWrite for PushWriter {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.pipe).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.pipe).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.pipe).poll_shutdown(cx)
+    }
+}
+
+/// Starts pushing `size` bytes as `name` through `client`, the bytes being
+/// written to the returned [`PushWriter`] rather than read from a reader
+pub(crate) fn push_writer(client: Client, name: &str, size: u64) -> PushWriter {
+    let (pipe, reader) = tokio::io::duplex(STREAM_BUF);
+    let name = name.to_string();
+    let done = tokio::spawn(async move { client.push_stream(&name, size, reader).await });
+    PushWriter { pipe, done }
+}
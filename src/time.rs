@@ -0,0 +1,115 @@
+//! Hybrid logical clocks, ordering control-plane changes across nodes.
+//!
+//! Wall clocks of different machines drift apart, so "which happened last"
+//! cannot be read from them alone. A hybrid logical clock (HLC) follows the
+//! wall clock but never runs backwards, and moves past every timestamp it is
+//! shown: a change stamped after hearing of another always sorts after it,
+//! whatever the two machines' clocks say.
+//!
+//! Each node keeps one [`Clock`] and stamps with it:
+//!
+//! - events it publishes (see [`crate::Node::subscribe`]),
+//! - file tag versions (see [`crate::schema::TagVersion`]), where the later
+//!   of two concurrent writes keeps the name,
+//! - topology epochs, minted past the newest epoch the walk saw.
+//!
+//! Timestamps received in tag versions and topology epochs are observed, so
+//! clocks of nodes talking to each other stay close.
+
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    str::FromStr,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Bits of a [`Timestamp`] holding the logical counter
+const LOGICAL_BITS: u32 = 16;
+
+/// A peer's timestamp further ahead of this node's wall clock than this is
+/// logged: one of the two clocks is off
+pub const MAX_DRIFT: Duration = Duration::from_secs(60);
+
+/// A hybrid logical timestamp: milliseconds since the UNIX epoch in the high
+/// 48 bits, a logical counter in the low 16, so it orders and travels as a
+/// plain `u64` (topology epochs are one). 0 stands for "not stamped".
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+pub struct Timestamp(pub u64);
+
+impl Timestamp {
+    /// Wall-clock part, in milliseconds since the UNIX epoch
+    pub fn wall_ms(&self) -> u64 {
+        self.0 >> LOGICAL_BITS
+    }
+
+    /// Changes stamped in the same millisecond
+    pub fn logical(&self) -> u64 {
+        self.0 & ((1 << LOGICAL_BITS) - 1)
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// The current wall-clock time, with a logical counter of 0
+    fn physical() -> Self {
+        let ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64);
+        Timestamp(ms << LOGICAL_BITS)
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for Timestamp {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse()
+            .map(Timestamp)
+            .map_err(|_| format!("invalid timestamp '{}'", s))
+    }
+}
+
+/// A node's hybrid logical clock
+#[derive(Debug, Default)]
+pub struct Clock {
+    last: AtomicU64,
+}
+
+impl Clock {
+    /// A timestamp newer than any this clock gave or observed
+    pub fn now(&self) -> Timestamp {
+        self.tick(Timestamp::default())
+    }
+
+    /// Moves this clock past `seen`, a timestamp received from another node
+    /// (or an epoch to outdate), and returns a timestamp newer than both
+    pub fn observe(&self, seen: Timestamp) -> Timestamp {
+        let physical = Timestamp::physical();
+        let drift = Duration::from_millis(seen.wall_ms().saturating_sub(physical.wall_ms()));
+        if drift > MAX_DRIFT {
+            tracing::warn!(seen = %seen, drift_ms = drift.as_millis() as u64, "Clock: Timestamp far ahead of the local clock");
+        }
+        self.tick(seen)
+    }
+
+    fn tick(&self, seen: Timestamp) -> Timestamp {
+        let physical = Timestamp::physical().0;
+        let next = |last: u64| last.max(seen.0).saturating_add(1).max(physical);
+        let last = self
+            .last
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |last| Some(next(last)))
+            .unwrap_or_else(|last| last);
+        Timestamp(next(last))
+    }
+}
@@ -0,0 +1,157 @@
+//! `RING TRACE`: a traceroute for the ring.
+//!
+//! The asked node sends `RING TRACE-HOP <dest> <path>` to its next hop, which
+//! answers `TRACE <port>` at once: the time until that answer is the hop's
+//! latency. The next hop then carries the trace on to its own next hop the
+//! same way, and relays what comes back, so the asked node receives one
+//! `HOP` line per hop as the trace moves on, and an `END` line.
+//!
+//! The trace ends at the destination, or where it cannot go on: a node
+//! without a next hop, a next hop that does not answer, a next hop already on
+//! the path (the ring loops without passing the destination), or after
+//! [`MAX_HOPS`] hops.
+
+use std::{fmt, str::FromStr, time::Duration};
+
+/// Hops after which a trace gives up
+pub const MAX_HOPS: usize = 64;
+
+/// How long a node waits for its next hop to answer
+pub const HOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a node waits for each line it relays back: longer than
+/// [`HOP_TIMEOUT`], which its next hop may spend waiting on its own
+pub const RELAY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A node the trace reached: `HOP <port> rtt_us=<n>[ via=<relay>]`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hop {
+    pub port: String,
+    /// From sending `RING TRACE-HOP` to the node's answer
+    pub rtt: Duration,
+    /// Relay the node was reached through (see [`crate::relay`])
+    pub via: Option<String>,
+}
+
+impl fmt::Display for Hop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "HOP {} rtt_us={}", self.port, self.rtt.as_micros())?;
+        if let Some(relay) = &self.via {
+            write!(f, " via={}", relay)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Hop {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid trace hop '{}'", s);
+        let mut words = s.split_whitespace();
+        if words.next() != Some("HOP") {
+            return Err(invalid());
+        }
+        let port = words.next().ok_or_else(invalid)?.to_string();
+        let rtt = words
+            .next()
+            .and_then(|w| w.strip_prefix("rtt_us="))
+            .and_then(|us| us.parse().ok())
+            .map(Duration::from_micros)
+            .ok_or_else(invalid)?;
+        let via = words
+            .next()
+            .and_then(|w| w.strip_prefix("via="))
+            .map(str::to_string);
+        Ok(Hop { port, rtt, via })
+    }
+}
+
+/// How a trace ended: `END <outcome>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum End {
+    /// The destination answered
+    Reached,
+    /// `at` has no next hop
+    NoNext { at: String },
+    /// `at`'s next hop, `next`, did not answer
+    Unreachable { at: String, next: String },
+    /// The last node's next hop, `at`, is already on the path
+    Loop { at: String },
+    /// [`MAX_HOPS`] hops without reaching the destination
+    MaxHops,
+    /// The trace stopped answering after `after`
+    Lost { after: String },
+}
+
+impl fmt::Display for End {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            End::Reached => f.write_str("REACHED"),
+            End::NoNext { at } => write!(f, "NO-NEXT {}", at),
+            End::Unreachable { at, next } => write!(f, "UNREACHABLE {}->{}", at, next),
+            End::Loop { at } => write!(f, "LOOP {}", at),
+            End::MaxHops => write!(f, "MAX-HOPS {}", MAX_HOPS),
+            End::Lost { after } => write!(f, "LOST {}", after),
+        }
+    }
+}
+
+impl FromStr for End {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid trace end '{}'", s);
+        let (outcome, arg) = s.split_once(' ').unwrap_or((s, ""));
+        let arg = arg.trim().to_string();
+        match outcome {
+            "REACHED" => Ok(End::Reached),
+            "NO-NEXT" => Ok(End::NoNext { at: arg }),
+            "UNREACHABLE" => {
+                let (at, next) = arg.split_once("->").ok_or_else(invalid)?;
+                Ok(End::Unreachable {
+                    at: at.to_string(),
+                    next: next.to_string(),
+                })
+            }
+            "LOOP" => Ok(End::Loop { at: arg }),
+            "MAX-HOPS" => Ok(End::MaxHops),
+            "LOST" => Ok(End::Lost { after: arg }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// A finished trace, as answered to `RING TRACE`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trace {
+    pub dest: String,
+    pub hops: Vec<Hop>,
+    pub end: End,
+}
+
+impl Trace {
+    /// Sum of the hop latencies
+    pub fn rtt(&self) -> Duration {
+        self.hops.iter().map(|hop| hop.rtt).sum()
+    }
+}
+
+/// `RING TRACE` reply lines, before the final `OK`: the hops, numbered from
+/// 1, then `TRACE <outcome> dest=<port> hops=<n> rtt_us=<total>`
+impl fmt::Display for Trace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, hop) in self.hops.iter().enumerate() {
+            let hop = hop.to_string();
+            writeln!(f, "HOP {} {}", i + 1, &hop["HOP ".len()..])?;
+        }
+        writeln!(
+            f,
+            "TRACE {} dest={} hops={} rtt_us={}",
+            self.end,
+            self.dest,
+            self.hops.len(),
+            self.rtt().as_micros()
+        )
+    }
+}
@@ -0,0 +1,237 @@
+//! In-flight push and pull operations.
+//!
+//! Every push or pull a node drives is registered under its token, so
+//! `FILE PROGRESS` can report how far it got and `FILE CANCEL` can stop it.
+
+use serde::Serialize;
+use std::{
+    fmt, io,
+    pin::Pin,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    task::{Context, Poll},
+};
+use tokio::{
+    io::{AsyncRead, ReadBuf},
+    sync::Notify,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransferKind {
+    Push,
+    Pull,
+}
+
+impl fmt::Display for TransferKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Push => "push",
+            Self::Pull => "pull",
+        })
+    }
+}
+
+impl std::str::FromStr for TransferKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "push" => Ok(Self::Push),
+            "pull" => Ok(Self::Pull),
+            _ => Err(format!("unknown transfer kind '{}'", s)),
+        }
+    }
+}
+
+/// Shared state of one transfer
+#[derive(Debug)]
+pub struct Transfer {
+    pub token: String,
+    pub kind: TransferKind,
+    pub name: String,
+    pub total: u64,
+    bytes: AtomicU64,
+    route: Vec<(u64, String)>,
+    cancelled: AtomicBool,
+    cancel: Notify,
+}
+
+/// Point-in-time view of a transfer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TransferProgress {
+    pub token: String,
+    pub kind: TransferKind,
+    pub name: String,
+    pub bytes: u64,
+    pub total: u64,
+    pub hop: String,
+}
+
+impl fmt::Display for TransferProgress {
+    /// `TRANSFER <token> <kind> <name> bytes=<n> total=<n> hop=<port>`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "TRANSFER {} {} {} bytes={} total={} hop={}",
+            self.token, self.kind, self.name, self.bytes, self.total, self.hop
+        )
+    }
+}
+
+impl std::str::FromStr for TransferProgress {
+    type Err = String;
+
+    /// Parses the line written by `Display` (names may contain spaces)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bad = || format!("malformed TRANSFER line '{}'", s.trim());
+        let mut right = s.trim().rsplitn(4, ' ');
+        let hop = right
+            .next()
+            .and_then(|v| v.strip_prefix("hop="))
+            .ok_or_else(bad)?;
+        let total = right
+            .next()
+            .and_then(|v| v.strip_prefix("total="))
+            .ok_or_else(bad)?;
+        let bytes = right
+            .next()
+            .and_then(|v| v.strip_prefix("bytes="))
+            .ok_or_else(bad)?;
+        let mut left = right.next().ok_or_else(bad)?.splitn(4, ' ');
+        let (Some("TRANSFER"), Some(token), Some(kind), Some(name)) =
+            (left.next(), left.next(), left.next(), left.next())
+        else {
+            return Err(bad());
+        };
+        Ok(TransferProgress {
+            token: token.to_string(),
+            kind: kind.parse()?,
+            name: name.to_string(),
+            bytes: bytes.parse().map_err(|_| bad())?,
+            total: total.parse().map_err(|_| bad())?,
+            hop: hop.to_string(),
+        })
+    }
+}
+
+/// Node address embedded in a token made by [`crate::node::Node::make_file_token`]
+/// (`file-<addr>-<n>`), i.e. the node tracking that transfer
+pub fn token_node(token: &str) -> Option<&str> {
+    token
+        .strip_prefix("file-")
+        .and_then(|rest| rest.rsplit_once('-'))
+        .map(|(addr, _)| addr)
+}
+
+/// Whether `token` could have been made by
+/// [`crate::node::Node::make_file_token`]: letters, digits and the characters
+/// of a node address only. Such a token is safe in a file name.
+pub fn is_file_token(token: &str) -> bool {
+    !token.is_empty()
+        && !token.contains("..")
+        && token
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b':' | b'[' | b']'))
+}
+
+impl Transfer {
+    /// `route` lists the node handling each stretch of the data, as
+    /// `(end offset, port)` pairs in order.
+    pub fn new(
+        token: String,
+        kind: TransferKind,
+        name: String,
+        total: u64,
+        route: Vec<(u64, String)>,
+    ) -> Self {
+        Self {
+            token,
+            kind,
+            name,
+            total,
+            bytes: AtomicU64::new(0),
+            route,
+            cancelled: AtomicBool::new(false),
+            cancel: Notify::new(),
+        }
+    }
+
+    /// Records `n` more bytes moved
+    pub fn advance(&self, n: u64) {
+        self.bytes.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    /// Node currently storing or serving data, from the bytes moved so far
+    pub fn hop(&self) -> &str {
+        let bytes = self.bytes();
+        self.route
+            .iter()
+            .find(|(end, _)| bytes < *end)
+            .or(self.route.last())
+            .map_or("?", |(_, port)| port.as_str())
+    }
+
+    pub fn progress(&self) -> TransferProgress {
+        TransferProgress {
+            token: self.token.clone(),
+            kind: self.kind,
+            name: self.name.clone(),
+            bytes: self.bytes(),
+            total: self.total,
+            hop: self.hop().to_string(),
+        }
+    }
+
+    /// Asks the transfer to stop; whoever drives it notices at the next await point
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+        self.cancel.notify_waiters();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Resolves once [`Transfer::cancel`] has been called
+    pub async fn cancelled(&self) {
+        let notified = self.cancel.notified();
+        if self.is_cancelled() {
+            return;
+        }
+        notified.await;
+    }
+}
+
+/// Reader that counts every byte read towards a transfer's progress
+pub struct ProgressReader<R> {
+    inner: R,
+    transfer: Arc<Transfer>,
+}
+
+impl<R> ProgressReader<R> {
+    pub fn new(inner: R, transfer: Arc<Transfer>) -> Self {
+        Self { inner, transfer }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for ProgressReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = poll {
+            self.transfer.advance((buf.filled().len() - before) as u64);
+        }
+        poll
+    }
+}
@@ -0,0 +1,215 @@
+//! Storage usage: what a node's data directory takes on disk (`NODE DU`), and
+//! what files take across the ring (`FILE DU`).
+//!
+//! A file's logical size is its length; its physical size is every stored
+//! copy of its chunks, content and backups, as their holders report them. A
+//! copy made with `FILE COPY` shares the chunks of its source and takes no
+//! physical bytes of its own. Versions (`<name>.v<N>`) are files of their own,
+//! also counted apart so their weight shows.
+
+use crate::protocol::encode_name;
+use serde::Serialize;
+use std::{fmt, io, path::Path, str::FromStr};
+use tokio::fs;
+
+/// Bytes and files under one entry of a node's data directory
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct DirUsage {
+    /// Directory name (`content`, `backup`, ...), or `.` for the files at the
+    /// top of the data directory
+    pub dir: String,
+    pub bytes: u64,
+    pub files: u64,
+}
+
+impl fmt::Display for DirUsage {
+    /// `<dir> bytes=<n> files=<n>`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} bytes={} files={}", self.dir, self.bytes, self.files)
+    }
+}
+
+impl FromStr for DirUsage {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid usage line '{}'", s.trim());
+        let mut words = s.split_whitespace();
+        let dir = words.next().ok_or_else(invalid)?.to_string();
+        let mut field = |key: &str| {
+            words
+                .next()
+                .and_then(|w| w.strip_prefix(key))
+                .and_then(|n| n.parse().ok())
+                .ok_or_else(invalid)
+        };
+        Ok(Self {
+            bytes: field("bytes=")?,
+            files: field("files=")?,
+            dir,
+        })
+    }
+}
+
+/// `NODE DU JSON` document
+#[derive(Debug, Clone, Serialize)]
+pub struct NodeUsageView {
+    /// Port of the node reporting its usage
+    pub node: String,
+    pub bytes: u64,
+    pub files: u64,
+    pub dirs: Vec<DirUsage>,
+}
+
+impl NodeUsageView {
+    pub fn new(node: String, dirs: Vec<DirUsage>) -> Self {
+        Self {
+            node,
+            bytes: dirs.iter().map(|d| d.bytes).sum(),
+            files: dirs.iter().map(|d| d.files).sum(),
+            dirs,
+        }
+    }
+}
+
+/// One file of a [`UsageView`]
+#[derive(Debug, Clone, Serialize)]
+pub struct FileUsageView {
+    pub name: String,
+    /// Length of the file
+    pub logical: u64,
+    /// Bytes of every stored copy of its chunks, content and backups
+    pub physical: u64,
+    /// Chunks found on their holder, out of `parts`
+    pub chunks: u32,
+    pub parts: u32,
+    /// Backup copies found
+    pub backups: u32,
+    /// File whose chunks this one shares (`FILE COPY`), and which holds the
+    /// physical bytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shared: Option<String>,
+}
+
+impl fmt::Display for FileUsageView {
+    /// `<name> logical=<n> physical=<n> chunks=<n>/<parts> backups=<n> [shared=<name>]`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} logical={} physical={} chunks={}/{} backups={}",
+            encode_name(&self.name),
+            self.logical,
+            self.physical,
+            self.chunks,
+            self.parts,
+            self.backups
+        )?;
+        if let Some(shared) = &self.shared {
+            write!(f, " shared={}", encode_name(shared))?;
+        }
+        Ok(())
+    }
+}
+
+/// `FILE DU JSON` document
+#[derive(Debug, Clone, Serialize)]
+pub struct UsageView {
+    /// Port of the node that gathered the report
+    pub node: String,
+    /// Name prefix the files were picked by (empty for all)
+    pub prefix: String,
+    pub files: usize,
+    /// Files that are a version (`<name>.v<N>`) of another stored file
+    pub versions: usize,
+    pub logical: u64,
+    pub physical: u64,
+    pub entries: Vec<FileUsageView>,
+}
+
+impl UsageView {
+    pub fn new(node: String, prefix: String, entries: Vec<FileUsageView>) -> Self {
+        let names: Vec<&str> = entries.iter().map(|e| e.name.as_str()).collect();
+        let versions = names
+            .iter()
+            .filter(|name| version_of(name).is_some_and(|base| names.contains(&base)))
+            .count();
+        Self {
+            node,
+            prefix,
+            files: entries.len(),
+            versions,
+            logical: entries.iter().map(|e| e.logical).sum(),
+            physical: entries.iter().map(|e| e.physical).sum(),
+            entries,
+        }
+    }
+
+    /// `TOTAL files=<n> versions=<n> logical=<n> physical=<n>`
+    pub fn total_line(&self) -> String {
+        format!(
+            "TOTAL files={} versions={} logical={} physical={}",
+            self.files, self.versions, self.logical, self.physical
+        )
+    }
+}
+
+/// Name `name` is a version of (`a.txt` for `a.txt.v3`), if it is one
+pub fn version_of(name: &str) -> Option<&str> {
+    let (base, version) = name.rsplit_once(".v")?;
+    let numbered = !version.is_empty() && version.bytes().all(|b| b.is_ascii_digit());
+    (numbered && !base.is_empty()).then_some(base)
+}
+
+/// Usage of every directory directly under `data_dir`, sorted by name, with
+/// the files lying at its top as `.`
+pub async fn dir_usage(data_dir: &Path) -> io::Result<Vec<DirUsage>> {
+    let mut top = DirUsage {
+        dir: ".".to_string(),
+        ..DirUsage::default()
+    };
+    let mut dirs = Vec::new();
+    let mut entries = fs::read_dir(data_dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let meta = entry.metadata().await?;
+        if meta.is_dir() {
+            let (bytes, files) = tree_size(&entry.path()).await?;
+            dirs.push(DirUsage {
+                dir: entry.file_name().to_string_lossy().into_owned(),
+                bytes,
+                files,
+            });
+        } else if meta.is_file() {
+            top.bytes += meta.len();
+            top.files += 1;
+        }
+    }
+    dirs.sort_by(|a, b| a.dir.cmp(&b.dir));
+    if top.files > 0 {
+        dirs.insert(0, top);
+    }
+    Ok(dirs)
+}
+
+/// Bytes and regular files below `dir`. Entries vanishing while it is read
+/// (chunks being moved out of staging) are skipped.
+async fn tree_size(dir: &Path) -> io::Result<(u64, u64)> {
+    let (mut bytes, mut files) = (0, 0);
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        let Ok(mut entries) = fs::read_dir(&current).await else {
+            continue;
+        };
+        while let Some(entry) = entries.next_entry().await? {
+            let Ok(meta) = entry.metadata().await else {
+                continue;
+            };
+            if meta.is_dir() {
+                pending.push(entry.path());
+            } else if meta.is_file() {
+                bytes += meta.len();
+                files += 1;
+            }
+        }
+    }
+    Ok((bytes, files))
+}
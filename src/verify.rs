@@ -0,0 +1,283 @@
+//! Chunk verification and background scrubbing.
+//!
+//! Stored chunks are re-hashed and compared with the manifest entry recorded
+//! when they were written. A bad content chunk is restored from the backup
+//! held by the predecessor; a bad backup is fetched again from the successor.
+
+use crate::{
+    NodeEvent,
+    checksum::{Digest, Sha256},
+    disk::{DiskScheduler, IO_BLOCK, IoClass},
+    manifest::{self, ChunkEntry},
+    node::{Node, port_str},
+    pack,
+    protocol::decode_name,
+    server,
+};
+use std::{
+    collections::BTreeSet, error::Error, fmt, io, path::Path, str::FromStr, sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    fs,
+    io::{AsyncRead, AsyncReadExt},
+};
+
+type AnyErr = Box<dyn Error + Send + Sync>;
+
+/// Pause between two chunks during a scrub pass, so scrubbing stays in the background
+const SCRUB_CHUNK_PAUSE: Duration = Duration::from_millis(50);
+
+/// Chunks written more recently than this are left alone by the scrubber,
+/// as they may still be in the middle of being stored
+const SCRUB_MIN_AGE: Duration = Duration::from_secs(60);
+
+/// Outcome of checking one chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChunkStatus {
+    /// Bytes on disk match the manifest
+    Ok,
+    /// Bytes on disk differ from the manifest and could not be repaired
+    Corrupt,
+    /// The chunk file is gone and could not be repaired
+    Missing,
+    /// The chunk was bad or missing and was restored from its replica
+    Repaired,
+    /// The node holding the chunk did not answer
+    Unreachable,
+}
+
+impl ChunkStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Ok => "ok",
+            Self::Corrupt => "corrupt",
+            Self::Missing => "missing",
+            Self::Repaired => "repaired-from-backup",
+            Self::Unreachable => "unreachable",
+        }
+    }
+
+    /// Whether the chunk is readable after the check
+    pub fn is_healthy(&self) -> bool {
+        matches!(self, Self::Ok | Self::Repaired)
+    }
+}
+
+impl fmt::Display for ChunkStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ChunkStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "ok" => Ok(Self::Ok),
+            "corrupt" => Ok(Self::Corrupt),
+            "missing" => Ok(Self::Missing),
+            "repaired-from-backup" => Ok(Self::Repaired),
+            "unreachable" => Ok(Self::Unreachable),
+            other => Err(format!("unknown chunk status '{}'", other)),
+        }
+    }
+}
+
+/// Hashes a file with a streaming read, each block under a disk permit of `class`
+pub async fn hash_file(
+    disk: &DiskScheduler,
+    class: IoClass,
+    path: &Path,
+) -> io::Result<ChunkEntry> {
+    hash_reader(disk, class, fs::File::open(path).await?).await
+}
+
+/// Hashes chunk `fname` of `subdir`, loose or packed (see [`pack::open`])
+pub(crate) async fn hash_chunk(
+    node: &Node,
+    class: IoClass,
+    subdir: &str,
+    fname: &str,
+) -> io::Result<ChunkEntry> {
+    let chunk = pack::open(node, subdir, fname).await?;
+    hash_reader(&node.disk, class, chunk.file.take(chunk.len)).await
+}
+
+async fn hash_reader<R: AsyncRead + Unpin>(
+    disk: &DiskScheduler,
+    class: IoClass,
+    mut file: R,
+) -> io::Result<ChunkEntry> {
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; IO_BLOCK];
+    let mut size = 0u64;
+    loop {
+        let n = disk.read(class, &mut file, &mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        size += n as u64;
+    }
+    Ok(ChunkEntry {
+        sha256: hasher.finalize(),
+        size,
+    })
+}
+
+/// Checks one chunk stored in this node's `subdir` (`content` or `backup`)
+/// against the manifest, repairing it from its replica if `repair` is set.
+/// Its disk I/O goes in `class`.
+pub(crate) async fn verify_local_chunk(
+    node: &Node,
+    subdir: &str,
+    name: &str,
+    repair: bool,
+    class: IoClass,
+) -> ChunkStatus {
+    let manifest_dir = node.manifest_dir(subdir);
+    let fname = server::sanitize_filename(name);
+    let expected = manifest::lookup(&manifest_dir, &fname).await;
+
+    // 1. Hash what is on disk
+    let status = match (hash_chunk(node, class, subdir, &fname).await, expected) {
+        (Ok(actual), Some(expected)) if actual == expected => return ChunkStatus::Ok,
+        (Ok(_), Some(_)) => ChunkStatus::Corrupt,
+        (Ok(actual), None) => {
+            // Stored before manifests existed: adopt the current bytes
+            tracing::info!(node = %node.port, chunk = %name, subdir, "Chunk has no manifest entry, recording it");
+            if let Err(e) = manifest::record(&manifest_dir, &fname, actual).await {
+                tracing::warn!(node = %node.port, chunk = %name, error = ?e, "Could not record manifest entry");
+            }
+            return ChunkStatus::Ok;
+        }
+        (Err(_), _) => ChunkStatus::Missing,
+    };
+
+    tracing::warn!(node = %node.port, chunk = %name, subdir, status = %status, "Chunk failed verification");
+
+    // 2. Restore it from the replica, if we know what it should hash to
+    let Some(expected) = expected.filter(|_| repair) else {
+        return status;
+    };
+    match repair_chunk(node, subdir, name, expected.sha256, class).await {
+        Ok(()) => {
+            tracing::info!(node = %node.port, chunk = %name, subdir, "Chunk repaired from replica");
+            ChunkStatus::Repaired
+        }
+        Err(e) => {
+            tracing::error!(node = %node.port, chunk = %name, subdir, error = ?e, "Chunk repair failed");
+            status
+        }
+    }
+}
+
+/// Fetches a good copy of a chunk and stores it in place of the local one.
+///
+/// Content chunks come from the `backup/` of this node's backup holders,
+/// backups from the `content/` of the chunk's holder (the successor when the
+/// file tags do not say). The copy is only kept if it matches `expected`.
+async fn repair_chunk(
+    node: &Node,
+    subdir: &str,
+    name: &str,
+    expected: Digest,
+    class: IoClass,
+) -> Result<(), AnyErr> {
+    let mut last_err: AnyErr = "no backup holder known".into();
+    if subdir == "backup" {
+        let holder = match server::chunk_holder_of(node, name).await {
+            Some(port) => Some(node.peer_addr(&port)),
+            None => node.get_next().await,
+        };
+        let holder = holder.ok_or("no chunk holder to fetch from")?;
+        let data = server::request_chunk_for_backup(node, &holder, name).await?;
+        return keep_if_matching(node, subdir, name, &data, expected, class).await;
+    }
+
+    for backup in server::backup_holders(node, port_str(&node.port)).await {
+        let addr = node.peer_addr(&backup);
+        let res = match server::request_backup_chunk_from(node, &addr, name).await {
+            Ok((data, _)) => keep_if_matching(node, subdir, name, &data, expected, class).await,
+            Err(e) => Err(e),
+        };
+        match res {
+            Ok(()) => return Ok(()),
+            Err(e) => last_err = e,
+        }
+    }
+    Err(last_err)
+}
+
+/// Stores `data` as the local copy of a chunk if it hashes to `expected`
+async fn keep_if_matching(
+    node: &Node,
+    subdir: &str,
+    name: &str,
+    data: &[u8],
+    expected: Digest,
+    class: IoClass,
+) -> Result<(), AnyErr> {
+    let actual = Sha256::digest(data);
+    if actual != expected {
+        return Err(format!("replica does not match manifest (got {})", actual).into());
+    }
+    server::save_into_node_dir(node, name, data, subdir, class).await?;
+    Ok(())
+}
+
+/// Background scrubber: every `scrub-interval`, re-hashes every chunk this
+/// node stores and repairs the bad ones. Chunks are checked one at a time
+/// with a pause in between, and their disk I/O goes in [`IoClass::Scrub`], so
+/// scrubbing never competes with client traffic.
+pub(crate) async fn spawn_scrub_loop(node: Arc<Node>) {
+    loop {
+        // Re-read every round, the interval can be hot-reloaded
+        let interval = node.settings().await.scrub_interval;
+        if interval.is_zero() {
+            // Scrubbing is disabled; check again later in case it is re-enabled
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            continue;
+        }
+        tokio::time::sleep(interval).await;
+
+        let (mut checked, mut bad) = (0u64, 0u64);
+        for subdir in ["content", "backup"] {
+            let Ok(mut entries) = fs::read_dir(node.data_dir.join(subdir)).await else {
+                continue;
+            };
+            let mut fnames = BTreeSet::new();
+            while let Ok(Some(entry)) = entries.next_entry().await {
+                let recent = entry
+                    .metadata()
+                    .await
+                    .and_then(|m| m.modified())
+                    .map(|t| t.elapsed().unwrap_or_default() < SCRUB_MIN_AGE)
+                    .unwrap_or(true);
+                if !recent {
+                    fnames.insert(entry.file_name().to_string_lossy().into_owned());
+                }
+            }
+            // Packed chunks were all written long enough ago
+            fnames.extend(node.packs.of(subdir).names());
+            for fname in fnames {
+                // Disk names are sanitized chunk names, whose escapes decode back
+                let name = decode_name(&fname);
+                let status = verify_local_chunk(&node, subdir, &name, true, IoClass::Scrub).await;
+                checked += 1;
+                if status != ChunkStatus::Ok {
+                    bad += 1;
+                    node.emit(NodeEvent::ChunkDamaged {
+                        name,
+                        backup: subdir == "backup",
+                        repaired: status == ChunkStatus::Repaired,
+                    });
+                }
+                tokio::time::sleep(SCRUB_CHUNK_PAUSE).await;
+            }
+        }
+        tracing::info!(node = %node.port, checked, bad, "Scrub pass finished");
+    }
+}
@@ -0,0 +1,186 @@
+//! `ouroboros_fs watch`: keeps a ring in step with a local directory.
+//!
+//! Every file of the directory is sent once at start, then again whenever it
+//! is created or written to, as reported by the OS (inotify, FSEvents, ...)
+//! through the `notify` crate. Events are gathered until the directory has
+//! been quiet for a moment, so a file being written is sent once, when done.
+//! Files go with `FILE PUSH`, or with `FILE SYNC` (see [`crate::delta`]) to
+//! send only the blocks that changed. Only the top level of the directory is
+//! watched, and files removed locally stay in the ring.
+
+use crate::{
+    auth, bulk,
+    checksum::{Digest, Sha256},
+    delta,
+    output::OutputFormat,
+    protocol::encode_name,
+};
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use serde_json::json;
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::{
+    fs,
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    sync::mpsc,
+};
+
+type AnyErr = Box<dyn Error + Send + Sync>;
+
+/// How long a push may take
+const PUSH_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// What to watch and where to send it
+#[derive(Debug, Clone)]
+pub struct WatchOptions {
+    pub dir: PathBuf,
+    /// Address of any node of the ring
+    pub addr: String,
+    /// Send changes with `FILE SYNC` instead of pushing whole files
+    pub delta: bool,
+    /// Block size of `FILE SYNC`
+    pub block_size: u64,
+    /// Quiet time after the last event before changed files are sent
+    pub debounce: Duration,
+    /// How sent files are reported
+    pub output: OutputFormat,
+}
+
+/// Watches `opts.dir` until the process is stopped, printing one line per
+/// file sent (`PUSHED`/`SYNCED ...`) or not (`FAILED <name>: <error>`).
+pub async fn watch(opts: &WatchOptions) -> Result<(), AnyErr> {
+    // Events name files under the absolute path; so must the initial scan
+    let dir = fs::canonicalize(&opts.dir).await?;
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<Event>| {
+        // The receiver only goes away with the watch itself
+        let _ = tx.send(res);
+    })?;
+    watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+    tracing::info!(dir = %opts.dir.display(), addr = %opts.addr, delta = opts.delta, "Watching directory");
+
+    // Content last sent per file, so touching a file does not send it again
+    let mut sent: HashMap<PathBuf, Digest> = HashMap::new();
+    let mut pending: HashSet<PathBuf> = HashSet::new();
+    let mut entries = fs::read_dir(&dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        pending.insert(entry.path());
+    }
+    send_pending(opts, &mut pending, &mut sent).await;
+
+    loop {
+        let event = if pending.is_empty() {
+            rx.recv().await
+        } else {
+            match tokio::time::timeout(opts.debounce, rx.recv()).await {
+                Ok(event) => event,
+                Err(_) => {
+                    send_pending(opts, &mut pending, &mut sent).await;
+                    continue;
+                }
+            }
+        };
+        match event {
+            Some(Ok(event))
+                if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) =>
+            {
+                pending.extend(event.paths);
+            }
+            Some(Ok(_)) => {}
+            Some(Err(e)) => tracing::warn!(dir = %opts.dir.display(), error = %e, "Watch error"),
+            None => return Err("the directory watcher stopped".into()),
+        }
+    }
+}
+
+/// Sends every pending file whose content changed since it was last sent
+async fn send_pending(
+    opts: &WatchOptions,
+    pending: &mut HashSet<PathBuf>,
+    sent: &mut HashMap<PathBuf, Digest>,
+) {
+    let mut paths: Vec<PathBuf> = pending.drain().collect();
+    paths.sort();
+    for path in paths {
+        let Some(name) = watched_name(&path) else {
+            continue;
+        };
+        // Gone again, or not a regular file
+        if !fs::metadata(&path).await.is_ok_and(|m| m.is_file()) {
+            continue;
+        }
+        let data = match fs::read(&path).await {
+            Ok(data) => data,
+            Err(e) => {
+                bulk::failed(opts.output, &name, e);
+                continue;
+            }
+        };
+        let digest = Sha256::digest(&data);
+        if sent.get(&path) == Some(&digest) {
+            continue;
+        }
+        let res = if opts.delta {
+            delta::sync_file(&opts.addr, &name, &data, opts.block_size)
+                .await
+                .map(|report| {
+                    let json = json!({"name": name, "sent": report.sent, "blocks": report.blocks, "bytes": report.bytes});
+                    ("synced", report.summary, json)
+                })
+        } else {
+            push(&opts.addr, &name, &data).await.map(|()| {
+                let line = format!("PUSHED {} bytes={}", encode_name(&name), data.len());
+                ("pushed", line, json!({"name": name, "bytes": data.len()}))
+            })
+        };
+        match res {
+            Ok((event, line, json)) => {
+                opts.output.emit(event, line, &json);
+                sent.insert(path, digest);
+            }
+            Err(e) => {
+                tracing::warn!(file = %name, error = %e, "Failed to send file");
+                bulk::failed(opts.output, &name, e);
+            }
+        }
+    }
+}
+
+/// Name a file is sent under. Hidden files and editor backups (`~`) are not
+/// sent.
+fn watched_name(path: &Path) -> Option<String> {
+    let name = path.file_name()?.to_str()?;
+    (!name.starts_with('.') && !name.ends_with('~')).then(|| name.to_string())
+}
+
+/// `FILE PUSH`es `data` as `name`, replacing a stored file of that name
+pub(crate) async fn push(addr: &str, name: &str, data: &[u8]) -> Result<(), AnyErr> {
+    let exchange = async {
+        let mut stream = BufReader::new(auth::connect(addr).await?);
+        let header = format!("FILE PUSH {} {}\n", data.len(), encode_name(name));
+        stream.get_mut().write_all(header.as_bytes()).await?;
+        stream.get_mut().write_all(data).await?;
+
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if stream.read_line(&mut line).await? == 0 {
+                return Err::<_, AnyErr>("connection closed before OK".into());
+            }
+            let l = line.trim();
+            if l == "OK" {
+                return Ok(());
+            }
+            if let Some(err) = l.strip_prefix("ERR") {
+                return Err(err.trim().to_string().into());
+            }
+        }
+    };
+    tokio::time::timeout(PUSH_TIMEOUT, exchange)
+        .await
+        .map_err(|_| -> AnyErr { "FILE PUSH timed out".into() })?
+}